@@ -66,6 +66,10 @@ $(window).on('load', function () {
         progress = progress + (step * 100 / total_steps);
         // Generate the appropriate html bulma progress bar.
         html = `<progress class="progress" title="${progress}%" value="${progress}" max="100"></progress>`;
+      } else if (type === 'Maintenance') {
+        // The maintenance steps are not subdivided, we only mark the previous steps as processed.
+        let progress = step * 100 / total_steps;
+        html = `<progress class="progress" title="${progress}%" value="${progress}" max="100"></progress>`;
       } else {
         html = `<progress class="progress" max="100"></progress>`;
       }
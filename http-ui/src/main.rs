@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::Display;
 use std::fs::{File, create_dir_all};
 use std::net::SocketAddr;
@@ -6,7 +6,7 @@ use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{mem, io};
 
 use askama_warp::Template;
@@ -29,10 +29,11 @@ use warp::filters::ws::Message;
 use warp::{Filter, http::Response};
 use meilisearch_tokenizer::{Analyzer, AnalyzerConfig};
 
-use milli::facet::FacetValue;
+use milli::facet::{FacetStringNormalization, FacetValue};
 use milli::update::UpdateIndexingStep::*;
-use milli::update::{UpdateBuilder, IndexDocumentsMethod, UpdateFormat};
-use milli::{obkv_to_json, Index, UpdateStore, SearchResult, MatchingWords, FacetCondition};
+use milli::update::{UpdateBuilder, IndexDocumentsMethod, MaxPositionPolicy, StopWordsDetection, UpdateFormat};
+use milli::{obkv_to_json, ComputedField, Index, UpdateStore, SearchResult, MatchingWords, FacetCondition, Relaxation};
+use milli::{Criterion, Cursor, DistinctMode, MatchPosition, Rank, SearchDefaults, TermsMatchingStrategy, TotalHits, TypoDetails};
 
 static GLOBAL_THREAD_POOL: OnceCell<ThreadPool> = OnceCell::new();
 
@@ -142,10 +143,23 @@ impl<'a, A: AsRef<[u8]>> Highlighter<'a, A> {
                 let analyzed = self.analyzer.analyze(&old_string);
                 for (word, token) in analyzed.reconstruct() {
                     if token.is_word() {
-                        let to_highlight = matching_words.matches(token.text());
-                        if to_highlight { string.push_str("<mark>") }
-                        string.push_str(word);
-                        if to_highlight { string.push_str("</mark>") }
+                        match matching_words.matching_bytes(token.text()) {
+                            Some(len) => {
+                                // The highlighted span is the part of the word matched by the query,
+                                // a word matching a prefix query word is highlighted on the prefix.
+                                let chars = token.text().get(..len).map_or(0, |text| text.chars().count());
+                                let end = word.char_indices().nth(chars).map_or(word.len(), |(i, _)| i);
+                                // We annotate the highlighted word with the query words it satisfies.
+                                let query_words = matching_words.matching_query_words(token.text());
+                                string.push_str("<mark title=\"");
+                                string.push_str(&query_words.join(" "));
+                                string.push_str("\">");
+                                string.push_str(&word[..end]);
+                                string.push_str("</mark>");
+                                string.push_str(&word[end..]);
+                            },
+                            None => string.push_str(word),
+                        }
                     } else {
                         string.push_str(word);
                     }
@@ -226,6 +240,7 @@ enum UpdateMeta {
     Settings(Settings),
     Facets(Facets),
     WordsPrefixes(WordsPrefixes),
+    Maintenance,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -237,6 +252,10 @@ enum UpdateMetaProgress {
         current: usize,
         total: Option<usize>,
     },
+    Maintenance {
+        step: usize,
+        total_steps: usize,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -260,12 +279,162 @@ struct Settings {
     #[serde(default)]
     faceted_attributes: Option<HashMap<String, String>>,
 
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    sortable_attributes: Option<Option<HashSet<String>>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    auto_filterable_attributes: Option<Option<HashSet<String>>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    stored_only_attributes: Option<Option<HashSet<String>>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    stop_words: Option<Option<BTreeSet<String>>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stop_words_detection: Option<StopWordsDetection>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    attributes_stop_words: Option<Option<HashMap<String, BTreeSet<String>>>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    attributes_languages: Option<Option<HashMap<String, String>>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    attributes_weights: Option<Option<HashMap<String, u8>>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    computed_fields: Option<Option<BTreeMap<String, ComputedField>>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    distinct_attribute: Option<Option<String>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    distinct_mode: Option<Option<DistinctMode>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    facet_string_normalizations: Option<Option<HashMap<String, FacetStringNormalization>>>,
+
     #[serde(
         default,
         deserialize_with = "deserialize_some",
         skip_serializing_if = "Option::is_none",
     )]
     criteria: Option<Option<Vec<String>>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    words_prefixes_threshold: Option<Option<f64>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    max_prefix_length: Option<Option<usize>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    min_prefix_query_length: Option<Option<usize>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    max_query_ngram: Option<Option<usize>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    split_words_enabled: Option<Option<bool>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    split_words_budget: Option<Option<usize>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    max_total_hits: Option<Option<usize>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    proximity_database_enabled: Option<Option<bool>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    max_position_policy: Option<Option<MaxPositionPolicy>>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none",
+    )]
+    search_defaults: Option<Option<SearchDefaults>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -431,6 +600,99 @@ async fn main() -> anyhow::Result<()> {
                         builder.set_faceted_fields(facet_types);
                     }
 
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(names) = settings.sortable_attributes {
+                        match names {
+                            Some(names) => builder.set_sortable_fields(names),
+                            None => builder.reset_sortable_fields(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(names) = settings.auto_filterable_attributes {
+                        match names {
+                            Some(names) => builder.set_auto_filterable_fields(names),
+                            None => builder.reset_auto_filterable_fields(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(names) = settings.stored_only_attributes {
+                        match names {
+                            Some(names) => builder.set_stored_only_fields(names),
+                            None => builder.reset_stored_only_fields(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(stop_words) = settings.stop_words {
+                        match stop_words {
+                            Some(stop_words) => builder.set_stop_words(stop_words),
+                            None => builder.reset_stop_words(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(detection) = settings.stop_words_detection {
+                        builder.detect_stop_words(detection);
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(stop_words) = settings.attributes_stop_words {
+                        match stop_words {
+                            Some(stop_words) => builder.set_attributes_stop_words(stop_words),
+                            None => builder.reset_attributes_stop_words(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(languages) = settings.attributes_languages {
+                        match languages {
+                            Some(languages) => builder.set_attributes_languages(languages),
+                            None => builder.reset_attributes_languages(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(weights) = settings.attributes_weights {
+                        match weights {
+                            Some(weights) => builder.set_attributes_weights(weights),
+                            None => builder.reset_attributes_weights(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(fields) = settings.computed_fields {
+                        match fields {
+                            Some(fields) => builder.set_computed_fields(fields),
+                            None => builder.reset_computed_fields(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(name) = settings.distinct_attribute {
+                        match name {
+                            Some(name) => builder.set_distinct_attribute(name),
+                            None => builder.reset_distinct_attribute(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(mode) = settings.distinct_mode {
+                        match mode {
+                            Some(mode) => builder.set_distinct_mode(mode),
+                            None => builder.reset_distinct_mode(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(normalizations) = settings.facet_string_normalizations {
+                        match normalizations {
+                            Some(normalizations) => builder.set_facet_string_normalizations(normalizations),
+                            None => builder.reset_facet_string_normalizations(),
+                        }
+                    }
+
                     // We transpose the settings JSON struct into a real setting update.
                     if let Some(criteria) = settings.criteria {
                         match criteria {
@@ -439,6 +701,86 @@ async fn main() -> anyhow::Result<()> {
                         }
                     }
 
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(threshold) = settings.words_prefixes_threshold {
+                        match threshold {
+                            Some(threshold) => builder.set_words_prefixes_threshold(threshold),
+                            None => builder.reset_words_prefixes_threshold(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(length) = settings.max_prefix_length {
+                        match length {
+                            Some(length) => builder.set_max_prefix_length(length),
+                            None => builder.reset_max_prefix_length(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(length) = settings.min_prefix_query_length {
+                        match length {
+                            Some(length) => builder.set_min_prefix_query_length(length),
+                            None => builder.reset_min_prefix_query_length(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(max) = settings.max_query_ngram {
+                        match max {
+                            Some(max) => builder.set_max_query_ngram(max),
+                            None => builder.reset_max_query_ngram(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(enabled) = settings.split_words_enabled {
+                        match enabled {
+                            Some(enabled) => builder.set_split_words_enabled(enabled),
+                            None => builder.reset_split_words_enabled(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(budget) = settings.split_words_budget {
+                        match budget {
+                            Some(budget) => builder.set_split_words_budget(budget),
+                            None => builder.reset_split_words_budget(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(max) = settings.max_total_hits {
+                        match max {
+                            Some(max) => builder.set_max_total_hits(max),
+                            None => builder.reset_max_total_hits(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(enabled) = settings.proximity_database_enabled {
+                        match enabled {
+                            Some(enabled) => builder.set_proximity_database_enabled(enabled),
+                            None => builder.reset_proximity_database_enabled(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(policy) = settings.max_position_policy {
+                        match policy {
+                            Some(policy) => builder.set_max_position_policy(policy),
+                            None => builder.reset_max_position_policy(),
+                        }
+                    }
+
+                    // We transpose the settings JSON struct into a real setting update.
+                    if let Some(defaults) = settings.search_defaults {
+                        match defaults {
+                            Some(defaults) => builder.set_search_defaults(defaults),
+                            None => builder.reset_search_defaults(),
+                        }
+                    }
+
                     let result = builder.execute(|indexing_step, update_id| {
                         let (current, total) = match indexing_step {
                             TransformFromUserIntoGenericFormat { documents_seen } => (documents_seen, None),
@@ -491,6 +833,25 @@ async fn main() -> anyhow::Result<()> {
                         Ok(()) => wtxn.commit().map_err(Into::into),
                         Err(e) => Err(e.into())
                     }
+                },
+                UpdateMeta::Maintenance => {
+                    // We must use the write transaction of the update here.
+                    let mut wtxn = index_cloned.write_txn()?;
+                    let builder = update_builder.maintenance(&mut wtxn, &index_cloned);
+                    let result = builder.execute(|maintenance_step, update_id| {
+                        let _ = update_status_sender_cloned.send(UpdateStatus::Progressing {
+                            update_id,
+                            meta: UpdateMetaProgress::Maintenance {
+                                step: maintenance_step.step(),
+                                total_steps: maintenance_step.number_of_steps(),
+                            }
+                        });
+                    });
+
+                    match result {
+                        Ok(()) => wtxn.commit().map_err(Into::into),
+                        Err(e) => Err(e.into())
+                    }
                 }
             };
 
@@ -670,14 +1031,58 @@ async fn main() -> anyhow::Result<()> {
         filters: Option<String>,
         facet_filters: Option<Vec<UntaggedEither<Vec<String>, String>>>,
         facet_distribution: Option<bool>,
+        sort: Option<Vec<String>>,
+        criteria: Option<Vec<String>>,
+        distinct: Option<String>,
+        group_by: Option<String>,
+        max_per_group: Option<usize>,
+        snapshot: Option<u64>,
+        after: Option<String>,
+        relax_on_empty: Option<bool>,
+        languages: Option<Vec<String>>,
+        synonyms: Option<HashMap<String, Vec<String>>>,
+        documents_facets: Option<Vec<String>>,
+        matches_positions: Option<bool>,
+        score_details: Option<bool>,
+        ranking_score_threshold: Option<f64>,
+        terms_matching_strategy: Option<TermsMatchingStrategy>,
+        /// The time budget of the search, in milliseconds.
+        time_budget: Option<u64>,
+        exhaustive_total_hits: Option<bool>,
+        explain: Option<bool>,
     }
 
     #[derive(Debug, Serialize)]
     #[serde(rename_all = "camelCase")]
     struct Answer {
         documents: Vec<Map<String, Value>>,
+        documents_scores: Vec<f64>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        documents_score_details: Vec<Vec<Rank>>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        documents_facets: Vec<BTreeMap<String, Vec<FacetValue>>>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        documents_matches: Vec<Vec<MatchPosition>>,
         number_of_candidates: u64,
+        total_hits: TotalHits,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        groups: Vec<AnswerGroup>,
         facets: BTreeMap<String, BTreeMap<FacetValue, u64>>,
+        snapshot: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cursor: Option<String>,
+        relaxations: Vec<Relaxation>,
+        degraded: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        typos: Option<TypoDetails>,
+    }
+
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct AnswerGroup {
+        /// The positions of the documents of the group in the returned documents.
+        documents: Vec<usize>,
+        count: u64,
     }
 
     let disable_highlighting = opt.disable_highlighting;
@@ -697,7 +1102,10 @@ async fn main() -> anyhow::Result<()> {
 
             let filters = match query.filters {
                 Some(condition) if !condition.trim().is_empty() => {
-                    Some(FacetCondition::from_str(&rtxn, &index, &condition).unwrap())
+                    match FacetCondition::from_str(&rtxn, &index, &condition) {
+                        Ok(condition) => Some(condition),
+                        Err(error) => return Response::builder().status(400).body(error.to_string()),
+                    }
                 },
                 _otherwise => None,
             };
@@ -705,7 +1113,10 @@ async fn main() -> anyhow::Result<()> {
             let facet_filters = match query.facet_filters {
                 Some(array) => {
                     let eithers = array.into_iter().map(Into::into);
-                    FacetCondition::from_array(&rtxn, &index, eithers).unwrap()
+                    match FacetCondition::from_array(&rtxn, &index, eithers) {
+                        Ok(condition) => condition,
+                        Err(error) => return Response::builder().status(400).body(error.to_string()),
+                    }
                 },
                 _otherwise => None,
             };
@@ -722,7 +1133,115 @@ async fn main() -> anyhow::Result<()> {
                 search.facet_condition(condition);
             }
 
-            let SearchResult { matching_words, candidates, documents_ids } = search.execute().unwrap();
+            if let Some(sort) = query.sort {
+                match sort.iter().map(|s| s.parse()).collect::<anyhow::Result<_>>() {
+                    Ok(sort) => { search.sort(sort); },
+                    Err(error) => return Response::builder().status(400).body(error.to_string()),
+                }
+            }
+
+            if let Some(criteria) = query.criteria {
+                let faceted_fields = index.faceted_fields(&rtxn).unwrap();
+                let criteria = criteria.iter()
+                    .map(|name| Criterion::from_str(&faceted_fields, name))
+                    .collect::<anyhow::Result<_>>();
+                match criteria {
+                    Ok(criteria) => { search.criteria(criteria); },
+                    Err(error) => return Response::builder().status(400).body(error.to_string()),
+                }
+            }
+
+            if let Some(distinct) = query.distinct {
+                search.distinct(distinct);
+            }
+
+            if let Some(field) = query.group_by {
+                search.group_by(field, query.max_per_group.unwrap_or(1));
+            }
+
+            if let Some(snapshot) = query.snapshot {
+                search.snapshot(snapshot);
+            }
+
+            if let Some(after) = query.after {
+                match after.parse::<Cursor>() {
+                    Ok(cursor) => { search.after(cursor); },
+                    Err(error) => return Response::builder().status(400).body(error.to_string()),
+                }
+            }
+
+            if let Some(relax_on_empty) = query.relax_on_empty {
+                search.relax_on_empty(relax_on_empty);
+            }
+
+            if let Some(languages) = query.languages {
+                search.languages(languages);
+            }
+
+            if let Some(synonyms) = query.synonyms {
+                search.synonyms(synonyms);
+            }
+
+            if let Some(fields) = query.documents_facets {
+                search.documents_facets(fields);
+            }
+
+            if let Some(matches_positions) = query.matches_positions {
+                search.matches_positions(matches_positions);
+            }
+
+            if let Some(score_details) = query.score_details {
+                search.score_details(score_details);
+            }
+
+            if let Some(threshold) = query.ranking_score_threshold {
+                search.ranking_score_threshold(threshold);
+            }
+
+            if let Some(strategy) = query.terms_matching_strategy {
+                search.terms_matching_strategy(strategy);
+            }
+
+            if let Some(budget) = query.time_budget {
+                search.time_budget(Duration::from_millis(budget));
+            }
+
+            if let Some(exhaustive) = query.exhaustive_total_hits {
+                search.exhaustive_total_hits(exhaustive);
+            }
+
+            let mut typos = match query.explain {
+                Some(true) => Some(search.typo_details().unwrap()),
+                _otherwise => None,
+            };
+
+            let SearchResult {
+                matching_words,
+                candidates,
+                documents_ids,
+                documents_scores,
+                documents_score_details,
+                documents_facets,
+                documents_matches,
+                total_hits,
+                groups,
+                cursor,
+                snapshot,
+                relaxations,
+                degraded,
+            } = search.execute().unwrap();
+
+            let groups = groups.into_iter().map(|group| AnswerGroup {
+                documents: group.documents_ids.iter()
+                    .filter_map(|id| documents_ids.iter().position(|docid| docid == id))
+                    .collect(),
+                count: group.count,
+            }).collect();
+
+            // The typos of the relaxed searches must be explained as they have been applied.
+            if let Some(typos) = typos.as_mut().filter(|_| relaxations.contains(&Relaxation::TyposIncreased)) {
+                typos.increase_typos();
+            }
 
             let number_of_candidates = candidates.len();
             let facets = if query.facet_distribution == Some(true) {
@@ -733,13 +1252,20 @@ async fn main() -> anyhow::Result<()> {
 
             let mut documents = Vec::new();
             let fields_ids_map = index.fields_ids_map(&rtxn).unwrap();
-            let displayed_fields = match index.displayed_fields_ids(&rtxn).unwrap() {
+            let search_defaults = index.search_defaults(&rtxn).unwrap();
+            let mut displayed_fields = match index.displayed_fields_ids(&rtxn).unwrap() {
                 Some(fields) => fields,
                 None => fields_ids_map.iter().map(|(id, _)| id).collect(),
             };
-            let attributes_to_highlight = match index.searchable_fields(&rtxn).unwrap() {
-                Some(fields) => fields.into_iter().map(String::from).collect(),
-                None => fields_ids_map.iter().map(|(_, name)| name).map(String::from).collect(),
+            if let Some(attributes) = &search_defaults.attributes_to_retrieve {
+                displayed_fields.retain(|id| {
+                    fields_ids_map.name(*id).map_or(false, |name| attributes.iter().any(|a| a == name))
+                });
+            }
+            let attributes_to_highlight = match (search_defaults.attributes_to_highlight, index.searchable_fields(&rtxn).unwrap()) {
+                (Some(attributes), _) => attributes.into_iter().collect(),
+                (None, Some(fields)) => fields.into_iter().map(String::from).collect(),
+                (None, None) => fields_ids_map.iter().map(|(_, name)| name).map(String::from).collect(),
             };
 
             let stop_words = fst::Set::default();
@@ -756,8 +1282,19 @@ async fn main() -> anyhow::Result<()> {
 
             let answer = Answer {
                 documents,
+                documents_scores,
+                documents_score_details,
+                documents_facets,
+                documents_matches,
                 number_of_candidates,
+                total_hits,
+                groups,
                 facets: facets.unwrap_or_default(),
+                snapshot,
+                cursor: cursor.map(|cursor| cursor.to_string()),
+                relaxations,
+                degraded,
+                typos,
             };
 
             Response::builder()
@@ -921,6 +1458,38 @@ async fn main() -> anyhow::Result<()> {
             warp::reply()
         });
 
+    let index_cloned = index.clone();
+    let stop_words_candidates_route = warp::filters::method::get()
+        .and(warp::path!("stop-words-candidates"))
+        .and(warp::query::query())
+        .map(move |detection: StopWordsDetection| {
+            let index = index_cloned.clone();
+            let rtxn = match index.read_txn() {
+                Ok(rtxn) => rtxn,
+                Err(error) => return Response::builder().status(500).body(error.to_string()),
+            };
+            let candidates = match detection.candidates(&rtxn, &index) {
+                Ok(candidates) => candidates,
+                Err(error) => return Response::builder().status(500).body(error.to_string()),
+            };
+
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_string(&candidates).unwrap())
+        });
+
+    let update_store_cloned = update_store.clone();
+    let update_status_sender_cloned = update_status_sender.clone();
+    let maintenance_route = warp::filters::method::post()
+        .and(warp::path!("maintenance"))
+        .map(move || {
+            let meta = UpdateMeta::Maintenance;
+            let update_id = update_store_cloned.register_update(&meta, &[]).unwrap();
+            let _ = update_status_sender_cloned.send(UpdateStatus::Pending { update_id, meta });
+            eprintln!("update {} registered", update_id);
+            warp::reply()
+        });
+
     let update_store_cloned = update_store.clone();
     let update_status_sender_cloned = update_status_sender.clone();
     let abort_update_id_route = warp::filters::method::delete()
@@ -996,6 +1565,8 @@ async fn main() -> anyhow::Result<()> {
         .or(change_settings_route)
         .or(change_facet_levels_route)
         .or(change_words_prefixes_route)
+        .or(stop_words_candidates_route)
+        .or(maintenance_route)
         .or(update_ws_route);
 
     let addr = SocketAddr::from_str(&opt.http_listen_addr)?;
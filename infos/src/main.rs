@@ -22,6 +22,9 @@ const WORD_PAIR_PROXIMITY_DOCIDS_DB_NAME: &str = "word-pair-proximity-docids";
 const FACET_FIELD_ID_VALUE_DOCIDS_NAME: &str = "facet-field-id-value-docids";
 const FIELD_ID_DOCID_FACET_VALUES_NAME: &str = "field-id-docid-facet-values";
 const WORD_PREFIX_PAIR_PROXIMITY_DOCIDS_DB_NAME: &str = "word-prefix-pair-proximity-docids";
+const PREFIX_WORD_PAIR_PROXIMITY_DOCIDS_DB_NAME: &str = "prefix-word-pair-proximity-docids";
+const WORD_POSITION_DOCIDS_DB_NAME: &str = "word-position-docids";
+const WORD_ATTRIBUTE_DOCIDS_DB_NAME: &str = "word-attribute-docids";
 const DOCUMENTS_DB_NAME: &str = "documents";
 
 const ALL_DATABASE_NAMES: &[&str] = &[
@@ -31,6 +34,9 @@ const ALL_DATABASE_NAMES: &[&str] = &[
     DOCID_WORD_POSITIONS_DB_NAME,
     WORD_PAIR_PROXIMITY_DOCIDS_DB_NAME,
     WORD_PREFIX_PAIR_PROXIMITY_DOCIDS_DB_NAME,
+    PREFIX_WORD_PAIR_PROXIMITY_DOCIDS_DB_NAME,
+    WORD_POSITION_DOCIDS_DB_NAME,
+    WORD_ATTRIBUTE_DOCIDS_DB_NAME,
     FACET_FIELD_ID_VALUE_DOCIDS_NAME,
     FIELD_ID_DOCID_FACET_VALUES_NAME,
     DOCUMENTS_DB_NAME,
@@ -42,6 +48,9 @@ const POSTINGS_DATABASE_NAMES: &[&str] = &[
     DOCID_WORD_POSITIONS_DB_NAME,
     WORD_PAIR_PROXIMITY_DOCIDS_DB_NAME,
     WORD_PREFIX_PAIR_PROXIMITY_DOCIDS_DB_NAME,
+    PREFIX_WORD_PAIR_PROXIMITY_DOCIDS_DB_NAME,
+    WORD_POSITION_DOCIDS_DB_NAME,
+    WORD_ATTRIBUTE_DOCIDS_DB_NAME,
 ];
 
 #[derive(Debug, StructOpt)]
@@ -181,6 +190,15 @@ enum Command {
     /// you can install it using `cargo install fst-bin`.
     ExportWordsPrefixFst,
 
+    /// Outputs the FST of the words that appear in the given field to standard output.
+    ///
+    /// One can use the FST binary helper to dissect and analyze it,
+    /// you can install it using `cargo install fst-bin`.
+    ExportFieldWordsFst {
+        /// The field name in the document.
+        field_name: String,
+    },
+
     /// Outputs the documents as JSON lines to the standard output.
     ///
     /// All of the fields are extracted, not just the displayed ones.
@@ -236,6 +254,7 @@ fn main() -> anyhow::Result<()> {
         },
         ExportWordsFst => export_words_fst(&index, &rtxn),
         ExportWordsPrefixFst => export_words_prefix_fst(&index, &rtxn),
+        ExportFieldWordsFst { field_name } => export_field_words_fst(&index, &rtxn, &field_name),
         ExportDocuments { internal_documents_ids } => {
             export_documents(&index, &rtxn, internal_documents_ids)
         },
@@ -332,6 +351,7 @@ fn biggest_value_sizes(index: &Index, rtxn: &heed::RoTxn, limit: usize) -> anyho
         facet_field_id_value_docids,
         field_id_docid_facet_values: _,
         documents,
+        ..
     } = index;
 
     let main_name = "main";
@@ -617,21 +637,30 @@ fn facet_stats(index: &Index, rtxn: &heed::RoTxn, field_name: String) -> anyhow:
 }
 
 fn export_words_fst(index: &Index, rtxn: &heed::RoTxn) -> anyhow::Result<()> {
+    let stdout = io::stdout();
+    index.export_words_fst(rtxn, stdout.lock())
+}
+
+fn export_words_prefix_fst(index: &Index, rtxn: &heed::RoTxn) -> anyhow::Result<()> {
     use std::io::Write as _;
 
     let mut stdout = io::stdout();
-    let words_fst = index.words_fst(rtxn)?;
-    stdout.write_all(words_fst.as_fst().as_bytes())?;
+    let words_prefixes_fst = index.words_prefixes_fst(rtxn)?;
+    stdout.write_all(words_prefixes_fst.as_fst().as_bytes())?;
 
     Ok(())
 }
 
-fn export_words_prefix_fst(index: &Index, rtxn: &heed::RoTxn) -> anyhow::Result<()> {
+fn export_field_words_fst(index: &Index, rtxn: &heed::RoTxn, field_name: &str) -> anyhow::Result<()> {
     use std::io::Write as _;
 
+    let fields_ids_map = index.fields_ids_map(rtxn)?;
+    let field_id = fields_ids_map.id(field_name)
+        .with_context(|| format!("field {} not found", field_name))?;
+
     let mut stdout = io::stdout();
-    let words_prefixes_fst = index.words_prefixes_fst(rtxn)?;
-    stdout.write_all(words_prefixes_fst.as_fst().as_bytes())?;
+    let words_fst = index.field_words_fst(rtxn, field_id)?;
+    stdout.write_all(words_fst.as_fst().as_bytes())?;
 
     Ok(())
 }
@@ -746,6 +775,9 @@ fn size_of_databases(index: &Index, rtxn: &heed::RoTxn, names: Vec<String>) -> a
             DOCID_WORD_POSITIONS_DB_NAME => index.docid_word_positions.as_polymorph(),
             WORD_PAIR_PROXIMITY_DOCIDS_DB_NAME => index.word_pair_proximity_docids.as_polymorph(),
             WORD_PREFIX_PAIR_PROXIMITY_DOCIDS_DB_NAME => index.word_prefix_pair_proximity_docids.as_polymorph(),
+            PREFIX_WORD_PAIR_PROXIMITY_DOCIDS_DB_NAME => index.prefix_word_pair_proximity_docids.as_polymorph(),
+            WORD_POSITION_DOCIDS_DB_NAME => index.word_position_docids.as_polymorph(),
+            WORD_ATTRIBUTE_DOCIDS_DB_NAME => index.word_attribute_docids.as_polymorph(),
             FACET_FIELD_ID_VALUE_DOCIDS_NAME => index.facet_field_id_value_docids.as_polymorph(),
             FIELD_ID_DOCID_FACET_VALUES_NAME => index.field_id_docid_facet_values.as_polymorph(),
             DOCUMENTS_DB_NAME => index.documents.as_polymorph(),
@@ -846,6 +878,18 @@ fn database_stats(index: &Index, rtxn: &heed::RoTxn, name: &str) -> anyhow::Resu
             let db = index.word_prefix_pair_proximity_docids.as_polymorph();
             compute_stats::<CboRoaringBitmapCodec>(*db, rtxn, name)
         },
+        PREFIX_WORD_PAIR_PROXIMITY_DOCIDS_DB_NAME => {
+            let db = index.prefix_word_pair_proximity_docids.as_polymorph();
+            compute_stats::<CboRoaringBitmapCodec>(*db, rtxn, name)
+        },
+        WORD_POSITION_DOCIDS_DB_NAME => {
+            let db = index.word_position_docids.as_polymorph();
+            compute_stats::<CboRoaringBitmapCodec>(*db, rtxn, name)
+        },
+        WORD_ATTRIBUTE_DOCIDS_DB_NAME => {
+            let db = index.word_attribute_docids.as_polymorph();
+            compute_stats::<CboRoaringBitmapCodec>(*db, rtxn, name)
+        },
         unknown => anyhow::bail!("unknown database {:?}", unknown),
     }
 }
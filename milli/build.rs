@@ -0,0 +1,20 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Exposes the tag of the `meilisearch-tokenizer` dependency of the manifest
+/// as the `MILLI_TOKENIZER_VERSION` environment variable, see `IndexMetadata`.
+fn main() {
+    let manifest_path = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.toml");
+    println!("cargo:rerun-if-changed={}", manifest_path.display());
+
+    let manifest = fs::read_to_string(&manifest_path).expect("could not read the manifest");
+    let version = manifest.lines()
+        .find(|line| line.trim_start().starts_with("meilisearch-tokenizer"))
+        .and_then(|line| line.split("tag = \"").nth(1))
+        .and_then(|tag| tag.split('"').next())
+        .map(|tag| tag.trim_start_matches('v'))
+        .expect("the meilisearch-tokenizer dependency must be pinned to a tag");
+
+    println!("cargo:rustc-env=MILLI_TOKENIZER_VERSION={}", version);
+}
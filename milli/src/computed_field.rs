@@ -0,0 +1,111 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::{json_to_string, FieldsIdsMap};
+
+/// A field that is derived from the other fields of the documents at indexing time,
+/// it is stored in the documents and indexed like any other field.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ComputedField {
+    /// Joins the values of the fields with the separator, the missing fields are ignored
+    /// and the values of the arrays are joined like the values of the fields.
+    Concat { fields: Vec<String>, separator: String },
+    /// Lowercases the strings of the value of the field.
+    Lowercase { field: String },
+}
+
+impl ComputedField {
+    /// The names of the fields this computed field is derived from.
+    pub fn source_fields(&self) -> Vec<&str> {
+        match self {
+            ComputedField::Concat { fields, .. } => fields.iter().map(String::as_str).collect(),
+            ComputedField::Lowercase { field } => vec![field.as_str()],
+        }
+    }
+
+    /// Computes the value of this field for the given document,
+    /// returns `None` if none of the source fields is in the document.
+    pub(crate) fn compute(
+        &self,
+        fields_ids_map: &FieldsIdsMap,
+        document: &obkv::KvReader,
+    ) -> anyhow::Result<Option<Value>>
+    {
+        let value_of = |name: &str| -> anyhow::Result<Option<Value>> {
+            match fields_ids_map.id(name).and_then(|id| document.get(id)) {
+                Some(bytes) => Ok(Some(serde_json::from_slice(bytes)?)),
+                None => Ok(None),
+            }
+        };
+
+        match self {
+            ComputedField::Concat { fields, separator } => {
+                let mut strings = Vec::new();
+                for name in fields {
+                    if let Some(value) = value_of(name)? {
+                        push_strings(&value, &mut strings);
+                    }
+                }
+
+                if strings.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(Value::String(strings.join(separator))))
+                }
+            },
+            ComputedField::Lowercase { field } => Ok(value_of(field)?.map(lowercase_value)),
+        }
+    }
+}
+
+/// Pushes the strings of the value, an array pushes the strings of each one of its values.
+fn push_strings(value: &Value, strings: &mut Vec<String>) {
+    match value {
+        Value::Array(array) => array.iter().for_each(|value| push_strings(value, strings)),
+        value => strings.extend(json_to_string(value)),
+    }
+}
+
+fn lowercase_value(value: Value) -> Value {
+    match value {
+        Value::String(string) => Value::String(string.to_lowercase()),
+        Value::Array(array) => Value::Array(array.into_iter().map(lowercase_value).collect()),
+        Value::Object(object) => Value::Object(object.into_iter().map(|(k, v)| (k, lowercase_value(v))).collect()),
+        otherwise => otherwise,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn compute_fields() {
+        let mut fields_ids_map = FieldsIdsMap::new();
+        let first_name = fields_ids_map.insert("first_name").unwrap();
+        let last_name = fields_ids_map.insert("last_name").unwrap();
+        fields_ids_map.insert("code").unwrap();
+
+        let mut buffer = Vec::new();
+        let mut writer = obkv::KvWriter::new(&mut buffer);
+        writer.insert(first_name, br#""Kevin""#).unwrap();
+        writer.insert(last_name, br#"["Le", "Gall"]"#).unwrap();
+        writer.finish().unwrap();
+        let document = obkv::KvReader::new(&buffer);
+
+        let full_name = ComputedField::Concat {
+            fields: vec!["first_name".into(), "middle_name".into(), "last_name".into()],
+            separator: " ".into(),
+        };
+        assert_eq!(full_name.compute(&fields_ids_map, &document).unwrap(), Some(json!("Kevin Le Gall")));
+
+        let lowercase = ComputedField::Lowercase { field: "last_name".into() };
+        assert_eq!(lowercase.compute(&fields_ids_map, &document).unwrap(), Some(json!(["le", "gall"])));
+
+        let lowercase = ComputedField::Lowercase { field: "code".into() };
+        assert_eq!(lowercase.compute(&fields_ids_map, &document).unwrap(), None);
+    }
+}
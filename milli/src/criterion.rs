@@ -1,45 +1,123 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::str::FromStr;
 
 use anyhow::{Context, bail};
+use ordered_float::OrderedFloat;
 use regex::Regex;
 use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
 
 use crate::facet::FacetType;
 
+/// The weight of the attributes that don't declare one, see `Index::attributes_weights`.
+pub const DEFAULT_ATTRIBUTE_WEIGHT: u8 = 1;
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum Criterion {
-    /// Sorted by increasing number of typos.
-    Typo,
+    /// Sorted by increasing number of typos, the documents with at least `max` typos
+    /// are returned in the same bucket.
+    Typo { max: Option<u8> },
     /// Sorted by decreasing number of matched query terms.
     Words,
-    /// Sorted by increasing distance between matched query terms.
-    Proximity,
-    /// Documents with quey words contained in more important
-    /// attributes are considred better.
+    /// Sorted by increasing distance between matched query terms, the documents
+    /// with a distance of at least `max` are returned in the same bucket.
+    Proximity { max: Option<u8> },
+    /// Documents with query words contained in attributes with a bigger
+    /// weight are considered better, see `DEFAULT_ATTRIBUTE_WEIGHT`.
     Attribute,
     /// Documents with query words at the front of an attribute is
     /// considered better than if it was at the back.
     WordsPosition,
     /// Sorted by the similarity of the matched words with the query words.
     Exactness,
+    /// Sorted by the sort expressions given at query time, if any, see `Search::sort`.
+    Sort,
     /// Sorted by the increasing value of the field specified.
     Asc(String),
     /// Sorted by the decreasing value of the field specified.
     Desc(String),
+    /// Sorted by the increasing distance between the value of the numeric field specified
+    /// and the origin, the documents at the same number of `scale` of it are equal.
+    Decay { field: String, origin: OrderedFloat<f64>, scale: OrderedFloat<f64> },
+    /// Sorted by a ranking rule defined outside of this crate and registered under
+    /// this name at search time, see `CriteriaBuilder::register_criterion`.
+    Custom(String),
+    /// Shuffles the documents of the previous buckets in an order determined by the seed,
+    /// the same seed always returns the documents in the same order.
+    Random(u64),
+    /// Reorders the documents of the previous buckets so that no more than `max` consecutive
+    /// documents share the same value of the faceted field, when other documents allow it.
+    /// It is meant to be the last criterion, the documents are returned one by one.
+    Diversify { field: String, max: u8 },
 }
 
 impl Criterion {
     pub fn from_str(faceted_attributes: &HashMap<String, FacetType>, txt: &str) -> anyhow::Result<Criterion> {
         match txt {
-            "typo" => Ok(Criterion::Typo),
+            "typo" => Ok(Criterion::Typo { max: None }),
             "words" => Ok(Criterion::Words),
-            "proximity" => Ok(Criterion::Proximity),
+            "proximity" => Ok(Criterion::Proximity { max: None }),
             "attribute" => Ok(Criterion::Attribute),
-            "wordsposition" => Ok(Criterion::WordsPosition),
+            "wordsposition" | "wordsPosition" => Ok(Criterion::WordsPosition),
             "exactness" => Ok(Criterion::Exactness),
+            "sort" => Ok(Criterion::Sort),
+            text if text.starts_with("typo(") => {
+                let max = parse_max_parameter("typo", text)?;
+                Ok(Criterion::Typo { max: Some(max) })
+            },
+            text if text.starts_with("proximity(") => {
+                let max = parse_max_parameter("proximity", text)?;
+                Ok(Criterion::Proximity { max: Some(max) })
+            },
+            text if text.starts_with("random") => {
+                let re = Regex::new(r#"^random\((\d+)\)$"#)?;
+                let caps = re.captures(text).with_context(|| format!("invalid random criterion: {}, expected `random(seed)`", text))?;
+                let seed = caps.get(1).unwrap().as_str().parse().with_context(|| format!("invalid random seed: {}", text))?;
+                Ok(Criterion::Random(seed))
+            },
+            text if text.starts_with("decay(") => {
+                let re = Regex::new(r#"^decay\(([\w_.-]+),\s*origin=([^,\s]+),\s*scale=([^,\s]+)\)$"#)?;
+                let caps = re.captures(text).with_context(|| {
+                    format!("invalid decay criterion: {}, expected `decay(field,origin=value,scale=value)`", text)
+                })?;
+                let field_name = caps.get(1).unwrap().as_str();
+                let origin: f64 = caps.get(2).unwrap().as_str().parse().with_context(|| format!("invalid origin parameter: {}", text))?;
+                let scale: f64 = caps.get(3).unwrap().as_str().parse().with_context(|| format!("invalid scale parameter: {}", text))?;
+                if !origin.is_finite() || !scale.is_finite() || scale <= 0.0 {
+                    bail!("invalid decay criterion: {}, the origin must be finite and the scale positive", text);
+                }
+                match faceted_attributes.get(field_name) {
+                    Some(FacetType::Integer) | Some(FacetType::Float) => (),
+                    Some(FacetType::String) => bail!("Can't use {:?} as a decay criterion as it isn't a numeric field.", field_name),
+                    None => bail!("Can't use {:?} as a criterion as it isn't a faceted field.", field_name),
+                }
+                let (origin, scale) = (OrderedFloat(origin), OrderedFloat(scale));
+                Ok(Criterion::Decay { field: field_name.to_string(), origin, scale })
+            },
+            text if text.starts_with("custom(") => {
+                let re = Regex::new(r#"^custom\(([\w_.-]+)\)$"#)?;
+                let caps = re.captures(text).with_context(|| format!("invalid custom criterion: {}, expected `custom(name)`", text))?;
+                Ok(Criterion::Custom(caps.get(1).unwrap().as_str().to_string()))
+            },
+            text if text.starts_with("diversify(") => {
+                let re = Regex::new(r#"^diversify\(([\w_.-]+)(?:,\s*max=(\d+))?\)$"#)?;
+                let caps = re.captures(text).with_context(|| {
+                    format!("invalid diversify criterion: {}, expected `diversify(field)` or `diversify(field,max=value)`", text)
+                })?;
+                let field_name = caps.get(1).unwrap().as_str();
+                let max = match caps.get(2) {
+                    Some(max) => max.as_str().parse().with_context(|| format!("invalid max parameter: {}", text))?,
+                    None => 1,
+                };
+                if max == 0 {
+                    bail!("invalid diversify criterion: {}, the max parameter must be at least 1", text);
+                }
+                faceted_attributes.get(field_name).with_context(|| format!("Can't use {:?} as a criterion as it isn't a faceted field.", field_name))?;
+                Ok(Criterion::Diversify { field: field_name.to_string(), max })
+            },
             text => {
-                let re = Regex::new(r#"(asc|desc)\(([\w_-]+)\)"#)?;
+                let re = Regex::new(r#"(asc|desc)\(([\w_.-]+)\)"#)?;
                 let caps = re.captures(text).with_context(|| format!("unknown criterion name: {}", text))?;
                 let order = caps.get(1).unwrap().as_str();
                 let field_name = caps.get(2).unwrap().as_str();
@@ -54,30 +132,149 @@ impl Criterion {
     }
 }
 
+/// Parses the `max` parameter of a criterion, e.g. the `1` of `typo(max=1)`.
+fn parse_max_parameter(name: &str, text: &str) -> anyhow::Result<u8> {
+    let re = Regex::new(&format!(r#"^{}\(max=(\d+)\)$"#, name))?;
+    let caps = re.captures(text).with_context(|| {
+        format!("invalid {} criterion: {}, expected `{}(max=value)`", name, text, name)
+    })?;
+    caps.get(1).unwrap().as_str().parse().with_context(|| format!("invalid max parameter: {}", text))
+}
+
+/// Where the documents that doesn't have a value for the sorted field are returned.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum NullsPlacement {
+    /// The documents without value are returned before the others.
+    First,
+    /// The documents without value are returned after the others.
+    Last,
+}
+
+impl Default for NullsPlacement {
+    fn default() -> NullsPlacement {
+        NullsPlacement::Last
+    }
+}
+
+/// A sort expression given at query time, like `price:asc` or `price:desc:nulls_first`.
+///
+/// The field must be declared as sortable in the index settings.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AscDesc {
+    field: String,
+    ascending: bool,
+    nulls: NullsPlacement,
+}
+
+impl AscDesc {
+    pub fn asc(field: impl Into<String>) -> AscDesc {
+        AscDesc { field: field.into(), ascending: true, nulls: NullsPlacement::default() }
+    }
+
+    pub fn desc(field: impl Into<String>) -> AscDesc {
+        AscDesc { field: field.into(), ascending: false, nulls: NullsPlacement::default() }
+    }
+
+    /// Defines where the documents without a value for this field are returned.
+    pub fn nulls(mut self, nulls: NullsPlacement) -> AscDesc {
+        self.nulls = nulls;
+        self
+    }
+
+    pub fn nulls_placement(&self) -> NullsPlacement {
+        self.nulls
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn is_ascending(&self) -> bool {
+        self.ascending
+    }
+}
+
+impl FromStr for AscDesc {
+    type Err = anyhow::Error;
+
+    fn from_str(txt: &str) -> anyhow::Result<AscDesc> {
+        let (expr, nulls) = if let Some(expr) = txt.strip_suffix(":nulls_first") {
+            (expr, NullsPlacement::First)
+        } else if let Some(expr) = txt.strip_suffix(":nulls_last") {
+            (expr, NullsPlacement::Last)
+        } else {
+            (txt, NullsPlacement::default())
+        };
+
+        let (field, order) = match expr.rfind(':') {
+            Some(index) => (&expr[..index], &expr[index + 1..]),
+            None => bail!("invalid sort expression {:?}, expected `field:asc` or `field:desc`", txt),
+        };
+
+        match order {
+            _ if field.is_empty() => bail!("invalid sort expression {:?}, the field is missing", txt),
+            "asc" => Ok(AscDesc::asc(field).nulls(nulls)),
+            "desc" => Ok(AscDesc::desc(field).nulls(nulls)),
+            otherwise => bail!("invalid sort order {:?}, expected `asc` or `desc`", otherwise),
+        }
+    }
+}
+
+impl fmt::Display for AscDesc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let order = if self.ascending { "asc" } else { "desc" };
+        match self.nulls {
+            NullsPlacement::First => write!(f, "{}:{}:nulls_first", self.field, order),
+            NullsPlacement::Last => write!(f, "{}:{}", self.field, order),
+        }
+    }
+}
+
 pub fn default_criteria() -> Vec<Criterion> {
     vec![
-        Criterion::Typo,
+        Criterion::Typo { max: None },
         Criterion::Words,
-        Criterion::Proximity,
+        Criterion::Proximity { max: None },
         Criterion::Attribute,
         Criterion::WordsPosition,
         Criterion::Exactness,
     ]
 }
 
+/// The typo and proximity criteria were unit variants before they accepted a maximum,
+/// they are stored as `"Typo"` and `"Proximity"` by the previous versions and are
+/// converted to the current encoding before being deserialized.
+pub(crate) fn upgrade_stored_criterion(criterion: Value) -> Value {
+    match criterion {
+        Value::String(name) if name == "Typo" || name == "Proximity" => {
+            let mut object = serde_json::Map::new();
+            object.insert(name, json!({ "max": null }));
+            Value::Object(object)
+        },
+        otherwise => otherwise,
+    }
+}
+
 impl fmt::Display for Criterion {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Criterion::*;
 
         match self {
-            Typo            => f.write_str("typo"),
-            Words           => f.write_str("words"),
-            Proximity       => f.write_str("proximity"),
-            Attribute       => f.write_str("attribute"),
-            WordsPosition   => f.write_str("wordsPosition"),
-            Exactness       => f.write_str("exactness"),
-            Asc(attr)       => write!(f, "asc({})", attr),
-            Desc(attr)      => write!(f, "desc({})", attr),
+            Typo { max: None }              => f.write_str("typo"),
+            Typo { max: Some(max) }         => write!(f, "typo(max={})", max),
+            Words                           => f.write_str("words"),
+            Proximity { max: None }         => f.write_str("proximity"),
+            Proximity { max: Some(max) }    => write!(f, "proximity(max={})", max),
+            Attribute                       => f.write_str("attribute"),
+            WordsPosition                   => f.write_str("wordsPosition"),
+            Exactness                       => f.write_str("exactness"),
+            Sort                            => f.write_str("sort"),
+            Asc(attr)                       => write!(f, "asc({})", attr),
+            Desc(attr)                      => write!(f, "desc({})", attr),
+            Decay { field, origin, scale }  => write!(f, "decay({},origin={},scale={})", field, origin, scale),
+            Custom(name)                    => write!(f, "custom({})", name),
+            Random(seed)                    => write!(f, "random({})", seed),
+            Diversify { field, max }        => write!(f, "diversify({},max={})", field, max),
         }
     }
 }
@@ -1,6 +1,9 @@
 use std::borrow::Cow;
 use std::convert::TryInto;
-use fst::{Streamer, IntoStreamer};
+use std::ops::{Bound, RangeBounds};
+use std::str;
+use fst::{Automaton, Streamer, IntoStreamer};
+use roaring::RoaringBitmap;
 
 pub struct ExternalDocumentsIds<'a> {
     pub(crate) hard: fst::Map<Cow<'a, [u8]>>,
@@ -28,6 +31,57 @@ impl<'a> ExternalDocumentsIds<'a> {
         }
     }
 
+    /// Returns the internal ids of the documents whose external id is an integer in the range,
+    /// the deleted documents are ignored.
+    ///
+    /// The integers of a given length and sign sort like their representations, the ids are
+    /// looked up by length, only the external ids of that length between the bounds are read.
+    pub fn integer_ids<R: RangeBounds<i64>>(&self, range: R) -> RoaringBitmap {
+        let start = match range.start_bound() {
+            Bound::Included(x) => *x as i128,
+            Bound::Excluded(x) => *x as i128 + 1,
+            Bound::Unbounded => i64::MIN as i128,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(x) => *x as i128,
+            Bound::Excluded(x) => *x as i128 - 1,
+            Bound::Unbounded => i64::MAX as i128,
+        };
+
+        let mut ids = RoaringBitmap::new();
+        // The representation of i64::MIN is the longest one, it is 20 bytes long.
+        for len in 1..=20 {
+            let positives = (if len == 1 { 0 } else { 10i128.pow(len - 1) }, 10i128.pow(len) - 1);
+            let negatives = (1 - 10i128.pow(len - 1), if len <= 2 { -1 } else { -10i128.pow(len - 2) });
+
+            for (min, max) in vec![positives, negatives] {
+                let (lo, hi) = (min.max(start), max.min(end));
+                if lo > hi { continue }
+
+                // The bigger a negative integer is, the smaller its representation is.
+                let (first, last) = if hi < 0 { (hi, lo) } else { (lo, hi) };
+                let (first, last) = (first.to_string(), last.to_string());
+                let automaton = ExactLength(len as usize);
+                let hard = self.hard.search(automaton).ge(&first).le(&last);
+                let soft = self.soft.search(automaton).ge(&first).le(&last);
+                let union_op = fst::map::OpBuilder::new().add(hard).add(soft).r#union();
+
+                let mut iter = union_op.into_stream();
+                while let Some((external_id, docids)) = iter.next() {
+                    // The soft map (index 1) always overrides the hard map (index 0).
+                    let id = docids.iter().find(|v| v.index == 1).unwrap_or(&docids[0]).value;
+                    let integer = str::from_utf8(external_id).ok().and_then(|id| id.parse::<i64>().ok());
+                    // u64 MAX means deleted in the soft fst map
+                    if id != u64::MAX && integer.map_or(false, |i| (lo..=hi).contains(&(i as i128))) {
+                        ids.insert(id.try_into().unwrap());
+                    }
+                }
+            }
+        }
+
+        ids
+    }
+
     pub fn delete_ids<A: AsRef<[u8]>>(&mut self, other: fst::Set<A>) -> fst::Result<()> {
         let other = fst::Map::from(other.into_fst());
         let union_op = self.soft.op().add(&other).r#union();
@@ -68,32 +122,76 @@ impl<'a> ExternalDocumentsIds<'a> {
         self.merge_soft_into_hard()
     }
 
-    fn merge_soft_into_hard(&mut self) -> fst::Result<()> {
-        if self.soft.len() >= self.hard.len() / 2 {
-            let union_op = self.hard.op().add(&self.soft).r#union();
-
-            let mut iter = union_op.into_stream();
-            let mut new_hard_builder = fst::MapBuilder::memory();
-            while let Some((external_id, docids)) = iter.next() {
-                if docids.len() == 2 {
-                    if docids[1].value != u64::MAX {
-                        new_hard_builder.insert(external_id, docids[1].value)?;
-                    }
-                } else {
-                    new_hard_builder.insert(external_id, docids[0].value)?;
+    /// Merges the soft map into the hard one, the ids marked as deleted are purged.
+    pub fn purge_soft_ids(&mut self) -> fst::Result<()> {
+        let union_op = self.hard.op().add(&self.soft).r#union();
+
+        let mut iter = union_op.into_stream();
+        let mut new_hard_builder = fst::MapBuilder::memory();
+        while let Some((external_id, docids)) = iter.next() {
+            // The soft map (index 1) always overrides the hard map (index 0).
+            let id = docids.iter().find(|v| v.index == 1).unwrap_or(&docids[0]).value;
+            if id != u64::MAX {
+                new_hard_builder.insert(external_id, id)?;
+            }
+        }
+
+        drop(iter);
+
+        self.hard = new_hard_builder.into_map().map_data(Cow::Owned)?;
+        self.soft = fst::Map::default().map_data(Cow::Owned)?;
+
+        Ok(())
+    }
+
+    /// Returns a new map where the internal ids are replaced by the ones returned by the
+    /// function, the deleted documents and the ids for which it returns `None` are dropped.
+    pub fn remap_ids<F: FnMut(u32) -> Option<u32>>(&self, mut f: F) -> fst::Result<ExternalDocumentsIds<'static>> {
+        let union_op = self.hard.op().add(&self.soft).r#union();
+
+        let mut iter = union_op.into_stream();
+        let mut new_hard_builder = fst::MapBuilder::memory();
+        while let Some((external_id, docids)) = iter.next() {
+            // The soft map (index 1) always overrides the hard map (index 0).
+            let id = docids.iter().find(|v| v.index == 1).unwrap_or(&docids[0]).value;
+            if id != u64::MAX {
+                if let Some(new_id) = f(id.try_into().unwrap()) {
+                    new_hard_builder.insert(external_id, new_id as u64)?;
                 }
             }
+        }
+
+        let hard = new_hard_builder.into_map().map_data(Cow::Owned)?;
+        let soft = fst::Map::default().map_data(Cow::Owned)?;
 
-            drop(iter);
+        Ok(ExternalDocumentsIds { hard, soft })
+    }
 
-            self.hard = new_hard_builder.into_map().map_data(Cow::Owned)?;
-            self.soft = fst::Map::default().map_data(Cow::Owned)?;
+    fn merge_soft_into_hard(&mut self) -> fst::Result<()> {
+        if self.soft.len() >= self.hard.len() / 2 {
+            self.purge_soft_ids()?;
         }
 
         Ok(())
     }
 }
 
+/// Matches the keys that are exactly the given number of bytes long.
+#[derive(Debug, Clone, Copy)]
+struct ExactLength(usize);
+
+impl Automaton for ExactLength {
+    type State = usize;
+
+    fn start(&self) -> usize { 0 }
+
+    fn is_match(&self, state: &usize) -> bool { *state == self.0 }
+
+    fn can_match(&self, state: &usize) -> bool { *state <= self.0 }
+
+    fn accept(&self, state: &usize, _byte: u8) -> usize { state + 1 }
+}
+
 impl Default for ExternalDocumentsIds<'static> {
     fn default() -> Self {
         ExternalDocumentsIds {
@@ -152,5 +250,53 @@ mod tests {
         assert_eq!(external_documents_ids.get("f"), None);
         assert_eq!(external_documents_ids.get("g"), Some(7));
         assert_eq!(external_documents_ids.get("h"), Some(8));
+
+    }
+
+    #[test]
+    fn integer_ids() {
+        let mut external_documents_ids = ExternalDocumentsIds::default();
+
+        let new_ids = vec![("-12", 0), ("-3", 1), ("0", 2), ("1000", 3), ("1500", 4), ("2000", 5), ("500", 6), ("abc", 7)];
+        let new_ids = fst::Map::from_iter(new_ids).unwrap();
+        external_documents_ids.insert_ids(&new_ids).unwrap();
+
+        let del_ids = fst::Set::from_iter(vec!["1500"]).unwrap();
+        external_documents_ids.delete_ids(del_ids).unwrap();
+
+        let ids = external_documents_ids.integer_ids(1000..2000);
+        assert_eq!(ids.iter().collect::<Vec<_>>(), vec![3]);
+
+        let ids = external_documents_ids.integer_ids(..=0);
+        assert_eq!(ids.iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        let ids = external_documents_ids.integer_ids(-5..);
+        assert_eq!(ids.iter().collect::<Vec<_>>(), vec![1, 2, 3, 5, 6]);
+
+        let ids = external_documents_ids.integer_ids(..);
+        assert_eq!(ids.len(), 6);
+    }
+
+    #[test]
+    fn purge_soft_ids() {
+        let mut external_documents_ids = ExternalDocumentsIds::default();
+
+        let new_ids = (b'a'..=b'h').map(|c| (vec![c], c as u64)).collect::<Vec<_>>();
+        let new_ids = fst::Map::from_iter(new_ids).unwrap();
+        external_documents_ids.insert_ids(&new_ids).unwrap();
+
+        // The soft map is small enough to not be merged into the hard one.
+        let new_ids = fst::Map::from_iter(vec![("i", 105)]).unwrap();
+        external_documents_ids.insert_ids(&new_ids).unwrap();
+        let del_ids = fst::Set::from_iter(vec!["a"]).unwrap();
+        external_documents_ids.delete_ids(del_ids).unwrap();
+        assert_eq!(external_documents_ids.soft.len(), 2);
+
+        external_documents_ids.purge_soft_ids().unwrap();
+        assert!(external_documents_ids.soft.is_empty());
+        assert_eq!(external_documents_ids.hard.len(), 8);
+        assert_eq!(external_documents_ids.get("a"), None);
+        assert_eq!(external_documents_ids.get("b"), Some(98));
+        assert_eq!(external_documents_ids.get("i"), Some(105));
     }
 }
@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Deserialize};
+
+/// Defines which differences between two string facet values are
+/// significant when they are compared to sort documents.
+///
+/// Note that the string facet values are compared in their normalized form, lowercased by
+/// default, see [`FacetStringNormalization`](super::FacetStringNormalization).
+#[derive(Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize)]
+pub enum CollationStrength {
+    /// Only the base letters are compared, "école" and "ecole" are considered equal.
+    Primary,
+    /// The base letters are compared first then the accents, "ecole" is sorted before "école".
+    Secondary,
+}
+
+impl Default for CollationStrength {
+    fn default() -> CollationStrength {
+        CollationStrength::Primary
+    }
+}
+
+impl CollationStrength {
+    /// Returns a key that sorts the strings according to this collation strength,
+    /// two strings are equal for this collation strength if their keys are equal.
+    pub fn collation_key(self, value: &str) -> String {
+        let mut key = deunicode::deunicode(value).to_lowercase();
+        if self == CollationStrength::Secondary {
+            key.push('\0');
+            key.push_str(value);
+        }
+        key
+    }
+}
+
+impl fmt::Display for CollationStrength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CollationStrength::Primary => f.write_str("primary"),
+            CollationStrength::Secondary => f.write_str("secondary"),
+        }
+    }
+}
+
+impl FromStr for CollationStrength {
+    type Err = InvalidCollationStrength;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("primary") {
+            Ok(CollationStrength::Primary)
+        } else if s.eq_ignore_ascii_case("secondary") {
+            Ok(CollationStrength::Secondary)
+        } else {
+            Err(InvalidCollationStrength)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct InvalidCollationStrength;
+
+impl fmt::Display for InvalidCollationStrength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(r#"Invalid collation strength, must be "primary" or "secondary""#)
+    }
+}
+
+impl Error for InvalidCollationStrength { }
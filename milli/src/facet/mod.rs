@@ -1,6 +1,10 @@
+mod collation;
 mod facet_type;
 mod facet_value;
+mod normalization;
 pub mod value_encoding;
 
+pub use self::collation::CollationStrength;
 pub use self::facet_type::FacetType;
 pub use self::facet_value::FacetValue;
+pub use self::normalization::FacetStringNormalization;
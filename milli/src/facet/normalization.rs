@@ -0,0 +1,55 @@
+use serde::{Serialize, Deserialize};
+
+/// Defines which differences between two string facet values of a field are
+/// ignored when they are indexed and when they are compared by the filters.
+///
+/// The values are indexed in their normalized form, the original values are kept
+/// for the fields that define a normalization, to be displayed instead of the keys.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct FacetStringNormalization {
+    /// "Paris" and "paris" are considered equal.
+    pub ignore_case: bool,
+    /// "Pâtisserie" and "Patisserie" are considered equal.
+    pub ignore_diacritics: bool,
+}
+
+impl Default for FacetStringNormalization {
+    fn default() -> FacetStringNormalization {
+        FacetStringNormalization { ignore_case: true, ignore_diacritics: false }
+    }
+}
+
+impl FacetStringNormalization {
+    /// Returns the key under which the value is indexed and compared, two values
+    /// are equal for this normalization if their keys are equal.
+    pub fn normalize(&self, value: &str) -> String {
+        let value = value.trim();
+        let value = if self.ignore_diacritics {
+            deunicode::deunicode(value)
+        } else {
+            value.to_string()
+        };
+
+        if self.ignore_case { value.to_lowercase() } else { value }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        let default = FacetStringNormalization::default();
+        assert_eq!(default.normalize(" Pâtisserie "), "pâtisserie");
+
+        let exact = FacetStringNormalization { ignore_case: false, ignore_diacritics: false };
+        assert_eq!(exact.normalize("Pâtisserie"), "Pâtisserie");
+
+        let both = FacetStringNormalization { ignore_case: true, ignore_diacritics: true };
+        assert_eq!(both.normalize("Pâtisserie"), "patisserie");
+        assert_eq!(both.normalize("PATISSERIE"), both.normalize("pâtisserie"));
+    }
+}
@@ -2,11 +2,14 @@ mod beu32_str_codec;
 mod obkv_codec;
 mod roaring_bitmap;
 mod roaring_bitmap_length;
+mod str_beu32_codec;
 mod str_str_u8_codec;
 pub mod facet;
 
 pub use self::beu32_str_codec::BEU32StrCodec;
 pub use self::obkv_codec::ObkvCodec;
 pub use self::roaring_bitmap::{BoRoaringBitmapCodec, CboRoaringBitmapCodec, RoaringBitmapCodec};
+pub use self::roaring_bitmap::{RoaringBitmapProbe, RoaringBitmapProbeCodec};
 pub use self::roaring_bitmap_length::{BoRoaringBitmapLenCodec, CboRoaringBitmapLenCodec, RoaringBitmapLenCodec};
+pub use self::str_beu32_codec::StrBEU32Codec;
 pub use self::str_str_u8_codec::StrStrU8Codec;
@@ -0,0 +1,199 @@
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::mem;
+
+use roaring::RoaringBitmap;
+
+const SERIAL_COOKIE_NO_RUNCONTAINER: u32 = 12346;
+/// The containers with this cardinality or less are serialized as sorted arrays of `u16`.
+const ARRAY_LIMIT: u64 = 4096;
+/// The containers with a greater cardinality are serialized as 65536 bits.
+const BITMAP_CONTAINER_BYTES: usize = 1024 * mem::size_of::<u64>();
+
+/// Decodes a serialized [`RoaringBitmap`] into a [`RoaringBitmapProbe`] that borrows the bytes.
+pub struct RoaringBitmapProbeCodec;
+
+impl<'a> heed::BytesDecode<'a> for RoaringBitmapProbeCodec {
+    type DItem = RoaringBitmapProbe<'a>;
+
+    fn bytes_decode(bytes: &'a [u8]) -> Option<Self::DItem> {
+        RoaringBitmapProbe::new(bytes)
+    }
+}
+
+/// A serialized roaring bitmap that is queried without being deserialized,
+/// only the containers of the probed documents ids are read.
+///
+/// It is much cheaper than deserializing a big bitmap to intersect it with a few candidates.
+#[derive(Clone, Copy)]
+pub struct RoaringBitmapProbe<'a> {
+    /// The key and the cardinality minus one of every container, in keys order.
+    descriptions: &'a [u8],
+    /// The position of every container in the bytes.
+    offsets: &'a [u8],
+    bytes: &'a [u8],
+}
+
+impl<'a> RoaringBitmapProbe<'a> {
+    /// Reads the header of the serialized bitmap, returns `None` if it is invalid
+    /// or if it uses run containers, they are never produced by the roaring crate.
+    pub fn new(bytes: &'a [u8]) -> Option<RoaringBitmapProbe<'a>> {
+        let cookie = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?);
+        if cookie != SERIAL_COOKIE_NO_RUNCONTAINER {
+            return None;
+        }
+
+        let size = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?) as usize;
+        if size > u16::max_value() as usize + 1 {
+            return None;
+        }
+
+        let descriptions = bytes.get(8..8 + size * 4)?;
+        let offsets = bytes.get(8 + size * 4..8 + size * 8)?;
+        Some(RoaringBitmapProbe { descriptions, offsets, bytes })
+    }
+
+    /// Returns the number of documents ids in the bitmap, only the header is read.
+    pub fn len(&self) -> u64 {
+        (0..self.containers_count()).map(|i| self.cardinality(i)).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.containers_count() == 0
+    }
+
+    /// Returns whether the bitmap contains the given value.
+    pub fn contains(&self, value: u32) -> bool {
+        let (key, low) = split(value);
+        self.find_container(key).map_or(false, |container| container.contains(low))
+    }
+
+    /// Returns the values of the given candidates that are part of this bitmap,
+    /// every container is looked up once and only the ones of the candidates are read.
+    pub fn intersection(&self, candidates: &RoaringBitmap) -> RoaringBitmap {
+        let mut current: Option<(u16, Option<Container>)> = None;
+        let values = candidates.iter().filter(|value| {
+            let (key, low) = split(*value);
+            let container = match current {
+                Some((current_key, container)) if current_key == key => container,
+                _ => {
+                    let container = self.find_container(key);
+                    current = Some((key, container));
+                    container
+                },
+            };
+            container.map_or(false, |container| container.contains(low))
+        });
+
+        RoaringBitmap::from_sorted_iter(values)
+    }
+
+    fn containers_count(&self) -> usize {
+        self.descriptions.len() / 4
+    }
+
+    fn key(&self, index: usize) -> u16 {
+        let bytes = &self.descriptions[index * 4..];
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    }
+
+    fn cardinality(&self, index: usize) -> u64 {
+        let bytes = &self.descriptions[index * 4 + 2..];
+        u64::from(u16::from_le_bytes([bytes[0], bytes[1]])) + 1
+    }
+
+    fn find_container(&self, key: u16) -> Option<Container<'a>> {
+        let (mut low, mut high) = (0, self.containers_count());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.key(middle).cmp(&key) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return self.container(middle),
+            }
+        }
+        None
+    }
+
+    fn container(&self, index: usize) -> Option<Container<'a>> {
+        let offset = self.offsets.get(index * 4..index * 4 + 4)?;
+        let offset = u32::from_le_bytes(offset.try_into().ok()?) as usize;
+        let cardinality = self.cardinality(index);
+        if cardinality <= ARRAY_LIMIT {
+            let len = cardinality as usize * mem::size_of::<u16>();
+            self.bytes.get(offset..offset + len).map(Container::Array)
+        } else {
+            self.bytes.get(offset..offset + BITMAP_CONTAINER_BYTES).map(Container::Bitmap)
+        }
+    }
+}
+
+/// Returns the container key and the value in the container of a value.
+fn split(value: u32) -> (u16, u16) {
+    ((value >> 16) as u16, value as u16)
+}
+
+#[derive(Clone, Copy)]
+enum Container<'a> {
+    /// The little-endian sorted values.
+    Array(&'a [u8]),
+    /// The little-endian `u64` words of the bits of the values.
+    Bitmap(&'a [u8]),
+}
+
+impl Container<'_> {
+    fn contains(&self, value: u16) -> bool {
+        match self {
+            Container::Array(bytes) => {
+                let (mut low, mut high) = (0, bytes.len() / 2);
+                while low < high {
+                    let middle = low + (high - low) / 2;
+                    let current = u16::from_le_bytes([bytes[middle * 2], bytes[middle * 2 + 1]]);
+                    match current.cmp(&value) {
+                        Ordering::Less => low = middle + 1,
+                        Ordering::Greater => high = middle,
+                        Ordering::Equal => return true,
+                    }
+                }
+                false
+            },
+            Container::Bitmap(bytes) => {
+                let byte = bytes[value as usize / 8];
+                byte & (1 << (value % 8)) != 0
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heed::{BytesDecode, BytesEncode};
+    use crate::heed_codec::RoaringBitmapCodec;
+
+    #[test]
+    fn probe_serialized_bitmap() {
+        let bitmap: RoaringBitmap = (0..500).chain(800..800_000).chain(920_056..930_032).step_by(3).collect();
+        let bytes = RoaringBitmapCodec::bytes_encode(&bitmap).unwrap();
+        let probe = RoaringBitmapProbeCodec::bytes_decode(&bytes).unwrap();
+
+        assert_eq!(probe.len(), bitmap.len());
+        for value in (0..1_000_000).step_by(7) {
+            assert_eq!(probe.contains(value), bitmap.contains(value), "{}", value);
+        }
+
+        let candidates: RoaringBitmap = (0..1_000_000).step_by(11).chain(2_000_000..2_000_010).collect();
+        assert_eq!(probe.intersection(&candidates), &bitmap & &candidates);
+    }
+
+    #[test]
+    fn probe_empty_bitmap() {
+        let bytes = RoaringBitmapCodec::bytes_encode(&RoaringBitmap::new()).unwrap();
+        let probe = RoaringBitmapProbeCodec::bytes_decode(&bytes).unwrap();
+
+        assert!(probe.is_empty());
+        assert!(!probe.contains(0));
+        assert!(probe.intersection(&(0..100).collect()).is_empty());
+    }
+}
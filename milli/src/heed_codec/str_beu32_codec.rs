@@ -0,0 +1,34 @@
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::str;
+
+/// Encodes a string followed by a zero byte and a big-endian `u32`, the zero byte makes sure
+/// that the entries of a string are not mixed with the ones of the strings it is a prefix of.
+pub struct StrBEU32Codec;
+
+impl<'a> heed::BytesDecode<'a> for StrBEU32Codec {
+    type DItem = (&'a str, u32);
+
+    fn bytes_decode(bytes: &'a [u8]) -> Option<Self::DItem> {
+        let footer_len = 1 + 4;
+        if bytes.len() < footer_len { return None }
+        let (str_bytes, footer) = bytes.split_at(bytes.len() - footer_len);
+        let (zero, n_bytes) = footer.split_first()?;
+        if *zero != 0 { return None }
+        let s = str::from_utf8(str_bytes).ok()?;
+        let n = n_bytes.try_into().map(u32::from_be_bytes).ok()?;
+        Some((s, n))
+    }
+}
+
+impl<'a> heed::BytesEncode<'a> for StrBEU32Codec {
+    type EItem = (&'a str, u32);
+
+    fn bytes_encode((s, n): &Self::EItem) -> Option<Cow<[u8]>> {
+        let mut bytes = Vec::with_capacity(s.len() + 1 + 4);
+        bytes.extend_from_slice(s.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&n.to_be_bytes());
+        Some(Cow::Owned(bytes))
+    }
+}
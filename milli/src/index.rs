@@ -1,36 +1,119 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
+use std::io;
 use std::path::Path;
+use std::sync::Arc;
 
 use anyhow::Context;
 use heed::types::*;
-use heed::{PolyDatabase, Database, RwTxn, RoTxn};
+use heed::{PolyDatabase, Database, RwTxn, RoTxn, BytesDecode, BytesEncode, CompactionOption};
 use roaring::RoaringBitmap;
 use chrono::{Utc, DateTime};
+use serde::{Deserialize, Serialize};
 
-use crate::facet::FacetType;
+use crate::facet::{CollationStrength, FacetStringNormalization, FacetType};
 use crate::fields_ids_map::FieldsIdsMap;
-use crate::{default_criteria, Criterion, Search, FacetDistribution};
-use crate::{BEU32, DocumentId, FieldId, ExternalDocumentsIds};
+use crate::proximity::positions_attributes;
+use crate::search::{DistinctMode, SearchCache, SearchDefaults, StoredQuery, DEFAULT_MAX_NGRAM};
+use crate::storage::{HeedReader, HeedWriter, IndexDatabase};
+use crate::criterion::upgrade_stored_criterion;
+use crate::update::{MaxPositionPolicy, MissingDatabases, SettingsSnapshot};
+use crate::{default_criteria, ComputedField, Criterion, Search, FacetDistribution, FacetValues};
+use crate::{BEU32, DocumentId, FieldId, ExternalDocumentsIds, IndexSnapshot};
 use crate::{
-    RoaringBitmapCodec, RoaringBitmapLenCodec, BEU32StrCodec,
-    StrStrU8Codec, ObkvCodec, BoRoaringBitmapCodec, CboRoaringBitmapCodec,
+    RoaringBitmapCodec, RoaringBitmapLenCodec, RoaringBitmapProbeCodec, BEU32StrCodec,
+    StrBEU32Codec, StrStrU8Codec, ObkvCodec, BoRoaringBitmapCodec, CboRoaringBitmapCodec,
 };
 
+pub const ATTRIBUTES_LANGUAGES_KEY: &str = "attributes-languages";
+pub const ATTRIBUTES_STOP_WORDS_KEY: &str = "attributes-stop-words";
+pub const ATTRIBUTES_WEIGHTS_KEY: &str = "attributes-weights";
+pub const AUTO_FILTERABLE_FIELDS_KEY: &str = "auto-filterable-fields";
+pub const BUILT_DATABASES_KEY: &str = "built-databases";
+pub const COLLATION_STRENGTH_KEY: &str = "collation-strength";
+pub const COMPUTED_FIELDS_KEY: &str = "computed-fields";
 pub const CRITERIA_KEY: &str = "criteria";
+pub const DISTINCT_ATTRIBUTE_KEY: &str = "distinct-attribute";
+pub const DISTINCT_MODE_KEY: &str = "distinct-mode";
 pub const DISPLAYED_FIELDS_KEY: &str = "displayed-fields";
 pub const DOCUMENTS_IDS_KEY: &str = "documents-ids";
+pub const FACET_STRING_DISPLAY_VALUES_PREFIX: &str = "facet-string-display-values";
+pub const FACET_STRING_NORMALIZATIONS_KEY: &str = "facet-string-normalizations";
 pub const FACETED_DOCUMENTS_IDS_PREFIX: &str = "faceted-documents-ids";
 pub const FACETED_FIELDS_KEY: &str = "faceted-fields";
 pub const FIELDS_IDS_MAP_KEY: &str = "fields-ids-map";
+pub const PROXIMITY_DATABASE_ENABLED_KEY: &str = "proximity-database-enabled";
+pub const MAX_POSITION_POLICY_KEY: &str = "max-position-policy";
 pub const PRIMARY_KEY_KEY: &str = "primary-key";
+pub const SEARCH_DEFAULTS_KEY: &str = "search-defaults";
 pub const SEARCHABLE_FIELDS_KEY: &str = "searchable-fields";
+pub const SORTABLE_FIELDS_KEY: &str = "sortable-fields";
+pub const SPLIT_WORDS_BUDGET_KEY: &str = "split-words-budget";
+pub const SPLIT_WORDS_ENABLED_KEY: &str = "split-words-enabled";
+pub const STOP_WORDS_KEY: &str = "stop-words";
+pub const STORED_ONLY_FIELDS_KEY: &str = "stored-only-fields";
+pub const STORED_QUERY_PREFIX: &str = "stored-query";
+pub const MAX_PREFIX_LENGTH_KEY: &str = "max-prefix-length";
+pub const MAX_QUERY_NGRAM_KEY: &str = "max-query-ngram";
+pub const MAX_TOTAL_HITS_KEY: &str = "max-total-hits";
+pub const METADATA_KEY: &str = "metadata";
+pub const MIN_PREFIX_QUERY_LENGTH_KEY: &str = "min-prefix-query-length";
 pub const HARD_EXTERNAL_DOCUMENTS_IDS_KEY: &str = "hard-external-documents-ids";
 pub const SOFT_EXTERNAL_DOCUMENTS_IDS_KEY: &str = "soft-external-documents-ids";
 pub const WORDS_FST_KEY: &str = "words-fst";
 pub const WORDS_PREFIXES_FST_KEY: &str = "words-prefixes-fst";
+pub const WORDS_PREFIXES_THRESHOLD_KEY: &str = "words-prefixes-threshold";
 const CREATED_AT_KEY: &str = "created-at";
+const POSITIONS_ENCODING_KEY: &str = "positions-encoding";
 const UPDATED_AT_KEY: &str = "updated-at";
+const UPDATE_SEQUENCE_KEY: &str = "update-sequence";
+const UPDATE_VERSION_KEY: &str = "update-version";
+
+/// The version of the tokenizer used to index the documents, it is
+/// the `meilisearch-tokenizer` tag of the manifest, see the build script.
+const TOKENIZER_VERSION: &str = env!("MILLI_TOKENIZER_VERSION");
+
+/// The features of the index format that this version of milli always writes.
+const FORMAT_FEATURES: &[&str] = &["word-docids-shards", "shifted-attribute-positions", "stored-query-keys"];
+
+/// The databases derived from the others that a previous version of milli didn't write,
+/// they are built when an index that doesn't have them is opened, see `MissingDatabases`.
+pub(crate) const DERIVED_DATABASES: &[&str] = &[
+    "prefix-word-pair-proximity-docids",
+    "word-position-docids",
+    "word-attribute-docids",
+];
+
+/// The main database value that describes how and by what an index has been written,
+/// it can be read by tools that need to check the compatibility of an index file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexMetadata {
+    /// The creation time of the index.
+    pub created_at: DateTime<Utc>,
+    /// The version of milli that last updated the index.
+    pub milli_version: String,
+    /// The version of the tokenizer that last updated the index.
+    pub tokenizer_version: String,
+    /// The names of the optional features enabled in this index.
+    pub features: Vec<String>,
+    pub primary_key: Option<String>,
+    /// A hash of the settings, see [`SettingsSnapshot::hash`].
+    pub settings_hash: u64,
+}
+
+/// The maximum size of a value stored under a single key of the main database,
+/// bigger values (e.g. the words FST of a huge index) are split into multiple chunks.
+const MAIN_VALUE_CHUNK_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+
+/// The maximum number of original string facet values recorded for a field,
+/// see `insert_facet_string_display_value`.
+pub(crate) const MAX_FACET_STRING_DISPLAY_VALUES: usize = 10_000;
+
+/// The number of low bits of the documents ids that are ignored to find the shard
+/// of the documents ids of a frequent word they belong to, a shard spans 65536 ids.
+pub(crate) const WORD_DOCIDS_SHARD_BITS: u32 = 16;
 
 #[derive(Clone)]
 pub struct Index {
@@ -40,6 +123,9 @@ pub struct Index {
     pub main: PolyDatabase,
     /// A word and all the documents ids containing the word.
     pub word_docids: Database<Str, RoaringBitmapCodec>,
+    /// Maps a frequent word and the first document id of a range of documents ids to the
+    /// documents ids of this range containing the word, see `WordDocidsShards`.
+    pub word_docids_shards: Database<StrBEU32Codec, RoaringBitmapCodec>,
     /// A prefix of word and all the documents ids containing this prefix.
     pub word_prefix_docids: Database<Str, RoaringBitmapCodec>,
     /// Maps a word and a document id (u32) to all the positions where the given word appears.
@@ -48,52 +134,86 @@ pub struct Index {
     pub word_pair_proximity_docids: Database<StrStrU8Codec, CboRoaringBitmapCodec>,
     /// Maps the proximity between a pair of word and prefix with all the docids where this relation appears.
     pub word_prefix_pair_proximity_docids: Database<StrStrU8Codec, CboRoaringBitmapCodec>,
+    /// Maps the proximity between a pair of prefix and word with all the docids where this relation appears.
+    pub prefix_word_pair_proximity_docids: Database<StrStrU8Codec, CboRoaringBitmapCodec>,
+    /// Maps a word and a position bucket with the documents ids where the first occurrence
+    /// of the word in an attribute falls in this bucket, see `proximity::position_bucket`.
+    pub word_position_docids: Database<StrBEU32Codec, CboRoaringBitmapCodec>,
+    /// Maps a word and the field id of an attribute with the documents ids containing
+    /// the word in this attribute, see `Search::searchable_attributes`.
+    pub word_attribute_docids: Database<StrBEU32Codec, CboRoaringBitmapCodec>,
     /// Maps the facet field id and the globally ordered value with the docids that corresponds to it.
     pub facet_field_id_value_docids: Database<ByteSlice, CboRoaringBitmapCodec>,
     /// Maps the document id, the facet field id and the globally ordered value.
     pub field_id_docid_facet_values: Database<ByteSlice, Unit>,
     /// Maps the document id to the document as an obkv store.
     pub documents: Database<OwnedType<BEU32>, ObkvCodec>,
+    /// The decoded structures shared by the searches made on the last version of the index.
+    pub(crate) search_cache: Arc<SearchCache>,
 }
 
 impl Index {
     pub fn new<P: AsRef<Path>>(mut options: heed::EnvOpenOptions, path: P) -> anyhow::Result<Index> {
-        options.max_dbs(9);
+        options.max_dbs(13);
 
         let env = options.open(path)?;
         let main = env.create_poly_database(Some("main"))?;
         let word_docids = env.create_database(Some("word-docids"))?;
+        let word_docids_shards = env.create_database(Some("word-docids-shards"))?;
         let word_prefix_docids = env.create_database(Some("word-prefix-docids"))?;
         let docid_word_positions = env.create_database(Some("docid-word-positions"))?;
         let word_pair_proximity_docids = env.create_database(Some("word-pair-proximity-docids"))?;
         let word_prefix_pair_proximity_docids = env.create_database(Some("word-prefix-pair-proximity-docids"))?;
+        let prefix_word_pair_proximity_docids = env.create_database(Some("prefix-word-pair-proximity-docids"))?;
+        let word_position_docids = env.create_database(Some("word-position-docids"))?;
+        let word_attribute_docids = env.create_database(Some("word-attribute-docids"))?;
         let facet_field_id_value_docids = env.create_database(Some("facet-field-id-value-docids"))?;
         let field_id_docid_facet_values = env.create_database(Some("field-id-docid-facet-values"))?;
         let documents = env.create_database(Some("documents"))?;
 
-        {
-            let mut txn = env.write_txn()?;
-            // The db was just created, we update its metadata with the relevant information.
-            if main.get::<_, Str, SerdeJson<DateTime<Utc>>>(&txn, CREATED_AT_KEY)?.is_none() {
-                let now = Utc::now();
-                main.put::<_, Str, SerdeJson<DateTime<Utc>>>(&mut txn, UPDATED_AT_KEY, &now)?;
-                main.put::<_, Str, SerdeJson<DateTime<Utc>>>(&mut txn, CREATED_AT_KEY, &now)?;
-                txn.commit()?;
-            }
-        }
-
-        Ok(Index {
+        let index = Index {
             env,
             main,
             word_docids,
+            word_docids_shards,
             word_prefix_docids,
             docid_word_positions,
             word_pair_proximity_docids,
             word_prefix_pair_proximity_docids,
+            prefix_word_pair_proximity_docids,
+            word_position_docids,
+            word_attribute_docids,
             facet_field_id_value_docids,
             field_id_docid_facet_values,
             documents,
-        })
+            search_cache: Arc::new(SearchCache::default()),
+        };
+
+        {
+            let mut txn = index.env.write_txn()?;
+            check_positions_encoding(&index.main, &mut txn)?;
+            // The db was just created, we update its metadata with the relevant information.
+            if index.main.get::<_, Str, SerdeJson<DateTime<Utc>>>(&txn, CREATED_AT_KEY)?.is_none() {
+                let now = Utc::now();
+                index.main.put::<_, Str, SerdeJson<DateTime<Utc>>>(&mut txn, UPDATED_AT_KEY, &now)?;
+                index.main.put::<_, Str, SerdeJson<DateTime<Utc>>>(&mut txn, CREATED_AT_KEY, &now)?;
+                let built = DERIVED_DATABASES.iter().map(|name| name.to_string()).collect();
+                index.put_built_databases(&mut txn, &built)?;
+                index.refresh_metadata(&mut txn)?;
+            }
+            // An index written by a previous version of milli can miss some databases.
+            let built = index.built_databases(&txn)?;
+            if DERIVED_DATABASES.iter().any(|name| !built.contains(*name)) {
+                MissingDatabases::new(&mut txn, &index, 0).execute()?;
+            }
+            // Nor the metadata, it is written as soon as the index is opened.
+            if index.metadata(&txn)?.is_none() {
+                index.refresh_metadata(&mut txn)?;
+            }
+            txn.commit()?;
+        }
+
+        Ok(index)
     }
 
     /// Create a write transaction to be able to write into the index.
@@ -120,22 +240,127 @@ impl Index {
         self.env.prepare_for_closing()
     }
 
+    /* storage */
+
+    /// Returns the given database without its types, see the `storage` module.
+    pub fn database(&self, database: IndexDatabase) -> PolyDatabase {
+        match database {
+            IndexDatabase::Main => self.main,
+            IndexDatabase::WordDocids => *self.word_docids.as_polymorph(),
+            IndexDatabase::WordPrefixDocids => *self.word_prefix_docids.as_polymorph(),
+            IndexDatabase::DocidWordPositions => *self.docid_word_positions.as_polymorph(),
+            IndexDatabase::WordPairProximityDocids => *self.word_pair_proximity_docids.as_polymorph(),
+            IndexDatabase::WordPrefixPairProximityDocids => *self.word_prefix_pair_proximity_docids.as_polymorph(),
+            IndexDatabase::FacetFieldIdValueDocids => *self.facet_field_id_value_docids.as_polymorph(),
+            IndexDatabase::FieldIdDocidFacetValues => *self.field_id_docid_facet_values.as_polymorph(),
+            IndexDatabase::Documents => *self.documents.as_polymorph(),
+            IndexDatabase::WordDocidsShards => *self.word_docids_shards.as_polymorph(),
+            IndexDatabase::PrefixWordPairProximityDocids => *self.prefix_word_pair_proximity_docids.as_polymorph(),
+            IndexDatabase::WordPositionDocids => *self.word_position_docids.as_polymorph(),
+            IndexDatabase::WordAttributeDocids => *self.word_attribute_docids.as_polymorph(),
+        }
+    }
+
+    /// Returns the raw entries of the given database, as seen by the transaction.
+    pub fn storage<'t, 'i>(&self, rtxn: &'t RoTxn<'i>, database: IndexDatabase) -> HeedReader<'t, 'i> {
+        HeedReader::new(rtxn, self.database(database))
+    }
+
+    /// Returns the given database as a `Storage` the raw entries are written into.
+    pub fn storage_mut<'t, 'i, 'u>(
+        &self,
+        wtxn: &'t mut RwTxn<'i, 'u>,
+        database: IndexDatabase,
+    ) -> HeedWriter<'t, 'i, 'u>
+    {
+        HeedWriter::new(wtxn, self.database(database))
+    }
+
+    /* chunked values */
+
+    /// Writes a value in the main database, splitting it into chunks of at most
+    /// `MAIN_VALUE_CHUNK_SIZE` bytes, the first chunk is stored under the key itself
+    /// and the following ones under the key suffixed by the chunk number.
+    pub(crate) fn put_main_bytes(&self, wtxn: &mut RwTxn, key: &str, bytes: &[u8]) -> heed::Result<()> {
+        self.put_main_bytes_chunked(wtxn, key, bytes, MAIN_VALUE_CHUNK_SIZE)
+    }
+
+    fn put_main_bytes_chunked(
+        &self,
+        wtxn: &mut RwTxn,
+        key: &str,
+        bytes: &[u8],
+        chunk_size: usize,
+    ) -> heed::Result<()>
+    {
+        let mut chunks = bytes.chunks(chunk_size);
+        let first = chunks.next().unwrap_or(&[]);
+        self.main.put::<_, Str, ByteSlice>(wtxn, key, first)?;
+
+        let mut number = 1;
+        for chunk in chunks {
+            self.main.put::<_, Str, ByteSlice>(wtxn, &chunk_key(key, number), chunk)?;
+            number += 1;
+        }
+
+        // We remove the remaining chunks of a previous and bigger value.
+        while self.main.delete::<_, Str>(wtxn, &chunk_key(key, number))? {
+            number += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes a value of the main database along with all its chunks.
+    pub(crate) fn delete_main_bytes(&self, wtxn: &mut RwTxn, key: &str) -> heed::Result<bool> {
+        let deleted = self.main.delete::<_, Str>(wtxn, key)?;
+        let mut number = 1;
+        while self.main.delete::<_, Str>(wtxn, &chunk_key(key, number))? {
+            number += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// Returns a value of the main database, reassembling its chunks if it was too big
+    /// to be stored under a single key, the value is only copied in this case.
+    pub(crate) fn main_bytes<'t>(&self, rtxn: &'t RoTxn, key: &str) -> heed::Result<Option<Cow<'t, [u8]>>> {
+        let mut bytes = match self.main.get::<_, Str, ByteSlice>(rtxn, key)? {
+            Some(first) => Cow::Borrowed(first),
+            None => return Ok(None),
+        };
+
+        for number in 1.. {
+            match self.main.get::<_, Str, ByteSlice>(rtxn, &chunk_key(key, number))? {
+                Some(chunk) => bytes.to_mut().extend_from_slice(chunk),
+                None => break,
+            }
+        }
+
+        Ok(Some(bytes))
+    }
+
     /* documents ids */
 
     /// Writes the documents ids that corresponds to the user-ids-documents-ids FST.
     pub fn put_documents_ids(&self, wtxn: &mut RwTxn, docids: &RoaringBitmap) -> heed::Result<()> {
-        self.main.put::<_, Str, RoaringBitmapCodec>(wtxn, DOCUMENTS_IDS_KEY, docids)
+        let bytes = RoaringBitmapCodec::bytes_encode(docids).ok_or(heed::Error::Encoding)?;
+        self.put_main_bytes(wtxn, DOCUMENTS_IDS_KEY, &bytes)
     }
 
     /// Returns the internal documents ids.
     pub fn documents_ids(&self, rtxn: &RoTxn) -> heed::Result<RoaringBitmap> {
-        Ok(self.main.get::<_, Str, RoaringBitmapCodec>(rtxn, DOCUMENTS_IDS_KEY)?.unwrap_or_default())
+        match self.main_bytes(rtxn, DOCUMENTS_IDS_KEY)? {
+            Some(bytes) => RoaringBitmapCodec::bytes_decode(&bytes).ok_or(heed::Error::Decoding),
+            None => Ok(RoaringBitmap::new()),
+        }
     }
 
     /// Returns the number of documents indexed in the database.
     pub fn number_of_documents(&self, rtxn: &RoTxn) -> anyhow::Result<u64> {
-        let count = self.main.get::<_, Str, RoaringBitmapLenCodec>(rtxn, DOCUMENTS_IDS_KEY)?;
-        Ok(count.unwrap_or_default())
+        match self.main_bytes(rtxn, DOCUMENTS_IDS_KEY)? {
+            Some(bytes) => Ok(RoaringBitmapLenCodec::bytes_decode(&bytes).ok_or(heed::Error::Decoding)?),
+            None => Ok(0),
+        }
     }
 
     /* primary key */
@@ -168,22 +393,22 @@ impl Index {
         let ExternalDocumentsIds { hard, soft } = external_documents_ids;
         let hard = hard.as_fst().as_bytes();
         let soft = soft.as_fst().as_bytes();
-        self.main.put::<_, Str, ByteSlice>(wtxn, HARD_EXTERNAL_DOCUMENTS_IDS_KEY, hard)?;
-        self.main.put::<_, Str, ByteSlice>(wtxn, SOFT_EXTERNAL_DOCUMENTS_IDS_KEY, soft)?;
+        self.put_main_bytes(wtxn, HARD_EXTERNAL_DOCUMENTS_IDS_KEY, hard)?;
+        self.put_main_bytes(wtxn, SOFT_EXTERNAL_DOCUMENTS_IDS_KEY, soft)?;
         Ok(())
     }
 
     /// Returns the external documents ids map which associate the external ids
     /// with the internal ids (i.e. `u32`).
     pub fn external_documents_ids<'t>(&self, rtxn: &'t RoTxn) -> anyhow::Result<ExternalDocumentsIds<'t>> {
-        let hard = self.main.get::<_, Str, ByteSlice>(rtxn, HARD_EXTERNAL_DOCUMENTS_IDS_KEY)?;
-        let soft = self.main.get::<_, Str, ByteSlice>(rtxn, SOFT_EXTERNAL_DOCUMENTS_IDS_KEY)?;
+        let hard = self.main_bytes(rtxn, HARD_EXTERNAL_DOCUMENTS_IDS_KEY)?;
+        let soft = self.main_bytes(rtxn, SOFT_EXTERNAL_DOCUMENTS_IDS_KEY)?;
         let hard = match hard {
-            Some(hard) => fst::Map::new(hard)?.map_data(Cow::Borrowed)?,
+            Some(hard) => fst::Map::new(hard)?,
             None => fst::Map::default().map_data(Cow::Owned)?,
         };
         let soft = match soft {
-            Some(soft) => fst::Map::new(soft)?.map_data(Cow::Borrowed)?,
+            Some(soft) => fst::Map::new(soft)?,
             None => fst::Map::default().map_data(Cow::Owned)?,
         };
         Ok(ExternalDocumentsIds::new(hard, soft))
@@ -305,6 +530,284 @@ impl Index {
         Ok(faceted_fields)
     }
 
+    /* auto-filterable fields */
+
+    /// Writes the auto-filterable fields names, the fields from which the numbers
+    /// found in the text are also indexed as integer facet values.
+    pub fn put_auto_filterable_fields(&self, wtxn: &mut RwTxn, fields: &HashSet<String>) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<_>>(wtxn, AUTO_FILTERABLE_FIELDS_KEY, fields)
+    }
+
+    /// Deletes the auto-filterable fields names.
+    pub fn delete_auto_filterable_fields(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, AUTO_FILTERABLE_FIELDS_KEY)
+    }
+
+    /// Returns the auto-filterable fields names, no field is auto-filterable by default.
+    pub fn auto_filterable_fields(&self, rtxn: &RoTxn) -> heed::Result<HashSet<String>> {
+        Ok(self.main.get::<_, Str, SerdeJson<_>>(rtxn, AUTO_FILTERABLE_FIELDS_KEY)?.unwrap_or_default())
+    }
+
+    /* sortable fields */
+
+    /// Writes the sortable fields names, the fields that can be used in query-time sort expressions.
+    pub fn put_sortable_fields(&self, wtxn: &mut RwTxn, fields: &HashSet<String>) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<_>>(wtxn, SORTABLE_FIELDS_KEY, fields)
+    }
+
+    /// Deletes the sortable fields names.
+    pub fn delete_sortable_fields(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, SORTABLE_FIELDS_KEY)
+    }
+
+    /// Returns the sortable fields names, no field is sortable by default.
+    pub fn sortable_fields(&self, rtxn: &RoTxn) -> heed::Result<HashSet<String>> {
+        Ok(self.main.get::<_, Str, SerdeJson<_>>(rtxn, SORTABLE_FIELDS_KEY)?.unwrap_or_default())
+    }
+
+    /* stored-only fields */
+
+    /// Writes the stored-only fields names, the fields that are returned
+    /// in the documents but never tokenized nor faceted.
+    pub fn put_stored_only_fields(&self, wtxn: &mut RwTxn, fields: &HashSet<String>) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<_>>(wtxn, STORED_ONLY_FIELDS_KEY, fields)
+    }
+
+    /// Deletes the stored-only fields names.
+    pub fn delete_stored_only_fields(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, STORED_ONLY_FIELDS_KEY)
+    }
+
+    /// Returns the stored-only fields names, every field is indexed by default.
+    pub fn stored_only_fields(&self, rtxn: &RoTxn) -> heed::Result<HashSet<String>> {
+        Ok(self.main.get::<_, Str, SerdeJson<_>>(rtxn, STORED_ONLY_FIELDS_KEY)?.unwrap_or_default())
+    }
+
+    /* computed fields */
+
+    /// Writes the fields that are derived from the other fields of the documents, by field name.
+    pub fn put_computed_fields(&self, wtxn: &mut RwTxn, fields: &BTreeMap<String, ComputedField>) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<_>>(wtxn, COMPUTED_FIELDS_KEY, fields)
+    }
+
+    /// Deletes the computed fields.
+    pub fn delete_computed_fields(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, COMPUTED_FIELDS_KEY)
+    }
+
+    /// Returns the fields that are derived from the other fields of the documents, by field name.
+    pub fn computed_fields(&self, rtxn: &RoTxn) -> heed::Result<BTreeMap<String, ComputedField>> {
+        Ok(self.main.get::<_, Str, SerdeJson<_>>(rtxn, COMPUTED_FIELDS_KEY)?.unwrap_or_default())
+    }
+
+    /* attributes stop words */
+
+    /// Writes the stop words that are only ignored in the given attributes, by attribute name.
+    pub fn put_attributes_stop_words(
+        &self,
+        wtxn: &mut RwTxn,
+        stop_words: &HashMap<String, BTreeSet<String>>,
+    ) -> heed::Result<()>
+    {
+        self.main.put::<_, Str, SerdeJson<_>>(wtxn, ATTRIBUTES_STOP_WORDS_KEY, stop_words)
+    }
+
+    /// Deletes the attributes stop words.
+    pub fn delete_attributes_stop_words(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, ATTRIBUTES_STOP_WORDS_KEY)
+    }
+
+    /// Returns the stop words that are only ignored in the given attributes, by attribute name.
+    pub fn attributes_stop_words(&self, rtxn: &RoTxn) -> heed::Result<HashMap<String, BTreeSet<String>>> {
+        Ok(self.main.get::<_, Str, SerdeJson<_>>(rtxn, ATTRIBUTES_STOP_WORDS_KEY)?.unwrap_or_default())
+    }
+
+    /* attributes languages */
+
+    /// Writes the language of the given attributes, by attribute name.
+    pub fn put_attributes_languages(&self, wtxn: &mut RwTxn, languages: &HashMap<String, String>) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<_>>(wtxn, ATTRIBUTES_LANGUAGES_KEY, languages)
+    }
+
+    /// Deletes the attributes languages.
+    pub fn delete_attributes_languages(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, ATTRIBUTES_LANGUAGES_KEY)
+    }
+
+    /// Returns the language of the attributes that declare one, by attribute name.
+    pub fn attributes_languages(&self, rtxn: &RoTxn) -> heed::Result<HashMap<String, String>> {
+        Ok(self.main.get::<_, Str, SerdeJson<_>>(rtxn, ATTRIBUTES_LANGUAGES_KEY)?.unwrap_or_default())
+    }
+
+    /* attributes weights */
+
+    /// Writes the weight of the given attributes, by attribute name.
+    pub fn put_attributes_weights(&self, wtxn: &mut RwTxn, weights: &HashMap<String, u8>) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<_>>(wtxn, ATTRIBUTES_WEIGHTS_KEY, weights)
+    }
+
+    /// Deletes the attributes weights.
+    pub fn delete_attributes_weights(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, ATTRIBUTES_WEIGHTS_KEY)
+    }
+
+    /// Returns the weight of the attributes that declare one, by attribute name,
+    /// the other attributes have a weight of `DEFAULT_ATTRIBUTE_WEIGHT`.
+    pub fn attributes_weights(&self, rtxn: &RoTxn) -> heed::Result<HashMap<String, u8>> {
+        Ok(self.main.get::<_, Str, SerdeJson<_>>(rtxn, ATTRIBUTES_WEIGHTS_KEY)?.unwrap_or_default())
+    }
+
+    /* distinct attribute */
+
+    /// Writes the name of the faceted field used to group the documents.
+    pub fn put_distinct_attribute(&self, wtxn: &mut RwTxn, field: &str) -> heed::Result<()> {
+        self.main.put::<_, Str, Str>(wtxn, DISTINCT_ATTRIBUTE_KEY, field)
+    }
+
+    /// Deletes the distinct attribute, the documents are no more grouped.
+    pub fn delete_distinct_attribute(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, DISTINCT_ATTRIBUTE_KEY)
+    }
+
+    /// Returns the name of the faceted field used to group the documents, if any.
+    pub fn distinct_attribute<'t>(&self, rtxn: &'t RoTxn) -> heed::Result<Option<&'t str>> {
+        self.main.get::<_, Str, Str>(rtxn, DISTINCT_ATTRIBUTE_KEY)
+    }
+
+    /* distinct mode */
+
+    /// Writes the way the documents with multiple values for the distinct attribute are grouped.
+    pub fn put_distinct_mode(&self, wtxn: &mut RwTxn, mode: DistinctMode) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<DistinctMode>>(wtxn, DISTINCT_MODE_KEY, &mode)
+    }
+
+    /// Deletes the distinct mode, the default one will be used.
+    pub fn delete_distinct_mode(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, DISTINCT_MODE_KEY)
+    }
+
+    /// Returns the way the documents with multiple values for the distinct attribute are grouped.
+    pub fn distinct_mode(&self, rtxn: &RoTxn) -> heed::Result<DistinctMode> {
+        let mode = self.main.get::<_, Str, SerdeJson<DistinctMode>>(rtxn, DISTINCT_MODE_KEY)?;
+        Ok(mode.unwrap_or_default())
+    }
+
+    /* stored queries */
+
+    /// Registers a query under the given name, replacing the query previously registered
+    /// with this name, see `Percolator` to find the stored queries matched by a document.
+    ///
+    /// Returns an error if the query can't be compiled with the settings of the index.
+    pub fn put_stored_query(&self, wtxn: &mut RwTxn, name: &str, query: &StoredQuery) -> anyhow::Result<()> {
+        query.validate(wtxn, self).with_context(|| format!("invalid stored query {:?}", name))?;
+        Ok(self.main.put::<_, Str, SerdeJson<_>>(wtxn, &stored_query_key(name), query)?)
+    }
+
+    /// Deletes the stored query registered with the given name, returns `true` if it existed.
+    pub fn delete_stored_query(&self, wtxn: &mut RwTxn, name: &str) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, &stored_query_key(name))
+    }
+
+    /// Returns the stored query registered with the given name.
+    pub fn stored_query(&self, rtxn: &RoTxn, name: &str) -> heed::Result<Option<StoredQuery>> {
+        self.main.get::<_, Str, SerdeJson<_>>(rtxn, &stored_query_key(name))
+    }
+
+    /// Returns the stored queries by name.
+    pub fn stored_queries(&self, rtxn: &RoTxn) -> heed::Result<BTreeMap<String, StoredQuery>> {
+        let prefix = stored_query_key("");
+        let iter = self.main.prefix_iter::<_, Str, SerdeJson<StoredQuery>>(rtxn, &prefix)?;
+        iter.map(|result| result.map(|(key, query)| (key[prefix.len()..].to_string(), query))).collect()
+    }
+
+    /* collation strength */
+
+    /// Writes the collation strength used to sort the documents by string facet values.
+    pub fn put_collation_strength(&self, wtxn: &mut RwTxn, strength: CollationStrength) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<CollationStrength>>(wtxn, COLLATION_STRENGTH_KEY, &strength)
+    }
+
+    /// Deletes the collation strength, the default one will be used.
+    pub fn delete_collation_strength(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, COLLATION_STRENGTH_KEY)
+    }
+
+    /// Returns the collation strength used to sort the documents by string facet values.
+    pub fn collation_strength(&self, rtxn: &RoTxn) -> heed::Result<CollationStrength> {
+        let strength = self.main.get::<_, Str, SerdeJson<CollationStrength>>(rtxn, COLLATION_STRENGTH_KEY)?;
+        Ok(strength.unwrap_or_default())
+    }
+
+    /* facet string normalizations */
+
+    /// Writes the way the string facet values are normalized, by attribute name.
+    pub fn put_facet_string_normalizations(
+        &self,
+        wtxn: &mut RwTxn,
+        normalizations: &HashMap<String, FacetStringNormalization>,
+    ) -> heed::Result<()>
+    {
+        self.main.put::<_, Str, SerdeJson<_>>(wtxn, FACET_STRING_NORMALIZATIONS_KEY, normalizations)
+    }
+
+    /// Deletes the facet string normalizations, the values of every field are only lowercased.
+    pub fn delete_facet_string_normalizations(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, FACET_STRING_NORMALIZATIONS_KEY)
+    }
+
+    /// Returns the way the string facet values are normalized, by attribute name,
+    /// the fields that aren't part of it use the default normalization.
+    pub fn facet_string_normalizations(&self, rtxn: &RoTxn) -> heed::Result<HashMap<String, FacetStringNormalization>> {
+        Ok(self.main.get::<_, Str, SerdeJson<_>>(rtxn, FACET_STRING_NORMALIZATIONS_KEY)?.unwrap_or_default())
+    }
+
+    /// Same as `facet_string_normalizations`, but returns the normalizations by field id,
+    /// the fields that are not in the fields ids map are ignored.
+    pub fn facet_string_normalizations_ids(&self, rtxn: &RoTxn) -> heed::Result<HashMap<FieldId, FacetStringNormalization>> {
+        let fields_ids_map = self.fields_ids_map(rtxn)?;
+        let normalizations = self.facet_string_normalizations(rtxn)?
+            .into_iter()
+            .filter_map(|(name, normalization)| fields_ids_map.id(&name).map(|id| (id, normalization)))
+            .collect();
+        Ok(normalizations)
+    }
+
+    /* facet string display values */
+
+    /// Writes the original string facet values of this field id, by normalized value.
+    pub fn put_facet_string_display_values(
+        &self,
+        wtxn: &mut RwTxn,
+        field_id: FieldId,
+        display_values: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()>
+    {
+        let key = facet_string_display_values_key(field_id);
+        let bytes = serde_json::to_vec(display_values)?;
+        Ok(self.put_main_bytes(wtxn, &key, &bytes)?)
+    }
+
+    /// Deletes the original string facet values of this field id.
+    pub fn delete_facet_string_display_values(&self, wtxn: &mut RwTxn, field_id: FieldId) -> heed::Result<bool> {
+        let key = facet_string_display_values_key(field_id);
+        let deleted = self.main.delete::<_, Str>(wtxn, &key)?;
+        let mut number = 1;
+        while self.main.delete::<_, Str>(wtxn, &chunk_key(&key, number))? {
+            number += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// Returns the original string facet values of this field id, by normalized value,
+    /// they are only kept for the fields that define a facet string normalization.
+    pub fn facet_string_display_values(&self, rtxn: &RoTxn, field_id: FieldId) -> anyhow::Result<BTreeMap<String, String>> {
+        let key = facet_string_display_values_key(field_id);
+        match self.main_bytes(rtxn, &key)? {
+            Some(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
     /* faceted documents ids */
 
     /// Writes the documents ids that are faceted under this field id.
@@ -337,23 +840,48 @@ impl Index {
     }
 
     pub fn criteria(&self, rtxn: &RoTxn) -> heed::Result<Vec<Criterion>> {
-        match self.main.get::<_, Str, SerdeJson<Vec<Criterion>>>(rtxn, CRITERIA_KEY)? {
-            Some(criteria) => Ok(criteria),
+        match self.main.get::<_, Str, SerdeJson<Vec<serde_json::Value>>>(rtxn, CRITERIA_KEY)? {
+            Some(criteria) => {
+                criteria.into_iter().map(|criterion| {
+                    let criterion = upgrade_stored_criterion(criterion);
+                    serde_json::from_value(criterion).map_err(|_| heed::Error::Decoding)
+                }).collect()
+            },
             None => Ok(default_criteria()),
         }
     }
 
+    /* stop words */
+
+    /// Writes the FST of the words that are ignored in every attribute and in the queries.
+    pub fn put_stop_words<A: AsRef<[u8]>>(&self, wtxn: &mut RwTxn, fst: &fst::Set<A>) -> heed::Result<()> {
+        self.put_main_bytes(wtxn, STOP_WORDS_KEY, fst.as_fst().as_bytes())
+    }
+
+    /// Deletes the stop words.
+    pub fn delete_stop_words(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.delete_main_bytes(wtxn, STOP_WORDS_KEY)
+    }
+
+    /// Returns the FST of the words that are ignored in every attribute and in the queries.
+    pub fn stop_words<'t>(&self, rtxn: &'t RoTxn) -> anyhow::Result<fst::Set<Cow<'t, [u8]>>> {
+        match self.main_bytes(rtxn, STOP_WORDS_KEY)? {
+            Some(bytes) => Ok(fst::Set::new(bytes)?),
+            None => Ok(fst::Set::default().map_data(Cow::Owned)?),
+        }
+    }
+
     /* words fst */
 
     /// Writes the FST which is the words dictionnary of the engine.
     pub fn put_words_fst<A: AsRef<[u8]>>(&self, wtxn: &mut RwTxn, fst: &fst::Set<A>) -> heed::Result<()> {
-        self.main.put::<_, Str, ByteSlice>(wtxn, WORDS_FST_KEY, fst.as_fst().as_bytes())
+        self.put_main_bytes(wtxn, WORDS_FST_KEY, fst.as_fst().as_bytes())
     }
 
     /// Returns the FST which is the words dictionnary of the engine.
     pub fn words_fst<'t>(&self, rtxn: &'t RoTxn) -> anyhow::Result<fst::Set<Cow<'t, [u8]>>> {
-        match self.main.get::<_, Str, ByteSlice>(rtxn, WORDS_FST_KEY)? {
-            Some(bytes) => Ok(fst::Set::new(bytes)?.map_data(Cow::Borrowed)?),
+        match self.main_bytes(rtxn, WORDS_FST_KEY)? {
+            Some(bytes) => Ok(fst::Set::new(bytes)?),
             None => Ok(fst::Set::default().map_data(Cow::Owned)?),
         }
     }
@@ -362,17 +890,241 @@ impl Index {
 
     /// Writes the FST which is the words prefixes dictionnary of the engine.
     pub fn put_words_prefixes_fst<A: AsRef<[u8]>>(&self, wtxn: &mut RwTxn, fst: &fst::Set<A>) -> heed::Result<()> {
-        self.main.put::<_, Str, ByteSlice>(wtxn, WORDS_PREFIXES_FST_KEY, fst.as_fst().as_bytes())
+        self.put_main_bytes(wtxn, WORDS_PREFIXES_FST_KEY, fst.as_fst().as_bytes())
     }
 
     /// Returns the FST which is the words prefixes dictionnary of the engine.
     pub fn words_prefixes_fst<'t>(&self, rtxn: &'t RoTxn) -> anyhow::Result<fst::Set<Cow<'t, [u8]>>> {
-        match self.main.get::<_, Str, ByteSlice>(rtxn, WORDS_PREFIXES_FST_KEY)? {
-            Some(bytes) => Ok(fst::Set::new(bytes)?.map_data(Cow::Borrowed)?),
+        match self.main_bytes(rtxn, WORDS_PREFIXES_FST_KEY)? {
+            Some(bytes) => Ok(fst::Set::new(bytes)?),
             None => Ok(fst::Set::default().map_data(Cow::Owned)?),
         }
     }
 
+    /* words vocabularies */
+
+    /// Writes the words FST into the given writer, it can be loaded back with `fst::Set::new`
+    /// and is meant to build external tools (e.g. spell-checkers) on the index vocabulary.
+    pub fn export_words_fst<W: io::Write>(&self, rtxn: &RoTxn, mut writer: W) -> anyhow::Result<()> {
+        let words_fst = self.words_fst(rtxn)?;
+        writer.write_all(words_fst.as_fst().as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the FST of the words that appear in the given field.
+    ///
+    /// This vocabulary is not stored, it is computed from the positions of the words
+    /// of every document and must therefore not be used at search time.
+    pub fn field_words_fst(&self, rtxn: &RoTxn, field_id: FieldId) -> anyhow::Result<fst::Set<Vec<u8>>> {
+        let mut words = BTreeSet::new();
+        for result in self.docid_word_positions.iter(rtxn)? {
+            let ((_docid, word), positions) = result?;
+            // The positions are ordered by attribute, we stop at the first attribute
+            // that is not before the field and check that it is the field.
+            let first = positions_attributes(&positions).find(|attribute| *attribute >= field_id as u32);
+            if first == Some(field_id as u32) {
+                words.insert(word);
+            }
+        }
+        Ok(fst::Set::from_iter(words)?)
+    }
+
+    /* words prefixes settings */
+
+    /// Writes the ratio of the words postings a prefix must cover to be part of the words prefixes databases.
+    pub fn put_words_prefixes_threshold(&self, wtxn: &mut RwTxn, threshold: f64) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<f64>>(wtxn, WORDS_PREFIXES_THRESHOLD_KEY, &threshold)
+    }
+
+    /// Deletes the words prefixes threshold, the default one will be used.
+    pub fn delete_words_prefixes_threshold(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, WORDS_PREFIXES_THRESHOLD_KEY)
+    }
+
+    /// Returns the ratio of the words postings a prefix must cover to be part of the words prefixes
+    /// databases, `None` if the default one is used.
+    pub fn words_prefixes_threshold(&self, rtxn: &RoTxn) -> heed::Result<Option<f64>> {
+        self.main.get::<_, Str, SerdeJson<f64>>(rtxn, WORDS_PREFIXES_THRESHOLD_KEY)
+    }
+
+    /// Writes the maximum length in bytes of the prefixes stored in the words prefixes databases.
+    pub fn put_max_prefix_length(&self, wtxn: &mut RwTxn, length: usize) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<usize>>(wtxn, MAX_PREFIX_LENGTH_KEY, &length)
+    }
+
+    /// Deletes the maximum prefix length, the default one will be used.
+    pub fn delete_max_prefix_length(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, MAX_PREFIX_LENGTH_KEY)
+    }
+
+    /// Returns the maximum length in bytes of the prefixes stored in the words prefixes
+    /// databases, `None` if the default one is used.
+    pub fn max_prefix_length(&self, rtxn: &RoTxn) -> heed::Result<Option<usize>> {
+        self.main.get::<_, Str, SerdeJson<usize>>(rtxn, MAX_PREFIX_LENGTH_KEY)
+    }
+
+    /* min prefix query length */
+
+    /// Writes the minimum number of characters the last word of a query must contain to be
+    /// considered as a prefix.
+    pub fn put_min_prefix_query_length(&self, wtxn: &mut RwTxn, length: usize) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<usize>>(wtxn, MIN_PREFIX_QUERY_LENGTH_KEY, &length)
+    }
+
+    /// Deletes the minimum prefix query length, the default one will be used.
+    pub fn delete_min_prefix_query_length(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, MIN_PREFIX_QUERY_LENGTH_KEY)
+    }
+
+    /// Returns the minimum number of characters the last word of a query must contain to be
+    /// considered as a prefix, by default every word can be a prefix.
+    pub fn min_prefix_query_length(&self, rtxn: &RoTxn) -> heed::Result<usize> {
+        let length = self.main.get::<_, Str, SerdeJson<usize>>(rtxn, MIN_PREFIX_QUERY_LENGTH_KEY)?;
+        Ok(length.unwrap_or(1))
+    }
+
+    /* max query ngram */
+
+    /// Writes the maximum number of consecutive query words that are concatenated
+    /// and searched as a single word.
+    pub fn put_max_query_ngram(&self, wtxn: &mut RwTxn, max: usize) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<usize>>(wtxn, MAX_QUERY_NGRAM_KEY, &max)
+    }
+
+    /// Deletes the maximum query ngram, the default one will be used.
+    pub fn delete_max_query_ngram(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, MAX_QUERY_NGRAM_KEY)
+    }
+
+    /// Returns the maximum number of consecutive query words that are concatenated
+    /// and searched as a single word, `DEFAULT_MAX_NGRAM` by default.
+    pub fn max_query_ngram(&self, rtxn: &RoTxn) -> heed::Result<usize> {
+        let max = self.main.get::<_, Str, SerdeJson<usize>>(rtxn, MAX_QUERY_NGRAM_KEY)?;
+        Ok(max.unwrap_or(DEFAULT_MAX_NGRAM))
+    }
+
+    /* split words */
+
+    /// Writes whether the query words are split in two words to match the documents
+    /// that contain them separately, e.g. `heythere` matching `hey there`.
+    pub fn put_split_words_enabled(&self, wtxn: &mut RwTxn, enabled: bool) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<bool>>(wtxn, SPLIT_WORDS_ENABLED_KEY, &enabled)
+    }
+
+    /// Deletes the split words setting, the query words will be split.
+    pub fn delete_split_words_enabled(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, SPLIT_WORDS_ENABLED_KEY)
+    }
+
+    /// Returns whether the query words are split in two words, they are by default.
+    pub fn split_words_enabled(&self, rtxn: &RoTxn) -> heed::Result<bool> {
+        let enabled = self.main.get::<_, Str, SerdeJson<bool>>(rtxn, SPLIT_WORDS_ENABLED_KEY)?;
+        Ok(enabled.unwrap_or(true))
+    }
+
+    /// Writes the maximum number of ways a query word is split in two words.
+    pub fn put_split_words_budget(&self, wtxn: &mut RwTxn, budget: usize) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<usize>>(wtxn, SPLIT_WORDS_BUDGET_KEY, &budget)
+    }
+
+    /// Deletes the split words budget, every way to split a query word will be explored.
+    pub fn delete_split_words_budget(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, SPLIT_WORDS_BUDGET_KEY)
+    }
+
+    /// Returns the maximum number of ways a query word is split in two words,
+    /// `None` if every way is explored.
+    pub fn split_words_budget(&self, rtxn: &RoTxn) -> heed::Result<Option<usize>> {
+        self.main.get::<_, Str, SerdeJson<usize>>(rtxn, SPLIT_WORDS_BUDGET_KEY)
+    }
+
+    /* max total hits */
+
+    /// Writes the maximum number of documents a search can rank, the documents
+    /// after this bound can't be reached by increasing the offset of a search.
+    pub fn put_max_total_hits(&self, wtxn: &mut RwTxn, max: usize) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<usize>>(wtxn, MAX_TOTAL_HITS_KEY, &max)
+    }
+
+    /// Deletes the maximum number of documents a search can rank, searches are unbounded.
+    pub fn delete_max_total_hits(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, MAX_TOTAL_HITS_KEY)
+    }
+
+    /// Returns the maximum number of documents a search can rank, if any.
+    pub fn max_total_hits(&self, rtxn: &RoTxn) -> heed::Result<Option<usize>> {
+        self.main.get::<_, Str, SerdeJson<usize>>(rtxn, MAX_TOTAL_HITS_KEY)
+    }
+
+    /* search defaults */
+
+    /// Writes the search parameters used when a search doesn't define them.
+    pub fn put_search_defaults(&self, wtxn: &mut RwTxn, defaults: &SearchDefaults) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<SearchDefaults>>(wtxn, SEARCH_DEFAULTS_KEY, defaults)
+    }
+
+    /// Deletes the search defaults, the searches will use the engine defaults.
+    pub fn delete_search_defaults(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, SEARCH_DEFAULTS_KEY)
+    }
+
+    /// Returns the search parameters used when a search doesn't define them.
+    pub fn search_defaults(&self, rtxn: &RoTxn) -> heed::Result<SearchDefaults> {
+        let defaults = self.main.get::<_, Str, SerdeJson<SearchDefaults>>(rtxn, SEARCH_DEFAULTS_KEY)?;
+        Ok(defaults.unwrap_or_default())
+    }
+
+    /* proximity database */
+
+    /// Writes whether the words pairs proximities database is built when documents are indexed.
+    pub fn put_proximity_database_enabled(&self, wtxn: &mut RwTxn, enabled: bool) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<bool>>(wtxn, PROXIMITY_DATABASE_ENABLED_KEY, &enabled)
+    }
+
+    /// Deletes the proximity database setting, the database will be built.
+    pub fn delete_proximity_database_enabled(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, PROXIMITY_DATABASE_ENABLED_KEY)
+    }
+
+    /// Returns whether the words pairs proximities database is built, when it isn't the
+    /// proximity between two words is approximated by their co-occurrence in the documents.
+    pub fn proximity_database_enabled(&self, rtxn: &RoTxn) -> heed::Result<bool> {
+        let enabled = self.main.get::<_, Str, SerdeJson<bool>>(rtxn, PROXIMITY_DATABASE_ENABLED_KEY)?;
+        Ok(enabled.unwrap_or(true))
+    }
+
+    /* built databases */
+
+    /// Writes the names of the derived databases that are built for all the documents.
+    pub(crate) fn put_built_databases(&self, wtxn: &mut RwTxn, names: &BTreeSet<String>) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<_>>(wtxn, BUILT_DATABASES_KEY, names)
+    }
+
+    /// Returns the names of the derived databases that are built for all the documents,
+    /// see `DERIVED_DATABASES`, the other ones are empty or partial and must not be read.
+    pub fn built_databases(&self, rtxn: &RoTxn) -> heed::Result<BTreeSet<String>> {
+        Ok(self.main.get::<_, Str, SerdeJson<_>>(rtxn, BUILT_DATABASES_KEY)?.unwrap_or_default())
+    }
+
+    /* max position policy */
+
+    /// Writes what happens to the words that appear after the maximum position of an attribute.
+    pub fn put_max_position_policy(&self, wtxn: &mut RwTxn, policy: MaxPositionPolicy) -> heed::Result<()> {
+        self.main.put::<_, Str, SerdeJson<MaxPositionPolicy>>(wtxn, MAX_POSITION_POLICY_KEY, &policy)
+    }
+
+    /// Deletes the max position policy, the words after the maximum position will be dropped.
+    pub fn delete_max_position_policy(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.main.delete::<_, Str>(wtxn, MAX_POSITION_POLICY_KEY)
+    }
+
+    /// Returns what happens to the words that appear after the maximum position of an attribute,
+    /// it is used by every update that indexes the documents.
+    pub fn max_position_policy(&self, rtxn: &RoTxn) -> heed::Result<MaxPositionPolicy> {
+        let policy = self.main.get::<_, Str, SerdeJson<MaxPositionPolicy>>(rtxn, MAX_POSITION_POLICY_KEY)?;
+        Ok(policy.unwrap_or_default())
+    }
+
     /* word documents count */
 
     /// Returns the number of documents ids associated with the given word,
@@ -381,6 +1133,57 @@ impl Index {
         self.word_docids.remap_data_type::<RoaringBitmapLenCodec>().get(rtxn, word)
     }
 
+    /// Returns an iterator over all the words of the index, in lexicographic order, along with
+    /// the number of documents containing them, the bitmaps of documents ids are never deserialized.
+    pub fn words_documents_count<'t>(
+        &self,
+        rtxn: &'t RoTxn,
+    ) -> heed::Result<heed::RoIter<'t, Str, RoaringBitmapLenCodec>>
+    {
+        self.word_docids.remap_data_type::<RoaringBitmapLenCodec>().iter(rtxn)
+    }
+
+    /* word documents ids shards */
+
+    /// Returns the documents ids of the word that are part of the given candidates.
+    ///
+    /// The documents ids of the frequent words are also stored by ranges of documents ids,
+    /// only the ranges containing candidates are read for them, the other words bitmaps are
+    /// probed without being deserialized, this is much cheaper than deserializing the whole
+    /// bitmap when the candidates are few.
+    pub fn word_docids_within(
+        &self,
+        rtxn: &RoTxn,
+        word: &str,
+        candidates: &RoaringBitmap,
+    ) -> heed::Result<Option<RoaringBitmap>>
+    {
+        let mut prefix = Vec::with_capacity(word.len() + 1);
+        prefix.extend_from_slice(word.as_bytes());
+        prefix.push(0);
+
+        let shards = self.word_docids_shards.remap_types::<ByteSlice, DecodeIgnore>();
+        let is_sharded = shards.prefix_iter(rtxn, &prefix)?.next().transpose()?.is_some();
+        if !is_sharded {
+            let probe = self.word_docids.remap_data_type::<RoaringBitmapProbeCodec>().get(rtxn, word)?;
+            return Ok(probe.map(|probe| probe.intersection(candidates)));
+        }
+
+        let mut docids = RoaringBitmap::new();
+        let mut last_shard = None;
+        for id in candidates {
+            let shard = id >> WORD_DOCIDS_SHARD_BITS << WORD_DOCIDS_SHARD_BITS;
+            if last_shard == Some(shard) { continue }
+            last_shard = Some(shard);
+            if let Some(shard_docids) = self.word_docids_shards.get(rtxn, &(word, shard))? {
+                docids.union_with(&shard_docids);
+            }
+        }
+
+        docids.intersect_with(candidates);
+        Ok(Some(docids))
+    }
+
     /* documents */
 
     /// Returns a [`Vec`] of the requested documents. Returns an error if a document is missing.
@@ -405,10 +1208,22 @@ impl Index {
         FacetDistribution::new(rtxn, self)
     }
 
+    /// Returns an iterator over the values of the given faceted field
+    /// along with the number of documents that contain them.
+    pub fn facet_values<'a>(&'a self, rtxn: &'a RoTxn, field_name: &str) -> anyhow::Result<FacetValues<'a>> {
+        FacetValues::new(rtxn, self, field_name)
+    }
+
     pub fn search<'a>(&'a self, rtxn: &'a RoTxn) -> Search<'a> {
         Search::new(rtxn, self)
     }
 
+    /// Opens a read transaction that can be shared to run several
+    /// operations that must see the same state of the index.
+    pub fn snapshot(&self) -> heed::Result<IndexSnapshot> {
+        IndexSnapshot::new(self)
+    }
+
     /// Returns the index creation time.
     pub fn created_at(&self, rtxn: &RoTxn) -> heed::Result<DateTime<Utc>> {
         let time = self.main
@@ -425,7 +1240,301 @@ impl Index {
         Ok(time)
     }
 
+    /// Returns the metadata of the index, the indexes written by a previous
+    /// version of milli have their metadata written when they are opened.
+    pub fn metadata(&self, rtxn: &RoTxn) -> heed::Result<Option<IndexMetadata>> {
+        self.main.get::<_, Str, SerdeJson<IndexMetadata>>(rtxn, METADATA_KEY)
+    }
+
+    /// Writes the metadata of the index from its current state, it must be
+    /// called by the updates that change the settings or the primary key.
+    pub(crate) fn refresh_metadata(&self, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+        let mut features: Vec<String> = FORMAT_FEATURES.iter().map(|f| f.to_string()).collect();
+        features.extend(self.built_databases(wtxn)?);
+        if self.proximity_database_enabled(wtxn)? {
+            features.push("proximity-database".to_string());
+        }
+
+        let metadata = IndexMetadata {
+            created_at: self.created_at(wtxn)?,
+            milli_version: env!("CARGO_PKG_VERSION").to_string(),
+            tokenizer_version: TOKENIZER_VERSION.to_string(),
+            features,
+            primary_key: self.primary_key(wtxn)?.map(ToOwned::to_owned),
+            settings_hash: SettingsSnapshot::new(wtxn, self)?.hash()?,
+        };
+
+        self.main.put::<_, Str, SerdeJson<IndexMetadata>>(wtxn, METADATA_KEY, &metadata)?;
+        Ok(())
+    }
+
+    /// Copies the LMDB environment of the index into the given file, without the free pages.
+    ///
+    /// The copy is made from a read transaction, it can be done while the index is used,
+    /// the copy can then replace the original file while the index is closed.
+    pub fn copy_compacted_to_path<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        self.env.copy_to_path(path, CompactionOption::Enabled)?;
+        Ok(())
+    }
+
+    /// Returns the number of updates that have been applied to this index.
+    ///
+    /// This sequence number identifies the state of the index seen by a read transaction,
+    /// it can be given to `Search::snapshot` to make sure that the following searches
+    /// are executed on the exact same version of the index.
+    pub fn update_sequence(&self, rtxn: &RoTxn) -> heed::Result<u64> {
+        let sequence = self.main.get::<_, Str, OwnedType<u64>>(rtxn, UPDATE_SEQUENCE_KEY)?;
+        Ok(sequence.unwrap_or(0))
+    }
+
+    /// Returns a random identifier of the version of the index seen by the transaction.
+    ///
+    /// Unlike the update sequence it is never reused, a write transaction that is aborted
+    /// leaves a sequence number that the next one will use again but not its version.
+    pub(crate) fn update_version(&self, rtxn: &RoTxn) -> heed::Result<u128> {
+        match self.main.get::<_, Str, ByteSlice>(rtxn, UPDATE_VERSION_KEY)? {
+            Some(bytes) => Ok(bytes.try_into().map(u128::from_be_bytes).unwrap_or_default()),
+            None => Ok(0),
+        }
+    }
+
     pub(crate) fn set_updated_at(&self, wtxn: &mut RwTxn, time: &DateTime<Utc>) -> heed::Result<()> {
+        let sequence = self.update_sequence(wtxn)?;
+        self.main.put::<_, Str, OwnedType<u64>>(wtxn, UPDATE_SEQUENCE_KEY, &(sequence + 1))?;
+        let version = uuid::Uuid::new_v4();
+        self.main.put::<_, Str, ByteSlice>(wtxn, UPDATE_VERSION_KEY, version.as_bytes())?;
         self.main.put::<_, Str, SerdeJson<DateTime<Utc>>>(wtxn, UPDATED_AT_KEY, &time)
     }
 }
+
+/// Returns the key under which the nth chunk of a main database value is stored.
+pub(crate) fn chunk_key(key: &str, number: usize) -> String {
+    format!("{}-chunk-{}", key, number)
+}
+
+/// Returns the key under which the stored query with the given name is stored.
+fn stored_query_key(name: &str) -> String {
+    format!("{}-{}", STORED_QUERY_PREFIX, name)
+}
+
+/// Returns the key under which the original string facet values of a field are stored.
+pub(crate) fn facet_string_display_values_key(field_id: FieldId) -> String {
+    format!("{}-{}", FACET_STRING_DISPLAY_VALUES_PREFIX, field_id)
+}
+
+/// Records the original form of a normalized string facet value if it is the first one seen.
+///
+/// The original values of a field are stored and read as a whole, at most
+/// `MAX_FACET_STRING_DISPLAY_VALUES` of them are recorded, the other values are displayed normalized.
+pub(crate) fn insert_facet_string_display_value(
+    display_values: &mut BTreeMap<String, String>,
+    normalized: String,
+    original: String,
+)
+{
+    if display_values.len() < MAX_FACET_STRING_DISPLAY_VALUES {
+        display_values.entry(normalized).or_insert(original);
+    }
+}
+
+/// Records the positions encoding of the new indexes, see `proximity::ATTRIBUTE_SHIFT`.
+///
+/// The indexes written with the previous `attribute * 1000 + index` encoding don't record it,
+/// their attributes and proximities can't be decoded, they are refused when they have documents.
+fn check_positions_encoding(main: &PolyDatabase, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    if main.get::<_, Str, DecodeIgnore>(wtxn, POSITIONS_ENCODING_KEY)?.is_some() {
+        return Ok(());
+    }
+
+    let documents_ids = main.get::<_, Str, RoaringBitmapCodec>(wtxn, DOCUMENTS_IDS_KEY)?;
+    if documents_ids.map_or(false, |ids| !ids.is_empty()) {
+        anyhow::bail!("the index was written with a previous positions encoding and must be reindexed");
+    }
+
+    main.put::<_, Str, Str>(wtxn, POSITIONS_ENCODING_KEY, "shifted-attribute")?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::ops::Deref;
+
+    use heed::EnvOpenOptions;
+
+    use crate::update::{IndexDocuments, Settings, UpdateFormat};
+
+    /// An index stored in a temporary directory that is deleted along with the index.
+    pub(crate) struct TempIndex {
+        inner: Index,
+        _tempdir: tempfile::TempDir,
+    }
+
+    impl TempIndex {
+        /// Returns a new index containing the documents of the CSV content.
+        pub(crate) fn from_csv(content: &[u8]) -> TempIndex {
+            TempIndex::from_csv_with_settings(content, |_| ())
+        }
+
+        /// Returns a new index containing the documents of the CSV content,
+        /// the settings are updated by the function before the documents are indexed.
+        pub(crate) fn from_csv_with_settings<F>(content: &[u8], update_settings: F) -> TempIndex
+        where F: FnOnce(&mut Settings),
+        {
+            let path = tempfile::tempdir().unwrap();
+            let mut options = EnvOpenOptions::new();
+            options.map_size(10 * 1024 * 1024); // 10 MB
+            let index = Index::new(options, &path).unwrap();
+
+            let mut wtxn = index.write_txn().unwrap();
+            let mut builder = Settings::new(&mut wtxn, &index, 0);
+            update_settings(&mut builder);
+            builder.execute(|_, _| ()).unwrap();
+
+            let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+            builder.update_format(UpdateFormat::Csv);
+            builder.execute(content, |_, _| ()).unwrap();
+            wtxn.commit().unwrap();
+
+            TempIndex { inner: index, _tempdir: path }
+        }
+    }
+
+    impl Deref for TempIndex {
+        type Target = Index;
+
+        fn deref(&self) -> &Index {
+            &self.inner
+        }
+    }
+
+    #[test]
+    fn refuse_previous_positions_encoding() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // An index written by a previous version has documents but no positions encoding.
+        let mut wtxn = index.write_txn().unwrap();
+        index.put_documents_ids(&mut wtxn, &(0..3).collect()).unwrap();
+        index.main.delete::<_, Str>(&mut wtxn, POSITIONS_ENCODING_KEY).unwrap();
+        wtxn.commit().unwrap();
+
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        assert!(Index::new(options, &path).is_err());
+    }
+
+    #[test]
+    fn capped_facet_string_display_values() {
+        let mut display_values = BTreeMap::new();
+        for i in 0..MAX_FACET_STRING_DISPLAY_VALUES {
+            insert_facet_string_display_value(&mut display_values, format!("value {}", i), format!("Value {}", i));
+        }
+
+        // The first original value seen is kept and no value is recorded past the limit.
+        insert_facet_string_display_value(&mut display_values, "value 0".to_string(), "VALUE 0".to_string());
+        insert_facet_string_display_value(&mut display_values, "other".to_string(), "Other".to_string());
+        assert_eq!(display_values.len(), MAX_FACET_STRING_DISPLAY_VALUES);
+        assert_eq!(display_values.get("value 0").map(String::as_str), Some("Value 0"));
+        assert_eq!(display_values.get("other"), None);
+    }
+
+    #[test]
+    fn chunked_main_values() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let value: Vec<u8> = (0..100).collect();
+        let mut wtxn = index.write_txn().unwrap();
+        index.put_main_bytes_chunked(&mut wtxn, "value", &value, 30).unwrap();
+        assert_eq!(index.main_bytes(&wtxn, "value").unwrap().as_deref(), Some(&value[..]));
+        assert!(index.main.get::<_, Str, ByteSlice>(&wtxn, &chunk_key("value", 3)).unwrap().is_some());
+
+        // The chunks of the previous value are removed when a smaller one is written.
+        index.put_main_bytes_chunked(&mut wtxn, "value", &value[..40], 30).unwrap();
+        assert_eq!(index.main_bytes(&wtxn, "value").unwrap().as_deref(), Some(&value[..40]));
+        assert!(index.main.get::<_, Str, ByteSlice>(&wtxn, &chunk_key("value", 2)).unwrap().is_none());
+
+        // Values that fit in a single chunk are not copied.
+        index.put_main_bytes_chunked(&mut wtxn, "value", &value[..10], 30).unwrap();
+        assert!(matches!(index.main_bytes(&wtxn, "value").unwrap(), Some(Cow::Borrowed(_))));
+        assert!(index.main_bytes(&wtxn, "missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn criteria_of_previous_versions() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // The typo and proximity criteria were stored as unit variants.
+        let mut wtxn = index.write_txn().unwrap();
+        let criteria = serde_json::json!(["Typo", "Words", "Proximity", { "Asc": "age" }]);
+        index.main.put::<_, Str, SerdeJson<serde_json::Value>>(&mut wtxn, CRITERIA_KEY, &criteria).unwrap();
+
+        let expected = vec![
+            Criterion::Typo { max: None },
+            Criterion::Words,
+            Criterion::Proximity { max: None },
+            Criterion::Asc("age".to_string()),
+        ];
+        assert_eq!(index.criteria(&wtxn).unwrap(), expected);
+
+        // The criteria are written back in the current encoding.
+        index.put_criteria(&mut wtxn, &expected).unwrap();
+        assert_eq!(index.criteria(&wtxn).unwrap(), expected);
+    }
+
+    #[test]
+    fn metadata() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let metadata = index.metadata(&rtxn).unwrap().unwrap();
+        assert_eq!(metadata.created_at, index.created_at(&rtxn).unwrap());
+        assert_eq!(metadata.milli_version, env!("CARGO_PKG_VERSION"));
+        assert!(metadata.features.iter().any(|f| f == "proximity-database"));
+        assert_eq!(metadata.primary_key, None);
+        let settings_hash = metadata.settings_hash;
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n0,kevin\n"[..];
+        let mut builder = crate::update::IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(crate::update::UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        let mut builder = crate::update::Settings::new(&mut wtxn, &index, 1);
+        builder.set_proximity_database_enabled(false);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let metadata = index.metadata(&rtxn).unwrap().unwrap();
+        assert_eq!(metadata.primary_key.as_deref(), Some("id"));
+        assert!(!metadata.features.iter().any(|f| f == "proximity-database"));
+        assert_ne!(metadata.settings_hash, settings_hash);
+        drop(rtxn);
+
+        // An index written by a version without metadata has it written on open.
+        let mut wtxn = index.write_txn().unwrap();
+        index.main.delete::<_, Str>(&mut wtxn, METADATA_KEY).unwrap();
+        wtxn.commit().unwrap();
+
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+        let rtxn = index.read_txn().unwrap();
+        let metadata = index.metadata(&rtxn).unwrap().unwrap();
+        assert_eq!(metadata.tokenizer_version, TOKENIZER_VERSION);
+        assert_eq!(metadata.primary_key.as_deref(), Some("id"));
+    }
+}
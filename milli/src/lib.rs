@@ -1,14 +1,20 @@
 #[macro_use] extern crate pest_derive;
 
+mod computed_field;
 mod criterion;
 mod external_documents_ids;
 mod fields_ids_map;
 mod search;
+mod snapshot;
+#[cfg(feature = "update-store")]
 mod update_store;
 pub mod facet;
 pub mod heed_codec;
 pub mod index;
+#[cfg(feature = "packed")]
+pub mod packed;
 pub mod proximity;
+pub mod storage;
 pub mod update;
 
 use std::borrow::Cow;
@@ -19,14 +25,24 @@ use anyhow::Context;
 use fxhash::{FxHasher32, FxHasher64};
 use serde_json::{Map, Value};
 
-pub use self::criterion::{Criterion, default_criteria};
+pub use self::computed_field::ComputedField;
+pub use self::criterion::{AscDesc, Criterion, NullsPlacement, default_criteria, DEFAULT_ATTRIBUTE_WEIGHT};
 pub use self::external_documents_ids::ExternalDocumentsIds;
 pub use self::fields_ids_map::FieldsIdsMap;
-pub use self::heed_codec::{BEU32StrCodec, StrStrU8Codec, ObkvCodec};
+pub use self::heed_codec::{BEU32StrCodec, StrBEU32Codec, StrStrU8Codec, ObkvCodec};
 pub use self::heed_codec::{RoaringBitmapCodec, BoRoaringBitmapCodec, CboRoaringBitmapCodec};
 pub use self::heed_codec::{RoaringBitmapLenCodec, BoRoaringBitmapLenCodec, CboRoaringBitmapLenCodec};
-pub use self::index::Index;
-pub use self::search::{Search, FacetDistribution, FacetCondition, SearchResult, MatchingWords};
+pub use self::heed_codec::{RoaringBitmapProbe, RoaringBitmapProbeCodec};
+pub use self::index::{Index, IndexMetadata};
+#[cfg(feature = "packed")]
+pub use self::packed::{PackedIndex, PackedSearch, PackedSearchResult};
+pub use self::search::{Search, Distinct, DistinctGroup, DistinctMode, FacetDistribution, FacetStats, FacetValues, FacetCondition, SearchResult, MatchingWords};
+pub use self::snapshot::IndexSnapshot;
+pub use self::search::{CriterionBuckets, Cursor, DocumentsGroup, MatchPosition, Percolator, QueryCost, Relaxation, SearchDefaults, StoredQuery, TotalHits};
+pub use self::search::{QueryLimits, QueryTooComplex, TermsMatchingStrategy, TypoDetails, TyposReason, WordTypos};
+pub use self::search::{ExactMatch, Rank, ScoreDetail};
+pub use self::search::{criteria, format};
+#[cfg(feature = "update-store")]
 pub use self::update_store::UpdateStore;
 
 pub type FastMap4<K, V> = HashMap<K, V, BuildHasherDefault<FxHasher32>>;
@@ -41,6 +57,7 @@ pub type Attribute = u32;
 pub type DocumentId = u32;
 pub type FieldId = u8;
 pub type Position = u32;
+pub type Proximity = u8;
 
 type MergeFn = for<'a> fn(&[u8], &[Cow<'a, [u8]>]) -> anyhow::Result<Vec<u8>>;
 
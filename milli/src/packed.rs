@@ -0,0 +1,587 @@
+//! A read-only and immutable representation of an index stored in a single file.
+//!
+//! A packed index contains the same entries as the LMDB databases of an index but without
+//! the free pages and the B-tree overhead, it is memory-mapped and meant to be shipped to
+//! and served by nodes that never update it.
+//!
+//! The file starts with the `MAGIC` bytes followed by the databases in the `DATABASES` order,
+//! every database is the big-endian `u64` number of entries followed by the entries sorted by
+//! key, an entry is the big-endian `u32` lengths of the key and the value followed by them.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::{Bound, Range};
+use std::path::Path;
+
+use anyhow::{bail, Context as _};
+use heed::{BytesDecode, BytesEncode, RoTxn};
+use meilisearch_tokenizer::{Analyzer, AnalyzerConfig, TokenKind};
+use memmap::Mmap;
+use roaring::RoaringBitmap;
+
+use crate::index::{chunk_key, ATTRIBUTES_WEIGHTS_KEY, DOCUMENTS_IDS_KEY, FIELDS_IDS_MAP_KEY};
+use crate::index::{PROXIMITY_DATABASE_ENABLED_KEY, STOP_WORDS_KEY, WORDS_FST_KEY, WORDS_PREFIXES_FST_KEY};
+use crate::search::criteria::{cooccurrence_docids, words_criteria, AttributesWeights, Context};
+use crate::search::{build_query_tree, TermsMatchingStrategy, DEFAULT_SEARCH_LIMIT};
+use crate::storage::{IndexDatabase, ReadStorage, StorageIter};
+use crate::{BEU32StrCodec, BoRoaringBitmapCodec, CboRoaringBitmapCodec, RoaringBitmapCodec, StrStrU8Codec};
+use crate::{RoaringBitmapLenCodec, RoaringBitmapProbeCodec, StrBEU32Codec};
+use crate::{DocumentId, FieldsIdsMap, Index};
+
+const MAGIC: &[u8; 16] = b"milli-packed-v5\0";
+
+/// The names of the databases, in the order they are written in the file, see `IndexDatabase::ALL`.
+pub const DATABASES: [&str; 13] = [
+    "main",
+    "word-docids",
+    "word-prefix-docids",
+    "docid-word-positions",
+    "word-pair-proximity-docids",
+    "word-prefix-pair-proximity-docids",
+    "facet-field-id-value-docids",
+    "field-id-docid-facet-values",
+    "documents",
+    "word-docids-shards",
+    "prefix-word-pair-proximity-docids",
+    "word-position-docids",
+    "word-attribute-docids",
+];
+
+const MAIN: usize = 0;
+const WORD_DOCIDS: usize = 1;
+const WORD_PREFIX_DOCIDS: usize = 2;
+const DOCID_WORD_POSITIONS: usize = 3;
+const WORD_PAIR_PROXIMITY_DOCIDS: usize = 4;
+const WORD_PREFIX_PAIR_PROXIMITY_DOCIDS: usize = 5;
+const DOCUMENTS: usize = 8;
+const PREFIX_WORD_PAIR_PROXIMITY_DOCIDS: usize = 10;
+const WORD_POSITION_DOCIDS: usize = 11;
+const WORD_ATTRIBUTE_DOCIDS: usize = 12;
+
+/// Writes all the entries of the index, as seen by the given transaction, into the writer.
+pub fn write_packed_index<W: Write>(index: &Index, rtxn: &RoTxn, mut writer: W) -> anyhow::Result<()> {
+    writer.write_all(MAGIC)?;
+
+    for database in IndexDatabase::ALL.iter() {
+        let storage = index.storage(rtxn, *database);
+        let count = storage.range((Bound::Unbounded, Bound::Unbounded))?
+            .fold(Ok(0u64), |count, result| result.and(count).map(|c| c + 1))?;
+        writer.write_all(&count.to_be_bytes())?;
+
+        for result in storage.range((Bound::Unbounded, Bound::Unbounded))? {
+            let (key, value) = result?;
+            let key_len: u32 = key.len().try_into().context("packed key too long")?;
+            let value_len: u32 = value.len().try_into().context("packed value too long")?;
+            writer.write_all(&key_len.to_be_bytes())?;
+            writer.write_all(&value_len.to_be_bytes())?;
+            writer.write_all(&key)?;
+            writer.write_all(&value)?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+struct Entry {
+    key: Range<usize>,
+    value: Range<usize>,
+}
+
+/// An index read from a packed file, the entries are never copied but read from the memory map.
+pub struct PackedIndex {
+    mmap: Mmap,
+    databases: Vec<Vec<Entry>>,
+}
+
+impl PackedIndex {
+    /// Opens a file written by `Index::export_packed`, only the offsets of the entries are loaded.
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<PackedIndex> {
+        let file = File::open(path)?;
+        // Safety: the packed files are immutable, they must not be modified while they are mapped.
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let bytes = &mmap[..];
+        if !bytes.starts_with(MAGIC) {
+            bail!("invalid packed index, the magic bytes are missing");
+        }
+
+        let mut offset = MAGIC.len();
+        let mut databases = Vec::with_capacity(DATABASES.len());
+        for name in DATABASES.iter() {
+            let count = read_u64(bytes, &mut offset).with_context(|| format!("truncated {} database", name))?;
+            // The count is read from the file, an entry is at least the two lengths of its key and value.
+            let remaining_entries = (bytes.len() - offset) / 8;
+            let mut entries = Vec::with_capacity(remaining_entries.min(count as usize));
+            for _ in 0..count {
+                let key_len = read_u32(bytes, &mut offset).with_context(|| format!("truncated {} database", name))?;
+                let value_len = read_u32(bytes, &mut offset).with_context(|| format!("truncated {} database", name))?;
+                let key = offset..offset + key_len as usize;
+                let value = key.end..key.end + value_len as usize;
+                if value.end > bytes.len() {
+                    bail!("truncated {} database", name);
+                }
+                offset = value.end;
+                entries.push(Entry { key, value });
+            }
+            databases.push(entries);
+        }
+
+        Ok(PackedIndex { mmap, databases })
+    }
+
+    /// Returns the database with the given name, see `DATABASES`.
+    pub fn database(&self, name: &str) -> Option<PackedDatabase> {
+        let index = DATABASES.iter().position(|n| *n == name)?;
+        Some(PackedDatabase { packed: self, index })
+    }
+
+    /// Returns a search context reading the words and the documents ids from this packed index.
+    pub(crate) fn context(&self) -> anyhow::Result<PackedContext> {
+        let words_fst = self.words_fst(WORDS_FST_KEY)?;
+        let words_prefixes_fst = self.words_fst(WORDS_PREFIXES_FST_KEY)?;
+        let proximity_database_enabled = match self.main_bytes(PROXIMITY_DATABASE_ENABLED_KEY) {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => true,
+        };
+
+        Ok(PackedContext { packed: self, words_fst, words_prefixes_fst, proximity_database_enabled })
+    }
+
+    /// Returns a search on this packed index, see `PackedSearch`.
+    pub fn search(&self) -> PackedSearch {
+        PackedSearch {
+            packed: self,
+            query: None,
+            offset: 0,
+            limit: DEFAULT_SEARCH_LIMIT,
+            terms_matching_strategy: TermsMatchingStrategy::default(),
+        }
+    }
+
+    /// Returns the internal documents ids.
+    pub fn documents_ids(&self) -> anyhow::Result<RoaringBitmap> {
+        match self.main_bytes(DOCUMENTS_IDS_KEY) {
+            Some(bytes) => Ok(RoaringBitmapCodec::bytes_decode(&bytes).ok_or(heed::Error::Decoding)?),
+            None => Ok(RoaringBitmap::new()),
+        }
+    }
+
+    /// Returns the document as an obkv store, the same way `Index::documents` does.
+    pub fn document(&self, id: DocumentId) -> Option<obkv::KvReader> {
+        self.get(DOCUMENTS, &id.to_be_bytes()).map(obkv::KvReader::new)
+    }
+
+    /// Returns the weights of the attributes, the same way `AttributesWeights::from_index` does.
+    fn attributes_weights(&self) -> anyhow::Result<AttributesWeights> {
+        let fields_ids_map: FieldsIdsMap = match self.main_bytes(FIELDS_IDS_MAP_KEY) {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => FieldsIdsMap::new(),
+        };
+        let weights: HashMap<String, u8> = match self.main_bytes(ATTRIBUTES_WEIGHTS_KEY) {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => HashMap::new(),
+        };
+
+        let weights = weights.into_iter()
+            .filter_map(|(name, weight)| fields_ids_map.id(&name).map(|id| (id as u32, weight)))
+            .collect();
+        let attributes = fields_ids_map.iter().map(|(id, _)| id as u32).collect();
+        Ok(AttributesWeights::new(weights, attributes))
+    }
+
+    fn words_fst(&self, key: &str) -> anyhow::Result<fst::Set<Cow<[u8]>>> {
+        match self.main_bytes(key) {
+            Some(bytes) => Ok(fst::Set::new(bytes)?),
+            None => Ok(fst::Set::default().map_data(Cow::Owned)?),
+        }
+    }
+
+    /// Returns a value of the main database, reassembling its chunks like `Index::main_bytes`.
+    fn main_bytes(&self, key: &str) -> Option<Cow<[u8]>> {
+        let mut bytes = Cow::Borrowed(self.get(MAIN, key.as_bytes())?);
+        for number in 1.. {
+            match self.get(MAIN, chunk_key(key, number).as_bytes()) {
+                Some(chunk) => bytes.to_mut().extend_from_slice(chunk),
+                None => break,
+            }
+        }
+        Some(bytes)
+    }
+
+    fn get(&self, database: usize, key: &[u8]) -> Option<&[u8]> {
+        let entries = &self.databases[database];
+        let position = entries.binary_search_by(|e| self.mmap[e.key.clone()].cmp(key)).ok()?;
+        Some(&self.mmap[entries[position].value.clone()])
+    }
+
+    /// Returns the position of the first entry of the database that is after the bound.
+    fn lower_bound(&self, database: usize, bound: Bound<&[u8]>) -> usize {
+        let entries = &self.databases[database];
+        match bound {
+            Bound::Included(key) => match entries.binary_search_by(|e| self.mmap[e.key.clone()].cmp(key)) {
+                Ok(i) | Err(i) => i,
+            },
+            Bound::Excluded(key) => match entries.binary_search_by(|e| self.mmap[e.key.clone()].cmp(key)) {
+                Ok(i) => i + 1,
+                Err(i) => i,
+            },
+            Bound::Unbounded => 0,
+        }
+    }
+
+    /// Returns the position following the last entry of the database that is before the bound.
+    fn upper_bound(&self, database: usize, bound: Bound<&[u8]>) -> usize {
+        let entries = &self.databases[database];
+        match bound {
+            Bound::Included(key) => match entries.binary_search_by(|e| self.mmap[e.key.clone()].cmp(key)) {
+                Ok(i) => i + 1,
+                Err(i) => i,
+            },
+            Bound::Excluded(key) => match entries.binary_search_by(|e| self.mmap[e.key.clone()].cmp(key)) {
+                Ok(i) | Err(i) => i,
+            },
+            Bound::Unbounded => entries.len(),
+        }
+    }
+
+    fn entries(&self, database: usize, range: Range<usize>) -> StorageIter {
+        let entries = &self.databases[database];
+        let start = range.start.min(range.end);
+        Box::new(entries[start..range.end].iter().map(move |e| {
+            Ok((Cow::Borrowed(&self.mmap[e.key.clone()]), Cow::Borrowed(&self.mmap[e.value.clone()])))
+        }))
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: &mut usize) -> Option<u32> {
+    let array = bytes.get(*offset..*offset + 4)?.try_into().ok()?;
+    *offset += 4;
+    Some(u32::from_be_bytes(array))
+}
+
+fn read_u64(bytes: &[u8], offset: &mut usize) -> Option<u64> {
+    let array = bytes.get(*offset..*offset + 8)?.try_into().ok()?;
+    *offset += 8;
+    Some(u64::from_be_bytes(array))
+}
+
+/// A search on a packed index, the documents are ranked by the default ranking rules that only
+/// read the words: typo, words, proximity, attribute, words position and exactness.
+///
+/// The filters, the facets and the other parameters of the `Search` of an `Index` are not supported.
+pub struct PackedSearch<'p> {
+    packed: &'p PackedIndex,
+    query: Option<String>,
+    offset: usize,
+    limit: usize,
+    terms_matching_strategy: TermsMatchingStrategy,
+}
+
+impl<'p> PackedSearch<'p> {
+    pub fn query(&mut self, query: impl Into<String>) -> &mut PackedSearch<'p> {
+        self.query = Some(query.into());
+        self
+    }
+
+    pub fn offset(&mut self, offset: usize) -> &mut PackedSearch<'p> {
+        self.offset = offset;
+        self
+    }
+
+    pub fn limit(&mut self, limit: usize) -> &mut PackedSearch<'p> {
+        self.limit = limit;
+        self
+    }
+
+    pub fn terms_matching_strategy(&mut self, strategy: TermsMatchingStrategy) -> &mut PackedSearch<'p> {
+        self.terms_matching_strategy = strategy;
+        self
+    }
+
+    pub fn execute(&self) -> anyhow::Result<PackedSearchResult> {
+        let ctx = self.packed.context()?;
+        let stop_words = self.packed.words_fst(STOP_WORDS_KEY)?;
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(&stop_words));
+
+        let (query_tree, query_words) = match self.query.as_ref() {
+            Some(query) => {
+                let result = analyzer.analyze(query);
+                let query_words: Vec<_> = result.tokens()
+                    .filter(|token| matches!(token.kind, TokenKind::Word))
+                    .map(|token| token.word.to_string())
+                    .collect();
+                let query_tree = build_query_tree(&ctx, self.terms_matching_strategy, result.tokens())?;
+                (query_tree, query_words)
+            },
+            None => (None, Vec::new()),
+        };
+
+        let weights = self.packed.attributes_weights()?;
+        let mut criteria = words_criteria(&ctx, query_tree, &query_words, &weights)?;
+
+        let mut offset = self.offset;
+        let mut limit = self.limit;
+        let mut candidates = RoaringBitmap::new();
+        let mut documents_ids = Vec::new();
+        while limit != 0 {
+            let result = match criteria.next()? {
+                Some(result) => result,
+                None => break,
+            };
+
+            candidates.union_with(&result.bucket_candidates);
+
+            let skipped = offset.min(result.candidates.len() as usize);
+            let bucket_start = documents_ids.len();
+            documents_ids.extend(result.candidates.iter().skip(skipped).take(limit));
+            offset -= skipped;
+            limit -= documents_ids.len() - bucket_start;
+        }
+
+        Ok(PackedSearchResult { candidates, documents_ids })
+    }
+}
+
+/// The documents returned by a `PackedSearch`.
+#[derive(Debug, Default)]
+pub struct PackedSearchResult {
+    /// The documents matching the query that have been seen while ranking the returned ones.
+    pub candidates: RoaringBitmap,
+    pub documents_ids: Vec<DocumentId>,
+}
+
+/// One of the databases of a packed index.
+pub struct PackedDatabase<'p> {
+    packed: &'p PackedIndex,
+    index: usize,
+}
+
+impl ReadStorage for PackedDatabase<'_> {
+    fn get(&self, key: &[u8]) -> anyhow::Result<Option<Cow<[u8]>>> {
+        Ok(self.packed.get(self.index, key).map(Cow::Borrowed))
+    }
+
+    fn range<'a>(&'a self, range: (Bound<&[u8]>, Bound<&[u8]>)) -> anyhow::Result<StorageIter<'a>> {
+        let start = self.packed.lower_bound(self.index, range.0);
+        let end = self.packed.upper_bound(self.index, range.1);
+        Ok(self.packed.entries(self.index, start..end))
+    }
+
+    fn prefix_iter<'a>(&'a self, prefix: &[u8]) -> anyhow::Result<StorageIter<'a>> {
+        let start = self.packed.lower_bound(self.index, Bound::Included(prefix));
+        let entries = &self.packed.databases[self.index][start..];
+        let len = entries.iter().take_while(|e| self.packed.mmap[e.key.clone()].starts_with(prefix)).count();
+        Ok(self.packed.entries(self.index, start..start + len))
+    }
+}
+
+pub(crate) struct PackedContext<'p> {
+    packed: &'p PackedIndex,
+    words_fst: fst::Set<Cow<'p, [u8]>>,
+    words_prefixes_fst: fst::Set<Cow<'p, [u8]>>,
+    proximity_database_enabled: bool,
+}
+
+impl<'p> PackedContext<'p> {
+    fn docids<C>(&self, database: usize, key: &[u8]) -> heed::Result<Option<RoaringBitmap>>
+    where C: BytesDecode<'p, DItem = RoaringBitmap>,
+    {
+        match self.packed.get(database, key) {
+            Some(bytes) => C::bytes_decode(bytes).ok_or(heed::Error::Decoding).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Context for PackedContext<'_> {
+    fn documents_ids(&self) -> heed::Result<RoaringBitmap> {
+        match self.packed.main_bytes(DOCUMENTS_IDS_KEY) {
+            Some(bytes) => RoaringBitmapCodec::bytes_decode(&bytes).ok_or(heed::Error::Decoding),
+            None => Ok(RoaringBitmap::new()),
+        }
+    }
+
+    fn word_docids(&self, word: &str) -> heed::Result<Option<RoaringBitmap>> {
+        self.docids::<RoaringBitmapCodec>(WORD_DOCIDS, word.as_bytes())
+    }
+
+    fn word_docids_within(&self, word: &str, candidates: &RoaringBitmap) -> heed::Result<Option<RoaringBitmap>> {
+        match self.packed.get(WORD_DOCIDS, word.as_bytes()) {
+            Some(bytes) => {
+                let probe = RoaringBitmapProbeCodec::bytes_decode(bytes).ok_or(heed::Error::Decoding)?;
+                Ok(Some(probe.intersection(candidates)))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn word_documents_count(&self, word: &str) -> heed::Result<Option<u64>> {
+        match self.packed.get(WORD_DOCIDS, word.as_bytes()) {
+            Some(bytes) => RoaringBitmapLenCodec::bytes_decode(bytes).ok_or(heed::Error::Decoding).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn word_prefix_docids(&self, word: &str) -> heed::Result<Option<RoaringBitmap>> {
+        self.docids::<RoaringBitmapCodec>(WORD_PREFIX_DOCIDS, word.as_bytes())
+    }
+
+    fn word_pair_proximity_docids(&self, left: &str, right: &str, proximity: u8) -> heed::Result<Option<RoaringBitmap>> {
+        if !self.proximity_database_enabled {
+            let left = self.word_docids(left)?;
+            let right = self.word_docids(right)?;
+            return Ok(cooccurrence_docids(left, right, proximity));
+        }
+
+        let key = StrStrU8Codec::bytes_encode(&(left, right, proximity)).ok_or(heed::Error::Encoding)?;
+        self.docids::<CboRoaringBitmapCodec>(WORD_PAIR_PROXIMITY_DOCIDS, &key)
+    }
+
+    fn word_prefix_pair_proximity_docids(&self, left: &str, right: &str, proximity: u8) -> heed::Result<Option<RoaringBitmap>> {
+        if !self.proximity_database_enabled {
+            let left = self.word_docids(left)?;
+            let right = self.word_prefix_docids(right)?;
+            return Ok(cooccurrence_docids(left, right, proximity));
+        }
+
+        let key = StrStrU8Codec::bytes_encode(&(left, right, proximity)).ok_or(heed::Error::Encoding)?;
+        self.docids::<CboRoaringBitmapCodec>(WORD_PREFIX_PAIR_PROXIMITY_DOCIDS, &key)
+    }
+
+    fn prefix_word_pair_proximity_docids(&self, left: &str, right: &str, proximity: u8) -> heed::Result<Option<RoaringBitmap>> {
+        if !self.proximity_database_enabled {
+            let left = self.word_prefix_docids(left)?;
+            let right = self.word_docids(right)?;
+            return Ok(cooccurrence_docids(left, right, proximity));
+        }
+
+        let key = StrStrU8Codec::bytes_encode(&(left, right, proximity)).ok_or(heed::Error::Encoding)?;
+        self.docids::<CboRoaringBitmapCodec>(PREFIX_WORD_PAIR_PROXIMITY_DOCIDS, &key)
+    }
+
+    fn word_position_docids(&self, word: &str, bucket: u32) -> heed::Result<Option<RoaringBitmap>> {
+        let key = StrBEU32Codec::bytes_encode(&(word, bucket)).ok_or(heed::Error::Encoding)?;
+        self.docids::<CboRoaringBitmapCodec>(WORD_POSITION_DOCIDS, &key)
+    }
+
+    fn word_attribute_docids(&self, word: &str, attribute: u32) -> heed::Result<Option<RoaringBitmap>> {
+        let key = StrBEU32Codec::bytes_encode(&(word, attribute)).ok_or(heed::Error::Encoding)?;
+        self.docids::<CboRoaringBitmapCodec>(WORD_ATTRIBUTE_DOCIDS, &key)
+    }
+
+    fn words_fst<'t>(&self) -> &'t fst::Set<Cow<[u8]>> {
+        &self.words_fst
+    }
+
+    fn in_prefix_cache(&self, word: &str) -> bool {
+        self.words_prefixes_fst.contains(word)
+    }
+
+    fn docid_words_positions(&self, docid: DocumentId) -> heed::Result<HashMap<String, RoaringBitmap>> {
+        let prefix = docid.to_be_bytes();
+        let database = self.packed.database(DATABASES[DOCID_WORD_POSITIONS]).unwrap();
+        let iter = database.prefix_iter(&prefix).map_err(|_| heed::Error::Decoding)?;
+
+        let mut words_positions = HashMap::new();
+        for result in iter {
+            let (key, value) = result.map_err(|_| heed::Error::Decoding)?;
+            let (_, word) = BEU32StrCodec::bytes_decode(&key).ok_or(heed::Error::Decoding)?;
+            let positions = BoRoaringBitmapCodec::bytes_decode(&value).ok_or(heed::Error::Decoding)?;
+            words_positions.insert(word.to_string(), positions);
+        }
+        Ok(words_positions)
+    }
+
+    fn proximity_database_enabled(&self) -> bool {
+        self.proximity_database_enabled
+    }
+}
+
+impl crate::search::QueryTreeContext for PackedContext<'_> {
+    fn word_docids(&self, word: &str) -> heed::Result<Option<RoaringBitmap>> {
+        Context::word_docids(self, word)
+    }
+
+    fn word_documents_count(&self, word: &str) -> heed::Result<Option<u64>> {
+        Context::word_documents_count(self, word)
+    }
+
+    fn synonyms<S: AsRef<str>>(&self, _words: &[S]) -> heed::Result<Option<Vec<Vec<String>>>> {
+        // The synonyms are only given with the `Search` of an `Index`.
+        Ok(None)
+    }
+}
+
+impl Index {
+    /// Writes the whole index into a single packed file that can be opened with `PackedIndex::open`.
+    pub fn export_packed<W: io::Write>(&self, rtxn: &RoTxn, writer: W) -> anyhow::Result<()> {
+        write_packed_index(self, rtxn, writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heed::EnvOpenOptions;
+
+    use crate::update::{IndexDocuments, UpdateFormat};
+    use super::*;
+
+    #[test]
+    fn export_and_read_packed_index() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n1,kevin\n2,kevina\n3,benoit\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        index.export_packed(&rtxn, &mut file).unwrap();
+
+        let packed = PackedIndex::open(file.path()).unwrap();
+        assert_eq!(packed.documents_ids().unwrap(), index.documents_ids(&rtxn).unwrap());
+        let (_, document) = index.documents(&rtxn, Some(2)).unwrap().pop().unwrap();
+        assert_eq!(packed.document(2).map(|d| d.iter().collect::<Vec<_>>()), Some(document.iter().collect()));
+
+        let ctx = packed.context().unwrap();
+        assert!(ctx.words_fst().contains("kevina"));
+        let kevin = index.word_docids.get(&rtxn, "kevin").unwrap();
+        assert_eq!(ctx.word_docids("kevin").unwrap(), kevin);
+        assert_eq!(ctx.word_docids("nobody").unwrap(), None);
+        let positions = ctx.docid_words_positions(2).unwrap();
+        assert_eq!(positions.keys().collect::<Vec<_>>(), vec!["benoit"]);
+
+        let result = index.search(&rtxn).query("kevin").execute().unwrap();
+        let packed_result = packed.search().query("kevin").execute().unwrap();
+        assert_eq!(packed_result.documents_ids, result.documents_ids);
+        assert_eq!(packed_result.candidates, (0..2).collect());
+        let packed_result = packed.search().query("kevin").offset(1).execute().unwrap();
+        assert_eq!(packed_result.documents_ids, &result.documents_ids[1..]);
+
+        let words = packed.database("word-docids").unwrap();
+        let prefixed = words.prefix_iter(b"kev").unwrap().map(|r| r.unwrap().0.into_owned());
+        assert_eq!(prefixed.collect::<Vec<_>>(), vec![b"kevin".to_vec(), b"kevina".to_vec()]);
+    }
+
+    #[test]
+    fn open_corrupted_packed_index() {
+        // The number of entries of the first database is far bigger than the file.
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(MAGIC).unwrap();
+        file.write_all(&u64::MAX.to_be_bytes()).unwrap();
+        file.write_all(&[0; 16]).unwrap();
+        file.flush().unwrap();
+
+        assert!(PackedIndex::open(file.path()).is_err());
+    }
+}
@@ -1,8 +1,44 @@
+//! The encoding of the positions of the words and the proximities between them.
+//!
+//! Every computation in this module saturates at the limits defined below, an extreme
+//! document (e.g. a huge attribute or a lot of attributes) can lose some precision but
+//! never produces a position of another attribute or a wrapped proximity.
+
 use std::cmp;
-use crate::{Attribute, Position};
+use std::ops::RangeInclusive;
+
+use roaring::RoaringBitmap;
+
+use crate::{Attribute, Position, Proximity};
+
+/// The number of low bits of a position that store the index of the word in its attribute,
+/// the high bits store the attribute. The attribute of a position is therefore a shift away
+/// and the positions of an attribute are a contiguous range, see `attribute_positions`.
+pub const ATTRIBUTE_SHIFT: u32 = 10;
+
+/// The number of positions reserved for every attribute.
+pub const ONE_ATTRIBUTE: u32 = 1 << ATTRIBUTE_SHIFT;
+
+/// The biggest index of a word in its attribute, the words that follow it are either
+/// ignored or indexed at this index, see `MaxPositionPolicy`.
+pub const MAX_INDEX_IN_ATTRIBUTE: Position = ONE_ATTRIBUTE - 1;
+
+/// The biggest attribute for which the positions can be encoded without overflowing.
+pub const MAX_ATTRIBUTE: Attribute = Position::max_value() >> ATTRIBUTE_SHIFT;
+
+/// The last position bucket, the one of the words at the end of the longest attributes.
+pub const MAX_POSITION_BUCKET: u32 = 14;
+
+/// The distance between two words in different attributes, the proximity never crosses attributes.
+pub const MAX_DISTANCE: u32 = 8;
+
+/// The biggest proximity of a pair of words stored in the words pairs proximities databases,
+/// the words that are further apart are only considered to be part of the same document.
+pub const MAX_PAIR_PROXIMITY: Proximity = 7;
 
-const ONE_ATTRIBUTE: u32 = 1000;
-const MAX_DISTANCE: u32 = 8;
+/// The biggest proximity between the words of a query, the bigger proximities saturate at it.
+/// It is lower than the maximum of a `Proximity` for the criterion to be able to move past it.
+pub const MAX_QUERY_PROXIMITY: Proximity = Proximity::max_value() - 1;
 
 pub fn index_proximity(lhs: u32, rhs: u32) -> u32 {
     if lhs <= rhs {
@@ -19,10 +55,120 @@ pub fn positions_proximity(lhs: Position, rhs: Position) -> u32 {
     else { index_proximity(lhs_index, rhs_index) }
 }
 
+/// Returns the proximity of two positions as it is stored in the pairs proximities keys,
+/// it is never bigger than `MAX_DISTANCE`.
+pub fn pair_proximity(lhs: Position, rhs: Position) -> Proximity {
+    positions_proximity(lhs, rhs) as Proximity
+}
+
+/// Returns the position of the word at this index of the attribute, the index saturates
+/// at `MAX_INDEX_IN_ATTRIBUTE` and the attribute at `MAX_ATTRIBUTE`.
+pub fn encode_position(attribute: Attribute, index: Position) -> Position {
+    let attribute = cmp::min(attribute, MAX_ATTRIBUTE);
+    let index = cmp::min(index, MAX_INDEX_IN_ATTRIBUTE);
+    (attribute << ATTRIBUTE_SHIFT) | index
+}
+
+/// Returns the attribute and the index in this attribute of a position.
 pub fn extract_position(position: Position) -> (Attribute, Position) {
-    (position / ONE_ATTRIBUTE, position % ONE_ATTRIBUTE)
+    (position_attribute(position), position & MAX_INDEX_IN_ATTRIBUTE)
+}
+
+/// Returns the attribute of a position.
+pub fn position_attribute(position: Position) -> Attribute {
+    position >> ATTRIBUTE_SHIFT
+}
+
+/// Returns the range of the positions of an attribute.
+pub fn attribute_positions(attribute: Attribute) -> RangeInclusive<Position> {
+    encode_position(attribute, 0)..=encode_position(attribute, MAX_INDEX_IN_ATTRIBUTE)
+}
+
+/// Returns the distinct attributes of the positions in ascending order.
+pub fn positions_attributes<'a>(positions: &'a RoaringBitmap) -> impl Iterator<Item=Attribute> + 'a {
+    let mut last = None;
+    positions.iter().filter_map(move |position| {
+        let attribute = position_attribute(position);
+        if last == Some(attribute) { return None }
+        last = Some(attribute);
+        Some(attribute)
+    })
+}
+
+/// Returns the bucket of the index of a word in its attribute, the first eight indexes have
+/// their own bucket and the following ones are grouped by powers of two (e.g. `8..16`, `16..32`).
+pub fn position_bucket(index: Position) -> u32 {
+    if index < 8 {
+        index
+    } else {
+        4 + (32 - index.leading_zeros())
+    }
 }
 
 pub fn path_proximity(path: &[Position]) -> u32 {
-    path.windows(2).map(|w| positions_proximity(w[0], w[1])).sum::<u32>()
+    path.windows(2).map(|w| positions_proximity(w[0], w[1])).fold(0, u32::saturating_add)
+}
+
+/// Adds two proximities of a query, the sum saturates at `MAX_QUERY_PROXIMITY`.
+pub fn add_query_proximities(lhs: Proximity, rhs: Proximity) -> Proximity {
+    cmp::min(lhs.saturating_add(rhs), MAX_QUERY_PROXIMITY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_never_cross_attributes() {
+        let last_of_first = encode_position(0, ONE_ATTRIBUTE - 1);
+        let first_of_second = encode_position(1, 0);
+        assert_eq!(extract_position(last_of_first), (0, ONE_ATTRIBUTE - 1));
+        assert_eq!(extract_position(first_of_second), (1, 0));
+
+        // The words are consecutive in the position space but not in the same attribute.
+        assert_eq!(positions_proximity(last_of_first, first_of_second), MAX_DISTANCE);
+        assert_eq!(positions_proximity(encode_position(1, 3), encode_position(1, 4)), 1);
+    }
+
+    #[test]
+    fn attributes_of_positions() {
+        assert_eq!(position_attribute(encode_position(3, 12)), 3);
+        assert_eq!(attribute_positions(2), 2048..=3071);
+        assert!(attribute_positions(2).all(|p| position_attribute(p) == 2));
+
+        let positions: RoaringBitmap = vec![
+            encode_position(0, 1),
+            encode_position(0, 7),
+            encode_position(2, 0),
+            encode_position(5, 3),
+            encode_position(5, 4),
+        ].into_iter().collect();
+        assert_eq!(positions_attributes(&positions).collect::<Vec<_>>(), vec![0, 2, 5]);
+    }
+
+    #[test]
+    fn positions_saturate() {
+        // The index saturates in its attribute, it never leaks into the next attribute.
+        let position = encode_position(2, ONE_ATTRIBUTE + 20);
+        assert_eq!(extract_position(position), (2, MAX_INDEX_IN_ATTRIBUTE));
+
+        // The attribute saturates instead of overflowing the position.
+        let position = encode_position(Attribute::max_value(), 3);
+        assert_eq!(extract_position(position), (MAX_ATTRIBUTE, 3));
+
+        assert_eq!(pair_proximity(encode_position(0, 0), encode_position(0, 500)), MAX_DISTANCE as Proximity);
+        assert_eq!(add_query_proximities(MAX_QUERY_PROXIMITY - 1, MAX_PAIR_PROXIMITY), MAX_QUERY_PROXIMITY);
+        assert_eq!(add_query_proximities(3, 4), 7);
+    }
+
+    #[test]
+    fn positions_buckets() {
+        assert_eq!(position_bucket(0), 0);
+        assert_eq!(position_bucket(7), 7);
+        assert_eq!(position_bucket(8), 8);
+        assert_eq!(position_bucket(15), 8);
+        assert_eq!(position_bucket(16), 9);
+        assert_eq!(position_bucket(512), 14);
+        assert_eq!(position_bucket(ONE_ATTRIBUTE - 1), MAX_POSITION_BUCKET);
+    }
 }
@@ -0,0 +1,165 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use heed::RoTxn;
+use roaring::RoaringBitmap;
+
+use crate::Index;
+
+/// The words that appear in at least this number of documents have their documents ids cached.
+const FREQUENT_WORD_MIN_DOCUMENTS: u64 = 10_000;
+
+/// The maximum number of frequent words bitmaps cached for a version of the index.
+const MAX_FREQUENT_WORDS: usize = 1_000;
+
+/// The decoded structures of the last version of an index, shared by all the searches
+/// made on this version.
+///
+/// Every version is identified by the random update version of the index, not by the update
+/// sequence: a write transaction that searches its own changes and is then aborted must not
+/// leave its structures under a sequence number that the next commit will use again.
+/// A search made with a transaction that sees a newer update sequence replaces the cached
+/// version, the searches still using the previous one keep their own copy alive.
+#[derive(Default)]
+pub struct SearchCache {
+    current: RwLock<Option<Arc<SearchStructures>>>,
+}
+
+impl SearchCache {
+    /// Returns the structures corresponding to the version of the index seen by the transaction.
+    pub(crate) fn structures(&self, index: &Index, rtxn: &RoTxn) -> anyhow::Result<Arc<SearchStructures>> {
+        let update_sequence = index.update_sequence(rtxn)?;
+        let update_version = index.update_version(rtxn)?;
+
+        if let Some(current) = self.current.read().unwrap().as_ref() {
+            if current.update_version == update_version {
+                return Ok(current.clone());
+            }
+        }
+
+        let structures = Arc::new(SearchStructures::new(index, rtxn, update_sequence, update_version)?);
+
+        // We only keep the newest version, the transactions that see an older
+        // version of the index are not meant to live long.
+        let mut current = self.current.write().unwrap();
+        match current.as_ref() {
+            Some(current) if current.update_sequence > update_sequence => (),
+            _ => *current = Some(structures.clone()),
+        }
+
+        Ok(structures)
+    }
+
+    /// Drops the cached version, the next search will load the structures again.
+    pub fn clear(&self) {
+        *self.current.write().unwrap() = None;
+    }
+}
+
+pub struct SearchStructures {
+    update_sequence: u64,
+    update_version: u128,
+    pub words_fst: fst::Set<Cow<'static, [u8]>>,
+    pub words_prefixes_fst: fst::Set<Cow<'static, [u8]>>,
+    frequent_words: RwLock<HashMap<String, RoaringBitmap>>,
+}
+
+impl SearchStructures {
+    fn new(
+        index: &Index,
+        rtxn: &RoTxn,
+        update_sequence: u64,
+        update_version: u128,
+    ) -> anyhow::Result<SearchStructures>
+    {
+        let words_fst = index.words_fst(rtxn)?.map_data(|bytes| Cow::Owned(bytes.into_owned()))?;
+        let words_prefixes_fst = index.words_prefixes_fst(rtxn)?.map_data(|bytes| Cow::Owned(bytes.into_owned()))?;
+
+        Ok(SearchStructures {
+            update_sequence,
+            update_version,
+            words_fst,
+            words_prefixes_fst,
+            frequent_words: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the documents ids of the word, the ones of the frequent words are decoded only once.
+    pub(crate) fn word_docids(&self, index: &Index, rtxn: &RoTxn, word: &str) -> heed::Result<Option<RoaringBitmap>> {
+        if let Some(docids) = self.frequent_words.read().unwrap().get(word) {
+            return Ok(Some(docids.clone()));
+        }
+
+        let docids = index.word_docids.get(rtxn, word)?;
+        if let Some(docids) = &docids {
+            if docids.len() >= FREQUENT_WORD_MIN_DOCUMENTS {
+                let mut frequent_words = self.frequent_words.write().unwrap();
+                if frequent_words.len() < MAX_FREQUENT_WORDS {
+                    frequent_words.insert(word.to_string(), docids.clone());
+                }
+            }
+        }
+
+        Ok(docids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::tests::TempIndex;
+    use crate::update::{IndexDocuments, UpdateFormat};
+    use super::*;
+
+    #[test]
+    fn versioned_structures() {
+        let content = &b"id,name\n1,kevin\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let first = index.search_cache.structures(&index, &rtxn).unwrap();
+        let second = index.search_cache.structures(&index, &rtxn).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(first.words_fst.contains("kevin"));
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n2,benoit\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // The commit increased the update sequence, the cached version is replaced.
+        let rtxn = index.read_txn().unwrap();
+        let third = index.search_cache.structures(&index, &rtxn).unwrap();
+        assert!(!Arc::ptr_eq(&first, &third));
+        assert!(third.words_fst.contains("benoit"));
+        assert!(!first.words_fst.contains("benoit"));
+        drop(rtxn);
+
+        // A write transaction searches its own changes and is aborted.
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n3,bernard\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 2);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        let aborted = index.search_cache.structures(&index, &wtxn).unwrap();
+        assert!(aborted.words_fst.contains("bernard"));
+        wtxn.abort().unwrap();
+
+        // The next commit uses the same update sequence, its structures must not be the aborted ones.
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n4,bertrand\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 2);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let fourth = index.search_cache.structures(&index, &rtxn).unwrap();
+        assert!(!Arc::ptr_eq(&aborted, &fourth));
+        assert!(fourth.words_fst.contains("bertrand"));
+        assert!(!fourth.words_fst.contains("bernard"));
+    }
+}
@@ -0,0 +1,205 @@
+use heed::RoTxn;
+use roaring::RoaringBitmap;
+use serde::Serialize;
+
+use crate::{Criterion, Index, RoaringBitmapLenCodec};
+use super::cache::SearchStructures;
+use super::query_tree::{Operation, Query, QueryKind};
+use super::{word_derivations, WordDerivationsCache};
+
+/// The number of proximities the proximity ranking rule reads for every pair of words.
+const PAIR_PROXIMITIES: u64 = 7;
+
+/// An estimation of the work required to execute a search, computed from the words
+/// dictionaries and the cardinalities of the bitmaps without deserializing them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryCost {
+    /// An upper bound of the number of documents matching the query and the filters.
+    pub candidates: u64,
+    /// The number of words of the index the query words are derived into by the typos and prefixes.
+    pub derived_words: u64,
+    /// An estimation of the number of entries read from the words and pairs databases.
+    pub database_reads: u64,
+}
+
+struct Estimator<'a> {
+    rtxn: &'a RoTxn<'a>,
+    index: &'a Index,
+    structures: &'a SearchStructures,
+    proximity: bool,
+    wdcache: WordDerivationsCache,
+}
+
+struct Estimate {
+    candidates: u64,
+    derived_words: u64,
+    database_reads: u64,
+}
+
+pub(crate) fn estimate_cost(
+    rtxn: &RoTxn,
+    index: &Index,
+    query_tree: Option<&Operation>,
+    facet_candidates: Option<&RoaringBitmap>,
+) -> anyhow::Result<QueryCost>
+{
+    let documents_count = index.number_of_documents(rtxn)?;
+    let max_candidates = facet_candidates.map_or(documents_count, |candidates| candidates.len());
+
+    let query_tree = match query_tree {
+        Some(query_tree) => query_tree,
+        None => return Ok(QueryCost { candidates: max_candidates, ..Default::default() }),
+    };
+
+    let structures = index.search_cache.structures(index, rtxn)?;
+    let proximity = index.criteria(rtxn)?.iter().any(|c| matches!(c, Criterion::Proximity { .. }));
+    let mut estimator = Estimator {
+        rtxn,
+        index,
+        structures: &structures,
+        proximity,
+        wdcache: WordDerivationsCache::new(),
+    };
+
+    let Estimate { candidates, derived_words, database_reads } = estimator.operation(query_tree)?;
+
+    Ok(QueryCost {
+        candidates: candidates.min(max_candidates),
+        derived_words,
+        database_reads,
+    })
+}
+
+impl Estimator<'_> {
+    fn operation(&mut self, operation: &Operation) -> anyhow::Result<Estimate> {
+        match operation {
+            Operation::And(ops) => {
+                let estimates = self.operations(ops)?;
+                let mut estimate = self.combine(&estimates, |a, b| a.min(b));
+                if self.proximity {
+                    estimate.database_reads += self.pairs_reads(ops)? * PAIR_PROXIMITIES;
+                }
+                Ok(estimate)
+            },
+            Operation::Consecutive(ops) => {
+                let estimates = self.operations(ops)?;
+                let mut estimate = self.combine(&estimates, |a, b| a.min(b));
+                estimate.database_reads += self.pairs_reads(ops)?;
+                Ok(estimate)
+            },
+            Operation::Or(_, ops) => {
+                let estimates = self.operations(ops)?;
+                Ok(self.combine(&estimates, |a, b| a.saturating_add(b)))
+            },
+            Operation::Query(query) => {
+                let mut candidates = 0u64;
+                let words = self.query_words(query)?;
+                for (word, is_prefix) in &words {
+                    let count = if *is_prefix {
+                        let db = self.index.word_prefix_docids.remap_data_type::<RoaringBitmapLenCodec>();
+                        db.get(self.rtxn, word)?
+                    } else {
+                        self.index.word_documents_count(self.rtxn, word)?
+                    };
+                    candidates = candidates.saturating_add(count.unwrap_or(0));
+                }
+                let count = words.len() as u64;
+                Ok(Estimate { candidates, derived_words: count, database_reads: count })
+            },
+        }
+    }
+
+    fn operations(&mut self, ops: &[Operation]) -> anyhow::Result<Vec<Estimate>> {
+        ops.iter().map(|op| self.operation(op)).collect()
+    }
+
+    fn combine(&self, estimates: &[Estimate], candidates: impl Fn(u64, u64) -> u64) -> Estimate {
+        let mut iter = estimates.iter();
+        let first = iter.next().map_or(0, |e| e.candidates);
+        Estimate {
+            candidates: iter.fold(first, |acc, e| candidates(acc, e.candidates)),
+            derived_words: estimates.iter().map(|e| e.derived_words).sum(),
+            database_reads: estimates.iter().map(|e| e.database_reads).sum(),
+        }
+    }
+
+    /// The number of pairs of words read for the adjacent operations.
+    fn pairs_reads(&mut self, ops: &[Operation]) -> anyhow::Result<u64> {
+        let mut reads = 0u64;
+        for pair in ops.windows(2) {
+            let left = self.words_count(&pair[0])?;
+            let right = self.words_count(&pair[1])?;
+            reads = reads.saturating_add(left.saturating_mul(right));
+        }
+        Ok(reads)
+    }
+
+    /// The number of words an operation can be replaced by in a pair of words,
+    /// the nested groups of words are resolved by the ranking rules on their own.
+    fn words_count(&mut self, operation: &Operation) -> anyhow::Result<u64> {
+        match operation {
+            Operation::Query(query) => Ok(self.query_words(query)?.len() as u64),
+            Operation::Or(_, ops) => {
+                let mut count = 0;
+                for op in ops {
+                    count += self.words_count(op)?;
+                }
+                Ok(count)
+            },
+            Operation::And(_) | Operation::Consecutive(_) => Ok(0),
+        }
+    }
+
+    /// Returns the words the query is derived into and whether they are cached prefixes.
+    fn query_words(&mut self, query: &Query) -> anyhow::Result<Vec<(String, bool)>> {
+        let structures = self.structures;
+        let words_fst = &structures.words_fst;
+        let words = match &query.kind {
+            QueryKind::Exact { word, .. } if query.prefix && structures.words_prefixes_fst.contains(word) => {
+                vec![(word.clone(), true)]
+            },
+            QueryKind::Exact { word, .. } if !query.prefix => vec![(word.clone(), false)],
+            QueryKind::Exact { word, .. } => {
+                word_derivations(word, true, 0, words_fst, &mut self.wdcache)?
+                    .iter().map(|(word, _)| (word.clone(), false)).collect()
+            },
+            QueryKind::Tolerant { typo, word } => {
+                word_derivations(word, query.prefix, *typo, words_fst, &mut self.wdcache)?
+                    .iter().map(|(word, _)| (word.clone(), false)).collect()
+            },
+        };
+        Ok(words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::tests::TempIndex;
+    use super::*;
+
+    #[test]
+    fn estimate_query_cost() {
+        let content = &b"id,name\n1,kevin\n2,kevina\n3,benoit\n4,kevin benoit\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+
+        let cost = index.search(&rtxn).estimate_cost().unwrap();
+        assert_eq!(cost, QueryCost { candidates: 4, derived_words: 0, database_reads: 0 });
+
+        // The exact word "benoit" appears in two documents.
+        let cost = index.search(&rtxn).query("\"benoit\"").estimate_cost().unwrap();
+        assert_eq!(cost, QueryCost { candidates: 2, derived_words: 1, database_reads: 1 });
+
+        // The "kevin" prefix is derived into "kevin" and "kevina" with and without typos.
+        let cost = index.search(&rtxn).query("kevin").authorize_typos(false).estimate_cost().unwrap();
+        assert!(cost.candidates >= 3);
+        assert!(cost.derived_words >= 2);
+
+        // The candidates can't be more than the documents.
+        let cost = index.search(&rtxn).query("kevin benoit").estimate_cost().unwrap();
+        assert!(cost.candidates <= 4);
+        assert!(cost.database_reads > cost.derived_words);
+    }
+}
@@ -1,7 +1,8 @@
 use std::collections::HashMap;
+use std::iter;
 use std::mem::take;
 
-use anyhow::{bail, Context as _};
+use anyhow::Context as _;
 use heed::{BytesDecode, BytesEncode};
 use itertools::Itertools;
 use log::debug;
@@ -9,15 +10,17 @@ use num_traits::Bounded;
 use ordered_float::OrderedFloat;
 use roaring::RoaringBitmap;
 
-use crate::facet::FacetType;
-use crate::heed_codec::facet::{FacetLevelValueF64Codec, FacetLevelValueI64Codec};
+use crate::criterion::NullsPlacement;
+use crate::facet::{CollationStrength, FacetType, FacetValue};
+use crate::heed_codec::facet::{FacetLevelValueF64Codec, FacetLevelValueI64Codec, FacetValueStringCodec};
 use crate::heed_codec::facet::{FieldDocIdFacetI64Codec, FieldDocIdFacetF64Codec};
 use crate::search::criteria::{resolve_query_tree, CriteriaBuilder};
+use crate::search::distinct::document_facet_values;
 use crate::search::facet::FacetIter;
 use crate::search::query_tree::Operation;
 use crate::search::WordDerivationsCache;
 use crate::{FieldsIdsMap, FieldId, Index};
-use super::{Criterion, CriterionResult};
+use super::{ranks_with, Criterion, CriterionResult, Rank, ScoreDetail};
 
 pub struct AscDesc<'t> {
     index: &'t Index,
@@ -25,11 +28,14 @@ pub struct AscDesc<'t> {
     field_name: String,
     field_id: FieldId,
     facet_type: FacetType,
+    collation: CollationStrength,
     ascending: bool,
+    nulls: NullsPlacement,
     query_tree: Option<Operation>,
     candidates: Box<dyn Iterator<Item = heed::Result<RoaringBitmap>> + 't>,
     bucket_candidates: RoaringBitmap,
     faceted_candidates: RoaringBitmap,
+    ranks: Vec<Rank>,
     parent: Option<Box<dyn Criterion + 't>>,
 }
 
@@ -40,9 +46,10 @@ impl<'t> AscDesc<'t> {
         query_tree: Option<Operation>,
         candidates: Option<RoaringBitmap>,
         field_name: String,
+        nulls: NullsPlacement,
     ) -> anyhow::Result<Self>
     {
-        Self::initial(index, rtxn, query_tree, candidates, field_name, true)
+        Self::initial(index, rtxn, query_tree, candidates, field_name, true, nulls)
     }
 
     pub fn initial_desc(
@@ -51,9 +58,10 @@ impl<'t> AscDesc<'t> {
         query_tree: Option<Operation>,
         candidates: Option<RoaringBitmap>,
         field_name: String,
+        nulls: NullsPlacement,
     ) -> anyhow::Result<Self>
     {
-        Self::initial(index, rtxn, query_tree, candidates, field_name, false)
+        Self::initial(index, rtxn, query_tree, candidates, field_name, false, nulls)
     }
 
     pub fn asc(
@@ -61,9 +69,10 @@ impl<'t> AscDesc<'t> {
         rtxn: &'t heed::RoTxn,
         parent: Box<dyn Criterion + 't>,
         field_name: String,
+        nulls: NullsPlacement,
     ) -> anyhow::Result<Self>
     {
-        Self::new(index, rtxn, parent, field_name, true)
+        Self::new(index, rtxn, parent, field_name, true, nulls)
     }
 
     pub fn desc(
@@ -71,9 +80,10 @@ impl<'t> AscDesc<'t> {
         rtxn: &'t heed::RoTxn,
         parent: Box<dyn Criterion + 't>,
         field_name: String,
+        nulls: NullsPlacement,
     ) -> anyhow::Result<Self>
     {
-        Self::new(index, rtxn, parent, field_name, false)
+        Self::new(index, rtxn, parent, field_name, false, nulls)
     }
 
     fn initial(
@@ -83,11 +93,13 @@ impl<'t> AscDesc<'t> {
         candidates: Option<RoaringBitmap>,
         field_name: String,
         ascending: bool,
+        nulls: NullsPlacement,
     ) -> anyhow::Result<Self>
     {
         let fields_ids_map = index.fields_ids_map(rtxn)?;
         let faceted_fields = index.faceted_fields(rtxn)?;
         let (field_id, facet_type) = field_id_facet_type(&fields_ids_map, &faceted_fields, &field_name)?;
+        let collation = index.collation_strength(rtxn)?;
 
         let faceted_candidates = index.faceted_documents_ids(rtxn, field_id)?;
         let candidates = match &query_tree {
@@ -99,20 +111,38 @@ impl<'t> AscDesc<'t> {
                 }
                 qt_candidates
             },
-            None => candidates.unwrap_or(faceted_candidates.clone()),
+            None => match candidates {
+                Some(candidates) => candidates,
+                None => index.documents_ids(rtxn)?,
+            },
         };
 
+        let candidates = facet_ordered_with_nulls(
+            index,
+            rtxn,
+            field_id,
+            facet_type,
+            collation,
+            ascending,
+            nulls,
+            candidates,
+            &faceted_candidates,
+        )?;
+
         Ok(AscDesc {
             index,
             rtxn,
             field_name,
             field_id,
             facet_type,
+            collation,
             ascending,
+            nulls,
             query_tree,
-            candidates: facet_ordered(index, rtxn, field_id, facet_type, ascending, candidates)?,
+            candidates,
             faceted_candidates,
             bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
             parent: None,
         })
     }
@@ -123,11 +153,13 @@ impl<'t> AscDesc<'t> {
         parent: Box<dyn Criterion + 't>,
         field_name: String,
         ascending: bool,
+        nulls: NullsPlacement,
     ) -> anyhow::Result<Self>
     {
         let fields_ids_map = index.fields_ids_map(rtxn)?;
         let faceted_fields = index.faceted_fields(rtxn)?;
         let (field_id, facet_type) = field_id_facet_type(&fields_ids_map, &faceted_fields, &field_name)?;
+        let collation = index.collation_strength(rtxn)?;
 
         Ok(AscDesc {
             index,
@@ -135,16 +167,38 @@ impl<'t> AscDesc<'t> {
             field_name,
             field_id,
             facet_type,
+            collation,
             ascending,
+            nulls,
             query_tree: None,
             candidates: Box::new(std::iter::empty()),
             faceted_candidates: index.faceted_documents_ids(rtxn, field_id)?,
             bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
             parent: Some(parent),
         })
     }
 }
 
+impl<'t> AscDesc<'t> {
+    /// Returns the value the documents of the bucket are sorted by, the one of its
+    /// first document, `None` for the bucket of the documents without a value.
+    fn bucket_value(&self, candidates: &RoaringBitmap) -> heed::Result<Option<FacetValue>> {
+        let docid = match candidates.min() {
+            Some(docid) => docid,
+            None => return Ok(None),
+        };
+
+        // The documents are sorted by their smallest value in ascending order, their biggest otherwise.
+        let values = document_facet_values(self.index, self.rtxn, self.field_id, self.facet_type, docid)?;
+        if self.ascending {
+            Ok(values.into_iter().next())
+        } else {
+            Ok(values.into_iter().last())
+        }
+    }
+}
+
 impl<'t> Criterion for AscDesc<'t> {
     #[logging_timer::time("AscDesc::{}")]
     fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
@@ -157,36 +211,36 @@ impl<'t> Criterion for AscDesc<'t> {
                 None => {
                     let query_tree = self.query_tree.take();
                     let bucket_candidates = take(&mut self.bucket_candidates);
+                    let ranks = ranks_with(&self.ranks, Rank::default());
                     match self.parent.as_mut() {
                         Some(parent) => {
                             match parent.next(wdcache)? {
-                                Some(CriterionResult { query_tree, candidates, bucket_candidates }) => {
+                                Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
                                     self.query_tree = query_tree;
+                                    self.ranks = ranks;
                                     let candidates = match (&self.query_tree, candidates) {
-                                        (_, Some(mut candidates)) => {
-                                            candidates.intersect_with(&self.faceted_candidates);
-                                            candidates
-                                        },
+                                        (_, Some(candidates)) => candidates,
                                         (Some(qt), None) => {
                                             let context = CriteriaBuilder::new(&self.rtxn, &self.index)?;
-                                            let mut candidates = resolve_query_tree(&context, qt, &mut HashMap::new(), wdcache)?;
-                                            candidates.intersect_with(&self.faceted_candidates);
-                                            candidates
+                                            resolve_query_tree(&context, qt, &mut HashMap::new(), wdcache)?
                                         },
-                                        (None, None) => take(&mut self.faceted_candidates),
+                                        (None, None) => self.index.documents_ids(self.rtxn)?,
                                     };
                                     if bucket_candidates.is_empty() {
                                         self.bucket_candidates.union_with(&candidates);
                                     } else {
                                         self.bucket_candidates.union_with(&bucket_candidates);
                                     }
-                                    self.candidates = facet_ordered(
+                                    self.candidates = facet_ordered_with_nulls(
                                         self.index,
                                         self.rtxn,
                                         self.field_id,
                                         self.facet_type,
+                                        self.collation,
                                         self.ascending,
+                                        self.nulls,
                                         candidates,
+                                        &self.faceted_candidates,
                                     )?;
                                 },
                                 None => return Ok(None),
@@ -201,6 +255,7 @@ impl<'t> Criterion for AscDesc<'t> {
                         query_tree,
                         candidates: Some(RoaringBitmap::new()),
                         bucket_candidates,
+                        ranks,
                     }));
                 },
                 Some(candidates) => {
@@ -209,10 +264,19 @@ impl<'t> Criterion for AscDesc<'t> {
                         None => candidates.clone(),
                     };
 
+                    // The documents are sorted by value, not by relevancy.
+                    let detail = ScoreDetail::Sort {
+                        field: self.field_name.clone(),
+                        ascending: self.ascending,
+                        nulls: self.nulls,
+                        value: self.bucket_value(&candidates)?,
+                    };
+
                     return Ok(Some(CriterionResult {
                         query_tree: self.query_tree.clone(),
                         candidates: Some(candidates),
                         bucket_candidates,
+                        ranks: ranks_with(&self.ranks, Rank::unranked(detail)),
                     }));
                 },
             }
@@ -235,15 +299,45 @@ fn field_id_facet_type(
     Ok((id, *facet_type))
 }
 
+/// Returns an iterator over groups of the given candidates in ascending or descending order,
+/// the candidates that doesn't have a value for this field are returned first or last.
+fn facet_ordered_with_nulls<'t>(
+    index: &'t Index,
+    rtxn: &'t heed::RoTxn,
+    field_id: FieldId,
+    facet_type: FacetType,
+    collation: CollationStrength,
+    ascending: bool,
+    nulls: NullsPlacement,
+    mut candidates: RoaringBitmap,
+    faceted_candidates: &RoaringBitmap,
+) -> anyhow::Result<Box<dyn Iterator<Item = heed::Result<RoaringBitmap>> + 't>>
+{
+    let missing = &candidates - faceted_candidates;
+    candidates.intersect_with(faceted_candidates);
+
+    let ordered = facet_ordered(index, rtxn, field_id, facet_type, collation, ascending, candidates)?;
+    if missing.is_empty() {
+        return Ok(ordered);
+    }
+
+    let missing = iter::once(Ok(missing));
+    match nulls {
+        NullsPlacement::First => Ok(Box::new(missing.chain(ordered))),
+        NullsPlacement::Last => Ok(Box::new(ordered.chain(missing))),
+    }
+}
+
 /// Returns an iterator over groups of the given candidates in ascending or descending order.
 ///
 /// It will either use an iterative or a recusrsive method on the whole facet database depending
-/// on the number of candidates to rank.
+/// on the number of candidates to rank. String facet values are compared by using the collation.
 fn facet_ordered<'t>(
     index: &'t Index,
     rtxn: &'t heed::RoTxn,
     field_id: FieldId,
     facet_type: FacetType,
+    collation: CollationStrength,
     ascending: bool,
     candidates: RoaringBitmap,
 ) -> anyhow::Result<Box<dyn Iterator<Item = heed::Result<RoaringBitmap>> + 't>>
@@ -281,10 +375,55 @@ fn facet_ordered<'t>(
                 Ok(Box::new(iter.map(|res| res.map(|(_, docids)| docids))))
             }
         },
-        FacetType::String => bail!("criteria facet type must be a number"),
+        FacetType::String => {
+            let iter = string_facet_ordered_iter(index, rtxn, field_id, collation, ascending, candidates)?;
+            Ok(Box::new(iter.map(Ok)) as Box<dyn Iterator<Item = _>>)
+        },
     }
 }
 
+/// Fetch the whole list of string facet values of the field, order them by using the
+/// collation and return the groups of candidates that are considered to be equal.
+fn string_facet_ordered_iter<'t>(
+    index: &'t Index,
+    rtxn: &'t heed::RoTxn,
+    field_id: FieldId,
+    collation: CollationStrength,
+    ascending: bool,
+    candidates: RoaringBitmap,
+) -> anyhow::Result<impl Iterator<Item = RoaringBitmap> + 't>
+{
+    let iter = index.facet_field_id_value_docids
+        .prefix_iter(rtxn, &[field_id])?
+        .remap_key_type::<FacetValueStringCodec>();
+
+    let mut keys_docids = Vec::new();
+    for result in iter {
+        let ((_, value), mut docids) = result?;
+        docids.intersect_with(&candidates);
+        if !docids.is_empty() {
+            keys_docids.push((collation.collation_key(value), docids));
+        }
+    }
+
+    // The sort is stable, the values that are equal for
+    // this collation are still ordered by their raw bytes.
+    keys_docids.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let iter = keys_docids.into_iter();
+    let iter = if ascending {
+        Box::new(iter) as Box<dyn Iterator<Item = _>>
+    } else {
+        Box::new(iter.rev())
+    };
+
+    let vec: Vec<RoaringBitmap> = iter.group_by(|(key, _)| key.clone())
+        .into_iter()
+        .map(|(_, group)| group.fold(RoaringBitmap::new(), |acc, (_, docids)| acc | docids))
+        .collect();
+
+    Ok(vec.into_iter())
+}
+
 /// Fetch the whole list of candidates facet values one by one and order them by it.
 ///
 /// This function is fast when the amount of candidates to rank is small.
@@ -0,0 +1,331 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::mem::take;
+
+use log::debug;
+use roaring::RoaringBitmap;
+
+use crate::criterion::DEFAULT_ATTRIBUTE_WEIGHT;
+use crate::search::query_tree::Operation;
+use crate::search::WordDerivationsCache;
+use crate::Index;
+use super::{is_skipped, query_derived_words, ranks_with, resolve_query_tree};
+use super::{Criterion, CriterionResult, Context, Rank, ScoreDetail};
+
+/// Returns the documents in which the query words appear in the attributes
+/// with the biggest weights first, see `Index::attributes_weights`.
+///
+/// The attributes of the same weight are equally important, the criterion returns
+/// a single bucket when all the attributes have the same weight.
+pub struct Attribute<'t> {
+    ctx: &'t dyn Context,
+    weights: &'t AttributesWeights,
+    query_tree: Option<Operation>,
+    buckets: std::vec::IntoIter<(Rank, RoaringBitmap)>,
+    bucket_candidates: RoaringBitmap,
+    ranks: Vec<Rank>,
+    parent: Option<Box<dyn Criterion + 't>>,
+    documents_to_skip: Option<&'t Cell<u64>>,
+}
+
+impl<'t> Attribute<'t> {
+    pub fn initial(
+        ctx: &'t dyn Context,
+        query_tree: Option<Operation>,
+        candidates: Option<RoaringBitmap>,
+        weights: &'t AttributesWeights,
+    ) -> anyhow::Result<Self>
+    {
+        let mut wdcache = WordDerivationsCache::new();
+        let candidates = match (&query_tree, candidates) {
+            (Some(qt), candidates) => {
+                let mut qt_candidates = resolve_query_tree(ctx, qt, &mut HashMap::new(), &mut wdcache)?;
+                if let Some(candidates) = candidates {
+                    qt_candidates.intersect_with(&candidates);
+                }
+                qt_candidates
+            },
+            (None, Some(candidates)) => candidates,
+            (None, None) => ctx.documents_ids()?,
+        };
+
+        let buckets = attribute_buckets(ctx, weights, query_tree.as_ref(), &candidates, &mut wdcache)?;
+
+        Ok(Attribute {
+            ctx,
+            weights,
+            query_tree,
+            buckets: buckets.into_iter(),
+            bucket_candidates: candidates,
+            ranks: Vec::new(),
+            parent: None,
+            documents_to_skip: None,
+        })
+    }
+
+    pub fn new(ctx: &'t dyn Context, parent: Box<dyn Criterion + 't>, weights: &'t AttributesWeights) -> Self {
+        Attribute {
+            ctx,
+            weights,
+            query_tree: None,
+            buckets: Vec::new().into_iter(),
+            bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
+            parent: Some(parent),
+            documents_to_skip: None,
+        }
+    }
+
+    /// The number of documents that will be skipped by the offset of the search,
+    /// the buckets received from the parent that only contain such documents aren't split.
+    pub fn documents_to_skip(mut self, documents: &'t Cell<u64>) -> Self {
+        self.documents_to_skip = Some(documents);
+        self
+    }
+}
+
+impl<'t> Criterion for Attribute<'t> {
+    #[logging_timer::time("Attribute::{}")]
+    fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+        debug!("Attribute iteration ({} buckets left)", self.buckets.len());
+
+        if let Some((rank, candidates)) = self.buckets.next() {
+            return Ok(Some(CriterionResult {
+                query_tree: self.query_tree.clone(),
+                candidates: Some(candidates),
+                bucket_candidates: take(&mut self.bucket_candidates),
+                ranks: ranks_with(&self.ranks, rank),
+            }));
+        }
+
+        let parent = match self.parent.as_mut() {
+            Some(parent) => parent,
+            None => return Ok(None),
+        };
+
+        match parent.next(wdcache)? {
+            Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
+                let candidates = match (&query_tree, candidates) {
+                    (_, Some(candidates)) => candidates,
+                    (Some(qt), None) => resolve_query_tree(self.ctx, qt, &mut HashMap::new(), wdcache)?,
+                    (None, None) => self.ctx.documents_ids()?,
+                };
+
+                if bucket_candidates.is_empty() {
+                    self.bucket_candidates.union_with(&candidates);
+                } else {
+                    self.bucket_candidates.union_with(&bucket_candidates);
+                }
+
+                self.query_tree = query_tree;
+                self.ranks = ranks;
+                // Neither the skipped buckets nor the ones given once the time budget is spent are split.
+                let mut buckets = if is_skipped(self.documents_to_skip, &candidates) || self.ctx.deadline_reached() {
+                    vec![(Rank::default(), candidates)].into_iter()
+                } else {
+                    attribute_buckets(
+                        self.ctx,
+                        self.weights,
+                        self.query_tree.as_ref(),
+                        &candidates,
+                        wdcache,
+                    )?.into_iter()
+                };
+                let (rank, candidates) = buckets.next().unwrap_or_default();
+                self.buckets = buckets;
+
+                Ok(Some(CriterionResult {
+                    query_tree: self.query_tree.clone(),
+                    candidates: Some(candidates),
+                    bucket_candidates: take(&mut self.bucket_candidates),
+                    ranks: ranks_with(&self.ranks, rank),
+                }))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// The weights of the attributes of the index, by attribute id, the attributes
+/// that don't declare a weight have a weight of `DEFAULT_ATTRIBUTE_WEIGHT`.
+#[derive(Debug, Clone)]
+pub struct AttributesWeights {
+    weights: HashMap<u32, u8>,
+    /// The different weights an attribute can have, from the biggest to the smallest.
+    distinct: Vec<u8>,
+    /// The ids of all the attributes of the index.
+    attributes: Vec<u32>,
+}
+
+impl AttributesWeights {
+    pub fn new(weights: HashMap<u32, u8>, attributes: Vec<u32>) -> AttributesWeights {
+        let mut distinct: Vec<_> = weights.values().copied().chain(Some(DEFAULT_ATTRIBUTE_WEIGHT)).collect();
+        distinct.sort_unstable_by(|a, b| b.cmp(a));
+        distinct.dedup();
+        let mut attributes: Vec<_> = attributes.into_iter().chain(weights.keys().copied()).collect();
+        attributes.sort_unstable();
+        attributes.dedup();
+        AttributesWeights { weights, distinct, attributes }
+    }
+
+    /// Reads the weights of the attributes of the index.
+    pub fn from_index(index: &Index, rtxn: &heed::RoTxn) -> anyhow::Result<AttributesWeights> {
+        let fields_ids_map = index.fields_ids_map(rtxn)?;
+        let weights = index.attributes_weights(rtxn)?.into_iter()
+            .filter_map(|(name, weight)| fields_ids_map.id(&name).map(|id| (id as u32, weight)))
+            .collect();
+        let attributes = fields_ids_map.iter().map(|(id, _)| id as u32).collect();
+        Ok(AttributesWeights::new(weights, attributes))
+    }
+
+    /// Returns the weight of an attribute, as it is encoded in the positions.
+    pub fn weight(&self, attribute: u32) -> u8 {
+        self.weights.get(&attribute).copied().unwrap_or(DEFAULT_ATTRIBUTE_WEIGHT)
+    }
+
+    /// Returns the attributes that have this weight.
+    pub fn attributes_with_weight(&self, weight: u8) -> impl Iterator<Item = u32> + '_ {
+        self.attributes.iter().copied().filter(move |attribute| self.weight(*attribute) == weight)
+    }
+
+    /// Returns the rank of a weight, `0` for the biggest weight.
+    pub fn rank(&self, weight: u8) -> usize {
+        self.distinct.iter().position(|w| *w == weight).unwrap_or_else(|| self.max_rank())
+    }
+
+    /// Returns the rank of the smallest weight.
+    pub fn max_rank(&self) -> usize {
+        self.distinct.len() - 1
+    }
+
+    /// Returns `true` if all the attributes have the same weight.
+    pub fn is_uniform(&self) -> bool {
+        self.distinct.len() == 1
+    }
+}
+
+impl Default for AttributesWeights {
+    fn default() -> AttributesWeights {
+        AttributesWeights::new(HashMap::new(), Vec::new())
+    }
+}
+
+/// Splits the candidates into the non-empty buckets of documents in which the best attribute
+/// containing a query word has the same weight, in the order they must be returned, with their ranks.
+///
+/// The documents of the last bucket are also the ones in which no attribute contains a query word.
+fn attribute_buckets(
+    ctx: &dyn Context,
+    weights: &AttributesWeights,
+    query_tree: Option<&Operation>,
+    candidates: &RoaringBitmap,
+    wdcache: &mut WordDerivationsCache,
+) -> anyhow::Result<Vec<(Rank, RoaringBitmap)>>
+{
+    let query_tree = match query_tree {
+        Some(query_tree) => query_tree,
+        None => return Ok(vec![(Rank::default(), candidates.clone())]),
+    };
+
+    // There is no need to read the positions when every attribute has the same weight.
+    if weights.is_uniform() {
+        let detail = ScoreDetail::Attribute { weight: weights.distinct[0] };
+        return Ok(vec![(Rank::new(0, 0, detail), candidates.clone())]);
+    }
+
+    let mut words = HashSet::new();
+    query_tree_words(ctx, query_tree, &mut words, wdcache)?;
+
+    // The documents containing a query word in the attributes of each weight, from the biggest
+    // weight, the attributes of the smallest weight aren't read, the remaining candidates are theirs.
+    let max_rank = weights.max_rank();
+    let mut candidates = candidates.clone();
+    let mut buckets = Vec::with_capacity(max_rank + 1);
+    for &weight in &weights.distinct[..max_rank] {
+        let mut bucket = RoaringBitmap::new();
+        for attribute in weights.attributes_with_weight(weight) {
+            for word in &words {
+                if let Some(docids) = ctx.word_attribute_docids(word, attribute)? {
+                    bucket.union_with(&docids);
+                }
+            }
+        }
+
+        bucket.intersect_with(&candidates);
+        candidates.difference_with(&bucket);
+        buckets.push(bucket);
+    }
+    buckets.push(candidates);
+
+    let buckets = buckets.into_iter().enumerate()
+        .filter(|(_, bucket)| !bucket.is_empty())
+        .map(|(rank, bucket)| {
+            let detail = ScoreDetail::Attribute { weight: weights.distinct[rank] };
+            (Rank::new(rank, max_rank, detail), bucket)
+        })
+        .collect();
+
+    Ok(buckets)
+}
+
+/// Collects the words of the index that the queries of the query tree can match.
+fn query_tree_words(
+    ctx: &dyn Context,
+    query_tree: &Operation,
+    words: &mut HashSet<String>,
+    wdcache: &mut WordDerivationsCache,
+) -> anyhow::Result<()>
+{
+    use Operation::{And, Consecutive, Or, Query};
+
+    match query_tree {
+        And(ops) | Consecutive(ops) | Or(_, ops) => {
+            for op in ops {
+                query_tree_words(ctx, op, words, wdcache)?;
+            }
+        },
+        Query(query) => words.extend(query_derived_words(ctx, query, wdcache)?),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use maplit::hashmap;
+
+    use crate::index::tests::TempIndex;
+    use super::*;
+
+    #[test]
+    fn weights_ranks() {
+        let weights = AttributesWeights::new(hashmap!{ 0 => 3, 1 => 3, 4 => 0 }, vec![0, 1, 2, 3, 4]);
+        assert_eq!(weights.weight(0), 3);
+        assert_eq!(weights.weight(2), DEFAULT_ATTRIBUTE_WEIGHT);
+        assert_eq!(weights.rank(3), 0);
+        assert_eq!(weights.rank(DEFAULT_ATTRIBUTE_WEIGHT), 1);
+        assert_eq!(weights.rank(0), 2);
+        assert_eq!(weights.max_rank(), 2);
+        assert_eq!(weights.attributes_with_weight(DEFAULT_ATTRIBUTE_WEIGHT).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(!weights.is_uniform());
+        assert!(AttributesWeights::default().is_uniform());
+    }
+
+    #[test]
+    fn heavier_attributes_first() {
+        let content = &b"id,body,title\n0,hello world,nothing\n1,nothing,hello world\n2,hello,world\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_criteria(vec!["words".into(), "attribute".into()]);
+            builder.set_attributes_weights(hashmap!{ "title".into() => 3, "body".into() => 1 });
+        });
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("hello world").score_details(true).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 2, 0]);
+
+        let details = &result.documents_score_details;
+        assert_eq!(details[0][1].detail, ScoreDetail::Attribute { weight: 3 });
+        assert_eq!(details[1][1].detail, ScoreDetail::Attribute { weight: 3 });
+        assert_eq!(details[2][1].detail, ScoreDetail::Attribute { weight: 1 });
+    }
+}
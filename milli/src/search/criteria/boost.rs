@@ -0,0 +1,114 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem::take;
+
+use log::debug;
+use roaring::RoaringBitmap;
+
+use crate::search::query_tree::Operation;
+use crate::search::WordDerivationsCache;
+use super::{resolve_query_tree, Criterion, CriterionResult, Context, Rank, ScoreDetail};
+
+/// Returns the documents that match the boosts with the biggest total factor first, see
+/// `Search::boost`, it is always the first stage and the ranking rules refine its buckets.
+pub struct Boost {
+    query_tree: Option<Operation>,
+    buckets: std::vec::IntoIter<(Rank, RoaringBitmap)>,
+    bucket_candidates: RoaringBitmap,
+}
+
+impl Boost {
+    pub fn initial(
+        ctx: &dyn Context,
+        query_tree: Option<Operation>,
+        candidates: Option<RoaringBitmap>,
+        boosts: &[(RoaringBitmap, u32)],
+    ) -> anyhow::Result<Self>
+    {
+        let candidates = match (&query_tree, candidates) {
+            (Some(qt), candidates) => {
+                let mut qt_candidates = resolve_query_tree(ctx, qt, &mut HashMap::new(), &mut WordDerivationsCache::new())?;
+                if let Some(candidates) = candidates {
+                    qt_candidates.intersect_with(&candidates);
+                }
+                qt_candidates
+            },
+            (None, Some(candidates)) => candidates,
+            (None, None) => ctx.documents_ids()?,
+        };
+
+        Ok(Boost {
+            query_tree,
+            buckets: boost_buckets(boosts, &candidates).into_iter(),
+            bucket_candidates: candidates,
+        })
+    }
+}
+
+impl Criterion for Boost {
+    #[logging_timer::time("Boost::{}")]
+    fn next(&mut self, _wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+        debug!("Boost iteration ({} buckets left)", self.buckets.len());
+
+        match self.buckets.next() {
+            Some((rank, candidates)) => Ok(Some(CriterionResult {
+                query_tree: self.query_tree.clone(),
+                candidates: Some(candidates),
+                bucket_candidates: take(&mut self.bucket_candidates),
+                ranks: vec![rank],
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Splits the candidates into the non-empty buckets of documents that match boosts with
+/// the same total factor, from the biggest total to the smallest one, along with their ranks.
+fn boost_buckets(boosts: &[(RoaringBitmap, u32)], candidates: &RoaringBitmap) -> Vec<(Rank, RoaringBitmap)> {
+    let mut totals = BTreeMap::new();
+    totals.insert(0, candidates.clone());
+
+    for (docids, factor) in boosts.iter().filter(|(_, factor)| *factor != 0) {
+        let mut boosted = BTreeMap::new();
+        for (total, mut candidates) in totals {
+            let matching = &candidates & docids;
+            candidates.difference_with(&matching);
+            if !matching.is_empty() {
+                let total = u32::saturating_add(total, *factor);
+                boosted.entry(total).or_insert_with(RoaringBitmap::new).union_with(&matching);
+            }
+            if !candidates.is_empty() {
+                boosted.entry(total).or_insert_with(RoaringBitmap::new).union_with(&candidates);
+            }
+        }
+        totals = boosted;
+    }
+
+    let max_rank = totals.len().saturating_sub(1);
+    totals.into_iter().rev().enumerate()
+        .map(|(rank, (factor, docids))| (Rank::new(rank, max_rank, ScoreDetail::Boost { factor }), docids))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn boosts_totals() {
+        let candidates: RoaringBitmap = (0..6).collect();
+        let promoted: RoaringBitmap = (0..2).chain(4..5).collect();
+        let in_stock: RoaringBitmap = (1..4).collect();
+        let boosts = vec![(promoted, 2), (in_stock, 1), ((0..6).collect(), 0)];
+
+        let buckets: Vec<_> = boost_buckets(&boosts, &candidates).into_iter()
+            .map(|(rank, docids)| (rank.rank, rank.detail, docids.iter().collect::<Vec<_>>()))
+            .collect();
+
+        assert_eq!(buckets, vec![
+            (0, ScoreDetail::Boost { factor: 3 }, vec![1]),
+            (1, ScoreDetail::Boost { factor: 2 }, vec![0, 4]),
+            (2, ScoreDetail::Boost { factor: 1 }, vec![2, 3]),
+            (3, ScoreDetail::Boost { factor: 0 }, vec![5]),
+        ]);
+    }
+}
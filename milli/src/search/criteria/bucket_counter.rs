@@ -0,0 +1,41 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::search::WordDerivationsCache;
+use super::{resolve_query_tree, Criterion, CriterionResult, Context};
+
+/// Records the number of candidates of every bucket returned by the wrapped criterion,
+/// the counts are shared with the caller that can read them once the criteria are exhausted.
+///
+/// The empty buckets some criteria return when they move to the next bucket of their parent are ignored.
+pub struct BucketCounter<'t> {
+    ctx: &'t dyn Context,
+    inner: Box<dyn Criterion + 't>,
+    counts: Rc<RefCell<Vec<u64>>>,
+}
+
+impl<'t> BucketCounter<'t> {
+    pub fn new(ctx: &'t dyn Context, inner: Box<dyn Criterion + 't>, counts: Rc<RefCell<Vec<u64>>>) -> Self {
+        BucketCounter { ctx, inner, counts }
+    }
+}
+
+impl<'t> Criterion for BucketCounter<'t> {
+    fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+        let result = self.inner.next(wdcache)?;
+
+        if let Some(CriterionResult { query_tree, candidates, .. }) = &result {
+            let count = match (query_tree, candidates) {
+                (_, Some(candidates)) => candidates.len(),
+                (Some(qt), None) => resolve_query_tree(self.ctx, qt, &mut HashMap::new(), wdcache)?.len(),
+                (None, None) => self.ctx.documents_ids()?.len(),
+            };
+            if count != 0 {
+                self.counts.borrow_mut().push(count);
+            }
+        }
+
+        Ok(result)
+    }
+}
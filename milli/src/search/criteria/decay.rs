@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::mem::take;
+
+use anyhow::{bail, Context as _};
+use log::debug;
+use roaring::RoaringBitmap;
+
+use crate::facet::FacetType;
+use crate::search::facet::{FacetCondition, FacetNumberOperator};
+use crate::search::query_tree::Operation;
+use crate::search::WordDerivationsCache;
+use crate::Index;
+use super::{ranks_with, resolve_query_tree, Criterion, CriterionResult, Context, Rank, ScoreDetail};
+
+/// The number of buckets of the documents with a value, the documents that are
+/// further than `DECAY_BUCKETS - 1` scales from the origin all share the last one.
+const DECAY_BUCKETS: usize = 16;
+
+/// Returns the documents with a value of the numeric field close to the origin first, e.g.
+/// the most recent documents when the field is a timestamp and the origin is now.
+///
+/// A document of the bucket `n` has a value at a distance between `n` and `n + 1` scales
+/// of the origin, the documents without a value are returned last. The buckets are computed
+/// with the facet levels of the field, the values of the documents are never read one by one.
+pub struct Decay<'t> {
+    ctx: &'t dyn Context,
+    field: String,
+    /// The documents with a value at a distance of at most `n + 1` scales of the origin,
+    /// for every bucket `n` except the last one.
+    rings: Vec<RoaringBitmap>,
+    faceted_candidates: RoaringBitmap,
+    query_tree: Option<Operation>,
+    buckets: std::vec::IntoIter<(Rank, RoaringBitmap)>,
+    bucket_candidates: RoaringBitmap,
+    ranks: Vec<Rank>,
+    parent: Option<Box<dyn Criterion + 't>>,
+}
+
+impl<'t> Decay<'t> {
+    pub fn initial(
+        ctx: &'t dyn Context,
+        index: &Index,
+        rtxn: &heed::RoTxn,
+        query_tree: Option<Operation>,
+        candidates: Option<RoaringBitmap>,
+        field: String,
+        origin: f64,
+        scale: f64,
+    ) -> anyhow::Result<Self>
+    {
+        let candidates = match (&query_tree, candidates) {
+            (Some(qt), candidates) => {
+                let mut qt_candidates = resolve_query_tree(ctx, qt, &mut HashMap::new(), &mut WordDerivationsCache::new())?;
+                if let Some(candidates) = candidates {
+                    qt_candidates.intersect_with(&candidates);
+                }
+                qt_candidates
+            },
+            (None, Some(candidates)) => candidates,
+            (None, None) => ctx.documents_ids()?,
+        };
+
+        let mut decay = Decay::create(ctx, index, rtxn, None, field, origin, scale)?;
+        decay.buckets = decay.decay_buckets(&candidates).into_iter();
+        decay.query_tree = query_tree;
+        decay.bucket_candidates = candidates;
+        Ok(decay)
+    }
+
+    pub fn new(
+        ctx: &'t dyn Context,
+        index: &Index,
+        rtxn: &heed::RoTxn,
+        parent: Box<dyn Criterion + 't>,
+        field: String,
+        origin: f64,
+        scale: f64,
+    ) -> anyhow::Result<Self>
+    {
+        Decay::create(ctx, index, rtxn, Some(parent), field, origin, scale)
+    }
+
+    fn create(
+        ctx: &'t dyn Context,
+        index: &Index,
+        rtxn: &heed::RoTxn,
+        parent: Option<Box<dyn Criterion + 't>>,
+        field: String,
+        origin: f64,
+        scale: f64,
+    ) -> anyhow::Result<Self>
+    {
+        let faceted_fields = index.faceted_fields(rtxn)?;
+        let facet_type = *faceted_fields.get(&field).with_context(|| {
+            format!("Can't use {:?} as a decay criterion as it isn't a faceted field.", field)
+        })?;
+        let fields_ids_map = index.fields_ids_map(rtxn)?;
+        let field_id = fields_ids_map.id(&field).with_context(|| {
+            format!("field {:?} isn't registered", field)
+        })?;
+
+        let mut rings = Vec::with_capacity(DECAY_BUCKETS - 1);
+        for n in 1..DECAY_BUCKETS {
+            let distance = scale * n as f64;
+            let (left, right) = (origin - distance, origin + distance);
+            let condition = match facet_type {
+                FacetType::Integer => {
+                    let between = FacetNumberOperator::Between(left.ceil() as i64, right.floor() as i64);
+                    FacetCondition::OperatorI64(field_id, between)
+                },
+                FacetType::Float => FacetCondition::OperatorF64(field_id, FacetNumberOperator::Between(left, right)),
+                FacetType::String => bail!("Can't use {:?} as a decay criterion as it isn't a numeric field.", field),
+            };
+            rings.push(condition.evaluate(rtxn, index)?);
+        }
+
+        Ok(Decay {
+            ctx,
+            field,
+            rings,
+            faceted_candidates: index.faceted_documents_ids(rtxn, field_id)?,
+            query_tree: None,
+            buckets: Vec::new().into_iter(),
+            bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
+            parent,
+        })
+    }
+
+    /// Splits the candidates into the non-empty buckets of documents with a value at the
+    /// same distance of the origin, in the order they must be returned, with their ranks.
+    fn decay_buckets(&self, candidates: &RoaringBitmap) -> Vec<(Rank, RoaringBitmap)> {
+        let max_rank = DECAY_BUCKETS;
+        let detail = |bucket: usize| ScoreDetail::Decay { field: self.field.clone(), bucket: bucket as u32 };
+
+        let mut buckets = Vec::new();
+        let mut remaining = candidates & &self.faceted_candidates;
+        let missing = candidates - &remaining;
+        for (bucket, ring) in self.rings.iter().enumerate() {
+            if remaining.is_empty() { break }
+
+            let bucket_docids = &remaining & ring;
+            if !bucket_docids.is_empty() {
+                remaining.difference_with(&bucket_docids);
+                buckets.push((Rank::new(bucket, max_rank, detail(bucket)), bucket_docids));
+            }
+        }
+
+        // The documents that are far from the origin and then the ones without a value.
+        let last = DECAY_BUCKETS - 1;
+        for (rank, docids) in vec![(last, remaining), (max_rank, missing)] {
+            if !docids.is_empty() {
+                buckets.push((Rank::new(rank, max_rank, detail(rank)), docids));
+            }
+        }
+
+        buckets
+    }
+}
+
+impl<'t> Criterion for Decay<'t> {
+    #[logging_timer::time("Decay::{}")]
+    fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+        debug!("Decay {} iteration ({} buckets left)", self.field, self.buckets.len());
+
+        if let Some((rank, candidates)) = self.buckets.next() {
+            return Ok(Some(CriterionResult {
+                query_tree: self.query_tree.clone(),
+                candidates: Some(candidates),
+                bucket_candidates: take(&mut self.bucket_candidates),
+                ranks: ranks_with(&self.ranks, rank),
+            }));
+        }
+
+        let parent = match self.parent.as_mut() {
+            Some(parent) => parent,
+            None => return Ok(None),
+        };
+
+        match parent.next(wdcache)? {
+            Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
+                let candidates = match (&query_tree, candidates) {
+                    (_, Some(candidates)) => candidates,
+                    (Some(qt), None) => resolve_query_tree(self.ctx, qt, &mut HashMap::new(), wdcache)?,
+                    (None, None) => self.ctx.documents_ids()?,
+                };
+
+                if bucket_candidates.is_empty() {
+                    self.bucket_candidates.union_with(&candidates);
+                } else {
+                    self.bucket_candidates.union_with(&bucket_candidates);
+                }
+
+                self.query_tree = query_tree;
+                self.ranks = ranks;
+                // The time budget is spent, the bucket is returned without being split.
+                let mut buckets = if self.ctx.deadline_reached() {
+                    vec![(Rank::default(), candidates)].into_iter()
+                } else {
+                    self.decay_buckets(&candidates).into_iter()
+                };
+                let (rank, candidates) = buckets.next().unwrap_or_default();
+                self.buckets = buckets;
+
+                Ok(Some(CriterionResult {
+                    query_tree: self.query_tree.clone(),
+                    candidates: Some(candidates),
+                    bucket_candidates: take(&mut self.bucket_candidates),
+                    ranks: ranks_with(&self.ranks, rank),
+                }))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use maplit::hashmap;
+
+    use crate::index::tests::TempIndex;
+    use super::*;
+
+    #[test]
+    fn closest_values_first() {
+        let content = &b"id,published\n0,950\n1,1003\n2,\n3,2000\n4,985\n5,1010\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_faceted_fields(hashmap!{ "published".into() => "integer".into() });
+            builder.set_criteria(vec!["decay(published,origin=1000,scale=10)".into()]);
+        });
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).score_details(true).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 5, 4, 0, 3, 2]);
+
+        let buckets: Vec<_> = result.documents_score_details.iter().map(|ranks| ranks[0].rank).collect();
+        assert_eq!(buckets, vec![0, 0, 1, 4, 15, 16]);
+    }
+}
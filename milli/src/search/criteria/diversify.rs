@@ -0,0 +1,254 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem::take;
+
+use anyhow::Context as _;
+use log::debug;
+use roaring::RoaringBitmap;
+
+use crate::facet::{FacetType, FacetValue};
+use crate::search::distinct::document_facet_values;
+use crate::search::query_tree::Operation;
+use crate::search::WordDerivationsCache;
+use crate::{DocumentId, FieldId, Index};
+use super::{ranks_with, resolve_query_tree, Criterion, CriterionResult, Context, Rank, ScoreDetail};
+
+/// Returns the documents of the parent buckets one by one, a document that would be the
+/// `max + 1`th consecutive document with the same facet value is returned after the next
+/// document with another value. The facet values are only read for the returned documents.
+pub struct Diversify<'t> {
+    ctx: &'t dyn Context,
+    index: &'t Index,
+    rtxn: &'t heed::RoTxn<'t>,
+    field_id: FieldId,
+    facet_type: FacetType,
+    max: usize,
+    query_tree: Option<Operation>,
+    candidates: std::vec::IntoIter<DocumentId>,
+    /// The documents of the current bucket that have been postponed, they all have the same value.
+    postponed: VecDeque<(DocumentId, FacetValue)>,
+    bucket_candidates: RoaringBitmap,
+    ranks: Vec<Rank>,
+    /// The value of the last returned document and the number of times it has been returned in a row.
+    last: Option<(FacetValue, usize)>,
+    parent: Option<Box<dyn Criterion + 't>>,
+}
+
+impl<'t> Diversify<'t> {
+    pub fn initial(
+        ctx: &'t dyn Context,
+        index: &'t Index,
+        rtxn: &'t heed::RoTxn,
+        query_tree: Option<Operation>,
+        candidates: Option<RoaringBitmap>,
+        field: String,
+        max: u8,
+    ) -> anyhow::Result<Self>
+    {
+        let candidates = match (&query_tree, candidates) {
+            (Some(qt), candidates) => {
+                let mut qt_candidates = resolve_query_tree(ctx, qt, &mut HashMap::new(), &mut WordDerivationsCache::new())?;
+                if let Some(candidates) = candidates {
+                    qt_candidates.intersect_with(&candidates);
+                }
+                qt_candidates
+            },
+            (None, Some(candidates)) => candidates,
+            (None, None) => ctx.documents_ids()?,
+        };
+
+        let mut diversify = Diversify::create(ctx, index, rtxn, None, field, max)?;
+        diversify.query_tree = query_tree;
+        diversify.candidates = candidates.iter().collect::<Vec<_>>().into_iter();
+        diversify.bucket_candidates = candidates;
+        Ok(diversify)
+    }
+
+    pub fn new(
+        ctx: &'t dyn Context,
+        index: &'t Index,
+        rtxn: &'t heed::RoTxn,
+        parent: Box<dyn Criterion + 't>,
+        field: String,
+        max: u8,
+    ) -> anyhow::Result<Self>
+    {
+        Diversify::create(ctx, index, rtxn, Some(parent), field, max)
+    }
+
+    fn create(
+        ctx: &'t dyn Context,
+        index: &'t Index,
+        rtxn: &'t heed::RoTxn,
+        parent: Option<Box<dyn Criterion + 't>>,
+        field: String,
+        max: u8,
+    ) -> anyhow::Result<Self>
+    {
+        let faceted_fields = index.faceted_fields(rtxn)?;
+        let facet_type = *faceted_fields.get(&field).with_context(|| {
+            format!("Can't use {:?} to diversify the results as it isn't a faceted field.", field)
+        })?;
+        let fields_ids_map = index.fields_ids_map(rtxn)?;
+        let field_id = fields_ids_map.id(&field).with_context(|| {
+            format!("field {:?} isn't registered", field)
+        })?;
+
+        Ok(Diversify {
+            ctx,
+            index,
+            rtxn,
+            field_id,
+            facet_type,
+            max: max.max(1) as usize,
+            query_tree: None,
+            candidates: Vec::new().into_iter(),
+            postponed: VecDeque::new(),
+            bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
+            last: None,
+            parent,
+        })
+    }
+
+    /// Returns whether a document with this value can be returned right now.
+    fn accepts(&self, value: &FacetValue) -> bool {
+        match &self.last {
+            Some((last, count)) => last != value || *count < self.max,
+            None => true,
+        }
+    }
+
+    /// Returns the next document of the current bucket, in the diversified order.
+    fn next_docid(&mut self) -> heed::Result<Option<DocumentId>> {
+        if let Some((_, value)) = self.postponed.front() {
+            if self.accepts(value) {
+                let (docid, value) = self.postponed.pop_front().unwrap();
+                self.returned(Some(value));
+                return Ok(Some(docid));
+            }
+        }
+
+        while let Some(docid) = self.candidates.next() {
+            // A document without value is never grouped with the other documents.
+            let values = document_facet_values(self.index, self.rtxn, self.field_id, self.facet_type, docid)?;
+            match values.into_iter().next() {
+                Some(value) if !self.accepts(&value) => self.postponed.push_back((docid, value)),
+                value => {
+                    self.returned(value);
+                    return Ok(Some(docid));
+                },
+            }
+        }
+
+        // There is no other value left in this bucket, we return the postponed documents anyway.
+        match self.postponed.pop_front() {
+            Some((docid, value)) => {
+                self.returned(Some(value));
+                Ok(Some(docid))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn returned(&mut self, value: Option<FacetValue>) {
+        self.last = match (self.last.take(), value) {
+            (Some((last, count)), Some(value)) if last == value => Some((last, count + 1)),
+            (_, value) => value.map(|value| (value, 1)),
+        };
+    }
+}
+
+impl<'t> Criterion for Diversify<'t> {
+    #[logging_timer::time("Diversify::{}")]
+    fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+        loop {
+            debug!("Diversify iteration ({} candidates left, {} postponed)", self.candidates.len(), self.postponed.len());
+
+            // The time budget is spent, the remaining documents of the bucket are returned together.
+            if self.candidates.len() + self.postponed.len() > 1 && self.ctx.deadline_reached() {
+                let postponed = self.postponed.drain(..).map(|(docid, _)| docid);
+                let candidates = postponed.chain(self.candidates.by_ref()).collect();
+                self.last = None;
+
+                return Ok(Some(CriterionResult {
+                    query_tree: self.query_tree.clone(),
+                    candidates: Some(candidates),
+                    bucket_candidates: take(&mut self.bucket_candidates),
+                    ranks: ranks_with(&self.ranks, Rank::unranked(ScoreDetail::Diversify)),
+                }));
+            }
+
+            if let Some(docid) = self.next_docid()? {
+                let mut candidates = RoaringBitmap::new();
+                candidates.insert(docid);
+
+                return Ok(Some(CriterionResult {
+                    query_tree: self.query_tree.clone(),
+                    candidates: Some(candidates),
+                    bucket_candidates: take(&mut self.bucket_candidates),
+                    ranks: ranks_with(&self.ranks, Rank::unranked(ScoreDetail::Diversify)),
+                }));
+            }
+
+            let parent = match self.parent.as_mut() {
+                Some(parent) => parent,
+                None => return Ok(None),
+            };
+
+            match parent.next(wdcache)? {
+                Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
+                    let candidates = match (&query_tree, candidates) {
+                        (_, Some(candidates)) => candidates,
+                        (Some(qt), None) => resolve_query_tree(self.ctx, qt, &mut HashMap::new(), wdcache)?,
+                        (None, None) => self.ctx.documents_ids()?,
+                    };
+
+                    if bucket_candidates.is_empty() {
+                        self.bucket_candidates.union_with(&candidates);
+                    } else {
+                        self.bucket_candidates.union_with(&bucket_candidates);
+                    }
+
+                    self.query_tree = query_tree;
+                    self.ranks = ranks;
+                    self.candidates = candidates.iter().collect::<Vec<_>>().into_iter();
+                },
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::index::tests::TempIndex;
+    use crate::update::Settings;
+
+    #[test]
+    fn diversify_by_seller() {
+        let content = &b"id,title,seller\n0,shoes,a\n1,shoes,a\n2,shoes,a\n3,shoes,b\n4,shoes,b\n5,shoes,c\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_faceted_fields(hashmap!{ "seller".into() => "string".into() });
+            builder.set_criteria(vec!["words".to_string(), "diversify(seller)".to_string()]);
+        });
+
+        // The documents of the seller "a" are interleaved with the other ones.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("shoes").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 3, 1, 4, 2, 5]);
+        drop(rtxn);
+
+        // Two consecutive documents of the same seller are allowed.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.set_criteria(vec!["words".to_string(), "diversify(seller,max=2)".to_string()]);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("shoes").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1, 3, 2, 4, 5]);
+    }
+}
@@ -0,0 +1,315 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::mem::take;
+
+use log::debug;
+use roaring::RoaringBitmap;
+use serde::Serialize;
+
+use crate::proximity::{extract_position, position_attribute};
+use crate::search::query_tree::Operation;
+use crate::search::WordDerivationsCache;
+use super::attribute::AttributesWeights;
+use super::{is_skipped, ranks_with, resolve_query_tree, Criterion, CriterionResult, Context, Rank, ScoreDetail};
+
+/// Returns the documents that contain the whole query literally before the others.
+///
+/// The documents with an attribute equal to the query are returned first, then the documents
+/// with an attribute that contains the query words as an exact phrase, and finally the others.
+/// Typos and prefixes are therefore never considered as exact matches.
+///
+/// The documents that match the query equally literally are then sorted by the weight
+/// of the attribute in which they match it, see `Index::attributes_weights`.
+pub struct Exactness<'t> {
+    ctx: &'t dyn Context,
+    query_words: &'t [String],
+    weights: &'t AttributesWeights,
+    query_tree: Option<Operation>,
+    buckets: std::vec::IntoIter<(Rank, RoaringBitmap)>,
+    bucket_candidates: RoaringBitmap,
+    ranks: Vec<Rank>,
+    parent: Option<Box<dyn Criterion + 't>>,
+    documents_to_skip: Option<&'t Cell<u64>>,
+}
+
+impl<'t> Exactness<'t> {
+    pub fn initial(
+        ctx: &'t dyn Context,
+        query_tree: Option<Operation>,
+        candidates: Option<RoaringBitmap>,
+        query_words: &'t [String],
+        weights: &'t AttributesWeights,
+    ) -> anyhow::Result<Self>
+    {
+        let candidates = match (&query_tree, candidates) {
+            (Some(qt), candidates) => {
+                let mut qt_candidates = resolve_query_tree(ctx, qt, &mut HashMap::new(), &mut WordDerivationsCache::new())?;
+                if let Some(candidates) = candidates {
+                    qt_candidates.intersect_with(&candidates);
+                }
+                qt_candidates
+            },
+            (None, Some(candidates)) => candidates,
+            (None, None) => ctx.documents_ids()?,
+        };
+
+        Ok(Exactness {
+            ctx,
+            query_words,
+            weights,
+            query_tree,
+            buckets: exactness_buckets(ctx, query_words, weights, &candidates)?.into_iter(),
+            bucket_candidates: candidates,
+            ranks: Vec::new(),
+            parent: None,
+            documents_to_skip: None,
+        })
+    }
+
+    pub fn new(
+        ctx: &'t dyn Context,
+        parent: Box<dyn Criterion + 't>,
+        query_words: &'t [String],
+        weights: &'t AttributesWeights,
+    ) -> Self
+    {
+        Exactness {
+            ctx,
+            query_words,
+            weights,
+            query_tree: None,
+            buckets: Vec::new().into_iter(),
+            bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
+            parent: Some(parent),
+            documents_to_skip: None,
+        }
+    }
+
+    /// The documents the offset of the search still skips, a bucket of the parent
+    /// made of such documents is returned as is, see `Fetcher::skip_ranking`.
+    pub fn documents_to_skip(mut self, documents: &'t Cell<u64>) -> Self {
+        self.documents_to_skip = Some(documents);
+        self
+    }
+}
+
+impl<'t> Criterion for Exactness<'t> {
+    #[logging_timer::time("Exactness::{}")]
+    fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+        debug!("Exactness iteration ({} buckets left)", self.buckets.len());
+
+        if let Some((rank, candidates)) = self.buckets.next() {
+            return Ok(Some(CriterionResult {
+                query_tree: self.query_tree.clone(),
+                candidates: Some(candidates),
+                bucket_candidates: take(&mut self.bucket_candidates),
+                ranks: ranks_with(&self.ranks, rank),
+            }));
+        }
+
+        let parent = match self.parent.as_mut() {
+            Some(parent) => parent,
+            None => return Ok(None),
+        };
+
+        match parent.next(wdcache)? {
+            Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
+                let candidates = match (&query_tree, candidates) {
+                    (_, Some(candidates)) => candidates,
+                    (Some(qt), None) => resolve_query_tree(self.ctx, qt, &mut HashMap::new(), wdcache)?,
+                    (None, None) => self.ctx.documents_ids()?,
+                };
+
+                if bucket_candidates.is_empty() {
+                    self.bucket_candidates.union_with(&candidates);
+                } else {
+                    self.bucket_candidates.union_with(&bucket_candidates);
+                }
+
+                self.query_tree = query_tree;
+                self.ranks = ranks;
+                // Neither the skipped buckets nor the ones given once the time budget is spent are split.
+                let mut buckets = if is_skipped(self.documents_to_skip, &candidates) || self.ctx.deadline_reached() {
+                    vec![(Rank::default(), candidates)].into_iter()
+                } else {
+                    exactness_buckets(self.ctx, self.query_words, self.weights, &candidates)?.into_iter()
+                };
+                let (rank, candidates) = buckets.next().unwrap_or_default();
+                self.buckets = buckets;
+
+                Ok(Some(CriterionResult {
+                    query_tree: self.query_tree.clone(),
+                    candidates: Some(candidates),
+                    bucket_candidates: take(&mut self.bucket_candidates),
+                    ranks: ranks_with(&self.ranks, rank),
+                }))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// How literally a document matches the query words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExactMatch {
+    /// One of the attributes is exactly the query.
+    Equal,
+    /// One of the attributes contains the query words next to each other.
+    Phrase,
+    None,
+}
+
+/// Splits the candidates into the non-empty buckets of documents that match the query
+/// equally, in the order they must be returned, along with their ranks.
+///
+/// Every exact match bucket is split by the weight of the attribute in which the documents match.
+fn exactness_buckets(
+    ctx: &dyn Context,
+    query_words: &[String],
+    weights: &AttributesWeights,
+    candidates: &RoaringBitmap,
+) -> anyhow::Result<Vec<(Rank, RoaringBitmap)>>
+{
+    if query_words.is_empty() {
+        return Ok(vec![(Rank::default(), candidates.clone())]);
+    }
+
+    let weights_count = weights.max_rank() + 1;
+    let mut buckets = vec![RoaringBitmap::new(); 2 * weights_count + 1];
+    for docid in candidates {
+        let words_positions = ctx.docid_words_positions(docid)?;
+        let bucket = match document_match(query_words, &words_positions, weights) {
+            (ExactMatch::None, _) => 2 * weights_count,
+            (exactness, weight) => exactness as usize * weights_count + weights.rank(weight),
+        };
+        buckets[bucket].insert(docid);
+    }
+
+    let matches = [ExactMatch::Equal, ExactMatch::Phrase, ExactMatch::None];
+    let max_rank = buckets.len() - 1;
+    let buckets = buckets.into_iter().enumerate()
+        .filter(|(_, bucket)| !bucket.is_empty())
+        .map(|(rank, bucket)| {
+            let exactness = matches[rank / weights_count];
+            (Rank::new(rank, max_rank, ScoreDetail::Exactness { exactness }), bucket)
+        })
+        .collect();
+
+    Ok(buckets)
+}
+
+/// Returns the best way the document, represented by its words positions, matches the query
+/// along with the biggest weight of the attributes in which it matches it that way.
+///
+/// The weight is meaningless when the document doesn't match the query literally.
+fn document_match(
+    query_words: &[String],
+    words_positions: &HashMap<String, RoaringBitmap>,
+    weights: &AttributesWeights,
+) -> (ExactMatch, u8)
+{
+    let (first_word, following_words) = match query_words.split_first() {
+        Some(split) => split,
+        None => return (ExactMatch::None, 0),
+    };
+
+    let first_positions = match words_positions.get(first_word) {
+        Some(positions) => positions,
+        None => return (ExactMatch::None, 0),
+    };
+
+    let mut best = (ExactMatch::None, 0);
+    for start in first_positions {
+        let (attribute, index) = extract_position(start);
+
+        let is_phrase = following_words.iter().zip(start + 1..).all(|(word, position)| {
+            position_attribute(position) == attribute
+                && words_positions.get(word).map_or(false, |ps| ps.contains(position))
+        });
+
+        if !is_phrase { continue }
+
+        // The attribute is equal to the query if the phrase is at the start
+        // of the attribute and no word follows it in this attribute.
+        let end = start + query_words.len() as u32;
+        let is_last = position_attribute(end) != attribute
+            || words_positions.values().all(|positions| !positions.contains(end));
+
+        let exactness = if index == 0 && is_last { ExactMatch::Equal } else { ExactMatch::Phrase };
+        let weight = weights.weight(attribute);
+        if exactness < best.0 || (exactness == best.0 && weight > best.1) {
+            best = (exactness, weight);
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use maplit::hashmap;
+
+    use crate::proximity::{encode_position, MAX_INDEX_IN_ATTRIBUTE};
+    use super::*;
+
+    fn s(s: &str) -> String { s.to_string() }
+
+    #[test]
+    fn document_exactness() {
+        let query = vec![s("hello"), s("world")];
+        let weights = AttributesWeights::default();
+
+        // "hello world" in the first attribute.
+        let positions = hashmap!{
+            s("hello") => (0..1).collect(),
+            s("world") => (1..2).collect(),
+        };
+        assert_eq!(document_match(&query, &positions, &weights).0, ExactMatch::Equal);
+
+        // "big hello world" in the second attribute.
+        let positions = hashmap!{
+            s("big") => vec![encode_position(1, 0)].into_iter().collect(),
+            s("hello") => vec![encode_position(1, 1)].into_iter().collect(),
+            s("world") => vec![encode_position(1, 2)].into_iter().collect(),
+        };
+        assert_eq!(document_match(&query, &positions, &weights).0, ExactMatch::Phrase);
+
+        // "hello world again" in the first attribute.
+        let positions = hashmap!{
+            s("hello") => (0..1).collect(),
+            s("world") => (1..2).collect(),
+            s("again") => (2..3).collect(),
+        };
+        assert_eq!(document_match(&query, &positions, &weights).0, ExactMatch::Phrase);
+
+        // "world hello" in the first attribute and "hello" at the end of it.
+        let positions = hashmap!{
+            s("world") => (0..1).collect(),
+            s("hello") => vec![1, MAX_INDEX_IN_ATTRIBUTE].into_iter().collect(),
+        };
+        assert_eq!(document_match(&query, &positions, &weights).0, ExactMatch::None);
+    }
+
+    #[test]
+    fn weighted_document_exactness() {
+        let query = vec![s("hello"), s("world")];
+        let weights = AttributesWeights::new(hashmap!{ 1 => 3 }, vec![0, 1]);
+
+        // "hello world" in the first attribute and "hello world" in the second one.
+        let positions = hashmap!{
+            s("hello") => vec![0, encode_position(1, 0)].into_iter().collect(),
+            s("world") => vec![1, encode_position(1, 1)].into_iter().collect(),
+        };
+        assert_eq!(document_match(&query, &positions, &weights), (ExactMatch::Equal, 3));
+
+        // "hello world" in the first attribute and "the hello world" in the second one.
+        let positions = hashmap!{
+            s("the") => vec![encode_position(1, 0)].into_iter().collect(),
+            s("hello") => vec![0, encode_position(1, 1)].into_iter().collect(),
+            s("world") => vec![1, encode_position(1, 2)].into_iter().collect(),
+        };
+        assert_eq!(document_match(&query, &positions, &weights), (ExactMatch::Equal, 1));
+    }
+}
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::mem::take;
 
@@ -6,7 +7,7 @@ use roaring::RoaringBitmap;
 
 use crate::search::query_tree::Operation;
 use crate::search::WordDerivationsCache;
-use super::{resolve_query_tree, Candidates, Criterion, CriterionResult, Context};
+use super::{ranking_score, resolve_query_tree, Candidates, Criterion, CriterionResult, Context, Rank};
 
 /// The result of a call to the fetcher.
 #[derive(Debug, Clone, PartialEq)]
@@ -17,6 +18,15 @@ pub struct FetcherResult {
     pub candidates: RoaringBitmap,
     /// Candidates that comes from the current bucket of the initial criterion.
     pub bucket_candidates: RoaringBitmap,
+    /// The rank of the current bucket for each one of the criteria, in order.
+    pub ranks: Vec<Rank>,
+}
+
+impl FetcherResult {
+    /// Returns the relevancy score, between `0` and `1`, of the candidates of the current bucket.
+    pub fn score(&self) -> f64 {
+        ranking_score(&self.ranks)
+    }
 }
 
 pub struct Fetcher<'t> {
@@ -25,6 +35,11 @@ pub struct Fetcher<'t> {
     candidates: Candidates,
     parent: Option<Box<dyn Criterion + 't>>,
     should_get_documents_ids: bool,
+    /// The number of documents that can still be returned, when the search is bounded.
+    remaining_hits: Option<u64>,
+    /// The number of documents that will be skipped by the offset of the search,
+    /// shared with the criteria that don't split the buckets of such documents.
+    documents_to_skip: Option<&'t Cell<u64>>,
     wdcache: WordDerivationsCache,
 }
 
@@ -41,6 +56,8 @@ impl<'t> Fetcher<'t> {
             candidates: candidates.map_or_else(Candidates::default, Candidates::Allowed),
             parent: None,
             should_get_documents_ids: true,
+            remaining_hits: None,
+            documents_to_skip: None,
             wdcache: WordDerivationsCache::new(),
         }
     }
@@ -56,12 +73,65 @@ impl<'t> Fetcher<'t> {
             candidates: Candidates::default(),
             parent: Some(parent),
             should_get_documents_ids: true,
+            remaining_hits: None,
+            documents_to_skip: None,
             wdcache: WordDerivationsCache::new(),
         }
     }
 
+    /// The counter of skipped documents shared with the criteria, see `skip_ranking`.
+    pub fn documents_to_skip(mut self, documents: &'t Cell<u64>) -> Self {
+        self.documents_to_skip = Some(documents);
+        self
+    }
+
+    /// Tells the criteria that the first `offset` documents will be skipped, the buckets they
+    /// receive that only contain such documents are returned as is, without reading the
+    /// positions of their documents, as the order of the skipped documents doesn't matter.
+    ///
+    /// It must not be used when the documents are filtered after being ranked,
+    /// a document can't be counted as skipped before it is known to be returned.
+    pub fn skip_ranking(&mut self, offset: usize) {
+        if let Some(documents) = self.documents_to_skip {
+            documents.set(offset as u64);
+        }
+    }
+
+    /// Bounds the number of documents returned by the fetcher, the buckets of the parent
+    /// criteria are no more computed once this number of documents has been returned.
+    ///
+    /// The documents are counted before being deduplicated, it must not be used when
+    /// they are filtered after being ranked, the pages would be returned incomplete.
+    pub fn max_total_hits(&mut self, max: usize) {
+        self.remaining_hits = Some(max as u64);
+    }
+
     #[logging_timer::time("Fetcher::{}")]
     pub fn next(&mut self) -> anyhow::Result<Option<FetcherResult>> {
+        if self.remaining_hits == Some(0) {
+            return Ok(None);
+        }
+
+        let mut result = match self.next_bucket()? {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+
+        if let Some(remaining) = self.remaining_hits.as_mut() {
+            if result.candidates.len() > *remaining {
+                result.candidates = result.candidates.iter().take(*remaining as usize).collect();
+            }
+            *remaining -= result.candidates.len();
+        }
+
+        if let Some(documents) = self.documents_to_skip {
+            documents.set(documents.get().saturating_sub(result.candidates.len()));
+        }
+
+        Ok(Some(result))
+    }
+
+    fn next_bucket(&mut self) -> anyhow::Result<Option<FetcherResult>> {
         use Candidates::{Allowed, Forbidden};
         loop {
             debug!("Fetcher iteration (should_get_documents_ids: {}) ({:?})",
@@ -85,20 +155,21 @@ impl<'t> Fetcher<'t> {
                         query_tree: self.query_tree.take(),
                         candidates: candidates.clone(),
                         bucket_candidates: candidates,
+                        ranks: Vec::new(),
                     }));
                 },
                 Forbidden(_) => {
                     match self.parent.as_mut() {
                         Some(parent) => {
                             match parent.next(&mut self.wdcache)? {
-                                Some(CriterionResult { query_tree, candidates, bucket_candidates }) => {
+                                Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
                                     let candidates = match (&query_tree, candidates) {
                                         (_, Some(candidates)) => candidates,
                                         (Some(qt), None) => resolve_query_tree(self.ctx, qt, &mut HashMap::new(), &mut self.wdcache)?,
                                         (None, None) => RoaringBitmap::new(),
                                     };
 
-                                    return Ok(Some(FetcherResult { query_tree, candidates, bucket_candidates }))
+                                    return Ok(Some(FetcherResult { query_tree, candidates, bucket_candidates, ranks }))
                                 },
                                 None => if should_get_documents_ids {
                                     let candidates = match &self.query_tree {
@@ -110,6 +181,7 @@ impl<'t> Fetcher<'t> {
                                         query_tree: self.query_tree.clone(),
                                         candidates: candidates.clone(),
                                         bucket_candidates: candidates,
+                                        ranks: Vec::new(),
                                     }));
                                 },
                             }
@@ -124,6 +196,7 @@ impl<'t> Fetcher<'t> {
                                 query_tree: self.query_tree.clone(),
                                 candidates: candidates.clone(),
                                 bucket_candidates: candidates,
+                                ranks: Vec::new(),
                             }));
                         },
                     }
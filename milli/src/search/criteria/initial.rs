@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use roaring::RoaringBitmap;
+
+use crate::search::query_tree::Operation;
+use crate::search::WordDerivationsCache;
+use super::{resolve_query_tree, Criterion, CriterionResult, Context};
+
+/// Returns all the candidates in a single bucket, it is the parent given to a custom
+/// criterion when it is the first ranking rule, see `CriteriaBuilder::register_criterion`.
+pub struct Initial {
+    answer: Option<CriterionResult>,
+}
+
+impl Initial {
+    pub fn new(
+        ctx: &dyn Context,
+        query_tree: Option<Operation>,
+        candidates: Option<RoaringBitmap>,
+    ) -> anyhow::Result<Self>
+    {
+        let candidates = match (&query_tree, candidates) {
+            (Some(qt), candidates) => {
+                let mut qt_candidates = resolve_query_tree(ctx, qt, &mut HashMap::new(), &mut WordDerivationsCache::new())?;
+                if let Some(candidates) = candidates {
+                    qt_candidates.intersect_with(&candidates);
+                }
+                qt_candidates
+            },
+            (None, Some(candidates)) => candidates,
+            (None, None) => ctx.documents_ids()?,
+        };
+
+        let answer = CriterionResult {
+            query_tree,
+            candidates: Some(candidates.clone()),
+            bucket_candidates: candidates,
+            ranks: Vec::new(),
+        };
+
+        Ok(Initial { answer: Some(answer) })
+    }
+}
+
+impl Criterion for Initial {
+    fn next(&mut self, _wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+        Ok(self.answer.take())
+    }
+}
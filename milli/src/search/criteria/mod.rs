@@ -1,39 +1,177 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::borrow::Cow;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::bail;
+use heed::BytesDecode;
+use heed::types::ByteSlice;
 use roaring::RoaringBitmap;
+use serde::Serialize;
 
-use crate::search::{word_derivations, WordDerivationsCache};
-use crate::{Index, DocumentId};
+use crate::criterion::{Criterion as Name, NullsPlacement};
+use crate::facet::FacetValue;
+use crate::proximity::{extract_position, pair_proximity, position_attribute, position_bucket, MAX_PAIR_PROXIMITY};
+use crate::search::cache::SearchStructures;
+use crate::search::word_derivations;
+use crate::{AscDesc as SortCriterion, Index, DocumentId, FieldId, StrBEU32Codec};
 
-use super::query_tree::{Operation, Query, QueryKind};
+use super::query_tree::{Query, QueryKind};
+pub use super::query_tree::Operation;
+pub use crate::search::WordDerivationsCache;
 use self::typo::Typo;
 use self::words::Words;
 use self::asc_desc::AscDesc;
 use self::proximity::Proximity;
+use self::attribute::Attribute;
+pub(crate) use self::attribute::AttributesWeights;
+use self::boost::Boost;
+use self::random::Random;
+use self::diversify::Diversify;
+use self::decay::Decay;
+use self::exactness::Exactness;
+pub use self::exactness::ExactMatch;
+use self::words_position::WordsPosition;
+use self::bucket_counter::BucketCounter;
+use self::initial::Initial;
 use self::fetcher::Fetcher;
 
 mod typo;
 mod words;
 mod asc_desc;
 mod proximity;
+mod attribute;
+mod boost;
+mod random;
+mod diversify;
+mod decay;
+mod exactness;
+mod words_position;
+mod bucket_counter;
+mod initial;
 pub mod fetcher;
 
+/// The maximum number of candidates for which the words documents ids are only read
+/// from the shards containing the candidates, see `Index::word_docids_within`.
+const CANDIDATES_HINT_LIMIT: u64 = 10_000;
+
 pub trait Criterion {
     fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>>;
 }
 
+/// Builds a ranking rule defined outside of this crate from the context of the search
+/// and the criterion it must refine the buckets of, see `CriteriaBuilder::register_criterion`.
+pub type CriterionFactory = Arc<
+    dyn for<'t> Fn(&'t dyn Context, Box<dyn Criterion + 't>) -> anyhow::Result<Box<dyn Criterion + 't>>
+    + Send + Sync
+>;
+
 /// The result of a call to the parent criterion.
 #[derive(Debug, Clone, PartialEq)]
 pub struct CriterionResult {
     /// The query tree that must be used by the children criterion to fetch candidates.
-    query_tree: Option<Operation>,
+    pub query_tree: Option<Operation>,
     /// The candidates that this criterion is allowed to return subsets of,
     /// if None, it is up to the child to compute the candidates itself.
-    candidates: Option<RoaringBitmap>,
+    pub candidates: Option<RoaringBitmap>,
     /// Candidates that comes from the current bucket of the initial criterion.
-    bucket_candidates: RoaringBitmap,
+    pub bucket_candidates: RoaringBitmap,
+    /// The rank of this bucket for each criterion, from the initial one to this one.
+    pub ranks: Vec<Rank>,
+}
+
+/// The position of a bucket among the buckets a criterion returns for a bucket of its parent.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rank {
+    /// The position of the bucket, `0` is the best one.
+    pub rank: u32,
+    /// The position of the worst bucket the criterion can return, the criteria that don't
+    /// sort the documents by relevancy (e.g. `Asc` or `Random`) always return `0`.
+    pub max_rank: u32,
+    /// What the documents of the bucket have in common for this criterion.
+    pub detail: ScoreDetail,
+}
+
+impl Rank {
+    pub fn new(rank: usize, max_rank: usize, detail: ScoreDetail) -> Rank {
+        Rank { rank: rank.min(max_rank) as u32, max_rank: max_rank as u32, detail }
+    }
+
+    /// The rank of the buckets of the criteria that don't sort the documents by relevancy.
+    pub fn unranked(detail: ScoreDetail) -> Rank {
+        Rank { rank: 0, max_rank: 0, detail }
+    }
+}
+
+/// What the documents of a bucket have in common for the criterion that returned it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "criterion", rename_all = "camelCase")]
+pub enum ScoreDetail {
+    /// The criterion didn't rank the documents, e.g. the documents of a placeholder search.
+    None,
+    /// The sum of the factors of the boosts the documents match, see `Search::boost`.
+    Boost { factor: u32 },
+    /// The number of query words the documents contain.
+    #[serde(rename_all = "camelCase")]
+    Words { matching_words: u32 },
+    /// The number of typos the documents contain, the last bucket also
+    /// contains the documents with more typos.
+    Typo { typos: u8 },
+    /// The sum of the proximities between the query words, the last bucket
+    /// also contains the documents with a bigger proximity.
+    Proximity { proximity: u8 },
+    /// The biggest weight of the attributes that contain a query word, the last
+    /// bucket also contains the documents without known positions.
+    Attribute { weight: u8 },
+    /// The position bucket of the query word that appears the earliest in an attribute,
+    /// see `position_bucket`, the last bucket contains the documents without known positions.
+    WordsPosition { position: u32 },
+    /// How literally the documents contain the query.
+    Exactness { exactness: ExactMatch },
+    /// The value of the field the documents are sorted by, `None` for the documents without one.
+    Sort { field: String, ascending: bool, nulls: NullsPlacement, value: Option<FacetValue> },
+    /// The number of scales between the value of the field and the origin, the last but one
+    /// bucket contains the documents far from it and the last one the documents without a value.
+    Decay { field: String, bucket: u32 },
+    Random,
+    Diversify,
+}
+
+impl Default for ScoreDetail {
+    fn default() -> ScoreDetail {
+        ScoreDetail::None
+    }
+}
+
+/// Returns the ranks of the parent bucket followed by the rank of the current bucket.
+fn ranks_with(parent_ranks: &[Rank], rank: Rank) -> Vec<Rank> {
+    let mut ranks = Vec::with_capacity(parent_ranks.len() + 1);
+    ranks.extend_from_slice(parent_ranks);
+    ranks.push(rank);
+    ranks
+}
+
+/// Returns `true` if the documents of the bucket will all be skipped by the offset of the
+/// search, the criteria that read the documents positions don't split such buckets.
+fn is_skipped(documents_to_skip: Option<&Cell<u64>>, candidates: &RoaringBitmap) -> bool {
+    documents_to_skip.map_or(false, |skip| candidates.len() <= skip.get())
+}
+
+/// Returns the relevancy score, between `0` and `1`, of the documents of a bucket with these ranks.
+///
+/// Every criterion splits the score range of the bucket of its parent into equal parts,
+/// one for each bucket it can return, the best documents have a score of `1`.
+pub fn ranking_score(ranks: &[Rank]) -> f64 {
+    let mut score = 1.0;
+    let mut range = 1.0;
+    for Rank { rank, max_rank, .. } in ranks {
+        range /= *max_rank as f64 + 1.0;
+        score -= range * *rank as f64;
+    }
+    score
 }
 
 /// Either a set of candidates that defines the candidates
@@ -62,18 +200,54 @@ impl Default for Candidates {
 pub trait Context {
     fn documents_ids(&self) -> heed::Result<RoaringBitmap>;
     fn word_docids(&self, word: &str) -> heed::Result<Option<RoaringBitmap>>;
+    /// Returns the documents ids of the word that are part of the given candidates.
+    fn word_docids_within(&self, word: &str, candidates: &RoaringBitmap) -> heed::Result<Option<RoaringBitmap>> {
+        Ok(self.word_docids(word)?.map(|docids| docids & candidates))
+    }
+    /// Returns the number of documents containing the word, an upper bound is accepted.
+    fn word_documents_count(&self, word: &str) -> heed::Result<Option<u64>> {
+        Ok(self.word_docids(word)?.map(|docids| docids.len()))
+    }
     fn word_prefix_docids(&self, word: &str) -> heed::Result<Option<RoaringBitmap>>;
     fn word_pair_proximity_docids(&self, left: &str, right: &str, proximity: u8) -> heed::Result<Option<RoaringBitmap>>;
     fn word_prefix_pair_proximity_docids(&self, left: &str, right: &str, proximity: u8) -> heed::Result<Option<RoaringBitmap>>;
+    fn prefix_word_pair_proximity_docids(&self, left: &str, right: &str, proximity: u8) -> heed::Result<Option<RoaringBitmap>>;
+    /// Returns the documents ids in which the first position of the word falls in the bucket.
+    fn word_position_docids(&self, word: &str, bucket: u32) -> heed::Result<Option<RoaringBitmap>>;
+    /// Returns the documents ids containing the word in the attribute with this field id.
+    fn word_attribute_docids(&self, word: &str, attribute: u32) -> heed::Result<Option<RoaringBitmap>>;
     fn words_fst<'t>(&self) -> &'t fst::Set<Cow<[u8]>>;
     fn in_prefix_cache(&self, word: &str) -> bool;
     fn docid_words_positions(&self, docid: DocumentId) -> heed::Result<HashMap<String, RoaringBitmap>>;
+    fn proximity_database_enabled(&self) -> bool;
+    /// Returns `true` once the time budget of the search is spent, the criteria then return
+    /// the buckets they are given without splitting them, see `CriteriaBuilder::deadline`.
+    fn deadline_reached(&self) -> bool {
+        false
+    }
 }
 pub struct CriteriaBuilder<'t> {
     rtxn: &'t heed::RoTxn<'t>,
     index: &'t Index,
-    words_fst: fst::Set<Cow<'t, [u8]>>,
-    words_prefixes_fst: fst::Set<Cow<'t, [u8]>>,
+    structures: Arc<SearchStructures>,
+    proximity_database_enabled: bool,
+    query_words: Vec<String>,
+    attributes_weights: AttributesWeights,
+    boosts: Vec<(RoaringBitmap, u32)>,
+    custom_criteria: HashMap<String, CriterionFactory>,
+    typo_candidates_limit: Option<u64>,
+    criteria: Option<Vec<Name>>,
+    candidates_hint: Option<RoaringBitmap>,
+    /// The field ids of the attributes the words are searched in, all of them when `None`.
+    searchable_attributes: Option<Vec<u32>>,
+    /// The documents of the words and prefixes in the searchable attributes and in the other
+    /// ones, the prefixes are expanded by scanning the word attribute docids only once.
+    attributes_docids_cache: RefCell<HashMap<(String, bool), (RoaringBitmap, RoaringBitmap)>>,
+    /// The number of documents the fetcher will still skip, see `Fetcher::skip_ranking`.
+    documents_to_skip: Cell<u64>,
+    deadline: Option<Instant>,
+    /// Whether a criterion didn't split a bucket because the deadline was reached.
+    degraded: Cell<bool>,
 }
 
 impl<'a> Context for CriteriaBuilder<'a> {
@@ -82,89 +256,613 @@ impl<'a> Context for CriteriaBuilder<'a> {
     }
 
     fn word_docids(&self, word: &str) -> heed::Result<Option<RoaringBitmap>> {
-        self.index.word_docids.get(self.rtxn, &word)
+        if let Some(attributes) = &self.searchable_attributes {
+            return self.attributes_word_docids(word, attributes);
+        }
+
+        match &self.candidates_hint {
+            Some(candidates) => self.index.word_docids_within(self.rtxn, word, candidates),
+            None => self.structures.word_docids(self.index, self.rtxn, word),
+        }
+    }
+
+    fn word_docids_within(&self, word: &str, candidates: &RoaringBitmap) -> heed::Result<Option<RoaringBitmap>> {
+        if let Some(attributes) = &self.searchable_attributes {
+            return Ok(self.attributes_word_docids(word, attributes)?.map(|docids| docids & candidates));
+        }
+
+        match &self.candidates_hint {
+            Some(hint) => {
+                let mut candidates = candidates.clone();
+                candidates.intersect_with(hint);
+                self.index.word_docids_within(self.rtxn, word, &candidates)
+            },
+            None => self.index.word_docids_within(self.rtxn, word, candidates),
+        }
+    }
+
+    fn word_documents_count(&self, word: &str) -> heed::Result<Option<u64>> {
+        match &self.searchable_attributes {
+            Some(attributes) => Ok(self.attributes_word_docids(word, attributes)?.map(|docids| docids.len())),
+            None => self.index.word_documents_count(self.rtxn, word),
+        }
     }
 
     fn word_prefix_docids(&self, word: &str) -> heed::Result<Option<RoaringBitmap>> {
-        self.index.word_prefix_docids.get(self.rtxn, &word)
+        match &self.searchable_attributes {
+            Some(attributes) => self.attributes_prefix_docids(word, attributes),
+            None => self.index.word_prefix_docids.get(self.rtxn, &word),
+        }
     }
 
     fn word_pair_proximity_docids(&self, left: &str, right: &str, proximity: u8) -> heed::Result<Option<RoaringBitmap>> {
+        if !self.proximity_database_enabled {
+            let left = self.word_docids(left)?;
+            let right = self.word_docids(right)?;
+            return Ok(cooccurrence_docids(left, right, proximity));
+        }
+
         let key = (left, right, proximity);
-        self.index.word_pair_proximity_docids.get(self.rtxn, &key)
+        let docids = self.index.word_pair_proximity_docids.get(self.rtxn, &key)?;
+        match &self.searchable_attributes {
+            Some(attributes) => self.attributes_pair_docids(docids, (left, false), (right, false), proximity, attributes),
+            None => Ok(docids),
+        }
     }
 
     fn word_prefix_pair_proximity_docids(&self, left: &str, right: &str, proximity: u8) -> heed::Result<Option<RoaringBitmap>> {
+        if !self.proximity_database_enabled {
+            let left = self.word_docids(left)?;
+            let right = self.word_prefix_docids(right)?;
+            return Ok(cooccurrence_docids(left, right, proximity));
+        }
+
         let key = (left, right, proximity);
-        self.index.word_prefix_pair_proximity_docids.get(self.rtxn, &key)
+        let docids = self.index.word_prefix_pair_proximity_docids.get(self.rtxn, &key)?;
+        match &self.searchable_attributes {
+            Some(attributes) => self.attributes_pair_docids(docids, (left, false), (right, true), proximity, attributes),
+            None => Ok(docids),
+        }
+    }
+
+    fn prefix_word_pair_proximity_docids(&self, left: &str, right: &str, proximity: u8) -> heed::Result<Option<RoaringBitmap>> {
+        if !self.proximity_database_enabled {
+            let left = self.word_prefix_docids(left)?;
+            let right = self.word_docids(right)?;
+            return Ok(cooccurrence_docids(left, right, proximity));
+        }
+
+        let key = (left, right, proximity);
+        let docids = self.index.prefix_word_pair_proximity_docids.get(self.rtxn, &key)?;
+        match &self.searchable_attributes {
+            Some(attributes) => self.attributes_pair_docids(docids, (left, true), (right, false), proximity, attributes),
+            None => Ok(docids),
+        }
+    }
+
+    fn word_position_docids(&self, word: &str, bucket: u32) -> heed::Result<Option<RoaringBitmap>> {
+        let docids = self.index.word_position_docids.get(self.rtxn, &(word, bucket))?;
+        match &self.searchable_attributes {
+            Some(attributes) => self.attributes_position_docids(docids, word, bucket, attributes),
+            None => Ok(docids),
+        }
+    }
+
+    fn word_attribute_docids(&self, word: &str, attribute: u32) -> heed::Result<Option<RoaringBitmap>> {
+        match &self.searchable_attributes {
+            Some(attributes) if !attributes.contains(&attribute) => Ok(None),
+            _ => self.index.word_attribute_docids.get(self.rtxn, &(word, attribute)),
+        }
     }
 
     fn words_fst<'t>(&self) -> &'t fst::Set<Cow<[u8]>> {
-        &self.words_fst
+        &self.structures.words_fst
     }
 
     fn in_prefix_cache(&self, word: &str) -> bool {
-        self.words_prefixes_fst.contains(word)
+        self.structures.words_prefixes_fst.contains(word)
     }
 
     fn docid_words_positions(&self, docid: DocumentId) -> heed::Result<HashMap<String, RoaringBitmap>> {
         let mut words_positions = HashMap::new();
         for result in self.index.docid_word_positions.prefix_iter(self.rtxn, &(docid, ""))? {
-            let ((_, word), positions) = result?;
+            let ((_, word), mut positions) = result?;
+            // The positions in the attributes that aren't searched are ignored.
+            if let Some(attributes) = &self.searchable_attributes {
+                positions = positions.iter().filter(|p| attributes.contains(&position_attribute(*p))).collect();
+                if positions.is_empty() { continue }
+            }
             words_positions.insert(word.to_string(), positions);
         }
         Ok(words_positions)
     }
+
+    fn proximity_database_enabled(&self) -> bool {
+        self.proximity_database_enabled
+    }
+
+    fn deadline_reached(&self) -> bool {
+        let reached = self.deadline.map_or(false, |deadline| Instant::now() >= deadline);
+        if reached {
+            self.degraded.set(true);
+        }
+        reached
+    }
 }
 
 impl<'t> CriteriaBuilder<'t> {
     pub fn new(rtxn: &'t heed::RoTxn<'t>, index: &'t Index) -> anyhow::Result<Self> {
-        let words_fst = index.words_fst(rtxn)?;
-        let words_prefixes_fst = index.words_prefixes_fst(rtxn)?;
-        Ok(Self { rtxn, index, words_fst, words_prefixes_fst })
+        let structures = index.search_cache.structures(index, rtxn)?;
+        let proximity_database_enabled = index.proximity_database_enabled(rtxn)?;
+        let attributes_weights = AttributesWeights::from_index(index, rtxn)?;
+        Ok(Self {
+            rtxn,
+            index,
+            structures,
+            proximity_database_enabled,
+            query_words: Vec::new(),
+            attributes_weights,
+            boosts: Vec::new(),
+            custom_criteria: HashMap::new(),
+            typo_candidates_limit: None,
+            criteria: None,
+            candidates_hint: None,
+            searchable_attributes: None,
+            attributes_docids_cache: RefCell::new(HashMap::new()),
+            documents_to_skip: Cell::new(0),
+            deadline: None,
+            degraded: Cell::new(false),
+        })
+    }
+
+    /// The words of the query, in order, used to find the documents that match it literally.
+    pub fn query_words(&mut self, words: Vec<String>) -> &mut Self {
+        self.query_words = words;
+        self
+    }
+
+    /// The documents to rank before the others, with their factors, the documents
+    /// that match boosts with a bigger total factor are returned first.
+    pub fn boosts(&mut self, boosts: Vec<(RoaringBitmap, u32)>) -> &mut Self {
+        self.boosts = boosts;
+        self
+    }
+
+    /// Registers a ranking rule defined outside of this crate, it is inserted in the chain
+    /// of criteria where a `Criterion::Custom` with the same name is in the ranking rules.
+    ///
+    /// The factory is given the criterion whose buckets it must refine, the first ranking
+    /// rule is given a criterion that returns all the candidates in a single bucket.
+    pub fn register_criterion<F>(&mut self, name: impl Into<String>, factory: F) -> &mut Self
+    where
+        F: for<'c> Fn(&'c dyn Context, Box<dyn Criterion + 'c>) -> anyhow::Result<Box<dyn Criterion + 'c>>,
+        F: Send + Sync + 'static,
+    {
+        self.custom_criteria.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    /// The ranking rules defined outside of this crate, by name, see `register_criterion`.
+    pub(crate) fn custom_criteria(&mut self, criteria: HashMap<String, CriterionFactory>) -> &mut Self {
+        self.custom_criteria.extend(criteria);
+        self
+    }
+
+    fn custom_criterion(&self, name: &str) -> anyhow::Result<&CriterionFactory> {
+        match self.custom_criteria.get(name) {
+            Some(factory) => Ok(factory),
+            None => bail!("Can't use the custom criterion {:?} as it hasn't been registered.", name),
+        }
+    }
+
+    /// The number of documents from which the typo criterion stops exploring the typo buckets.
+    pub fn typo_candidates_limit(&mut self, limit: Option<u64>) -> &mut Self {
+        self.typo_candidates_limit = limit;
+        self
+    }
+
+    /// The ranking rules to use instead of the ones of the index.
+    pub fn criteria(&mut self, criteria: Option<Vec<Name>>) -> &mut Self {
+        self.criteria = criteria;
+        self
+    }
+
+    /// The documents the results will be restricted to, when they are few enough
+    /// only the shards of the frequent words containing them are read.
+    pub fn candidates_hint(&mut self, candidates: Option<&RoaringBitmap>) -> &mut Self {
+        self.candidates_hint = candidates
+            .filter(|candidates| candidates.len() <= CANDIDATES_HINT_LIMIT)
+            .cloned();
+        self
+    }
+
+    /// Only searches the words in the attributes with these field ids, the documents must
+    /// contain the words in one of them, e.g. to search the titles only for this query.
+    pub fn searchable_attributes(&mut self, attributes: Option<Vec<FieldId>>) -> &mut Self {
+        self.searchable_attributes = attributes.map(|ids| ids.into_iter().map(u32::from).collect());
+        self.attributes_docids_cache.get_mut().clear();
+        self
+    }
+
+    /// The instant from which the criteria stop splitting the buckets they are given, the
+    /// remaining documents of a bucket are then returned together, see `is_degraded`.
+    ///
+    /// The documents are all returned, only their order is less refined.
+    pub fn deadline(&mut self, deadline: Option<Instant>) -> &mut Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Returns `true` if a criterion returned a bucket without splitting it because the
+    /// deadline was reached, some documents may then have been returned before better ones.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.get()
+    }
+
+    /// Returns the documents containing the word in one of the given attributes.
+    fn attributes_word_docids(&self, word: &str, attributes: &[u32]) -> heed::Result<Option<RoaringBitmap>> {
+        let mut docids = None;
+        for attribute in attributes {
+            if let Some(attribute_docids) = self.word_attribute_docids(word, *attribute)? {
+                docids.get_or_insert_with(RoaringBitmap::new).union_with(&attribute_docids);
+            }
+        }
+        Ok(docids)
+    }
+
+    /// Returns the documents containing a word starting with the prefix in one of the given attributes.
+    fn attributes_prefix_docids(&self, prefix: &str, attributes: &[u32]) -> heed::Result<Option<RoaringBitmap>> {
+        let (searched, _) = self.attributes_split_docids(prefix, true, attributes)?;
+        Ok(if searched.is_empty() { None } else { Some(searched) })
+    }
+
+    /// Returns the documents containing the word, or a word starting with it when it is a prefix,
+    /// in one of the given attributes and the documents containing it in the other attributes.
+    ///
+    /// The given attributes are always the searchable attributes, the results are cached.
+    fn attributes_split_docids(
+        &self,
+        word: &str,
+        prefix: bool,
+        attributes: &[u32],
+    ) -> heed::Result<(RoaringBitmap, RoaringBitmap)>
+    {
+        let cache_key = (word.to_string(), prefix);
+        if let Some(split) = self.attributes_docids_cache.borrow().get(&cache_key) {
+            return Ok(split.clone());
+        }
+
+        // The word is followed by a zero byte in the keys, the longer words are skipped.
+        let mut key_prefix = word.as_bytes().to_vec();
+        if !prefix { key_prefix.push(0) }
+
+        let database = self.index.word_attribute_docids.remap_key_type::<ByteSlice>();
+        let mut searched = RoaringBitmap::new();
+        let mut others = RoaringBitmap::new();
+        for result in database.prefix_iter(self.rtxn, &key_prefix)? {
+            let (key, docids) = result?;
+            let (_, attribute) = StrBEU32Codec::bytes_decode(key).ok_or(heed::Error::Decoding)?;
+            if attributes.contains(&attribute) {
+                searched.union_with(&docids);
+            } else {
+                others.union_with(&docids);
+            }
+        }
+
+        self.attributes_docids_cache.borrow_mut().insert(cache_key, (searched.clone(), others.clone()));
+        Ok((searched, others))
+    }
+
+    /// The pairs proximities databases don't know the attributes of the pairs, the documents
+    /// that only contain the words in the given attributes keep the proximity of the database,
+    /// the proximity of the other ones is computed from the positions in the given attributes.
+    fn attributes_pair_docids(
+        &self,
+        docids: Option<RoaringBitmap>,
+        (left, left_prefix): (&str, bool),
+        (right, right_prefix): (&str, bool),
+        proximity: u8,
+        attributes: &[u32],
+    ) -> heed::Result<Option<RoaringBitmap>>
+    {
+        let (left_searched, left_others) = self.attributes_split_docids(left, left_prefix, attributes)?;
+        let (right_searched, right_others) = self.attributes_split_docids(right, right_prefix, attributes)?;
+        let candidates = left_searched & right_searched;
+        let recomputed = &candidates & &(left_others | right_others);
+
+        let mut docids = docids.unwrap_or_default() & candidates;
+        docids.difference_with(&recomputed);
+        for docid in recomputed.iter() {
+            let words_positions = self.docid_words_positions(docid)?;
+            let found = words_positions.iter()
+                .filter(|(w, _)| word_matches(w, left, left_prefix))
+                .any(|(_, lps)| {
+                    words_positions.iter()
+                        .filter(|(w, _)| word_matches(w, right, right_prefix))
+                        .any(|(_, rps)| min_pair_proximity(lps, rps) == Some(proximity))
+                });
+            if found { docids.insert(docid); }
+        }
+
+        Ok(Some(docids))
+    }
+
+    /// The documents that contain the word in other attributes than the given ones can have
+    /// their first position in another bucket, it is computed from the positions in the given attributes.
+    fn attributes_position_docids(
+        &self,
+        docids: Option<RoaringBitmap>,
+        word: &str,
+        bucket: u32,
+        attributes: &[u32],
+    ) -> heed::Result<Option<RoaringBitmap>>
+    {
+        let (searched, others) = self.attributes_split_docids(word, false, attributes)?;
+        let recomputed = &searched & &others;
+
+        let mut docids = docids.unwrap_or_default() & searched;
+        docids.difference_with(&recomputed);
+        for docid in recomputed.iter() {
+            let words_positions = self.docid_words_positions(docid)?;
+            let index = words_positions.get(word).and_then(|ps| ps.iter().map(|p| extract_position(p).1).min());
+            if index.map(position_bucket) == Some(bucket) { docids.insert(docid); }
+        }
+
+        Ok(Some(docids))
+    }
+
+    /// The sort criteria resolve the query tree without this context, the candidates
+    /// given to them must already be the ones containing the words in the searched attributes.
+    fn searchable_candidates(
+        &self,
+        query_tree: Option<&Operation>,
+        candidates: Option<RoaringBitmap>,
+    ) -> anyhow::Result<Option<RoaringBitmap>>
+    {
+        match (&self.searchable_attributes, query_tree) {
+            (Some(_), Some(qt)) => {
+                let mut qt_candidates = resolve_query_tree(self, qt, &mut HashMap::new(), &mut WordDerivationsCache::new())?;
+                if let Some(candidates) = candidates {
+                    qt_candidates.intersect_with(&candidates);
+                }
+                Ok(Some(qt_candidates))
+            },
+            _ => Ok(candidates),
+        }
     }
 
     pub fn build(
+        &'t self,
+        query_tree: Option<Operation>,
+        facet_candidates: Option<RoaringBitmap>,
+        sort_criteria: Option<Vec<SortCriterion>>,
+    ) -> anyhow::Result<Fetcher<'t>>
+    {
+        self.build_criteria(query_tree, facet_candidates, sort_criteria, None)
+    }
+
+    /// Identical to `build` but also returns, for every criterion in order, the number of
+    /// candidates of the buckets it returned, the counts are filled while the fetcher is used.
+    pub fn build_with_bucket_counts(
+        &'t self,
+        query_tree: Option<Operation>,
+        facet_candidates: Option<RoaringBitmap>,
+        sort_criteria: Option<Vec<SortCriterion>>,
+    ) -> anyhow::Result<(Fetcher<'t>, Vec<(Name, Rc<RefCell<Vec<u64>>>)>)>
+    {
+        let mut counters = Vec::new();
+        let fetcher = self.build_criteria(query_tree, facet_candidates, sort_criteria, Some(&mut counters))?;
+        Ok((fetcher, counters))
+    }
+
+    fn build_criteria(
         &'t self,
         mut query_tree: Option<Operation>,
         mut facet_candidates: Option<RoaringBitmap>,
+        sort_criteria: Option<Vec<SortCriterion>>,
+        mut counters: Option<&mut Vec<(Name, Rc<RefCell<Vec<u64>>>)>>,
     ) -> anyhow::Result<Fetcher<'t>>
     {
-        use crate::criterion::Criterion as Name;
+        // Each one of the query-time sort criteria refines the buckets returned by the previous one.
+        let sort_criteria: Vec<_> = sort_criteria.unwrap_or_default().into_iter().map(|sort| {
+            let name = if sort.is_ascending() {
+                Name::Asc(sort.field().to_string())
+            } else {
+                Name::Desc(sort.field().to_string())
+            };
+            (name, sort.nulls_placement())
+        }).collect();
+
+        let index_criteria = match &self.criteria {
+            Some(criteria) => criteria.clone(),
+            None => self.index.criteria(&self.rtxn)?,
+        };
+
+        // The query-time sort criteria are applied at the position of the sort ranking rule,
+        // or before the ranking rules of the index when there is no such rule.
+        let criteria: Vec<_> = if index_criteria.contains(&Name::Sort) {
+            index_criteria.into_iter().flat_map(|name| match name {
+                Name::Sort => sort_criteria.clone(),
+                name => vec![(name, NullsPlacement::default())],
+            }).collect()
+        } else {
+            let index_criteria = index_criteria.into_iter().map(|name| (name, NullsPlacement::default()));
+            sort_criteria.into_iter().chain(index_criteria).collect()
+        };
+
+        // A placeholder search returns all the documents, the ranking rules that don't depend
+        // on the query words (e.g. `Asc`) must still be given all of them to sort.
+        if query_tree.is_none() && facet_candidates.is_none() {
+            facet_candidates = Some(self.documents_ids()?);
+        }
 
+        // The boosts split the candidates before any ranking rule,
+        // the ranking rules then order the documents of every boosted bucket.
         let mut criterion = None as Option<Box<dyn Criterion>>;
-        for name in self.index.criteria(&self.rtxn)? {
+        if !self.boosts.is_empty() {
+            let boost = Boost::initial(self, query_tree.take(), facet_candidates.take(), &self.boosts)?;
+            criterion = Some(Box::new(boost));
+        }
+
+        for (name, nulls) in criteria {
+            let counted_name = counters.as_ref().map(|_| name.clone());
             criterion = Some(match criterion.take() {
                 Some(father) => match name {
-                    Name::Typo => Box::new(Typo::new(self, father)),
+                    Name::Typo { max } => {
+                        Box::new(Typo::new(self, father, max).candidates_limit(self.typo_candidates_limit))
+                    },
                     Name::Words => Box::new(Words::new(self, father)),
-                    Name::Proximity => Box::new(Proximity::new(self, father)),
-                    Name::Asc(field) => Box::new(AscDesc::asc(&self.index, &self.rtxn, father, field)?),
-                    Name::Desc(field) => Box::new(AscDesc::desc(&self.index, &self.rtxn, father, field)?),
-                    _otherwise => father,
+                    Name::Proximity { max } => {
+                        Box::new(Proximity::new(self, father, max).documents_to_skip(&self.documents_to_skip))
+                    },
+                    Name::Attribute => {
+                        let attribute = Attribute::new(self, father, &self.attributes_weights);
+                        Box::new(attribute.documents_to_skip(&self.documents_to_skip))
+                    },
+                    Name::Asc(field) => Box::new(AscDesc::asc(&self.index, &self.rtxn, father, field, nulls)?),
+                    Name::Desc(field) => Box::new(AscDesc::desc(&self.index, &self.rtxn, father, field, nulls)?),
+                    Name::Random(seed) => Box::new(Random::new(self, father, seed)),
+                    Name::Decay { field, origin, scale } => {
+                        Box::new(Decay::new(self, &self.index, &self.rtxn, father, field, origin.0, scale.0)?)
+                    },
+                    Name::Diversify { field, max } => {
+                        Box::new(Diversify::new(self, &self.index, &self.rtxn, father, field, max)?)
+                    },
+                    Name::WordsPosition => Box::new(WordsPosition::new(self, father)),
+                    Name::Exactness => {
+                        let exactness = Exactness::new(self, father, &self.query_words, &self.attributes_weights);
+                        Box::new(exactness.documents_to_skip(&self.documents_to_skip))
+                    },
+                    Name::Custom(name) => self.custom_criterion(&name)?(self, father)?,
+                    _otherwise => {
+                        criterion = Some(father);
+                        continue;
+                    },
                 },
                 None => match name {
-                    Name::Typo => Box::new(Typo::initial(self, query_tree.take(), facet_candidates.take())),
+                    Name::Typo { max } => {
+                        let typo = Typo::initial(self, query_tree.take(), facet_candidates.take(), max);
+                        Box::new(typo.candidates_limit(self.typo_candidates_limit))
+                    },
                     Name::Words => Box::new(Words::initial(self, query_tree.take(), facet_candidates.take())),
-                    Name::Proximity => Box::new(Proximity::initial(self, query_tree.take(), facet_candidates.take())),
+                    Name::Proximity { max } => {
+                        Box::new(Proximity::initial(self, query_tree.take(), facet_candidates.take(), max))
+                    },
+                    Name::Attribute => {
+                        Box::new(Attribute::initial(self, query_tree.take(), facet_candidates.take(), &self.attributes_weights)?)
+                    },
                     Name::Asc(field) => {
-                        Box::new(AscDesc::initial_asc(&self.index, &self.rtxn, query_tree.take(), facet_candidates.take(), field)?)
+                        let candidates = self.searchable_candidates(query_tree.as_ref(), facet_candidates.take())?;
+                        Box::new(AscDesc::initial_asc(&self.index, &self.rtxn, query_tree.take(), candidates, field, nulls)?)
                     },
                     Name::Desc(field) => {
-                        Box::new(AscDesc::initial_desc(&self.index, &self.rtxn, query_tree.take(), facet_candidates.take(), field)?)
+                        let candidates = self.searchable_candidates(query_tree.as_ref(), facet_candidates.take())?;
+                        Box::new(AscDesc::initial_desc(&self.index, &self.rtxn, query_tree.take(), candidates, field, nulls)?)
+                    },
+                    Name::Random(seed) => {
+                        Box::new(Random::initial(self, query_tree.take(), facet_candidates.take(), seed)?)
+                    },
+                    Name::Decay { field, origin, scale } => {
+                        let candidates = facet_candidates.take();
+                        Box::new(Decay::initial(self, &self.index, &self.rtxn, query_tree.take(), candidates, field, origin.0, scale.0)?)
+                    },
+                    Name::Diversify { field, max } => {
+                        Box::new(Diversify::initial(self, &self.index, &self.rtxn, query_tree.take(), facet_candidates.take(), field, max)?)
+                    },
+                    Name::WordsPosition => {
+                        Box::new(WordsPosition::initial(self, query_tree.take(), facet_candidates.take())?)
+                    },
+                    Name::Exactness => {
+                        let words = &self.query_words;
+                        let weights = &self.attributes_weights;
+                        Box::new(Exactness::initial(self, query_tree.take(), facet_candidates.take(), words, weights)?)
+                    },
+                    Name::Custom(name) => {
+                        let initial = Initial::new(self, query_tree.take(), facet_candidates.take())?;
+                        self.custom_criterion(&name)?(self, Box::new(initial))?
                     },
                     _otherwise => continue,
                 },
             });
+
+            if let (Some(counters), Some(name)) = (counters.as_mut(), counted_name) {
+                let counts = Rc::new(RefCell::new(Vec::new()));
+                let inner = criterion.take().unwrap();
+                criterion = Some(Box::new(BucketCounter::new(self, inner, counts.clone())));
+                counters.push((name, counts));
+            }
         }
 
         match criterion {
-            Some(criterion) => Ok(Fetcher::new(self, criterion)),
+            Some(criterion) => Ok(Fetcher::new(self, criterion).documents_to_skip(&self.documents_to_skip)),
             None => Ok(Fetcher::initial(self, query_tree, facet_candidates)),
         }
     }
 }
 
+/// Builds the default ranking rules that only read the words of the context, the ones that
+/// read the facets of an `Index` (e.g. `Asc`) are skipped, it is used by the packed indexes.
+#[cfg(feature = "packed")]
+pub(crate) fn words_criteria<'t>(
+    ctx: &'t dyn Context,
+    query_tree: Option<Operation>,
+    query_words: &'t [String],
+    weights: &'t AttributesWeights,
+) -> anyhow::Result<Fetcher<'t>>
+{
+    if query_tree.is_none() {
+        return Ok(Fetcher::initial(ctx, None, Some(ctx.documents_ids()?)));
+    }
+
+    let criterion = Box::new(Typo::initial(ctx, query_tree, None, None));
+    let criterion = Box::new(Words::new(ctx, criterion));
+    let criterion = Box::new(Proximity::new(ctx, criterion, None));
+    let criterion = Box::new(Attribute::new(ctx, criterion, weights));
+    let criterion = Box::new(WordsPosition::new(ctx, criterion));
+    let criterion = Box::new(Exactness::new(ctx, criterion, query_words, weights));
+    Ok(Fetcher::new(ctx, criterion))
+}
+
+/// When the words pairs proximities are not indexed, the documents that contain both words
+/// are considered to contain them side by side, the proximity degrades to a co-occurrence.
+fn word_matches(word: &str, query: &str, prefix: bool) -> bool {
+    if prefix { word.starts_with(query) } else { word == query }
+}
+
+/// Returns the smallest proximity between the positions, as it is stored in
+/// the pairs proximities databases, `None` if the words are too far apart.
+fn min_pair_proximity(left: &RoaringBitmap, right: &RoaringBitmap) -> Option<u8> {
+    let mut min_proximity = None;
+    for lp in left {
+        for rp in right {
+            let proximity = pair_proximity(lp, rp);
+            if proximity >= 1 && proximity <= MAX_PAIR_PROXIMITY && min_proximity.map_or(true, |mp| proximity < mp) {
+                min_proximity = Some(proximity);
+            }
+        }
+    }
+    min_proximity
+}
+
+pub(crate) fn cooccurrence_docids(
+    left: Option<RoaringBitmap>,
+    right: Option<RoaringBitmap>,
+    proximity: u8,
+) -> Option<RoaringBitmap>
+{
+    match (left, right) {
+        (Some(mut left), Some(right)) if proximity == 1 => {
+            left.intersect_with(&right);
+            Some(left)
+        },
+        _ => None,
+    }
+}
+
+/// The number of times a word must be more frequent than the candidates of the other
+/// operations of an intersection for its bitmap to be probed instead of being deserialized.
+const PROBING_RATIO: u64 = 64;
+
 pub fn resolve_query_tree<'t>(
     ctx: &'t dyn Context,
     query_tree: &Operation,
@@ -183,15 +881,37 @@ pub fn resolve_query_tree<'t>(
 
         match query_tree {
             And(ops) => {
-                let mut ops = ops.iter().map(|op| {
-                    resolve_operation(ctx, op, cache, wdcache)
-                }).collect::<anyhow::Result<Vec<_>>>()?;
+                // The exact words are not resolved upfront, their documents counts are read
+                // without deserializing their bitmaps and the biggest of them are probed with
+                // the candidates of the other operations instead of being fully deserialized.
+                let mut words = Vec::new();
+                let mut resolved = Vec::new();
+                for op in ops {
+                    match op {
+                        Query(Query { prefix: false, kind: QueryKind::Exact { word, .. } }) => {
+                            match ctx.word_documents_count(word)? {
+                                Some(count) => words.push((count, word)),
+                                None => return Ok(RoaringBitmap::new()),
+                            }
+                        },
+                        op => resolved.push(resolve_operation(ctx, op, cache, wdcache)?),
+                    }
+                }
 
-                ops.sort_unstable_by_key(|cds| cds.len());
+                words.sort_unstable_by_key(|(count, _)| *count);
+                let mut words = words.into_iter();
+                if resolved.is_empty() {
+                    match words.next() {
+                        Some((_, word)) => resolved.push(ctx.word_docids(word)?.unwrap_or_default()),
+                        None => return Ok(RoaringBitmap::new()),
+                    }
+                }
+
+                resolved.sort_unstable_by_key(|cds| cds.len());
 
                 let mut candidates = RoaringBitmap::new();
                 let mut first_loop = true;
-                for docids in ops {
+                for docids in resolved {
                     if first_loop {
                         candidates = docids;
                         first_loop = false;
@@ -199,32 +919,21 @@ pub fn resolve_query_tree<'t>(
                         candidates.intersect_with(&docids);
                     }
                 }
-                Ok(candidates)
-            },
-            Consecutive(ops) => {
-                let mut candidates = RoaringBitmap::new();
-                let mut first_loop = true;
-                for slice in ops.windows(2) {
-                    match (&slice[0], &slice[1]) {
-                        (Operation::Query(left), Operation::Query(right)) => {
-                            match query_pair_proximity_docids(ctx, left, right, 1, wdcache)? {
-                                pair_docids if pair_docids.is_empty() => {
-                                    return Ok(RoaringBitmap::new())
-                                },
-                                pair_docids if first_loop => {
-                                    candidates = pair_docids;
-                                    first_loop = false;
-                                },
-                                pair_docids => {
-                                    candidates.intersect_with(&pair_docids);
-                                },
-                            }
-                        },
-                        _ => bail!("invalid consecutive query type"),
+
+                for (count, word) in words {
+                    if candidates.is_empty() {
+                        break;
+                    } else if count >= candidates.len().saturating_mul(PROBING_RATIO) {
+                        candidates = ctx.word_docids_within(word, &candidates)?.unwrap_or_default();
+                    } else {
+                        let docids = ctx.word_docids(word)?.unwrap_or_default();
+                        candidates.intersect_with(&docids);
                     }
                 }
+
                 Ok(candidates)
             },
+            Consecutive(ops) => resolve_phrase(ctx, ops, wdcache),
             Or(_, ops) => {
                 let mut candidates = RoaringBitmap::new();
                 for op in ops {
@@ -240,6 +949,114 @@ pub fn resolve_query_tree<'t>(
     resolve_operation(ctx, query_tree, cache, wdcache)
 }
 
+/// Returns the documents that contain the queries of the phrase side by side, in order.
+///
+/// The pairs proximities only guarantee that every pair of words is adjacent somewhere in
+/// a document, when the phrase is longer than a pair or when the pairs proximities are not
+/// indexed, the positions of the words in the candidates are checked to confirm the phrase.
+pub(crate) fn resolve_phrase(
+    ctx: &dyn Context,
+    ops: &[Operation],
+    wdcache: &mut WordDerivationsCache,
+) -> anyhow::Result<RoaringBitmap>
+{
+    let mut candidates = RoaringBitmap::new();
+    let mut first_loop = true;
+    for slice in ops.windows(2) {
+        match (&slice[0], &slice[1]) {
+            (Operation::Query(left), Operation::Query(right)) => {
+                match query_pair_proximity_docids(ctx, left, right, 1, wdcache)? {
+                    pair_docids if pair_docids.is_empty() => {
+                        return Ok(RoaringBitmap::new())
+                    },
+                    pair_docids if first_loop => {
+                        candidates = pair_docids;
+                        first_loop = false;
+                    },
+                    pair_docids => {
+                        candidates.intersect_with(&pair_docids);
+                    },
+                }
+            },
+            _ => bail!("invalid consecutive query type"),
+        }
+    }
+
+    if ops.len() > 2 || !ctx.proximity_database_enabled() {
+        candidates = verify_phrase_positions(ctx, ops, candidates, wdcache)?;
+    }
+
+    Ok(candidates)
+}
+
+/// Keeps the candidates in which the words of the queries follow each other in the same attribute.
+fn verify_phrase_positions(
+    ctx: &dyn Context,
+    ops: &[Operation],
+    candidates: RoaringBitmap,
+    wdcache: &mut WordDerivationsCache,
+) -> anyhow::Result<RoaringBitmap>
+{
+    // We retrieve the words that every query of the phrase can match.
+    let mut queries_words = Vec::with_capacity(ops.len());
+    for op in ops {
+        let query = match op {
+            Operation::Query(query) => query,
+            _ => bail!("invalid consecutive query type"),
+        };
+        queries_words.push(query_derived_words(ctx, query, wdcache)?);
+    }
+
+    let last = ops.len().saturating_sub(1) as u32;
+    let mut verified = RoaringBitmap::new();
+    for docid in candidates {
+        let words_positions = ctx.docid_words_positions(docid)?;
+        let positions = |words: &[String]| {
+            words.iter().filter_map(|word| words_positions.get(word)).fold(RoaringBitmap::new(), |acc, p| acc | p)
+        };
+
+        // The positions at which the phrase could start, every query
+        // reduces them to the ones where it appears at the right offset.
+        let mut starts = match queries_words.first() {
+            Some(words) => positions(words),
+            None => continue,
+        };
+        for (offset, words) in queries_words.iter().enumerate().skip(1) {
+            let offset = offset as u32;
+            let shifted = positions(words).iter().filter(|p| *p >= offset).map(|p| p - offset);
+            starts.intersect_with(&RoaringBitmap::from_sorted_iter(shifted));
+            if starts.is_empty() { break }
+        }
+
+        let in_one_attribute = |start: u32| position_attribute(start) == position_attribute(start + last);
+        if starts.iter().any(in_one_attribute) {
+            verified.insert(docid);
+        }
+    }
+
+    Ok(verified)
+}
+
+/// Returns the words of the index that the query can match.
+fn query_derived_words(
+    ctx: &dyn Context,
+    query: &Query,
+    wdcache: &mut WordDerivationsCache,
+) -> anyhow::Result<Vec<String>>
+{
+    let words = match &query.kind {
+        QueryKind::Exact { word, .. } if !query.prefix => vec![word.clone()],
+        QueryKind::Exact { word, .. } => {
+            word_derivations(word, true, 0, ctx.words_fst(), wdcache)?
+                .iter().map(|(word, _)| word.clone()).collect()
+        },
+        QueryKind::Tolerant { typo, word } => {
+            word_derivations(word, query.prefix, *typo, ctx.words_fst(), wdcache)?
+                .iter().map(|(word, _)| word.clone()).collect()
+        },
+    };
+    Ok(words)
+}
 
 fn all_word_pair_proximity_docids<T: AsRef<str>, U: AsRef<str>>(
     ctx: &dyn Context,
@@ -307,8 +1124,33 @@ fn query_pair_proximity_docids(
         return Ok(candidates);
     }
 
+    let left_prefix = left.prefix;
     let prefix = right.prefix;
     match (&left.kind, &right.kind) {
+        // The prefix is on the left side of the pair when the word being typed
+        // has been moved before another word of the query (e.g. optional words).
+        (QueryKind::Exact { word: left, .. }, QueryKind::Exact { word: right, .. }) if left_prefix && !prefix => {
+            if ctx.in_prefix_cache(&left) {
+                Ok(ctx.prefix_word_pair_proximity_docids(left.as_str(), right.as_str(), proximity)?.unwrap_or_default())
+            } else {
+                let l_words = word_derivations(&left, true, 0, ctx.words_fst(), wdcache)?;
+                all_word_pair_proximity_docids(ctx, l_words, &[(right, 0)], proximity)
+            }
+        },
+        (QueryKind::Exact { word: left, .. }, QueryKind::Tolerant { typo, word: right }) if left_prefix && !prefix => {
+            let r_words = word_derivations(&right, false, *typo, ctx.words_fst(), wdcache)?.to_owned();
+            if ctx.in_prefix_cache(&left) {
+                let mut docids = RoaringBitmap::new();
+                for (right, _) in r_words {
+                    let current_docids = ctx.prefix_word_pair_proximity_docids(left.as_ref(), right.as_ref(), proximity)?.unwrap_or_default();
+                    docids.union_with(&current_docids);
+                }
+                Ok(docids)
+            } else {
+                let l_words = word_derivations(&left, true, 0, ctx.words_fst(), wdcache)?;
+                all_word_pair_proximity_docids(ctx, l_words, &r_words, proximity)
+            }
+        },
         (QueryKind::Exact { word: left, .. }, QueryKind::Exact { word: right, .. }) => {
             if prefix && ctx.in_prefix_cache(&right) {
                 Ok(ctx.word_prefix_pair_proximity_docids(left.as_str(), right.as_str(), proximity)?.unwrap_or_default())
@@ -320,7 +1162,7 @@ fn query_pair_proximity_docids(
             }
         },
         (QueryKind::Tolerant { typo, word: left }, QueryKind::Exact { word: right, .. }) => {
-            let l_words = word_derivations(&left, false, *typo, ctx.words_fst(), wdcache)?.to_owned();
+            let l_words = word_derivations(&left, left_prefix, *typo, ctx.words_fst(), wdcache)?.to_owned();
             if prefix && ctx.in_prefix_cache(&right) {
                 let mut docids = RoaringBitmap::new();
                 for (left, _) in l_words {
@@ -340,7 +1182,7 @@ fn query_pair_proximity_docids(
             all_word_pair_proximity_docids(ctx, &[(left, 0)], &r_words, proximity)
         },
         (QueryKind::Tolerant { typo: l_typo, word: left }, QueryKind::Tolerant { typo: r_typo, word: right }) => {
-            let l_words = word_derivations(&left, false, *l_typo, ctx.words_fst(), wdcache)?.to_owned();
+            let l_words = word_derivations(&left, left_prefix, *l_typo, ctx.words_fst(), wdcache)?.to_owned();
             let r_words = word_derivations(&right, prefix, *r_typo, ctx.words_fst(), wdcache)?;
             all_word_pair_proximity_docids(ctx, &l_words, &r_words, proximity)
         },
@@ -362,6 +1204,9 @@ pub mod test {
         word_prefix_docids: HashMap<String, RoaringBitmap>,
         word_pair_proximity_docids: HashMap<(String, String, i32), RoaringBitmap>,
         word_prefix_pair_proximity_docids: HashMap<(String, String, i32), RoaringBitmap>,
+        prefix_word_pair_proximity_docids: HashMap<(String, String, i32), RoaringBitmap>,
+        word_position_docids: HashMap<(String, u32), RoaringBitmap>,
+        word_attribute_docids: HashMap<(String, u32), RoaringBitmap>,
     }
 
     impl<'a> Context for TestContext<'a> {
@@ -387,6 +1232,19 @@ pub mod test {
             Ok(self.word_prefix_pair_proximity_docids.get(&key).cloned())
         }
 
+        fn prefix_word_pair_proximity_docids(&self, left: &str, right: &str, proximity: u8) -> heed::Result<Option<RoaringBitmap>> {
+            let key = (left.to_string(), right.to_string(), proximity.into());
+            Ok(self.prefix_word_pair_proximity_docids.get(&key).cloned())
+        }
+
+        fn word_position_docids(&self, word: &str, bucket: u32) -> heed::Result<Option<RoaringBitmap>> {
+            Ok(self.word_position_docids.get(&(word.to_string(), bucket)).cloned())
+        }
+
+        fn word_attribute_docids(&self, word: &str, attribute: u32) -> heed::Result<Option<RoaringBitmap>> {
+            Ok(self.word_attribute_docids.get(&(word.to_string(), attribute)).cloned())
+        }
+
         fn words_fst<'t>(&self) -> &'t fst::Set<Cow<[u8]>> {
             &self.words_fst
         }
@@ -398,6 +1256,10 @@ pub mod test {
         fn docid_words_positions(&self, _docid: DocumentId) -> heed::Result<HashMap<String, RoaringBitmap>> {
             todo!()
         }
+
+        fn proximity_database_enabled(&self) -> bool {
+            true
+        }
     }
 
     impl<'a> Default for TestContext<'a> {
@@ -476,6 +1338,10 @@ pub mod test {
                 (s("this"), s("20"), 2) => word_pair_proximity_docids.get(&(s("this"), s("2020"), 2)).unwrap() | word_pair_proximity_docids.get(&(s("this"), s("2021"), 2)).unwrap(),
             };
 
+            let prefix_word_pair_proximity_docids = hashmap!{
+                (s("wor"), s("split"), 1) => word_pair_proximity_docids.get(&(s("word"), s("split"), 1)).unwrap() | word_pair_proximity_docids.get(&(s("world"), s("split"), 1)).unwrap(),
+            };
+
             let mut keys = word_docids.keys().collect::<Vec<_>>();
             keys.sort_unstable();
             let words_fst = fst::Set::from_iter(keys).unwrap().map_data(|v| Cow::Owned(v)).unwrap();
@@ -486,7 +1352,101 @@ pub mod test {
                 word_prefix_docids,
                 word_pair_proximity_docids,
                 word_prefix_pair_proximity_docids,
+                prefix_word_pair_proximity_docids,
+                word_position_docids: HashMap::new(),
+                word_attribute_docids: HashMap::new(),
             }
         }
     }
+
+    #[test]
+    fn left_prefix_pair_proximity() {
+        let context = TestContext::default();
+        let mut wdcache = WordDerivationsCache::new();
+
+        let left = Query { prefix: true, kind: QueryKind::exact(s("wor")) };
+        let right = Query { prefix: false, kind: QueryKind::exact(s("split")) };
+        let docids = query_pair_proximity_docids(&context, &left, &right, 1, &mut wdcache).unwrap();
+
+        let expected = context.word_pair_proximity_docids("word", "split", 1).unwrap().unwrap()
+            | context.word_pair_proximity_docids("world", "split", 1).unwrap().unwrap();
+        assert_eq!(docids, expected);
+    }
+
+    #[test]
+    fn intersection_probes_frequent_words() {
+        let mut context = TestContext::default();
+        let mut cache = HashMap::new();
+        let mut wdcache = WordDerivationsCache::new();
+
+        // "frequent" is much more frequent than "rare" and is probed with its documents.
+        let rare: RoaringBitmap = context.word_docids[&s("is")].iter().step_by(1000).collect();
+        let frequent = &context.word_docids[&s("is")] | &context.word_docids[&s("this")];
+        context.word_docids.insert(s("rare"), rare.clone());
+        context.word_docids.insert(s("frequent"), frequent);
+
+        let exact = |word: &str| Operation::Query(Query { prefix: false, kind: QueryKind::exact(s(word)) });
+        let query_tree = Operation::And(vec![exact("frequent"), exact("rare"), exact("is")]);
+        let docids = resolve_query_tree(&context, &query_tree, &mut cache, &mut wdcache).unwrap();
+        assert!(!docids.is_empty());
+        assert_eq!(docids, rare);
+
+        // A word that is not in the index empties the intersection.
+        let query_tree = Operation::And(vec![exact("frequent"), exact("missing")]);
+        let docids = resolve_query_tree(&context, &query_tree, &mut cache, &mut wdcache).unwrap();
+        assert!(docids.is_empty());
+    }
+
+    #[test]
+    fn searchable_attributes_pairs_and_positions() {
+        use crate::index::tests::TempIndex;
+
+        let content = &b"id,title,body\n0,hello big wide world,hello world\n1,hello world,nothing\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_searchable_fields(vec![s("title"), s("body")]);
+        });
+
+        let rtxn = index.read_txn().unwrap();
+        let title = index.fields_ids_map(&rtxn).unwrap().id("title").unwrap();
+        let bitmap = |ids: &[u32]| ids.iter().copied().collect::<RoaringBitmap>();
+
+        // The body of the document 0 contains the words next to each other.
+        let context = CriteriaBuilder::new(&rtxn, &index).unwrap();
+        assert_eq!(context.word_pair_proximity_docids("hello", "world", 1).unwrap(), Some(bitmap(&[0, 1])));
+        assert_eq!(context.word_pair_proximity_docids("hello", "world", 3).unwrap(), None);
+        assert_eq!(context.word_position_docids("world", 1).unwrap(), Some(bitmap(&[0, 1])));
+
+        // Only the positions in the title count when only the title is searched.
+        let mut context = CriteriaBuilder::new(&rtxn, &index).unwrap();
+        context.searchable_attributes(Some(vec![title]));
+        assert_eq!(context.word_pair_proximity_docids("hello", "world", 1).unwrap(), Some(bitmap(&[1])));
+        assert_eq!(context.word_pair_proximity_docids("hello", "world", 3).unwrap(), Some(bitmap(&[0])));
+        assert_eq!(context.word_position_docids("world", 1).unwrap(), Some(bitmap(&[1])));
+        assert_eq!(context.word_position_docids("world", 3).unwrap(), Some(bitmap(&[0])));
+
+        // The prefixes are expanded in the title only, the second time from the cache.
+        assert_eq!(context.word_prefix_docids("wi").unwrap(), Some(bitmap(&[0])));
+        assert_eq!(context.word_prefix_docids("wi").unwrap(), Some(bitmap(&[0])));
+        assert_eq!(context.word_prefix_docids("no").unwrap(), None);
+
+        // The cached documents are forgotten when the searched attributes change.
+        let body = index.fields_ids_map(&rtxn).unwrap().id("body").unwrap();
+        context.searchable_attributes(Some(vec![body]));
+        assert_eq!(context.word_prefix_docids("no").unwrap(), Some(bitmap(&[1])));
+    }
+
+    #[test]
+    fn ranking_scores() {
+        assert_eq!(ranking_score(&[]), 1.0);
+
+        // The second criterion splits the range of the bucket of the first one.
+        let typo = Rank::new(1, 1, ScoreDetail::Typo { typos: 1 });
+        let proximity = Rank::new(1, 3, ScoreDetail::Proximity { proximity: 1 });
+        let ranks = [typo.clone(), proximity.clone()];
+        assert_eq!(ranking_score(&ranks), 0.375);
+
+        // The criteria that don't rank by relevancy don't change the scores.
+        let ranks = [typo, Rank::unranked(ScoreDetail::Random), proximity];
+        assert_eq!(ranking_score(&ranks), 0.375);
+    }
 }
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::collections::btree_map::{self, BTreeMap};
 use std::collections::hash_map::HashMap;
 use std::mem::take;
@@ -6,19 +7,25 @@ use roaring::RoaringBitmap;
 use log::debug;
 
 use crate::{DocumentId, Position, search::{query_tree::QueryKind}};
+use crate::proximity::{add_query_proximities, MAX_PAIR_PROXIMITY, MAX_QUERY_PROXIMITY};
 use crate::search::query_tree::{maximum_proximity, Operation, Query};
 use crate::search::{build_dfa, WordDerivationsCache};
-use super::{Candidates, Criterion, CriterionResult, Context, query_docids, query_pair_proximity_docids, resolve_query_tree};
+use super::{is_skipped, ranks_with, Candidates, Criterion, CriterionResult, Context, Rank, ScoreDetail};
+use super::{query_docids, query_pair_proximity_docids, resolve_query_tree};
 
 pub struct Proximity<'t> {
     ctx: &'t dyn Context,
     query_tree: Option<(usize, Operation)>,
     proximity: u8,
+    /// The proximity from which the documents are returned in the same bucket.
+    max_proximity: Option<u8>,
     candidates: Candidates,
     bucket_candidates: RoaringBitmap,
+    ranks: Vec<Rank>,
     parent: Option<Box<dyn Criterion + 't>>,
     candidates_cache: HashMap<(Operation, u8), Vec<(Query, Query, RoaringBitmap)>>,
     plane_sweep_cache: Option<btree_map::IntoIter<u8, RoaringBitmap>>,
+    documents_to_skip: Option<&'t Cell<u64>>,
 }
 
 impl<'t> Proximity<'t> {
@@ -26,32 +33,46 @@ impl<'t> Proximity<'t> {
         ctx: &'t dyn Context,
         query_tree: Option<Operation>,
         candidates: Option<RoaringBitmap>,
+        max_proximity: Option<u8>,
     ) -> Self
     {
         Proximity {
             ctx,
             query_tree: query_tree.map(|op| (maximum_proximity(&op), op)),
             proximity: 0,
+            max_proximity,
             candidates: candidates.map_or_else(Candidates::default, Candidates::Allowed),
             bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
             parent: None,
             candidates_cache: HashMap::new(),
             plane_sweep_cache: None,
+            documents_to_skip: None,
         }
     }
 
-    pub fn new(ctx: &'t dyn Context, parent: Box<dyn Criterion + 't>) -> Self {
+    pub fn new(ctx: &'t dyn Context, parent: Box<dyn Criterion + 't>, max_proximity: Option<u8>) -> Self {
         Proximity {
             ctx,
             query_tree: None,
             proximity: 0,
+            max_proximity,
             candidates: Candidates::default(),
             bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
             parent: Some(parent),
             candidates_cache: HashMap::new(),
             plane_sweep_cache: None,
+            documents_to_skip: None,
         }
     }
+
+    /// Doesn't compute the proximities of the documents of the buckets of the parent
+    /// that will be skipped by the offset, see `Fetcher::skip_ranking`.
+    pub fn documents_to_skip(mut self, documents: &'t Cell<u64>) -> Self {
+        self.documents_to_skip = Some(documents);
+        self
+    }
 }
 
 impl<'t> Criterion for Proximity<'t> {
@@ -71,6 +92,7 @@ impl<'t> Criterion for Proximity<'t> {
                         query_tree: self.query_tree.take().map(|(_, qt)| qt),
                         candidates: Some(take(&mut self.candidates).into_inner()),
                         bucket_candidates: take(&mut self.bucket_candidates),
+                        ranks: ranks_with(&self.ranks, Rank::default()),
                     }));
                 },
                 (Some((max_prox, query_tree)), Allowed(candidates)) => {
@@ -78,11 +100,35 @@ impl<'t> Criterion for Proximity<'t> {
                         // reset state to (None, Forbidden(_))
                         self.query_tree = None;
                         self.candidates = Candidates::default();
+                    } else if self.ctx.deadline_reached() {
+                        // The time budget is spent, the remaining documents of the parent bucket are returned together.
+                        let rank = bucket_rank(self.proximity, *max_prox, self.max_proximity);
+                        let query_tree = query_tree.clone();
+                        let new_candidates = take(candidates);
+                        // reset state to (None, Forbidden(_))
+                        self.query_tree = None;
+                        self.candidates = Candidates::default();
+
+                        let bucket_candidates = match self.parent {
+                            Some(_) => take(&mut self.bucket_candidates),
+                            None => new_candidates.clone(),
+                        };
+
+                        return Ok(Some(CriterionResult {
+                            query_tree: Some(query_tree),
+                            candidates: Some(new_candidates),
+                            bucket_candidates,
+                            ranks: ranks_with(&self.ranks, rank),
+                        }));
                     } else {
                         let mut new_candidates = if candidates.len() <= 1000 {
                             if let Some(cache) = self.plane_sweep_cache.as_mut() {
                                 match cache.next() {
-                                    Some((p, candidates)) => {
+                                    Some((p, mut candidates)) => {
+                                        // The documents with a bigger proximity are returned in the same bucket.
+                                        if self.max_proximity.map_or(false, |max| p >= max) {
+                                            cache.by_ref().for_each(|(_, docids)| candidates.union_with(&docids));
+                                        }
                                         self.proximity = p;
                                         candidates
                                     },
@@ -105,18 +151,21 @@ impl<'t> Criterion for Proximity<'t> {
                                 continue
                             }
                         } else { // use set theory based algorithm
-                            resolve_candidates(
-                               self.ctx,
-                               &query_tree,
-                               self.proximity,
-                               &mut self.candidates_cache,
-                               wdcache,
-                           )?
+                            resolve_bucket_candidates(
+                                self.ctx,
+                                &query_tree,
+                                *max_prox,
+                                &mut self.proximity,
+                                self.max_proximity,
+                                &mut self.candidates_cache,
+                                wdcache,
+                            )?
                         };
 
                         new_candidates.intersect_with(&candidates);
                         candidates.difference_with(&new_candidates);
-                        self.proximity += 1;
+                        let rank = bucket_rank(self.proximity, *max_prox, self.max_proximity);
+                        self.proximity = self.proximity.saturating_add(1);
 
                         let bucket_candidates = match self.parent {
                             Some(_) => take(&mut self.bucket_candidates),
@@ -127,6 +176,7 @@ impl<'t> Criterion for Proximity<'t> {
                             query_tree: Some(query_tree.clone()),
                             candidates: Some(new_candidates),
                             bucket_candidates,
+                            ranks: ranks_with(&self.ranks, rank),
                         }));
                     }
                 },
@@ -135,17 +185,26 @@ impl<'t> Criterion for Proximity<'t> {
                         self.query_tree = None;
                         self.candidates = Candidates::default();
                     } else {
-                        let mut new_candidates = resolve_candidates(
+                        // Once the time budget is spent, the documents with a bigger proximity are returned in this bucket.
+                        let last_bucket_proximity = if self.ctx.deadline_reached() {
+                            Some(self.proximity)
+                        } else {
+                            self.max_proximity
+                        };
+                        let mut new_candidates = resolve_bucket_candidates(
                             self.ctx,
                             &query_tree,
-                            self.proximity,
+                            *max_prox,
+                            &mut self.proximity,
+                            last_bucket_proximity,
                             &mut self.candidates_cache,
                             wdcache,
                         )?;
 
                         new_candidates.difference_with(&candidates);
                         candidates.union_with(&new_candidates);
-                        self.proximity += 1;
+                        let rank = bucket_rank(self.proximity, *max_prox, self.max_proximity);
+                        self.proximity = self.proximity.saturating_add(1);
 
                         let bucket_candidates = match self.parent {
                             Some(_) => take(&mut self.bucket_candidates),
@@ -156,6 +215,7 @@ impl<'t> Criterion for Proximity<'t> {
                             query_tree: Some(query_tree.clone()),
                             candidates: Some(new_candidates),
                             bucket_candidates,
+                            ranks: ranks_with(&self.ranks, rank),
                         }));
                     }
                 },
@@ -165,13 +225,14 @@ impl<'t> Criterion for Proximity<'t> {
                         query_tree: None,
                         candidates: Some(candidates.clone()),
                         bucket_candidates: candidates,
+                        ranks: ranks_with(&self.ranks, Rank::default()),
                     }));
                 },
                 (None, Forbidden(_)) => {
                     match self.parent.as_mut() {
                         Some(parent) => {
                             match parent.next(wdcache)? {
-                                Some(CriterionResult { query_tree, candidates, bucket_candidates }) => {
+                                Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
                                     let candidates = match (&query_tree, candidates) {
                                         (_, Some(candidates)) => candidates,
                                         (Some(qt), None) => resolve_query_tree(self.ctx, qt, &mut HashMap::new(), wdcache)?,
@@ -184,10 +245,20 @@ impl<'t> Criterion for Proximity<'t> {
                                         self.bucket_candidates.union_with(&bucket_candidates);
                                     }
 
+                                    if is_skipped(self.documents_to_skip, &candidates) {
+                                        return Ok(Some(CriterionResult {
+                                            query_tree,
+                                            candidates: Some(candidates),
+                                            bucket_candidates: take(&mut self.bucket_candidates),
+                                            ranks: ranks_with(&ranks, Rank::default()),
+                                        }));
+                                    }
+
                                     self.query_tree = query_tree.map(|op| (maximum_proximity(&op), op));
                                     self.proximity = 0;
                                     self.candidates = Candidates::Allowed(candidates);
                                     self.plane_sweep_cache = None;
+                                    self.ranks = ranks;
                                 },
                                 None => return Ok(None),
                             }
@@ -200,6 +271,40 @@ impl<'t> Criterion for Proximity<'t> {
     }
 }
 
+/// Returns the rank of the bucket of documents with the given proximity.
+fn bucket_rank(proximity: u8, max_proximity: usize, last_bucket_proximity: Option<u8>) -> Rank {
+    let max_rank = last_bucket_proximity.map_or(max_proximity, |last| max_proximity.min(last as usize));
+    Rank::new(proximity as usize, max_rank, ScoreDetail::Proximity { proximity })
+}
+
+/// Returns the candidates with the given proximity.
+///
+/// If `proximity` reached `last_bucket_proximity` the candidates with a bigger proximity are
+/// also returned and `proximity` is moved to the maximum proximity.
+fn resolve_bucket_candidates(
+    ctx: &dyn Context,
+    query_tree: &Operation,
+    max_proximity: usize,
+    proximity: &mut u8,
+    last_bucket_proximity: Option<u8>,
+    cache: &mut HashMap<(Operation, u8), Vec<(Query, Query, RoaringBitmap)>>,
+    wdcache: &mut WordDerivationsCache,
+) -> anyhow::Result<RoaringBitmap>
+{
+    if last_bucket_proximity.map_or(false, |last| *proximity >= last) {
+        let mut candidates = RoaringBitmap::new();
+        let max_proximity = max_proximity.min(MAX_QUERY_PROXIMITY as usize) as u8;
+        for proximity in *proximity..=max_proximity {
+            let docids = resolve_candidates(ctx, query_tree, proximity, cache, wdcache)?;
+            candidates.union_with(&docids);
+        }
+        *proximity = max_proximity;
+        Ok(candidates)
+    } else {
+        resolve_candidates(ctx, query_tree, *proximity, cache, wdcache)
+    }
+}
+
 fn resolve_candidates<'t>(
     ctx: &'t dyn Context,
     query_tree: &Operation,
@@ -257,11 +362,9 @@ fn resolve_candidates<'t>(
             (0..=mana.min(left_max)).map(move |m| (m, mana - m))
         }
 
-        let pair_max_proximity = 7;
-
         let mut output = Vec::new();
 
-        for (pair_p, left_right_p) in pair_combinations(proximity, pair_max_proximity) {
+        for (pair_p, left_right_p) in pair_combinations(proximity, MAX_PAIR_PROXIMITY) {
             for (left_p, right_p) in pair_combinations(left_right_p, left_right_p) {
                 let left_key = (left.clone(), left_p);
                 if !cache.contains_key(&left_key) {
@@ -376,14 +479,16 @@ fn resolve_plane_sweep_candidates(
                     // if a pair overlap, meaning that they share at least a word, we return None
                     if rpos1 >= lpos2 { return None }
                     // if groups are in the good order (query order) we remove 1 to the proximity
-                    // the proximity is clamped to 7
+                    // the proximity is clamped to the maximum proximity of a pair
                     let pair_proximity = if i1 < i2 {
-                        (*lpos2 - *rpos1 - 1).min(7)
+                        (*lpos2 - *rpos1 - 1).min(MAX_PAIR_PROXIMITY as u32)
                     } else {
-                        (*lpos2 - *rpos1).min(7)
+                        (*lpos2 - *rpos1).min(MAX_PAIR_PROXIMITY as u32)
                     };
 
-                    proximity += pair_proximity as u8 + prox2;
+                    // the sum of the proximities saturates with very long queries
+                    let pair_proximity = add_query_proximities(pair_proximity as u8, *prox2);
+                    proximity = add_query_proximities(proximity, pair_proximity);
                 }
             }
 
@@ -558,7 +663,7 @@ fn resolve_plane_sweep_candidates(
             wdcache,
         )?;
         let best_proximity = positions.into_iter().min_by_key(|(_, proximity, _)| *proximity);
-        let best_proximity = best_proximity.map(|(_, proximity, _)| proximity).unwrap_or(7);
+        let best_proximity = best_proximity.map(|(_, proximity, _)| proximity).unwrap_or(MAX_PAIR_PROXIMITY);
         candidates.entry(best_proximity).or_insert_with(RoaringBitmap::new).insert(docid);
     }
 
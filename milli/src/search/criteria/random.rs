@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::mem::take;
+
+use log::debug;
+use roaring::RoaringBitmap;
+
+use crate::search::query_tree::Operation;
+use crate::search::WordDerivationsCache;
+use crate::DocumentId;
+use super::{ranks_with, resolve_query_tree, Criterion, CriterionResult, Context, Rank, ScoreDetail};
+
+/// Returns the documents of the parent buckets one by one in an order that only depends
+/// on the seed and the documents ids, the same seed always gives the same order.
+pub struct Random<'t> {
+    ctx: &'t dyn Context,
+    seed: u64,
+    query_tree: Option<Operation>,
+    candidates: std::vec::IntoIter<DocumentId>,
+    bucket_candidates: RoaringBitmap,
+    ranks: Vec<Rank>,
+    parent: Option<Box<dyn Criterion + 't>>,
+}
+
+impl<'t> Random<'t> {
+    pub fn initial(
+        ctx: &'t dyn Context,
+        query_tree: Option<Operation>,
+        candidates: Option<RoaringBitmap>,
+        seed: u64,
+    ) -> anyhow::Result<Self>
+    {
+        let candidates = match (&query_tree, candidates) {
+            (Some(qt), candidates) => {
+                let mut qt_candidates = resolve_query_tree(ctx, qt, &mut HashMap::new(), &mut WordDerivationsCache::new())?;
+                if let Some(candidates) = candidates {
+                    qt_candidates.intersect_with(&candidates);
+                }
+                qt_candidates
+            },
+            (None, Some(candidates)) => candidates,
+            (None, None) => ctx.documents_ids()?,
+        };
+
+        Ok(Random {
+            ctx,
+            seed,
+            query_tree,
+            candidates: shuffle(seed, &candidates).into_iter(),
+            bucket_candidates: candidates,
+            ranks: Vec::new(),
+            parent: None,
+        })
+    }
+
+    pub fn new(ctx: &'t dyn Context, parent: Box<dyn Criterion + 't>, seed: u64) -> Self {
+        Random {
+            ctx,
+            seed,
+            query_tree: None,
+            candidates: Vec::new().into_iter(),
+            bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
+            parent: Some(parent),
+        }
+    }
+}
+
+impl<'t> Criterion for Random<'t> {
+    #[logging_timer::time("Random::{}")]
+    fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+        debug!("Random iteration ({} candidates left)", self.candidates.len());
+
+        if let Some(docid) = self.candidates.next() {
+            let mut candidates = RoaringBitmap::new();
+            candidates.insert(docid);
+
+            return Ok(Some(CriterionResult {
+                query_tree: self.query_tree.clone(),
+                candidates: Some(candidates),
+                bucket_candidates: take(&mut self.bucket_candidates),
+                ranks: ranks_with(&self.ranks, Rank::unranked(ScoreDetail::Random)),
+            }));
+        }
+
+        let parent = match self.parent.as_mut() {
+            Some(parent) => parent,
+            None => return Ok(None),
+        };
+
+        match parent.next(wdcache)? {
+            Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
+                let candidates = match (&query_tree, candidates) {
+                    (_, Some(candidates)) => candidates,
+                    (Some(qt), None) => resolve_query_tree(self.ctx, qt, &mut HashMap::new(), wdcache)?,
+                    (None, None) => self.ctx.documents_ids()?,
+                };
+
+                if bucket_candidates.is_empty() {
+                    self.bucket_candidates.union_with(&candidates);
+                } else {
+                    self.bucket_candidates.union_with(&bucket_candidates);
+                }
+
+                self.query_tree = query_tree;
+                self.ranks = ranks;
+                let mut shuffled = shuffle(self.seed, &candidates).into_iter();
+                let candidates = shuffled.next().into_iter().collect();
+                self.candidates = shuffled;
+
+                Ok(Some(CriterionResult {
+                    query_tree: self.query_tree.clone(),
+                    candidates: Some(candidates),
+                    bucket_candidates: take(&mut self.bucket_candidates),
+                    ranks: ranks_with(&self.ranks, Rank::unranked(ScoreDetail::Random)),
+                }))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Orders the candidates by a hash of the seed and their documents ids.
+fn shuffle(seed: u64, candidates: &RoaringBitmap) -> Vec<DocumentId> {
+    let mut docids: Vec<_> = candidates.iter().collect();
+    docids.sort_unstable_by_key(|docid| (splitmix64(seed ^ splitmix64(*docid as u64)), *docid));
+    docids
+}
+
+/// The SplitMix64 finalizer, a fast and well distributed 64-bit mixing function.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deterministic_shuffle() {
+        let candidates: RoaringBitmap = (0..100).collect();
+
+        let first = shuffle(42, &candidates);
+        assert_eq!(first, shuffle(42, &candidates));
+        assert_ne!(first, shuffle(43, &candidates));
+        assert_ne!(first, candidates.iter().collect::<Vec<_>>());
+
+        // The relative order of the documents doesn't depend on the other candidates.
+        let subset: RoaringBitmap = (0..50).collect();
+        let expected: Vec<_> = first.iter().copied().filter(|id| *id < 50).collect();
+        assert_eq!(shuffle(42, &subset), expected);
+    }
+}
@@ -1,19 +1,25 @@
 use std::{borrow::Cow, collections::HashMap, mem::take};
 
-use anyhow::bail;
 use log::debug;
 use roaring::RoaringBitmap;
 
 use crate::search::query_tree::{maximum_typo, Operation, Query, QueryKind};
 use crate::search::{word_derivations, WordDerivationsCache};
-use super::{Candidates, Criterion, CriterionResult, Context, query_docids, query_pair_proximity_docids};
+use super::{ranks_with, Candidates, Criterion, CriterionResult, Context, Rank, ScoreDetail};
+use super::{query_docids, resolve_phrase};
 
 pub struct Typo<'t> {
     ctx: &'t dyn Context,
     query_tree: Option<(usize, Operation)>,
     number_typos: u8,
+    /// The number of typos from which the documents are returned in the same bucket.
+    max_typos: Option<u8>,
+    /// The number of documents from which the buckets with typos are no longer explored.
+    candidates_limit: Option<u64>,
+    returned_candidates: u64,
     candidates: Candidates,
     bucket_candidates: RoaringBitmap,
+    ranks: Vec<Rank>,
     parent: Option<Box<dyn Criterion + 't>>,
     candidates_cache: HashMap<(Operation, u8), RoaringBitmap>,
 }
@@ -23,30 +29,49 @@ impl<'t> Typo<'t> {
         ctx: &'t dyn Context,
         query_tree: Option<Operation>,
         candidates: Option<RoaringBitmap>,
+        max_typos: Option<u8>,
     ) -> Self
     {
         Typo {
             ctx,
             query_tree: query_tree.map(|op| (maximum_typo(&op), op)),
             number_typos: 0,
+            max_typos,
+            candidates_limit: None,
+            returned_candidates: 0,
             candidates: candidates.map_or_else(Candidates::default, Candidates::Allowed),
             bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
             parent: None,
             candidates_cache: HashMap::new(),
         }
     }
 
-    pub fn new(ctx: &'t dyn Context, parent: Box<dyn Criterion + 't>) -> Self {
+    pub fn new(ctx: &'t dyn Context, parent: Box<dyn Criterion + 't>, max_typos: Option<u8>) -> Self {
         Typo {
             ctx,
             query_tree: None,
             number_typos: 0,
+            max_typos,
+            candidates_limit: None,
+            returned_candidates: 0,
             candidates: Candidates::default(),
             bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
             parent: Some(parent),
             candidates_cache: HashMap::new(),
         }
     }
+
+    /// Stops exploring the buckets of documents with typos once this criterion returned
+    /// at least `limit` documents, the documents without typos are always returned.
+    ///
+    /// It avoids flooding the results with documents that only match the query with typos
+    /// when there already are enough documents that match it exactly.
+    pub fn candidates_limit(mut self, limit: Option<u64>) -> Self {
+        self.candidates_limit = limit;
+        self
+    }
 }
 
 impl<'t> Criterion for Typo<'t> {
@@ -56,39 +81,58 @@ impl<'t> Criterion for Typo<'t> {
         loop {
             debug!("Typo at iteration {} ({:?})", self.number_typos, self.candidates);
 
+            let limit_reached = self.number_typos > 0
+                && self.candidates_limit.map_or(false, |limit| self.returned_candidates >= limit);
+
             match (&mut self.query_tree, &mut self.candidates) {
                 (_, Allowed(candidates)) if candidates.is_empty() => {
                     return Ok(Some(CriterionResult {
                         query_tree: self.query_tree.take().map(|(_, qt)| qt),
                         candidates: Some(take(&mut self.candidates).into_inner()),
                         bucket_candidates: take(&mut self.bucket_candidates),
+                        ranks: ranks_with(&self.ranks, Rank::default()),
                     }));
                 },
                 (Some((max_typos, query_tree)), Allowed(candidates)) => {
-                    if self.number_typos as usize > *max_typos {
+                    if self.number_typos as usize > *max_typos || limit_reached {
                         self.query_tree = None;
                         self.candidates = Candidates::default();
-                    } else {
-                        let fst = self.ctx.words_fst();
-                        let new_query_tree = if self.number_typos < 2 {
-                            alterate_query_tree(&fst, query_tree.clone(), self.number_typos, wdcache)?
-                        } else if self.number_typos == 2 {
-                            *query_tree = alterate_query_tree(&fst, query_tree.clone(), self.number_typos, wdcache)?;
-                            query_tree.clone()
-                        } else {
-                            query_tree.clone()
+                    } else if self.ctx.deadline_reached() {
+                        // The time budget is spent, the remaining documents of the parent
+                        // bucket are returned together, they all match the query with any typo.
+                        let rank = bucket_rank(self.number_typos, *max_typos, self.max_typos);
+                        let new_query_tree = alterate_query_tree(self.ctx.words_fst(), query_tree.clone(), 2, wdcache)?;
+                        let new_candidates = take(candidates);
+                        self.query_tree = None;
+                        self.candidates = Candidates::default();
+                        self.returned_candidates += new_candidates.len();
+
+                        let bucket_candidates = match self.parent {
+                            Some(_) => take(&mut self.bucket_candidates),
+                            None => new_candidates.clone(),
                         };
 
-                        let mut new_candidates = resolve_candidates(
+                        return Ok(Some(CriterionResult {
+                            query_tree: Some(new_query_tree),
+                            candidates: Some(new_candidates),
+                            bucket_candidates,
+                            ranks: ranks_with(&self.ranks, rank),
+                        }));
+                    } else {
+                        let rank = bucket_rank(self.number_typos, *max_typos, self.max_typos);
+                        let (new_query_tree, mut new_candidates) = resolve_bucket(
                             self.ctx,
-                            &new_query_tree,
-                            self.number_typos,
+                            query_tree,
+                            *max_typos,
+                            &mut self.number_typos,
+                            self.max_typos,
                             &mut self.candidates_cache,
                             wdcache,
                         )?;
                         new_candidates.intersect_with(&candidates);
                         candidates.difference_with(&new_candidates);
                         self.number_typos += 1;
+                        self.returned_candidates += new_candidates.len();
 
                         let bucket_candidates = match self.parent {
                             Some(_) => take(&mut self.bucket_candidates),
@@ -99,40 +143,42 @@ impl<'t> Criterion for Typo<'t> {
                             query_tree: Some(new_query_tree),
                             candidates: Some(new_candidates),
                             bucket_candidates,
+                            ranks: ranks_with(&self.ranks, rank),
                         }));
                     }
                 },
                 (Some((max_typos, query_tree)), Forbidden(candidates)) => {
-                    if self.number_typos as usize > *max_typos {
+                    if self.number_typos as usize > *max_typos || limit_reached {
                         self.query_tree = None;
                         self.candidates = Candidates::default();
                     } else {
-                        let fst = self.ctx.words_fst();
-                        let new_query_tree = if self.number_typos < 2 {
-                            alterate_query_tree(&fst, query_tree.clone(), self.number_typos, wdcache)?
-                        } else if self.number_typos == 2 {
-                            *query_tree = alterate_query_tree(&fst, query_tree.clone(), self.number_typos, wdcache)?;
-                            query_tree.clone()
+                        let rank = bucket_rank(self.number_typos, *max_typos, self.max_typos);
+                        // Once the time budget is spent, the documents with more typos are returned in this bucket.
+                        let last_bucket_typos = if self.ctx.deadline_reached() {
+                            Some(self.number_typos)
                         } else {
-                            query_tree.clone()
+                            self.max_typos
                         };
-
-                        let mut new_candidates = resolve_candidates(
+                        let (new_query_tree, mut new_candidates) = resolve_bucket(
                             self.ctx,
-                            &new_query_tree,
-                            self.number_typos,
+                            query_tree,
+                            *max_typos,
+                            &mut self.number_typos,
+                            last_bucket_typos,
                             &mut self.candidates_cache,
                             wdcache,
                         )?;
                         new_candidates.difference_with(&candidates);
                         candidates.union_with(&new_candidates);
                         self.number_typos += 1;
+                        self.returned_candidates += new_candidates.len();
                         self.bucket_candidates.union_with(&new_candidates);
 
                         return Ok(Some(CriterionResult {
                             query_tree: Some(new_query_tree),
                             candidates: Some(new_candidates),
                             bucket_candidates: take(&mut self.bucket_candidates),
+                            ranks: ranks_with(&self.ranks, rank),
                         }));
                     }
                 },
@@ -142,17 +188,19 @@ impl<'t> Criterion for Typo<'t> {
                         query_tree: None,
                         candidates: Some(candidates.clone()),
                         bucket_candidates: candidates,
+                        ranks: ranks_with(&self.ranks, Rank::default()),
                     }));
                 },
                 (None, Forbidden(_)) => {
                     match self.parent.as_mut() {
                         Some(parent) => {
                             match parent.next(wdcache)? {
-                                Some(CriterionResult { query_tree, candidates, bucket_candidates }) => {
+                                Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
                                     self.query_tree = query_tree.map(|op| (maximum_typo(&op), op));
                                     self.number_typos = 0;
                                     self.candidates = candidates.map_or_else(Candidates::default, Candidates::Allowed);
                                     self.bucket_candidates.union_with(&bucket_candidates);
+                                    self.ranks = ranks;
                                 },
                                 None => return Ok(None),
                             }
@@ -165,6 +213,53 @@ impl<'t> Criterion for Typo<'t> {
     }
 }
 
+/// Returns the rank of the bucket of documents with `number_typos` typos.
+fn bucket_rank(number_typos: u8, max_typos: usize, last_bucket_typos: Option<u8>) -> Rank {
+    let max_rank = last_bucket_typos.map_or(max_typos, |last| max_typos.min(last as usize));
+    Rank::new(number_typos as usize, max_rank, ScoreDetail::Typo { typos: number_typos })
+}
+
+/// Returns the query tree and the candidates of the bucket of documents with `number_typos` typos.
+///
+/// If `number_typos` reached `last_bucket_typos` the documents with more typos are also
+/// returned in this bucket and `number_typos` is moved to the maximum number of typos.
+fn resolve_bucket(
+    ctx: &dyn Context,
+    query_tree: &mut Operation,
+    max_typos: usize,
+    number_typos: &mut u8,
+    last_bucket_typos: Option<u8>,
+    cache: &mut HashMap<(Operation, u8), RoaringBitmap>,
+    wdcache: &mut WordDerivationsCache,
+) -> anyhow::Result<(Operation, RoaringBitmap)>
+{
+    let fst = ctx.words_fst();
+
+    if last_bucket_typos.map_or(false, |last| *number_typos >= last) {
+        // The query tree with all the derivations allows to resolve any number of typos.
+        let new_query_tree = alterate_query_tree(&fst, query_tree.clone(), 2, wdcache)?;
+        let mut candidates = RoaringBitmap::new();
+        for typos in *number_typos..=max_typos as u8 {
+            let docids = resolve_candidates(ctx, &new_query_tree, typos, cache, wdcache)?;
+            candidates.union_with(&docids);
+        }
+        *number_typos = max_typos as u8;
+        return Ok((new_query_tree, candidates));
+    }
+
+    let new_query_tree = if *number_typos < 2 {
+        alterate_query_tree(&fst, query_tree.clone(), *number_typos, wdcache)?
+    } else if *number_typos == 2 {
+        *query_tree = alterate_query_tree(&fst, query_tree.clone(), *number_typos, wdcache)?;
+        query_tree.clone()
+    } else {
+        query_tree.clone()
+    };
+
+    let candidates = resolve_candidates(ctx, &new_query_tree, *number_typos, cache, wdcache)?;
+    Ok((new_query_tree, candidates))
+}
+
 /// Modify the query tree by replacing every tolerant query by an Or operation
 /// containing all of the corresponding exact words in the words FST. Each tolerant
 /// query will only be replaced by exact query with up to `number_typos` maximum typos.
@@ -243,30 +338,7 @@ fn resolve_candidates<'t>(
             And(ops) => {
                 mdfs(ctx, ops, number_typos, cache, wdcache)
             },
-            Consecutive(ops) => {
-                let mut candidates = RoaringBitmap::new();
-                let mut first_loop = true;
-                for slice in ops.windows(2) {
-                    match (&slice[0], &slice[1]) {
-                        (Operation::Query(left), Operation::Query(right)) => {
-                            match query_pair_proximity_docids(ctx, left, right, 1, wdcache)? {
-                                pair_docids if pair_docids.is_empty() => {
-                                    return Ok(RoaringBitmap::new())
-                                },
-                                pair_docids if first_loop => {
-                                    candidates = pair_docids;
-                                    first_loop = false;
-                                },
-                                pair_docids => {
-                                    candidates.intersect_with(&pair_docids);
-                                },
-                            }
-                        },
-                        _ => bail!("invalid consecutive query type"),
-                    }
-                }
-                Ok(candidates)
-            },
+            Consecutive(ops) => resolve_phrase(ctx, ops, wdcache),
             Or(_, ops) => {
                 let mut candidates = RoaringBitmap::new();
                 for op in ops {
@@ -345,7 +417,59 @@ mod test {
         let facet_candidates = None;
 
         let mut wdcache = WordDerivationsCache::new();
-        let mut criteria = Typo::initial(&context, query_tree, facet_candidates);
+        let mut criteria = Typo::initial(&context, query_tree, facet_candidates, None);
+
+        assert!(criteria.next(&mut wdcache).unwrap().is_none());
+    }
+
+    #[test]
+    fn initial_query_tree_max_typos() {
+        let context = TestContext::default();
+        let query_tree = Operation::Or(false, vec![
+            Operation::And(vec![
+                Operation::Query(Query { prefix: false, kind: QueryKind::exact("split".to_string()) }),
+                Operation::Query(Query { prefix: false, kind: QueryKind::exact("this".to_string()) }),
+                Operation::Query(Query { prefix: false, kind: QueryKind::tolerant(1, "world".to_string()) }),
+            ])
+        ]);
+
+        let mut wdcache = WordDerivationsCache::new();
+        let mut criteria = Typo::initial(&context, Some(query_tree), None, Some(0));
+
+        // The documents with zero and one typo are returned in the same bucket.
+        let candidates = context.word_docids("split").unwrap().unwrap()
+            & context.word_docids("this").unwrap().unwrap()
+            & (context.word_docids("world").unwrap().unwrap() | context.word_docids("word").unwrap().unwrap());
+
+        let result = criteria.next(&mut wdcache).unwrap().unwrap();
+        assert_eq!(result.candidates, Some(candidates.clone()));
+        assert_eq!(result.bucket_candidates, candidates);
+
+        assert!(criteria.next(&mut wdcache).unwrap().is_none());
+    }
+
+    #[test]
+    fn initial_query_tree_candidates_limit() {
+        let context = TestContext::default();
+        let query_tree = Operation::Or(false, vec![
+            Operation::And(vec![
+                Operation::Query(Query { prefix: false, kind: QueryKind::exact("split".to_string()) }),
+                Operation::Query(Query { prefix: false, kind: QueryKind::exact("this".to_string()) }),
+                Operation::Query(Query { prefix: false, kind: QueryKind::tolerant(1, "world".to_string()) }),
+            ])
+        ]);
+
+        let candidates = context.word_docids("split").unwrap().unwrap()
+            & context.word_docids("this").unwrap().unwrap()
+            & context.word_docids("world").unwrap().unwrap();
+
+        let mut wdcache = WordDerivationsCache::new();
+        let mut criteria = Typo::initial(&context, Some(query_tree), None, None)
+            .candidates_limit(Some(candidates.len()));
+
+        // There are enough documents without typos, the bucket with one typo is skipped.
+        let result = criteria.next(&mut wdcache).unwrap().unwrap();
+        assert_eq!(result.candidates, Some(candidates));
 
         assert!(criteria.next(&mut wdcache).unwrap().is_none());
     }
@@ -364,7 +488,7 @@ mod test {
         let facet_candidates = None;
 
         let mut wdcache = WordDerivationsCache::new();
-        let mut criteria = Typo::initial(&context, Some(query_tree), facet_candidates);
+        let mut criteria = Typo::initial(&context, Some(query_tree), facet_candidates, None);
 
         let candidates_1 = context.word_docids("split").unwrap().unwrap()
             & context.word_docids("this").unwrap().unwrap()
@@ -379,6 +503,7 @@ mod test {
             ])),
             candidates: Some(candidates_1.clone()),
             bucket_candidates: candidates_1,
+            ranks: vec![Rank { rank: 0, max_rank: 1, detail: ScoreDetail::Typo { typos: 0 } }],
         };
 
         assert_eq!(criteria.next(&mut wdcache).unwrap(), Some(expected_1));
@@ -401,6 +526,7 @@ mod test {
             ])),
             candidates: Some(candidates_2.clone()),
             bucket_candidates: candidates_2,
+            ranks: vec![Rank { rank: 1, max_rank: 1, detail: ScoreDetail::Typo { typos: 1 } }],
         };
 
         assert_eq!(criteria.next(&mut wdcache).unwrap(), Some(expected_2));
@@ -413,12 +539,13 @@ mod test {
         let facet_candidates = context.word_docids("earth").unwrap().unwrap();
 
         let mut wdcache = WordDerivationsCache::new();
-        let mut criteria = Typo::initial(&context, query_tree, Some(facet_candidates.clone()));
+        let mut criteria = Typo::initial(&context, query_tree, Some(facet_candidates.clone()), None);
 
         let expected = CriterionResult {
             query_tree: None,
             candidates: Some(facet_candidates.clone()),
             bucket_candidates: facet_candidates,
+            ranks: vec![Rank::default()],
         };
 
         // first iteration, returns the facet candidates
@@ -442,7 +569,7 @@ mod test {
         let facet_candidates = context.word_docids("earth").unwrap().unwrap();
 
         let mut wdcache = WordDerivationsCache::new();
-        let mut criteria = Typo::initial(&context, Some(query_tree), Some(facet_candidates.clone()));
+        let mut criteria = Typo::initial(&context, Some(query_tree), Some(facet_candidates.clone()), None);
 
         let candidates_1 = context.word_docids("split").unwrap().unwrap()
             & context.word_docids("this").unwrap().unwrap()
@@ -457,6 +584,7 @@ mod test {
             ])),
             candidates: Some(&candidates_1 & &facet_candidates),
             bucket_candidates: candidates_1 & &facet_candidates,
+            ranks: vec![Rank { rank: 0, max_rank: 1, detail: ScoreDetail::Typo { typos: 0 } }],
         };
 
         assert_eq!(criteria.next(&mut wdcache).unwrap(), Some(expected_1));
@@ -479,6 +607,7 @@ mod test {
             ])),
             candidates: Some(&candidates_2 & &facet_candidates),
             bucket_candidates: candidates_2 & &facet_candidates,
+            ranks: vec![Rank { rank: 1, max_rank: 1, detail: ScoreDetail::Typo { typos: 1 } }],
         };
 
         assert_eq!(criteria.next(&mut wdcache).unwrap(), Some(expected_2));
@@ -6,13 +6,16 @@ use roaring::RoaringBitmap;
 
 use crate::search::query_tree::Operation;
 use crate::search::WordDerivationsCache;
-use super::{resolve_query_tree, Criterion, CriterionResult, Context};
+use super::{ranks_with, resolve_query_tree, Criterion, CriterionResult, Context, Rank, ScoreDetail};
 
 pub struct Words<'t> {
     ctx: &'t dyn Context,
     query_trees: Vec<Operation>,
+    /// The rank of the worst query tree, the first one of `query_trees`.
+    max_rank: usize,
     candidates: Option<RoaringBitmap>,
     bucket_candidates: RoaringBitmap,
+    ranks: Vec<Rank>,
     parent: Option<Box<dyn Criterion + 't>>,
     candidates_cache: HashMap<(Operation, u8), RoaringBitmap>,
 }
@@ -24,11 +27,14 @@ impl<'t> Words<'t> {
         candidates: Option<RoaringBitmap>,
     ) -> Self
     {
+        let query_trees = query_tree.map(explode_query_tree).unwrap_or_default();
         Words {
             ctx,
-            query_trees: query_tree.map(explode_query_tree).unwrap_or_default(),
+            max_rank: query_trees.len().saturating_sub(1),
+            query_trees,
             candidates,
             bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
             parent: None,
             candidates_cache: HashMap::default(),
         }
@@ -38,8 +44,10 @@ impl<'t> Words<'t> {
         Words {
             ctx,
             query_trees: Vec::default(),
+            max_rank: 0,
             candidates: None,
             bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
             parent: Some(parent),
             candidates_cache: HashMap::default(),
         }
@@ -52,13 +60,42 @@ impl<'t> Criterion for Words<'t> {
         loop {
             debug!("Words at iteration {} ({:?})", self.query_trees.len(), self.candidates);
 
-            match (self.query_trees.pop(), &mut self.candidates) {
+            // The query trees are popped from the one that contains the most words.
+            let query_tree = self.query_trees.pop();
+            let matching_words = query_tree.as_ref().map_or(0, count_words);
+            let rank = Rank::new(
+                self.max_rank - self.query_trees.len(),
+                self.max_rank,
+                ScoreDetail::Words { matching_words },
+            );
+
+            match (query_tree, &mut self.candidates) {
                 (query_tree, Some(candidates)) if candidates.is_empty() => {
                     self.query_trees = Vec::new();
                     return Ok(Some(CriterionResult {
                         query_tree,
                         candidates: self.candidates.take(),
                         bucket_candidates: take(&mut self.bucket_candidates),
+                        ranks: ranks_with(&self.ranks, rank),
+                    }));
+                },
+                (Some(qt), candidates) if !self.query_trees.is_empty() && self.ctx.deadline_reached() => {
+                    // The time budget is spent, the documents matching any of the remaining
+                    // query trees are returned together, under the query tree they come from.
+                    let mut query_trees = take(&mut self.query_trees);
+                    query_trees.push(qt);
+                    let candidates = candidates.take();
+
+                    let bucket_candidates = match self.parent {
+                        Some(_) => take(&mut self.bucket_candidates),
+                        None => candidates.clone().unwrap_or_default(),
+                    };
+
+                    return Ok(Some(CriterionResult {
+                        query_tree: Some(Operation::or(true, query_trees)),
+                        candidates,
+                        bucket_candidates,
+                        ranks: ranks_with(&self.ranks, rank),
                     }));
                 },
                 (Some(qt), Some(candidates)) => {
@@ -75,6 +112,7 @@ impl<'t> Criterion for Words<'t> {
                         query_tree: Some(qt),
                         candidates: Some(found_candidates),
                         bucket_candidates,
+                        ranks: ranks_with(&self.ranks, rank),
                     }));
                 },
                 (Some(qt), None) => {
@@ -87,6 +125,7 @@ impl<'t> Criterion for Words<'t> {
                         query_tree: Some(qt),
                         candidates: None,
                         bucket_candidates,
+                        ranks: ranks_with(&self.ranks, rank),
                     }));
                 },
                 (None, Some(_)) => {
@@ -95,15 +134,18 @@ impl<'t> Criterion for Words<'t> {
                         query_tree: None,
                         candidates: candidates.clone(),
                         bucket_candidates: candidates.unwrap_or_default(),
+                        ranks: ranks_with(&self.ranks, Rank::default()),
                     }));
                 },
                 (None, None) => {
                     match self.parent.as_mut() {
                         Some(parent) => {
                             match parent.next(wdcache)? {
-                                Some(CriterionResult { query_tree, candidates, bucket_candidates }) => {
+                                Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
                                     self.query_trees = query_tree.map(explode_query_tree).unwrap_or_default();
+                                    self.max_rank = self.query_trees.len().saturating_sub(1);
                                     self.candidates = candidates;
+                                    self.ranks = ranks;
                                     self.bucket_candidates.union_with(&bucket_candidates);
                                 },
                                 None => return Ok(None),
@@ -117,6 +159,16 @@ impl<'t> Criterion for Words<'t> {
     }
 }
 
+/// Returns the number of query words the documents matching this query tree contain,
+/// the alternatives of a word (e.g. its synonyms) are counted once.
+fn count_words(query_tree: &Operation) -> u32 {
+    match query_tree {
+        Operation::And(ops) | Operation::Consecutive(ops) => ops.iter().map(count_words).sum(),
+        Operation::Or(_, ops) => ops.iter().map(count_words).max().unwrap_or(0),
+        Operation::Query(_) => 1,
+    }
+}
+
 fn explode_query_tree(query_tree: Operation) -> Vec<Operation> {
     match query_tree {
         Operation::Or(true, ops) => ops,
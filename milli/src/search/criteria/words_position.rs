@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::mem::take;
+
+use log::debug;
+use roaring::RoaringBitmap;
+
+use crate::proximity::MAX_POSITION_BUCKET;
+use crate::search::query_tree::Operation;
+use crate::search::WordDerivationsCache;
+use super::{query_derived_words, ranks_with, resolve_query_tree};
+use super::{Criterion, CriterionResult, Context, Rank, ScoreDetail};
+
+/// Returns the documents in which the query words appear at the start of an attribute first.
+///
+/// The documents are ranked by the position bucket of the query word that appears the earliest,
+/// only the first position of every word in the attributes is considered, see `position_bucket`.
+pub struct WordsPosition<'t> {
+    ctx: &'t dyn Context,
+    query_tree: Option<Operation>,
+    /// The last query tree along with the documents of every position bucket it matches.
+    positions_docids: Option<(Operation, Vec<RoaringBitmap>)>,
+    buckets: std::vec::IntoIter<(Rank, RoaringBitmap)>,
+    bucket_candidates: RoaringBitmap,
+    ranks: Vec<Rank>,
+    parent: Option<Box<dyn Criterion + 't>>,
+}
+
+impl<'t> WordsPosition<'t> {
+    pub fn initial(
+        ctx: &'t dyn Context,
+        query_tree: Option<Operation>,
+        candidates: Option<RoaringBitmap>,
+    ) -> anyhow::Result<Self>
+    {
+        let mut wdcache = WordDerivationsCache::new();
+        let candidates = match (&query_tree, candidates) {
+            (Some(qt), candidates) => {
+                let mut qt_candidates = resolve_query_tree(ctx, qt, &mut HashMap::new(), &mut wdcache)?;
+                if let Some(candidates) = candidates {
+                    qt_candidates.intersect_with(&candidates);
+                }
+                qt_candidates
+            },
+            (None, Some(candidates)) => candidates,
+            (None, None) => ctx.documents_ids()?,
+        };
+
+        let mut positions_docids = None;
+        let buckets = words_position_buckets(ctx, query_tree.as_ref(), &mut positions_docids, &candidates, &mut wdcache)?;
+
+        Ok(WordsPosition {
+            ctx,
+            query_tree,
+            positions_docids,
+            buckets: buckets.into_iter(),
+            bucket_candidates: candidates,
+            ranks: Vec::new(),
+            parent: None,
+        })
+    }
+
+    pub fn new(ctx: &'t dyn Context, parent: Box<dyn Criterion + 't>) -> Self {
+        WordsPosition {
+            ctx,
+            query_tree: None,
+            positions_docids: None,
+            buckets: Vec::new().into_iter(),
+            bucket_candidates: RoaringBitmap::new(),
+            ranks: Vec::new(),
+            parent: Some(parent),
+        }
+    }
+}
+
+impl<'t> Criterion for WordsPosition<'t> {
+    #[logging_timer::time("WordsPosition::{}")]
+    fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+        debug!("WordsPosition iteration ({} buckets left)", self.buckets.len());
+
+        if let Some((rank, candidates)) = self.buckets.next() {
+            return Ok(Some(CriterionResult {
+                query_tree: self.query_tree.clone(),
+                candidates: Some(candidates),
+                bucket_candidates: take(&mut self.bucket_candidates),
+                ranks: ranks_with(&self.ranks, rank),
+            }));
+        }
+
+        let parent = match self.parent.as_mut() {
+            Some(parent) => parent,
+            None => return Ok(None),
+        };
+
+        match parent.next(wdcache)? {
+            Some(CriterionResult { query_tree, candidates, bucket_candidates, ranks }) => {
+                let candidates = match (&query_tree, candidates) {
+                    (_, Some(candidates)) => candidates,
+                    (Some(qt), None) => resolve_query_tree(self.ctx, qt, &mut HashMap::new(), wdcache)?,
+                    (None, None) => self.ctx.documents_ids()?,
+                };
+
+                if bucket_candidates.is_empty() {
+                    self.bucket_candidates.union_with(&candidates);
+                } else {
+                    self.bucket_candidates.union_with(&bucket_candidates);
+                }
+
+                self.query_tree = query_tree;
+                self.ranks = ranks;
+                // The time budget is spent, the bucket is returned without being split.
+                let mut buckets = if self.ctx.deadline_reached() {
+                    vec![(Rank::default(), candidates)].into_iter()
+                } else {
+                    words_position_buckets(
+                        self.ctx,
+                        self.query_tree.as_ref(),
+                        &mut self.positions_docids,
+                        &candidates,
+                        wdcache,
+                    )?.into_iter()
+                };
+                let (rank, candidates) = buckets.next().unwrap_or_default();
+                self.buckets = buckets;
+
+                Ok(Some(CriterionResult {
+                    query_tree: self.query_tree.clone(),
+                    candidates: Some(candidates),
+                    bucket_candidates: take(&mut self.bucket_candidates),
+                    ranks: ranks_with(&self.ranks, rank),
+                }))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Splits the candidates into the non-empty buckets of documents in which the query words
+/// first appear in the same position bucket, in the order they must be returned, with their ranks.
+///
+/// The documents of the last bucket are the ones for which the positions are unknown.
+fn words_position_buckets(
+    ctx: &dyn Context,
+    query_tree: Option<&Operation>,
+    positions_docids: &mut Option<(Operation, Vec<RoaringBitmap>)>,
+    candidates: &RoaringBitmap,
+    wdcache: &mut WordDerivationsCache,
+) -> anyhow::Result<Vec<(Rank, RoaringBitmap)>>
+{
+    let query_tree = match query_tree {
+        Some(query_tree) => query_tree,
+        None => return Ok(vec![(Rank::default(), candidates.clone())]),
+    };
+
+    // The parent criteria often return the same query tree for many buckets.
+    if positions_docids.as_ref().map_or(true, |(qt, _)| qt != query_tree) {
+        let docids = query_positions_docids(ctx, query_tree, wdcache)?;
+        *positions_docids = Some((query_tree.clone(), docids));
+    }
+    let positions_docids = positions_docids.as_ref().map(|(_, docids)| docids.as_slice()).unwrap_or_default();
+
+    let max_rank = MAX_POSITION_BUCKET as usize + 1;
+    let mut buckets = Vec::new();
+    let mut remaining = candidates.clone();
+    for (bucket, docids) in positions_docids.iter().enumerate() {
+        if remaining.is_empty() { break }
+
+        let bucket_docids = &remaining & docids;
+        if !bucket_docids.is_empty() {
+            remaining.difference_with(&bucket_docids);
+            let detail = ScoreDetail::WordsPosition { position: bucket as u32 };
+            buckets.push((Rank::new(bucket, max_rank, detail), bucket_docids));
+        }
+    }
+
+    if !remaining.is_empty() {
+        let detail = ScoreDetail::WordsPosition { position: max_rank as u32 };
+        buckets.push((Rank::new(max_rank, max_rank, detail), remaining));
+    }
+
+    Ok(buckets)
+}
+
+/// Returns, for every position bucket, the documents in which one of the words of the query tree
+/// first appears in an attribute at a position of this bucket or of a previous one.
+///
+/// The candidates already match the query tree, the words of all its operations are considered.
+fn query_positions_docids(
+    ctx: &dyn Context,
+    query_tree: &Operation,
+    wdcache: &mut WordDerivationsCache,
+) -> anyhow::Result<Vec<RoaringBitmap>>
+{
+    use Operation::{And, Consecutive, Or, Query};
+
+    let buckets_count = MAX_POSITION_BUCKET as usize + 1;
+    match query_tree {
+        And(ops) | Consecutive(ops) | Or(_, ops) => {
+            let mut docids = vec![RoaringBitmap::new(); buckets_count];
+            for op in ops {
+                let op_docids = query_positions_docids(ctx, op, wdcache)?;
+                docids.iter_mut().zip(&op_docids).for_each(|(d, o)| d.union_with(o));
+            }
+            Ok(docids)
+        },
+        Query(query) => {
+            let mut docids = vec![RoaringBitmap::new(); buckets_count];
+            for word in query_derived_words(ctx, query, wdcache)? {
+                for (bucket, docids) in (0..).zip(docids.iter_mut()) {
+                    if let Some(word_docids) = ctx.word_position_docids(&word, bucket)? {
+                        docids.union_with(&word_docids);
+                    }
+                }
+            }
+
+            // The documents of a bucket are also part of the following buckets.
+            for bucket in 1..docids.len() {
+                let (previous, following) = docids.split_at_mut(bucket);
+                following[0].union_with(&previous[bucket - 1]);
+            }
+
+            Ok(docids)
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::index::tests::TempIndex;
+    use super::*;
+
+    #[test]
+    fn words_at_the_start_first() {
+        let content = &b"id,title\n0,a b c d e f g h i j hello world\n1,hello world\n2,the big world hello\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_criteria(vec!["words".into(), "wordsPosition".into()]);
+        });
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("hello world").score_details(true).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 2, 0]);
+
+        let details = &result.documents_score_details;
+        assert_eq!(details[0][1].detail, ScoreDetail::WordsPosition { position: 0 });
+        assert_eq!(details[1][1].detail, ScoreDetail::WordsPosition { position: 2 });
+        assert_eq!(details[2][1].detail, ScoreDetail::WordsPosition { position: 8 });
+    }
+}
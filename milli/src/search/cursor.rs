@@ -0,0 +1,191 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use ordered_float::OrderedFloat;
+use serde::{Serialize, Deserialize};
+
+use crate::criterion::NullsPlacement;
+use crate::facet::{CollationStrength, FacetValue};
+use crate::search::criteria::{Rank, ScoreDetail};
+use crate::DocumentId;
+
+/// The position of the last document of a page in the ranking, given to `Search::after`
+/// to get the documents that follow it without an offset.
+///
+/// It identifies the bucket of every ranking rule by what its documents have in common,
+/// not by its position, the next pages are therefore stable while the index is updated.
+/// The documents added to the buckets before the cursor are never returned.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    keys: Vec<BucketKey>,
+    docid: DocumentId,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum BucketKey {
+    /// The rank of a bucket of a relevancy ranking rule, the smallest first.
+    Rank(u32),
+    /// The total factor of a boosted bucket, the biggest first.
+    Factor(u32),
+    /// The value of the documents of a sorted bucket.
+    Value { value: Option<CursorValue>, ascending: bool, nulls_first: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum CursorValue {
+    String(String),
+    Float(f64),
+    Integer(i64),
+}
+
+impl From<FacetValue> for CursorValue {
+    fn from(value: FacetValue) -> CursorValue {
+        match value {
+            FacetValue::String(string) => CursorValue::String(string),
+            FacetValue::Float(float) => CursorValue::Float(float.into_inner()),
+            FacetValue::Integer(integer) => CursorValue::Integer(integer),
+        }
+    }
+}
+
+impl Cursor {
+    /// Returns the cursor of a document found in a bucket with these ranks, `None` when
+    /// the order of the documents is random and can't be reproduced from one page to the next.
+    pub(crate) fn new(ranks: &[Rank], docid: DocumentId) -> Option<Cursor> {
+        bucket_keys(ranks).map(|keys| Cursor { keys, docid })
+    }
+
+    /// The document the cursor was created from, the last one of its page.
+    pub fn docid(&self) -> DocumentId {
+        self.docid
+    }
+
+    /// Compares the bucket with these ranks to the bucket of the cursor, the
+    /// documents of the buckets that are `Less` have already been returned.
+    pub(crate) fn cmp_bucket(&self, ranks: &[Rank], collation: CollationStrength) -> anyhow::Result<Ordering> {
+        let keys = match bucket_keys(ranks) {
+            Some(keys) => keys,
+            None => bail!("Can't paginate with a cursor the documents sorted randomly."),
+        };
+
+        if keys.len() != self.keys.len() {
+            bail!("The cursor has been created with other ranking rules.");
+        }
+
+        for (key, cursor_key) in keys.iter().zip(&self.keys) {
+            match cmp_keys(key, cursor_key, collation)? {
+                Ordering::Equal => continue,
+                ordering => return Ok(ordering),
+            }
+        }
+
+        Ok(Ordering::Equal)
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        f.write_str(&json)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Cursor, Self::Err> {
+        serde_json::from_str(s).with_context(|| format!("invalid cursor {:?}", s))
+    }
+}
+
+fn bucket_keys(ranks: &[Rank]) -> Option<Vec<BucketKey>> {
+    ranks.iter().map(|rank| match &rank.detail {
+        ScoreDetail::Random | ScoreDetail::Diversify => None,
+        ScoreDetail::Boost { factor } => Some(BucketKey::Factor(*factor)),
+        ScoreDetail::Sort { ascending, nulls, value, .. } => Some(BucketKey::Value {
+            value: value.clone().map(CursorValue::from),
+            ascending: *ascending,
+            nulls_first: *nulls == NullsPlacement::First,
+        }),
+        _ => Some(BucketKey::Rank(rank.rank)),
+    }).collect()
+}
+
+/// Returns `Less` if the bucket of the first key is returned before the one of the second key.
+fn cmp_keys(a: &BucketKey, b: &BucketKey, collation: CollationStrength) -> anyhow::Result<Ordering> {
+    use BucketKey::{Factor, Rank, Value};
+
+    match (a, b) {
+        (Rank(a), Rank(b)) => Ok(a.cmp(b)),
+        (Factor(a), Factor(b)) => Ok(b.cmp(a)),
+        (Value { value: a, ascending, nulls_first }, Value { value: b, .. }) => {
+            let ordering = match (a, b) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) if *nulls_first => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) if *nulls_first => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => {
+                    let ordering = cmp_values(a, b, collation)?;
+                    if *ascending { ordering } else { ordering.reverse() }
+                },
+            };
+            Ok(ordering)
+        },
+        _ => bail!("The cursor has been created with other ranking rules."),
+    }
+}
+
+fn cmp_values(a: &CursorValue, b: &CursorValue, collation: CollationStrength) -> anyhow::Result<Ordering> {
+    use CursorValue::{Float, Integer, String};
+
+    match (a, b) {
+        (String(a), String(b)) => Ok(collation.collation_key(a).cmp(&collation.collation_key(b))),
+        (Float(a), Float(b)) => Ok(OrderedFloat(*a).cmp(&OrderedFloat(*b))),
+        (Integer(a), Integer(b)) => Ok(a.cmp(b)),
+        _ => bail!("The cursor has been created with other ranking rules."),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip() {
+        let detail = ScoreDetail::Sort {
+            field: "price".into(),
+            ascending: false,
+            nulls: NullsPlacement::Last,
+            value: Some(FacetValue::Integer(30)),
+        };
+        let ranks = vec![Rank::new(1, 3, ScoreDetail::Typo { typos: 1 }), Rank::unranked(detail)];
+        let cursor = Cursor::new(&ranks, 42).unwrap();
+        let parsed: Cursor = cursor.to_string().parse().unwrap();
+        assert_eq!(parsed, cursor);
+        assert_eq!(parsed.docid(), 42);
+    }
+
+    #[test]
+    fn buckets_order() {
+        let collation = CollationStrength::default();
+        let sort = |value: Option<i64>| Rank::unranked(ScoreDetail::Sort {
+            field: "price".into(),
+            ascending: false,
+            nulls: NullsPlacement::Last,
+            value: value.map(FacetValue::Integer),
+        });
+        let typo = |typos: u8| Rank::new(typos as usize, 2, ScoreDetail::Typo { typos });
+
+        let cursor = Cursor::new(&[typo(1), sort(Some(30))], 0).unwrap();
+        assert_eq!(cursor.cmp_bucket(&[typo(0), sort(Some(10))], collation).unwrap(), Ordering::Less);
+        assert_eq!(cursor.cmp_bucket(&[typo(1), sort(Some(40))], collation).unwrap(), Ordering::Less);
+        assert_eq!(cursor.cmp_bucket(&[typo(1), sort(Some(30))], collation).unwrap(), Ordering::Equal);
+        assert_eq!(cursor.cmp_bucket(&[typo(1), sort(Some(20))], collation).unwrap(), Ordering::Greater);
+        assert_eq!(cursor.cmp_bucket(&[typo(1), sort(None)], collation).unwrap(), Ordering::Greater);
+        assert!(cursor.cmp_bucket(&[typo(1)], collation).is_err());
+        assert!(Cursor::new(&[Rank::unranked(ScoreDetail::Random)], 0).is_none());
+    }
+}
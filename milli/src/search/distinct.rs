@@ -0,0 +1,275 @@
+use anyhow::Context;
+use heed::BytesDecode;
+use roaring::RoaringBitmap;
+use serde::{Serialize, Deserialize};
+
+use crate::facet::{FacetType, FacetValue};
+use crate::heed_codec::facet::{FieldDocIdFacetStringCodec, FieldDocIdFacetF64Codec, FieldDocIdFacetI64Codec};
+use crate::search::facet::FacetValues;
+use crate::{Index, FieldId, DocumentId};
+
+/// How the documents that have multiple values for the distinct attribute are grouped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DistinctMode {
+    /// A document belongs to the group of its smallest value, `["b", "a"]` is part of the `"a"` group.
+    FirstValue,
+    /// A document belongs to the group of its whole set of values, `["b", "a"]` and `["a", "b"]`
+    /// are part of the same group but `["a"]` is not.
+    AllValues,
+}
+
+impl Default for DistinctMode {
+    fn default() -> DistinctMode {
+        DistinctMode::FirstValue
+    }
+}
+
+/// The group of a document, only one document of each group is returned.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DistinctGroup {
+    /// The sorted values identifying the group, there is only one with the `FirstValue` mode.
+    Values(Vec<FacetValue>),
+    /// The document doesn't have a value for the distinct attribute, it is alone in its group.
+    Document(DocumentId),
+}
+
+/// Retrieves the group of the documents according to the values of a faceted field,
+/// the distinct attribute, see `DistinctMode` for the documents with multiple values.
+pub struct Distinct<'a> {
+    index: &'a Index,
+    rtxn: &'a heed::RoTxn<'a>,
+    field_id: FieldId,
+    facet_type: FacetType,
+    mode: DistinctMode,
+}
+
+impl<'a> Distinct<'a> {
+    /// Creates a `Distinct` for the given field, returns an error if the field isn't faceted.
+    ///
+    /// The documents with multiple values are grouped according to the distinct mode of the index.
+    pub fn new(rtxn: &'a heed::RoTxn, index: &'a Index, field_name: &str) -> anyhow::Result<Distinct<'a>> {
+        let faceted_fields = index.faceted_fields(rtxn)?;
+        let facet_type = *faceted_fields.get(field_name).with_context(|| {
+            format!("Can't use {:?} as a distinct attribute as it isn't a faceted field.", field_name)
+        })?;
+
+        let fields_ids_map = index.fields_ids_map(rtxn)?;
+        let field_id = fields_ids_map.id(field_name).with_context(|| {
+            format!("missing field name {:?} from the fields id map", field_name)
+        })?;
+
+        let mode = index.distinct_mode(rtxn)?;
+
+        Ok(Distinct { index, rtxn, field_id, facet_type, mode })
+    }
+
+    /// Creates a `Distinct` for the distinct attribute of the index, if there is one.
+    pub fn from_index(rtxn: &'a heed::RoTxn, index: &'a Index) -> anyhow::Result<Option<Distinct<'a>>> {
+        match index.distinct_attribute(rtxn)? {
+            Some(name) => Distinct::new(rtxn, index, name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the group of the given document.
+    pub fn group(&self, docid: DocumentId) -> heed::Result<DistinctGroup> {
+        let mut values = document_facet_values(self.index, self.rtxn, self.field_id, self.facet_type, docid)?;
+        if values.is_empty() {
+            return Ok(DistinctGroup::Document(docid));
+        }
+
+        values.sort_unstable();
+        values.dedup();
+        if self.mode == DistinctMode::FirstValue {
+            values.truncate(1);
+        }
+        Ok(DistinctGroup::Values(values))
+    }
+
+    /// Returns the number of candidates that belong to each of the given groups.
+    ///
+    /// The documents of the groups are not retrieved one by one, the candidates are intersected
+    /// with the documents ids of every value of the field, in ascending order:
+    ///  - with the `FirstValue` mode a document is counted in the group of the first value it is
+    ///    seen with, the iteration stops once the values of all the groups have been seen,
+    ///  - with the `AllValues` mode a document is counted in a group when it has all the values
+    ///    of the group and none of the other values of the field.
+    pub fn groups_counts(&self, groups: &[&DistinctGroup], candidates: &RoaringBitmap) -> heed::Result<Vec<u64>> {
+        let mut counts: Vec<_> = groups.iter().map(|group| match group {
+            DistinctGroup::Document(docid) => candidates.contains(*docid) as u64,
+            DistinctGroup::Values(_) => 0,
+        }).collect();
+
+        let mut remaining = groups.iter().filter(|g| matches!(g, DistinctGroup::Values(_))).count();
+        if remaining == 0 {
+            return Ok(counts);
+        }
+
+        let values = FacetValues::from_field_id(self.rtxn, self.index, self.field_id, self.facet_type)?;
+        let values = values.candidates(candidates.clone()).docids();
+
+        match self.mode {
+            DistinctMode::FirstValue => {
+                // The documents already counted in the group of a smaller value.
+                let mut seen = RoaringBitmap::new();
+                for result in values {
+                    let (value, docids) = result?;
+                    let first_seen = &docids - &seen;
+                    seen.union_with(&docids);
+
+                    for (group, count) in groups.iter().zip(&mut counts) {
+                        if let DistinctGroup::Values(values) = group {
+                            if values[0] == value {
+                                *count = first_seen.len();
+                                remaining -= 1;
+                            }
+                        }
+                    }
+
+                    if remaining == 0 { break }
+                }
+            },
+            DistinctMode::AllValues => {
+                // The documents with all the values of the group and the ones with other values.
+                let mut matching = vec![None; groups.len()];
+                let mut excluded = vec![RoaringBitmap::new(); groups.len()];
+                for result in values {
+                    let (value, docids) = result?;
+                    for (i, group) in groups.iter().enumerate() {
+                        if let DistinctGroup::Values(values) = group {
+                            if values.binary_search(&value).is_ok() {
+                                matching[i] = Some(match matching[i].take() {
+                                    Some(mut group_docids) => { group_docids.intersect_with(&docids); group_docids },
+                                    None => docids.clone(),
+                                });
+                            } else {
+                                excluded[i].union_with(&docids);
+                            }
+                        }
+                    }
+                }
+
+                for ((count, matching), excluded) in counts.iter_mut().zip(matching).zip(excluded) {
+                    if let Some(mut matching) = matching {
+                        matching.difference_with(&excluded);
+                        *count = matching.len();
+                    }
+                }
+            },
+        }
+
+        Ok(counts)
+    }
+}
+
+/// Returns the facet values of a document for the given field, in the database order.
+pub fn document_facet_values(
+    index: &Index,
+    rtxn: &heed::RoTxn,
+    field_id: FieldId,
+    facet_type: FacetType,
+    docid: DocumentId,
+) -> heed::Result<Vec<FacetValue>>
+{
+    fn fetch_facet_values<'t, KC, K: 't>(
+        index: &Index,
+        rtxn: &'t heed::RoTxn,
+        field_id: FieldId,
+        docid: DocumentId,
+    ) -> heed::Result<Vec<FacetValue>>
+    where
+        KC: BytesDecode<'t, DItem = (FieldId, DocumentId, K)>,
+        K: Into<FacetValue>,
+    {
+        let mut key_buffer = vec![field_id];
+        key_buffer.extend_from_slice(&docid.to_be_bytes());
+        let iter = index.field_id_docid_facet_values
+            .prefix_iter(rtxn, &key_buffer)?
+            .remap_key_type::<KC>();
+
+        let mut values = Vec::new();
+        for result in iter {
+            let ((_, _, value), ()) = result?;
+            values.push(value.into());
+        }
+
+        Ok(values)
+    }
+
+    match facet_type {
+        FacetType::String => fetch_facet_values::<FieldDocIdFacetStringCodec, _>(index, rtxn, field_id, docid),
+        FacetType::Float => fetch_facet_values::<FieldDocIdFacetF64Codec, _>(index, rtxn, field_id, docid),
+        FacetType::Integer => fetch_facet_values::<FieldDocIdFacetI64Codec, _>(index, rtxn, field_id, docid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heed::EnvOpenOptions;
+    use maplit::hashmap;
+
+    use crate::update::{IndexDocuments, Settings, UpdateFormat};
+    use super::*;
+
+    #[test]
+    fn groups_counts() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "tags".into() => "string".into() });
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = &br#"[
+            { "id": 0, "tags": ["b", "a"] },
+            { "id": 1, "tags": ["b"] },
+            { "id": 2, "tags": ["a"] },
+            { "id": 3, "tags": ["a", "b"] },
+            { "id": 4 }
+        ]"#[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Json);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let candidates = index.documents_ids(&rtxn).unwrap();
+        let values = |values: &[&str]| {
+            DistinctGroup::Values(values.iter().map(|v| FacetValue::String(v.to_string())).collect())
+        };
+
+        // The counts must be the ones found by retrieving the group of every document.
+        let distinct = Distinct::new(&rtxn, &index, "tags").unwrap();
+        let groups = vec![values(&["a"]), values(&["b"]), DistinctGroup::Document(4)];
+        let groups: Vec<_> = groups.iter().collect();
+        let counts = distinct.groups_counts(&groups, &candidates).unwrap();
+        assert_eq!(counts, vec![3, 1, 1]);
+        for (group, count) in groups.iter().zip(&counts) {
+            let expected = candidates.iter().filter(|d| &&distinct.group(*d).unwrap() == group).count();
+            assert_eq!(*count, expected as u64);
+        }
+
+        let candidates: RoaringBitmap = (1..5).collect();
+        let counts = distinct.groups_counts(&groups, &candidates).unwrap();
+        assert_eq!(counts, vec![2, 1, 1]);
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.set_distinct_mode(DistinctMode::AllValues);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let candidates = index.documents_ids(&rtxn).unwrap();
+        let distinct = Distinct::new(&rtxn, &index, "tags").unwrap();
+        let groups = vec![values(&["a", "b"]), values(&["a"]), values(&["b"])];
+        let groups: Vec<_> = groups.iter().collect();
+        let counts = distinct.groups_counts(&groups, &candidates).unwrap();
+        assert_eq!(counts, vec![2, 1, 1]);
+    }
+}
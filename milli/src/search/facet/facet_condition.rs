@@ -1,3 +1,4 @@
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::ops::Bound::{self, Included, Excluded};
@@ -7,16 +8,17 @@ use anyhow::Context;
 use either::Either;
 use heed::types::{ByteSlice, DecodeIgnore};
 use log::debug;
-use num_traits::Bounded;
+use num_traits::{Bounded, ToPrimitive};
 use pest::error::{Error as PestError, ErrorVariant};
 use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use roaring::RoaringBitmap;
 
-use crate::facet::FacetType;
+use crate::facet::{FacetStringNormalization, FacetType, FacetValue};
 use crate::heed_codec::facet::FacetValueStringCodec;
 use crate::heed_codec::facet::{FacetLevelValueI64Codec, FacetLevelValueF64Codec};
-use crate::{Index, FieldId, FieldsIdsMap, CboRoaringBitmapCodec};
+use crate::search::distinct::document_facet_values;
+use crate::{Index, FieldId, FieldsIdsMap, CboRoaringBitmapCodec, CboRoaringBitmapLenCodec};
 
 use super::FacetRange;
 use super::parser::Rule;
@@ -25,6 +27,10 @@ use super::parser::{PREC_CLIMBER, FilterParser};
 use self::FacetCondition::*;
 use self::FacetNumberOperator::*;
 
+/// The number of candidates under which the remaining conditions of an intersection are
+/// checked against the facet values of these candidates instead of being entirely resolved.
+const FEW_CANDIDATES: u64 = 1_000;
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum FacetNumberOperator<T> {
     GreaterThan(T),
@@ -52,6 +58,33 @@ impl<T> FacetNumberOperator<T> {
     }
 }
 
+impl<T: PartialOrd> FacetNumberOperator<T> {
+    /// Whether the given values of a document satisfy this operator, like the documents
+    /// ids, a document without value never satisfies the operator.
+    fn matches_any(&self, mut values: impl Iterator<Item = T>) -> bool {
+        match self {
+            NotEqual(x) => {
+                let mut values = values.peekable();
+                values.peek().is_some() && values.all(|value| &value != x)
+            },
+            operator => values.any(|value| operator.matches(&value)),
+        }
+    }
+
+    /// Whether the given value satisfies this operator.
+    fn matches(&self, value: &T) -> bool {
+        match self {
+            GreaterThan(x)        => value > x,
+            GreaterThanOrEqual(x) => value >= x,
+            Equal(x)              => value == x,
+            NotEqual(x)           => value != x,
+            LowerThan(x)          => value < x,
+            LowerThanOrEqual(x)   => value <= x,
+            Between(x, y)         => value >= x && value <= y,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum FacetStringOperator {
     Equal(String),
@@ -59,8 +92,14 @@ pub enum FacetStringOperator {
 }
 
 impl FacetStringOperator {
+    #[allow(dead_code)]
     fn equal(s: &str) -> Self {
-        FacetStringOperator::Equal(s.to_lowercase())
+        FacetStringOperator::normalized_equal(s, FacetStringNormalization::default())
+    }
+
+    /// The value is normalized like the facet values of the field it is compared to.
+    fn normalized_equal(s: &str, normalization: FacetStringNormalization) -> Self {
+        FacetStringOperator::Equal(normalization.normalize(s))
     }
 
     #[allow(dead_code)]
@@ -81,6 +120,9 @@ pub enum FacetCondition {
     OperatorI64(FieldId, FacetNumberOperator<i64>),
     OperatorF64(FieldId, FacetNumberOperator<f64>),
     OperatorString(FieldId, FacetStringOperator),
+    /// An operator on the numeric primary key of the documents, resolved using the
+    /// external documents ids, the primary key doesn't need to be faceted.
+    PrimaryKey(FacetNumberOperator<i64>),
     Or(Box<Self>, Box<Self>),
     And(Box<Self>, Box<Self>),
 }
@@ -127,6 +169,31 @@ fn get_field_id_facet_type<'a>(
     Ok((field_id, facet_type))
 }
 
+/// Whether the comparison is done on the primary key while it isn't faceted, in which
+/// case it is evaluated using the external documents ids instead of the facet databases.
+fn is_unfaceted_primary_key(
+    fields_ids_map: &FieldsIdsMap,
+    faceted_fields: &HashMap<FieldId, FacetType>,
+    primary_key: Option<&str>,
+    pair: &Pair<Rule>,
+) -> bool
+{
+    match pair.as_rule() {
+        Rule::greater | Rule::geq | Rule::eq | Rule::neq |
+        Rule::leq | Rule::less | Rule::between => (),
+        _ => return false,
+    }
+
+    let key = match pair.clone().into_inner().next() {
+        Some(key) => key,
+        None => return false,
+    };
+
+    primary_key == Some(key.as_str()) && fields_ids_map
+        .id(key.as_str())
+        .map_or(true, |fid| !faceted_fields.contains_key(&fid))
+}
+
 fn pest_parse<T>(pair: Pair<Rule>) -> Result<T, pest::error::Error<Rule>>
 where T: FromStr,
       T::Err: ToString,
@@ -156,6 +223,7 @@ impl FacetCondition {
         fn facet_condition(
             fields_ids_map: &FieldsIdsMap,
             faceted_fields: &HashMap<String, FacetType>,
+            normalizations: &HashMap<FieldId, FacetStringNormalization>,
             key: &str,
             value: &str,
         ) -> anyhow::Result<FacetCondition>
@@ -172,7 +240,10 @@ impl FacetCondition {
             };
 
             let operator = match ftype {
-                FacetType::String => OperatorString(fid, FacetStringOperator::equal(value)),
+                FacetType::String => {
+                    let normalization = normalizations.get(&fid).copied().unwrap_or_default();
+                    OperatorString(fid, FacetStringOperator::normalized_equal(value, normalization))
+                },
                 FacetType::Float => OperatorF64(fid, FacetNumberOperator::Equal(value.parse()?)),
                 FacetType::Integer => OperatorI64(fid, FacetNumberOperator::Equal(value.parse()?)),
             };
@@ -182,6 +253,7 @@ impl FacetCondition {
 
         let fields_ids_map = index.fields_ids_map(rtxn)?;
         let faceted_fields = index.faceted_fields(rtxn)?;
+        let normalizations = index.facet_string_normalizations_ids(rtxn)?;
         let mut ands = None;
 
         for either in array {
@@ -192,7 +264,7 @@ impl FacetCondition {
                         let mut iter = rule.as_ref().splitn(2, ':');
                         let key = iter.next().context("missing facet condition key")?;
                         let value = iter.next().context("missing facet condition value")?;
-                        let condition = facet_condition(&fields_ids_map, &faceted_fields, key, value)?;
+                        let condition = facet_condition(&fields_ids_map, &faceted_fields, &normalizations, key, value)?;
                         ors = match ors.take() {
                             Some(ors) => Some(Or(Box::new(ors), Box::new(condition))),
                             None => Some(condition),
@@ -210,7 +282,7 @@ impl FacetCondition {
                     let mut iter = rule.as_ref().splitn(2, ':');
                     let key = iter.next().context("missing facet condition key")?;
                     let value = iter.next().context("missing facet condition value")?;
-                    let condition = facet_condition(&fields_ids_map, &faceted_fields, key, value)?;
+                    let condition = facet_condition(&fields_ids_map, &faceted_fields, &normalizations, key, value)?;
                     ands = match ands.take() {
                         Some(ands) => Some(And(Box::new(ands), Box::new(condition))),
                         None => Some(condition),
@@ -230,29 +302,34 @@ impl FacetCondition {
     {
         let fields_ids_map = index.fields_ids_map(rtxn)?;
         let faceted_fields = index.faceted_fields_ids(rtxn)?;
+        let normalizations = index.facet_string_normalizations_ids(rtxn)?;
+        let primary_key = index.primary_key(rtxn)?;
         let lexed = FilterParser::parse(Rule::prgm, expression)?;
-        FacetCondition::from_pairs(&fields_ids_map, &faceted_fields, lexed)
+        FacetCondition::from_pairs(&fields_ids_map, &faceted_fields, &normalizations, primary_key, lexed)
     }
 
     fn from_pairs(
         fim: &FieldsIdsMap,
         ff: &HashMap<FieldId, FacetType>,
+        fsn: &HashMap<FieldId, FacetStringNormalization>,
+        pk: Option<&str>,
         expression: Pairs<Rule>,
     ) -> anyhow::Result<Self>
     {
         PREC_CLIMBER.climb(
             expression,
             |pair: Pair<Rule>| match pair.as_rule() {
+                _ if is_unfaceted_primary_key(fim, ff, pk, &pair) => Ok(Self::primary_key(pair)?),
                 Rule::greater => Ok(Self::greater_than(fim, ff, pair)?),
                 Rule::geq => Ok(Self::greater_than_or_equal(fim, ff, pair)?),
-                Rule::eq => Ok(Self::equal(fim, ff, pair)?),
-                Rule::neq => Ok(Self::equal(fim, ff, pair)?.negate()),
+                Rule::eq => Ok(Self::equal(fim, ff, fsn, pair)?),
+                Rule::neq => Ok(Self::equal(fim, ff, fsn, pair)?.negate()),
                 Rule::leq => Ok(Self::lower_than_or_equal(fim, ff, pair)?),
                 Rule::less => Ok(Self::lower_than(fim, ff, pair)?),
                 Rule::between => Ok(Self::between(fim, ff, pair)?),
-                Rule::not => Ok(Self::from_pairs(fim, ff, pair.into_inner())?.negate()),
-                Rule::prgm => Self::from_pairs(fim, ff, pair.into_inner()),
-                Rule::term => Self::from_pairs(fim, ff, pair.into_inner()),
+                Rule::not => Ok(Self::from_pairs(fim, ff, fsn, pk, pair.into_inner())?.negate()),
+                Rule::prgm => Self::from_pairs(fim, ff, fsn, pk, pair.into_inner()),
+                Rule::term => Self::from_pairs(fim, ff, fsn, pk, pair.into_inner()),
                 _ => unreachable!(),
             },
             |lhs: anyhow::Result<Self>, op: Pair<Rule>, rhs: anyhow::Result<Self>| {
@@ -276,11 +353,34 @@ impl FacetCondition {
                 (a, Some(b)) => Or(Box::new(OperatorF64(fid, a)), Box::new(OperatorF64(fid, b))),
             },
             OperatorString(fid, op) => OperatorString(fid, op.negate()),
+            PrimaryKey(op) => match op.negate() {
+                (op, None) => PrimaryKey(op),
+                (a, Some(b)) => Or(Box::new(PrimaryKey(a)), Box::new(PrimaryKey(b))),
+            },
             Or(a, b) => And(Box::new(a.negate()), Box::new(b.negate())),
             And(a, b) => Or(Box::new(a.negate()), Box::new(b.negate())),
         }
     }
 
+    fn primary_key(item: Pair<Rule>) -> Result<FacetCondition, PestError<Rule>> {
+        let rule = item.as_rule();
+        let mut items = item.into_inner();
+        // lexing ensures that we at least have a key
+        let _key = items.next().unwrap();
+        let value = items.next().unwrap();
+        let operator = match rule {
+            Rule::greater => GreaterThan(pest_parse(value)?),
+            Rule::geq => GreaterThanOrEqual(pest_parse(value)?),
+            Rule::eq => Equal(pest_parse(value)?),
+            Rule::neq => NotEqual(pest_parse(value)?),
+            Rule::leq => LowerThanOrEqual(pest_parse(value)?),
+            Rule::less => LowerThan(pest_parse(value)?),
+            Rule::between => Between(pest_parse(value)?, pest_parse(items.next().unwrap())?),
+            _ => unreachable!(),
+        };
+        Ok(PrimaryKey(operator))
+    }
+
     fn between(
         fields_ids_map: &FieldsIdsMap,
         faceted_fields: &HashMap<FieldId, FacetType>,
@@ -317,6 +417,7 @@ impl FacetCondition {
     fn equal(
         fields_ids_map: &FieldsIdsMap,
         faceted_fields: &HashMap<FieldId, FacetType>,
+        normalizations: &HashMap<FieldId, FacetStringNormalization>,
         item: Pair<Rule>,
     ) -> anyhow::Result<FacetCondition>
     {
@@ -326,7 +427,11 @@ impl FacetCondition {
         match ftype {
             FacetType::Integer => Ok(OperatorI64(fid, Equal(pest_parse(value)?))),
             FacetType::Float => Ok(OperatorF64(fid, Equal(pest_parse(value)?))),
-            FacetType::String => Ok(OperatorString(fid, FacetStringOperator::equal(value.as_str()))),
+            FacetType::String => {
+                let normalization = normalizations.get(&fid).copied().unwrap_or_default();
+                let operator = FacetStringOperator::normalized_equal(value.as_str(), normalization);
+                Ok(OperatorString(fid, operator))
+            },
         }
     }
 
@@ -553,6 +658,61 @@ impl FacetCondition {
         }
     }
 
+    /// Estimates the number of documents that satisfy the operator from the bounds of the
+    /// values of the field, assuming that the values are uniformly distributed.
+    fn estimate_number_operator<'t, T: 't, KC>(
+        rtxn: &'t heed::RoTxn,
+        index: &Index,
+        db: heed::Database<ByteSlice, CboRoaringBitmapCodec>,
+        field_id: FieldId,
+        operator: FacetNumberOperator<T>,
+    ) -> anyhow::Result<u64>
+    where
+        T: Copy + PartialOrd + Bounded + ToPrimitive,
+        KC: heed::BytesDecode<'t, DItem = (u8, u8, T, T)>,
+        KC: for<'x> heed::BytesEncode<'x, EItem = (u8, u8, T, T)>,
+    {
+        let faceted_count = index.faceted_documents_ids(rtxn, field_id)?.len();
+        let equal_count = |value: T| -> heed::Result<u64> {
+            let db = db.remap_types::<KC, CboRoaringBitmapLenCodec>();
+            Ok(db.get(rtxn, &(field_id, 0, value, value))?.unwrap_or(0))
+        };
+
+        let (left, right) = match operator {
+            Equal(val) => return Ok(equal_count(val)?),
+            NotEqual(val) => return Ok(faceted_count.saturating_sub(equal_count(val)?)),
+            GreaterThan(val) | GreaterThanOrEqual(val) => (Some(val), None),
+            LowerThan(val) | LowerThanOrEqual(val) => (None, Some(val)),
+            Between(left, right) => (Some(left), Some(right)),
+        };
+
+        // The smallest and the biggest values of the field are the first and last keys of the level 0.
+        let db = db.remap_types::<KC, DecodeIgnore>();
+        let level_zero_value = |result: Option<((u8, u8, T, T), ())>| {
+            result.and_then(|((id, level, value, _), _)| {
+                if id == field_id && level == 0 { value.to_f64() } else { None }
+            })
+        };
+        let min = level_zero_value(db.get_greater_than_or_equal_to(rtxn, &(field_id, 0, T::min_value(), T::min_value()))?);
+        let max = level_zero_value(db.get_lower_than_or_equal_to(rtxn, &(field_id, 0, T::max_value(), T::max_value()))?);
+        let (min, max) = match min.zip(max) {
+            Some(bounds) => bounds,
+            None => return Ok(0),
+        };
+
+        let left = left.and_then(|v| v.to_f64()).map_or(min, |v| v.max(min));
+        let right = right.and_then(|v| v.to_f64()).map_or(max, |v| v.min(max));
+        let ratio = if left > right {
+            0.0
+        } else if max > min {
+            (right - left) / (max - min)
+        } else {
+            1.0
+        };
+
+        Ok((faceted_count as f64 * ratio.min(1.0)) as u64)
+    }
+
     fn evaluate_string_operator(
         rtxn: &heed::RoTxn,
         index: &Index,
@@ -577,6 +737,246 @@ impl FacetCondition {
         }
     }
 
+    /// Looks the integer primary keys that satisfy the operator up in the external documents
+    /// ids, the documents with a primary key that isn't an integer are never returned.
+    fn evaluate_primary_key_operator(
+        rtxn: &heed::RoTxn,
+        index: &Index,
+        operator: FacetNumberOperator<i64>,
+    ) -> anyhow::Result<RoaringBitmap>
+    {
+        let external_documents_ids = index.external_documents_ids(rtxn)?;
+        let docids = match operator {
+            GreaterThan(val) => external_documents_ids.integer_ids((Excluded(val), Bound::Unbounded)),
+            GreaterThanOrEqual(val) => external_documents_ids.integer_ids(val..),
+            Equal(val) => external_documents_ids.get(val.to_string()).into_iter().collect(),
+            NotEqual(val) => {
+                let mut docids = external_documents_ids.integer_ids(..val);
+                docids.union_with(&external_documents_ids.integer_ids((Excluded(val), Bound::Unbounded)));
+                docids
+            },
+            LowerThan(val) => external_documents_ids.integer_ids(..val),
+            LowerThanOrEqual(val) => external_documents_ids.integer_ids(..=val),
+            Between(left, right) => external_documents_ids.integer_ids(left..=right),
+        };
+        Ok(docids)
+    }
+
+    /// Whether a document, with the given facet values and numeric primary key, satisfies
+    /// the condition, the documents don't have to be part of the index.
+    pub fn matches_values(&self, values: &HashMap<FieldId, Vec<FacetValue>>, primary_key: Option<i64>) -> bool {
+        let field_values = |fid: &FieldId| values.get(fid).map_or(&[][..], Vec::as_slice).iter();
+        match self {
+            OperatorI64(fid, op) => {
+                op.matches_any(field_values(fid).filter_map(|v| match v { FacetValue::Integer(i) => Some(*i), _ => None }))
+            },
+            OperatorF64(fid, op) => {
+                op.matches_any(field_values(fid).filter_map(|v| match v { FacetValue::Float(f) => Some(f.into_inner()), _ => None }))
+            },
+            OperatorString(fid, op) => {
+                let mut strings = field_values(fid).filter_map(|v| match v { FacetValue::String(s) => Some(s), _ => None });
+                match op {
+                    FacetStringOperator::Equal(string) => strings.any(|s| s == string),
+                    FacetStringOperator::NotEqual(string) => {
+                        let mut strings = strings.peekable();
+                        strings.peek().is_some() && strings.all(|s| s != string)
+                    },
+                }
+            },
+            PrimaryKey(op) => op.matches_any(primary_key.into_iter()),
+            Or(lhs, rhs) => lhs.matches_values(values, primary_key) || rhs.matches_values(values, primary_key),
+            And(lhs, rhs) => lhs.matches_values(values, primary_key) && rhs.matches_values(values, primary_key),
+        }
+    }
+
+    /// Estimates the number of documents that satisfy this condition without resolving it,
+    /// only the number of documents of a facet value or of a field and the bounds of the
+    /// values of the fields are read.
+    pub fn estimate(&self, rtxn: &heed::RoTxn, index: &Index) -> anyhow::Result<u64> {
+        let db = index.facet_field_id_value_docids;
+        match self {
+            OperatorI64(fid, op) => {
+                Self::estimate_number_operator::<i64, FacetLevelValueI64Codec>(rtxn, index, db, *fid, *op)
+            },
+            OperatorF64(fid, op) => {
+                Self::estimate_number_operator::<f64, FacetLevelValueF64Codec>(rtxn, index, db, *fid, *op)
+            },
+            OperatorString(fid, op) => {
+                let db = db.remap_types::<FacetValueStringCodec, CboRoaringBitmapLenCodec>();
+                match op {
+                    FacetStringOperator::Equal(string) => Ok(db.get(rtxn, &(*fid, string))?.unwrap_or(0)),
+                    FacetStringOperator::NotEqual(string) => {
+                        let faceted_count = index.faceted_documents_ids(rtxn, *fid)?.len();
+                        let count = db.get(rtxn, &(*fid, string))?.unwrap_or(0);
+                        Ok(faceted_count.saturating_sub(count))
+                    },
+                }
+            },
+            // The primary key operators scan all the external ids, they are evaluated last.
+            PrimaryKey(_) => Ok(u64::MAX),
+            Or(lhs, rhs) => Ok(lhs.estimate(rtxn, index)?.saturating_add(rhs.estimate(rtxn, index)?)),
+            And(lhs, rhs) => Ok(lhs.estimate(rtxn, index)?.min(rhs.estimate(rtxn, index)?)),
+        }
+    }
+
+    /// Lists the conditions of a chain of intersections.
+    fn and_conditions<'a>(&'a self, output: &mut Vec<&'a FacetCondition>) {
+        match self {
+            And(lhs, rhs) => {
+                lhs.and_conditions(output);
+                rhs.and_conditions(output);
+            },
+            other => output.push(other),
+        }
+    }
+
+    /// Lists the branches of a chain of unions.
+    fn or_conditions<'a>(&'a self, output: &mut Vec<&'a FacetCondition>) {
+        match self {
+            Or(lhs, rhs) => {
+                lhs.or_conditions(output);
+                rhs.or_conditions(output);
+            },
+            other => output.push(other),
+        }
+    }
+
+    /// Lists the faceted fields this condition refers to, the primary key isn't listed.
+    fn fields(&self, output: &mut Vec<(FieldId, FacetType)>) {
+        match self {
+            OperatorI64(fid, _) => output.push((*fid, FacetType::Integer)),
+            OperatorF64(fid, _) => output.push((*fid, FacetType::Float)),
+            OperatorString(fid, _) => output.push((*fid, FacetType::String)),
+            PrimaryKey(_) => (),
+            Or(lhs, rhs) | And(lhs, rhs) => {
+                lhs.fields(output);
+                rhs.fields(output);
+            },
+        }
+    }
+
+    fn has_primary_key(&self) -> bool {
+        match self {
+            PrimaryKey(_) => true,
+            Or(lhs, rhs) | And(lhs, rhs) => lhs.has_primary_key() || rhs.has_primary_key(),
+            _ => false,
+        }
+    }
+
+    /// Keeps the candidates that satisfy this condition by reading their facet values.
+    fn filter_candidates(
+        &self,
+        rtxn: &heed::RoTxn,
+        index: &Index,
+        candidates: &RoaringBitmap,
+    ) -> anyhow::Result<RoaringBitmap>
+    {
+        let mut fields = Vec::new();
+        self.fields(&mut fields);
+        fields.sort_unstable();
+        fields.dedup();
+
+        let mut output = RoaringBitmap::new();
+        let mut values = HashMap::new();
+        for docid in candidates {
+            values.clear();
+            for (fid, facet_type) in &fields {
+                values.insert(*fid, document_facet_values(index, rtxn, *fid, *facet_type, docid)?);
+            }
+            if self.matches_values(&values, None) {
+                output.insert(docid);
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Keeps the candidates that satisfy this condition, when there are few candidates they
+    /// are checked against their facet values, otherwise the condition is resolved from them.
+    fn evaluate_within(
+        &self,
+        rtxn: &heed::RoTxn,
+        index: &Index,
+        candidates: &RoaringBitmap,
+    ) -> anyhow::Result<RoaringBitmap>
+    {
+        if candidates.len() <= FEW_CANDIDATES && !self.has_primary_key() {
+            return self.filter_candidates(rtxn, index, candidates);
+        }
+
+        match self {
+            Or(_, _) => self.evaluate_or_within(rtxn, index, candidates),
+            And(_, _) => self.evaluate_and(rtxn, index, Some(candidates)),
+            other => Ok(other.evaluate(rtxn, index)? & candidates),
+        }
+    }
+
+    /// Resolves the conditions of an intersection from the most selective to the least
+    /// selective one, each condition only keeps the candidates of the previous ones, see
+    /// `evaluate_within`. The first condition is entirely resolved if no candidates are given.
+    fn evaluate_and(
+        &self,
+        rtxn: &heed::RoTxn,
+        index: &Index,
+        candidates: Option<&RoaringBitmap>,
+    ) -> anyhow::Result<RoaringBitmap>
+    {
+        let mut conditions = Vec::new();
+        self.and_conditions(&mut conditions);
+
+        let mut estimated = Vec::with_capacity(conditions.len());
+        for condition in conditions {
+            estimated.push((condition.estimate(rtxn, index)?, condition));
+        }
+        estimated.sort_by_key(|(estimate, _)| *estimate);
+
+        let mut conditions = estimated.into_iter().map(|(_, condition)| condition);
+        let mut candidates = match (candidates, conditions.next()) {
+            (Some(candidates), Some(condition)) => condition.evaluate_within(rtxn, index, candidates)?,
+            (None, Some(condition)) => condition.evaluate(rtxn, index)?,
+            (_, None) => return Ok(RoaringBitmap::new()),
+        };
+
+        for condition in conditions {
+            if candidates.is_empty() { break }
+            candidates = condition.evaluate_within(rtxn, index, &candidates)?;
+        }
+
+        Ok(candidates)
+    }
+
+    /// Keeps the candidates that satisfy one of the branches of a union. The branches are
+    /// evaluated from the least to the most selective one, the candidates they match are
+    /// removed from the ones of the next branches which are likely to be checked against
+    /// the facet values of few candidates. The branches that scan the primary key are last.
+    fn evaluate_or_within(
+        &self,
+        rtxn: &heed::RoTxn,
+        index: &Index,
+        candidates: &RoaringBitmap,
+    ) -> anyhow::Result<RoaringBitmap>
+    {
+        let mut branches = Vec::new();
+        self.or_conditions(&mut branches);
+
+        let mut estimated = Vec::with_capacity(branches.len());
+        for branch in branches {
+            estimated.push((branch.has_primary_key(), Reverse(branch.estimate(rtxn, index)?), branch));
+        }
+        estimated.sort_by_key(|(primary_key, estimate, _)| (*primary_key, *estimate));
+
+        let mut remaining = candidates.clone();
+        let mut output = RoaringBitmap::new();
+        for (_, _, branch) in estimated {
+            if remaining.is_empty() { break }
+            let matched = branch.evaluate_within(rtxn, index, &remaining)?;
+            remaining.difference_with(&matched);
+            output.union_with(&matched);
+        }
+
+        Ok(output)
+    }
+
     pub fn evaluate(
         &self,
         rtxn: &heed::RoTxn,
@@ -595,16 +995,13 @@ impl FacetCondition {
                 let db = db.remap_key_type::<FacetValueStringCodec>();
                 Self::evaluate_string_operator(rtxn, index, db, *fid, op)
             },
+            PrimaryKey(op) => Self::evaluate_primary_key_operator(rtxn, index, *op),
             Or(lhs, rhs) => {
                 let lhs = lhs.evaluate(rtxn, index)?;
                 let rhs = rhs.evaluate(rtxn, index)?;
                 Ok(lhs | rhs)
             },
-            And(lhs, rhs) => {
-                let lhs = lhs.evaluate(rtxn, index)?;
-                let rhs = rhs.evaluate(rtxn, index)?;
-                Ok(lhs & rhs)
-            },
+            And(_, _) => self.evaluate_and(rtxn, index, None),
         }
     }
 }
@@ -612,7 +1009,8 @@ impl FacetCondition {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::update::Settings;
+    use crate::index::tests::TempIndex;
+    use crate::update::{IndexDocuments, Settings, UpdateFormat};
     use heed::EnvOpenOptions;
     use maplit::hashmap;
 
@@ -753,4 +1151,126 @@ mod tests {
         ).unwrap();
         assert_eq!(condition, expected);
     }
+
+    #[test]
+    fn primary_key_range() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Index some documents, the primary key isn't faceted.
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n1,kevin\n5,kevina\n1000,benoit\n1500,bernard\n2000,bertrand\nabc,bernie\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let external_ids = index.external_documents_ids(&rtxn).unwrap();
+        let docids = |ids: &[&str]| ids.iter().map(|id| external_ids.get(id).unwrap()).collect::<RoaringBitmap>();
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "id >= 1000 AND id < 2000").unwrap();
+        let expected = And(
+            Box::new(PrimaryKey(GreaterThanOrEqual(1000))),
+            Box::new(PrimaryKey(LowerThan(2000))),
+        );
+        assert_eq!(condition, expected);
+        assert_eq!(condition.evaluate(&rtxn, &index).unwrap(), docids(&["1000", "1500"]));
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "NOT id 1000 TO 2000").unwrap();
+        assert_eq!(condition.evaluate(&rtxn, &index).unwrap(), docids(&["1", "5"]));
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "id = 1500").unwrap();
+        assert_eq!(condition.evaluate(&rtxn, &index).unwrap(), docids(&["1500"]));
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "id != 5").unwrap();
+        assert_eq!(condition.evaluate(&rtxn, &index).unwrap(), docids(&["1", "1000", "1500", "2000"]));
+
+        // Other fields must still be faceted to be filtered on.
+        assert!(FacetCondition::from_str(&rtxn, &index, "name = kevin").is_err());
+    }
+
+    #[test]
+    fn and_conditions_by_selectivity() {
+        let mut content = String::from("id,name,age\n");
+        for i in 0..100 {
+            let name = if i % 10 == 0 { "kevin" } else { "bob" };
+            content.push_str(&format!("{},{},{}\n", i, name, i));
+        }
+        let index = TempIndex::from_csv_with_settings(content.as_bytes(), |builder| {
+            builder.set_faceted_fields(hashmap!{
+                "age".into() => "integer".into(),
+                "name".into() => "string".into(),
+            });
+        });
+
+        let rtxn = index.read_txn().unwrap();
+
+        // The estimations are exact for the string and number equalities.
+        let condition = FacetCondition::from_str(&rtxn, &index, "name = kevin").unwrap();
+        assert_eq!(condition.estimate(&rtxn, &index).unwrap(), 10);
+        let condition = FacetCondition::from_str(&rtxn, &index, "age = 42").unwrap();
+        assert_eq!(condition.estimate(&rtxn, &index).unwrap(), 1);
+        // The ranges are estimated from the bounds of the values.
+        let condition = FacetCondition::from_str(&rtxn, &index, "age >= 50").unwrap();
+        let estimate = condition.estimate(&rtxn, &index).unwrap();
+        assert!(estimate >= 40 && estimate <= 60, "{}", estimate);
+
+        // The intersections return the same documents whatever the evaluation order.
+        let external_ids = index.external_documents_ids(&rtxn).unwrap();
+        let docids = |ids: &[&str]| ids.iter().map(|id| external_ids.get(id).unwrap()).collect::<RoaringBitmap>();
+
+        let filter = "age >= 50 AND (name = kevin OR age = 55) AND age != 70";
+        let condition = FacetCondition::from_str(&rtxn, &index, filter).unwrap();
+        assert_eq!(condition.evaluate(&rtxn, &index).unwrap(), docids(&["50", "55", "60", "80", "90"]));
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "name = kevin AND name != kevin").unwrap();
+        assert!(condition.evaluate(&rtxn, &index).unwrap().is_empty());
+    }
+
+    #[test]
+    fn or_branches_by_selectivity() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(100 * 1024 * 1024); // 100 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{
+            "age".into() => "integer".into(),
+            "name".into() => "string".into(),
+        });
+        builder.execute(|_, _| ()).unwrap();
+
+        let mut content = String::from("id,name,age\n");
+        for i in 0..3000 {
+            let name = if i % 10 == 0 { "kevin" } else { "bob" };
+            content.push_str(&format!("{},{},{}\n", i, name, i));
+        }
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content.as_bytes(), |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let external_ids = index.external_documents_ids(&rtxn).unwrap();
+        let docids = |ages: &mut dyn Iterator<Item=u32>| {
+            ages.map(|age| external_ids.get(age.to_string()).unwrap()).collect::<RoaringBitmap>()
+        };
+
+        // The union is resolved from the candidates of the range, its biggest branch is resolved
+        // first and the other ones are checked against the facet values of the remaining candidates.
+        let filter = "age >= 1500 AND (name = kevin OR age = 1501 OR age >= 2500)";
+        let condition = FacetCondition::from_str(&rtxn, &index, filter).unwrap();
+        let expected = docids(&mut (1500..2500).step_by(10).chain(1501..1502).chain(2500..3000));
+        assert_eq!(condition.evaluate(&rtxn, &index).unwrap(), expected);
+
+        let filter = "age >= 1500 AND ((age < 2000 AND name = kevin) OR age >= 2990)";
+        let condition = FacetCondition::from_str(&rtxn, &index, filter).unwrap();
+        let expected = docids(&mut (1500..2000).step_by(10).chain(2990..3000));
+        assert_eq!(condition.evaluate(&rtxn, &index).unwrap(), expected);
+    }
 }
@@ -1,15 +1,17 @@
 use std::collections::{HashSet, BTreeMap};
-use std::ops::Bound::Unbounded;
-use std::{cmp, fmt};
+use std::iter::FromIterator;
+use std::{cmp, fmt, mem};
 
 use anyhow::Context;
 use heed::BytesDecode;
 use roaring::RoaringBitmap;
+use serde::Serialize;
 
 use crate::facet::{FacetType, FacetValue};
-use crate::heed_codec::facet::{FacetValueStringCodec, FacetLevelValueF64Codec, FacetLevelValueI64Codec};
+use crate::heed_codec::facet::{FacetLevelValueF64Codec, FacetLevelValueI64Codec};
 use crate::heed_codec::facet::{FieldDocIdFacetStringCodec, FieldDocIdFacetF64Codec, FieldDocIdFacetI64Codec};
-use crate::search::facet::{FacetIter, FacetRange};
+use crate::search::distinct::{document_facet_values, Distinct};
+use crate::search::facet::{FacetIter, FacetValues};
 use crate::{Index, FieldId, DocumentId};
 
 /// The default number of values by facets that will
@@ -24,10 +26,34 @@ const MAX_VALUES_BY_FACET: usize = 1000;
 /// the system to choose between one algorithm or another.
 const CANDIDATES_THRESHOLD: u64 = 1000;
 
+/// The number of candidates the approximate facet distributions are computed from.
+const DEFAULT_SAMPLE_SIZE: u64 = 10_000;
+
+/// The smallest and the biggest values of a number faceted field among the candidates.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FacetStats {
+    pub min: FacetValue,
+    pub max: FacetValue,
+}
+
+impl FacetStats {
+    /// Extends these stats with the ones computed from other documents, e.g. from another index.
+    pub fn merge(&mut self, other: &FacetStats) {
+        if other.min < self.min {
+            self.min = other.min.clone();
+        }
+        if other.max > self.max {
+            self.max = other.max.clone();
+        }
+    }
+}
+
 pub struct FacetDistribution<'a> {
     facets: Option<HashSet<String>>,
     candidates: Option<RoaringBitmap>,
     max_values_by_facet: usize,
+    distinct: bool,
+    sample_size: Option<u64>,
     rtxn: &'a heed::RoTxn<'a>,
     index: &'a Index,
 }
@@ -38,6 +64,8 @@ impl<'a> FacetDistribution<'a> {
             facets: None,
             candidates: None,
             max_values_by_facet: DEFAULT_VALUES_BY_FACET,
+            distinct: false,
+            sample_size: None,
             rtxn,
             index,
         }
@@ -58,13 +86,103 @@ impl<'a> FacetDistribution<'a> {
         self
     }
 
+    /// Counts the number of groups of documents defined by the distinct attribute of the
+    /// index instead of the number of documents, documents without a value for the
+    /// distinct attribute are counted on their own.
+    ///
+    /// It is ignored if the index doesn't have a distinct attribute.
+    pub fn distinct(&mut self, distinct: bool) -> &mut Self {
+        self.distinct = distinct;
+        self
+    }
+
+    /// Estimates the distribution from a sample of the candidates when there are more of them
+    /// than the sample size, the counts are then extrapolated to all the candidates.
+    ///
+    /// The share of the candidates that have a value is estimated within `±1 / sqrt(sample size)`
+    /// with a confidence of 95%, i.e. ±1% with the default sample of 10 000 candidates.
+    ///
+    /// It is ignored when no candidates are given or when the documents are grouped by the
+    /// distinct attribute, the counts are cheap to compute exactly in the first case.
+    pub fn approximate(&mut self, approximate: bool) -> &mut Self {
+        self.sample_size = if approximate { Some(DEFAULT_SAMPLE_SIZE) } else { None };
+        self
+    }
+
+    /// Estimates the distribution from a sample of this number of candidates, see `approximate`.
+    pub fn sample_size(&mut self, size: u64) -> &mut Self {
+        self.sample_size = Some(cmp::max(size, 1));
+        self
+    }
+
+    /// Returns a pseudo-random sample of the candidates, all of them if they are not more
+    /// than the sample size, in which case the distribution is exact.
+    ///
+    /// A candidate is kept when its hash falls into the first part of the hashes range, this
+    /// is deterministic and doesn't depend on the order in which the documents were indexed.
+    fn sampled_candidates(&self) -> Option<RoaringBitmap> {
+        let sample_size = self.sample_size?;
+        let candidates = self.candidates.as_ref()?;
+        let total = candidates.len();
+        if total <= sample_size {
+            return Some(candidates.clone());
+        }
+
+        let sample = candidates.iter().filter(|docid| fxhash::hash64(docid) % total < sample_size);
+        Some(sample.collect())
+    }
+
+    /// Computes the facet values of a sample of the candidates
+    /// and extrapolates the counts to all the candidates.
+    fn facet_values_from_sample(
+        &self,
+        field_id: FieldId,
+        facet_type: FacetType,
+        sample: &RoaringBitmap,
+        total: u64,
+    ) -> heed::Result<BTreeMap<FacetValue, u64>>
+    {
+        let values = self.facet_values_from_documents(field_id, facet_type, sample, usize::MAX)?;
+        let ratio = total as f64 / cmp::max(sample.len(), 1) as f64;
+        let iter = values.into_iter().map(|(value, count)| (value, (count as f64 * ratio).round() as u64));
+        Ok(BTreeMap::from_iter(iter.take(self.max_values_by_facet)))
+    }
+
+    /// The documents are grouped by the distinct attribute, we must iterate over
+    /// every candidate to retrieve its group along with its facet values.
+    fn distinct_facet_values(
+        &self,
+        field_id: FieldId,
+        facet_type: FacetType,
+        distinct: &Distinct,
+    ) -> heed::Result<BTreeMap<FacetValue, u64>>
+    {
+        let candidates = match &self.candidates {
+            Some(candidates) => candidates.clone(),
+            None => self.index.documents_ids(self.rtxn)?,
+        };
+
+        let mut groups = BTreeMap::<_, HashSet<_>>::new();
+        for docid in candidates {
+            let group = distinct.group(docid)?;
+
+            for value in document_facet_values(self.index, self.rtxn, field_id, facet_type, docid)? {
+                groups.entry(value).or_default().insert(group.clone());
+            }
+        }
+
+        let iter = groups.into_iter().map(|(value, groups)| (value, groups.len() as u64));
+        Ok(BTreeMap::from_iter(iter.take(self.max_values_by_facet)))
+    }
+
     /// There is a small amount of candidates OR we ask for facet string values so we
-    /// decide to iterate over the facet values of each one of them, one by one.
+    /// decide to iterate over the facet values of the first `limit` of them, one by one.
     fn facet_values_from_documents(
         &self,
         field_id: FieldId,
         facet_type: FacetType,
         candidates: &RoaringBitmap,
+        limit: usize,
     ) -> heed::Result<BTreeMap<FacetValue, u64>>
     {
         fn fetch_facet_values<'t, KC, K: 't>(
@@ -72,6 +190,7 @@ impl<'a> FacetDistribution<'a> {
             rtxn: &'t heed::RoTxn,
             field_id: FieldId,
             candidates: &RoaringBitmap,
+            limit: usize,
         ) -> heed::Result<BTreeMap<FacetValue, u64>>
         where
             KC: BytesDecode<'t, DItem = (FieldId, DocumentId, K)>,
@@ -80,7 +199,7 @@ impl<'a> FacetDistribution<'a> {
             let mut facet_values = BTreeMap::new();
             let mut key_buffer = vec![field_id];
 
-            for docid in candidates.into_iter().take(CANDIDATES_THRESHOLD as usize) {
+            for docid in candidates.into_iter().take(limit) {
                 key_buffer.truncate(1);
                 key_buffer.extend_from_slice(&docid.to_be_bytes());
                 let iter = index.field_id_docid_facet_values
@@ -100,13 +219,13 @@ impl<'a> FacetDistribution<'a> {
         let rtxn = self.rtxn;
         match facet_type {
             FacetType::String => {
-                fetch_facet_values::<FieldDocIdFacetStringCodec, _>(index, rtxn, field_id, candidates)
+                fetch_facet_values::<FieldDocIdFacetStringCodec, _>(index, rtxn, field_id, candidates, limit)
             },
             FacetType::Float => {
-                fetch_facet_values::<FieldDocIdFacetF64Codec, _>(index, rtxn, field_id, candidates)
+                fetch_facet_values::<FieldDocIdFacetF64Codec, _>(index, rtxn, field_id, candidates, limit)
             },
             FacetType::Integer => {
-                fetch_facet_values::<FieldDocIdFacetI64Codec, _>(index, rtxn, field_id, candidates)
+                fetch_facet_values::<FieldDocIdFacetI64Codec, _>(index, rtxn, field_id, candidates, limit)
             },
         }
     }
@@ -160,36 +279,12 @@ impl<'a> FacetDistribution<'a> {
         facet_type: FacetType,
     ) -> heed::Result<BTreeMap<FacetValue, u64>>
     {
-        let db = self.index.facet_field_id_value_docids;
-        let level = 0;
-        let iter = match facet_type {
-            FacetType::String => {
-                let iter = db
-                    .prefix_iter(self.rtxn, &[field_id])?
-                    .remap_key_type::<FacetValueStringCodec>()
-                    .map(|r| r.map(|((_, v), docids)| (FacetValue::from(v), docids)));
-                Box::new(iter) as Box::<dyn Iterator<Item=_>>
-            },
-            FacetType::Float => {
-                let db = db.remap_key_type::<FacetLevelValueF64Codec>();
-                let range = FacetRange::<f64, _>::new(
-                    self.rtxn, db, field_id, level, Unbounded, Unbounded,
-                )?;
-                Box::new(range.map(|r| r.map(|((_, _, v, _), docids)| (FacetValue::from(v), docids))))
-            },
-            FacetType::Integer => {
-                let db = db.remap_key_type::<FacetLevelValueI64Codec>();
-                let range = FacetRange::<i64, _>::new(
-                    self.rtxn, db, field_id, level, Unbounded, Unbounded,
-                )?;
-                Box::new(range.map(|r| r.map(|((_, _, v, _), docids)| (FacetValue::from(v), docids))))
-            },
-        };
+        let iter = FacetValues::from_field_id(self.rtxn, self.index, field_id, facet_type)?;
 
         let mut facet_values = BTreeMap::new();
         for result in iter {
-            let (value, docids) = result?;
-            facet_values.insert(value, docids.len());
+            let (value, count) = result?;
+            facet_values.insert(value, count);
             if facet_values.len() == self.max_values_by_facet {
                 break;
             }
@@ -202,13 +297,20 @@ impl<'a> FacetDistribution<'a> {
         &self,
         field_id: FieldId,
         facet_type: FacetType,
+        distinct: Option<&Distinct>,
+        sample: Option<&RoaringBitmap>,
     ) -> heed::Result<BTreeMap<FacetValue, u64>>
     {
-        if let Some(candidates) = self.candidates.as_ref() {
+        if let Some(distinct) = distinct {
+            self.distinct_facet_values(field_id, facet_type, distinct)
+        } else if let Some(candidates) = self.candidates.as_ref() {
             // Classic search, candidates were specified, we must return facet values only related
             // to those candidates. We also enter here for facet strings for performance reasons.
-            if candidates.len() <= CANDIDATES_THRESHOLD || facet_type == FacetType::String {
-                self.facet_values_from_documents(field_id, facet_type, candidates)
+            if let Some(sample) = sample {
+                self.facet_values_from_sample(field_id, facet_type, sample, candidates.len())
+            } else if candidates.len() <= CANDIDATES_THRESHOLD || facet_type == FacetType::String {
+                let limit = CANDIDATES_THRESHOLD as usize;
+                self.facet_values_from_documents(field_id, facet_type, candidates, limit)
             } else {
                 self.facet_values_from_facet_levels(field_id, facet_type, candidates)
             }
@@ -217,28 +319,150 @@ impl<'a> FacetDistribution<'a> {
         }
     }
 
-    pub fn execute(&self) -> anyhow::Result<BTreeMap<String, BTreeMap<FacetValue, u64>>> {
-        let fields_ids_map = self.index.fields_ids_map(self.rtxn)?;
+    /// Returns the requested faceted fields along with their type.
+    fn faceted_fields(&self) -> heed::Result<Vec<(String, FacetType)>> {
         let faceted_fields = self.index.faceted_fields(self.rtxn)?;
-        let fields_ids: Vec<_> = match &self.facets {
+        let fields = match &self.facets {
             Some(names) => names
                 .iter()
                 .filter_map(|n| faceted_fields.get(n).map(|t| (n.to_string(), *t)))
                 .collect(),
             None => faceted_fields.into_iter().collect(),
         };
+        Ok(fields)
+    }
+
+    pub fn execute(&self) -> anyhow::Result<BTreeMap<String, BTreeMap<FacetValue, u64>>> {
+        let fields_ids_map = self.index.fields_ids_map(self.rtxn)?;
+        let fields_ids = self.faceted_fields()?;
+
+        let distinct = if self.distinct { Distinct::from_index(self.rtxn, self.index)? } else { None };
+        let sample = self.sampled_candidates();
 
         let mut facets_values = BTreeMap::new();
         for (name, ftype) in fields_ids {
             let fid = fields_ids_map.id(&name).with_context(|| {
                 format!("missing field name {:?} from the fields id map", name)
             })?;
-            let values = self.facet_values(fid, ftype)?;
+            let mut values = self.facet_values(fid, ftype, distinct.as_ref(), sample.as_ref())?;
+
+            // The string values of the fields that define a normalization are returned as they
+            // were first seen in the documents, not as they are stored in the facet databases.
+            if ftype == FacetType::String {
+                let display_values = self.index.facet_string_display_values(self.rtxn, fid)?;
+                if !display_values.is_empty() {
+                    values = values.into_iter().map(|(value, count)| match value {
+                        FacetValue::String(string) => match display_values.get(&string) {
+                            Some(original) => (FacetValue::String(original.clone()), count),
+                            None => (FacetValue::String(string), count),
+                        },
+                        value => (value, count),
+                    }).collect();
+                }
+            }
+
             facets_values.insert(name, values);
         }
 
         Ok(facets_values)
     }
+
+    /// Returns the smallest and the biggest values of the number faceted fields among the
+    /// candidates, the fields that none of the candidates have a value for are not returned.
+    ///
+    /// The stats are always exact, the sample size and the distinct attribute are ignored.
+    pub fn execute_stats(&self) -> anyhow::Result<BTreeMap<String, FacetStats>> {
+        let fields_ids_map = self.index.fields_ids_map(self.rtxn)?;
+        let candidates = match &self.candidates {
+            Some(candidates) => candidates.clone(),
+            None => self.index.documents_ids(self.rtxn)?,
+        };
+
+        let mut facets_stats = BTreeMap::new();
+        for (name, ftype) in self.faceted_fields()? {
+            let fid = fields_ids_map.id(&name).with_context(|| {
+                format!("missing field name {:?} from the fields id map", name)
+            })?;
+
+            let (min, max) = match ftype {
+                FacetType::Float => {
+                    let min = FacetIter::<f64, FacetLevelValueF64Codec>::new_reducing(
+                        self.rtxn, self.index, fid, candidates.clone(),
+                    )?.next().transpose()?;
+                    let max = FacetIter::<f64, FacetLevelValueF64Codec>::new_reverse_reducing(
+                        self.rtxn, self.index, fid, candidates.clone(),
+                    )?.next().transpose()?;
+                    (min.map(|(v, _)| FacetValue::from(v)), max.map(|(v, _)| FacetValue::from(v)))
+                },
+                FacetType::Integer => {
+                    let min = FacetIter::<i64, FacetLevelValueI64Codec>::new_reducing(
+                        self.rtxn, self.index, fid, candidates.clone(),
+                    )?.next().transpose()?;
+                    let max = FacetIter::<i64, FacetLevelValueI64Codec>::new_reverse_reducing(
+                        self.rtxn, self.index, fid, candidates.clone(),
+                    )?.next().transpose()?;
+                    (min.map(|(v, _)| FacetValue::from(v)), max.map(|(v, _)| FacetValue::from(v)))
+                },
+                FacetType::String => continue,
+            };
+
+            if let (Some(min), Some(max)) = (min, max) {
+                facets_stats.insert(name, FacetStats { min, max });
+            }
+        }
+
+        Ok(facets_stats)
+    }
+
+    /// Combines the distributions computed from several indexes with the same faceted
+    /// fields (e.g. the shards of a dataset) into one, the counts of a value are summed.
+    ///
+    /// Only the first `max_values_by_facet` values of each field, in the order of the values,
+    /// are kept. A distribution is truncated to its first values too, the merged counts are
+    /// only exact when the distributions have been computed with a `max_values_by_facet` at
+    /// least as big as this one: the values of a distribution computed with a smaller limit
+    /// stop earlier, the values that come after its last one are undercounted.
+    ///
+    /// The counts of distributions computed with `distinct` are also summed,
+    /// a group of documents split between several indexes is therefore counted once by index.
+    pub fn merge<I>(distributions: I, max_values_by_facet: usize) -> BTreeMap<String, BTreeMap<FacetValue, u64>>
+    where
+        I: IntoIterator<Item = BTreeMap<String, BTreeMap<FacetValue, u64>>>,
+    {
+        let mut merged = BTreeMap::<_, BTreeMap<_, u64>>::new();
+        for distribution in distributions {
+            for (name, values) in distribution {
+                let merged_values = merged.entry(name).or_default();
+                for (value, count) in values {
+                    *merged_values.entry(value).or_default() += count;
+                }
+            }
+        }
+
+        for values in merged.values_mut() {
+            *values = mem::take(values).into_iter().take(max_values_by_facet).collect();
+        }
+
+        merged
+    }
+
+    /// Combines the stats computed from several indexes with the same faceted fields
+    /// into one, keeping the smallest minimum and the biggest maximum of each field.
+    pub fn merge_stats<I>(stats: I) -> BTreeMap<String, FacetStats>
+    where
+        I: IntoIterator<Item = BTreeMap<String, FacetStats>>,
+    {
+        let mut merged = BTreeMap::<String, FacetStats>::new();
+        for stats in stats {
+            for (name, field_stats) in stats {
+                match merged.get_mut(&name) {
+                    Some(merged_stats) => merged_stats.merge(&field_stats),
+                    None => { merged.insert(name, field_stats); },
+                }
+            }
+        }
+        merged
+    }
 }
 
 impl fmt::Debug for FacetDistribution<'_> {
@@ -247,6 +471,8 @@ impl fmt::Debug for FacetDistribution<'_> {
             facets,
             candidates,
             max_values_by_facet,
+            distinct,
+            sample_size,
             rtxn: _,
             index: _,
         } = self;
@@ -255,6 +481,95 @@ impl fmt::Debug for FacetDistribution<'_> {
             .field("facets", facets)
             .field("candidates", candidates)
             .field("max_values_by_facet", max_values_by_facet)
+            .field("distinct", distinct)
+            .field("sample_size", sample_size)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use crate::index::tests::TempIndex;
+    use super::*;
+
+    #[test]
+    fn merge_distributions_and_stats() {
+        let contents = ["id,color,price\n0,blue,10\n1,red,30\n", "id,color,price\n0,blue,5\n1,green,20\n"];
+        let indexes: Vec<_> = contents.iter().map(|content| {
+            TempIndex::from_csv_with_settings(content.as_bytes(), |builder| {
+                builder.set_faceted_fields(hashmap!{
+                    "color".into() => "string".into(),
+                    "price".into() => "integer".into(),
+                });
+            })
+        }).collect();
+
+        let mut distributions = Vec::new();
+        let mut stats = Vec::new();
+        for index in &indexes {
+            let rtxn = index.read_txn().unwrap();
+            distributions.push(index.facets_distribution(&rtxn).execute().unwrap());
+            stats.push(index.facets_distribution(&rtxn).execute_stats().unwrap());
+        }
+
+        let merged = FacetDistribution::merge(distributions.clone(), 100);
+        let colors: Vec<_> = merged["color"].iter().map(|(v, c)| (v.clone(), *c)).collect();
+        let expected = vec![(FacetValue::from("blue"), 2), (FacetValue::from("green"), 1), (FacetValue::from("red"), 1)];
+        assert_eq!(colors, expected);
+
+        // Only the first values of each field are kept.
+        let merged = FacetDistribution::merge(distributions, 1);
+        assert_eq!(merged["color"].len(), 1);
+
+        // The distributions truncated to the same number of values are merged with exact counts.
+        let truncated = indexes.iter().map(|index| {
+            let rtxn = index.read_txn().unwrap();
+            index.facets_distribution(&rtxn).max_values_by_facet(1).execute().unwrap()
+        });
+        let merged = FacetDistribution::merge(truncated, 1);
+        assert_eq!(merged["color"].iter().next(), Some((&FacetValue::from("blue"), &2)));
+
+        // The string fields don't have stats.
+        let merged = FacetDistribution::merge_stats(stats);
+        assert!(!merged.contains_key("color"));
+        assert_eq!(merged["price"], FacetStats { min: FacetValue::Integer(5), max: FacetValue::Integer(30) });
+    }
+
+    #[test]
+    fn approximate_facet_distribution() {
+        let colors = ["blue", "green", "red"];
+        let mut content = String::from("id,color\n");
+        for i in 0..3000 {
+            content.push_str(&format!("{},{}\n", i, colors[i % 3]));
+        }
+        let index = TempIndex::from_csv_with_settings(content.as_bytes(), |builder| {
+            builder.set_faceted_fields(hashmap!{ "color".into() => "string".into() });
+        });
+
+        let rtxn = index.read_txn().unwrap();
+        let candidates = index.documents_ids(&rtxn).unwrap();
+
+        // The sample contains all the candidates, the counts are exact.
+        let distribution = index.facets_distribution(&rtxn)
+            .candidates(candidates.clone())
+            .approximate(true)
+            .execute()
+            .unwrap();
+        let counts: Vec<_> = distribution["color"].values().copied().collect();
+        assert_eq!(counts, vec![1000, 1000, 1000]);
+
+        // Only a tenth of the candidates are read, the counts are estimated.
+        let distribution = index.facets_distribution(&rtxn)
+            .candidates(candidates)
+            .sample_size(300)
+            .execute()
+            .unwrap();
+        let values = &distribution["color"];
+        assert_eq!(values.len(), 3);
+        for count in values.values() {
+            assert!((800..=1200).contains(count), "{} is too far from 1000", count);
+        }
+    }
+}
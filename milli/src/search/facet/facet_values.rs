@@ -0,0 +1,112 @@
+use std::ops::Bound::Unbounded;
+
+use anyhow::Context;
+use roaring::RoaringBitmap;
+
+use crate::facet::{FacetType, FacetValue};
+use crate::heed_codec::facet::{FacetValueStringCodec, FacetLevelValueF64Codec, FacetLevelValueI64Codec};
+use crate::search::facet::FacetRange;
+use crate::{Index, FieldId};
+
+/// An iterator over all the values of a faceted field, in the database order,
+/// along with the number of documents that contain them.
+pub struct FacetValues<'t> {
+    iter: Box<dyn Iterator<Item = heed::Result<(FacetValue, RoaringBitmap)>> + 't>,
+    candidates: Option<RoaringBitmap>,
+}
+
+impl<'t> FacetValues<'t> {
+    /// Creates an iterator over the values of the given faceted field,
+    /// returns an error if the field isn't faceted.
+    pub fn new(rtxn: &'t heed::RoTxn, index: &'t Index, field_name: &str) -> anyhow::Result<FacetValues<'t>> {
+        let faceted_fields = index.faceted_fields(rtxn)?;
+        let facet_type = *faceted_fields.get(field_name).with_context(|| {
+            format!("Can't iterate over the values of {:?} as it isn't a faceted field.", field_name)
+        })?;
+
+        let fields_ids_map = index.fields_ids_map(rtxn)?;
+        let field_id = fields_ids_map.id(field_name).with_context(|| {
+            format!("missing field name {:?} from the fields id map", field_name)
+        })?;
+
+        FacetValues::from_field_id(rtxn, index, field_id, facet_type).map_err(Into::into)
+    }
+
+    pub(crate) fn from_field_id(
+        rtxn: &'t heed::RoTxn,
+        index: &'t Index,
+        field_id: FieldId,
+        facet_type: FacetType,
+    ) -> heed::Result<FacetValues<'t>>
+    {
+        let db = index.facet_field_id_value_docids;
+        let level = 0;
+        let iter = match facet_type {
+            FacetType::String => {
+                let iter = db
+                    .prefix_iter(rtxn, &[field_id])?
+                    .remap_key_type::<FacetValueStringCodec>()
+                    .map(|r| r.map(|((_, v), docids)| (FacetValue::from(v), docids)));
+                Box::new(iter) as Box::<dyn Iterator<Item=_> + 't>
+            },
+            FacetType::Float => {
+                let db = db.remap_key_type::<FacetLevelValueF64Codec>();
+                let range = FacetRange::<f64, _>::new(
+                    rtxn, db, field_id, level, Unbounded, Unbounded,
+                )?;
+                Box::new(range.map(|r| r.map(|((_, _, v, _), docids)| (FacetValue::from(v), docids))))
+            },
+            FacetType::Integer => {
+                let db = db.remap_key_type::<FacetLevelValueI64Codec>();
+                let range = FacetRange::<i64, _>::new(
+                    rtxn, db, field_id, level, Unbounded, Unbounded,
+                )?;
+                Box::new(range.map(|r| r.map(|((_, _, v, _), docids)| (FacetValue::from(v), docids))))
+            },
+        };
+
+        Ok(FacetValues { iter, candidates: None })
+    }
+
+    /// Only counts the documents that are part of the candidates,
+    /// the values that no candidate contains are skipped.
+    pub fn candidates(mut self, candidates: RoaringBitmap) -> FacetValues<'t> {
+        self.candidates = Some(candidates);
+        self
+    }
+
+    /// Returns the documents ids that contain the values instead of their number.
+    pub(crate) fn docids(self) -> impl Iterator<Item = heed::Result<(FacetValue, RoaringBitmap)>> + 't {
+        let FacetValues { iter, candidates } = self;
+        iter.filter_map(move |result| match result {
+            Ok((value, mut docids)) => {
+                if let Some(candidates) = &candidates {
+                    docids.intersect_with(candidates);
+                    if docids.is_empty() { return None }
+                }
+                Some(Ok((value, docids)))
+            },
+            Err(e) => Some(Err(e)),
+        })
+    }
+}
+
+impl Iterator for FacetValues<'_> {
+    type Item = heed::Result<(FacetValue, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (value, mut docids) = match self.iter.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if let Some(candidates) = &self.candidates {
+                docids.intersect_with(candidates);
+                if docids.is_empty() { continue }
+            }
+
+            return Some(Ok((value, docids.len())));
+        }
+    }
+}
@@ -13,10 +13,12 @@ use crate::heed_codec::CboRoaringBitmapCodec;
 use crate::{Index, FieldId};
 
 pub use self::facet_condition::{FacetCondition, FacetNumberOperator, FacetStringOperator};
-pub use self::facet_distribution::FacetDistribution;
+pub use self::facet_distribution::{FacetDistribution, FacetStats};
+pub use self::facet_values::FacetValues;
 
 mod facet_condition;
 mod facet_distribution;
+mod facet_values;
 mod parser;
 
 pub struct FacetRange<'t, T: 't, KC> {
@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+
+use meilisearch_tokenizer::{Analyzer, AnalyzerConfig};
+
+use super::MatchingWords;
+
+/// The default number of words of a snippet.
+pub const DEFAULT_CROP_LENGTH: usize = 10;
+
+/// The default marker of the text removed before and after a snippet.
+pub const DEFAULT_CROP_MARKER: &str = "…";
+
+/// Crops the text of a field into a snippet of `crop_length` words around its best
+/// matches, the window of words that contains the most query words, then the most matches.
+///
+/// The snippet is centered on the matches of the window, the text removed before and after
+/// it is replaced by the crop marker. A text that isn't longer than the snippet is kept whole.
+pub struct Cropper<'a, A> {
+    analyzer: Analyzer<'a, A>,
+    crop_length: usize,
+    crop_marker: String,
+}
+
+impl<'a, A: AsRef<[u8]>> Cropper<'a, A> {
+    pub fn new(stop_words: &'a fst::Set<A>) -> Self {
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words));
+        Cropper {
+            analyzer,
+            crop_length: DEFAULT_CROP_LENGTH,
+            crop_marker: DEFAULT_CROP_MARKER.to_string(),
+        }
+    }
+
+    /// The number of words of the snippets, at least one.
+    pub fn crop_length(&mut self, crop_length: usize) -> &mut Self {
+        self.crop_length = crop_length.max(1);
+        self
+    }
+
+    pub fn crop_marker(&mut self, crop_marker: impl Into<String>) -> &mut Self {
+        self.crop_marker = crop_marker.into();
+        self
+    }
+
+    /// Returns the snippet of the text around the words that match the query.
+    pub fn crop(&self, text: &str, matching_words: &MatchingWords) -> String {
+        let analyzed = self.analyzer.analyze(text);
+
+        // The byte span of every word of the text along with the query words it matches.
+        let mut words = Vec::new();
+        let mut offset = 0;
+        for (word, token) in analyzed.reconstruct() {
+            if token.is_separator().is_none() {
+                let query_words = matching_words.matching_query_words(token.text());
+                words.push((offset, offset + word.len(), query_words));
+            }
+            offset += word.len();
+        }
+
+        if words.len() <= self.crop_length {
+            return text.to_string();
+        }
+
+        let (start, end) = best_window(&words, self.crop_length);
+        let mut snippet = String::new();
+        if start != 0 {
+            snippet.push_str(&self.crop_marker);
+        }
+        snippet.push_str(&text[words[start].0..words[end - 1].1]);
+        if end != words.len() {
+            snippet.push_str(&self.crop_marker);
+        }
+        snippet
+    }
+}
+
+/// Returns the range of the words of the snippet, the first window of `length` words with
+/// the most distinct query words then the most matches, shifted to center its matches.
+fn best_window(words: &[(usize, usize, Vec<&str>)], length: usize) -> (usize, usize) {
+    let mut best = None;
+    for start in 0..=words.len() - length {
+        let window = &words[start..start + length];
+        let query_words: HashSet<_> = window.iter().flat_map(|(_, _, query_words)| query_words).collect();
+        let matches = window.iter().filter(|(_, _, query_words)| !query_words.is_empty()).count();
+        let score = (query_words.len(), matches);
+        if best.map_or(true, |(best_score, _)| score > best_score) {
+            best = Some((score, start));
+        }
+    }
+
+    let start = match best {
+        Some(((0, _), _)) | None => return (0, length),
+        Some((_, start)) => start,
+    };
+
+    let window = &words[start..start + length];
+    let first = window.iter().position(|(_, _, query_words)| !query_words.is_empty()).unwrap_or(0);
+    let last = window.iter().rposition(|(_, _, query_words)| !query_words.is_empty()).unwrap_or(0);
+    let before = (length - (last - first + 1)) / 2;
+    let start = (start + first).saturating_sub(before).min(words.len() - length);
+    (start, start + length)
+}
+
+#[cfg(test)]
+mod test {
+    use heed::EnvOpenOptions;
+
+    use crate::Index;
+    use super::*;
+
+    #[test]
+    fn crop_around_matches() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("quick fox").execute().unwrap();
+        let matching_words = &result.matching_words;
+
+        let stop_words = fst::Set::default();
+        let mut cropper = Cropper::new(&stop_words);
+        cropper.crop_length(3);
+
+        let text = "one two three four five the quick brown fox six seven eight";
+        assert_eq!(cropper.crop(text, matching_words), "…quick brown fox…");
+
+        // The window with both query words wins over the one with the most matches.
+        let text = "fox fox fox one two three four quick the fox";
+        assert_eq!(cropper.crop(text, matching_words), "…quick the fox");
+
+        let text = "the fox jumps over the lazy dog";
+        assert_eq!(cropper.crop(text, matching_words), "the fox jumps…");
+
+        let text = "nothing matches here at all";
+        assert_eq!(cropper.crop(text, matching_words), "nothing matches here…");
+
+        cropper.crop_length(10).crop_marker("...");
+        assert_eq!(cropper.crop(text, matching_words), text);
+    }
+}
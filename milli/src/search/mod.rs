@@ -1,40 +1,97 @@
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::hash_map::{HashMap, Entry};
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
+use std::mem::take;
 use std::str::Utf8Error;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use anyhow::{bail, Context};
 use fst::{IntoStreamer, Streamer, Set};
 use levenshtein_automata::{DFA, LevenshteinAutomatonBuilder as LevBuilder};
 use log::debug;
-use meilisearch_tokenizer::{AnalyzerConfig, Analyzer};
+use meilisearch_tokenizer::{AnalyzerConfig, Analyzer, TokenKind};
 use once_cell::sync::Lazy;
 use roaring::bitmap::RoaringBitmap;
+use serde::{Serialize, Deserialize};
+use unicode_segmentation::UnicodeSegmentation;
 
+use crate::facet::{FacetType, FacetValue};
 use crate::search::criteria::fetcher::FetcherResult;
-use crate::{Index, DocumentId};
+use crate::search::distinct::document_facet_values;
+use crate::proximity::{extract_position, MAX_INDEX_IN_ATTRIBUTE};
+use crate::update::process_tokens;
+use crate::{json_to_string, AscDesc, Criterion, Index, DocumentId, FieldId, Position};
 
+pub use self::cache::SearchCache;
+pub use self::cursor::Cursor;
+pub use self::cost::QueryCost;
+pub use self::criteria::{ExactMatch, Rank, ScoreDetail};
+pub use self::distinct::{Distinct, DistinctGroup, DistinctMode};
 pub use self::facet::FacetIter;
-pub use self::facet::{FacetCondition, FacetDistribution, FacetNumberOperator, FacetStringOperator};
-pub use self::query_tree::MatchingWords;
-use self::query_tree::QueryTreeBuilder;
+pub use self::facet::{FacetCondition, FacetDistribution, FacetStats, FacetValues, FacetNumberOperator, FacetStringOperator};
+pub use self::percolate::{Percolator, StoredQuery};
+pub use self::query_tree::{MatchingWords, QueryLimits, QueryTooComplex, TermsMatchingStrategy, TyposReason, WordTypos};
+pub use self::query_tree::{ONE_TYPO_MIN_WORD_LEN, TWO_TYPOS_MIN_WORD_LEN, DEFAULT_MAX_NGRAM, MAX_NGRAM_LIMIT};
+use self::query_tree::{increase_typos, BooleanQuery, Operation, QueryTreeBuilder};
+#[cfg(feature = "packed")]
+pub(crate) use self::query_tree::{build_query_tree, Context as QueryTreeContext};
 
 // Building these factories is not free.
 static LEVDIST0: Lazy<LevBuilder> = Lazy::new(|| LevBuilder::new(0, true));
 static LEVDIST1: Lazy<LevBuilder> = Lazy::new(|| LevBuilder::new(1, true));
 static LEVDIST2: Lazy<LevBuilder> = Lazy::new(|| LevBuilder::new(2, true));
 
+/// The number of documents returned when neither the search nor the index defines it.
+pub(crate) const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+mod cache;
+mod cost;
+mod cursor;
+pub mod criteria;
+mod distinct;
 mod facet;
+pub mod format;
+mod percolate;
 mod query_tree;
-mod criteria;
 
+#[derive(Clone)]
 pub struct Search<'a> {
     query: Option<String>,
     facet_condition: Option<FacetCondition>,
+    soft_facet_condition: Option<FacetCondition>,
+    in_documents: Option<RoaringBitmap>,
+    excluded_terms: Vec<String>,
+    boolean_syntax: bool,
+    query_limits: QueryLimits,
+    boosts: Vec<(FacetCondition, u32)>,
+    sort_criteria: Option<Vec<AscDesc>>,
+    criteria: Option<Vec<Criterion>>,
+    custom_criteria: HashMap<String, criteria::CriterionFactory>,
+    snapshot: Option<u64>,
+    distinct: Option<String>,
+    group_by: Option<(String, usize)>,
+    languages: Option<Vec<String>>,
+    searchable_attributes: Option<Vec<String>>,
+    synonyms: HashMap<Vec<String>, Vec<Vec<String>>>,
+    documents_facets: Option<Vec<String>>,
+    matches_positions: bool,
+    score_details: bool,
+    ranking_score_threshold: Option<f64>,
     offset: usize,
-    limit: usize,
-    optional_words: bool,
+    after: Option<Cursor>,
+    skip_offset_ranking: bool,
+    limit: Option<usize>,
+    optional_words: Option<bool>,
+    terms_matching_strategy: Option<TermsMatchingStrategy>,
     authorize_typos: bool,
+    typo_candidates_limit: Option<u64>,
+    relax_on_empty: bool,
+    time_budget: Option<Duration>,
+    exhaustive_total_hits: bool,
+    increased_typos: bool,
     rtxn: &'a heed::RoTxn<'a>,
     index: &'a Index,
 }
@@ -44,10 +101,37 @@ impl<'a> Search<'a> {
         Search {
             query: None,
             facet_condition: None,
+            soft_facet_condition: None,
+            in_documents: None,
+            excluded_terms: Vec::new(),
+            boolean_syntax: false,
+            query_limits: QueryLimits::default(),
+            boosts: Vec::new(),
+            sort_criteria: None,
+            criteria: None,
+            custom_criteria: HashMap::new(),
+            snapshot: None,
+            distinct: None,
+            group_by: None,
+            languages: None,
+            searchable_attributes: None,
+            synonyms: HashMap::new(),
+            documents_facets: None,
+            matches_positions: false,
+            score_details: false,
+            ranking_score_threshold: None,
             offset: 0,
-            limit: 20,
-            optional_words: true,
+            after: None,
+            skip_offset_ranking: false,
+            limit: None,
+            optional_words: None,
+            terms_matching_strategy: None,
             authorize_typos: true,
+            typo_candidates_limit: None,
+            relax_on_empty: false,
+            time_budget: None,
+            exhaustive_total_hits: false,
+            increased_typos: false,
             rtxn,
             index,
         }
@@ -63,13 +147,44 @@ impl<'a> Search<'a> {
         self
     }
 
+    /// Only returns the documents ranked after the one of the given cursor, the `cursor` of
+    /// the `SearchResult` of the previous page. The pages stay consistent while the index is
+    /// updated, the documents are never returned twice, unlike with an offset.
+    ///
+    /// The search must use the same query and ranking rules as the one of the previous page.
+    pub fn after(&mut self, cursor: Cursor) -> &mut Search<'a> {
+        self.after = Some(cursor);
+        self
+    }
+
+    /// Doesn't rank the documents skipped by the offset when they can be skipped by whole
+    /// buckets, the costly ranking rules don't read the positions of their words, deep pages
+    /// then cost about the same as the first one. The returned documents are unchanged.
+    ///
+    /// It is ignored when the search has a distinct attribute or a ranking score
+    /// threshold as the skipped documents must then be known in order.
+    pub fn skip_offset_ranking(&mut self, value: bool) -> &mut Search<'a> {
+        self.skip_offset_ranking = value;
+        self
+    }
+
+    /// The maximum number of documents returned, overrides the limit of the `SearchDefaults`.
     pub fn limit(&mut self, limit: usize) -> &mut Search<'a> {
-        self.limit = limit;
+        self.limit = Some(limit);
         self
     }
 
+    /// Whether the documents that don't contain all the query words are returned, overrides
+    /// the `optional_words` and the `terms_matching_strategy` of the `SearchDefaults`.
     pub fn optional_words(&mut self, value: bool) -> &mut Search<'a> {
-        self.optional_words = value;
+        self.optional_words = Some(value);
+        self
+    }
+
+    /// Defines which query words are removed first to find the documents that don't contain
+    /// all of them, it overrides the optional words of the search and of the `SearchDefaults`.
+    pub fn terms_matching_strategy(&mut self, strategy: TermsMatchingStrategy) -> &mut Search<'a> {
+        self.terms_matching_strategy = Some(strategy);
         self
     }
 
@@ -78,76 +193,1022 @@ impl<'a> Search<'a> {
         self
     }
 
+    /// Stops returning documents that only match the query with typos once the typo
+    /// ranking rule returned at least `limit` documents, the documents that match the
+    /// query without typos are always returned.
+    pub fn typo_candidates_limit(&mut self, limit: u64) -> &mut Search<'a> {
+        self.typo_candidates_limit = Some(limit);
+        self
+    }
+
     pub fn facet_condition(&mut self, condition: FacetCondition) -> &mut Search<'a> {
         self.facet_condition = Some(condition);
         self
     }
 
+    /// Restricts the search to the given documents, e.g. the ones a user is allowed to see,
+    /// the other documents are never returned nor counted in the candidates.
+    pub fn in_documents(&mut self, documents: RoaringBitmap) -> &mut Search<'a> {
+        self.in_documents = Some(documents);
+        self
+    }
+
+    /// Removes the documents that contain any of these terms before ranking, like when
+    /// the terms are prefixed by a dash in the query, e.g. `-word`.
+    pub fn excluded_terms(&mut self, terms: Vec<String>) -> &mut Search<'a> {
+        self.excluded_terms = terms;
+        self
+    }
+
+    /// Parses the query with the boolean syntax, the `AND`, `OR` and `NOT` operators and
+    /// the parentheses, every term is then required unless it is part of a disjunction.
+    pub fn boolean_syntax(&mut self, boolean_syntax: bool) -> &mut Search<'a> {
+        self.boolean_syntax = boolean_syntax;
+        self
+    }
+
+    /// The limits on the complexity of the query, the search returns a `QueryTooComplex`
+    /// error when the query exceeds them instead of resolving a huge query tree.
+    pub fn query_limits(&mut self, limits: QueryLimits) -> &mut Search<'a> {
+        self.query_limits = limits;
+        self
+    }
+
+    /// A facet condition that the documents must satisfy like the `facet_condition`
+    /// but that is the first one to be dropped when the search is relaxed.
+    pub fn soft_facet_condition(&mut self, condition: FacetCondition) -> &mut Search<'a> {
+        self.soft_facet_condition = Some(condition);
+        self
+    }
+
+    /// Ranks the documents that satisfy the facet condition before the others, the ranking
+    /// rules then order the boosted documents and the other documents separately.
+    ///
+    /// When many boosts are given, the documents are ordered by the sum of the factors of
+    /// the boosts they satisfy, a boost with a factor of `0` has no effect.
+    pub fn boost(&mut self, condition: FacetCondition, factor: u32) -> &mut Search<'a> {
+        self.boosts.push((condition, factor));
+        self
+    }
+
+    /// When the search doesn't find any document it is executed again with the following
+    /// relaxations, one after the other, until some documents are found: the soft facet
+    /// condition is dropped, the words are given one more typo and then the rarest words
+    /// of the query are removed one by one. The relaxations applied are in the `SearchResult`.
+    pub fn relax_on_empty(&mut self, value: bool) -> &mut Search<'a> {
+        self.relax_on_empty = value;
+        self
+    }
+
+    /// The maximum time the search can take, once it is spent the ranking rules stop splitting
+    /// the buckets they are given and return the remaining documents of a bucket together,
+    /// the result is then marked as degraded, see `SearchResult::degraded`.
+    ///
+    /// The budget is checked by the ranking rules every time they split a bucket,
+    /// no matching document is dropped, only their order is less refined.
+    pub fn time_budget(&mut self, budget: Duration) -> &mut Search<'a> {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Whether all the documents matching the search are counted, see `SearchResult::total_hits`.
+    ///
+    /// The query tree is then entirely resolved against the facet candidates once, the documents
+    /// are not ranked but the distinct attribute is applied to all of them.
+    /// By default, only the documents of the buckets computed to return the page are counted.
+    pub fn exhaustive_total_hits(&mut self, value: bool) -> &mut Search<'a> {
+        self.exhaustive_total_hits = value;
+        self
+    }
+
+    /// Sorts the documents by the given sort expressions, the first one being
+    /// the most important, each following one refines the buckets of the previous one.
+    ///
+    /// The ranking rules of the index then order the documents that share the same sort
+    /// values by relevance, a field with a different value for every document therefore
+    /// leaves them nothing to order.
+    pub fn sort(&mut self, criteria: Vec<AscDesc>) -> &mut Search<'a> {
+        self.sort_criteria = Some(criteria);
+        self
+    }
+
+    /// Ranks the documents with the given ranking rules instead of the ones of the index,
+    /// for this search only. The fields of the `asc`, `desc` and `diversify` rules must be
+    /// faceted, like for the ranking rules of the index, the sort expressions are still
+    /// applied before these rules.
+    pub fn criteria(&mut self, criteria: Vec<Criterion>) -> &mut Search<'a> {
+        self.criteria = Some(criteria);
+        self
+    }
+
+    /// Registers a ranking rule defined outside of this crate, it is used where the ranking
+    /// rules of the search or of the index contain a `Criterion::Custom` with the same name.
+    ///
+    /// See `CriteriaBuilder::register_criterion` for what the factory is given.
+    pub fn register_criterion<F>(&mut self, name: impl Into<String>, factory: F) -> &mut Search<'a>
+    where
+        F: for<'c> Fn(&'c dyn criteria::Context, Box<dyn criteria::Criterion + 'c>)
+            -> anyhow::Result<Box<dyn criteria::Criterion + 'c>>,
+        F: Send + Sync + 'static,
+    {
+        self.custom_criteria.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    /// Collapses the documents that share the same value for the given faceted field into
+    /// groups of at most `max_per_group` documents, see `SearchResult::groups`. The groups are
+    /// ordered by their best document and the offset and the limit count groups, not documents.
+    ///
+    /// The distinct attribute is ignored, every group is made of the best documents of a value.
+    pub fn group_by(&mut self, field: impl Into<String>, max_per_group: usize) -> &mut Search<'a> {
+        self.group_by = Some((field.into(), max_per_group.max(1)));
+        self
+    }
+
+    /// Only returns the first document of each group of documents that share the same
+    /// value for the given faceted field, instead of the distinct attribute of the index.
+    pub fn distinct(&mut self, field: impl Into<String>) -> &mut Search<'a> {
+        self.distinct = Some(field.into());
+        self
+    }
+
+    /// Only searches the query words in the given attributes for this query, they must be
+    /// searchable. The documents are found with the words of each attribute, not filtered after.
+    pub fn searchable_attributes(&mut self, attributes: &[&str]) -> &mut Search<'a> {
+        self.searchable_attributes = Some(attributes.iter().map(|a| a.to_string()).collect());
+        self
+    }
+
+    /// Only matches the query in the attributes of the given languages and in
+    /// the attributes that don't declare a language, see the attributes languages setting.
+    ///
+    /// The attributes are restricted like with `searchable_attributes`, both restrictions apply.
+    pub fn languages(&mut self, languages: Vec<String>) -> &mut Search<'a> {
+        self.languages = Some(languages.iter().map(|l| l.to_lowercase()).collect());
+        self
+    }
+
+    /// Adds synonyms that are only used by this search, they are never stored in the index.
+    ///
+    /// The keys are the words or the sequences of words of the query that are also searched
+    /// as the alternatives associated to them, both are tokenized like the query.
+    pub fn synonyms(&mut self, synonyms: HashMap<String, Vec<String>>) -> &mut Search<'a> {
+        let stop_words = &Set::default();
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words));
+        let words = |text: &str| -> Vec<String> {
+            analyzer.analyze(text).tokens()
+                .filter(|token| matches!(token.kind, TokenKind::Word))
+                .map(|token| token.word.to_string())
+                .collect()
+        };
+
+        for (key, alternatives) in synonyms {
+            let key = words(&key);
+            if key.is_empty() { continue }
+            let entry = self.synonyms.entry(key).or_insert_with(Vec::new);
+            for alternative in alternatives {
+                let alternative = words(&alternative);
+                if !alternative.is_empty() && !entry.contains(&alternative) {
+                    entry.push(alternative);
+                }
+            }
+        }
+
+        self
+    }
+
+    /// Returns the values of the given faceted fields for every returned document, they are
+    /// read from the facet databases, the stored documents don't need to be fetched.
+    pub fn documents_facets(&mut self, fields: Vec<String>) -> &mut Search<'a> {
+        self.documents_facets = Some(fields);
+        self
+    }
+
+    /// Returns, for every returned document, the fields and the byte ranges of the words
+    /// that match the query, including the ones matching a prefix or with typos.
+    pub fn matches_positions(&mut self, matches_positions: bool) -> &mut Search<'a> {
+        self.matches_positions = matches_positions;
+        self
+    }
+
+    /// Returns, for every returned document, what it has in common with the other documents
+    /// of its bucket for each ranking rule, e.g. its number of typos or its sort value.
+    pub fn score_details(&mut self, score_details: bool) -> &mut Search<'a> {
+        self.score_details = score_details;
+        self
+    }
+
+    /// Stops returning documents once the ranking score of the current bucket, see
+    /// `SearchResult::documents_scores`, is lower than the given threshold.
+    ///
+    /// As the buckets are returned from the best to the worst one, the remaining
+    /// buckets are not even computed.
+    pub fn ranking_score_threshold(&mut self, threshold: f64) -> &mut Search<'a> {
+        self.ranking_score_threshold = Some(threshold);
+        self
+    }
+
+    /// Pins the search to the version of the index identified by the given update sequence,
+    /// the one returned in the `SearchResult` of the first page of results.
+    ///
+    /// The search fails if the index has been updated since, this way a user paging through
+    /// the results never sees duplicated or missing documents. A caller that wants to keep
+    /// serving the old results must reuse the read transaction of the first page.
+    pub fn snapshot(&mut self, update_sequence: u64) -> &mut Search<'a> {
+        self.snapshot = Some(update_sequence);
+        self
+    }
+
     pub fn execute(&self) -> anyhow::Result<SearchResult> {
-        // We create the query tree by spliting the query into tokens.
-        let before = Instant::now();
-        let query_tree = match self.query.as_ref() {
+        self.execute_by_bucket(|_| Ok(()))
+    }
+
+    /// Executes the search and calls the given function with the documents of each bucket,
+    /// in order, as soon as the ranking rules returned it. It makes it possible to start
+    /// sending the first documents while the following buckets are still being computed.
+    ///
+    /// The documents ids given to the function are the ones of the final `SearchResult`,
+    /// the offset, the limit and the distinct attribute are already applied.
+    pub fn execute_by_bucket<F>(&self, mut on_bucket: F) -> anyhow::Result<SearchResult>
+    where
+        F: FnMut(&[DocumentId]) -> anyhow::Result<()>,
+    {
+        // The relaxed searches share the time budget of the first one.
+        let deadline = self.time_budget.map(|budget| Instant::now() + budget);
+        let mut result = self.execute_once(deadline, &mut on_bucket)?;
+        if !self.relax_on_empty {
+            return Ok(result);
+        }
+
+        // No bucket has been given to the function when nothing is found,
+        // the buckets of the relaxed search are therefore the only ones it sees.
+        let mut relaxed = self.clone();
+        let mut relaxations = Vec::new();
+        while result.candidates.is_empty() {
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                result.degraded = true;
+                break;
+            }
+
+            match relaxed.relax()? {
+                Some(relaxation) => relaxations.push(relaxation),
+                None => break,
+            }
+            result = relaxed.execute_once(deadline, &mut on_bucket)?;
+        }
+
+        result.relaxations = relaxations;
+        Ok(result)
+    }
+
+    /// Applies the next relaxation to this search, returns `None` if it can't be relaxed more.
+    fn relax(&mut self) -> anyhow::Result<Option<Relaxation>> {
+        if self.soft_facet_condition.take().is_some() {
+            return Ok(Some(Relaxation::SoftFilterDropped));
+        }
+
+        let query = match &self.query {
+            Some(query) => query.clone(),
+            None => return Ok(None),
+        };
+
+        if self.authorize_typos && !self.increased_typos {
+            self.increased_typos = true;
+            return Ok(Some(Relaxation::TyposIncreased));
+        }
+
+        let stop_words = &self.index.stop_words(self.rtxn)?;
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words));
+        let result = analyzer.analyze(&query);
+        let mut words: Vec<_> = result.tokens()
+            .filter(|token| matches!(token.kind, TokenKind::Word))
+            .map(|token| token.word.to_string())
+            .collect();
+
+        if words.len() < 2 {
+            return Ok(None);
+        }
+
+        let mut rarest = None;
+        for (i, word) in words.iter().enumerate() {
+            let count = self.index.word_documents_count(self.rtxn, word)?.unwrap_or(0);
+            if rarest.map_or(true, |(_, c)| count < c) {
+                rarest = Some((i, count));
+            }
+        }
+
+        let word = words.remove(rarest.map_or(0, |(i, _)| i));
+        self.query = Some(words.join(" "));
+        Ok(Some(Relaxation::WordDropped(word)))
+    }
+
+    fn execute_once<F>(&self, deadline: Option<Instant>, on_bucket: &mut F) -> anyhow::Result<SearchResult>
+    where
+        F: FnMut(&[DocumentId]) -> anyhow::Result<()>,
+    {
+        // We check that the index is still in the state the snapshot was taken from.
+        let update_sequence = self.index.update_sequence(self.rtxn)?;
+        if let Some(snapshot) = self.snapshot {
+            if snapshot != update_sequence {
+                bail!(
+                    "The index has been updated since the snapshot {} was taken (current snapshot is {}).",
+                    snapshot, update_sequence,
+                );
+            }
+        }
+
+        let PreparedSearch { query_tree, matching_words, query_words, facet_candidates, boosts } = self.prepare()?;
+
+        // The documents are grouped like with a distinct attribute, a group can just have more documents.
+        let grouping = match &self.group_by {
+            Some(_) if self.after.is_some() => bail!("Can't paginate the groups of documents with a cursor."),
+            Some((name, max_per_group)) => Some((Distinct::new(self.rtxn, self.index, name)?, *max_per_group)),
+            None => None,
+        };
+
+        // The distinct attribute of the query overrides the one of the index.
+        let distinct = match &self.distinct {
+            _ if grouping.is_some() => None,
+            Some(name) => Some(Distinct::new(self.rtxn, self.index, name)?),
+            None => Distinct::from_index(self.rtxn, self.index)?,
+        };
+
+        let mut criteria_builder = criteria::CriteriaBuilder::new(self.rtxn, self.index)?;
+        criteria_builder.query_words(query_words);
+        criteria_builder.boosts(boosts);
+        criteria_builder.typo_candidates_limit(self.typo_candidates_limit);
+        criteria_builder.criteria(self.criteria.clone());
+        criteria_builder.custom_criteria(self.custom_criteria.clone());
+        criteria_builder.candidates_hint(facet_candidates.as_ref());
+        criteria_builder.searchable_attributes(self.restricted_attributes_ids()?);
+        criteria_builder.deadline(deadline);
+
+        // The groups count all the documents matching the search, not only the ranked ones.
+        let all_candidates = if self.exhaustive_total_hits || grouping.is_some() {
+            let candidates = match (&query_tree, &facet_candidates) {
+                (Some(qt), facet_candidates) => {
+                    let mut wdcache = WordDerivationsCache::new();
+                    let mut candidates = criteria::resolve_query_tree(&criteria_builder, qt, &mut HashMap::new(), &mut wdcache)?;
+                    if let Some(facet_candidates) = facet_candidates {
+                        candidates.intersect_with(facet_candidates);
+                    }
+                    candidates
+                },
+                (None, Some(facet_candidates)) => facet_candidates.clone(),
+                (None, None) => self.index.documents_ids(self.rtxn)?,
+            };
+            Some(candidates)
+        } else {
+            None
+        };
+
+        let mut criteria = criteria_builder.build(query_tree, facet_candidates, self.sort_criteria.clone())?;
+
+        let defaults = self.index.search_defaults(self.rtxn)?;
+        let mut offset = self.offset;
+        let mut limit = self.limit.or(defaults.limit).unwrap_or(DEFAULT_SEARCH_LIMIT);
+
+        // The documents returned after the max total hits can't be returned, the page is cut
+        // at this bound. The distinct attribute and the groups count as one hit per group.
+        let max_total_hits = self.index.max_total_hits(self.rtxn)?;
+        if let Some(max) = max_total_hits {
+            limit = limit.min(max.saturating_sub(offset));
+        }
+
+        // When no document can be removed after being ranked, the ones after the requested
+        // page are never returned, the parent criteria stop computing buckets once it is full,
+        // or are not even computed when the requested page is entirely after the max total hits.
+        // A zero limit still ranks the first bucket to count the candidates.
+        let filters_ranked = distinct.is_some() || grouping.is_some();
+        let mut max_ranked = None;
+        if !filters_ranked {
+            if let Some(max) = max_total_hits {
+                max_ranked = Some(if offset < max { max } else { 0 });
+            }
+
+            let page_end = offset.saturating_add(limit);
+            if page_end != 0 && self.after.is_none() {
+                max_ranked = Some(max_ranked.map_or(page_end, |max| max.min(page_end)));
+            }
+        }
+
+        if let Some(max) = max_ranked {
+            criteria.max_total_hits(max);
+        }
+
+        // The threshold is checked on the score of every bucket, the skipped ones must be ranked.
+        if self.skip_offset_ranking && !filters_ranked && self.ranking_score_threshold.is_none() {
+            criteria.skip_ranking(offset);
+        }
+
+        let mut documents_ids = Vec::new();
+        let mut documents_scores = Vec::new();
+        let mut documents_score_details = Vec::new();
+        let mut initial_candidates = RoaringBitmap::new();
+        // The groups of the documents that have already been seen.
+        let mut seen_groups = HashSet::new();
+        let collation = self.index.collation_strength(self.rtxn)?;
+        let mut passed_cursor = self.after.is_none();
+        let mut cursor = None;
+        let mut groups: Vec<DocumentsGroup> = Vec::new();
+        // The position of the groups in the returned ones, `None` for the groups skipped by the offset.
+        let mut groups_positions = HashMap::new();
+        // The number of returned groups that can still receive documents.
+        let mut open_groups = 0;
+        while let Some(result) = criteria.next()? {
+            let score = result.score();
+            if self.ranking_score_threshold.map_or(false, |threshold| score < threshold) {
+                break;
+            }
+
+            let FetcherResult { mut candidates, bucket_candidates, ranks, .. } = result;
+
+            debug!("Number of candidates found {}", candidates.len());
+
+            initial_candidates.union_with(&bucket_candidates);
+
+            // The documents ranked before the cursor have been returned in the previous pages,
+            // their groups are still seen so that no other document of these groups is returned.
+            if let Some(after) = self.after.as_ref().filter(|_| !passed_cursor) {
+                let returned = match after.cmp_bucket(&ranks, collation)? {
+                    Ordering::Less => take(&mut candidates),
+                    Ordering::Equal => {
+                        passed_cursor = true;
+                        candidates.iter().take_while(|docid| *docid <= after.docid()).collect()
+                    },
+                    Ordering::Greater => {
+                        passed_cursor = true;
+                        RoaringBitmap::new()
+                    },
+                };
+                candidates.difference_with(&returned);
+
+                if let Some(distinct) = &distinct {
+                    for docid in returned {
+                        seen_groups.insert(distinct.group(docid)?);
+                    }
+                }
+            }
+
+            let bucket_start = documents_ids.len();
+            match (&grouping, &distinct) {
+                (Some((grouping, max_per_group)), _) => {
+                    for docid in candidates {
+                        if limit == 0 && open_groups == 0 { break }
+
+                        let group = grouping.group(docid)?;
+                        match groups_positions.get(&group) {
+                            Some(Some(position)) => {
+                                let group: &mut DocumentsGroup = &mut groups[*position];
+                                if group.documents_ids.len() < *max_per_group {
+                                    group.documents_ids.push(docid);
+                                    documents_ids.push(docid);
+                                    if group.documents_ids.len() == *max_per_group {
+                                        open_groups -= 1;
+                                    }
+                                }
+                            },
+                            Some(None) => (),
+                            None if offset != 0 => {
+                                offset -= 1;
+                                groups_positions.insert(group, None);
+                            },
+                            None if limit != 0 => {
+                                limit -= 1;
+                                groups_positions.insert(group.clone(), Some(groups.len()));
+                                groups.push(DocumentsGroup { group, documents_ids: vec![docid], count: 0 });
+                                documents_ids.push(docid);
+                                if *max_per_group > 1 {
+                                    open_groups += 1;
+                                }
+                            },
+                            None => (),
+                        }
+                    }
+                },
+                (None, Some(distinct)) => {
+                    for docid in candidates {
+                        if limit == 0 { break }
+
+                        // The other documents of the group will not be returned.
+                        if !seen_groups.insert(distinct.group(docid)?) { continue }
+
+                        if offset != 0 {
+                            offset -= 1;
+                        } else {
+                            documents_ids.push(docid);
+                            limit -= 1;
+                        }
+                    }
+                },
+                (None, None) => {
+                    let mut len = candidates.len() as usize;
+                    let mut candidates = candidates.into_iter();
+
+                    if offset != 0 {
+                        candidates.by_ref().take(offset).for_each(drop);
+                        offset = offset.saturating_sub(len.min(offset));
+                        len = len.saturating_sub(len.min(offset));
+                    }
+
+                    if len != 0 {
+                        documents_ids.extend(candidates.take(limit));
+                        limit = limit.saturating_sub(len.min(limit));
+                    }
+                },
+            }
+
+            if documents_ids.len() != bucket_start {
+                cursor = documents_ids.last().and_then(|docid| Cursor::new(&ranks, *docid));
+                documents_scores.resize(documents_ids.len(), score);
+                if self.score_details {
+                    documents_score_details.resize(documents_ids.len(), ranks);
+                }
+                on_bucket(&documents_ids[bucket_start..])?;
+            }
+
+            if limit == 0 && open_groups == 0 { break }
+        }
+
+        if let (Some((grouping, _)), Some(candidates)) = (&grouping, &all_candidates) {
+            if !groups.is_empty() {
+                let groups_values: Vec<_> = groups.iter().map(|g| &g.group).collect();
+                let counts = grouping.groups_counts(&groups_values, candidates)?;
+                for (group, count) in groups.iter_mut().zip(counts) {
+                    group.count = count;
+                }
+            }
+        }
+
+        let total_hits = match all_candidates.filter(|_| self.exhaustive_total_hits) {
+            Some(candidates) => {
+                let count = match &distinct {
+                    Some(distinct) => {
+                        let mut groups = HashSet::new();
+                        for docid in &candidates {
+                            groups.insert(distinct.group(docid)?);
+                        }
+                        groups.len() as u64
+                    },
+                    None => candidates.len(),
+                };
+
+                initial_candidates = candidates;
+                TotalHits::Exhaustive(count)
+            },
+            None => TotalHits::Estimated(initial_candidates.len()),
+        };
+
+        let documents_facets = match &self.documents_facets {
+            Some(fields) => self.documents_facet_values(fields, &documents_ids)?,
+            None => Vec::new(),
+        };
+
+        let documents_matches = if self.matches_positions {
+            self.documents_matches(&matching_words, &documents_ids)?
+        } else {
+            Vec::new()
+        };
+
+        Ok(SearchResult {
+            matching_words,
+            candidates: initial_candidates,
+            documents_ids,
+            documents_scores,
+            documents_score_details,
+            documents_facets,
+            documents_matches,
+            total_hits,
+            groups,
+            cursor,
+            snapshot: update_sequence,
+            relaxations: Vec::new(),
+            degraded: criteria_builder.is_degraded(),
+        })
+    }
+
+    /// Returns the values of the given faceted fields for each of the documents, by field name.
+    fn documents_facet_values(
+        &self,
+        fields: &[String],
+        documents_ids: &[DocumentId],
+    ) -> anyhow::Result<Vec<BTreeMap<String, Vec<FacetValue>>>>
+    {
+        let fields_ids_map = self.index.fields_ids_map(self.rtxn)?;
+        let faceted_fields = self.index.faceted_fields(self.rtxn)?;
+
+        let mut facets = Vec::with_capacity(fields.len());
+        for name in fields {
+            let facet_type = *faceted_fields.get(name).with_context(|| {
+                format!("Can't return the values of {:?} as it isn't a faceted field.", name)
+            })?;
+            let field_id = fields_ids_map.id(name).with_context(|| {
+                format!("missing field name {:?} from the fields id map", name)
+            })?;
+            // The string values are returned as they were first seen in the documents.
+            let display_values = match facet_type {
+                FacetType::String => self.index.facet_string_display_values(self.rtxn, field_id)?,
+                _ => BTreeMap::new(),
+            };
+            facets.push((name, field_id, facet_type, display_values));
+        }
+
+        let mut documents_facets = Vec::with_capacity(documents_ids.len());
+        for docid in documents_ids {
+            let mut document_facets = BTreeMap::new();
+            for (name, field_id, facet_type, display_values) in &facets {
+                let values = document_facet_values(self.index, self.rtxn, *field_id, *facet_type, *docid)?;
+                let values = values.into_iter().map(|value| match value {
+                    FacetValue::String(string) => match display_values.get(&string) {
+                        Some(original) => FacetValue::String(original.clone()),
+                        None => FacetValue::String(string),
+                    },
+                    value => value,
+                }).collect();
+                document_facets.insert(name.to_string(), values);
+            }
+            documents_facets.push(document_facets);
+        }
+
+        Ok(documents_facets)
+    }
+
+    /// Returns the words of each of the documents that match the query, the words found in the
+    /// word positions of the documents are searched for in their fields to know their byte ranges.
+    fn documents_matches(
+        &self,
+        matching_words: &MatchingWords,
+        documents_ids: &[DocumentId],
+    ) -> anyhow::Result<Vec<Vec<MatchPosition>>>
+    {
+        let fields_ids_map = self.index.fields_ids_map(self.rtxn)?;
+        let stop_words = self.index.stop_words(self.rtxn)?;
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(&stop_words));
+
+        let mut documents_matches = Vec::with_capacity(documents_ids.len());
+        for (docid, document) in self.index.documents(self.rtxn, documents_ids.iter().copied())? {
+            // The indexes of the matching words in each attribute.
+            let mut attributes: BTreeMap<FieldId, HashSet<Position>> = BTreeMap::new();
+            for result in self.index.docid_word_positions.prefix_iter(self.rtxn, &(docid, ""))? {
+                let ((_, word), positions) = result?;
+                if matching_words.matches(word) {
+                    for position in positions {
+                        let (attribute, index) = extract_position(position);
+                        attributes.entry(attribute as FieldId).or_default().insert(index);
+                    }
+                }
+            }
+
+            let mut matches = Vec::new();
+            for (field_id, indexes) in attributes {
+                let field = fields_ids_map.name(field_id).with_context(|| {
+                    format!("missing field id {} from the fields id map", field_id)
+                })?;
+                let content = match document.get(field_id) {
+                    Some(bytes) => serde_json::from_slice::<serde_json::Value>(bytes).ok().as_ref().and_then(json_to_string),
+                    None => None,
+                };
+                let content = match content {
+                    Some(content) => content,
+                    None => continue,
+                };
+
+                // The words are found with the positions they have been indexed with, their text
+                // is checked too as the indexes saturate at the end of the long attributes.
+                let analyzed = analyzer.analyze(&content);
+                for (index, token) in process_tokens(analyzed.tokens()) {
+                    let index = index.min(MAX_INDEX_IN_ATTRIBUTE as usize) as Position;
+                    if indexes.contains(&index) && matching_words.matches(token.text()) {
+                        matches.push(MatchPosition {
+                            field: field.to_string(),
+                            start: token.byte_start,
+                            length: token.byte_end - token.byte_start,
+                        });
+                    }
+                }
+            }
+            documents_matches.push(matches);
+        }
+
+        Ok(documents_matches)
+    }
+
+    /// Returns, for every ranking rule in order, the number of documents of each bucket
+    /// it returned, the documents themselves are never fetched.
+    ///
+    /// All the buckets are computed, the offset, the limit and the distinct attribute are ignored.
+    pub fn bucket_counts(&self) -> anyhow::Result<Vec<CriterionBuckets>> {
+        let PreparedSearch { query_tree, query_words, facet_candidates, boosts, .. } = self.prepare()?;
+
+        let mut criteria_builder = criteria::CriteriaBuilder::new(self.rtxn, self.index)?;
+        criteria_builder.query_words(query_words);
+        criteria_builder.boosts(boosts);
+        criteria_builder.typo_candidates_limit(self.typo_candidates_limit);
+        criteria_builder.criteria(self.criteria.clone());
+        criteria_builder.custom_criteria(self.custom_criteria.clone());
+        criteria_builder.searchable_attributes(self.restricted_attributes_ids()?);
+        let (mut criteria, counters) = criteria_builder.build_with_bucket_counts(
+            query_tree,
+            facet_candidates,
+            self.sort_criteria.clone(),
+        )?;
+
+        while criteria.next()?.is_some() {}
+
+        let buckets = counters.into_iter().map(|(criterion, counts)| {
+            CriterionBuckets { criterion, buckets: counts.borrow().clone() }
+        });
+
+        Ok(buckets.collect())
+    }
+
+    /// Estimates the number of candidates and the database reads of the search without
+    /// executing it, callers can use it to reject or reroute the too expensive queries.
+    ///
+    /// The filters are evaluated to bound the number of candidates.
+    pub fn estimate_cost(&self) -> anyhow::Result<QueryCost> {
+        let PreparedSearch { query_tree, facet_candidates, .. } = self.prepare()?;
+        cost::estimate_cost(self.rtxn, self.index, query_tree.as_ref(), facet_candidates.as_ref())
+    }
+
+    /// Returns the typo tolerance applied to this search and to each of its query words,
+    /// it doesn't take into account the relaxations applied when nothing is found.
+    pub fn typo_details(&self) -> anyhow::Result<TypoDetails> {
+        let words = match self.query.as_ref() {
             Some(query) => {
                 let mut builder = QueryTreeBuilder::new(self.rtxn, self.index);
-                builder.optional_words(self.optional_words);
                 builder.authorize_typos(self.authorize_typos);
-                let stop_words = &Set::default();
+                let stop_words = &self.index.stop_words(self.rtxn)?;
                 let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words));
                 let result = analyzer.analyze(query);
-                let tokens = result.tokens();
-                builder.build(tokens)?
+                builder.word_typos(result.tokens())
             },
-            None => None,
+            None => Vec::new(),
         };
 
-        debug!("query tree: {:?} took {:.02?}", query_tree, before.elapsed());
+        let criteria = match &self.criteria {
+            Some(criteria) => criteria.clone(),
+            None => self.index.criteria(self.rtxn)?,
+        };
 
-        // We create the original candidates with the facet conditions results.
-        let before = Instant::now();
-        let facet_candidates = match &self.facet_condition {
-            Some(condition) => Some(condition.evaluate(self.rtxn, self.index)?),
-            None => None,
+        let typo_criterion_max = criteria.into_iter().find_map(|criterion| {
+            match criterion {
+                Criterion::Typo { max } => max,
+                _ => None,
+            }
+        });
+
+        let mut details = TypoDetails {
+            authorize_typos: self.authorize_typos,
+            one_typo_min_word_len: ONE_TYPO_MIN_WORD_LEN,
+            two_typos_min_word_len: TWO_TYPOS_MIN_WORD_LEN,
+            typo_criterion_max,
+            typo_candidates_limit: self.typo_candidates_limit,
+            words,
         };
 
-        debug!("facet candidates: {:?} took {:.02?}", facet_candidates, before.elapsed());
+        if self.increased_typos {
+            details.increase_typos();
+        }
+
+        Ok(details)
+    }
 
-        let matching_words = match query_tree.as_ref() {
-            Some(query_tree) => MatchingWords::from_query_tree(&query_tree),
-            None => MatchingWords::default(),
+    /// Returns the field ids of the attributes the query words are searched in, `None` for all of them.
+    fn searchable_attributes_ids(&self) -> anyhow::Result<Option<Vec<FieldId>>> {
+        let attributes = match &self.searchable_attributes {
+            Some(attributes) => attributes,
+            None => return Ok(None),
         };
 
-        let criteria_builder = criteria::CriteriaBuilder::new(self.rtxn, self.index)?;
-        let mut criteria = criteria_builder.build(query_tree, facet_candidates)?;
+        let fields_ids_map = self.index.fields_ids_map(self.rtxn)?;
+        let searchable_fields = self.index.searchable_fields(self.rtxn)?;
+        let mut ids = Vec::with_capacity(attributes.len());
+        for name in attributes {
+            if searchable_fields.as_ref().map_or(false, |fields| !fields.contains(&name.as_str())) {
+                bail!("Can't search in {:?} as it isn't a searchable attribute.", name);
+            }
+            // An attribute that no document contains yet has no field id.
+            if let Some(id) = fields_ids_map.id(name) {
+                ids.push(id);
+            }
+        }
 
-        let mut offset = self.offset;
-        let mut limit = self.limit;
-        let mut documents_ids = Vec::new();
-        let mut initial_candidates = RoaringBitmap::new();
-        while let Some(FetcherResult { candidates, bucket_candidates, .. }) = criteria.next()? {
+        Ok(Some(ids))
+    }
 
-            debug!("Number of candidates found {}", candidates.len());
+    /// Returns the field ids of the attributes the query words are searched in, the searchable
+    /// attributes of the search that are also in the languages of the search, `None` for all.
+    fn restricted_attributes_ids(&self) -> anyhow::Result<Option<Vec<FieldId>>> {
+        let attributes = self.searchable_attributes_ids()?;
+        let languages = match &self.languages {
+            Some(languages) => languages,
+            None => return Ok(attributes),
+        };
 
-            let mut len = candidates.len() as usize;
-            let mut candidates = candidates.into_iter();
+        let language_attributes = self.language_attributes(languages)?;
+        match attributes {
+            Some(ids) => Ok(Some(ids.into_iter().filter(|id| language_attributes.contains(id)).collect())),
+            None => Ok(Some(language_attributes)),
+        }
+    }
 
-            initial_candidates.union_with(&bucket_candidates);
+    /// Returns the attributes in which the query words can match for the given languages.
+    fn language_attributes(&self, languages: &[String]) -> anyhow::Result<Vec<FieldId>> {
+        let fields_ids_map = self.index.fields_ids_map(self.rtxn)?;
+        let attributes_languages = self.index.attributes_languages(self.rtxn)?;
+
+        let attributes = fields_ids_map.iter().filter(|(_, name)| {
+            attributes_languages.get(*name).map_or(true, |language| languages.contains(language))
+        });
 
-            if offset != 0 {
-                candidates.by_ref().take(offset).for_each(drop);
-                offset = offset.saturating_sub(len.min(offset));
-                len = len.saturating_sub(len.min(offset));
+        Ok(attributes.map(|(id, _)| id).collect())
+    }
+
+    /// Checks the sort expressions and computes the query tree and the facet candidates.
+    fn prepare(&self) -> anyhow::Result<PreparedSearch> {
+        // We check that the sort expressions only refer to sortable fields.
+        if let Some(sort_criteria) = &self.sort_criteria {
+            let sortable_fields = self.index.sortable_fields(self.rtxn)?;
+            for criterion in sort_criteria {
+                if !sortable_fields.contains(criterion.field()) {
+                    bail!("Can't sort on {:?} as it isn't a sortable field.", criterion.field());
+                }
             }
+        }
 
-            if len != 0 {
-                documents_ids.extend(candidates.take(limit));
-                limit = limit.saturating_sub(len.min(limit));
+        // We check that the ranking rules of the query only refer to faceted fields.
+        if let Some(criteria) = &self.criteria {
+            let faceted_fields = self.index.faceted_fields(self.rtxn)?;
+            for criterion in criteria {
+                match criterion {
+                    Criterion::Asc(field)
+                    | Criterion::Desc(field)
+                    | Criterion::Diversify { field, .. }
+                    | Criterion::Decay { field, .. } => {
+                        if !faceted_fields.contains_key(field) {
+                            bail!("Can't use {:?} as a criterion as it isn't a faceted field.", field);
+                        }
+                    },
+                    _ => (),
+                }
             }
+        }
 
-            if limit == 0 { break }
+        // We create the query tree by spliting the query into tokens.
+        let before = Instant::now();
+        let stop_words = &self.index.stop_words(self.rtxn)?;
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words));
+        let (query, mut excluded_terms) = match self.query.as_ref() {
+            Some(query) => {
+                let (query, excluded_terms) = extract_excluded_terms(&analyzer, query);
+                (Some(query), excluded_terms)
+            },
+            None => (None, Vec::new()),
+        };
+        let (query_tree, matching_words, query_words) = match query {
+            Some(query) => {
+                let mut builder = QueryTreeBuilder::new(self.rtxn, self.index);
+                let defaults = self.index.search_defaults(self.rtxn)?;
+                match (self.terms_matching_strategy, self.optional_words) {
+                    (Some(strategy), _) => builder.terms_matching_strategy(strategy),
+                    (None, Some(optional_words)) => builder.optional_words(optional_words),
+                    (None, None) => builder.terms_matching_strategy(defaults.resolved_terms_matching_strategy()),
+                };
+                builder.authorize_typos(self.authorize_typos);
+                builder.min_prefix_length(self.index.min_prefix_query_length(self.rtxn)?);
+                builder.max_ngram(self.index.max_query_ngram(self.rtxn)?);
+                builder.split_words(self.index.split_words_enabled(self.rtxn)?);
+                builder.split_words_budget(self.index.split_words_budget(self.rtxn)?);
+                builder.limits(self.query_limits);
+                builder.synonyms(self.synonyms.clone());
+                let result = analyzer.analyze(&query);
+                let (mut query_tree, mut matching_words, query_words) = if self.boolean_syntax {
+                    let BooleanQuery { query_tree, matching_words, query_words, excluded_words } =
+                        builder.build_boolean(result.tokens())?;
+                    excluded_terms.extend(excluded_words);
+                    (query_tree, matching_words, query_words)
+                } else {
+                    let query_words = result.tokens()
+                        .filter(|token| matches!(token.kind, TokenKind::Word))
+                        .map(|token| token.word.to_string())
+                        .collect();
+                    let matching_words = builder.matching_words(result.tokens())?;
+                    (builder.build(result.tokens())?, matching_words, query_words)
+                };
+                if let (true, Some(tree)) = (self.increased_typos, query_tree.as_mut()) {
+                    increase_typos(tree);
+                    matching_words.increase_typos();
+                }
+                (query_tree, matching_words, query_words)
+            },
+            None => (None, MatchingWords::default(), Vec::new()),
+        };
+
+        debug!("query tree: {:?} took {:.02?}", query_tree, before.elapsed());
+
+        // We create the original candidates with the facet conditions results.
+        let before = Instant::now();
+        let facet_candidates = match (&self.facet_condition, &self.soft_facet_condition) {
+            (Some(condition), Some(soft_condition)) => {
+                let mut candidates = condition.evaluate(self.rtxn, self.index)?;
+                candidates.intersect_with(&soft_condition.evaluate(self.rtxn, self.index)?);
+                Some(candidates)
+            },
+            (Some(condition), None) | (None, Some(condition)) => Some(condition.evaluate(self.rtxn, self.index)?),
+            (None, None) => None,
+        };
+
+        // The documents the search is restricted to are intersected like the facet candidates.
+        let facet_candidates = match (facet_candidates, &self.in_documents) {
+            (Some(candidates), Some(documents)) => Some(candidates & documents),
+            (None, Some(documents)) => Some(documents & self.index.documents_ids(self.rtxn)?),
+            (candidates, None) => candidates,
+        };
+
+        // The documents that contain an excluded term are removed from the candidates,
+        // the criteria start from the complement of the excluded documents.
+        for term in &self.excluded_terms {
+            let result = analyzer.analyze(term);
+            let words = result.tokens().filter(|token| matches!(token.kind, TokenKind::Word));
+            excluded_terms.extend(words.map(|token| token.word.to_string()));
+        }
+
+        let facet_candidates = if excluded_terms.is_empty() {
+            facet_candidates
+        } else {
+            let mut excluded = RoaringBitmap::new();
+            for word in &excluded_terms {
+                if let Some(docids) = self.index.word_docids.get(self.rtxn, word)? {
+                    excluded.union_with(&docids);
+                }
+            }
+            let mut candidates = match facet_candidates {
+                Some(candidates) => candidates,
+                None => self.index.documents_ids(self.rtxn)?,
+            };
+            candidates.difference_with(&excluded);
+            Some(candidates)
+        };
+
+        debug!("facet candidates: {:?} took {:.02?}", facet_candidates, before.elapsed());
+
+        let mut boosts = Vec::with_capacity(self.boosts.len());
+        for (condition, factor) in &self.boosts {
+            boosts.push((condition.evaluate(self.rtxn, self.index)?, *factor));
         }
 
-        Ok(SearchResult { matching_words, candidates: initial_candidates, documents_ids })
+        Ok(PreparedSearch { query_tree, matching_words, query_words, facet_candidates, boosts })
+    }
+}
+
+/// Removes the terms prefixed by a dash, e.g. `-word`, from the query and returns them
+/// normalized, a dash between two words, like in `wi-fi`, doesn't exclude anything.
+fn extract_excluded_terms<A: AsRef<[u8]>>(analyzer: &Analyzer<A>, query: &str) -> (String, Vec<String>) {
+    let mut cleaned = String::with_capacity(query.len());
+    let mut excluded_terms = Vec::new();
+    let mut kept_from = 0;
+    let mut negation = None;
+    let mut quoted = false;
+
+    let result = analyzer.analyze(query);
+    for token in result.tokens() {
+        match token.kind {
+            TokenKind::Separator(_) => {
+                let text = &query[token.byte_start..token.byte_end];
+                if text.chars().filter(|&c| c == '"').count() % 2 != 0 {
+                    quoted = !quoted;
+                }
+                negation = None;
+                if !quoted && text.ends_with('-') {
+                    let dash = token.byte_end - 1;
+                    if query[..dash].chars().last().map_or(true, char::is_whitespace) {
+                        negation = Some(dash);
+                    }
+                }
+            },
+            TokenKind::Word => {
+                if let Some(dash) = negation.take() {
+                    cleaned.push_str(&query[kept_from..dash]);
+                    kept_from = token.byte_end;
+                    excluded_terms.push(token.word.to_string());
+                }
+            },
+            _ => negation = None,
+        }
     }
+
+    cleaned.push_str(&query[kept_from..]);
+    (cleaned, excluded_terms)
+}
+
+struct PreparedSearch {
+    query_tree: Option<Operation>,
+    matching_words: MatchingWords,
+    query_words: Vec<String>,
+    facet_candidates: Option<RoaringBitmap>,
+    /// The documents that satisfy the facet condition of every boost, with its factor.
+    boosts: Vec<(RoaringBitmap, u32)>,
 }
 
 impl fmt::Debug for Search<'_> {
@@ -155,20 +1216,74 @@ impl fmt::Debug for Search<'_> {
         let Search {
             query,
             facet_condition,
+            soft_facet_condition,
+            in_documents,
+            excluded_terms,
+            boolean_syntax,
+            query_limits,
+            boosts,
+            sort_criteria,
+            criteria,
+            custom_criteria,
+            snapshot,
+            distinct,
+            group_by,
+            languages,
+            searchable_attributes,
+            synonyms,
+            documents_facets,
+            matches_positions,
+            score_details,
+            ranking_score_threshold,
             offset,
+            after,
+            skip_offset_ranking,
             limit,
             optional_words,
+            terms_matching_strategy,
             authorize_typos,
+            typo_candidates_limit,
+            relax_on_empty,
+            time_budget,
+            exhaustive_total_hits,
+            increased_typos,
             rtxn: _,
             index: _,
         } = self;
         f.debug_struct("Search")
             .field("query", query)
             .field("facet_condition", facet_condition)
+            .field("soft_facet_condition", soft_facet_condition)
+            .field("in_documents", &in_documents.as_ref().map(|documents| documents.len()))
+            .field("excluded_terms", excluded_terms)
+            .field("boolean_syntax", boolean_syntax)
+            .field("query_limits", query_limits)
+            .field("boosts", boosts)
+            .field("sort_criteria", sort_criteria)
+            .field("criteria", criteria)
+            .field("custom_criteria", &custom_criteria.keys().collect::<Vec<_>>())
+            .field("snapshot", snapshot)
+            .field("distinct", distinct)
+            .field("group_by", group_by)
+            .field("languages", languages)
+            .field("searchable_attributes", searchable_attributes)
+            .field("synonyms", synonyms)
+            .field("documents_facets", documents_facets)
+            .field("matches_positions", matches_positions)
+            .field("score_details", score_details)
+            .field("ranking_score_threshold", ranking_score_threshold)
             .field("offset", offset)
+            .field("after", after)
+            .field("skip_offset_ranking", skip_offset_ranking)
             .field("limit", limit)
             .field("optional_words", optional_words)
+            .field("terms_matching_strategy", terms_matching_strategy)
             .field("authorize_typos", authorize_typos)
+            .field("typo_candidates_limit", typo_candidates_limit)
+            .field("relax_on_empty", relax_on_empty)
+            .field("time_budget", time_budget)
+            .field("exhaustive_total_hits", exhaustive_total_hits)
+            .field("increased_typos", increased_typos)
             .finish()
     }
 }
@@ -176,9 +1291,163 @@ impl fmt::Debug for Search<'_> {
 #[derive(Default)]
 pub struct SearchResult {
     pub matching_words: MatchingWords,
+    /// The documents matching the search that have been seen while ranking the returned ones,
+    /// or all of them when the total hits are exhaustive, see `Search::exhaustive_total_hits`.
     pub candidates: RoaringBitmap,
-    // TODO those documents ids should be associated with their criteria scores.
     pub documents_ids: Vec<DocumentId>,
+    /// The relevancy score, between `0` and `1`, of every returned document in the same order
+    /// as the documents ids, it only depends on the buckets of the ranking rules the document
+    /// has been found in, the rules that don't sort by relevancy (e.g. `Asc`) are ignored.
+    pub documents_scores: Vec<f64>,
+    /// The ranks of every returned document for each ranking rule in order, when
+    /// requested with `Search::score_details`, in the same order as the documents ids.
+    pub documents_score_details: Vec<Vec<Rank>>,
+    /// The values of the faceted fields requested with `Search::documents_facets`
+    /// of every returned document, in the same order as the documents ids.
+    pub documents_facets: Vec<BTreeMap<String, Vec<FacetValue>>>,
+    /// The words matching the query of every returned document, when requested with
+    /// `Search::matches_positions`, in the same order as the documents ids.
+    pub documents_matches: Vec<Vec<MatchPosition>>,
+    /// The number of documents matching the search, once deduplicated by the distinct attribute.
+    pub total_hits: TotalHits,
+    /// The groups of documents, when requested with `Search::group_by`, ordered by their best
+    /// document. The documents ids are then the documents of all the groups in ranking order.
+    pub groups: Vec<DocumentsGroup>,
+    /// The position of the last returned document, to give to `Search::after` to get the next
+    /// page, `None` when no document is returned or when the documents are sorted randomly.
+    pub cursor: Option<Cursor>,
+    /// The update sequence of the index these results were computed from,
+    /// to give to `Search::snapshot` when requesting the next pages.
+    pub snapshot: u64,
+    /// The relaxations, in order, that have been applied to find these documents.
+    pub relaxations: Vec<Relaxation>,
+    /// Whether the time budget of the search has been spent before all the requested
+    /// documents were ranked, some of them are then in a less refined order.
+    pub degraded: bool,
+}
+
+/// A word of a document that matches the query, the byte range is the one of the word in
+/// the text of the field, the arrays and the objects are concatenated like when indexed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchPosition {
+    pub field: String,
+    pub start: usize,
+    pub length: usize,
+}
+
+/// Documents that share the same value for the field the search groups the documents by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentsGroup {
+    pub group: DistinctGroup,
+    /// The best documents of the group, in ranking order.
+    pub documents_ids: Vec<DocumentId>,
+    /// The number of documents of the group that match the search.
+    pub count: u64,
+}
+
+/// The number of documents matching a search, either counted exactly or estimated
+/// from the documents seen while ranking the returned ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TotalHits {
+    Exhaustive(u64),
+    Estimated(u64),
+}
+
+impl TotalHits {
+    pub fn count(&self) -> u64 {
+        match self {
+            TotalHits::Exhaustive(count) | TotalHits::Estimated(count) => *count,
+        }
+    }
+
+    pub fn is_exhaustive(&self) -> bool {
+        matches!(self, TotalHits::Exhaustive(_))
+    }
+}
+
+impl Default for TotalHits {
+    fn default() -> TotalHits {
+        TotalHits::Estimated(0)
+    }
+}
+
+/// The search parameters stored in the index settings, they are used by the searches
+/// that don't define them, so that the clients don't have to repeat them.
+///
+/// The engine only uses the limit, the optional words and the terms matching strategy,
+/// the other parameters are stored for the layers that format the documents.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchDefaults {
+    pub limit: Option<usize>,
+    pub optional_words: Option<bool>,
+    /// Overrides the optional words, like `Search::terms_matching_strategy` does.
+    pub terms_matching_strategy: Option<TermsMatchingStrategy>,
+    pub crop_length: Option<usize>,
+    pub attributes_to_retrieve: Option<Vec<String>>,
+    pub attributes_to_highlight: Option<Vec<String>>,
+}
+
+impl SearchDefaults {
+    /// Returns the terms matching strategy of the searches that define neither
+    /// a strategy nor the optional words.
+    pub(crate) fn resolved_terms_matching_strategy(&self) -> TermsMatchingStrategy {
+        match (self.terms_matching_strategy, self.optional_words) {
+            (Some(strategy), _) => strategy,
+            (None, Some(false)) => TermsMatchingStrategy::All,
+            (None, _) => TermsMatchingStrategy::Last,
+        }
+    }
+}
+
+/// A relaxation applied to a search that didn't find any document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Relaxation {
+    /// The soft facet condition has been ignored.
+    SoftFilterDropped,
+    /// The words of the query have been given one more typo.
+    TyposIncreased,
+    /// The rarest word of the query has been removed.
+    WordDropped(String),
+}
+
+/// The typo tolerance applied to a search, see `Search::typo_details`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypoDetails {
+    pub authorize_typos: bool,
+    /// The minimum length of a query word to accept one typo.
+    pub one_typo_min_word_len: usize,
+    /// The minimum length of a query word to accept two typos.
+    pub two_typos_min_word_len: usize,
+    /// The documents with at least this number of typos are returned in
+    /// the same bucket by the typo ranking rule.
+    pub typo_criterion_max: Option<u8>,
+    pub typo_candidates_limit: Option<u64>,
+    /// The number of typos accepted for every word of the query, in order.
+    pub words: Vec<WordTypos>,
+}
+
+impl TypoDetails {
+    /// Gives one more typo to the words that are not part of a phrase, as
+    /// done by the search when it has been relaxed with `Relaxation::TyposIncreased`.
+    pub fn increase_typos(&mut self) {
+        for word in self.words.iter_mut().filter(|word| word.reason != TyposReason::Phrase) {
+            word.max_typos = (word.max_typos + 1).min(2);
+            word.reason = TyposReason::Increased;
+        }
+    }
+}
+
+/// The number of documents of each bucket returned by a ranking rule, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CriterionBuckets {
+    pub criterion: Criterion,
+    pub buckets: Vec<u64>,
 }
 
 pub type WordDerivationsCache = HashMap<(String, bool, u8), Vec<(String, u8)>>;
@@ -194,14 +1463,27 @@ pub fn word_derivations<'c>(
     match cache.entry((word.to_string(), is_prefix, max_typo)) {
         Entry::Occupied(entry) => Ok(entry.into_mut()),
         Entry::Vacant(entry) => {
+            // The automatons count the edits on characters, a grapheme cluster made of multiple
+            // characters (e.g. a letter followed by a combining accent) would cost multiple typos,
+            // we therefore widen the search and compute the distance on the grapheme clusters.
+            let has_clusters = max_typo != 0 && word.graphemes(true).count() != word.chars().count();
+            let dfa_typo = if has_clusters { 2 } else { max_typo };
+
             let mut derived_words = Vec::new();
-            let dfa = build_dfa(word, max_typo, is_prefix);
+            let dfa = build_dfa(word, dfa_typo, is_prefix);
             let mut stream = fst.search_with_state(&dfa).into_stream();
 
-            while let Some((word, state)) = stream.next() {
-                let word = std::str::from_utf8(word)?;
-                let distance = dfa.distance(state);
-                derived_words.push((word.to_string(), distance.to_u8()));
+            while let Some((derived, state)) = stream.next() {
+                let derived = std::str::from_utf8(derived)?;
+                let distance = if has_clusters {
+                    grapheme_distance(word, derived, is_prefix)
+                } else {
+                    dfa.distance(state).to_u8()
+                };
+
+                if distance <= max_typo {
+                    derived_words.push((derived.to_string(), distance));
+                }
             }
 
             Ok(entry.insert(derived_words))
@@ -209,6 +1491,41 @@ pub fn word_derivations<'c>(
     }
 }
 
+/// Computes the number of typos between the grapheme clusters of the two words, a typo being
+/// an insertion, a deletion, a substitution or a transposition of two adjacent clusters.
+///
+/// When `is_prefix` is `true` the best prefix of the derived word is compared to the query word.
+fn grapheme_distance(query: &str, derived: &str, is_prefix: bool) -> u8 {
+    let query: Vec<_> = query.graphemes(true).collect();
+    let derived: Vec<_> = derived.graphemes(true).collect();
+
+    // matrix[i][j] is the distance between the i first query clusters and the j first derived ones.
+    let mut matrix = vec![vec![0usize; derived.len() + 1]; query.len() + 1];
+    for (i, row) in matrix.iter_mut().enumerate() { row[0] = i }
+    matrix[0] = (0..=derived.len()).collect();
+
+    for i in 1..=query.len() {
+        for j in 1..=derived.len() {
+            let cost = if query[i - 1] == derived[j - 1] { 0 } else { 1 };
+            let mut distance = (matrix[i - 1][j] + 1)
+                .min(matrix[i][j - 1] + 1)
+                .min(matrix[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && query[i - 1] == derived[j - 2] && query[i - 2] == derived[j - 1] {
+                distance = distance.min(matrix[i - 2][j - 2] + 1);
+            }
+            matrix[i][j] = distance;
+        }
+    }
+
+    let distance = if is_prefix {
+        matrix[query.len()].iter().copied().min().unwrap_or(0)
+    } else {
+        matrix[query.len()][derived.len()]
+    };
+
+    distance.min(u8::MAX as usize) as u8
+}
+
 pub fn build_dfa(word: &str, typos: u8, is_prefix: bool) -> DFA {
     let lev = match typos {
         0 => &LEVDIST0,
@@ -222,3 +1539,657 @@ pub fn build_dfa(word: &str, typos: u8, is_prefix: bool) -> DFA {
         lev.build_dfa(word)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::index::tests::TempIndex;
+    use super::*;
+
+    #[test]
+    fn grapheme_clusters_typos() {
+        // An "e" followed by a combining grave accent is a single grapheme cluster.
+        let query = "e\u{300}cole";
+        assert_eq!(grapheme_distance(query, "\u{e8}cole", false), 1);
+        assert_eq!(grapheme_distance(query, "ecole", false), 1);
+        assert_eq!(grapheme_distance(query, "ecoles", false), 2);
+        assert_eq!(grapheme_distance(query, "ecoles", true), 1);
+
+        let fst = Set::from_iter(vec!["ecole", "ecoles", "\u{e8}cole"]).unwrap().map_data(Cow::Owned).unwrap();
+        let mut cache = WordDerivationsCache::new();
+        let words = word_derivations(query, false, 1, &fst, &mut cache).unwrap();
+        assert_eq!(words, &[(s("ecole"), 1), (s("\u{e8}cole"), 1)][..]);
+    }
+
+    #[test]
+    fn query_synonyms() {
+        use maplit::hashmap;
+
+        let content = &b"id,name\n0,new york city\n1,nyc subway\n2,paris\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let sorted = |mut ids: Vec<DocumentId>| { ids.sort_unstable(); ids };
+
+        let result = index.search(&rtxn).query("nyc").execute().unwrap();
+        assert_eq!(sorted(result.documents_ids), vec![1]);
+
+        let result = index.search(&rtxn)
+            .query("nyc")
+            .synonyms(hashmap!{ s("NYC") => vec![s("New York")] })
+            .execute()
+            .unwrap();
+        assert_eq!(sorted(result.documents_ids), vec![0, 1]);
+
+        // The synonyms can be given for a sequence of words of the query.
+        let result = index.search(&rtxn)
+            .query("big apple")
+            .synonyms(hashmap!{ s("big apple") => vec![s("nyc")] })
+            .execute()
+            .unwrap();
+        assert_eq!(sorted(result.documents_ids), vec![1]);
+
+        // They are only used by the search they are given to.
+        let result = index.search(&rtxn).query("nyc").execute().unwrap();
+        assert_eq!(sorted(result.documents_ids), vec![1]);
+    }
+
+    #[test]
+    fn documents_facets() {
+        use heed::EnvOpenOptions;
+        use maplit::hashmap;
+        use crate::update::{IndexDocuments, Settings, UpdateFormat};
+
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{
+            s("color") => s("string"),
+            s("price") => s("integer"),
+        });
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = &br#"[
+            { "id": 0, "name": "shirt", "color": ["Red", "Blue"], "price": 20 },
+            { "id": 1, "name": "pants", "color": "Blue", "price": 35 }
+        ]"#[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Json);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn)
+            .query("shirt")
+            .documents_facets(vec![s("color"), s("price")])
+            .execute()
+            .unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        assert_eq!(result.documents_facets.len(), 1);
+        let facets = &result.documents_facets[0];
+        assert_eq!(facets["color"], vec![FacetValue::from("blue"), FacetValue::from("red")]);
+        assert_eq!(facets["price"], vec![FacetValue::Integer(20)]);
+
+        // The values are only returned when they are requested.
+        let result = index.search(&rtxn).query("shirt").execute().unwrap();
+        assert!(result.documents_facets.is_empty());
+
+        // Only the faceted fields can be requested.
+        assert!(index.search(&rtxn).documents_facets(vec![s("name")]).execute().is_err());
+    }
+
+    #[test]
+    fn ranking_scores() {
+        let content = &b"id,title\n0,hello world\n1,hello big world\n2,helo world\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("hello world").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1, 2]);
+
+        // The best document has the maximum score and the scores decrease with the buckets.
+        let scores = result.documents_scores;
+        assert_eq!(scores.len(), 3);
+        assert_eq!(scores[0], 1.0);
+        assert!(scores[0] > scores[1] && scores[1] > scores[2] && scores[2] > 0.0);
+    }
+
+    #[test]
+    fn ranking_score_threshold() {
+        let content = &b"id,title\n0,hello world\n1,hello big world\n2,helo world\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("hello world").execute().unwrap();
+        let scores = result.documents_scores;
+
+        // The document with a typo has the lowest score, it is dropped.
+        let threshold = (scores[1] + scores[2]) / 2.0;
+        let result = index.search(&rtxn).query("hello world").ranking_score_threshold(threshold).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1]);
+
+        let result = index.search(&rtxn).query("hello world").ranking_score_threshold(0.0).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn score_details() {
+        use crate::DEFAULT_ATTRIBUTE_WEIGHT;
+
+        let content = &b"id,title\n0,hello world\n1,hello big world\n2,helo world\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("hello world").execute().unwrap();
+        assert!(result.documents_score_details.is_empty());
+
+        let result = index.search(&rtxn).query("hello world").score_details(true).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1, 2]);
+        let details = result.documents_score_details;
+        assert_eq!(details.len(), 3);
+
+        // The default ranking rules are typo, words, proximity, attribute, words position and exactness.
+        assert_eq!(details[0][0].detail, ScoreDetail::Typo { typos: 0 });
+        assert_eq!(details[0][1].detail, ScoreDetail::Words { matching_words: 2 });
+        assert_eq!(details[0][3].detail, ScoreDetail::Attribute { weight: DEFAULT_ATTRIBUTE_WEIGHT });
+        assert_eq!(details[0][4].detail, ScoreDetail::WordsPosition { position: 0 });
+        assert_eq!(details[0][5].detail, ScoreDetail::Exactness { exactness: ExactMatch::Equal });
+        assert_eq!(details[2][0].detail, ScoreDetail::Typo { typos: 1 });
+        assert!(details[0][2].rank < details[1][2].rank);
+    }
+
+    #[test]
+    fn boost() {
+        use maplit::hashmap;
+
+        let content = &b"id,title,promoted\n0,hello world,0\n1,hello big world,1\n2,helo world,1\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_faceted_fields(hashmap!{ "promoted".into() => "integer".into() });
+        });
+
+        let rtxn = index.read_txn().unwrap();
+        let promoted = FacetCondition::from_str(&rtxn, &index, "promoted = 1").unwrap();
+
+        // The promoted documents are returned first, ordered by relevancy.
+        let result = index.search(&rtxn).query("hello world").boost(promoted.clone(), 1).score_details(true).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 2, 0]);
+        assert_eq!(result.documents_score_details[0][0].detail, ScoreDetail::Boost { factor: 1 });
+        assert_eq!(result.documents_score_details[2][0].detail, ScoreDetail::Boost { factor: 0 });
+
+        // A boost without factor doesn't change the order.
+        let result = index.search(&rtxn).query("hello world").boost(promoted, 0).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn custom_criterion() {
+        use super::criteria::{Context, Criterion as _, CriterionResult};
+
+        /// Returns the documents with an even id before the others.
+        struct EvenFirst<'t> {
+            ctx: &'t dyn Context,
+            parent: Box<dyn criteria::Criterion + 't>,
+            odd: Option<CriterionResult>,
+        }
+
+        impl criteria::Criterion for EvenFirst<'_> {
+            fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+                if let Some(odd) = self.odd.take() {
+                    return Ok(Some(odd));
+                }
+
+                let mut result = match self.parent.next(wdcache)? {
+                    Some(result) => result,
+                    None => return Ok(None),
+                };
+
+                let candidates = match result.candidates.take() {
+                    Some(candidates) => candidates,
+                    None => self.ctx.documents_ids()?,
+                };
+                let even: RoaringBitmap = candidates.iter().filter(|id| id % 2 == 0).collect();
+                let odd = candidates - &even;
+
+                let mut odd_ranks = result.ranks.clone();
+                odd_ranks.push(Rank::new(1, 1, ScoreDetail::None));
+                self.odd = Some(CriterionResult {
+                    query_tree: result.query_tree.clone(),
+                    candidates: Some(odd),
+                    bucket_candidates: RoaringBitmap::new(),
+                    ranks: odd_ranks,
+                });
+
+                result.candidates = Some(even);
+                result.ranks.push(Rank::new(0, 1, ScoreDetail::None));
+                Ok(Some(result))
+            }
+        }
+
+        let content = &b"id,title\n0,hello\n1,hello\n2,helo\n3,hello\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let mut search = index.search(&rtxn);
+        search.query("hello");
+        search.register_criterion("evenFirst", |ctx, parent| {
+            Ok(Box::new(EvenFirst { ctx, parent, odd: None }))
+        });
+
+        // As the first ranking rule, then refining the buckets of the typo ranking rule.
+        let result = search.criteria(vec![Criterion::Custom("evenFirst".into())]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 2, 1, 3]);
+        let result = search.criteria(vec![Criterion::Typo { max: None }, Criterion::Custom("evenFirst".into())]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1, 3, 2]);
+
+        let result = search.criteria(vec![Criterion::Custom("unknown".into())]).execute();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn placeholder_search() {
+        use maplit::hashmap;
+
+        let content = &b"id,title,price\n0,hello,10\n1,world,40\n2,hello world,20\n3,hello,30\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_faceted_fields(hashmap!{ "price".into() => "integer".into() });
+            builder.set_criteria(vec!["typo".into(), "words".into(), "proximity".into(), "desc(price)".into()]);
+        });
+
+        // The textual ranking rules return a single bucket, the documents are sorted by price.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 3, 2, 0]);
+        assert_eq!(result.candidates.len(), 4);
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "price < 35").unwrap();
+        let result = index.search(&rtxn).facet_condition(condition).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![3, 2, 0]);
+    }
+
+    #[test]
+    fn in_documents() {
+        let content = &b"id,title\n0,hello\n1,hello world\n2,world\n3,hello\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let allowed: RoaringBitmap = vec![1, 2, 3].into_iter().collect();
+        let result = index.search(&rtxn).query("hello").in_documents(allowed.clone()).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![3, 1]);
+        assert_eq!(result.candidates.len(), 2);
+
+        // A placeholder search only returns the allowed documents.
+        let result = index.search(&rtxn).in_documents(allowed.clone()).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 2, 3]);
+        drop(rtxn);
+
+        // The allowed documents that were deleted or never existed are ignored.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = crate::update::DeleteDocuments::new(&mut wtxn, &index, 1).unwrap();
+        builder.delete_document(3);
+        builder.execute().unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let allowed = allowed | (10..12).collect::<RoaringBitmap>();
+        let result = index.search(&rtxn).in_documents(allowed).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 2]);
+        assert_eq!(result.candidates.len(), 2);
+    }
+
+    #[test]
+    fn query_limits() {
+        use maplit::hashmap;
+
+        let content = &b"id,title\n0,hello world\n1,hello\n2,hallo hullo\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        // There are no limits by default.
+        let rtxn = index.read_txn().unwrap();
+        let query = vec!["hello"; 17].join(" ");
+        assert!(index.search(&rtxn).query(&query).execute().is_ok());
+
+        let limits = QueryLimits { max_terms: 16, ..QueryLimits::default() };
+        let error = index.search(&rtxn).query(query).query_limits(limits).execute().unwrap_err();
+        let expected = QueryTooComplex::TooManyTerms { terms: 17, max: 16 };
+        assert_eq!(error.downcast_ref::<QueryTooComplex>(), Some(&expected));
+
+        let limits = QueryLimits { max_depth: 3, ..QueryLimits::default() };
+        let error = index.search(&rtxn).query("hello world").query_limits(limits).execute().unwrap_err();
+        assert!(matches!(error.downcast_ref::<QueryTooComplex>(), Some(QueryTooComplex::TooDeep { max: 3, .. })));
+
+        // "hello" is derived into "hello", "hallo" and "hullo" with one typo.
+        let limits = QueryLimits { max_derivations: 2, ..QueryLimits::default() };
+        let error = index.search(&rtxn).query("hello").query_limits(limits).execute().unwrap_err();
+        let expected = QueryTooComplex::TooManyDerivations { word: "hello".to_string(), derivations: 3, max: 2 };
+        assert_eq!(error.downcast_ref::<QueryTooComplex>(), Some(&expected));
+
+        let limits = QueryLimits { max_derivations: 4, ..QueryLimits::default() };
+        let synonyms = hashmap!{ "hello".to_string() => vec!["hi".to_string(), "hey".to_string()] };
+        let error = index.search(&rtxn).query("hello").synonyms(synonyms).query_limits(limits).execute().unwrap_err();
+        let expected = QueryTooComplex::TooManyDerivations { word: "hello".to_string(), derivations: 5, max: 4 };
+        assert_eq!(error.downcast_ref::<QueryTooComplex>(), Some(&expected));
+
+        // The queries within the limits are executed as usual.
+        let result = index.search(&rtxn).query("hello world").query_limits(limits).execute().unwrap();
+        assert_eq!(result.documents_ids[0], 0);
+    }
+
+    #[test]
+    fn excluded_terms() {
+        let content = &b"id,title\n0,hello\n1,hello world\n2,world\n3,hello wi-fi\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("hello -world").execute().unwrap();
+        let mut documents_ids = result.documents_ids;
+        documents_ids.sort_unstable();
+        assert_eq!(documents_ids, vec![0, 3]);
+        assert_eq!(result.candidates.len(), 2);
+
+        // A dash between two words doesn't exclude anything.
+        let result = index.search(&rtxn).query("wi-fi").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![3]);
+
+        // A query made of excluded terms is a placeholder search without their documents.
+        let result = index.search(&rtxn).query("-hello").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![2]);
+
+        let result = index.search(&rtxn).excluded_terms(vec!["Wi".to_string()]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn time_budget() {
+        let content = &b"id,title\n0,helo\n1,hello\n2,helo\n3,hello\n4,hello\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let mut search = index.search(&rtxn);
+        search.query("hello").criteria(vec![Criterion::Typo { max: None }]);
+
+        let result = search.execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 3, 4, 0, 2]);
+        assert!(!result.degraded);
+
+        // The budget is already spent, the documents with and without typos are returned together.
+        let result = search.time_budget(Duration::from_secs(0)).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1, 2, 3, 4]);
+        assert!(result.degraded);
+
+        // The bucket given by the parent criterion is not split either, no document is dropped.
+        search.criteria(vec![Criterion::Words, Criterion::Typo { max: None }]);
+        let result = search.execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1, 2, 3, 4]);
+        assert!(result.degraded);
+    }
+
+    #[test]
+    fn exhaustive_total_hits() {
+        let content = &b"id,title\n0,helo\n1,hello\n2,helo\n3,hello\n4,hello\n5,world\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let mut search = index.search(&rtxn);
+        search.query("hello").criteria(vec![Criterion::Typo { max: None }]).limit(1);
+
+        // Only the documents without typos have been seen.
+        let result = search.execute().unwrap();
+        assert_eq!(result.total_hits, TotalHits::Estimated(3));
+
+        let result = search.exhaustive_total_hits(true).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1]);
+        assert_eq!(result.total_hits, TotalHits::Exhaustive(5));
+        assert_eq!(result.candidates.len(), 5);
+    }
+
+    #[test]
+    fn searchable_attributes() {
+        let content = &b"id,title,body\n0,hello,world\n1,world,hello\n2,help,nothing\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_searchable_fields(vec![s("title"), s("body")]);
+        });
+
+        let rtxn = index.read_txn().unwrap();
+        let sorted = |mut ids: Vec<DocumentId>| { ids.sort_unstable(); ids };
+
+        let result = index.search(&rtxn).query("hello").execute().unwrap();
+        assert_eq!(sorted(result.documents_ids), vec![0, 1]);
+
+        let result = index.search(&rtxn).query("hello").searchable_attributes(&["title"]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        assert_eq!(result.candidates.len(), 1);
+
+        // The prefixes are searched in the attributes too.
+        let result = index.search(&rtxn).query("hel").searchable_attributes(&["title"]).execute().unwrap();
+        assert_eq!(sorted(result.documents_ids), vec![0, 2]);
+        let result = index.search(&rtxn).query("hel").searchable_attributes(&["body"]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1]);
+
+        // Only the searchable attributes can be searched.
+        assert!(index.search(&rtxn).query("hello").searchable_attributes(&["id"]).execute().is_err());
+    }
+
+    #[test]
+    fn matches_positions() {
+        use heed::EnvOpenOptions;
+        use crate::update::{IndexDocuments, UpdateFormat};
+
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &br#"[
+            { "id": 0, "title": "Le Petit Prince", "body": "the little prince meets a fox" }
+        ]"#[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Json);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let position = |field: &str, start, length| MatchPosition { field: s(field), start, length };
+
+        // A word with a typo and a prefix.
+        let result = index.search(&rtxn).query("prinse fo").matches_positions(true).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        assert_eq!(result.documents_matches, vec![vec![
+            position("title", 9, 6),
+            position("body", 11, 6),
+            position("body", 26, 3),
+        ]]);
+
+        // The matches are only returned when they are requested.
+        let result = index.search(&rtxn).query("prinse fo").execute().unwrap();
+        assert!(result.documents_matches.is_empty());
+    }
+
+    #[test]
+    fn group_by() {
+        use maplit::hashmap;
+
+        let content = &b"id,brand,price\n0,apple,50\n1,apple,40\n2,apple,30\n3,sony,45\n4,lg,20\n5,sony,10\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_faceted_fields(hashmap!{ "brand".into() => "string".into(), "price".into() => "integer".into() });
+            builder.set_criteria(vec!["desc(price)".into()]);
+        });
+
+        let rtxn = index.read_txn().unwrap();
+        let group = |brand: &str| DistinctGroup::Values(vec![FacetValue::String(brand.into())]);
+
+        let result = index.search(&rtxn).group_by("brand", 2).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 3, 1, 4, 5]);
+        assert_eq!(result.groups, vec![
+            DocumentsGroup { group: group("apple"), documents_ids: vec![0, 1], count: 3 },
+            DocumentsGroup { group: group("sony"), documents_ids: vec![3, 5], count: 2 },
+            DocumentsGroup { group: group("lg"), documents_ids: vec![4], count: 1 },
+        ]);
+
+        // The offset and the limit count the groups.
+        let result = index.search(&rtxn).group_by("brand", 1).offset(1).limit(1).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![3]);
+        assert_eq!(result.groups, vec![
+            DocumentsGroup { group: group("sony"), documents_ids: vec![3], count: 2 },
+        ]);
+    }
+
+    #[test]
+    fn cursor_pagination() {
+        use maplit::hashmap;
+        use crate::update::{IndexDocuments, UpdateFormat};
+
+        let content = &b"id,price\n0,10\n1,40\n2,20\n3,30\n4,20\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_faceted_fields(hashmap!{ "price".into() => "integer".into() });
+            builder.set_criteria(vec!["desc(price)".into()]);
+        });
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).limit(2).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 3]);
+        let cursor = result.cursor.unwrap();
+        drop(rtxn);
+
+        // A document is added before the cursor and another one after it.
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,price\n5,50\n6,25\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 2);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).limit(2).after(cursor).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![6, 2]);
+
+        let result = index.search(&rtxn).limit(2).after(result.cursor.unwrap()).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![4, 0]);
+    }
+
+    #[test]
+    fn max_total_hits() {
+        let content = &b"id,title\n0,hello world\n1,hello big world\n2,helo world\n3,hello\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| builder.set_max_total_hits(2));
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("hello").execute().unwrap();
+        assert_eq!(result.documents_ids.len(), 2);
+
+        // The limit is cut at the bound.
+        let result = index.search(&rtxn).query("hello").offset(1).limit(10).execute().unwrap();
+        assert_eq!(result.documents_ids.len(), 1);
+
+        // A page after the bound is empty.
+        let result = index.search(&rtxn).query("hello").offset(2).execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+    }
+
+    #[test]
+    fn max_total_hits_distinct() {
+        use maplit::hashmap;
+
+        let content = &b"id,product
+0,shirt
+1,shirt
+2,shirt
+3,pants
+4,socks
+"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_faceted_fields(hashmap!{ "product".into() => "string".into() });
+            builder.set_distinct_attribute("product".into());
+            builder.set_max_total_hits(2);
+        });
+
+        // The bound counts the documents returned once deduplicated, not the ranked ones.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 3]);
+
+        let result = index.search(&rtxn).offset(1).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![3]);
+
+        let result = index.search(&rtxn).offset(2).execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+    }
+
+    #[test]
+    fn paginate_buckets() {
+        use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+        use super::criteria::CriterionResult;
+
+        /// Counts the buckets asked to the parent criterion.
+        struct Counting<'t> {
+            parent: Box<dyn criteria::Criterion + 't>,
+            calls: Arc<AtomicUsize>,
+        }
+
+        impl criteria::Criterion for Counting<'_> {
+            fn next(&mut self, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Option<CriterionResult>> {
+                self.calls.fetch_add(1, Relaxed);
+                self.parent.next(wdcache)
+            }
+        }
+
+        let content = &b"id,title\n0,helo\n1,hello\n2,helo\n3,hello\n4,hello\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut search = index.search(&rtxn);
+        let counter = calls.clone();
+        search.register_criterion("counting", move |_ctx, parent| {
+            Ok(Box::new(Counting { parent, calls: counter.clone() }))
+        });
+        search.query("hello").criteria(vec![Criterion::Typo { max: None }, Criterion::Custom("counting".into())]);
+
+        // The first typo bucket fills the page, the second one is never computed.
+        let result = search.offset(0).limit(2).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 3]);
+        assert_eq!(calls.swap(0, Relaxed), 1);
+
+        // The page overlaps the two typo buckets.
+        let result = search.offset(2).limit(2).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![4, 0]);
+        assert_eq!(calls.swap(0, Relaxed), 2);
+
+        // The page can't be filled, the criteria are asked until they are exhausted.
+        let result = search.offset(4).limit(2).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![2]);
+        assert!(calls.swap(0, Relaxed) > 2);
+
+        // The candidates are still counted without any document to return.
+        let result = search.offset(0).limit(0).execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+        assert!(!result.candidates.is_empty());
+    }
+
+    #[test]
+    fn skip_offset_ranking() {
+        let content = &b"id,title\n\
+            0,hello big world\n\
+            1,hello world\n\
+            2,world hello\n\
+            3,the hello world\n\
+            4,hello the big world\n\
+            5,hello world\n\
+            6,world of hello\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let criteria = vec![Criterion::Words, Criterion::Proximity { max: None }, Criterion::Exactness];
+        for offset in 0..8 {
+            let mut search = index.search(&rtxn);
+            search.query("hello world").criteria(criteria.clone()).offset(offset).limit(2);
+            let ranked = search.execute().unwrap();
+            let skipped = search.skip_offset_ranking(true).execute().unwrap();
+            assert_eq!(skipped.documents_ids, ranked.documents_ids, "offset {}", offset);
+        }
+    }
+
+    fn s(s: &str) -> String { s.to_string() }
+}
@@ -0,0 +1,491 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use fst::Set;
+use log::warn;
+use meilisearch_tokenizer::{Analyzer, AnalyzerConfig};
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+
+use crate::facet::{FacetStringNormalization, FacetType, FacetValue};
+use crate::proximity::{encode_position, position_attribute, ONE_ATTRIBUTE};
+use crate::update::process_tokens;
+use crate::{json_to_string, FieldId, FieldsIdsMap, Index};
+use super::facet::FacetCondition;
+use super::query_tree::{Operation, Query, QueryKind, QueryTreeBuilder, TermsMatchingStrategy};
+use super::{word_derivations, WordDerivationsCache};
+
+/// A query registered in the index, it is matched against the documents given to a `Percolator`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredQuery {
+    /// The text of the query, a stored query without text matches any document.
+    pub query: Option<String>,
+    /// A filter expression the documents must satisfy, like the ones given to a search.
+    pub filter: Option<String>,
+}
+
+struct CompiledQuery {
+    name: String,
+    query_tree: Option<Operation>,
+    condition: Option<FacetCondition>,
+}
+
+/// The stored queries indexed by the words of their query trees, a document can only
+/// match a stored query if it contains one of the words the stored query requires.
+#[derive(Default)]
+struct RequiredWords {
+    /// The stored queries that require one of these words, without typos nor prefix.
+    exact: HashMap<String, Vec<usize>>,
+    /// The stored queries that require one of the words these queries are derived into.
+    derived: HashMap<Query, Vec<usize>>,
+    /// The stored queries that don't require any word, e.g. the ones without text.
+    any: Vec<usize>,
+}
+
+impl RequiredWords {
+    fn insert(&mut self, id: usize, query_tree: Option<&Operation>) {
+        match query_tree.and_then(required_queries) {
+            Some(queries) => for query in queries {
+                match &query.kind {
+                    QueryKind::Exact { word, .. } | QueryKind::Tolerant { typo: 0, word } if !query.prefix => {
+                        self.exact.entry(word.clone()).or_default().push(id);
+                    },
+                    _ => self.derived.entry(query.clone()).or_default().push(id),
+                }
+            },
+            None => self.any.push(id),
+        }
+    }
+
+    /// Returns the stored queries that can match the document, in the order they were inserted.
+    fn candidates(&self, words: &DocumentWords, wdcache: &mut WordDerivationsCache) -> anyhow::Result<BTreeSet<usize>> {
+        let mut candidates: BTreeSet<_> = self.any.iter().copied().collect();
+
+        for word in words.positions.keys() {
+            if let Some(ids) = self.exact.get(word) {
+                candidates.extend(ids);
+            }
+        }
+
+        for (query, ids) in &self.derived {
+            if !words.query_positions(query, wdcache)?.is_empty() {
+                candidates.extend(ids);
+            }
+        }
+
+        Ok(candidates)
+    }
+}
+
+/// Returns the queries of the query tree one of which must match a document for the whole
+/// query tree to match it, `None` when a document can match it without matching any query.
+fn required_queries(operation: &Operation) -> Option<Vec<&Query>> {
+    match operation {
+        // Every operation must match, the one that requires the fewest queries is chosen.
+        Operation::And(ops) | Operation::Consecutive(ops) => {
+            ops.iter().filter_map(required_queries).min_by_key(Vec::len)
+        },
+        // Any operation can match, the queries of all of them are required.
+        Operation::Or(_, ops) => {
+            let mut queries = Vec::new();
+            for op in ops {
+                queries.extend(required_queries(op)?);
+            }
+            Some(queries)
+        },
+        Operation::Query(query) => Some(vec![query]),
+    }
+}
+
+/// Returns the stored queries a document matches, the document doesn't have to be part of
+/// the index, it can be percolated before being indexed to alert the users that saved a search.
+///
+/// The stored queries are compiled once, with the settings of the index, and indexed by the
+/// words they require, only the stored queries that require one of the words of a document
+/// are matched against it, the words derivations are shared between all of them.
+pub struct Percolator {
+    queries: Vec<CompiledQuery>,
+    required_words: RequiredWords,
+    primary_key: Option<String>,
+    fields_ids_map: FieldsIdsMap,
+    searchable_fields: Option<HashSet<String>>,
+    stored_only_fields: HashSet<String>,
+    faceted_fields: HashMap<FieldId, FacetType>,
+    facet_string_normalizations: HashMap<FieldId, FacetStringNormalization>,
+    auto_filterable_fields: HashSet<FieldId>,
+    stop_words: Set<Vec<u8>>,
+}
+
+impl StoredQuery {
+    /// Returns an error if the query can't be matched against the documents with the
+    /// settings of the index, e.g. if its filter refers to a field that is not faceted.
+    pub fn validate(&self, rtxn: &heed::RoTxn, index: &Index) -> anyhow::Result<()> {
+        let stop_words = index.stop_words(rtxn)?.map_data(Cow::into_owned)?;
+        let compiler = QueryCompiler::new(rtxn, index, &stop_words)?;
+        compiler.compile(String::new(), self).map(drop)
+    }
+}
+
+/// Compiles the stored queries with the settings of the index.
+struct QueryCompiler<'a> {
+    rtxn: &'a heed::RoTxn<'a>,
+    index: &'a Index,
+    terms_matching_strategy: TermsMatchingStrategy,
+    min_prefix_length: usize,
+    max_ngram: usize,
+    split_words: bool,
+    split_words_budget: Option<usize>,
+    analyzer: Analyzer<'a, Vec<u8>>,
+}
+
+impl<'a> QueryCompiler<'a> {
+    fn new(rtxn: &'a heed::RoTxn<'a>, index: &'a Index, stop_words: &'a Set<Vec<u8>>) -> anyhow::Result<QueryCompiler<'a>> {
+        Ok(QueryCompiler {
+            rtxn,
+            index,
+            terms_matching_strategy: index.search_defaults(rtxn)?.resolved_terms_matching_strategy(),
+            min_prefix_length: index.min_prefix_query_length(rtxn)?,
+            max_ngram: index.max_query_ngram(rtxn)?,
+            split_words: index.split_words_enabled(rtxn)?,
+            split_words_budget: index.split_words_budget(rtxn)?,
+            analyzer: Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words)),
+        })
+    }
+
+    fn compile(&self, name: String, stored: &StoredQuery) -> anyhow::Result<CompiledQuery> {
+        let query_tree = match &stored.query {
+            Some(query) => {
+                let mut builder = QueryTreeBuilder::new(self.rtxn, self.index);
+                builder.terms_matching_strategy(self.terms_matching_strategy);
+                builder.min_prefix_length(self.min_prefix_length);
+                builder.max_ngram(self.max_ngram);
+                builder.split_words(self.split_words);
+                builder.split_words_budget(self.split_words_budget);
+                let result = self.analyzer.analyze(query);
+                builder.build(result.tokens())?
+            },
+            None => None,
+        };
+
+        let condition = match &stored.filter {
+            Some(filter) => Some(FacetCondition::from_str(self.rtxn, self.index, filter)?),
+            None => None,
+        };
+
+        Ok(CompiledQuery { name, query_tree, condition })
+    }
+}
+
+impl Percolator {
+    /// The stored queries are validated when they are put but the settings of the index can
+    /// change afterward, the stored queries that can't be compiled anymore are ignored.
+    pub fn new(rtxn: &heed::RoTxn, index: &Index) -> anyhow::Result<Percolator> {
+        let stop_words = index.stop_words(rtxn)?.map_data(Cow::into_owned)?;
+        let compiler = QueryCompiler::new(rtxn, index, &stop_words)?;
+
+        let mut queries = Vec::new();
+        for (name, stored) in index.stored_queries(rtxn)? {
+            match compiler.compile(name.clone(), &stored) {
+                Ok(query) => queries.push(query),
+                Err(error) => warn!("ignoring the stored query {:?} that can't be compiled: {}", name, error),
+            }
+        }
+
+        // The compiler borrows the stop words that are moved into the percolator.
+        drop(compiler);
+
+        let mut required_words = RequiredWords::default();
+        for (id, query) in queries.iter().enumerate() {
+            required_words.insert(id, query.query_tree.as_ref());
+        }
+
+        let fields_ids_map = index.fields_ids_map(rtxn)?;
+        let auto_filterable_fields = index.auto_filterable_fields(rtxn)?
+            .iter()
+            .filter_map(|name| fields_ids_map.id(name))
+            .collect();
+
+        Ok(Percolator {
+            queries,
+            required_words,
+            primary_key: index.primary_key(rtxn)?.map(String::from),
+            searchable_fields: index.searchable_fields(rtxn)?.map(|fields| fields.into_iter().map(String::from).collect()),
+            stored_only_fields: index.stored_only_fields(rtxn)?,
+            faceted_fields: index.faceted_fields_ids(rtxn)?,
+            facet_string_normalizations: index.facet_string_normalizations_ids(rtxn)?,
+            auto_filterable_fields,
+            fields_ids_map,
+            stop_words,
+        })
+    }
+
+    /// Returns the names of the stored queries the document matches, in alphabetical order.
+    pub fn matches(&self, document: &Map<String, Value>) -> anyhow::Result<Vec<&str>> {
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(&self.stop_words));
+        let words = DocumentWords::new(self, &analyzer, document)?;
+        let values = self.facet_values(&analyzer, document);
+        let primary_key = self.primary_key.as_ref()
+            .and_then(|name| document.get(name))
+            .and_then(|value| match value {
+                Value::Number(number) => number.as_i64(),
+                Value::String(string) => string.parse().ok(),
+                _ => None,
+            });
+
+        let mut wdcache = WordDerivationsCache::new();
+        let mut matches = Vec::new();
+        for id in self.required_words.candidates(&words, &mut wdcache)? {
+            let query = &self.queries[id];
+            if let Some(condition) = &query.condition {
+                if !condition.matches_values(&values, primary_key) { continue }
+            }
+
+            if let Some(query_tree) = &query.query_tree {
+                if !words.matches(query_tree, &mut wdcache)? { continue }
+            }
+
+            matches.push(query.name.as_str());
+        }
+
+        Ok(matches)
+    }
+
+    fn is_searchable(&self, name: &str) -> bool {
+        !self.stored_only_fields.contains(name)
+            && self.searchable_fields.as_ref().map_or(true, |fields| fields.contains(name))
+    }
+
+    /// Extracts the facet values of the document, the values that can't be
+    /// parsed as the type of the faceted field are ignored.
+    fn facet_values(
+        &self,
+        analyzer: &Analyzer<Vec<u8>>,
+        document: &Map<String, Value>,
+    ) -> HashMap<FieldId, Vec<FacetValue>>
+    {
+        let mut values = HashMap::new();
+        for (name, value) in document {
+            let field_id = match self.fields_ids_map.id(name) {
+                Some(field_id) if !self.stored_only_fields.contains(name) => field_id,
+                _ => continue,
+            };
+
+            let facet_type = match self.faceted_fields.get(&field_id) {
+                Some(facet_type) => *facet_type,
+                None => continue,
+            };
+
+            let output = values.entry(field_id).or_insert_with(Vec::new);
+            if self.auto_filterable_fields.contains(&field_id) {
+                // The numbers found in the text of the auto-filterable fields are their facet values.
+                let content = json_to_string(value).unwrap_or_default();
+                let analyzed = analyzer.analyze(&content);
+                let numbers = analyzed.tokens()
+                    .filter(|token| token.is_word())
+                    .filter_map(|token| token.text().parse().ok());
+                output.extend(numbers.map(FacetValue::Integer));
+            } else {
+                let normalization = self.facet_string_normalizations.get(&field_id).copied().unwrap_or_default();
+                let parse = |v: &Value| parse_facet_value(facet_type, normalization, v);
+                match value {
+                    Value::Array(array) => output.extend(array.iter().filter_map(parse)),
+                    value => output.extend(parse(value)),
+                }
+            }
+        }
+        values
+    }
+}
+
+/// Parses a facet value like it is done at indexing time.
+fn parse_facet_value(
+    facet_type: FacetType,
+    normalization: FacetStringNormalization,
+    value: &Value,
+) -> Option<FacetValue>
+{
+    match (facet_type, value) {
+        (_, Value::Bool(boolean)) => Some(FacetValue::Integer(*boolean as i64)),
+        (FacetType::String, Value::Number(number)) => Some(FacetValue::String(number.to_string())),
+        (FacetType::Float, Value::Number(number)) => number.as_f64().map(FacetValue::from),
+        (FacetType::Integer, Value::Number(number)) => number.as_i64().map(FacetValue::Integer),
+        (facet_type, Value::String(string)) => {
+            let string = string.trim();
+            match facet_type {
+                _ if string.is_empty() => None,
+                FacetType::String => Some(FacetValue::String(normalization.normalize(string))),
+                FacetType::Float => string.parse::<f64>().ok().map(FacetValue::from),
+                FacetType::Integer => string.parse().ok().map(FacetValue::Integer),
+            }
+        },
+        _ => None,
+    }
+}
+
+/// The words of the searchable attributes of a document along with their positions.
+struct DocumentWords {
+    words_fst: Set<Cow<'static, [u8]>>,
+    positions: HashMap<String, Vec<u32>>,
+}
+
+impl DocumentWords {
+    fn new(
+        percolator: &Percolator,
+        analyzer: &Analyzer<Vec<u8>>,
+        document: &Map<String, Value>,
+    ) -> anyhow::Result<DocumentWords>
+    {
+        let mut positions = HashMap::<_, Vec<_>>::new();
+        let searchable = document.iter().filter(|(name, _)| percolator.is_searchable(name));
+        for (attribute, (_, value)) in searchable.enumerate() {
+            let content = match json_to_string(value) {
+                Some(content) => content,
+                None => continue,
+            };
+
+            let analyzed = analyzer.analyze(&content);
+            for (pos, token) in process_tokens(analyzed.tokens()) {
+                // The words of an attribute that are after its last position are ignored, like at indexing time.
+                if pos >= ONE_ATTRIBUTE as usize { break }
+                let position = encode_position(attribute as u32, pos as u32);
+                positions.entry(token.text().to_string()).or_default().push(position);
+            }
+        }
+
+        let mut words: Vec<_> = positions.keys().collect();
+        words.sort_unstable();
+        let words_fst = Set::from_iter(words)?.map_data(Cow::Owned)?;
+
+        Ok(DocumentWords { words_fst, positions })
+    }
+
+    fn matches(&self, operation: &Operation, wdcache: &mut WordDerivationsCache) -> anyhow::Result<bool> {
+        match operation {
+            Operation::And(ops) => {
+                for op in ops {
+                    if !self.matches(op, wdcache)? { return Ok(false) }
+                }
+                Ok(true)
+            },
+            Operation::Or(_, ops) => {
+                for op in ops {
+                    if self.matches(op, wdcache)? { return Ok(true) }
+                }
+                Ok(false)
+            },
+            Operation::Consecutive(ops) => {
+                // The positions of the last word of the sequences found so far,
+                // the words of a sequence must all be in the same attribute.
+                let mut ends: Option<HashSet<u32>> = None;
+                for op in ops {
+                    let positions = match op {
+                        Operation::Query(query) => self.query_positions(query, wdcache)?,
+                        // Only the words can be consecutive, the other operations can match anywhere.
+                        other => if self.matches(other, wdcache)? { continue } else { return Ok(false) },
+                    };
+
+                    let next_ends: HashSet<_> = match ends {
+                        Some(ends) => positions.into_iter()
+                            .filter(|&p| p.checked_sub(1).map_or(false, |prev| {
+                                ends.contains(&prev) && position_attribute(prev) == position_attribute(p)
+                            }))
+                            .collect(),
+                        None => positions.into_iter().collect(),
+                    };
+
+                    if next_ends.is_empty() { return Ok(false) }
+                    ends = Some(next_ends);
+                }
+                Ok(true)
+            },
+            Operation::Query(query) => Ok(!self.query_positions(query, wdcache)?.is_empty()),
+        }
+    }
+
+    /// Returns the positions of the document words the query is derived into.
+    fn query_positions(&self, query: &Query, wdcache: &mut WordDerivationsCache) -> anyhow::Result<Vec<u32>> {
+        let (word, typo) = match &query.kind {
+            QueryKind::Exact { word, .. } => (word, 0),
+            QueryKind::Tolerant { typo, word } => (word, *typo),
+        };
+
+        let mut positions = Vec::new();
+        for (derived, _typo) in word_derivations(word, query.prefix, typo, &self.words_fst, wdcache)? {
+            positions.extend(self.positions.get(derived).into_iter().flatten().copied());
+        }
+        Ok(positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use serde_json::json;
+
+    use crate::index::tests::TempIndex;
+    use crate::update::Settings;
+    use super::*;
+
+    #[test]
+    fn percolate_documents() {
+        let content = &b"id,title,price\n0,old boots,10\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_faceted_fields(hashmap!{ "price".into() => "integer".into() });
+        });
+        let mut wtxn = index.write_txn().unwrap();
+
+        let stored = |query: Option<&str>, filter: Option<&str>| StoredQuery {
+            query: query.map(String::from),
+            filter: filter.map(String::from),
+        };
+        index.put_stored_query(&mut wtxn, "cheap", &stored(Some("shoes"), Some("price < 50"))).unwrap();
+        index.put_stored_query(&mut wtxn, "phrase", &stored(Some("\"red shoes\""), None)).unwrap();
+        index.put_stored_query(&mut wtxn, "boots", &stored(Some("boots"), None)).unwrap();
+        index.put_stored_query(&mut wtxn, "expensive", &stored(None, Some("price >= 100"))).unwrap();
+        index.put_stored_query(&mut wtxn, "removed", &stored(None, None)).unwrap();
+        assert!(index.delete_stored_query(&mut wtxn, "removed").unwrap());
+        assert!(!index.delete_stored_query(&mut wtxn, "removed").unwrap());
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.stored_query(&rtxn, "boots").unwrap(), Some(stored(Some("boots"), None)));
+        assert_eq!(index.stored_query(&rtxn, "removed").unwrap(), None);
+        let percolator = Percolator::new(&rtxn, &index).unwrap();
+
+        // The phrase is indexed by its first word and the query without text by nothing.
+        assert_eq!(percolator.required_words.exact.get("red"), Some(&vec![3]));
+        assert_eq!(percolator.required_words.any, vec![2]);
+
+        let document = json!({ "id": 1, "title": "Red shoes for running", "price": 30 });
+        let matches = percolator.matches(document.as_object().unwrap()).unwrap();
+        assert_eq!(matches, vec!["cheap", "phrase"]);
+
+        // The words of the phrase are not in the right order.
+        let document = json!({ "id": 2, "title": "shoes, red", "price": 120 });
+        let matches = percolator.matches(document.as_object().unwrap()).unwrap();
+        assert_eq!(matches, vec!["expensive"]);
+
+        // The words of the phrase are at the end of an attribute and the start of the next one.
+        let document = json!({ "a": format!("{}red", "boring ".repeat(1023)), "b": "shoes", "id": 3 });
+        let matches = percolator.matches(document.as_object().unwrap()).unwrap();
+        assert!(!matches.contains(&"phrase"));
+        drop(rtxn);
+
+        // The stored queries that can't be compiled are rejected.
+        let mut wtxn = index.write_txn().unwrap();
+        assert!(index.put_stored_query(&mut wtxn, "invalid", &stored(None, Some("color = red"))).is_err());
+
+        // The stored queries that are not valid anymore are ignored.
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.reset_faceted_fields();
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let percolator = Percolator::new(&rtxn, &index).unwrap();
+        let document = json!({ "id": 1, "title": "Red shoes for running", "price": 30 });
+        let matches = percolator.matches(document.as_object().unwrap()).unwrap();
+        assert_eq!(matches, vec!["phrase"]);
+    }
+}
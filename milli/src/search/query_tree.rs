@@ -1,17 +1,79 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
 use std::{fmt, cmp, mem};
 
+use anyhow::bail;
 use levenshtein_automata::{DFA, Distance};
 use meilisearch_tokenizer::{TokenKind, tokenizer::TokenStream};
 use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
 use slice_group_by::GroupBy;
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::Index;
-use super::build_dfa;
+use crate::proximity::{MAX_PAIR_PROXIMITY, MAX_QUERY_PROXIMITY};
+use super::{build_dfa, word_derivations, WordDerivationsCache};
 
 type IsOptionalWord = bool;
 type IsPrefix = bool;
 
+/// The minimum length, in grapheme clusters, of a query word to accept one typo.
+pub const ONE_TYPO_MIN_WORD_LEN: usize = 5;
+/// The minimum length, in grapheme clusters, of a query word to accept two typos.
+pub const TWO_TYPOS_MIN_WORD_LEN: usize = 9;
+/// The default maximum number of consecutive query words concatenated into a single word.
+pub const DEFAULT_MAX_NGRAM: usize = 3;
+/// The highest maximum ngram an index can define, the query trees grow quickly with it.
+pub const MAX_NGRAM_LIMIT: usize = 5;
+
+/// The limits on the complexity of a query, the queries that exceed them are rejected with
+/// a `QueryTooComplex` error before their query tree is resolved against the index.
+///
+/// There are no limits by default, e.g. `QueryLimits { max_terms: 16, max_depth: 48,
+/// max_derivations: 64 }` protects the readers from the queries that explode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryLimits {
+    /// The maximum number of words of a query, the words of its phrases included.
+    pub max_terms: usize,
+    /// The maximum depth of the query tree, the nested parentheses of the boolean syntax included.
+    pub max_depth: usize,
+    /// The maximum number of alternatives of a query word, its synonyms, split words
+    /// and the words of the index it is derived into with its typos or as a prefix.
+    pub max_derivations: usize,
+}
+
+impl Default for QueryLimits {
+    fn default() -> QueryLimits {
+        QueryLimits { max_terms: usize::MAX, max_depth: usize::MAX, max_derivations: usize::MAX }
+    }
+}
+
+/// The error returned when a query exceeds one of its `QueryLimits`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryTooComplex {
+    TooManyTerms { terms: usize, max: usize },
+    TooDeep { depth: usize, max: usize },
+    TooManyDerivations { word: String, derivations: usize, max: usize },
+}
+
+impl fmt::Display for QueryTooComplex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryTooComplex::TooManyTerms { terms, max } => {
+                write!(f, "The query contains {} words but at most {} are allowed", terms, max)
+            },
+            QueryTooComplex::TooDeep { depth, max } => {
+                write!(f, "The query is {} levels deep but at most {} are allowed", depth, max)
+            },
+            QueryTooComplex::TooManyDerivations { word, derivations, max } => {
+                write!(f, "The query word {:?} has {} alternatives but at most {} are allowed", word, derivations, max)
+            },
+        }
+    }
+}
+
+impl Error for QueryTooComplex { }
+
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Operation {
     And(Vec<Operation>),
@@ -152,7 +214,7 @@ impl fmt::Debug for Query {
     }
 }
 
-trait Context {
+pub(crate) trait Context {
     fn word_docids(&self, word: &str) -> heed::Result<Option<RoaringBitmap>>;
     fn synonyms<S: AsRef<str>>(&self, words: &[S]) -> heed::Result<Option<Vec<Vec<String>>>>;
     fn word_documents_count(&self, word: &str) -> heed::Result<Option<u64>> {
@@ -161,14 +223,55 @@ trait Context {
             None => Ok(None),
         }
     }
+
+    /// The maximum number of ways a query word is split in two words, `None` if unbounded.
+    fn max_word_splits(&self) -> Option<usize> {
+        None
+    }
+
+    fn query_limits(&self) -> QueryLimits {
+        QueryLimits::default()
+    }
+
+    /// The number of words of the index a query word is derived into, with its typos or as a prefix.
+    fn word_derivations_count(&self, _word: &str, _prefix: bool, _typo: u8) -> anyhow::Result<usize> {
+        Ok(1)
+    }
+}
+
+/// Defines which words of the query can be removed, one after the other, to also find the
+/// documents that contain fewer query words, they are returned after the ones that contain
+/// all of them by the `words` ranking rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TermsMatchingStrategy {
+    /// Every query word must be contained by the documents.
+    All,
+    /// The words are removed from the end of the query.
+    Last,
+    /// The words contained by the most documents are removed first,
+    /// the words of equal frequency are removed from the end of the query.
+    Frequency,
+}
+
+impl Default for TermsMatchingStrategy {
+    fn default() -> TermsMatchingStrategy {
+        TermsMatchingStrategy::Last
+    }
 }
 
 /// The query tree builder is the interface to build a query tree.
 pub struct QueryTreeBuilder<'a> {
     rtxn: &'a heed::RoTxn<'a>,
     index: &'a Index,
-    optional_words: bool,
+    terms_matching_strategy: TermsMatchingStrategy,
     authorize_typos: bool,
+    min_prefix_length: usize,
+    max_ngram: usize,
+    split_words: bool,
+    split_words_budget: Option<usize>,
+    limits: QueryLimits,
+    synonyms: HashMap<Vec<String>, Vec<Vec<String>>>,
 }
 
 impl<'a> Context for QueryTreeBuilder<'a> {
@@ -180,8 +283,24 @@ impl<'a> Context for QueryTreeBuilder<'a> {
         self.index.word_documents_count(self.rtxn, word)
     }
 
-    fn synonyms<S: AsRef<str>>(&self, _words: &[S]) -> heed::Result<Option<Vec<Vec<String>>>> {
-        Ok(None)
+    fn synonyms<S: AsRef<str>>(&self, words: &[S]) -> heed::Result<Option<Vec<Vec<String>>>> {
+        // The index doesn't store synonyms, only the ones given with the query are used.
+        let words: Vec<_> = words.iter().map(|s| s.as_ref().to_owned()).collect();
+        Ok(self.synonyms.get(&words).cloned())
+    }
+
+    fn max_word_splits(&self) -> Option<usize> {
+        if self.split_words { self.split_words_budget } else { Some(0) }
+    }
+
+    fn query_limits(&self) -> QueryLimits {
+        self.limits
+    }
+
+    fn word_derivations_count(&self, word: &str, prefix: bool, typo: u8) -> anyhow::Result<usize> {
+        let words_fst = self.index.words_fst(self.rtxn)?;
+        let derivations = word_derivations(word, prefix, typo, &words_fst, &mut WordDerivationsCache::new())?;
+        Ok(derivations.len())
     }
 }
 
@@ -189,7 +308,18 @@ impl<'a> QueryTreeBuilder<'a> {
     /// Create a `QueryTreeBuilder` from a heed ReadOnly transaction `rtxn`
     /// and an Index `index`.
     pub fn new(rtxn: &'a heed::RoTxn<'a>, index: &'a Index) -> Self {
-        Self { rtxn, index, optional_words: true, authorize_typos: true }
+        Self {
+            rtxn,
+            index,
+            terms_matching_strategy: TermsMatchingStrategy::default(),
+            authorize_typos: true,
+            min_prefix_length: 1,
+            max_ngram: DEFAULT_MAX_NGRAM,
+            split_words: true,
+            split_words_budget: None,
+            limits: QueryLimits::default(),
+            synonyms: HashMap::new(),
+        }
     }
 
     /// if `optional_words` is set to `false` the query tree will be
@@ -198,7 +328,19 @@ impl<'a> QueryTreeBuilder<'a> {
     /// default value if not called: `true`
     #[allow(unused)]
     pub fn optional_words(&mut self, optional_words: bool) -> &mut Self {
-        self.optional_words = optional_words;
+        self.terms_matching_strategy = if optional_words {
+            TermsMatchingStrategy::Last
+        } else {
+            TermsMatchingStrategy::All
+        };
+        self
+    }
+
+    /// Defines which words of the query can be removed to find more documents,
+    /// `optional_words(true)` is equivalent to `TermsMatchingStrategy::Last`.
+    /// default value if not called: `TermsMatchingStrategy::Last`
+    pub fn terms_matching_strategy(&mut self, strategy: TermsMatchingStrategy) -> &mut Self {
+        self.terms_matching_strategy = strategy;
         self
     }
 
@@ -212,6 +354,55 @@ impl<'a> QueryTreeBuilder<'a> {
         self
     }
 
+    /// if the last word of the query contains less than `min_prefix_length` characters
+    /// it will not be considered as a prefix, this avoids matching a huge number of
+    /// words when the user only typed a few letters.
+    /// default value if not called: `1`
+    pub fn min_prefix_length(&mut self, min_prefix_length: usize) -> &mut Self {
+        self.min_prefix_length = min_prefix_length;
+        self
+    }
+
+    /// The maximum number of consecutive query words that are concatenated and searched as a
+    /// single word, e.g. `wordone wordtwo` also matches `wordonewordtwo`, at least one.
+    /// default value if not called: `DEFAULT_MAX_NGRAM`
+    pub fn max_ngram(&mut self, max_ngram: usize) -> &mut Self {
+        self.max_ngram = max_ngram.max(1);
+        self
+    }
+
+    /// if `split_words` is set to `false` the query words are never split in two words,
+    /// e.g. `heythere` doesn't match the documents that contain `hey there`.
+    /// default value if not called: `true`
+    pub fn split_words(&mut self, split_words: bool) -> &mut Self {
+        self.split_words = split_words;
+        self
+    }
+
+    /// The maximum number of ways a query word is split in two words, each way costs two
+    /// lookups in the database, the long words are only split at their first characters.
+    /// default value if not called: every way is explored
+    pub fn split_words_budget(&mut self, budget: Option<usize>) -> &mut Self {
+        self.split_words_budget = budget;
+        self
+    }
+
+    /// The limits on the complexity of the query, building the query tree
+    /// of a query that exceeds them returns a `QueryTooComplex` error.
+    /// default value if not called: `QueryLimits::default()`
+    pub fn limits(&mut self, limits: QueryLimits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The synonyms of the words of the query, by sequence of query words, they are only
+    /// used to build this query tree and are never stored in the index.
+    /// default value if not called: no synonyms
+    pub fn synonyms(&mut self, synonyms: HashMap<Vec<String>, Vec<Vec<String>>>) -> &mut Self {
+        self.synonyms = synonyms;
+        self
+    }
+
     /// Build the query tree:
     /// - if `optional_words` is set to `false` the query tree will be
     ///   generated forcing all query words to be present in each matching documents
@@ -220,18 +411,97 @@ impl<'a> QueryTreeBuilder<'a> {
     ///   forcing all query words to match documents without any typo
     ///   (the criterion `typo` will be ignored)
     pub fn build(&self, query: TokenStream) -> anyhow::Result<Option<Operation>> {
-        let primitive_query = create_primitive_query(query);
+        let mut primitive_query = create_primitive_query(query);
+        disable_short_prefix(&mut primitive_query, self.min_prefix_length);
         if !primitive_query.is_empty() {
-            create_query_tree(self, self.optional_words, self.authorize_typos, primitive_query).map(Some)
+            let strategy = self.terms_matching_strategy;
+            create_query_tree(self, strategy, self.authorize_typos, self.max_ngram, primitive_query).map(Some)
         } else {
             Ok(None)
         }
     }
+
+    /// List all words which can be considered as a match for the query, a word can be
+    /// mapped back to the query words it satisfies, e.g. to annotate the highlights.
+    pub fn matching_words(&self, query: TokenStream) -> anyhow::Result<MatchingWords> {
+        let mut primitive_query = create_primitive_query(query);
+        disable_short_prefix(&mut primitive_query, self.min_prefix_length);
+        matching_words(self, self.authorize_typos, self.max_ngram, &primitive_query).map_err(Into::into)
+    }
+
+    /// Build the query tree of a query written with the boolean syntax: the `AND`, `OR`
+    /// and `NOT` operators, written in uppercase, and the parentheses to group the terms.
+    /// The words excluded by the `NOT` operator are returned apart from the query tree.
+    pub fn build_boolean(&self, query: TokenStream) -> anyhow::Result<BooleanQuery> {
+        create_boolean_query(self, self.authorize_typos, self.min_prefix_length, query)
+    }
+
+    /// Returns the number of typos accepted for every word of the query and why.
+    pub fn word_typos(&self, query: TokenStream) -> Vec<WordTypos> {
+        let primitive_query = create_primitive_query(query);
+        let mut word_typos = Vec::new();
+        for part in primitive_query {
+            match part {
+                PrimitiveQueryPart::Word(word, _) => {
+                    let (max_typos, reason) = typos_reason(&word, self.authorize_typos);
+                    word_typos.push(WordTypos { word, max_typos, reason });
+                },
+                PrimitiveQueryPart::Phrase(words) => {
+                    let words = words.into_iter().map(|word| {
+                        WordTypos { word, max_typos: 0, reason: TyposReason::Phrase }
+                    });
+                    word_typos.extend(words);
+                },
+            }
+        }
+        word_typos
+    }
+}
+
+/// Builds the query tree of a query with the default parameters of the `QueryTreeBuilder`,
+/// the words and their documents are read from the given context.
+pub(crate) fn build_query_tree(
+    ctx: &impl Context,
+    terms_matching_strategy: TermsMatchingStrategy,
+    query: TokenStream,
+) -> anyhow::Result<Option<Operation>>
+{
+    let primitive_query = create_primitive_query(query);
+    if !primitive_query.is_empty() {
+        create_query_tree(ctx, terms_matching_strategy, true, DEFAULT_MAX_NGRAM, primitive_query).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// The number of typos accepted for a query word.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WordTypos {
+    pub word: String,
+    pub max_typos: u8,
+    pub reason: TyposReason,
+}
+
+/// What determined the number of typos accepted for a query word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TyposReason {
+    /// The search doesn't authorize typos.
+    TyposDisabled,
+    /// The word is part of a phrase, the phrases are matched without typos.
+    Phrase,
+    /// The length of the word, see `ONE_TYPO_MIN_WORD_LEN` and `TWO_TYPOS_MIN_WORD_LEN`.
+    WordLength,
+    /// The search didn't find any document and has been retried with one more typo.
+    Increased,
 }
 
-/// Split the word depending on the frequency of subwords in the database documents.
+/// Split the word depending on the frequency of subwords in the database documents,
+/// only the first splits allowed by the budget of the context are explored.
 fn split_best_frequency<'a>(ctx: &impl Context, word: &'a str) -> heed::Result<Option<Operation>> {
-    let chars = word.char_indices().skip(1);
+    let budget = ctx.max_word_splits().unwrap_or(usize::MAX);
+    let chars = word.char_indices().skip(1).take(budget);
     let mut best = None;
 
     for (i, _) in chars {
@@ -255,16 +525,37 @@ fn split_best_frequency<'a>(ctx: &impl Context, word: &'a str) -> heed::Result<O
 }
 
 /// Return the `QueryKind` of a word depending on `authorize_typos`
-/// and the provided word length.
+/// and the provided word length, in grapheme clusters.
 fn typos(word: String, authorize_typos: bool) -> QueryKind {
+    match typos_reason(&word, authorize_typos) {
+        (0, _) => QueryKind::exact(word),
+        (typo, _) => QueryKind::tolerant(typo, word),
+    }
+}
+
+/// Returns the number of typos accepted for a word and what determined it.
+fn typos_reason(word: &str, authorize_typos: bool) -> (u8, TyposReason) {
     if authorize_typos {
-        match word.len() {
-            0..=4 => QueryKind::exact(word),
-            5..=8 => QueryKind::tolerant(1, word),
-            _     => QueryKind::tolerant(2, word),
+        match word.graphemes(true).count() {
+            len if len >= TWO_TYPOS_MIN_WORD_LEN => (2, TyposReason::WordLength),
+            len if len >= ONE_TYPO_MIN_WORD_LEN => (1, TyposReason::WordLength),
+            _ => (0, TyposReason::WordLength),
         }
     } else {
-        QueryKind::exact(word)
+        (0, TyposReason::TyposDisabled)
+    }
+}
+
+/// Allows one more typo on every word of the query tree, up to two typos,
+/// the words of the phrases and of the split words stay exact.
+pub fn increase_typos(operation: &mut Operation) {
+    match operation {
+        Operation::And(ops) | Operation::Or(_, ops) => ops.iter_mut().for_each(increase_typos),
+        Operation::Consecutive(_) => (),
+        Operation::Query(Query { kind, .. }) => {
+            let typo = (kind.typo() + 1).min(2);
+            *kind = QueryKind::tolerant(typo, kind.word().to_string());
+        },
     }
 }
 
@@ -283,29 +574,182 @@ fn synonyms(ctx: &impl Context, word: &[&str]) -> heed::Result<Option<Vec<Operat
     }))
 }
 
-/// The query tree builder is the interface to build a query tree.
+/// The words of the documents that match a query, built from the query words along with
+/// their typo derivations, prefixes, ngrams, split words and synonyms. A document word can be
+/// checked and mapped back to the query words it corresponds to, e.g. to highlight it.
 #[derive(Default)]
 pub struct MatchingWords {
-    dfas: Vec<(DFA, u8)>,
+    dfas: Vec<MatchingWord>,
+}
+
+struct MatchingWord {
+    dfa: DFA,
+    word: String,
+    typo: u8,
+    prefix: IsPrefix,
+    /// The words of the phrases and the split halves never accept typos.
+    exact: bool,
+    /// The query words this word is derived from.
+    originals: Vec<String>,
+}
+
+impl MatchingWord {
+    fn new(word: String, typo: u8, prefix: IsPrefix, exact: bool, originals: Vec<String>) -> MatchingWord {
+        let dfa = build_dfa(&word, typo, prefix);
+        MatchingWord { dfa, word, typo, prefix, exact, originals }
+    }
+
+    fn matches(&self, word: &str) -> bool {
+        matches_dfa(&self.dfa, self.typo, word)
+    }
+
+    /// Returns the number of bytes of the word matched by the automaton, `None` if it doesn't match.
+    ///
+    /// A prefix automaton matches the shortest prefix of the word that has the best distance
+    /// to the query word, the other automatons match the whole word.
+    fn matching_bytes(&self, word: &str) -> Option<usize> {
+        let distance = match self.dfa.eval(word) {
+            Distance::Exact(t) if t <= self.typo => Distance::Exact(t),
+            _ => return None,
+        };
+
+        if !self.prefix {
+            return Some(word.len());
+        }
+
+        let mut state = self.dfa.initial_state();
+        for (i, byte) in word.bytes().enumerate() {
+            state = self.dfa.transition(state, byte);
+            if word.is_char_boundary(i + 1) && self.dfa.distance(state) == distance {
+                return Some(i + 1);
+            }
+        }
+
+        Some(word.len())
+    }
 }
 
 impl MatchingWords {
-    /// List all words which can be considered as a match for the query tree.
+    /// List all words which can be considered as a match for the query tree,
+    /// each word is considered as derived from itself.
     pub fn from_query_tree(tree: &Operation) -> Self {
         Self {
-            dfas: fetch_queries(tree).into_iter().map(|(w, t, p)| (build_dfa(w, t, p), t)).collect()
+            dfas: fetch_queries(tree).into_iter()
+                .map(|(w, t, p)| MatchingWord::new(w.to_string(), t, p, false, vec![w.to_string()]))
+                .collect()
+        }
+    }
+
+    /// Allows one more typo on every word, up to two typos, like `increase_typos` does
+    /// with the query tree, the words still map back to the query words they derive from.
+    pub fn increase_typos(&mut self) {
+        for matching in self.dfas.iter_mut().filter(|matching| !matching.exact) {
+            let typo = (matching.typo + 1).min(2);
+            if typo != matching.typo {
+                matching.typo = typo;
+                matching.dfa = build_dfa(&matching.word, typo, matching.prefix);
+            }
         }
     }
 
     /// Return true if the word match.
     pub fn matches(&self, word: &str) -> bool {
-        self.dfas.iter().any(|(dfa, typo)| match dfa.eval(word) {
-            Distance::Exact(t) => t <= *typo,
-            Distance::AtLeast(_) => false,
-        })
+        self.dfas.iter().any(|matching| matching.matches(word))
+    }
+
+    /// Returns the query words that the given word satisfies, in the query order,
+    /// an empty list is returned if the word doesn't match.
+    ///
+    /// A derived word (e.g. a typo, a prefix, a synonym or the half of a split word)
+    /// is mapped back to the query words it has been derived from.
+    pub fn matching_query_words(&self, word: &str) -> Vec<&str> {
+        let mut query_words = Vec::new();
+        for matching in &self.dfas {
+            if matching.matches(word) {
+                for original in &matching.originals {
+                    if !query_words.contains(&original.as_str()) {
+                        query_words.push(original.as_str());
+                    }
+                }
+            }
+        }
+        query_words
+    }
+
+    /// Returns the number of bytes of the given word that match the query, `None` if it doesn't.
+    ///
+    /// The span is the one matched by the automaton of the query word: a document word that
+    /// matches a prefix query word (e.g. `kevin` for `kev`) is only matched on the prefix
+    /// (e.g. `kev`), a word matching a query word with typos is matched entirely.
+    pub fn matching_bytes(&self, word: &str) -> Option<usize> {
+        self.dfas.iter().filter_map(|matching| matching.matching_bytes(word)).max()
     }
 }
 
+fn matches_dfa(dfa: &DFA, typo: u8, word: &str) -> bool {
+    match dfa.eval(word) {
+        Distance::Exact(t) => t <= typo,
+        Distance::AtLeast(_) => false,
+    }
+}
+
+/// Lists all words which can be considered as a match for the primitive query,
+/// along with the query words they are derived from.
+fn matching_words(
+    ctx: &impl Context,
+    authorize_typos: bool,
+    max_ngram: usize,
+    query: &[PrimitiveQueryPart],
+) -> heed::Result<MatchingWords>
+{
+    fn exact(word: String, originals: &[String]) -> MatchingWord {
+        MatchingWord::new(word, 0, false, true, originals.to_vec())
+    }
+
+    let mut words = Vec::new();
+    for sub_query in query.linear_group_by(|a, b| !(a.is_phrase() || b.is_phrase())) {
+        for (i, part) in sub_query.iter().enumerate() {
+            let word = match part {
+                PrimitiveQueryPart::Phrase(phrase) => {
+                    phrase.iter().for_each(|word| words.push(exact(word.clone(), &[word.clone()])));
+                    continue;
+                },
+                PrimitiveQueryPart::Word(word, _) => word,
+            };
+
+            // The split halves of a word are derived from this word only.
+            if let Some(Operation::Consecutive(halves)) = split_best_frequency(ctx, word)? {
+                let halves = halves.iter().filter_map(Operation::query);
+                halves.for_each(|half| words.push(exact(half.kind.word().to_string(), &[word.clone()])));
+            }
+
+            // The ngrams and the synonyms of the words starting at this one.
+            for ngram in 1..=max_ngram.min(sub_query.len() - i) {
+                let group = &sub_query[i..i + ngram];
+                let originals: Vec<_> = group.iter().filter_map(|part| match part {
+                    PrimitiveQueryPart::Word(word, _) => Some(word.clone()),
+                    PrimitiveQueryPart::Phrase(_) => None,
+                }).collect();
+
+                for synonym in ctx.synonyms(&originals)?.unwrap_or_default() {
+                    synonym.into_iter().for_each(|word| {
+                        words.push(MatchingWord::new(word, 0, false, false, originals.clone()))
+                    });
+                }
+
+                let is_prefix = group.last().map_or(false, |part| part.is_prefix());
+                let (typo, word) = match typos(originals.concat(), authorize_typos) {
+                    QueryKind::Tolerant { typo, word } => (typo, word),
+                    QueryKind::Exact { word, .. } => (0, word),
+                };
+                words.push(MatchingWord::new(word, typo, is_prefix, false, originals));
+            }
+        }
+    }
+
+    Ok(MatchingWords { dfas: words })
+}
+
 /// Lists all words which can be considered as a match for the query tree.
 fn fetch_queries(tree: &Operation) -> HashSet<(&str, u8, IsPrefix)> {
     fn resolve_ops<'a>(tree: &'a Operation, out: &mut HashSet<(&'a str, u8, IsPrefix)>) {
@@ -325,55 +769,100 @@ fn fetch_queries(tree: &Operation) -> HashSet<(&str, u8, IsPrefix)> {
     queries
 }
 
+/// Matches on the `PrimitiveQueryPart` and create an operation from it.
+fn resolve_primitive_part(
+    ctx: &impl Context,
+    authorize_typos: bool,
+    part: PrimitiveQueryPart,
+) -> anyhow::Result<Operation>
+{
+    match part {
+        // 1. try to split word in 2
+        // 2. try to fetch synonyms
+        // 3. create an operation containing the word
+        // 4. wrap all in an OR operation
+        PrimitiveQueryPart::Word(word, prefix) => {
+            let mut children = synonyms(ctx, &[&word])?.unwrap_or_default();
+            if let Some(child) = split_best_frequency(ctx, &word)? {
+                children.push(child);
+            }
+            let query = Query { prefix, kind: typos(word, authorize_typos) };
+            check_derivations(ctx, &query, children.len())?;
+            children.push(Operation::Query(query));
+            Ok(Operation::or(false, children))
+        },
+        // create a CONSECUTIVE operation wrapping all word in the phrase
+        PrimitiveQueryPart::Phrase(words) => {
+            Ok(Operation::phrase(words))
+        },
+    }
+}
+
+/// Returns an error if a query word, or a concatenation of words, has more alternatives than
+/// allowed, the other alternatives are the synonyms and the split words of the query word.
+fn check_derivations(ctx: &impl Context, query: &Query, alternatives: usize) -> anyhow::Result<()> {
+    let max = ctx.query_limits().max_derivations;
+    // The derivations are only counted when there is a limit, it reads the words FST.
+    if max == usize::MAX { return Ok(()) }
+
+    let typo = if query.kind.is_exact() { 0 } else { query.kind.typo() };
+    let word = query.kind.word();
+    let derivations = alternatives + ctx.word_derivations_count(word, query.prefix, typo)?;
+    if derivations > max {
+        let word = word.to_string();
+        return Err(QueryTooComplex::TooManyDerivations { word, derivations, max }.into());
+    }
+    Ok(())
+}
+
+/// Returns an error if the query contains more words than allowed.
+fn check_terms(ctx: &impl Context, query: &[PrimitiveQueryPart]) -> Result<(), QueryTooComplex> {
+    let max = ctx.query_limits().max_terms;
+    let terms = query.iter().map(|part| match part {
+        PrimitiveQueryPart::Word(..) => 1,
+        PrimitiveQueryPart::Phrase(words) => words.len(),
+    }).sum();
+    if terms > max {
+        return Err(QueryTooComplex::TooManyTerms { terms, max });
+    }
+    Ok(())
+}
+
+/// Returns an error if the query tree is deeper than allowed.
+fn check_depth(ctx: &impl Context, depth: usize) -> Result<(), QueryTooComplex> {
+    let max = ctx.query_limits().max_depth;
+    if depth > max {
+        return Err(QueryTooComplex::TooDeep { depth, max });
+    }
+    Ok(())
+}
+
 /// Main function that creates the final query tree from the primitive query.
 fn create_query_tree(
     ctx: &impl Context,
-    optional_words: bool,
+    terms_matching_strategy: TermsMatchingStrategy,
     authorize_typos: bool,
+    max_ngram: usize,
     query: PrimitiveQuery,
 ) -> anyhow::Result<Operation>
 {
-    /// Matches on the `PrimitiveQueryPart` and create an operation from it.
-    fn resolve_primitive_part(
-        ctx: &impl Context,
-        authorize_typos: bool,
-        part: PrimitiveQueryPart,
-    ) -> anyhow::Result<Operation>
-    {
-        match part {
-            // 1. try to split word in 2
-            // 2. try to fetch synonyms
-            // 3. create an operation containing the word
-            // 4. wrap all in an OR operation
-            PrimitiveQueryPart::Word(word, prefix) => {
-                let mut children = synonyms(ctx, &[&word])?.unwrap_or_default();
-                if let Some(child) = split_best_frequency(ctx, &word)? {
-                    children.push(child);
-                }
-                children.push(Operation::Query(Query { prefix, kind: typos(word, authorize_typos) }));
-                Ok(Operation::or(false, children))
-            },
-            // create a CONSECUTIVE operation wrapping all word in the phrase
-            PrimitiveQueryPart::Phrase(words) => {
-                Ok(Operation::phrase(words))
-            },
-        }
-    }
+    // The number of words is checked first, the query tree grows exponentially with it.
+    check_terms(ctx, &query)?;
 
-    /// Create all ngrams 1..=3 generating query tree branches.
+    /// Create all ngrams 1..=max_ngram generating query tree branches.
     fn ngrams(
         ctx: &impl Context,
         authorize_typos: bool,
+        max_ngram: usize,
         query: &[PrimitiveQueryPart],
     ) -> anyhow::Result<Operation>
     {
-        const MAX_NGRAM: usize = 3;
         let mut op_children = Vec::new();
 
         for sub_query in query.linear_group_by(|a, b| !(a.is_phrase() || b.is_phrase()) ) {
             let mut or_op_children = Vec::new();
 
-            for ngram in 1..=MAX_NGRAM.min(sub_query.len()) {
+            for ngram in 1..=max_ngram.min(sub_query.len()) {
                 if let Some(group) = sub_query.get(..ngram) {
                     let mut and_op_children = Vec::new();
                     let tail = &sub_query[ngram..];
@@ -396,13 +885,14 @@ fn create_query_tree(
                             let mut operations = synonyms(ctx, &words)?.unwrap_or_default();
                             let concat = words.concat();
                             let query = Query { prefix: is_prefix, kind: typos(concat, authorize_typos) };
+                            check_derivations(ctx, &query, operations.len())?;
                             operations.push(Operation::Query(query));
                             and_op_children.push(Operation::or(false, operations));
                         }
                     }
 
                     if !is_last {
-                        let ngrams = ngrams(ctx, authorize_typos, tail)?;
+                        let ngrams = ngrams(ctx, authorize_typos, max_ngram, tail)?;
                         and_op_children.push(ngrams);
                     }
                     or_op_children.push(Operation::and(and_op_children));
@@ -414,10 +904,12 @@ fn create_query_tree(
         Ok(Operation::and(op_children))
     }
 
-    /// Create a new branch removing the last non-phrase query parts.
+    /// Create a new branch removing the non-phrase query parts in the given order.
     fn optional_word(
         ctx: &impl Context,
         authorize_typos: bool,
+        max_ngram: usize,
+        removal_order: &[usize],
         query: PrimitiveQuery,
     ) -> anyhow::Result<Operation>
     {
@@ -426,32 +918,292 @@ fn create_query_tree(
 
         let start = number_phrases + (number_phrases == 0) as usize;
         for len in start..=query.len() {
-            let mut word_count = len - number_phrases;
-            let query: Vec<_> = query.iter().filter_map(|p| {
-                if p.is_phrase() {
-                    Some(p)
-                } else if word_count != 0 {
-                    word_count -= 1;
-                    Some(p)
-                } else {
-                    None
-                }
-            })
-            .cloned()
-            .collect();
+            let removed = &removal_order[..query.len() - len];
+            let query: Vec<_> = query.iter().enumerate()
+                .filter(|(i, _)| !removed.contains(i))
+                .map(|(_, p)| p.clone())
+                .collect();
 
-            let ngrams = ngrams(ctx, authorize_typos, &query)?;
+            let ngrams = ngrams(ctx, authorize_typos, max_ngram, &query)?;
             operation_children.push(ngrams);
         }
 
         Ok(Operation::or(true, operation_children))
     }
 
-    if optional_words {
-        optional_word(ctx, authorize_typos, query)
-    } else {
-        ngrams(ctx, authorize_typos, query.as_slice())
+    // The positions of the words of the query, in the order they are removed.
+    let mut removal_order: Vec<_> = query.iter().enumerate()
+        .filter(|(_, p)| !p.is_phrase())
+        .map(|(i, _)| i)
+        .rev()
+        .collect();
+
+    let operation = match terms_matching_strategy {
+        TermsMatchingStrategy::All => ngrams(ctx, authorize_typos, max_ngram, query.as_slice()),
+        TermsMatchingStrategy::Last => optional_word(ctx, authorize_typos, max_ngram, &removal_order, query),
+        TermsMatchingStrategy::Frequency => {
+            let mut frequencies = HashMap::new();
+            for &i in &removal_order {
+                if let PrimitiveQueryPart::Word(word, _) = &query[i] {
+                    frequencies.insert(i, ctx.word_documents_count(word)?.unwrap_or(0));
+                }
+            }
+            // The sort is stable, the words of equal frequency are still removed from the end.
+            removal_order.sort_by_key(|i| cmp::Reverse(frequencies[i]));
+            optional_word(ctx, authorize_typos, max_ngram, &removal_order, query)
+        },
+    }?;
+
+    check_depth(ctx, maximum_depth(&operation))?;
+    Ok(operation)
+}
+
+/// The query tree of a query written with the boolean syntax along with the words it excludes.
+pub struct BooleanQuery {
+    pub query_tree: Option<Operation>,
+    pub matching_words: MatchingWords,
+    /// The words of the query that aren't excluded.
+    pub query_words: Vec<String>,
+    /// The words following a `NOT` operator, the documents containing them must be removed.
+    pub excluded_words: Vec<String>,
+}
+
+/// The tokens of a query written with the boolean syntax.
+#[derive(Debug, Clone)]
+enum BooleanToken {
+    Part(PrimitiveQueryPart),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+/// The expression represented by a query written with the boolean syntax.
+#[derive(Debug, Clone)]
+enum BooleanExpr {
+    Part(PrimitiveQueryPart),
+    And(Vec<BooleanExpr>),
+    Or(Vec<BooleanExpr>),
+    Not(Box<BooleanExpr>),
+}
+
+/// Splits the query into words, phrases, operators and parentheses, the operators
+/// are only recognized when written in uppercase and outside of a phrase.
+fn create_boolean_tokens(query: TokenStream, min_prefix_length: usize) -> Vec<BooleanToken> {
+    let mut tokens = Vec::new();
+    let mut phrase = Vec::new();
+    let mut quoted = false;
+
+    let mut peekable = query.peekable();
+    while let Some(token) = peekable.next() {
+        match token.kind {
+            TokenKind::Word if quoted => phrase.push(token.word.to_string()),
+            TokenKind::Word => match token.text() {
+                "AND" => tokens.push(BooleanToken::And),
+                "OR" => tokens.push(BooleanToken::Or),
+                "NOT" => tokens.push(BooleanToken::Not),
+                _ => {
+                    // Only the last word of the query is a prefix, like with the default syntax.
+                    let word = token.word.to_string();
+                    let prefix = peekable.peek().is_none() && word.chars().count() >= min_prefix_length;
+                    tokens.push(BooleanToken::Part(PrimitiveQueryPart::Word(word, prefix)));
+                },
+            },
+            TokenKind::Separator(_) => {
+                for c in token.text().chars() {
+                    match c {
+                        '"' => {
+                            quoted = !quoted;
+                            if !quoted && !phrase.is_empty() {
+                                tokens.push(BooleanToken::Part(PrimitiveQueryPart::Phrase(mem::take(&mut phrase))));
+                            }
+                        },
+                        '(' if !quoted => tokens.push(BooleanToken::Open),
+                        ')' if !quoted => tokens.push(BooleanToken::Close),
+                        _ => (),
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+
+    // If a quote is never closed, we consider all of the end of the query as a phrase.
+    if !phrase.is_empty() {
+        tokens.push(BooleanToken::Part(PrimitiveQueryPart::Phrase(phrase)));
+    }
+
+    tokens
+}
+
+/// Parses the boolean tokens, the `AND` operator binds tighter than the `OR` operator and
+/// two terms without an operator between them are joined by an implicit `AND`.
+fn parse_boolean_expr(tokens: Vec<BooleanToken>) -> anyhow::Result<Option<BooleanExpr>> {
+    type Tokens = std::iter::Peekable<std::vec::IntoIter<BooleanToken>>;
+
+    fn parse_or(tokens: &mut Tokens) -> anyhow::Result<BooleanExpr> {
+        let mut children = vec![parse_and(tokens)?];
+        while let Some(BooleanToken::Or) = tokens.peek() {
+            tokens.next();
+            children.push(parse_and(tokens)?);
+        }
+        Ok(if children.len() == 1 { children.pop().unwrap() } else { BooleanExpr::Or(children) })
+    }
+
+    fn parse_and(tokens: &mut Tokens) -> anyhow::Result<BooleanExpr> {
+        let mut children = vec![parse_unary(tokens)?];
+        loop {
+            match tokens.peek() {
+                Some(BooleanToken::And) => {
+                    tokens.next();
+                    children.push(parse_unary(tokens)?);
+                },
+                Some(BooleanToken::Or) | Some(BooleanToken::Close) | None => break,
+                Some(_) => children.push(parse_unary(tokens)?),
+            }
+        }
+        Ok(if children.len() == 1 { children.pop().unwrap() } else { BooleanExpr::And(children) })
+    }
+
+    fn parse_unary(tokens: &mut Tokens) -> anyhow::Result<BooleanExpr> {
+        match tokens.next() {
+            Some(BooleanToken::Part(part)) => Ok(BooleanExpr::Part(part)),
+            Some(BooleanToken::Not) => Ok(BooleanExpr::Not(Box::new(parse_unary(tokens)?))),
+            Some(BooleanToken::Open) => {
+                let expr = parse_or(tokens)?;
+                match tokens.next() {
+                    Some(BooleanToken::Close) => Ok(expr),
+                    _ => bail!("A parenthesis of the query is never closed."),
+                }
+            },
+            Some(BooleanToken::Close) => bail!("The query contains an unexpected closing parenthesis."),
+            Some(BooleanToken::And) | Some(BooleanToken::Or) => {
+                bail!("The AND and OR operators of the query must be between two terms.")
+            },
+            None => bail!("An operator at the end of the query is missing its term."),
+        }
+    }
+
+    let mut tokens = tokens.into_iter().peekable();
+    if tokens.peek().is_none() {
+        return Ok(None);
+    }
+
+    let expr = parse_or(&mut tokens)?;
+    match tokens.next() {
+        Some(_) => bail!("The query contains an unexpected closing parenthesis."),
+        None => Ok(Some(expr)),
+    }
+}
+
+/// Creates the query tree of a query written with the boolean syntax, the `NOT` operator
+/// can only exclude words, or disjunctions of words, from the whole query.
+fn create_boolean_query(
+    ctx: &impl Context,
+    authorize_typos: bool,
+    min_prefix_length: usize,
+    query: TokenStream,
+) -> anyhow::Result<BooleanQuery>
+{
+    fn excluded_words(expr: BooleanExpr, out: &mut Vec<String>) -> anyhow::Result<()> {
+        match expr {
+            BooleanExpr::Part(PrimitiveQueryPart::Word(word, _)) => {
+                out.push(word);
+                Ok(())
+            },
+            BooleanExpr::Or(children) => children.into_iter().try_for_each(|child| excluded_words(child, out)),
+            _ => bail!("The NOT operator can only exclude words, e.g. `quick NOT (brown OR red)`."),
+        }
+    }
+
+    fn operation(
+        ctx: &impl Context,
+        authorize_typos: bool,
+        expr: BooleanExpr,
+        parts: &mut PrimitiveQuery,
+    ) -> anyhow::Result<Operation>
+    {
+        match expr {
+            BooleanExpr::Part(part) => {
+                parts.push(part.clone());
+                resolve_primitive_part(ctx, authorize_typos, part)
+            },
+            BooleanExpr::And(children) => {
+                let children = children.into_iter().map(|child| operation(ctx, authorize_typos, child, parts));
+                children.collect::<anyhow::Result<_>>().map(Operation::and)
+            },
+            BooleanExpr::Or(children) => {
+                let children = children.into_iter().map(|child| operation(ctx, authorize_typos, child, parts));
+                children.collect::<anyhow::Result<_>>().map(|children| Operation::or(false, children))
+            },
+            BooleanExpr::Not(_) => {
+                bail!("The NOT operator can only exclude words from the whole query, not from a group.")
+            },
+        }
+    }
+
+    let tokens = create_boolean_tokens(query, min_prefix_length);
+
+    // The parentheses are checked before parsing, the parser recurses on every one of them.
+    let terms: Vec<_> = tokens.iter().filter_map(|token| match token {
+        BooleanToken::Part(part) => Some(part.clone()),
+        _ => None,
+    }).collect();
+    check_terms(ctx, &terms)?;
+    let mut nesting = 0usize;
+    for token in &tokens {
+        match token {
+            BooleanToken::Open => {
+                nesting += 1;
+                check_depth(ctx, nesting)?;
+            },
+            BooleanToken::Close => nesting = nesting.saturating_sub(1),
+            _ => (),
+        }
     }
+
+    let children = match parse_boolean_expr(tokens)? {
+        Some(BooleanExpr::And(children)) => children,
+        Some(expr) => vec![expr],
+        None => Vec::new(),
+    };
+
+    let mut excluded = Vec::new();
+    let mut included = Vec::new();
+    for child in children {
+        match child {
+            BooleanExpr::Not(expr) => excluded_words(*expr, &mut excluded)?,
+            expr => included.push(expr),
+        }
+    }
+
+    let mut parts = Vec::new();
+    let query_tree = match included.len() {
+        0 => None,
+        _ => Some(operation(ctx, authorize_typos, BooleanExpr::And(included), &mut parts)?),
+    };
+    if let Some(tree) = &query_tree {
+        check_depth(ctx, maximum_depth(tree))?;
+    }
+
+    let query_words = parts.iter().flat_map(|part| match part {
+        PrimitiveQueryPart::Word(word, _) => vec![word.clone()],
+        PrimitiveQueryPart::Phrase(words) => words.clone(),
+    }).collect();
+
+    // The terms of the boolean query are never merged into ngrams, the parts are matched alone.
+    let mut words = Vec::new();
+    for part in parts {
+        words.extend(matching_words(ctx, authorize_typos, 1, &[part])?.dfas);
+    }
+
+    Ok(BooleanQuery {
+        query_tree,
+        matching_words: MatchingWords { dfas: words },
+        query_words,
+        excluded_words: excluded,
+    })
 }
 
 type PrimitiveQuery = Vec<PrimitiveQueryPart>;
@@ -516,6 +1268,25 @@ fn create_primitive_query(query: TokenStream) -> PrimitiveQuery {
     primitive_query
 }
 
+/// Makes the last word of the query an exact word if it
+/// contains less than `min_length` characters.
+fn disable_short_prefix(query: &mut PrimitiveQuery, min_length: usize) {
+    if let Some(PrimitiveQueryPart::Word(word, is_prefix)) = query.last_mut() {
+        if word.chars().count() < min_length {
+            *is_prefix = false;
+        }
+    }
+}
+
+/// Returns the depth of this Operation, a single query is one level deep.
+pub fn maximum_depth(operation: &Operation) -> usize {
+    use Operation::{Or, And, Query, Consecutive};
+    match operation {
+        Or(_, ops) | And(ops) | Consecutive(ops) => 1 + ops.iter().map(maximum_depth).max().unwrap_or(0),
+        Query(_) => 1,
+    }
+}
+
 /// Returns the maximum number of typos that this Operation allows.
 pub fn maximum_typo(operation: &Operation) -> usize {
     use Operation::{Or, And, Query, Consecutive};
@@ -526,17 +1297,18 @@ pub fn maximum_typo(operation: &Operation) -> usize {
     }
 }
 
-/// Returns the maximum proximity that this Operation allows.
+/// Returns the maximum proximity that this Operation allows, it saturates at `MAX_QUERY_PROXIMITY`.
 pub fn maximum_proximity(operation: &Operation) -> usize {
     use Operation::{Or, And, Query, Consecutive};
-    match operation {
+    let proximity = match operation {
         Or(_, ops) => ops.iter().map(maximum_proximity).max().unwrap_or(0),
         And(ops) => {
-            ops.iter().map(maximum_proximity).sum::<usize>()
-            + ops.len().saturating_sub(1) * 7
+            let pairs = ops.len().saturating_sub(1).saturating_mul(MAX_PAIR_PROXIMITY as usize);
+            ops.iter().map(maximum_proximity).fold(pairs, usize::saturating_add)
         },
         Query(_) | Consecutive(_) => 0,
-    }
+    };
+    cmp::min(proximity, MAX_QUERY_PROXIMITY as usize)
 }
 
 #[cfg(test)]
@@ -563,10 +1335,26 @@ mod test {
             authorize_typos: bool,
             query: TokenStream,
         ) -> anyhow::Result<Option<Operation>>
+        {
+            let strategy = if optional_words {
+                TermsMatchingStrategy::Last
+            } else {
+                TermsMatchingStrategy::All
+            };
+            self.build_with_strategy(strategy, authorize_typos, query)
+        }
+
+        fn build_with_strategy(
+            &self,
+            terms_matching_strategy: TermsMatchingStrategy,
+            authorize_typos: bool,
+            query: TokenStream,
+        ) -> anyhow::Result<Option<Operation>>
         {
             let primitive_query = create_primitive_query(query);
             if !primitive_query.is_empty() {
-                create_query_tree(self, optional_words, authorize_typos, primitive_query).map(Some)
+                create_query_tree(self, terms_matching_strategy, authorize_typos, DEFAULT_MAX_NGRAM, primitive_query)
+                    .map(Some)
             } else {
                 Ok(None)
             }
@@ -664,6 +1452,78 @@ mod test {
         assert_eq!(expected, query_tree);
     }
 
+    #[test]
+    fn matching_words_provenance() {
+        let query = "hello world";
+        let stop_words = &Set::default();
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words));
+        let result = analyzer.analyze(query);
+        let primitive_query = create_primitive_query(result.tokens());
+
+        let matching_words = matching_words(&TestContext::default(), true, &primitive_query).unwrap();
+
+        // typos and prefixes
+        assert_eq!(matching_words.matching_query_words("hallo"), vec!["hello"]);
+        assert_eq!(matching_words.matching_query_words("worldwide"), vec!["world"]);
+        // synonyms
+        assert_eq!(matching_words.matching_query_words("hi"), vec!["hello"]);
+        assert_eq!(matching_words.matching_query_words("nature"), vec!["world"]);
+        // ngrams
+        assert_eq!(matching_words.matching_query_words("helloworld"), vec!["hello", "world"]);
+        assert!(matching_words.matching_query_words("unknown").is_empty());
+        assert!(!matching_words.matches("unknown"));
+
+        // only the prefix matched by the automaton is matched in a longer word
+        assert_eq!(matching_words.matching_bytes("worldwide"), Some("world".len()));
+        assert_eq!(matching_words.matching_bytes("world"), Some("world".len()));
+        assert_eq!(matching_words.matching_bytes("hallo"), Some("hallo".len()));
+        assert_eq!(matching_words.matching_bytes("unknown"), None);
+    }
+
+    #[test]
+    fn matching_words_increased_typos() {
+        let query = "hello \"big world\"";
+        let stop_words = &Set::default();
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words));
+        let result = analyzer.analyze(query);
+        let primitive_query = create_primitive_query(result.tokens());
+
+        let mut matching_words = matching_words(&TestContext::default(), true, &primitive_query).unwrap();
+        assert!(matching_words.matching_query_words("hallu").is_empty());
+        assert!(matching_words.matching_query_words("bog").is_empty());
+
+        matching_words.increase_typos();
+        assert_eq!(matching_words.matching_query_words("hallu"), vec!["hello"]);
+        // the words of the phrases stay exact
+        assert!(matching_words.matching_query_words("bog").is_empty());
+        assert_eq!(matching_words.matching_query_words("big"), vec!["big"]);
+    }
+
+    #[test]
+    fn short_prefix() {
+        let query = "hey f";
+        let stop_words = &Set::default();
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words));
+        let result = analyzer.analyze(query);
+        let tokens = result.tokens();
+
+        let expected = Operation::Or(false, vec![
+            Operation::And(vec![
+                Operation::Query(Query { prefix: false, kind: QueryKind::exact("hey".to_string()) }),
+                Operation::Query(Query { prefix: false, kind: QueryKind::exact("f".to_string()) }),
+            ]),
+            Operation::Query(Query { prefix: false, kind: QueryKind::exact("heyf".to_string()) }),
+        ]);
+
+        let mut primitive_query = create_primitive_query(tokens);
+        disable_short_prefix(&mut primitive_query, 2);
+        let strategy = TermsMatchingStrategy::All;
+        let query_tree = create_query_tree(&TestContext::default(), strategy, true, DEFAULT_MAX_NGRAM, primitive_query)
+            .unwrap();
+
+        assert_eq!(expected, query_tree);
+    }
+
     #[test]
     fn no_prefix() {
         let query = "hey friends ";
@@ -872,6 +1732,82 @@ mod test {
         assert_eq!(expected, query_tree);
     }
 
+    #[test]
+    fn frequency_terms_matching_strategy() {
+        let stop_words = &Set::default();
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words));
+        let context = TestContext::default();
+        let build = |strategy, query: &str| {
+            let result = analyzer.analyze(query);
+            context.build_with_strategy(strategy, true, result.tokens()).unwrap().unwrap()
+        };
+
+        // "world" is more frequent than "hello" which is more frequent than "split".
+        let query_tree = build(TermsMatchingStrategy::Frequency, "hello world split ");
+        let expected = Operation::Or(true, vec![
+            build(TermsMatchingStrategy::All, "split "),
+            build(TermsMatchingStrategy::All, "hello split "),
+            build(TermsMatchingStrategy::All, "hello world split "),
+        ]);
+        assert_eq!(query_tree, expected);
+
+        // The words are removed from the end with the last strategy.
+        let query_tree = build(TermsMatchingStrategy::Last, "hello world split ");
+        let expected = Operation::Or(true, vec![
+            build(TermsMatchingStrategy::All, "hello "),
+            build(TermsMatchingStrategy::All, "hello world "),
+            build(TermsMatchingStrategy::All, "hello world split "),
+        ]);
+        assert_eq!(query_tree, expected);
+    }
+
+    #[test]
+    fn boolean_syntax() {
+        let stop_words = &Set::default();
+        let analyzer = Analyzer::new(AnalyzerConfig::default_with_stopwords(stop_words));
+        let context = TestContext::default();
+        let build = |query: &str| {
+            let result = analyzer.analyze(query);
+            context.build(false, true, result.tokens()).unwrap().unwrap()
+        };
+        let build_boolean = |query: &str| {
+            let result = analyzer.analyze(query);
+            create_boolean_query(&context, true, 1, result.tokens())
+        };
+
+        let query = build_boolean("hello OR world ").unwrap();
+        let expected = Operation::Or(false, vec![build("hello "), build("world ")]);
+        assert_eq!(query.query_tree, Some(expected));
+        assert!(query.excluded_words.is_empty());
+
+        let query = build_boolean("(hello OR hi) AND world NOT earth NOT (split OR ngrams) ").unwrap();
+        let expected = Operation::And(vec![
+            Operation::Or(false, vec![build("hello "), build("hi ")]),
+            build("world "),
+        ]);
+        assert_eq!(query.query_tree, Some(expected));
+        assert_eq!(query.query_words, vec!["hello", "hi", "world"]);
+        assert_eq!(query.excluded_words, vec!["earth", "split", "ngrams"]);
+        assert!(query.matching_words.matches("hello"));
+        assert!(!query.matching_words.matches("ngrams"));
+
+        // The operators are only recognized in uppercase and outside of the phrases.
+        let query = build_boolean("\"hello OR world\" and split ").unwrap();
+        assert_eq!(query.query_words, vec!["hello", "or", "world", "and", "split"]);
+
+        // A query that only excludes words doesn't have a query tree.
+        let query = build_boolean("NOT hello").unwrap();
+        assert_eq!(query.query_tree, None);
+        assert_eq!(query.excluded_words, vec!["hello"]);
+
+        assert!(build_boolean("hello AND").is_err());
+        assert!(build_boolean("OR hello").is_err());
+        assert!(build_boolean("(hello world").is_err());
+        assert!(build_boolean("hello) world").is_err());
+        assert!(build_boolean("hello OR NOT world").is_err());
+        assert!(build_boolean("hello NOT \"split world\"").is_err());
+    }
+
     #[test]
     fn optional_word_phrase() {
         let query = "\"hey my\"";
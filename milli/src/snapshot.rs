@@ -0,0 +1,108 @@
+use heed::RoTxn;
+
+use crate::{DocumentId, FacetDistribution, Index, Search};
+
+/// A consistent view of an index, all the operations done through it
+/// see the same state of the index, the one at the time it was opened.
+///
+/// The read transaction is shared, a `&IndexSnapshot` can be copied and
+/// sent to the other threads of a scope (e.g. `rayon::scope`) to run the
+/// search, the facet distribution and the documents fetch concurrently.
+pub struct IndexSnapshot<'i> {
+    index: &'i Index,
+    rtxn: RoTxn<'i>,
+    update_sequence: u64,
+}
+
+impl<'i> IndexSnapshot<'i> {
+    pub fn new(index: &'i Index) -> heed::Result<IndexSnapshot<'i>> {
+        let rtxn = index.read_txn()?;
+        let update_sequence = index.update_sequence(&rtxn)?;
+        Ok(IndexSnapshot { index, rtxn, update_sequence })
+    }
+
+    pub fn index(&self) -> &'i Index {
+        self.index
+    }
+
+    pub fn rtxn(&self) -> &RoTxn<'i> {
+        &self.rtxn
+    }
+
+    /// Returns the update sequence of the index at the time this snapshot was opened,
+    /// two snapshots with the same update sequence see the same state of the index.
+    pub fn update_sequence(&self) -> u64 {
+        self.update_sequence
+    }
+
+    pub fn search(&self) -> Search {
+        self.index.search(&self.rtxn)
+    }
+
+    pub fn facets_distribution(&self) -> FacetDistribution {
+        self.index.facets_distribution(&self.rtxn)
+    }
+
+    /// Returns the requested documents. Returns an error if a document is missing.
+    pub fn documents(
+        &self,
+        ids: impl IntoIterator<Item=DocumentId>,
+    ) -> anyhow::Result<Vec<(DocumentId, obkv::KvReader)>>
+    {
+        self.index.documents(&self.rtxn, ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heed::EnvOpenOptions;
+
+    use crate::update::{IndexDocuments, UpdateFormat};
+    use super::*;
+
+    #[test]
+    fn snapshot_ignores_later_updates() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n1,kevin\n2,kevina\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let snapshot = index.snapshot().unwrap();
+        let update_sequence = snapshot.update_sequence();
+
+        // We index more documents from another thread while the snapshot is open.
+        let snapshot = &snapshot;
+        rayon::scope(|s| {
+            s.spawn(|_| {
+                let mut wtxn = index.write_txn().unwrap();
+                let content = &b"id,name\n3,kevin\n"[..];
+                let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+                builder.update_format(UpdateFormat::Csv);
+                builder.execute(content, |_, _| ()).unwrap();
+                wtxn.commit().unwrap();
+            });
+
+            s.spawn(move |_| {
+                let result = snapshot.search().query("kevin").execute().unwrap();
+                let documents = snapshot.documents(result.documents_ids).unwrap();
+                assert_eq!(documents.len(), 2);
+            });
+        });
+
+        assert_eq!(snapshot.update_sequence(), update_sequence);
+        let result = snapshot.search().query("kevin").execute().unwrap();
+        assert_eq!(result.documents_ids.len(), 2);
+
+        let snapshot = index.snapshot().unwrap();
+        assert!(snapshot.update_sequence() > update_sequence);
+        let result = snapshot.search().query("kevin").execute().unwrap();
+        assert_eq!(result.documents_ids.len(), 3);
+    }
+}
@@ -0,0 +1,292 @@
+//! A raw key-value abstraction over the databases of an index.
+//!
+//! This module defines the operations a backend must support so that alternative backends
+//! (in-memory, packed read-only files...) can be experimented with, database by database.
+//!
+//! The update paths write the entries they compute through the `Storage` trait and the
+//! packed indexes are read through the `ReadStorage` one, see `Index::storage_mut` and
+//! `Index::storage`. The typed accessors of the `Index` still use heed directly.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use heed::types::ByteSlice;
+use heed::{PolyDatabase, RoTxn, RwTxn};
+
+pub type StorageEntry<'a> = anyhow::Result<(Cow<'a, [u8]>, Cow<'a, [u8]>)>;
+pub type StorageIter<'a> = Box<dyn Iterator<Item = StorageEntry<'a>> + 'a>;
+
+/// The read operations of a database, keys are ordered lexicographically.
+pub trait ReadStorage {
+    fn get(&self, key: &[u8]) -> anyhow::Result<Option<Cow<[u8]>>>;
+
+    /// Returns the entries with a key in the given bounds, in key order.
+    fn range<'a>(&'a self, range: (Bound<&[u8]>, Bound<&[u8]>)) -> anyhow::Result<StorageIter<'a>>;
+
+    /// Returns the entries with a key that starts with the given prefix, in key order.
+    fn prefix_iter<'a>(&'a self, prefix: &[u8]) -> anyhow::Result<StorageIter<'a>>;
+}
+
+/// The write operations of a database.
+pub trait Storage: ReadStorage {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()>;
+
+    /// Writes an entry with a key bigger than all the keys of the database, the backends
+    /// that keep the keys sorted can avoid searching for the position of the key.
+    fn append(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        self.put(key, value)
+    }
+
+    /// Removes the entry, returns `true` if it was present.
+    fn delete(&mut self, key: &[u8]) -> anyhow::Result<bool>;
+
+    /// Removes all the entries.
+    fn clear(&mut self) -> anyhow::Result<()>;
+}
+
+/// The databases of an index, in the order they are written in the packed indexes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndexDatabase {
+    Main,
+    WordDocids,
+    WordPrefixDocids,
+    DocidWordPositions,
+    WordPairProximityDocids,
+    WordPrefixPairProximityDocids,
+    FacetFieldIdValueDocids,
+    FieldIdDocidFacetValues,
+    Documents,
+    WordDocidsShards,
+    PrefixWordPairProximityDocids,
+    WordPositionDocids,
+    WordAttributeDocids,
+}
+
+impl IndexDatabase {
+    pub const ALL: [IndexDatabase; 13] = [
+        IndexDatabase::Main,
+        IndexDatabase::WordDocids,
+        IndexDatabase::WordPrefixDocids,
+        IndexDatabase::DocidWordPositions,
+        IndexDatabase::WordPairProximityDocids,
+        IndexDatabase::WordPrefixPairProximityDocids,
+        IndexDatabase::FacetFieldIdValueDocids,
+        IndexDatabase::FieldIdDocidFacetValues,
+        IndexDatabase::Documents,
+        IndexDatabase::WordDocidsShards,
+        IndexDatabase::PrefixWordPairProximityDocids,
+        IndexDatabase::WordPositionDocids,
+        IndexDatabase::WordAttributeDocids,
+    ];
+}
+
+/// A read-only view of an LMDB database.
+pub struct HeedReader<'t, 'i> {
+    rtxn: &'t RoTxn<'i>,
+    db: PolyDatabase,
+}
+
+impl<'t, 'i> HeedReader<'t, 'i> {
+    pub fn new(rtxn: &'t RoTxn<'i>, db: PolyDatabase) -> HeedReader<'t, 'i> {
+        HeedReader { rtxn, db }
+    }
+}
+
+impl ReadStorage for HeedReader<'_, '_> {
+    fn get(&self, key: &[u8]) -> anyhow::Result<Option<Cow<[u8]>>> {
+        heed_get(self.rtxn, self.db, key)
+    }
+
+    fn range<'a>(&'a self, range: (Bound<&[u8]>, Bound<&[u8]>)) -> anyhow::Result<StorageIter<'a>> {
+        heed_range(self.rtxn, self.db, range)
+    }
+
+    fn prefix_iter<'a>(&'a self, prefix: &[u8]) -> anyhow::Result<StorageIter<'a>> {
+        heed_prefix_iter(self.rtxn, self.db, prefix)
+    }
+}
+
+/// A writable view of an LMDB database, the changes are visible once the transaction is committed.
+pub struct HeedWriter<'t, 'i, 'u> {
+    wtxn: &'t mut RwTxn<'i, 'u>,
+    db: PolyDatabase,
+}
+
+impl<'t, 'i, 'u> HeedWriter<'t, 'i, 'u> {
+    pub fn new(wtxn: &'t mut RwTxn<'i, 'u>, db: PolyDatabase) -> HeedWriter<'t, 'i, 'u> {
+        HeedWriter { wtxn, db }
+    }
+}
+
+impl ReadStorage for HeedWriter<'_, '_, '_> {
+    fn get(&self, key: &[u8]) -> anyhow::Result<Option<Cow<[u8]>>> {
+        heed_get(&*self.wtxn, self.db, key)
+    }
+
+    fn range<'a>(&'a self, range: (Bound<&[u8]>, Bound<&[u8]>)) -> anyhow::Result<StorageIter<'a>> {
+        heed_range(&*self.wtxn, self.db, range)
+    }
+
+    fn prefix_iter<'a>(&'a self, prefix: &[u8]) -> anyhow::Result<StorageIter<'a>> {
+        heed_prefix_iter(&*self.wtxn, self.db, prefix)
+    }
+}
+
+impl Storage for HeedWriter<'_, '_, '_> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        Ok(self.db.put::<_, ByteSlice, ByteSlice>(self.wtxn, key, value)?)
+    }
+
+    fn append(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        let mut iter = self.db.iter_mut::<_, ByteSlice, ByteSlice>(self.wtxn)?;
+        Ok(iter.append(key, value)?)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> anyhow::Result<bool> {
+        Ok(self.db.delete::<_, ByteSlice>(self.wtxn, key)?)
+    }
+
+    fn clear(&mut self) -> anyhow::Result<()> {
+        Ok(self.db.clear(self.wtxn)?)
+    }
+}
+
+fn heed_get<'a>(rtxn: &'a RoTxn, db: PolyDatabase, key: &[u8]) -> anyhow::Result<Option<Cow<'a, [u8]>>> {
+    Ok(db.get::<_, ByteSlice, ByteSlice>(rtxn, key)?.map(Cow::Borrowed))
+}
+
+fn heed_range<'a>(
+    rtxn: &'a RoTxn,
+    db: PolyDatabase,
+    range: (Bound<&[u8]>, Bound<&[u8]>),
+) -> anyhow::Result<StorageIter<'a>>
+{
+    let iter = db.range::<_, ByteSlice, ByteSlice, _>(rtxn, &range)?;
+    Ok(Box::new(iter.map(|result| {
+        result.map(|(k, v)| (Cow::Borrowed(k), Cow::Borrowed(v))).map_err(Into::into)
+    })))
+}
+
+fn heed_prefix_iter<'a>(rtxn: &'a RoTxn, db: PolyDatabase, prefix: &[u8]) -> anyhow::Result<StorageIter<'a>> {
+    let iter = db.prefix_iter::<_, ByteSlice, ByteSlice>(rtxn, prefix)?;
+    Ok(Box::new(iter.map(|result| {
+        result.map(|(k, v)| (Cow::Borrowed(k), Cow::Borrowed(v))).map_err(Into::into)
+    })))
+}
+
+/// A database entirely kept in memory, mostly useful for tests and experiments.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStorage {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl ReadStorage for MemoryStorage {
+    fn get(&self, key: &[u8]) -> anyhow::Result<Option<Cow<[u8]>>> {
+        Ok(self.entries.get(key).map(|v| Cow::Borrowed(v.as_slice())))
+    }
+
+    fn range<'a>(&'a self, range: (Bound<&[u8]>, Bound<&[u8]>)) -> anyhow::Result<StorageIter<'a>> {
+        let iter = self.entries.range::<[u8], _>(range);
+        Ok(Box::new(iter.map(|(k, v)| Ok((Cow::Borrowed(k.as_slice()), Cow::Borrowed(v.as_slice()))))))
+    }
+
+    fn prefix_iter<'a>(&'a self, prefix: &[u8]) -> anyhow::Result<StorageIter<'a>> {
+        let prefix = prefix.to_vec();
+        let iter = self.entries.range::<[u8], _>((Bound::Included(prefix.as_slice()), Bound::Unbounded))
+            .take_while(move |(k, _)| k.starts_with(&prefix));
+        Ok(Box::new(iter.map(|(k, v)| Ok((Cow::Borrowed(k.as_slice()), Cow::Borrowed(v.as_slice()))))))
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        self.entries.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> anyhow::Result<bool> {
+        Ok(self.entries.remove(key).is_some())
+    }
+
+    fn clear(&mut self) -> anyhow::Result<()> {
+        self.entries.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heed::EnvOpenOptions;
+
+    use crate::Index;
+    use super::*;
+
+    fn keys(iter: StorageIter) -> Vec<Vec<u8>> {
+        iter.map(|result| result.unwrap().0.into_owned()).collect()
+    }
+
+    fn check_storage<S: Storage>(storage: &mut S) {
+        storage.put(b"hello", b"1").unwrap();
+        storage.put(b"help", b"2").unwrap();
+        storage.put(b"world", b"3").unwrap();
+
+        assert_eq!(storage.get(b"help").unwrap().as_deref(), Some(&b"2"[..]));
+        assert_eq!(storage.get(b"hel").unwrap(), None);
+
+        let iter = storage.prefix_iter(b"hel").unwrap();
+        assert_eq!(keys(iter), vec![b"hello".to_vec(), b"help".to_vec()]);
+
+        let iter = storage.range((Bound::Excluded(&b"hello"[..]), Bound::Included(&b"world"[..]))).unwrap();
+        assert_eq!(keys(iter), vec![b"help".to_vec(), b"world".to_vec()]);
+
+        assert!(storage.delete(b"hello").unwrap());
+        assert!(!storage.delete(b"hello").unwrap());
+        assert_eq!(storage.get(b"hello").unwrap(), None);
+
+        storage.append(b"zebra", b"4").unwrap();
+        let iter = storage.range((Bound::Excluded(&b"world"[..]), Bound::Unbounded)).unwrap();
+        assert_eq!(keys(iter), vec![b"zebra".to_vec()]);
+    }
+
+    #[test]
+    fn memory_storage() {
+        let mut storage = MemoryStorage::new();
+        check_storage(&mut storage);
+        assert_eq!(storage.len(), 3);
+
+        storage.clear().unwrap();
+        assert!(storage.is_empty());
+    }
+
+    #[test]
+    fn heed_storage() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        check_storage(&mut index.storage_mut(&mut wtxn, IndexDatabase::WordDocids));
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let reader = index.storage(&rtxn, IndexDatabase::WordDocids);
+        assert_eq!(reader.get(b"help").unwrap().as_deref(), Some(&b"2"[..]));
+        assert_eq!(index.word_docids.len(&rtxn).unwrap(), 3);
+    }
+}
@@ -2,6 +2,13 @@ use std::iter::{Chain, FromIterator};
 use std::ops::RangeInclusive;
 use roaring::bitmap::{RoaringBitmap, IntoIter};
 
+/// An iterator over the internal documents ids that are not used by any document.
+///
+/// The ids of the deleted documents are recycled first, in ascending order, before the ids
+/// that were never used. Internal ids are therefore always kept compact and the `u32` space
+/// can only be exhausted by an index that contains more than `u32::MAX` documents,
+/// the number of deletions and additions an index has seen doesn't matter.
+/// The `CompactDocumentsIds` update renumbers the ids of an index left sparse by deletions.
 pub struct AvailableDocumentsIds {
     iter: Chain<IntoIter, RangeInclusive<u32>>,
 }
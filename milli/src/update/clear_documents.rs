@@ -1,5 +1,6 @@
 use chrono::Utc;
 use roaring::RoaringBitmap;
+use crate::storage::{IndexDatabase, Storage};
 use crate::{ExternalDocumentsIds, Index};
 
 pub struct ClearDocuments<'t, 'u, 'i> {
@@ -20,18 +21,6 @@ impl<'t, 'u, 'i> ClearDocuments<'t, 'u, 'i> {
 
     pub fn execute(self) -> anyhow::Result<u64> {
         self.index.set_updated_at(self.wtxn, &Utc::now())?;
-        let Index {
-            env: _env,
-            main: _main,
-            word_docids,
-            word_prefix_docids,
-            docid_word_positions,
-            word_pair_proximity_docids,
-            word_prefix_pair_proximity_docids,
-            facet_field_id_value_docids,
-            field_id_docid_facet_values,
-            documents,
-        } = self.index;
 
         // We retrieve the number of documents ids that we are deleting.
         let number_of_documents = self.index.number_of_documents(self.wtxn)?;
@@ -48,15 +37,15 @@ impl<'t, 'u, 'i> ClearDocuments<'t, 'u, 'i> {
             self.index.put_faceted_documents_ids(self.wtxn, field_id, &RoaringBitmap::default())?;
         }
 
+        // We remove the original string facet values, the normalizations may have changed.
+        for (field_id, _) in self.index.fields_ids_map(self.wtxn)?.iter() {
+            self.index.delete_facet_string_display_values(self.wtxn, field_id)?;
+        }
+
         // Clear the other databases.
-        word_docids.clear(self.wtxn)?;
-        word_prefix_docids.clear(self.wtxn)?;
-        docid_word_positions.clear(self.wtxn)?;
-        word_pair_proximity_docids.clear(self.wtxn)?;
-        word_prefix_pair_proximity_docids.clear(self.wtxn)?;
-        facet_field_id_value_docids.clear(self.wtxn)?;
-        field_id_docid_facet_values.clear(self.wtxn)?;
-        documents.clear(self.wtxn)?;
+        for database in IndexDatabase::ALL.iter().filter(|db| **db != IndexDatabase::Main) {
+            self.index.storage_mut(self.wtxn, *database).clear()?;
+        }
 
         Ok(number_of_documents)
     }
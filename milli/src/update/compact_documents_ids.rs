@@ -0,0 +1,155 @@
+use anyhow::Context;
+use chrono::Utc;
+use grenad::CompressionType;
+use rayon::ThreadPool;
+
+use crate::Index;
+use super::index_documents::Transform;
+use super::{ClearDocuments, IndexDocuments, IndexDocumentsMethod, UpdateIndexingStep};
+
+/// Gives the documents new internal ids going from zero to the number of documents.
+///
+/// The ids of the deleted documents are recycled by the next additions, the `u32` space can
+/// only run out when the index contains more than `u32::MAX` documents. However the ids of an
+/// index where a lot of documents were deleted stay sparse until enough documents are added,
+/// this update reindexes the documents under compact ids, the external ids are kept.
+pub struct CompactDocumentsIds<'a, 't, 'u, 'i> {
+    wtxn: &'t mut heed::RwTxn<'i, 'u>,
+    index: &'i Index,
+    pub(crate) log_every_n: Option<usize>,
+    pub(crate) max_nb_chunks: Option<usize>,
+    pub(crate) max_memory: Option<usize>,
+    pub(crate) linked_hash_map_size: Option<usize>,
+    pub(crate) chunk_compression_type: CompressionType,
+    pub(crate) chunk_compression_level: Option<u32>,
+    pub(crate) chunk_fusing_shrink_size: Option<u64>,
+    pub(crate) thread_pool: Option<&'a ThreadPool>,
+    update_id: u64,
+}
+
+impl<'a, 't, 'u, 'i> CompactDocumentsIds<'a, 't, 'u, 'i> {
+    pub fn new(
+        wtxn: &'t mut heed::RwTxn<'i, 'u>,
+        index: &'i Index,
+        update_id: u64,
+    ) -> CompactDocumentsIds<'a, 't, 'u, 'i>
+    {
+        CompactDocumentsIds {
+            wtxn,
+            index,
+            log_every_n: None,
+            max_nb_chunks: None,
+            max_memory: None,
+            linked_hash_map_size: None,
+            chunk_compression_type: CompressionType::None,
+            chunk_compression_level: None,
+            chunk_fusing_shrink_size: None,
+            thread_pool: None,
+            update_id,
+        }
+    }
+
+    /// Returns `false` when the ids were already compact and nothing had to be reindexed.
+    pub fn execute<F>(self, progress_callback: F) -> anyhow::Result<bool>
+    where
+        F: Fn(UpdateIndexingStep, u64) + Sync
+    {
+        self.index.set_updated_at(self.wtxn, &Utc::now())?;
+
+        // The ids are compact when the biggest one is the number of documents minus one.
+        let documents_ids = self.index.documents_ids(self.wtxn)?;
+        match documents_ids.max() {
+            Some(max) if u64::from(max) + 1 != documents_ids.len() => (),
+            _otherwise => return Ok(false),
+        }
+
+        let transform = Transform {
+            rtxn: &self.wtxn,
+            index: self.index,
+            log_every_n: self.log_every_n,
+            chunk_compression_type: self.chunk_compression_type,
+            chunk_compression_level: self.chunk_compression_level,
+            chunk_fusing_shrink_size: self.chunk_fusing_shrink_size,
+            max_nb_chunks: self.max_nb_chunks,
+            max_memory: self.max_memory,
+            index_documents_method: IndexDocumentsMethod::ReplaceDocuments,
+            autogenerate_docids: false,
+        };
+
+        // There are documents in the index, the primary key must be set.
+        let primary_key = self.index.primary_key(&self.wtxn)?.context("Index must have a primary key")?;
+        let output = transform.compact_documents_ids(primary_key.to_string())?;
+
+        // We clear the full database and index the documents under their new ids.
+        ClearDocuments::new(self.wtxn, self.index, self.update_id).execute()?;
+
+        let update_id = self.update_id;
+        let mut indexing_builder = IndexDocuments::new(self.wtxn, self.index, self.update_id);
+        indexing_builder.log_every_n = self.log_every_n;
+        indexing_builder.max_nb_chunks = self.max_nb_chunks;
+        indexing_builder.max_memory = self.max_memory;
+        indexing_builder.linked_hash_map_size = self.linked_hash_map_size;
+        indexing_builder.chunk_compression_type = self.chunk_compression_type;
+        indexing_builder.chunk_compression_level = self.chunk_compression_level;
+        indexing_builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+        indexing_builder.thread_pool = self.thread_pool;
+        indexing_builder.execute_raw(output, |step| progress_callback(step, update_id))?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heed::EnvOpenOptions;
+
+    use crate::update::{DeleteDocuments, IndexDocuments, UpdateFormat};
+    use super::*;
+
+    #[test]
+    fn compact_sparse_documents_ids() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n0,kevin\n1,kevina\n2,benoit\n3,bernard\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+
+        let mut builder = DeleteDocuments::new(&mut wtxn, &index, 1).unwrap();
+        builder.delete_document(0);
+        builder.delete_document(2);
+        builder.execute().unwrap();
+
+        let compacted = CompactDocumentsIds::new(&mut wtxn, &index, 2).execute(|_, _| ()).unwrap();
+        assert!(compacted);
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.documents_ids(&rtxn).unwrap(), (0..2).collect());
+
+        let external_documents_ids = index.external_documents_ids(&rtxn).unwrap();
+        assert_eq!(external_documents_ids.get("1"), Some(0));
+        assert_eq!(external_documents_ids.get("3"), Some(1));
+        assert_eq!(external_documents_ids.get("0"), None);
+
+        assert_eq!(index.word_docids.get(&rtxn, "kevina").unwrap(), Some((0..1).collect()));
+        assert_eq!(index.word_docids.get(&rtxn, "bernard").unwrap(), Some((1..2).collect()));
+        assert!(index.word_docids.get(&rtxn, "benoit").unwrap().is_none());
+
+        let fields_ids_map = index.fields_ids_map(&rtxn).unwrap();
+        let name_id = fields_ids_map.id("name").unwrap();
+        let documents = index.documents(&rtxn, Some(1)).unwrap();
+        assert_eq!(documents[0].1.get(name_id), Some(&br#""bernard""#[..]));
+        drop(rtxn);
+
+        // The ids are already compact, nothing is reindexed.
+        let mut wtxn = index.write_txn().unwrap();
+        let compacted = CompactDocumentsIds::new(&mut wtxn, &index, 3).execute(|_, _| ()).unwrap();
+        assert!(!compacted);
+        wtxn.commit().unwrap();
+    }
+}
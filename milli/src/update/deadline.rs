@@ -0,0 +1,26 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+
+/// The error returned by an update that didn't finish before its deadline.
+///
+/// The update is interrupted in the middle of its work, the write
+/// transaction must be aborted, nothing of it must be committed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DeadlineExceeded;
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("The update didn't finish before its deadline and has been interrupted")
+    }
+}
+
+impl Error for DeadlineExceeded { }
+
+/// Returns an error if the deadline, if any, is reached.
+pub(crate) fn check_deadline(deadline: Option<Instant>) -> Result<(), DeadlineExceeded> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(DeadlineExceeded),
+        _ => Ok(()),
+    }
+}
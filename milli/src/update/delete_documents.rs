@@ -1,13 +1,16 @@
+use std::collections::HashMap;
+
 use anyhow::anyhow;
 use chrono::Utc;
 use fst::IntoStreamer;
+use heed::BytesDecode;
 use heed::types::ByteSlice;
 use roaring::RoaringBitmap;
 use serde_json::Value;
 
 use crate::facet::FacetType;
-use crate::{Index, BEU32, SmallString32, ExternalDocumentsIds};
-use crate::heed_codec::facet::{FieldDocIdFacetStringCodec, FieldDocIdFacetF64Codec, FieldDocIdFacetI64Codec};
+use crate::{Index, BEU32, FieldId, SmallString32, ExternalDocumentsIds};
+use crate::heed_codec::facet::{FacetValueStringCodec, FieldDocIdFacetStringCodec, FieldDocIdFacetF64Codec, FieldDocIdFacetI64Codec};
 use super::ClearDocuments;
 
 pub struct DeleteDocuments<'t, 'u, 'i> {
@@ -81,13 +84,18 @@ impl<'t, 'u, 'i> DeleteDocuments<'t, 'u, 'i> {
             env: _env,
             main: _main,
             word_docids,
+            word_docids_shards,
             word_prefix_docids,
             docid_word_positions,
             word_pair_proximity_docids,
             word_prefix_pair_proximity_docids,
+            prefix_word_pair_proximity_docids,
+            word_position_docids,
+            word_attribute_docids,
             facet_field_id_value_docids,
             field_id_docid_facet_values,
             documents,
+            search_cache: _,
         } = self.index;
 
         // Retrieve the words and the external documents ids contained in the documents.
@@ -138,7 +146,9 @@ impl<'t, 'u, 'i> DeleteDocuments<'t, 'u, 'i> {
 
         // Maybe we can improve the get performance of the words
         // if we sort the words first, keeping the LMDB pages in cache.
+        // A word appears once per deleted document, we only need to visit it once.
         words.sort_unstable();
+        words.dedup();
 
         // We iterate over the words and delete the documents ids
         // from the word docids database.
@@ -167,21 +177,25 @@ impl<'t, 'u, 'i> DeleteDocuments<'t, 'u, 'i> {
         });
         let words_to_delete = fst::Set::from_iter(words_to_delete)?;
 
-        let new_words_fst = {
-            // We retrieve the current words FST from the database.
-            let words_fst = self.index.words_fst(self.wtxn)?;
-            let difference = words_fst.op().add(&words_to_delete).difference();
+        // We only rewrite the words FST when some words are no more part of the dictionnary,
+        // the previous FST is streamed and the deleted words are skipped.
+        if !words_to_delete.is_empty() {
+            let new_words_fst = {
+                // We retrieve the current words FST from the database.
+                let words_fst = self.index.words_fst(self.wtxn)?;
+                let difference = words_fst.op().add(&words_to_delete).difference();
 
-            // We stream the new external ids that does no more contains the to-delete external ids.
-            let mut new_words_fst_builder = fst::SetBuilder::memory();
-            new_words_fst_builder.extend_stream(difference.into_stream())?;
+                // We stream the new words that does no more contains the to-delete words.
+                let mut new_words_fst_builder = fst::SetBuilder::memory();
+                new_words_fst_builder.extend_stream(difference.into_stream())?;
 
-            // We create an words FST set from the above builder.
-            new_words_fst_builder.into_set()
-        };
+                // We create an words FST set from the above builder.
+                new_words_fst_builder.into_set()
+            };
 
-        // We write the new words FST into the main database.
-        self.index.put_words_fst(self.wtxn, &new_words_fst)?;
+            // We write the new words FST into the main database.
+            self.index.put_words_fst(self.wtxn, &new_words_fst)?;
+        }
 
         // We iterate over the word prefix docids database and remove the deleted documents ids
         // from every docids lists. We register the empty prefixes in an fst Set for futur deletion.
@@ -221,6 +235,29 @@ impl<'t, 'u, 'i> DeleteDocuments<'t, 'u, 'i> {
             self.index.put_words_prefixes_fst(self.wtxn, &new_words_prefixes_fst)?;
         }
 
+        // We delete the documents ids from the shards of the words of the deleted documents, the
+        // words that are no more frequent keep their shards until the next indexation rebuilds them.
+        let shards = word_docids_shards.remap_key_type::<ByteSlice>();
+        let mut prefix = Vec::new();
+        for (word, _) in &words {
+            // The word is followed by a zero byte in the keys, the longer words are skipped.
+            prefix.clear();
+            prefix.extend_from_slice(word.as_bytes());
+            prefix.push(0);
+
+            let mut iter = shards.prefix_iter_mut(self.wtxn, &prefix)?;
+            while let Some(result) = iter.next() {
+                let (key, mut docids) = result?;
+                let previous_len = docids.len();
+                docids.difference_with(&self.documents_ids);
+                if docids.is_empty() {
+                    iter.del_current()?;
+                } else if docids.len() != previous_len {
+                    iter.put_current(key, &docids)?;
+                }
+            }
+        }
+
         // We delete the documents ids from the word prefix pair proximity database docids
         // and remove the empty pairs too.
         let db = word_prefix_pair_proximity_docids.remap_key_type::<ByteSlice>();
@@ -238,6 +275,22 @@ impl<'t, 'u, 'i> DeleteDocuments<'t, 'u, 'i> {
 
         drop(iter);
 
+        // We do the same for the prefix word pair proximity database.
+        let db = prefix_word_pair_proximity_docids.remap_key_type::<ByteSlice>();
+        let mut iter = db.iter_mut(self.wtxn)?;
+        while let Some(result) = iter.next() {
+            let (key, mut docids) = result?;
+            let previous_len = docids.len();
+            docids.difference_with(&self.documents_ids);
+            if docids.is_empty() {
+                iter.del_current()?;
+            } else if docids.len() != previous_len {
+                iter.put_current(key, &docids)?;
+            }
+        }
+
+        drop(iter);
+
         // We delete the documents ids that are under the pairs of words,
         // it is faster and use no memory to iterate over all the words pairs than
         // to compute the cartesian product of every words of the deleted documents.
@@ -255,9 +308,39 @@ impl<'t, 'u, 'i> DeleteDocuments<'t, 'u, 'i> {
 
         drop(iter);
 
+        // We do the same for the words positions buckets, there are only a few buckets by word.
+        let mut iter = word_position_docids.remap_key_type::<ByteSlice>().iter_mut(self.wtxn)?;
+        while let Some(result) = iter.next() {
+            let (bytes, mut docids) = result?;
+            let previous_len = docids.len();
+            docids.difference_with(&self.documents_ids);
+            if docids.is_empty() {
+                iter.del_current()?;
+            } else if docids.len() != previous_len {
+                iter.put_current(bytes, &docids)?;
+            }
+        }
+
+        drop(iter);
+
+        // And for the attributes of the words.
+        let mut iter = word_attribute_docids.remap_key_type::<ByteSlice>().iter_mut(self.wtxn)?;
+        while let Some(result) = iter.next() {
+            let (bytes, mut docids) = result?;
+            let previous_len = docids.len();
+            docids.difference_with(&self.documents_ids);
+            if docids.is_empty() {
+                iter.del_current()?;
+            } else if docids.len() != previous_len {
+                iter.put_current(bytes, &docids)?;
+            }
+        }
+
+        drop(iter);
+
         // Remove the documents ids from the faceted documents ids.
         let faceted_fields = self.index.faceted_fields_ids(self.wtxn)?;
-        for (field_id, facet_type) in faceted_fields {
+        for (&field_id, &facet_type) in &faceted_fields {
             let mut docids = self.index.faceted_documents_ids(self.wtxn, field_id)?;
             docids.difference_with(&self.documents_ids);
             self.index.put_faceted_documents_ids(self.wtxn, field_id, &docids)?;
@@ -295,13 +378,21 @@ impl<'t, 'u, 'i> DeleteDocuments<'t, 'u, 'i> {
             }
         }
 
-        // We delete the documents ids that are under the facet field id values.
+        // We delete the documents ids that are under the facet field id values,
+        // we keep the string values that are no more used by any document.
+        let mut removed_string_values: HashMap<FieldId, Vec<String>> = HashMap::new();
         let mut iter = facet_field_id_value_docids.iter_mut(self.wtxn)?;
         while let Some(result) = iter.next() {
             let (bytes, mut docids) = result?;
             let previous_len = docids.len();
             docids.difference_with(&self.documents_ids);
             if docids.is_empty() {
+                let field_id = bytes[0];
+                if let Some(FacetType::String) = faceted_fields.get(&field_id) {
+                    if let Some((_, value)) = FacetValueStringCodec::bytes_decode(bytes) {
+                        removed_string_values.entry(field_id).or_default().push(value.to_string());
+                    }
+                }
                 iter.del_current()?;
             } else if docids.len() != previous_len {
                 iter.put_current(bytes, &docids)?;
@@ -310,6 +401,18 @@ impl<'t, 'u, 'i> DeleteDocuments<'t, 'u, 'i> {
 
         drop(iter);
 
+        // The original forms of the string values that are no more used are removed.
+        for (field_id, values) in removed_string_values {
+            let mut display_values = self.index.facet_string_display_values(self.wtxn, field_id)?;
+            let previous_len = display_values.len();
+            values.iter().for_each(|value| { display_values.remove(value); });
+            if display_values.is_empty() {
+                self.index.delete_facet_string_display_values(self.wtxn, field_id)?;
+            } else if display_values.len() != previous_len {
+                self.index.put_facet_string_display_values(self.wtxn, field_id, &display_values)?;
+            }
+        }
+
         Ok(self.documents_ids.len())
     }
 }
@@ -317,8 +420,10 @@ impl<'t, 'u, 'i> DeleteDocuments<'t, 'u, 'i> {
 #[cfg(test)]
 mod tests {
     use heed::EnvOpenOptions;
+    use maplit::hashmap;
 
-    use crate::update::{IndexDocuments, UpdateFormat};
+    use crate::facet::FacetStringNormalization;
+    use crate::update::{IndexDocuments, IndexDocumentsMethod, Settings, UpdateFormat};
     use super::*;
 
     #[test]
@@ -348,4 +453,115 @@ mod tests {
 
         wtxn.commit().unwrap();
     }
+
+    #[test]
+    fn recycle_deleted_documents_ids() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n0,kevin\n1,kevina\n2,benoit\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+
+        let mut builder = DeleteDocuments::new(&mut wtxn, &index, 1).unwrap();
+        builder.delete_document(1);
+        builder.execute().unwrap();
+
+        // The new document takes the internal id of the deleted one.
+        let content = &b"id,name\n3,bernard\n4,bertrand\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 2);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let external_documents_ids = index.external_documents_ids(&rtxn).unwrap();
+        assert_eq!(external_documents_ids.get("3"), Some(1));
+        assert_eq!(external_documents_ids.get("4"), Some(3));
+        assert_eq!(index.documents_ids(&rtxn).unwrap(), (0..4).collect());
+
+        // Nothing remains of the deleted document under the recycled id.
+        assert!(index.word_docids.get(&rtxn, "kevina").unwrap().is_none());
+        assert_eq!(index.word_docids.get(&rtxn, "bernard").unwrap(), Some((1..2).collect()));
+    }
+
+    #[test]
+    fn words_fst_delta() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n0,kevin\n1,kevina\n2,benoit\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+
+        // The words of the second batch are merged into the previous words FST.
+        let content = &b"id,name\n3,kevin\n4,tamo\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.index_documents_method(IndexDocumentsMethod::UpdateDocuments);
+        builder.execute(content, |_, _| ()).unwrap();
+
+        let words: Vec<_> = index.words_fst(&wtxn).unwrap().stream().into_strs().unwrap();
+        assert_eq!(words, vec!["0", "1", "2", "3", "4", "benoit", "kevin", "kevina", "tamo"]);
+
+        // "kevin" is still used by the document 3, only the words of the document 2 are removed.
+        let mut builder = DeleteDocuments::new(&mut wtxn, &index, 2).unwrap();
+        builder.delete_document(0);
+        builder.delete_document(2);
+        builder.execute().unwrap();
+
+        let words: Vec<_> = index.words_fst(&wtxn).unwrap().stream().into_strs().unwrap();
+        assert_eq!(words, vec!["1", "3", "4", "kevin", "kevina", "tamo"]);
+
+        wtxn.commit().unwrap();
+    }
+
+    #[test]
+    fn delete_facet_string_display_values() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "city".into() => "string".into() });
+        let normalization = FacetStringNormalization { ignore_case: true, ignore_diacritics: true };
+        builder.set_facet_string_normalizations(hashmap!{ "city".into() => normalization });
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = "id,city\n0,Besançon\n1,besancon\n2,Paris\n".as_bytes();
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+
+        let city_id = index.fields_ids_map(&wtxn).unwrap().id("city").unwrap();
+        let display_values = index.facet_string_display_values(&wtxn, city_id).unwrap();
+        assert_eq!(display_values.values().collect::<Vec<_>>(), vec!["Besançon", "Paris"]);
+
+        // "Besançon" is still used by the document 1, only "Paris" is removed.
+        let mut builder = DeleteDocuments::new(&mut wtxn, &index, 2).unwrap();
+        builder.delete_document(0);
+        builder.delete_document(2);
+        builder.execute().unwrap();
+
+        let display_values = index.facet_string_display_values(&wtxn, city_id).unwrap();
+        assert_eq!(display_values.values().collect::<Vec<_>>(), vec!["Besançon"]);
+
+        let mut builder = DeleteDocuments::new(&mut wtxn, &index, 3).unwrap();
+        builder.delete_document(1);
+        builder.execute().unwrap();
+
+        assert!(index.facet_string_display_values(&wtxn, city_id).unwrap().is_empty());
+
+        wtxn.commit().unwrap();
+    }
 }
@@ -13,9 +13,10 @@ use roaring::RoaringBitmap;
 use crate::facet::FacetType;
 use crate::heed_codec::CboRoaringBitmapCodec;
 use crate::heed_codec::facet::{FacetLevelValueI64Codec, FacetLevelValueF64Codec};
+use crate::storage::IndexDatabase;
 use crate::Index;
 use crate::update::index_documents::WriteMethod;
-use crate::update::index_documents::{create_writer, writer_into_reader, write_into_lmdb_database};
+use crate::update::index_documents::{create_writer, writer_into_reader, write_into_storage};
 
 pub struct Facets<'t, 'u, 'i> {
     wtxn: &'t mut heed::RwTxn<'i, 'u>,
@@ -129,9 +130,8 @@ impl<'t, 'u, 'i> Facets<'t, 'u, 'i> {
             };
 
             if let Some(content) = content {
-                write_into_lmdb_database(
-                    self.wtxn,
-                    *self.index.facet_field_id_value_docids.as_polymorph(),
+                write_into_storage(
+                    &mut self.index.storage_mut(self.wtxn, IndexDatabase::FacetFieldIdValueDocids),
                     content,
                     |_, _| anyhow::bail!("invalid facet level merging"),
                     WriteMethod::GetMergePut,
@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 
 use anyhow::{bail, ensure, Context};
 use bstr::ByteSlice as _;
@@ -6,17 +7,20 @@ use fst::IntoStreamer;
 use roaring::RoaringBitmap;
 
 use crate::heed_codec::CboRoaringBitmapCodec;
+use crate::index::insert_facet_string_display_value;
 
 const WORDS_FST_KEY: &[u8] = crate::index::WORDS_FST_KEY.as_bytes();
 const FIELDS_IDS_MAP_KEY: &[u8] = crate::index::FIELDS_IDS_MAP_KEY.as_bytes();
 const DOCUMENTS_IDS_KEY: &[u8] = crate::index::DOCUMENTS_IDS_KEY.as_bytes();
+const FACET_STRING_DISPLAY_VALUES_PREFIX: &[u8] = crate::index::FACET_STRING_DISPLAY_VALUES_PREFIX.as_bytes();
 
 pub fn main_merge(key: &[u8], values: &[Cow<[u8]>]) -> anyhow::Result<Vec<u8>> {
     match key {
         WORDS_FST_KEY => {
             let fsts: Vec<_> = values.iter().map(|v| fst::Set::new(v).unwrap()).collect();
 
-            // Union of the FSTs
+            // Union of the FSTs, the words FST stored in the database is merged with the FSTs
+            // of the words of the new documents, it is never built from the word docids again.
             let mut op = fst::set::OpBuilder::new();
             fsts.iter().for_each(|fst| op.push(fst.into_stream()));
             let op = op.r#union();
@@ -30,6 +34,17 @@ pub fn main_merge(key: &[u8], values: &[Cow<[u8]>]) -> anyhow::Result<Vec<u8>> {
             Ok(values[0].to_vec())
         },
         DOCUMENTS_IDS_KEY => roaring_bitmap_merge(values),
+        key if key.starts_with(FACET_STRING_DISPLAY_VALUES_PREFIX) => {
+            // The first original value seen for a normalized value is the one that is kept.
+            let mut display_values = BTreeMap::<String, String>::new();
+            for value in values {
+                let other: BTreeMap<String, String> = serde_json::from_slice(value)?;
+                for (normalized, original) in other {
+                    insert_facet_string_display_value(&mut display_values, normalized, original);
+                }
+            }
+            Ok(serde_json::to_vec(&display_values)?)
+        },
         otherwise => bail!("wut {:?}", otherwise),
     }
 }
@@ -52,6 +67,14 @@ pub fn words_pairs_proximities_docids_merge(_key: &[u8], values: &[Cow<[u8]>]) -
     cbo_roaring_bitmap_merge(values)
 }
 
+pub fn word_position_docids_merge(_key: &[u8], values: &[Cow<[u8]>]) -> anyhow::Result<Vec<u8>> {
+    cbo_roaring_bitmap_merge(values)
+}
+
+pub fn word_attribute_docids_merge(_key: &[u8], values: &[Cow<[u8]>]) -> anyhow::Result<Vec<u8>> {
+    cbo_roaring_bitmap_merge(values)
+}
+
 pub fn facet_field_value_docids_merge(_key: &[u8], values: &[Cow<[u8]>]) -> anyhow::Result<Vec<u8>> {
     cbo_roaring_bitmap_merge(values)
 }
@@ -1,8 +1,9 @@
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{self, Seek, SeekFrom};
 use std::num::NonZeroUsize;
+use std::str;
 use std::sync::mpsc::sync_channel;
 use std::time::Instant;
 
@@ -10,22 +11,24 @@ use anyhow::Context;
 use bstr::ByteSlice as _;
 use chrono::Utc;
 use grenad::{MergerIter, Writer, Sorter, Merger, Reader, FileFuse, CompressionType};
-use heed::types::ByteSlice;
 use log::{debug, info, error};
 use memmap::Mmap;
 use rayon::ThreadPool;
 use rayon::prelude::*;
 use serde::{Serialize, Deserialize};
 
-use crate::index::Index;
-use crate::update::{Facets, WordsPrefixes, UpdateIndexingStep};
-use self::store::{Store, Readers};
+use crate::index::{Index, WORDS_FST_KEY};
+use crate::storage::{IndexDatabase, Storage};
+use crate::update::{Facets, WordDocidsShards, WordsPrefixes, UpdateIndexingStep};
+use crate::update::deadline::check_deadline;
+use self::store::{nested_faceted_fields, Store, Readers};
 pub use self::merge_function::{
     main_merge, word_docids_merge, words_pairs_proximities_docids_merge,
     docid_word_positions_merge, documents_merge, facet_field_value_docids_merge,
-    field_id_docid_facet_values_merge,
+    field_id_docid_facet_values_merge, word_position_docids_merge, word_attribute_docids_merge,
 };
 pub use self::transform::{Transform, TransformOutput};
+pub(crate) use self::store::process_tokens;
 
 use crate::MergeFn;
 use super::UpdateBuilder;
@@ -37,6 +40,19 @@ mod transform;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DocumentAdditionResult {
     nb_documents: usize,
+    /// The external ids of the documents that contained words beyond the maximum indexable
+    /// position, associated with the number of words that were clamped or dropped.
+    truncated_documents: BTreeMap<String, usize>,
+}
+
+impl DocumentAdditionResult {
+    pub fn nb_documents(&self) -> usize {
+        self.nb_documents
+    }
+
+    pub fn truncated_documents(&self) -> &BTreeMap<String, usize> {
+        &self.truncated_documents
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -97,9 +113,8 @@ pub fn merge_readers(sources: Vec<Reader<FileFuse>>, merge: MergeFn) -> Merger<F
     builder.build()
 }
 
-pub fn merge_into_lmdb_database(
-    wtxn: &mut heed::RwTxn,
-    database: heed::PolyDatabase,
+pub fn merge_into_storage<S: Storage>(
+    storage: &mut S,
     sources: Vec<Reader<FileFuse>>,
     merge: MergeFn,
     method: WriteMethod,
@@ -109,9 +124,8 @@ pub fn merge_into_lmdb_database(
     let before = Instant::now();
 
     let merger = merge_readers(sources, merge);
-    merger_iter_into_lmdb_database(
-        wtxn,
-        database,
+    merger_iter_into_storage(
+        storage,
         merger.into_merge_iter()?,
         merge,
         method,
@@ -121,9 +135,8 @@ pub fn merge_into_lmdb_database(
     Ok(())
 }
 
-pub fn write_into_lmdb_database(
-    wtxn: &mut heed::RwTxn,
-    database: heed::PolyDatabase,
+pub fn write_into_storage<S: Storage>(
+    storage: &mut S,
     mut reader: Reader<FileFuse>,
     merge: MergeFn,
     method: WriteMethod,
@@ -132,40 +145,16 @@ pub fn write_into_lmdb_database(
     debug!("Writing MTBL stores...");
     let before = Instant::now();
 
-    match method {
-        WriteMethod::Append => {
-            let mut out_iter = database.iter_mut::<_, ByteSlice, ByteSlice>(wtxn)?;
-            while let Some((k, v)) = reader.next()? {
-                out_iter.append(k, v).with_context(|| {
-                    format!("writing {:?} into LMDB", k.as_bstr())
-                })?;
-            }
-        },
-        WriteMethod::GetMergePut => {
-            while let Some((k, v)) = reader.next()? {
-                let mut iter = database.prefix_iter_mut::<_, ByteSlice, ByteSlice>(wtxn, k)?;
-                match iter.next().transpose()? {
-                    Some((key, old_val)) if key == k => {
-                        let vals = vec![Cow::Borrowed(old_val), Cow::Borrowed(v)];
-                        let val = merge(k, &vals)?;
-                        iter.put_current(k, &val)?;
-                    },
-                    _ => {
-                        drop(iter);
-                        database.put::<_, ByteSlice, ByteSlice>(wtxn, k, v)?;
-                    },
-                }
-            }
-        }
+    while let Some((k, v)) = reader.next()? {
+        write_entry(storage, k, v, merge, method)?;
     }
 
     debug!("MTBL stores merged in {:.02?}!", before.elapsed());
     Ok(())
 }
 
-pub fn sorter_into_lmdb_database(
-    wtxn: &mut heed::RwTxn,
-    database: heed::PolyDatabase,
+pub fn sorter_into_storage<S: Storage>(
+    storage: &mut S,
     sorter: Sorter<MergeFn>,
     merge: MergeFn,
     method: WriteMethod,
@@ -174,9 +163,8 @@ pub fn sorter_into_lmdb_database(
     debug!("Writing MTBL sorter...");
     let before = Instant::now();
 
-    merger_iter_into_lmdb_database(
-        wtxn,
-        database,
+    merger_iter_into_storage(
+        storage,
         sorter.into_iter()?,
         merge,
         method,
@@ -186,42 +174,43 @@ pub fn sorter_into_lmdb_database(
     Ok(())
 }
 
-fn merger_iter_into_lmdb_database<R: io::Read>(
-    wtxn: &mut heed::RwTxn,
-    database: heed::PolyDatabase,
+fn merger_iter_into_storage<S: Storage, R: io::Read>(
+    storage: &mut S,
     mut sorter: MergerIter<R, MergeFn>,
     merge: MergeFn,
     method: WriteMethod,
 ) -> anyhow::Result<()>
+{
+    while let Some((k, v)) = sorter.next()? {
+        write_entry(storage, k, v, merge, method)?;
+    }
+
+    Ok(())
+}
+
+/// Writes an entry into the storage, the keys must be given in order when appending.
+fn write_entry<S: Storage>(
+    storage: &mut S,
+    key: &[u8],
+    value: &[u8],
+    merge: MergeFn,
+    method: WriteMethod,
+) -> anyhow::Result<()>
 {
     match method {
         WriteMethod::Append => {
-            let mut out_iter = database.iter_mut::<_, ByteSlice, ByteSlice>(wtxn)?;
-            while let Some((k, v)) = sorter.next()? {
-                out_iter.append(k, v).with_context(|| {
-                    format!("writing {:?} into LMDB", k.as_bstr())
-                })?;
-            }
+            storage.append(key, value).with_context(|| {
+                format!("writing {:?} into the storage", key.as_bstr())
+            })
         },
         WriteMethod::GetMergePut => {
-            while let Some((k, v)) = sorter.next()? {
-                let mut iter = database.prefix_iter_mut::<_, ByteSlice, ByteSlice>(wtxn, k)?;
-                match iter.next().transpose()? {
-                    Some((key, old_val)) if key == k => {
-                        let vals = vec![Cow::Borrowed(old_val), Cow::Borrowed(v)];
-                        let val = merge(k, &vals).expect("merge failed");
-                        iter.put_current(k, &val)?;
-                    },
-                    _ => {
-                        drop(iter);
-                        database.put::<_, ByteSlice, ByteSlice>(wtxn, k, v)?;
-                    },
-                }
-            }
+            let merged = match storage.get(key)? {
+                Some(old_value) => Some(merge(key, &[old_value, Cow::Borrowed(value)])?),
+                None => None,
+            };
+            storage.put(key, merged.as_deref().unwrap_or(value))
         },
     }
-
-    Ok(())
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -236,6 +225,26 @@ pub enum IndexDocumentsMethod {
     UpdateDocuments,
 }
 
+/// Defines what happens to the words that appear after the maximum indexable
+/// position of an attribute, see `proximity::MAX_INDEX_IN_ATTRIBUTE`.
+///
+/// It is a setting of the index, see `Settings::set_max_position_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MaxPositionPolicy {
+    /// The words are ignored, they will not be searchable.
+    Drop,
+    /// The words are all indexed at the last position of the attribute,
+    /// they are searchable but the proximity between them is lost.
+    Clamp,
+}
+
+impl Default for MaxPositionPolicy {
+    fn default() -> MaxPositionPolicy {
+        MaxPositionPolicy::Drop
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[non_exhaustive]
 pub enum UpdateFormat {
@@ -258,6 +267,7 @@ pub struct IndexDocuments<'t, 'u, 'i, 'a> {
     pub(crate) chunk_compression_level: Option<u32>,
     pub(crate) chunk_fusing_shrink_size: Option<u64>,
     pub(crate) thread_pool: Option<&'a ThreadPool>,
+    pub(crate) deadline: Option<Instant>,
     facet_level_group_size: Option<NonZeroUsize>,
     facet_min_level_size: Option<NonZeroUsize>,
     words_prefix_threshold: Option<f64>,
@@ -285,6 +295,7 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
             chunk_compression_level: None,
             chunk_fusing_shrink_size: None,
             thread_pool: None,
+            deadline: None,
             facet_level_group_size: None,
             facet_min_level_size: None,
             words_prefix_threshold: None,
@@ -312,6 +323,12 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
         self.autogenerate_docids = false;
     }
 
+    /// Interrupts the indexing with a `DeadlineExceeded` error once the deadline
+    /// is reached, the write transaction must then be aborted.
+    pub fn deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
     pub fn execute<R, F>(self, reader: R, progress_callback: F) -> anyhow::Result<DocumentAdditionResult>
     where
         R: io::Read,
@@ -341,15 +358,17 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
             UpdateFormat::JsonStream => transform.output_from_json_stream(reader, &progress_callback)?,
         };
 
-        let nb_documents = output.documents_count;
-
         info!("Update transformed in {:.02?}", before_transform.elapsed());
+        check_deadline(self.deadline)?;
 
-        self.execute_raw(output, progress_callback)?;
-        Ok(DocumentAdditionResult { nb_documents })
+        self.execute_raw(output, progress_callback)
     }
 
-    pub fn execute_raw<F>(self, output: TransformOutput, progress_callback: F) -> anyhow::Result<()>
+    pub fn execute_raw<F>(
+        self,
+        output: TransformOutput,
+        progress_callback: F,
+    ) -> anyhow::Result<DocumentAdditionResult>
     where
         F: Fn(UpdateIndexingStep) + Sync
     {
@@ -377,6 +396,7 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
                 chunk_compression_level: self.chunk_compression_level,
                 chunk_fusing_shrink_size: self.chunk_fusing_shrink_size,
                 thread_pool: self.thread_pool,
+                max_duration: None,
                 update_id: self.update_id,
             };
             let mut deletion_builder = update_builder.delete_documents(self.wtxn, self.index)?;
@@ -404,12 +424,38 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
             FacetLevel0ValuesDocids,
         }
 
-        let faceted_fields = self.index.faceted_fields_ids(self.wtxn)?;
+        // The stored-only fields are never extracted, they are only part of the documents.
+        let stored_only_fields: HashSet<_> = self.index.stored_only_fields(self.wtxn)?
+            .iter()
+            .filter_map(|name| fields_ids_map.id(name))
+            .collect();
+
+        let mut faceted_fields = self.index.faceted_fields_ids(self.wtxn)?;
+        faceted_fields.retain(|id, _| !stored_only_fields.contains(id));
+        let mut nested_faceted_fields = nested_faceted_fields(&fields_ids_map, &self.index.faceted_fields(self.wtxn)?);
+        nested_faceted_fields.retain(|id, _| !stored_only_fields.contains(id));
+        let facet_string_normalizations: HashMap<_, _> = self.index.facet_string_normalizations(self.wtxn)?
+            .into_iter()
+            .filter_map(|(name, normalization)| Some((fields_ids_map.id(&name)?, normalization)))
+            .collect();
+        let auto_filterable_fields: HashSet<_> = self.index.auto_filterable_fields(self.wtxn)?
+            .iter()
+            .filter_map(|name| fields_ids_map.id(name))
+            .filter(|id| !stored_only_fields.contains(id))
+            .collect();
+        let attributes_stop_words: HashMap<_, HashSet<_>> = self.index.attributes_stop_words(self.wtxn)?
+            .into_iter()
+            .filter_map(|(name, words)| Some((fields_ids_map.id(&name)?, words.into_iter().collect())))
+            .collect();
         let searchable_fields: HashSet<_> = match self.index.searchable_fields_ids(self.wtxn)? {
-            Some(fields) => fields.iter().copied().collect(),
-            None => fields_ids_map.iter().map(|(id, _name)| id).collect(),
+            Some(fields) => fields.iter().copied().filter(|id| !stored_only_fields.contains(id)).collect(),
+            None => fields_ids_map.iter().map(|(id, _name)| id).filter(|id| !stored_only_fields.contains(id)).collect(),
         };
 
+        let primary_key_id = fields_ids_map.id(&primary_key);
+        let max_position_policy = self.index.max_position_policy(self.wtxn)?;
+        let proximity_database_enabled = self.index.proximity_database_enabled(self.wtxn)?;
+        let stop_words = self.index.stop_words(self.wtxn)?.map_data(Cow::into_owned)?;
         let linked_hash_map_size = self.linked_hash_map_size;
         let max_nb_chunks = self.max_nb_chunks;
         let max_memory = self.max_memory;
@@ -417,6 +463,7 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
         let chunk_compression_level = self.chunk_compression_level;
         let log_every_n = self.log_every_n;
         let chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+        let deadline = self.deadline;
 
         let backup_pool;
         let pool = match self.thread_pool {
@@ -436,10 +483,16 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
             let readers = rayon::iter::repeatn(documents, num_threads)
                 .enumerate()
                 .map(|(i, documents)| {
-                    let stop_words = fst::Set::default();
                     let store = Store::new(
                         searchable_fields.clone(),
                         faceted_fields.clone(),
+                        nested_faceted_fields.clone(),
+                        facet_string_normalizations.clone(),
+                        auto_filterable_fields.clone(),
+                        attributes_stop_words.clone(),
+                        primary_key_id,
+                        max_position_policy,
+                        proximity_database_enabled,
                         linked_hash_map_size,
                         max_nb_chunks,
                         max_memory_by_job,
@@ -454,6 +507,7 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
                         i,
                         num_threads,
                         log_every_n,
+                        deadline,
                         &progress_callback,
                     )
                 })
@@ -463,26 +517,35 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
             let mut word_docids_readers = Vec::with_capacity(readers.len());
             let mut docid_word_positions_readers = Vec::with_capacity(readers.len());
             let mut words_pairs_proximities_docids_readers = Vec::with_capacity(readers.len());
+            let mut word_position_docids_readers = Vec::with_capacity(readers.len());
+            let mut word_attribute_docids_readers = Vec::with_capacity(readers.len());
             let mut facet_field_value_docids_readers = Vec::with_capacity(readers.len());
             let mut field_id_docid_facet_values_readers = Vec::with_capacity(readers.len());
             let mut documents_readers = Vec::with_capacity(readers.len());
+            let mut truncated_documents = BTreeMap::new();
             readers.into_iter().for_each(|readers| {
                 let Readers {
                     main,
                     word_docids,
                     docid_word_positions,
                     words_pairs_proximities_docids,
+                    word_position_docids,
+                    word_attribute_docids,
                     facet_field_value_docids,
                     field_id_docid_facet_values,
-                    documents
+                    documents,
+                    truncated_documents: store_truncated_documents,
                 } = readers;
                 main_readers.push(main);
                 word_docids_readers.push(word_docids);
                 docid_word_positions_readers.push(docid_word_positions);
                 words_pairs_proximities_docids_readers.push(words_pairs_proximities_docids);
+                word_position_docids_readers.push(word_position_docids);
+                word_attribute_docids_readers.push(word_attribute_docids);
                 facet_field_value_docids_readers.push(facet_field_value_docids);
                 field_id_docid_facet_values_readers.push(field_id_docid_facet_values);
                 documents_readers.push(documents);
+                truncated_documents.extend(store_truncated_documents);
             });
 
             // This is the function that merge the readers
@@ -525,7 +588,10 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
                 docid_word_positions_readers,
                 documents_readers,
                 words_pairs_proximities_docids_readers,
+                word_position_docids_readers,
+                word_attribute_docids_readers,
                 field_id_docid_facet_values_readers,
+                truncated_documents,
             )) as anyhow::Result<_>
         })?;
 
@@ -534,7 +600,10 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
             docid_word_positions_readers,
             documents_readers,
             words_pairs_proximities_docids_readers,
+            word_position_docids_readers,
+            word_attribute_docids_readers,
             field_id_docid_facet_values_readers,
+            truncated_documents,
         ) = readers;
 
         let mut documents_ids = self.index.documents_ids(self.wtxn)?;
@@ -552,6 +621,7 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
 
         // We write the primary key field id into the main database
         self.index.put_primary_key(self.wtxn, &primary_key)?;
+        self.index.refresh_metadata(self.wtxn)?;
 
         // We write the external documents ids into the main database.
         self.index.put_external_documents_ids(self.wtxn, &external_documents_ids)?;
@@ -561,8 +631,10 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
         documents_ids.union_with(&replaced_documents_ids);
         self.index.put_documents_ids(self.wtxn, &documents_ids)?;
 
+        check_deadline(self.deadline)?;
+
         let mut database_count = 0;
-        let total_databases = 7;
+        let total_databases = 9;
 
         progress_callback(UpdateIndexingStep::MergeDataIntoFinalDatabase {
             databases_seen: 0,
@@ -570,9 +642,8 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
         });
 
         debug!("Writing the docid word positions into LMDB on disk...");
-        merge_into_lmdb_database(
-            self.wtxn,
-            *self.index.docid_word_positions.as_polymorph(),
+        merge_into_storage(
+            &mut self.index.storage_mut(self.wtxn, IndexDatabase::DocidWordPositions),
             docid_word_positions_readers,
             docid_word_positions_merge,
             write_method
@@ -585,9 +656,8 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
         });
 
         debug!("Writing the documents into LMDB on disk...");
-        merge_into_lmdb_database(
-            self.wtxn,
-            *self.index.documents.as_polymorph(),
+        merge_into_storage(
+            &mut self.index.storage_mut(self.wtxn, IndexDatabase::Documents),
             documents_readers,
             documents_merge,
             write_method
@@ -600,9 +670,8 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
         });
 
         debug!("Writing the field id docid facet values into LMDB on disk...");
-        merge_into_lmdb_database(
-            self.wtxn,
-            *self.index.field_id_docid_facet_values.as_polymorph(),
+        merge_into_storage(
+            &mut self.index.storage_mut(self.wtxn, IndexDatabase::FieldIdDocidFacetValues),
             field_id_docid_facet_values_readers,
             field_id_docid_facet_values_merge,
             write_method,
@@ -615,9 +684,8 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
         });
 
         debug!("Writing the words pairs proximities docids into LMDB on disk...");
-        merge_into_lmdb_database(
-            self.wtxn,
-            *self.index.word_pair_proximity_docids.as_polymorph(),
+        merge_into_storage(
+            &mut self.index.storage_mut(self.wtxn, IndexDatabase::WordPairProximityDocids),
             words_pairs_proximities_docids_readers,
             words_pairs_proximities_docids_merge,
             write_method,
@@ -629,25 +697,60 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
             total_databases,
         });
 
+        debug!("Writing the words positions docids into LMDB on disk...");
+        merge_into_storage(
+            &mut self.index.storage_mut(self.wtxn, IndexDatabase::WordPositionDocids),
+            word_position_docids_readers,
+            word_position_docids_merge,
+            write_method,
+        )?;
+
+        database_count += 1;
+        progress_callback(UpdateIndexingStep::MergeDataIntoFinalDatabase {
+            databases_seen: database_count,
+            total_databases,
+        });
+
+        debug!("Writing the words attributes docids into LMDB on disk...");
+        merge_into_storage(
+            &mut self.index.storage_mut(self.wtxn, IndexDatabase::WordAttributeDocids),
+            word_attribute_docids_readers,
+            word_attribute_docids_merge,
+            write_method,
+        )?;
+
+        database_count += 1;
+        progress_callback(UpdateIndexingStep::MergeDataIntoFinalDatabase {
+            databases_seen: database_count,
+            total_databases,
+        });
+
+        let mut words_fst_delta = None;
         for (db_type, result) in receiver {
             let content = result?;
             match db_type {
                 DatabaseType::Main => {
                     debug!("Writing the main elements into LMDB on disk...");
-                    write_into_lmdb_database(
-                        self.wtxn,
-                        self.index.main,
-                        content,
-                        main_merge,
-                        WriteMethod::GetMergePut,
-                    )?;
+                    // The main values can be chunked, we must go through the index
+                    // to read the previous values and write the merged ones.
+                    let mut content = content;
+                    while let Some((key, value)) = content.next()? {
+                        let key = str::from_utf8(key)?;
+                        // The words of the documents of this update, their shards are computed again.
+                        if key == WORDS_FST_KEY {
+                            words_fst_delta = Some(fst::Set::new(value.to_vec())?);
+                        }
+                        let merged = match self.index.main_bytes(self.wtxn, key)? {
+                            Some(old) => main_merge(key.as_bytes(), &[old, Cow::Borrowed(value)])?,
+                            None => value.to_vec(),
+                        };
+                        self.index.put_main_bytes(self.wtxn, key, &merged)?;
+                    }
                 },
                 DatabaseType::WordDocids => {
                     debug!("Writing the words docids into LMDB on disk...");
-                    let db = *self.index.word_docids.as_polymorph();
-                    write_into_lmdb_database(
-                        self.wtxn,
-                        db,
+                    write_into_storage(
+                        &mut self.index.storage_mut(self.wtxn, IndexDatabase::WordDocids),
                         content,
                         word_docids_merge,
                         write_method,
@@ -655,10 +758,8 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
                 },
                 DatabaseType::FacetLevel0ValuesDocids => {
                     debug!("Writing the facet values docids into LMDB on disk...");
-                    let db = *self.index.facet_field_id_value_docids.as_polymorph();
-                    write_into_lmdb_database(
-                        self.wtxn,
-                        db,
+                    write_into_storage(
+                        &mut self.index.storage_mut(self.wtxn, IndexDatabase::FacetFieldIdValueDocids),
                         content,
                         facet_field_value_docids_merge,
                         write_method,
@@ -673,6 +774,8 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
             });
         }
 
+        check_deadline(self.deadline)?;
+
         // Run the facets update operation.
         let mut builder = Facets::new(self.wtxn, self.index, self.update_id);
         builder.chunk_compression_type = self.chunk_compression_type;
@@ -686,6 +789,8 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
         }
         builder.execute()?;
 
+        check_deadline(self.deadline)?;
+
         // Run the words prefixes update operation.
         let mut builder = WordsPrefixes::new(self.wtxn, self.index, self.update_id);
         builder.chunk_compression_type = self.chunk_compression_type;
@@ -699,11 +804,21 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
         }
         builder.execute()?;
 
+        // Run the word docids shards update operation, only for the words of the documents.
+        let mut builder = WordDocidsShards::new(self.wtxn, self.index, self.update_id);
+        builder.words(words_fst_delta.unwrap_or_default());
+        builder.execute()?;
+
         debug_assert_eq!(database_count, total_databases);
 
         info!("Transform output indexed in {:.02?}", before_indexing.elapsed());
 
-        Ok(())
+        if !truncated_documents.is_empty() {
+            info!("{} documents contained words beyond the maximum position ({:?})",
+                truncated_documents.len(), max_position_policy);
+        }
+
+        Ok(DocumentAdditionResult { nb_documents: documents_count, truncated_documents })
     }
 }
 
@@ -711,6 +826,11 @@ impl<'t, 'u, 'i, 'a> IndexDocuments<'t, 'u, 'i, 'a> {
 mod tests {
     use super::*;
     use heed::EnvOpenOptions;
+    use maplit::hashmap;
+
+    use crate::proximity::ONE_ATTRIBUTE;
+    use crate::update::{DeadlineExceeded, Settings};
+    use crate::{Criterion, CriterionBuckets, FacetCondition, Relaxation, TyposReason};
 
     #[test]
     fn simple_document_replacement() {
@@ -1129,4 +1249,353 @@ mod tests {
 
         drop(rtxn);
     }
+
+    #[test]
+    fn max_position_policy() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // We send a document with a word after the maximum position, it is dropped by default.
+        let long_text = format!("{} hidden", "word ".repeat(ONE_ATTRIBUTE as usize));
+        let content = format!(r#"[{{ "id": 0, "text": "{}" }}, {{ "id": 1, "text": "short" }}]"#, long_text);
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Json);
+        let result = builder.execute(content.as_bytes(), |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        assert_eq!(result.truncated_documents().len(), 1);
+        assert_eq!(result.truncated_documents().get("0"), Some(&1));
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.max_position_policy(&rtxn).unwrap(), MaxPositionPolicy::Drop);
+        let result = index.search(&rtxn).query(r#""hidden""#).execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+        drop(rtxn);
+
+        // We clamp the words in the last position, the documents are indexed again.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_max_position_policy(MaxPositionPolicy::Clamp);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query(r#""hidden""#).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        drop(rtxn);
+
+        // The next additions follow the policy of the index.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 2);
+        builder.update_format(UpdateFormat::Json);
+        let result = builder.execute(content.as_bytes(), |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        assert_eq!(result.truncated_documents().get("0"), Some(&1));
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query(r#""hidden""#).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        drop(rtxn);
+    }
+
+    #[test]
+    fn search_snapshot() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n1,kevin\n2,kevina\n3,benoit\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // We retrieve the snapshot of the first page of results.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).limit(1).execute().unwrap();
+        let snapshot = result.snapshot;
+        assert_eq!(snapshot, index.update_sequence(&rtxn).unwrap());
+
+        // The index has not changed, the second page can be requested.
+        index.search(&rtxn).snapshot(snapshot).offset(1).execute().unwrap();
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n4,kevin\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // A document was added, the second page would not be consistent with the first one.
+        let rtxn = index.read_txn().unwrap();
+        assert!(index.update_sequence(&rtxn).unwrap() > snapshot);
+        assert!(index.search(&rtxn).snapshot(snapshot).offset(1).execute().is_err());
+    }
+
+    #[test]
+    fn search_by_bucket() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "age".into() => "integer".into() });
+        builder.set_criteria(vec!["asc(age)".into()]);
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = &b"id,age\n1,32\n2,25\n3,32\n4,40\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // The documents are given bucket by bucket, the limit is applied.
+        let rtxn = index.read_txn().unwrap();
+        let mut buckets = Vec::new();
+        let result = index.search(&rtxn).limit(3).execute_by_bucket(|docids| {
+            buckets.push(docids.to_vec());
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(buckets, vec![vec![1], vec![0, 2]]);
+        assert_eq!(result.documents_ids, vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn search_bucket_counts() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{
+            "age".into() => "integer".into(),
+            "rank".into() => "integer".into(),
+        });
+        builder.set_criteria(vec!["asc(age)".into(), "desc(rank)".into()]);
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = &b"id,age,rank\n1,32,1\n2,25,1\n3,32,2\n4,40,1\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // The buckets of the second criterion refine the ones of the first criterion.
+        let rtxn = index.read_txn().unwrap();
+        let counts = index.search(&rtxn).bucket_counts().unwrap();
+        let expected = vec![
+            CriterionBuckets { criterion: Criterion::Asc("age".into()), buckets: vec![1, 2, 1] },
+            CriterionBuckets { criterion: Criterion::Desc("rank".into()), buckets: vec![1, 1, 1, 1] },
+        ];
+        assert_eq!(counts, expected);
+    }
+
+    #[test]
+    fn search_relax_on_empty() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "age".into() => "integer".into() });
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = &b"id,name,age\n1,kevin,32\n2,kevina,25\n3,benoit,40\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+
+        // The search is not relaxed by default.
+        let result = index.search(&rtxn).query("kevn").execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+        assert!(result.relaxations.is_empty());
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "age > 100").unwrap();
+        let result = index.search(&rtxn)
+            .query("kevin")
+            .soft_facet_condition(condition)
+            .relax_on_empty(true)
+            .execute()
+            .unwrap();
+        assert_eq!(result.relaxations, vec![Relaxation::SoftFilterDropped]);
+        assert_eq!(result.documents_ids, vec![0, 1]);
+
+        // A short word is given one typo.
+        let result = index.search(&rtxn).query("kevn").relax_on_empty(true).execute().unwrap();
+        assert_eq!(result.relaxations, vec![Relaxation::TyposIncreased]);
+        assert!(result.documents_ids.contains(&0));
+
+        let result = index.search(&rtxn)
+            .query("benoit zzzz")
+            .optional_words(false)
+            .relax_on_empty(true)
+            .execute()
+            .unwrap();
+        let expected = vec![Relaxation::TyposIncreased, Relaxation::WordDropped("zzzz".into())];
+        assert_eq!(result.relaxations, expected);
+        assert_eq!(result.documents_ids, vec![2]);
+    }
+
+    #[test]
+    fn index_documents_deadline() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // The deadline is already reached, the indexing is interrupted.
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n1,kevin\n2,kevina\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.deadline(Instant::now());
+        let error = builder.execute(content, |_, _| ()).unwrap_err();
+        assert_eq!(error.downcast_ref::<DeadlineExceeded>(), Some(&DeadlineExceeded));
+        drop(wtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut update_builder = UpdateBuilder::new(0);
+        update_builder.max_duration(std::time::Duration::from_secs(60));
+        let mut builder = update_builder.index_documents(&mut wtxn, &index);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.number_of_documents(&rtxn).unwrap(), 2);
+    }
+
+    #[test]
+    fn search_typo_details() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_criteria(vec!["words".to_string(), "typo(max=1)".to_string()]);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let details = index.search(&rtxn).query("kevin \"hello world\" benoit").typo_details().unwrap();
+        assert!(details.authorize_typos);
+        assert_eq!(details.typo_criterion_max, Some(1));
+        let words: Vec<_> = details.words.iter().map(|w| (w.word.as_str(), w.max_typos, w.reason)).collect();
+        assert_eq!(words, vec![
+            ("kevin", 1, TyposReason::WordLength),
+            ("hello", 0, TyposReason::Phrase),
+            ("world", 0, TyposReason::Phrase),
+            ("benoit", 1, TyposReason::WordLength),
+        ]);
+
+        let mut details = index.search(&rtxn).query("kev").authorize_typos(false).typo_details().unwrap();
+        assert_eq!(details.words[0].max_typos, 0);
+        assert_eq!(details.words[0].reason, TyposReason::TyposDisabled);
+
+        // The relaxed searches give one more typo to the words.
+        details.increase_typos();
+        assert_eq!(details.words[0].max_typos, 1);
+        assert_eq!(details.words[0].reason, TyposReason::Increased);
+    }
+
+    #[test]
+    fn search_phrase_positions() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n1,big red car\n2,big red dog red car\n3,car red big\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // Every pair of the phrase is adjacent in the second document but not the whole phrase.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("\"big red car\"").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_proximity_database_enabled(false);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // Without the pairs proximities the words of the third document co-occur.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("\"red car\"").execute().unwrap();
+        let mut documents_ids = result.documents_ids;
+        documents_ids.sort_unstable();
+        assert_eq!(documents_ids, vec![0, 1]);
+    }
+
+    #[test]
+    fn words_documents_count() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n1,kevin\n2,kevin\n3,benoit\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.word_documents_count(&rtxn, "kevin").unwrap(), Some(2));
+        assert_eq!(index.word_documents_count(&rtxn, "tamo").unwrap(), None);
+
+        let counts: Vec<_> = index.words_documents_count(&rtxn).unwrap().map(Result::unwrap).collect();
+        assert_eq!(counts, vec![("1", 1), ("2", 1), ("3", 1), ("benoit", 1), ("kevin", 2)]);
+    }
+
+    #[test]
+    fn export_vocabularies() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name,city\n1,kevin,paris\n2,benoit,kevin\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let mut bytes = Vec::new();
+        index.export_words_fst(&rtxn, &mut bytes).unwrap();
+        let words = fst::Set::new(bytes).unwrap().stream().into_strs().unwrap();
+        assert_eq!(words, vec!["1", "2", "benoit", "kevin", "paris"]);
+
+        let fields_ids_map = index.fields_ids_map(&rtxn).unwrap();
+        let city = fields_ids_map.id("city").unwrap();
+        let words = index.field_words_fst(&rtxn, city).unwrap().stream().into_strs().unwrap();
+        assert_eq!(words, vec!["kevin", "paris"]);
+    }
 }
@@ -1,6 +1,6 @@
 use std::borrow::Cow;
-use std::collections::{BTreeMap, HashMap, HashSet};
-use std::convert::{TryFrom, TryInto};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
 use std::fs::File;
 use std::iter::FromIterator;
 use std::time::Instant;
@@ -19,23 +19,28 @@ use roaring::RoaringBitmap;
 use serde_json::Value;
 use tempfile::tempfile;
 
-use crate::facet::FacetType;
+use crate::facet::{FacetStringNormalization, FacetType};
 use crate::heed_codec::facet::{FacetValueStringCodec, FacetLevelValueF64Codec, FacetLevelValueI64Codec};
 use crate::heed_codec::facet::{FieldDocIdFacetStringCodec, FieldDocIdFacetF64Codec, FieldDocIdFacetI64Codec};
 use crate::heed_codec::{BoRoaringBitmapCodec, CboRoaringBitmapCodec};
+use crate::index::{facet_string_display_values_key, insert_facet_string_display_value};
+use crate::proximity::{encode_position, extract_position, pair_proximity, position_attribute, position_bucket};
+use crate::proximity::{MAX_INDEX_IN_ATTRIBUTE, MAX_PAIR_PROXIMITY};
 use crate::update::UpdateIndexingStep;
-use crate::{json_to_string, SmallVec8, SmallVec32, SmallString32, Position, DocumentId, FieldId};
+use crate::update::deadline::check_deadline;
+use crate::{json_to_string, FieldsIdsMap, SmallVec8, SmallVec32, SmallString32, Position, Proximity, DocumentId, FieldId};
 
-use super::{MergeFn, create_writer, create_sorter, writer_into_reader};
+use super::{MaxPositionPolicy, MergeFn, create_writer, create_sorter, writer_into_reader};
 use super::merge_function::{
-    main_merge, word_docids_merge, words_pairs_proximities_docids_merge,
+    main_merge, word_docids_merge, words_pairs_proximities_docids_merge, word_position_docids_merge,
+    word_attribute_docids_merge,
     facet_field_value_docids_merge, field_id_docid_facet_values_merge,
 };
 
 const LMDB_MAX_KEY_LENGTH: usize = 511;
 const ONE_KILOBYTE: usize = 1024 * 1024;
 
-const MAX_POSITION: usize = 1000;
+const MAX_POSITION: usize = MAX_INDEX_IN_ATTRIBUTE as usize + 1;
 const WORDS_FST_KEY: &[u8] = crate::index::WORDS_FST_KEY.as_bytes();
 
 pub struct Readers {
@@ -43,22 +48,76 @@ pub struct Readers {
     pub word_docids: Reader<FileFuse>,
     pub docid_word_positions: Reader<FileFuse>,
     pub words_pairs_proximities_docids: Reader<FileFuse>,
+    pub word_position_docids: Reader<FileFuse>,
+    pub word_attribute_docids: Reader<FileFuse>,
     pub facet_field_value_docids: Reader<FileFuse>,
     pub field_id_docid_facet_values: Reader<FileFuse>,
     pub documents: Reader<FileFuse>,
+    /// The external ids of the documents that contained words beyond the maximum
+    /// indexable position, associated with the number of words that were affected.
+    pub truncated_documents: BTreeMap<String, usize>,
+}
+
+/// A faceted field that is nested inside of an object field of the documents,
+/// it is declared in the settings by using a dotted path (e.g. `person.address.city`).
+#[derive(Debug, Clone)]
+pub struct NestedFacet {
+    /// The field id of the dotted path itself.
+    pub field_id: FieldId,
+    /// The keys to follow, from the object field, to reach the facet values.
+    pub path: Vec<String>,
+    pub facet_type: FacetType,
+}
+
+/// Returns the nested faceted fields grouped by the id of the
+/// top-level field of the documents they must be extracted from.
+pub fn nested_faceted_fields(
+    fields_ids_map: &FieldsIdsMap,
+    faceted_fields: &HashMap<String, FacetType>,
+) -> HashMap<FieldId, Vec<NestedFacet>>
+{
+    let mut nested_faceted_fields = HashMap::new();
+    for (name, facet_type) in faceted_fields {
+        let mut path = name.split('.');
+        let root = match path.next() {
+            Some(root) if root.len() != name.len() => root,
+            _otherwise => continue,
+        };
+
+        if let (Some(root_id), Some(field_id)) = (fields_ids_map.id(root), fields_ids_map.id(name)) {
+            let path = path.map(ToOwned::to_owned).collect();
+            let nested = NestedFacet { field_id, path, facet_type: *facet_type };
+            nested_faceted_fields.entry(root_id).or_insert_with(Vec::new).push(nested);
+        }
+    }
+    nested_faceted_fields
 }
 
 pub struct Store<'s, A> {
     // Indexing parameters
     searchable_fields: HashSet<FieldId>,
     faceted_fields: HashMap<FieldId, FacetType>,
+    nested_faceted_fields: HashMap<FieldId, Vec<NestedFacet>>,
+    facet_string_normalizations: HashMap<FieldId, FacetStringNormalization>,
+    auto_filterable_fields: HashSet<FieldId>,
+    attributes_stop_words: HashMap<FieldId, HashSet<String>>,
+    primary_key_id: Option<FieldId>,
+    max_position_policy: MaxPositionPolicy,
+    proximity_database_enabled: bool,
+    // Statistics
+    truncated_documents: BTreeMap<String, usize>,
     // Caches
     word_docids: LinkedHashMap<SmallVec32<u8>, RoaringBitmap>,
     word_docids_limit: usize,
-    words_pairs_proximities_docids: LinkedHashMap<(SmallVec32<u8>, SmallVec32<u8>, u8), RoaringBitmap>,
+    words_pairs_proximities_docids: LinkedHashMap<(SmallVec32<u8>, SmallVec32<u8>, Proximity), RoaringBitmap>,
     words_pairs_proximities_docids_limit: usize,
+    word_position_docids: LinkedHashMap<(SmallVec32<u8>, u32), RoaringBitmap>,
+    word_position_docids_limit: usize,
+    word_attribute_docids: LinkedHashMap<(SmallVec32<u8>, u32), RoaringBitmap>,
+    word_attribute_docids_limit: usize,
     facet_field_value_docids: LinkedHashMap<(u8, FacetValue), RoaringBitmap>,
     facet_field_value_docids_limit: usize,
+    facet_string_display_values: HashMap<FieldId, BTreeMap<String, String>>,
     // MTBL parameters
     chunk_compression_type: CompressionType,
     chunk_compression_level: Option<u32>,
@@ -67,6 +126,8 @@ pub struct Store<'s, A> {
     main_sorter: Sorter<MergeFn>,
     word_docids_sorter: Sorter<MergeFn>,
     words_pairs_proximities_docids_sorter: Sorter<MergeFn>,
+    word_position_docids_sorter: Sorter<MergeFn>,
+    word_attribute_docids_sorter: Sorter<MergeFn>,
     facet_field_value_docids_sorter: Sorter<MergeFn>,
     field_id_docid_facet_values_sorter: Sorter<MergeFn>,
     // MTBL writers
@@ -80,6 +141,13 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
     pub fn new(
         searchable_fields: HashSet<FieldId>,
         faceted_fields: HashMap<FieldId, FacetType>,
+        nested_faceted_fields: HashMap<FieldId, Vec<NestedFacet>>,
+        facet_string_normalizations: HashMap<FieldId, FacetStringNormalization>,
+        auto_filterable_fields: HashSet<FieldId>,
+        attributes_stop_words: HashMap<FieldId, HashSet<String>>,
+        primary_key_id: Option<FieldId>,
+        max_position_policy: MaxPositionPolicy,
+        proximity_database_enabled: bool,
         linked_hash_map_size: Option<usize>,
         max_nb_chunks: Option<usize>,
         max_memory: Option<usize>,
@@ -90,7 +158,7 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
     ) -> anyhow::Result<Self>
     {
         // We divide the max memory by the number of sorter the Store have.
-        let max_memory = max_memory.map(|mm| cmp::max(ONE_KILOBYTE, mm / 4));
+        let max_memory = max_memory.map(|mm| cmp::max(ONE_KILOBYTE, mm / 6));
         let linked_hash_map_size = linked_hash_map_size.unwrap_or(500);
 
         let main_sorter = create_sorter(
@@ -117,6 +185,22 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
             max_nb_chunks,
             max_memory,
         );
+        let word_position_docids_sorter = create_sorter(
+            word_position_docids_merge,
+            chunk_compression_type,
+            chunk_compression_level,
+            chunk_fusing_shrink_size,
+            max_nb_chunks,
+            max_memory,
+        );
+        let word_attribute_docids_sorter = create_sorter(
+            word_attribute_docids_merge,
+            chunk_compression_type,
+            chunk_compression_level,
+            chunk_fusing_shrink_size,
+            max_nb_chunks,
+            max_memory,
+        );
         let facet_field_value_docids_sorter = create_sorter(
             facet_field_value_docids_merge,
             chunk_compression_type,
@@ -147,13 +231,27 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
             // Indexing parameters.
             searchable_fields,
             faceted_fields,
+            nested_faceted_fields,
+            facet_string_normalizations,
+            auto_filterable_fields,
+            attributes_stop_words,
+            primary_key_id,
+            max_position_policy,
+            proximity_database_enabled,
+            // Statistics
+            truncated_documents: BTreeMap::new(),
             // Caches
             word_docids: LinkedHashMap::with_capacity(linked_hash_map_size),
             word_docids_limit: linked_hash_map_size,
             words_pairs_proximities_docids: LinkedHashMap::with_capacity(linked_hash_map_size),
             words_pairs_proximities_docids_limit: linked_hash_map_size,
+            word_position_docids: LinkedHashMap::with_capacity(linked_hash_map_size),
+            word_position_docids_limit: linked_hash_map_size,
+            word_attribute_docids: LinkedHashMap::with_capacity(linked_hash_map_size),
+            word_attribute_docids_limit: linked_hash_map_size,
             facet_field_value_docids: LinkedHashMap::with_capacity(linked_hash_map_size),
             facet_field_value_docids_limit: linked_hash_map_size,
+            facet_string_display_values: HashMap::new(),
             // MTBL parameters
             chunk_compression_type,
             chunk_compression_level,
@@ -162,6 +260,8 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
             main_sorter,
             word_docids_sorter,
             words_pairs_proximities_docids_sorter,
+            word_position_docids_sorter,
+            word_attribute_docids_sorter,
             facet_field_value_docids_sorter,
             field_id_docid_facet_values_sorter,
             // MTBL writers
@@ -227,7 +327,7 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
     // Save the documents ids under the words pairs proximities that it contains.
     fn insert_words_pairs_proximities_docids<'a>(
         &mut self,
-        words_pairs_proximities: impl IntoIterator<Item=((&'a str, &'a str), u8)>,
+        words_pairs_proximities: impl IntoIterator<Item=((&'a str, &'a str), Proximity)>,
         id: DocumentId,
     ) -> anyhow::Result<()>
     {
@@ -261,6 +361,49 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
         Ok(())
     }
 
+    // Save the documents ids under the words and the bucket of their first position in an attribute.
+    fn insert_word_position_docid(&mut self, word: &str, bucket: u32, id: DocumentId) -> anyhow::Result<()> {
+        let key = (SmallVec32::from(word.as_bytes()), bucket);
+        // if get_refresh finds the element it is assured to be at the end of the linked hash map.
+        match self.word_position_docids.get_refresh(&key) {
+            Some(old) => { old.insert(id); },
+            None => {
+                // A newly inserted element is append at the end of the linked hash map.
+                self.word_position_docids.insert(key, RoaringBitmap::from_iter(Some(id)));
+                // If the word position docids just reached it's capacity we must make sure
+                // to remove one element, this way next time we insert we doesn't grow the capacity.
+                if self.word_position_docids.len() == self.word_position_docids_limit {
+                    // Removing the front element is equivalent to removing the LRU element.
+                    let lru = self.word_position_docids.pop_front();
+                    Self::write_word_position_docids(&mut self.word_position_docids_sorter, lru)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Save the documents ids under the words and the field ids of the attributes they appear in.
+    fn insert_word_attribute_docid(&mut self, word: &str, attribute: u32, id: DocumentId) -> anyhow::Result<()> {
+        let key = (SmallVec32::from(word.as_bytes()), attribute);
+        // if get_refresh finds the element it is assured to be at the end of the linked hash map.
+        match self.word_attribute_docids.get_refresh(&key) {
+            Some(old) => { old.insert(id); },
+            None => {
+                // A newly inserted element is append at the end of the linked hash map.
+                self.word_attribute_docids.insert(key, RoaringBitmap::from_iter(Some(id)));
+                // If the word attribute docids just reached it's capacity we must make sure
+                // to remove one element, this way next time we insert we doesn't grow the capacity.
+                if self.word_attribute_docids.len() == self.word_attribute_docids_limit {
+                    // Removing the front element is equivalent to removing the LRU element.
+                    let lru = self.word_attribute_docids.pop_front();
+                    // The keys have the same layout as the ones of the words positions.
+                    Self::write_word_position_docids(&mut self.word_attribute_docids_sorter, lru)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn write_document(
         &mut self,
         document_id: DocumentId,
@@ -270,14 +413,33 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
     ) -> anyhow::Result<()>
     {
         // We compute the list of words pairs proximities (self-join) and write it directly to disk.
-        let words_pair_proximities = compute_words_pair_proximities(&words_positions);
-        self.insert_words_pairs_proximities_docids(words_pair_proximities, document_id)?;
+        if self.proximity_database_enabled {
+            let words_pair_proximities = compute_words_pair_proximities(&words_positions);
+            self.insert_words_pairs_proximities_docids(words_pair_proximities, document_id)?;
+        }
 
         // We store document_id associated with all the words the record contains.
         for (word, _) in words_positions.iter() {
             self.insert_word_docid(word, document_id)?;
         }
 
+        // We store document_id associated with all the words and the bucket of the first
+        // position they appear at in an attribute, whatever the attribute is.
+        for (word, positions) in words_positions.iter() {
+            let index = positions.iter().map(|p| extract_position(*p).1).min();
+            if let Some(index) = index {
+                self.insert_word_position_docid(word, position_bucket(index), document_id)?;
+            }
+        }
+
+        // We store document_id associated with all the words and the attributes they appear in.
+        for (word, positions) in words_positions.iter() {
+            let attributes: BTreeSet<_> = positions.iter().map(|p| position_attribute(*p)).collect();
+            for attribute in attributes {
+                self.insert_word_attribute_docid(word, attribute, document_id)?;
+            }
+        }
+
         self.documents_writer.insert(document_id.to_be_bytes(), record)?;
         Self::write_docid_word_positions(&mut self.docid_word_positions_writer, document_id, words_positions)?;
 
@@ -295,7 +457,7 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
 
     fn write_words_pairs_proximities(
         sorter: &mut Sorter<MergeFn>,
-        iter: impl IntoIterator<Item=((SmallVec32<u8>, SmallVec32<u8>, u8), RoaringBitmap)>,
+        iter: impl IntoIterator<Item=((SmallVec32<u8>, SmallVec32<u8>, Proximity), RoaringBitmap)>,
     ) -> anyhow::Result<()>
     {
         let mut key = Vec::new();
@@ -321,6 +483,32 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
         Ok(())
     }
 
+    fn write_word_position_docids(
+        sorter: &mut Sorter<MergeFn>,
+        iter: impl IntoIterator<Item=((SmallVec32<u8>, u32), RoaringBitmap)>,
+    ) -> anyhow::Result<()>
+    {
+        let mut key = Vec::new();
+        let mut buffer = Vec::new();
+
+        for ((word, bucket), docids) in iter {
+            key.clear();
+            key.extend_from_slice(word.as_bytes());
+            key.push(0);
+            key.extend_from_slice(&bucket.to_be_bytes());
+            // We serialize the document ids into a buffer
+            buffer.clear();
+            buffer.reserve(CboRoaringBitmapCodec::serialized_size(&docids));
+            CboRoaringBitmapCodec::serialize_into(&docids, &mut buffer)?;
+            // that we write under the generated key into MTBL
+            if lmdb_key_valid_size(&key) {
+                sorter.insert(&key, &buffer)?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn write_docid_word_positions(
         writer: &mut Writer<File>,
         id: DocumentId,
@@ -428,6 +616,7 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
         thread_index: usize,
         num_threads: usize,
         log_every_n: Option<usize>,
+        deadline: Option<Instant>,
         mut progress_callback: F,
     ) -> anyhow::Result<Readers>
     where F: FnMut(UpdateIndexingStep),
@@ -445,6 +634,8 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
 
             // We skip documents that must not be indexed by this thread.
             if count % num_threads == thread_index {
+                check_deadline(deadline)?;
+
                 // This is a log routine that we do every `log_every_n` documents.
                 if thread_index == 0 && log_every_n.map_or(false, |len| count % len == 0) {
                     info!("We have seen {} documents so far ({:.02?}).", format_count(count), before.elapsed());
@@ -455,17 +646,58 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
                     before = Instant::now();
                 }
 
+                let mut truncated_words = 0;
                 for (attr, content) in document.iter() {
-                    if self.faceted_fields.contains_key(&attr) || self.searchable_fields.contains(&attr) {
+                    if self.faceted_fields.contains_key(&attr)
+                        || self.nested_faceted_fields.contains_key(&attr)
+                        || self.searchable_fields.contains(&attr)
+                    {
                         let value = serde_json::from_slice(content)?;
 
-                        if let Some(ftype) = self.faceted_fields.get(&attr) {
-                            let mut values = parse_facet_value(*ftype, &value).with_context(|| {
+                        // The numbers found in the text of the auto-filterable fields are
+                        // their facet values, the other words are only searchable.
+                        if self.auto_filterable_fields.contains(&attr) {
+                            if let Some(content) = json_to_string(&value) {
+                                let analyzed = self.analyzer.analyze(&content);
+                                let numbers = analyzed.tokens()
+                                    .filter(|token| token.is_word())
+                                    .filter_map(|token| token.text().parse().ok())
+                                    .map(FacetValue::Integer);
+                                facet_values.entry(attr).or_insert_with(SmallVec8::new).extend(numbers);
+                            }
+                        } else if let Some(ftype) = self.faceted_fields.get(&attr) {
+                            let normalization = self.facet_string_normalizations.get(&attr).copied();
+                            // The original values are only kept for the fields that define a normalization.
+                            let display_values = if normalization.is_some() {
+                                Some(self.facet_string_display_values.entry(attr).or_default())
+                            } else {
+                                None
+                            };
+                            let normalization = normalization.unwrap_or_default();
+                            let mut values = parse_facet_value(*ftype, normalization, &value, display_values).with_context(|| {
                                 format!("extracting facets from the value {}", value)
                             })?;
                             facet_values.entry(attr).or_insert_with(SmallVec8::new).extend(values.drain(..));
                         }
 
+                        for nested in self.nested_faceted_fields.get(&attr).into_iter().flatten() {
+                            let mut nested_values = Vec::new();
+                            extract_nested_values(&value, &nested.path, &mut nested_values);
+                            for nested_value in nested_values {
+                                let normalization = self.facet_string_normalizations.get(&nested.field_id).copied();
+                                let display_values = if normalization.is_some() {
+                                    Some(self.facet_string_display_values.entry(nested.field_id).or_default())
+                                } else {
+                                    None
+                                };
+                                let normalization = normalization.unwrap_or_default();
+                                let mut values = parse_facet_value(nested.facet_type, normalization, nested_value, display_values).with_context(|| {
+                                    format!("extracting facets from the nested value {}", nested_value)
+                                })?;
+                                facet_values.entry(nested.field_id).or_insert_with(SmallVec8::new).extend(values.drain(..));
+                            }
+                        }
+
                         if self.searchable_fields.contains(&attr) {
                             let content = match json_to_string(&value) {
                                 Some(content) => content,
@@ -474,15 +706,41 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
 
                             let analyzed = self.analyzer.analyze(&content);
                             let tokens = process_tokens(analyzed.tokens());
-
-                            for (pos, token) in tokens.take_while(|(pos, _)| *pos < MAX_POSITION) {
-                                let position = (attr as usize * MAX_POSITION + pos) as u32;
+                            let stop_words = self.attributes_stop_words.get(&attr);
+
+                            for (pos, token) in tokens {
+                                // The attribute stop words keep their position, like the other stop words.
+                                if stop_words.map_or(false, |words| words.contains(token.text())) {
+                                    continue;
+                                }
+
+                                let pos = if pos < MAX_POSITION {
+                                    pos
+                                } else {
+                                    truncated_words += 1;
+                                    match self.max_position_policy {
+                                        MaxPositionPolicy::Drop => continue,
+                                        MaxPositionPolicy::Clamp => MAX_POSITION - 1,
+                                    }
+                                };
+                                let position = encode_position(attr as u32, pos as u32);
                                 words_positions.entry(token.text().to_string()).or_insert_with(SmallVec32::new).push(position);
                             }
                         }
                     }
                 }
 
+                if truncated_words != 0 {
+                    let external_id = self.primary_key_id
+                        .and_then(|id| document.get(id))
+                        .and_then(|bytes| serde_json::from_slice(bytes).ok())
+                        .and_then(|value: Value| json_to_string(&value))
+                        .unwrap_or_else(|| document_id.to_string());
+                    debug!("{:?}: document {} has {} words beyond the maximum position",
+                        thread_index, external_id, truncated_words);
+                    self.truncated_documents.insert(external_id, truncated_words);
+                }
+
                 // We write the document in the documents store.
                 self.write_document(document_id, &mut words_positions, &mut facet_values, value)?;
             }
@@ -511,6 +769,14 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
             &mut self.words_pairs_proximities_docids_sorter,
             self.words_pairs_proximities_docids,
         )?;
+        Self::write_word_position_docids(
+            &mut self.word_position_docids_sorter,
+            self.word_position_docids,
+        )?;
+        Self::write_word_position_docids(
+            &mut self.word_attribute_docids_sorter,
+            self.word_attribute_docids,
+        )?;
         Self::write_facet_field_value_docids(
             &mut self.facet_field_value_docids_sorter,
             self.facet_field_value_docids,
@@ -530,12 +796,23 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
         let fst = builder.into_set();
         self.main_sorter.insert(WORDS_FST_KEY, fst.as_fst().as_bytes())?;
 
+        for (field_id, display_values) in &self.facet_string_display_values {
+            let key = facet_string_display_values_key(*field_id);
+            self.main_sorter.insert(key, serde_json::to_vec(display_values)?)?;
+        }
+
         let mut main_wtr = tempfile().and_then(|f| create_writer(comp_type, comp_level, f))?;
         self.main_sorter.write_into(&mut main_wtr)?;
 
         let mut words_pairs_proximities_docids_wtr = tempfile().and_then(|f| create_writer(comp_type, comp_level, f))?;
         self.words_pairs_proximities_docids_sorter.write_into(&mut words_pairs_proximities_docids_wtr)?;
 
+        let mut word_position_docids_wtr = tempfile().and_then(|f| create_writer(comp_type, comp_level, f))?;
+        self.word_position_docids_sorter.write_into(&mut word_position_docids_wtr)?;
+
+        let mut word_attribute_docids_wtr = tempfile().and_then(|f| create_writer(comp_type, comp_level, f))?;
+        self.word_attribute_docids_sorter.write_into(&mut word_attribute_docids_wtr)?;
+
         let mut facet_field_value_docids_wtr = tempfile().and_then(|f| create_writer(comp_type, comp_level, f))?;
         self.facet_field_value_docids_sorter.write_into(&mut facet_field_value_docids_wtr)?;
 
@@ -545,6 +822,8 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
         let main = writer_into_reader(main_wtr, shrink_size)?;
         let word_docids = writer_into_reader(word_docids_wtr, shrink_size)?;
         let words_pairs_proximities_docids = writer_into_reader(words_pairs_proximities_docids_wtr, shrink_size)?;
+        let word_position_docids = writer_into_reader(word_position_docids_wtr, shrink_size)?;
+        let word_attribute_docids = writer_into_reader(word_attribute_docids_wtr, shrink_size)?;
         let facet_field_value_docids = writer_into_reader(facet_field_value_docids_wtr, shrink_size)?;
         let field_id_docid_facet_values = writer_into_reader(field_id_docid_facet_values_wtr, shrink_size)?;
         let docid_word_positions = writer_into_reader(self.docid_word_positions_writer, shrink_size)?;
@@ -555,20 +834,23 @@ impl<'s, A: AsRef<[u8]>> Store<'s, A> {
             word_docids,
             docid_word_positions,
             words_pairs_proximities_docids,
+            word_position_docids,
+            word_attribute_docids,
             facet_field_value_docids,
             field_id_docid_facet_values,
             documents,
+            truncated_documents: self.truncated_documents,
         })
     }
 }
 
-/// Outputs a list of all pairs of words with the shortest proximity between 1 and 7 inclusive.
+/// Outputs a list of all pairs of words with the shortest proximity between 1 and `MAX_PAIR_PROXIMITY` inclusive.
 ///
 /// This list is used by the engine to calculate the documents containing words that are
 /// close to each other.
 fn compute_words_pair_proximities(
     word_positions: &HashMap<String, SmallVec32<Position>>,
-) -> HashMap<(&str, &str), u8>
+) -> HashMap<(&str, &str), Proximity>
 {
     use itertools::Itertools;
 
@@ -576,11 +858,10 @@ fn compute_words_pair_proximities(
     for ((w1, ps1), (w2, ps2)) in word_positions.iter().cartesian_product(word_positions) {
         let mut min_prox = None;
         for (ps1, ps2) in ps1.iter().cartesian_product(ps2) {
-            let prox = crate::proximity::positions_proximity(*ps1, *ps2);
-            let prox = u8::try_from(prox).unwrap();
+            let prox = pair_proximity(*ps1, *ps2);
             // We don't care about a word that appear at the
             // same position or too far from the other.
-            if prox >= 1 && prox <= 7 && min_prox.map_or(true, |mp| prox < mp) {
+            if prox >= 1 && prox <= MAX_PAIR_PROXIMITY && min_prox.map_or(true, |mp| prox < mp) {
                 min_prox = Some(prox)
             }
         }
@@ -593,6 +874,23 @@ fn compute_words_pair_proximities(
     words_pair_proximities
 }
 
+/// Follows the keys of the path in the given value and collects the values found at
+/// the end of it, arrays are traversed, every one of their elements follows the path.
+fn extract_nested_values<'a>(value: &'a Value, path: &[String], output: &mut Vec<&'a Value>) {
+    match (value, path.split_first()) {
+        (Value::Array(values), _) => {
+            values.iter().for_each(|value| extract_nested_values(value, path, output));
+        },
+        (Value::Object(object), Some((key, path))) => {
+            if let Some(value) = object.get(key) {
+                extract_nested_values(value, path, output);
+            }
+        },
+        (value, None) => output.push(value),
+        (_, Some(_)) => (),
+    }
+}
+
 fn format_count(n: usize) -> String {
     human_format::Formatter::new().with_decimals(1).with_separator("").format(n as f64)
 }
@@ -611,7 +909,7 @@ enum FacetValue {
 /// take an iterator on tokens and compute their relative position depending on separator kinds
 /// if it's an `Hard` separator we add an additional relative proximity of 8 between words,
 /// else we keep the standart proximity of 1 between words.
-fn process_tokens<'a>(tokens: impl Iterator<Item = Token<'a>>) -> impl Iterator<Item = (usize, Token<'a>)> {
+pub(crate) fn process_tokens<'a>(tokens: impl Iterator<Item = Token<'a>>) -> impl Iterator<Item = (usize, Token<'a>)> {
     tokens
         .skip_while(|token| token.is_separator().is_some())
         .scan((0, None), |(offset, prev_kind), token| {
@@ -638,12 +936,23 @@ fn process_tokens<'a>(tokens: impl Iterator<Item = Token<'a>>) -> impl Iterator<
     .filter(|(_, t)| t.is_word())
 }
 
-fn parse_facet_value(ftype: FacetType, value: &Value) -> anyhow::Result<SmallVec8<FacetValue>> {
+/// Parses the facet values of a field, the string values are normalized and, if a map of display
+/// values is given, the first original value seen for each normalized value is recorded in it.
+fn parse_facet_value(
+    ftype: FacetType,
+    normalization: FacetStringNormalization,
+    value: &Value,
+    mut display_values: Option<&mut BTreeMap<String, String>>,
+) -> anyhow::Result<SmallVec8<FacetValue>>
+{
     use FacetValue::*;
 
     fn inner_parse_facet_value(
         ftype: FacetType,
+        normalization: FacetStringNormalization,
         value: &Value,
+        // The String type is shadowed by the FacetValue variant in this function.
+        display_values: &mut Option<&mut BTreeMap<std::string::String, std::string::String>>,
         can_recurse: bool,
         output: &mut SmallVec8<FacetValue>,
     ) -> anyhow::Result<()>
@@ -680,12 +989,17 @@ fn parse_facet_value(ftype: FacetType, value: &Value) -> anyhow::Result<SmallVec
                 },
             },
             Value::String(string) => {
-                let string = string.trim().to_lowercase();
+                let string = string.trim();
                 if string.is_empty() { return Ok(()) }
                 match ftype {
                     FacetType::String => {
-                        let string = SmallString32::from(string);
-                        output.push(String(string));
+                        let normalized = normalization.normalize(string);
+                        if let Some(display_values) = display_values {
+                            if !display_values.contains_key(&normalized) {
+                                insert_facet_string_display_value(display_values, normalized.clone(), string.to_string());
+                            }
+                        }
+                        output.push(String(SmallString32::from(normalized)));
                         Ok(())
                     },
                     FacetType::Float => match string.parse() {
@@ -705,7 +1019,9 @@ fn parse_facet_value(ftype: FacetType, value: &Value) -> anyhow::Result<SmallVec
                 }
             },
             Value::Array(values) => if can_recurse {
-                values.iter().map(|v| inner_parse_facet_value(ftype, v, false, output)).collect()
+                values.iter().map(|v| {
+                    inner_parse_facet_value(ftype, normalization, v, display_values, false, output)
+                }).collect()
             } else {
                 bail!("invalid facet type, expecting {} found sub-array ()", ftype)
             },
@@ -714,6 +1030,6 @@ fn parse_facet_value(ftype: FacetType, value: &Value) -> anyhow::Result<SmallVec
     }
 
     let mut facet_values = SmallVec8::new();
-    inner_parse_facet_value(ftype, value, true, &mut facet_values)?;
+    inner_parse_facet_value(ftype, normalization, value, &mut display_values, true, &mut facet_values)?;
     Ok(facet_values)
 }
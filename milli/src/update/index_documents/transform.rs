@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 use std::iter::Peekable;
@@ -6,11 +7,12 @@ use std::time::Instant;
 
 use anyhow::{anyhow, Context};
 use grenad::CompressionType;
+use heed::types::ByteSlice;
 use log::info;
 use roaring::RoaringBitmap;
 use serde_json::{Map, Value};
 
-use crate::{Index, BEU32, MergeFn, FieldsIdsMap, ExternalDocumentsIds, FieldId};
+use crate::{Index, BEU32, MergeFn, FieldsIdsMap, ExternalDocumentsIds, FieldId, ComputedField};
 use crate::update::{AvailableDocumentsIds, UpdateIndexingStep};
 use super::merge_function::merge_two_obkvs;
 use super::{create_writer, create_sorter, IndexDocumentsMethod};
@@ -365,6 +367,8 @@ impl Transform<'_, '_> {
         let mut replaced_documents_ids = RoaringBitmap::new();
         let mut new_documents_ids = RoaringBitmap::new();
         let mut obkv_buffer = Vec::new();
+        let mut computed_buffer = Vec::new();
+        let computed_fields = computed_fields_ids(self.index, self.rtxn, &fields_ids_map)?;
 
         // While we write into final file we get or generate the internal documents ids.
         let mut documents_count = 0;
@@ -401,14 +405,25 @@ impl Transform<'_, '_> {
                 None => {
                     // If this user id is new we add it to the external documents ids map
                     // for new ids and into the list of new documents.
-                    let new_docid = available_documents_ids.next()
-                        .context("no more available documents ids")?;
+                    let new_docid = available_documents_ids.next().with_context(|| {
+                        format!("no more available documents ids, an index can't contain more than {} documents",
+                            u64::from(u32::max_value()) + 1)
+                    })?;
                     new_external_documents_ids_builder.insert(external_id, new_docid as u64)?;
                     new_documents_ids.insert(new_docid);
                     (new_docid, update_obkv)
                 },
             };
 
+            // The computed fields are computed once the document has been merged.
+            let obkv = if computed_fields.is_empty() {
+                obkv
+            } else {
+                let document = obkv::KvReader::new(obkv);
+                write_computed_fields(&computed_fields, &fields_ids_map, &document, &mut computed_buffer)?;
+                computed_buffer.as_slice()
+            };
+
             // We insert the document under the documents ids map into the final file.
             final_sorter.insert(docid.to_be_bytes(), obkv)?;
             documents_count += 1;
@@ -466,6 +481,8 @@ impl Transform<'_, '_> {
         let mut writer = create_writer(self.chunk_compression_type, self.chunk_compression_level, file)?;
 
         let mut obkv_buffer = Vec::new();
+        let mut computed_buffer = Vec::new();
+        let computed_fields = computed_fields_ids(self.index, self.rtxn, &new_fields_ids_map)?;
         for result in self.index.documents.iter(self.rtxn)? {
             let (docid, obkv) = result?;
             let docid = docid.get();
@@ -481,6 +498,16 @@ impl Transform<'_, '_> {
             }
 
             let buffer = obkv_writer.into_inner()?;
+
+            // We compute the fields again as the computed fields may have changed.
+            let buffer = if computed_fields.is_empty() {
+                buffer.as_slice()
+            } else {
+                let document = obkv::KvReader::new(buffer);
+                write_computed_fields(&computed_fields, &new_fields_ids_map, &document, &mut computed_buffer)?;
+                computed_buffer.as_slice()
+            };
+
             writer.insert(docid.to_be_bytes(), buffer)?;
         }
 
@@ -499,6 +526,47 @@ impl Transform<'_, '_> {
             documents_file,
         })
     }
+
+    /// Gives the documents of the index new internal ids going from zero to the number of
+    /// documents, in the order of their current ids, the external ids are kept.
+    pub fn compact_documents_ids(self, primary_key: String) -> anyhow::Result<TransformOutput> {
+        let fields_ids_map = self.index.fields_ids_map(self.rtxn)?;
+        let external_documents_ids = self.index.external_documents_ids(self.rtxn)?;
+        let documents_ids = self.index.documents_ids(self.rtxn)?;
+        let documents_count = documents_ids.len() as usize;
+
+        // We create a final writer to write the documents in order under their new ids.
+        let file = tempfile::tempfile()?;
+        let mut writer = create_writer(self.chunk_compression_type, self.chunk_compression_level, file)?;
+
+        // The documents are iterated in the order of their ids, the new ids are therefore
+        // also ordered and the documents can be written directly into the final writer.
+        let mut new_ids = HashMap::with_capacity(documents_count);
+        let documents = self.index.documents.remap_data_type::<ByteSlice>();
+        for (new_docid, result) in documents.iter(self.rtxn)?.enumerate() {
+            let (docid, obkv) = result?;
+            let new_docid = new_docid as u32;
+            new_ids.insert(docid.get(), new_docid);
+            writer.insert(new_docid.to_be_bytes(), obkv)?;
+        }
+
+        let external_documents_ids = external_documents_ids.remap_ids(|id| new_ids.get(&id).copied())?;
+
+        // Once we have written all the documents, we extract
+        // the file and reset the seek to be able to read it again.
+        let mut documents_file = writer.into_inner()?;
+        documents_file.seek(SeekFrom::Start(0))?;
+
+        Ok(TransformOutput {
+            primary_key,
+            fields_ids_map,
+            external_documents_ids,
+            new_documents_ids: (0..documents_count as u32).collect(),
+            replaced_documents_ids: RoaringBitmap::default(),
+            documents_count,
+            documents_file,
+        })
+    }
 }
 
 /// Given an optional primary key and an optional alternative name, returns the (field_id, attr_name)
@@ -537,6 +605,51 @@ fn compute_primary_key_pair(
     }
 }
 
+/// Returns the computed fields of the index along with their ids,
+/// the computed fields are inserted in the fields ids map by the settings.
+fn computed_fields_ids(
+    index: &Index,
+    rtxn: &heed::RoTxn,
+    fields_ids_map: &FieldsIdsMap,
+) -> anyhow::Result<Vec<(FieldId, ComputedField)>>
+{
+    let mut computed_fields = Vec::new();
+    for (name, computed_field) in index.computed_fields(rtxn)? {
+        let id = fields_ids_map.id(&name).with_context(|| {
+            format!("missing computed field {:?} from the fields ids map", name)
+        })?;
+        computed_fields.push((id, computed_field));
+    }
+    Ok(computed_fields)
+}
+
+/// Writes the document along with the values of its computed fields into the buffer,
+/// the fields are written in the fields ids order.
+fn write_computed_fields(
+    computed_fields: &[(FieldId, ComputedField)],
+    fields_ids_map: &FieldsIdsMap,
+    document: &obkv::KvReader,
+    buffer: &mut Vec<u8>,
+) -> anyhow::Result<()>
+{
+    let mut fields: BTreeMap<_, _> = document.iter().map(|(id, value)| (id, Cow::Borrowed(value))).collect();
+    for (field_id, computed_field) in computed_fields {
+        match computed_field.compute(fields_ids_map, document)? {
+            Some(value) => { fields.insert(*field_id, Cow::Owned(serde_json::to_vec(&value)?)); },
+            None => { fields.remove(field_id); },
+        }
+    }
+
+    buffer.clear();
+    let mut writer = obkv::KvWriter::new(buffer);
+    for (field_id, value) in fields {
+        writer.insert(field_id, value)?;
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
 /// Only the last value associated with an id is kept.
 fn keep_latest_obkv(_key: &[u8], obkvs: &[Cow<[u8]>]) -> anyhow::Result<Vec<u8>> {
     obkvs.last().context("no last value").map(|last| last.clone().into_owned())
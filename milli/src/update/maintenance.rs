@@ -0,0 +1,216 @@
+use chrono::Utc;
+use grenad::CompressionType;
+use heed::types::{DecodeIgnore, Str};
+use log::debug;
+use rayon::ThreadPool;
+
+use crate::Index;
+use super::{Facets, WordDocidsShards, WordsPrefixes};
+
+use MaintenanceStep::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaintenanceStep {
+    /// Reindexes the documents under compact internal ids when the deletions left
+    /// holes in the ids, see `update::CompactDocumentsIds`.
+    CompactDocumentsIds,
+
+    /// Merges the recently added and deleted external documents ids into
+    /// the main external documents ids map, forgetting the deleted ones.
+    PurgeSoftDeletedIds,
+
+    /// Rebuilds the words FST from the words that are still part of the documents.
+    RebuildWordsFst,
+
+    /// Recomputes the facet levels of all the faceted fields.
+    RebuildFacets,
+
+    /// Recomputes the words prefixes FST and the prefixes databases.
+    RebuildWordsPrefixes,
+
+    /// Recomputes the shards of the documents ids of the frequent words.
+    RebuildWordDocidsShards,
+}
+
+impl MaintenanceStep {
+    pub const fn step(&self) -> usize {
+        match self {
+            CompactDocumentsIds => 0,
+            PurgeSoftDeletedIds => 1,
+            RebuildWordsFst => 2,
+            RebuildFacets => 3,
+            RebuildWordsPrefixes => 4,
+            RebuildWordDocidsShards => 5,
+        }
+    }
+
+    pub const fn number_of_steps(&self) -> usize {
+        6
+    }
+}
+
+/// Runs all the heavy datastructures rebuilds in a single pass, the updates only rebuild
+/// them incrementally, an operator can run this when the index is not under load.
+///
+/// Once the write transaction committed, the LMDB file can be compacted with
+/// `Index::copy_compacted_to_path`, the freed pages are only reclaimed this way.
+pub struct Maintenance<'a, 't, 'u, 'i> {
+    wtxn: &'t mut heed::RwTxn<'i, 'u>,
+    index: &'i Index,
+    pub(crate) log_every_n: Option<usize>,
+    pub(crate) max_nb_chunks: Option<usize>,
+    pub(crate) max_memory: Option<usize>,
+    pub(crate) linked_hash_map_size: Option<usize>,
+    pub(crate) chunk_compression_type: CompressionType,
+    pub(crate) chunk_compression_level: Option<u32>,
+    pub(crate) chunk_fusing_shrink_size: Option<u64>,
+    pub(crate) thread_pool: Option<&'a ThreadPool>,
+    update_id: u64,
+}
+
+impl<'a, 't, 'u, 'i> Maintenance<'a, 't, 'u, 'i> {
+    pub fn new(
+        wtxn: &'t mut heed::RwTxn<'i, 'u>,
+        index: &'i Index,
+        update_id: u64,
+    ) -> Maintenance<'a, 't, 'u, 'i>
+    {
+        Maintenance {
+            wtxn,
+            index,
+            log_every_n: None,
+            max_nb_chunks: None,
+            max_memory: None,
+            linked_hash_map_size: None,
+            chunk_compression_type: CompressionType::None,
+            chunk_compression_level: None,
+            chunk_fusing_shrink_size: None,
+            thread_pool: None,
+            update_id,
+        }
+    }
+
+    pub fn execute<F>(self, progress_callback: F) -> anyhow::Result<()>
+    where
+        F: Fn(MaintenanceStep, u64) + Sync
+    {
+        self.index.set_updated_at(self.wtxn, &Utc::now())?;
+
+        // The documents are reindexed first, the following steps rebuild what they indexed.
+        debug!("Compacting the documents ids...");
+        progress_callback(CompactDocumentsIds, self.update_id);
+        let mut builder = super::CompactDocumentsIds::new(self.wtxn, self.index, self.update_id);
+        builder.log_every_n = self.log_every_n;
+        builder.max_nb_chunks = self.max_nb_chunks;
+        builder.max_memory = self.max_memory;
+        builder.linked_hash_map_size = self.linked_hash_map_size;
+        builder.chunk_compression_type = self.chunk_compression_type;
+        builder.chunk_compression_level = self.chunk_compression_level;
+        builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+        builder.thread_pool = self.thread_pool;
+        builder.execute(|_, _| ())?;
+
+        debug!("Purging the soft deleted external documents ids...");
+        progress_callback(PurgeSoftDeletedIds, self.update_id);
+        let mut external_documents_ids = self.index.external_documents_ids(self.wtxn)?.into_static();
+        external_documents_ids.purge_soft_ids()?;
+        self.index.put_external_documents_ids(self.wtxn, &external_documents_ids)?;
+
+        // The words FST is only shrunk by the deletions when it is cheap enough,
+        // we rebuild it from the words that still have documents ids.
+        debug!("Rebuilding the words FST...");
+        progress_callback(RebuildWordsFst, self.update_id);
+        let mut builder = fst::SetBuilder::memory();
+        for result in self.index.word_docids.remap_types::<Str, DecodeIgnore>().iter(self.wtxn)? {
+            let (word, ()) = result?;
+            builder.insert(word)?;
+        }
+        self.index.put_words_fst(self.wtxn, &builder.into_set())?;
+
+        debug!("Rebuilding the facet levels...");
+        progress_callback(RebuildFacets, self.update_id);
+        let mut builder = Facets::new(self.wtxn, self.index, self.update_id);
+        builder.chunk_compression_type = self.chunk_compression_type;
+        builder.chunk_compression_level = self.chunk_compression_level;
+        builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+        builder.execute()?;
+
+        debug!("Rebuilding the words prefixes...");
+        progress_callback(RebuildWordsPrefixes, self.update_id);
+        let mut builder = WordsPrefixes::new(self.wtxn, self.index, self.update_id);
+        builder.chunk_compression_type = self.chunk_compression_type;
+        builder.chunk_compression_level = self.chunk_compression_level;
+        builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+        builder.execute()?;
+
+        debug!("Rebuilding the word docids shards...");
+        progress_callback(RebuildWordDocidsShards, self.update_id);
+        WordDocidsShards::new(self.wtxn, self.index, self.update_id).execute()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use heed::EnvOpenOptions;
+
+    use crate::update::{DeleteDocuments, IndexDocuments, UpdateFormat};
+    use super::*;
+
+    #[test]
+    fn run_maintenance() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n1,kevin\n2,kevina\n3,benoit\n4,bernard\n5,bertrand\n6,bernie\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+
+        // A single deletion is too small to be merged into the hard external ids.
+        let mut builder = DeleteDocuments::new(&mut wtxn, &index, 1).unwrap();
+        builder.delete_external_id("3");
+        builder.execute().unwrap();
+        assert!(!index.external_documents_ids(&wtxn).unwrap().soft.is_empty());
+
+        // A word that no document contains anymore but that is still in the words FST.
+        let words_fst = {
+            let words_fst = index.words_fst(&wtxn).unwrap();
+            let stale_words = fst::Set::from_iter(vec!["zebulon"]).unwrap();
+            let mut builder = fst::SetBuilder::memory();
+            builder.extend_stream(words_fst.op().add(&stale_words).r#union()).unwrap();
+            builder.into_set()
+        };
+        index.put_words_fst(&mut wtxn, &words_fst).unwrap();
+
+        let steps = Mutex::new(Vec::new());
+        let builder = Maintenance::new(&mut wtxn, &index, 2);
+        builder.execute(|step, _| steps.lock().unwrap().push(step.step())).unwrap();
+        wtxn.commit().unwrap();
+
+        assert_eq!(steps.into_inner().unwrap(), vec![0, 1, 2, 3, 4, 5]);
+
+        // The hole the deleted document left in the ids is filled.
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.documents_ids(&rtxn).unwrap(), (0..5).collect());
+        let external_documents_ids = index.external_documents_ids(&rtxn).unwrap();
+        assert!(external_documents_ids.soft.is_empty());
+        assert_eq!(external_documents_ids.get("1"), Some(0));
+        assert_eq!(external_documents_ids.get("3"), None);
+        let words_fst = index.words_fst(&rtxn).unwrap();
+        assert!(words_fst.contains("kevin"));
+        assert!(!words_fst.contains("benoit"));
+        assert!(!words_fst.contains("zebulon"));
+        drop(rtxn);
+
+        let copy = tempfile::tempdir().unwrap();
+        index.copy_compacted_to_path(copy.path().join("data.mdb")).unwrap();
+        assert!(copy.path().join("data.mdb").exists());
+    }
+}
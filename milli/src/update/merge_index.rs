@@ -0,0 +1,480 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+
+use anyhow::{bail, Context};
+use chrono::Utc;
+use fst::{IntoStreamer, Streamer};
+use grenad::CompressionType;
+use heed::types::ByteSlice;
+use log::debug;
+use roaring::RoaringBitmap;
+
+use crate::facet::FacetType;
+use crate::index::insert_facet_string_display_value;
+use crate::proximity::{encode_position, extract_position};
+use crate::{DocumentId, FieldId, Index, BEU32};
+use super::{AvailableDocumentsIds, DeleteDocuments, Facets, WordDocidsShards, WordsPrefixes};
+
+/// Merges all the documents of another index into this index.
+///
+/// The documents are not extracted and tokenized again, the entries of the databases of the
+/// source index are remapped to the internal documents ids and fields ids of this index and
+/// merged into its own databases. The documents of the source replace the documents of this
+/// index that have the same external id.
+///
+/// Both indexes must index their documents the same way: the primary key, the searchable fields,
+/// the stored only fields, the stop words, the languages, the computed fields, the collation
+/// strength and the facet string normalizations must be the same, the fields faceted by this index must be faceted with the same type
+/// by the source. The facets of the fields that are only faceted by the source are ignored.
+pub struct MergeIndex<'t, 'u, 'i, 's> {
+    wtxn: &'t mut heed::RwTxn<'i, 'u>,
+    index: &'i Index,
+    source: &'s Index,
+    source_rtxn: &'s heed::RoTxn<'s>,
+    pub(crate) chunk_compression_type: CompressionType,
+    pub(crate) chunk_compression_level: Option<u32>,
+    pub(crate) chunk_fusing_shrink_size: Option<u64>,
+    update_id: u64,
+}
+
+impl<'t, 'u, 'i, 's> MergeIndex<'t, 'u, 'i, 's> {
+    pub fn new(
+        wtxn: &'t mut heed::RwTxn<'i, 'u>,
+        index: &'i Index,
+        source: &'s Index,
+        source_rtxn: &'s heed::RoTxn<'s>,
+        update_id: u64,
+    ) -> MergeIndex<'t, 'u, 'i, 's>
+    {
+        MergeIndex {
+            wtxn,
+            index,
+            source,
+            source_rtxn,
+            chunk_compression_type: CompressionType::None,
+            chunk_compression_level: None,
+            chunk_fusing_shrink_size: None,
+            update_id,
+        }
+    }
+
+    /// Returns the number of documents merged into this index.
+    pub fn execute(self) -> anyhow::Result<u64> {
+        self.index.set_updated_at(self.wtxn, &Utc::now())?;
+
+        let source = self.source;
+        let srtxn = self.source_rtxn;
+
+        if source.documents_ids(srtxn)?.is_empty() {
+            return Ok(0);
+        }
+
+        check_compatibility(self.index, self.wtxn, source, srtxn)?;
+
+        let primary_key = source.primary_key(srtxn)?.context("the source index has no primary key")?;
+        match self.index.primary_key(self.wtxn)?.map(ToOwned::to_owned) {
+            Some(current) if current != primary_key => {
+                bail!("the primary key of the source index ({:?}) is not the primary key of the index ({:?})",
+                    primary_key, current)
+            },
+            Some(_) => (),
+            None => {
+                self.index.put_primary_key(self.wtxn, primary_key)?;
+                self.index.refresh_metadata(self.wtxn)?;
+            },
+        }
+
+        // We register the fields of the source and map them to the fields ids of this index.
+        let mut fields_ids_map = self.index.fields_ids_map(self.wtxn)?;
+        let source_fields_ids_map = source.fields_ids_map(srtxn)?;
+        let mut fields_ids = HashMap::new();
+        for (source_id, name) in source_fields_ids_map.iter() {
+            let id = fields_ids_map.insert(name).context("field id limit reached")?;
+            fields_ids.insert(source_id, id);
+        }
+        self.index.put_fields_ids_map(self.wtxn, &fields_ids_map)?;
+
+        // The facets of the source are only kept for the fields that this index facets.
+        let mut faceted_fields = HashMap::new();
+        for (name, facet_type) in self.index.faceted_fields(self.wtxn)? {
+            if let Some(source_id) = source_fields_ids_map.id(&name) {
+                faceted_fields.insert(source_id, (fields_ids[&source_id], facet_type));
+            }
+        }
+
+        let mut source_external_ids = source.external_documents_ids(srtxn)?.into_static();
+        source_external_ids.purge_soft_ids()?;
+        let source_external_ids = source_external_ids.hard;
+
+        // The documents of this index that have the same external id
+        // as a document of the source are replaced, we delete them first.
+        let replaced_documents_ids = {
+            let external_documents_ids = self.index.external_documents_ids(self.wtxn)?;
+            let mut replaced_documents_ids = RoaringBitmap::new();
+            let mut stream = source_external_ids.stream();
+            while let Some((external_id, _)) = stream.next() {
+                if let Some(docid) = external_documents_ids.get(external_id) {
+                    replaced_documents_ids.insert(docid);
+                }
+            }
+            replaced_documents_ids
+        };
+
+        if !replaced_documents_ids.is_empty() {
+            debug!("Deleting the {} replaced documents...", replaced_documents_ids.len());
+            let mut builder = DeleteDocuments::new(self.wtxn, self.index, self.update_id)?;
+            builder.delete_documents(&replaced_documents_ids);
+            builder.execute()?;
+        }
+
+        // We give a new internal id to every document of the source.
+        let mut documents_ids = self.index.documents_ids(self.wtxn)?;
+        let mut available_documents_ids = AvailableDocumentsIds::from_documents_ids(&documents_ids);
+        let mut docids_map = HashMap::new();
+        let mut new_external_documents_ids_builder = fst::MapBuilder::memory();
+        let mut stream = source_external_ids.stream();
+        while let Some((external_id, source_docid)) = stream.next() {
+            let docid = available_documents_ids.next().with_context(|| {
+                format!("no more available documents ids, an index can't contain more than {} documents",
+                    u64::from(u32::max_value()) + 1)
+            })?;
+            new_external_documents_ids_builder.insert(external_id, docid as u64)?;
+            docids_map.insert(source_docid as DocumentId, docid);
+            documents_ids.insert(docid);
+        }
+
+        let new_external_documents_ids = new_external_documents_ids_builder.into_map();
+        let mut external_documents_ids = self.index.external_documents_ids(self.wtxn)?.into_static();
+        external_documents_ids.insert_ids(&new_external_documents_ids)?;
+        self.index.put_external_documents_ids(self.wtxn, &external_documents_ids)?;
+        self.index.put_documents_ids(self.wtxn, &documents_ids)?;
+
+        let remap_docids = |docids: RoaringBitmap| -> RoaringBitmap {
+            docids.iter().filter_map(|docid| docids_map.get(&docid).copied()).collect()
+        };
+
+        debug!("Merging the documents of the source index...");
+        let documents = self.index.documents.remap_data_type::<ByteSlice>();
+        for result in source.documents.iter(srtxn)? {
+            let (source_docid, obkv) = result?;
+            let docid = match docids_map.get(&source_docid.get()) {
+                Some(docid) => *docid,
+                None => continue,
+            };
+
+            // The fields of an obkv must be written in order.
+            let fields: BTreeMap<FieldId, &[u8]> = obkv.iter().map(|(id, value)| (fields_ids[&id], value)).collect();
+            let mut writer = obkv::KvWriter::new(Vec::new());
+            for (field_id, value) in fields {
+                writer.insert(field_id, value)?;
+            }
+            documents.put(self.wtxn, &BEU32::new(docid), &writer.into_inner()?)?;
+        }
+
+        debug!("Merging the words docids of the source index...");
+        for result in source.word_docids.iter(srtxn)? {
+            let (word, docids) = result?;
+            let mut docids = remap_docids(docids);
+            if let Some(current) = self.index.word_docids.get(self.wtxn, word)? {
+                docids.union_with(&current);
+            }
+            self.index.word_docids.put(self.wtxn, word, &docids)?;
+        }
+
+        debug!("Merging the words positions of the source index...");
+        for result in source.docid_word_positions.iter(srtxn)? {
+            let ((source_docid, word), positions) = result?;
+            let docid = match docids_map.get(&source_docid) {
+                Some(docid) => *docid,
+                None => continue,
+            };
+
+            // The positions are prefixed by the field id of the attribute they are in.
+            let positions: RoaringBitmap = positions.iter().filter_map(|position| {
+                let (attribute, index) = extract_position(position);
+                let field_id = fields_ids.get(&(attribute as FieldId))?;
+                Some(encode_position(*field_id as u32, index))
+            }).collect();
+            self.index.docid_word_positions.put(self.wtxn, &(docid, word), &positions)?;
+        }
+
+        // The positions buckets don't depend on the attributes, only the documents ids are remapped.
+        debug!("Merging the words positions docids of the source index...");
+        let source_db = source.word_position_docids.remap_key_type::<ByteSlice>();
+        let db = self.index.word_position_docids.remap_key_type::<ByteSlice>();
+        for result in source_db.iter(srtxn)? {
+            let (key, docids) = result?;
+            let mut docids = remap_docids(docids);
+            if let Some(current) = db.get(self.wtxn, key)? {
+                docids.union_with(&current);
+            }
+            db.put(self.wtxn, key, &docids)?;
+        }
+
+        // The attributes of the words are the field ids of the source index, they are remapped.
+        debug!("Merging the words attributes docids of the source index...");
+        for result in source.word_attribute_docids.iter(srtxn)? {
+            let ((word, attribute), docids) = result?;
+            let field_id = match fields_ids.get(&(attribute as FieldId)) {
+                Some(field_id) => *field_id as u32,
+                None => continue,
+            };
+            let mut docids = remap_docids(docids);
+            if let Some(current) = self.index.word_attribute_docids.get(self.wtxn, &(word, field_id))? {
+                docids.union_with(&current);
+            }
+            self.index.word_attribute_docids.put(self.wtxn, &(word, field_id), &docids)?;
+        }
+
+        // The words pairs proximities are not merged when this index doesn't store them.
+        if self.index.proximity_database_enabled(self.wtxn)? {
+            debug!("Merging the words pairs proximities of the source index...");
+            let source_db = source.word_pair_proximity_docids.remap_key_type::<ByteSlice>();
+            let db = self.index.word_pair_proximity_docids.remap_key_type::<ByteSlice>();
+            for result in source_db.iter(srtxn)? {
+                let (key, docids) = result?;
+                let mut docids = remap_docids(docids);
+                if let Some(current) = db.get(self.wtxn, key)? {
+                    docids.union_with(&current);
+                }
+                db.put(self.wtxn, key, &docids)?;
+            }
+        }
+
+        debug!("Merging the facet values of the source index...");
+        for result in source.facet_field_id_value_docids.iter(srtxn)? {
+            let (key, docids) = result?;
+            let (field_id, facet_type) = match key.first().and_then(|id| faceted_fields.get(id)) {
+                Some(field) => *field,
+                None => continue,
+            };
+
+            // Only the level 0 of the numbers is merged, the other levels are computed again below.
+            if facet_type != FacetType::String && key.get(1) != Some(&0) {
+                continue;
+            }
+
+            let mut key = key.to_vec();
+            key[0] = field_id;
+            let mut docids = remap_docids(docids);
+            if let Some(current) = self.index.facet_field_id_value_docids.get(self.wtxn, &key)? {
+                docids.union_with(&current);
+            }
+            self.index.facet_field_id_value_docids.put(self.wtxn, &key, &docids)?;
+        }
+
+        for result in source.field_id_docid_facet_values.iter(srtxn)? {
+            let (key, ()) = result?;
+            let field_id = match key.first().and_then(|id| faceted_fields.get(id)) {
+                Some((field_id, _)) => *field_id,
+                None => continue,
+            };
+
+            let source_docid = key.get(1..5)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u32::from_be_bytes)
+                .context("invalid facet value key")?;
+            let docid = match docids_map.get(&source_docid) {
+                Some(docid) => *docid,
+                None => continue,
+            };
+
+            let mut key = key.to_vec();
+            key[0] = field_id;
+            key[1..5].copy_from_slice(&docid.to_be_bytes());
+            self.index.field_id_docid_facet_values.put(self.wtxn, &key, &())?;
+        }
+
+        // The original string facet values already known by this index are kept.
+        for (source_field_id, (field_id, facet_type)) in &faceted_fields {
+            if *facet_type != FacetType::String { continue }
+            let source_display_values = source.facet_string_display_values(srtxn, *source_field_id)?;
+            if source_display_values.is_empty() { continue }
+            let mut display_values = self.index.facet_string_display_values(self.wtxn, *field_id)?;
+            for (normalized, original) in source_display_values {
+                insert_facet_string_display_value(&mut display_values, normalized, original);
+            }
+            self.index.put_facet_string_display_values(self.wtxn, *field_id, &display_values)?;
+        }
+
+        // We add the words of the source to the words FST.
+        let new_words_fst = {
+            let words_fst = self.index.words_fst(self.wtxn)?;
+            let source_words_fst = source.words_fst(srtxn)?;
+            let union = words_fst.op().add(&source_words_fst).r#union();
+
+            let mut new_words_fst_builder = fst::SetBuilder::memory();
+            new_words_fst_builder.extend_stream(union.into_stream())?;
+            new_words_fst_builder.into_set()
+        };
+        self.index.put_words_fst(self.wtxn, &new_words_fst)?;
+
+        // The facet levels, the faceted documents ids and the prefixes are computed
+        // again from the merged databases, the shards only for the words of the source.
+        let mut builder = Facets::new(self.wtxn, self.index, self.update_id);
+        builder.chunk_compression_type = self.chunk_compression_type;
+        builder.chunk_compression_level = self.chunk_compression_level;
+        builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+        builder.execute()?;
+
+        let mut builder = WordsPrefixes::new(self.wtxn, self.index, self.update_id);
+        builder.chunk_compression_type = self.chunk_compression_type;
+        builder.chunk_compression_level = self.chunk_compression_level;
+        builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+        builder.execute()?;
+
+        let mut builder = WordDocidsShards::new(self.wtxn, self.index, self.update_id);
+        builder.words(source.words_fst(srtxn)?.map_data(Cow::into_owned)?);
+        builder.execute()?;
+
+        Ok(docids_map.len() as u64)
+    }
+}
+
+/// Returns an error if the source index doesn't index its documents like the index.
+fn check_compatibility(index: &Index, rtxn: &heed::RoTxn, source: &Index, srtxn: &heed::RoTxn) -> anyhow::Result<()> {
+    let incompatible = |setting: &str| {
+        anyhow::anyhow!("the {} of the source index are not the ones of the index", setting)
+    };
+
+    if index.searchable_fields(rtxn)? != source.searchable_fields(srtxn)? {
+        return Err(incompatible("searchable fields"));
+    }
+    if index.stored_only_fields(rtxn)? != source.stored_only_fields(srtxn)? {
+        return Err(incompatible("stored only fields"));
+    }
+    if index.stop_words(rtxn)?.as_fst().as_bytes() != source.stop_words(srtxn)?.as_fst().as_bytes() {
+        return Err(incompatible("stop words"));
+    }
+    if index.attributes_stop_words(rtxn)? != source.attributes_stop_words(srtxn)? {
+        return Err(incompatible("attributes stop words"));
+    }
+    if index.attributes_languages(rtxn)? != source.attributes_languages(srtxn)? {
+        return Err(incompatible("attributes languages"));
+    }
+    if index.computed_fields(rtxn)? != source.computed_fields(srtxn)? {
+        return Err(incompatible("computed fields"));
+    }
+    if index.collation_strength(rtxn)? != source.collation_strength(srtxn)? {
+        return Err(incompatible("collation strength settings"));
+    }
+    if index.facet_string_normalizations(rtxn)? != source.facet_string_normalizations(srtxn)? {
+        return Err(incompatible("facet string normalizations"));
+    }
+    if index.proximity_database_enabled(rtxn)? && !source.proximity_database_enabled(srtxn)? {
+        return Err(incompatible("proximity database settings"));
+    }
+
+    let source_faceted_fields = source.faceted_fields(srtxn)?;
+    for (name, facet_type) in index.faceted_fields(rtxn)? {
+        if source_faceted_fields.get(&name) != Some(&facet_type) {
+            bail!("the field {:?} must be faceted as {} by the source index", name, facet_type);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use heed::EnvOpenOptions;
+    use maplit::{btreeset, hashmap};
+
+    use crate::update::{IndexDocuments, Settings, UpdateFormat};
+    use crate::FacetCondition;
+    use super::*;
+
+    fn create_index(content: &[u8]) -> (tempfile::TempDir, Index) {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "age".into() => "integer".into() });
+        builder.execute(|_, _| ()).unwrap();
+
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        (path, index)
+    }
+
+    #[test]
+    fn merge_two_indexes() {
+        let (_path, index) = create_index(&b"id,name,age\n1,kevin,20\n2,bob,30\n"[..]);
+        // The fields are not registered in the same order in the source.
+        let (_source_path, source) = create_index(&b"age,name,id\n25,kevina,2\n40,kevin,3\n"[..]);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let srtxn = source.read_txn().unwrap();
+        let merged = MergeIndex::new(&mut wtxn, &index, &source, &srtxn, 2).execute().unwrap();
+        wtxn.commit().unwrap();
+        assert_eq!(merged, 2);
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.number_of_documents(&rtxn).unwrap(), 3);
+
+        // The document "2" of the source replaced the one of the index.
+        let external_ids = index.external_documents_ids(&rtxn).unwrap();
+        let ids: Vec<_> = ["1", "2", "3"].iter().map(|id| external_ids.get(id).unwrap()).collect();
+        let fields_ids_map = index.fields_ids_map(&rtxn).unwrap();
+        let name_id = fields_ids_map.id("name").unwrap();
+        let (_, document) = index.documents(&rtxn, Some(ids[1])).unwrap().pop().unwrap();
+        assert_eq!(document.get(name_id), Some(&br#""kevina""#[..]));
+
+        let result = index.search(&rtxn).query("kevin").execute().unwrap();
+        let mut documents_ids = result.documents_ids;
+        documents_ids.sort_unstable();
+        let mut expected = ids.clone();
+        expected.sort_unstable();
+        assert_eq!(documents_ids, expected);
+
+        let result = index.search(&rtxn).query("bob").execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "age > 22").unwrap();
+        let mut result = index.search(&rtxn).facet_condition(condition).execute().unwrap();
+        result.documents_ids.sort_unstable();
+        let mut expected = vec![ids[1], ids[2]];
+        expected.sort_unstable();
+        assert_eq!(result.documents_ids, expected);
+    }
+
+    #[test]
+    fn merge_incompatible_indexes() {
+        let (_path, index) = create_index(&b"id,name,age\n1,kevin,20\n"[..]);
+        let (_source_path, source) = create_index(&b"id,name,age\n2,kevina,25\n"[..]);
+
+        let mut wtxn = source.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &source, 2);
+        builder.set_searchable_fields(vec!["name".into()]);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let srtxn = source.read_txn().unwrap();
+        assert!(MergeIndex::new(&mut wtxn, &index, &source, &srtxn, 2).execute().is_err());
+    }
+
+    #[test]
+    fn merge_indexes_with_other_stop_words() {
+        let (_path, index) = create_index(&b"id,name,age\n1,kevin,20\n"[..]);
+        let (_source_path, source) = create_index(&b"id,name,age\n2,the kevina,25\n"[..]);
+
+        let mut wtxn = source.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &source, 2);
+        builder.set_stop_words(btreeset!{ "the".to_string() });
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let srtxn = source.read_txn().unwrap();
+        let error = MergeIndex::new(&mut wtxn, &index, &source, &srtxn, 2).execute().unwrap_err();
+        assert!(error.to_string().contains("stop words"));
+    }
+}
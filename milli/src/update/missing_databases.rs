@@ -0,0 +1,182 @@
+use std::iter::FromIterator;
+
+use anyhow::Context;
+use grenad::CompressionType;
+use heed::BytesEncode;
+use log::debug;
+use roaring::RoaringBitmap;
+
+use crate::index::DERIVED_DATABASES;
+use crate::proximity::{extract_position, position_bucket, positions_attributes};
+use crate::storage::IndexDatabase;
+use crate::update::index_documents::{create_sorter, sorter_into_storage, WriteMethod};
+use crate::update::index_documents::{word_attribute_docids_merge, word_position_docids_merge};
+use crate::{CboRoaringBitmapCodec, Index, StrBEU32Codec};
+use super::WordsPrefixes;
+
+/// Builds the derived databases that an index written by a previous version of milli
+/// doesn't have, see `index::DERIVED_DATABASES`.
+///
+/// It is run when such an index is opened, the databases are only advertised
+/// in the features of the index metadata once they are built for all the documents.
+pub(crate) struct MissingDatabases<'t, 'u, 'i> {
+    wtxn: &'t mut heed::RwTxn<'i, 'u>,
+    index: &'i Index,
+    update_id: u64,
+}
+
+impl<'t, 'u, 'i> MissingDatabases<'t, 'u, 'i> {
+    pub fn new(
+        wtxn: &'t mut heed::RwTxn<'i, 'u>,
+        index: &'i Index,
+        update_id: u64,
+    ) -> MissingDatabases<'t, 'u, 'i>
+    {
+        MissingDatabases { wtxn, index, update_id }
+    }
+
+    pub fn execute(self) -> anyhow::Result<()> {
+        let mut built = self.index.built_databases(self.wtxn)?;
+
+        if !built.contains("prefix-word-pair-proximity-docids") {
+            // The prefixes databases are all computed again from the words pairs proximities.
+            debug!("Building the prefix word pair proximity docids...");
+            WordsPrefixes::new(self.wtxn, self.index, self.update_id).execute()?;
+        }
+
+        let positions = !built.contains("word-position-docids");
+        let attributes = !built.contains("word-attribute-docids");
+        if positions || attributes {
+            debug!("Building the word position and word attribute docids...");
+            build_words_positions_databases(self.wtxn, self.index, positions, attributes)?;
+        }
+
+        built.extend(DERIVED_DATABASES.iter().map(|name| name.to_string()));
+        self.index.put_built_databases(self.wtxn, &built)?;
+        self.index.refresh_metadata(self.wtxn)
+    }
+}
+
+/// Computes the word position docids and the word attribute docids from the positions
+/// of the words of every document, only the requested databases are written.
+fn build_words_positions_databases(
+    wtxn: &mut heed::RwTxn,
+    index: &Index,
+    positions: bool,
+    attributes: bool,
+) -> anyhow::Result<()>
+{
+    let mut position_sorter = create_sorter(word_position_docids_merge, CompressionType::None, None, None, None, None);
+    let mut attribute_sorter = create_sorter(word_attribute_docids_merge, CompressionType::None, None, None, None, None);
+
+    let mut buffer = Vec::new();
+    for result in index.docid_word_positions.iter(wtxn)? {
+        let ((docid, word), word_positions) = result?;
+        buffer.clear();
+        CboRoaringBitmapCodec::serialize_into(&RoaringBitmap::from_iter(Some(docid)), &mut buffer)?;
+
+        // The bucket of the first position the word appears at in an attribute, like the indexer.
+        let first_index = word_positions.iter().map(|p| extract_position(p).1).min();
+        if let (true, Some(first_index)) = (positions, first_index) {
+            let key = StrBEU32Codec::bytes_encode(&(word, position_bucket(first_index)))
+                .context("could not serialize word position key")?;
+            position_sorter.insert(key, &buffer)?;
+        }
+
+        if attributes {
+            for attribute in positions_attributes(&word_positions) {
+                let key = StrBEU32Codec::bytes_encode(&(word, attribute))
+                    .context("could not serialize word attribute key")?;
+                attribute_sorter.insert(key, &buffer)?;
+            }
+        }
+    }
+
+    if positions {
+        index.word_position_docids.clear(wtxn)?;
+        sorter_into_storage(
+            &mut index.storage_mut(wtxn, IndexDatabase::WordPositionDocids),
+            position_sorter,
+            word_position_docids_merge,
+            WriteMethod::Append,
+        )?;
+    }
+
+    if attributes {
+        index.word_attribute_docids.clear(wtxn)?;
+        sorter_into_storage(
+            &mut index.storage_mut(wtxn, IndexDatabase::WordAttributeDocids),
+            attribute_sorter,
+            word_attribute_docids_merge,
+            WriteMethod::Append,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use heed::EnvOpenOptions;
+
+    use crate::update::{IndexDocuments, UpdateFormat};
+    use super::*;
+
+    #[test]
+    fn build_missing_databases_on_open() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,text\n0,hello world\n1,help wanted\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+
+        // We simulate an index written by a version that didn't have the derived databases.
+        index.put_built_databases(&mut wtxn, &BTreeSet::new()).unwrap();
+        index.prefix_word_pair_proximity_docids.clear(&mut wtxn).unwrap();
+        index.word_position_docids.clear(&mut wtxn).unwrap();
+        index.word_attribute_docids.clear(&mut wtxn).unwrap();
+        index.refresh_metadata(&mut wtxn).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let metadata = index.metadata(&rtxn).unwrap().unwrap();
+        assert!(!metadata.features.iter().any(|f| f == "prefix-word-pair-proximity-docids"));
+        assert!(!metadata.features.iter().any(|f| f == "word-position-docids"));
+        assert!(!metadata.features.iter().any(|f| f == "word-attribute-docids"));
+        drop(rtxn);
+
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let docids = index.prefix_word_pair_proximity_docids.get(&rtxn, &("he", "world", 1)).unwrap();
+        assert_eq!(docids.map(|ids| ids.iter().collect::<Vec<_>>()), Some(vec![0]));
+
+        // "wanted" is the second word of its attribute, "help" the first one.
+        let docids = index.word_position_docids.get(&rtxn, &("wanted", position_bucket(1))).unwrap();
+        assert_eq!(docids.map(|ids| ids.iter().collect::<Vec<_>>()), Some(vec![1]));
+        let docids = index.word_position_docids.get(&rtxn, &("help", position_bucket(0))).unwrap();
+        assert_eq!(docids.map(|ids| ids.iter().collect::<Vec<_>>()), Some(vec![1]));
+
+        // "hello" and "help" are in the text attribute, not in the id one.
+        let fields_ids_map = index.fields_ids_map(&rtxn).unwrap();
+        let (id, text) = (fields_ids_map.id("id").unwrap(), fields_ids_map.id("text").unwrap());
+        let docids = index.word_attribute_docids.get(&rtxn, &("hello", text as u32)).unwrap();
+        assert_eq!(docids.map(|ids| ids.iter().collect::<Vec<_>>()), Some(vec![0]));
+        let docids = index.word_attribute_docids.get(&rtxn, &("help", id as u32)).unwrap();
+        assert_eq!(docids, None);
+
+        let metadata = index.metadata(&rtxn).unwrap().unwrap();
+        assert!(metadata.features.iter().any(|f| f == "prefix-word-pair-proximity-docids"));
+        assert!(metadata.features.iter().any(|f| f == "word-position-docids"));
+        assert!(metadata.features.iter().any(|f| f == "word-attribute-docids"));
+    }
+}
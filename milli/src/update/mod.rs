@@ -1,19 +1,35 @@
 mod available_documents_ids;
 mod clear_documents;
+mod compact_documents_ids;
+mod deadline;
 mod delete_documents;
 mod facets;
 mod index_documents;
+mod maintenance;
+mod merge_index;
+mod missing_databases;
 mod settings;
+mod stop_words_detection;
 mod update_builder;
 mod update_step;
+mod word_docids_shards;
 mod words_prefixes;
 
 pub use self::available_documents_ids::AvailableDocumentsIds;
 pub use self::clear_documents::ClearDocuments;
+pub use self::compact_documents_ids::CompactDocumentsIds;
+pub use self::deadline::DeadlineExceeded;
 pub use self::delete_documents::DeleteDocuments;
 pub use self::facets::Facets;
 pub use self::index_documents::{IndexDocuments, IndexDocumentsMethod, UpdateFormat, DocumentAdditionResult};
-pub use self::settings::Settings;
+pub use self::index_documents::MaxPositionPolicy;
+pub(crate) use self::index_documents::process_tokens;
+pub use self::maintenance::{Maintenance, MaintenanceStep};
+pub use self::merge_index::MergeIndex;
+pub(crate) use self::missing_databases::MissingDatabases;
+pub use self::settings::{Settings, SettingsSnapshot};
+pub use self::stop_words_detection::{StopWordCandidate, StopWordsDetection};
 pub use self::update_builder::UpdateBuilder;
 pub use self::update_step::UpdateIndexingStep;
+pub use self::word_docids_shards::WordDocidsShards;
 pub use self::words_prefixes::WordsPrefixes;
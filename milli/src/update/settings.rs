@@ -1,17 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::str::FromStr;
+use std::time::Instant;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use chrono::Utc;
 use grenad::CompressionType;
 use itertools::Itertools;
 use rayon::ThreadPool;
+use serde::{Serialize, Deserialize};
 
 use crate::criterion::Criterion;
-use crate::facet::FacetType;
-use crate::update::index_documents::{Transform, IndexDocumentsMethod};
-use crate::update::{ClearDocuments, IndexDocuments, UpdateIndexingStep};
-use crate::{Index, FieldsIdsMap};
+use crate::facet::{CollationStrength, FacetStringNormalization, FacetType};
+use crate::update::index_documents::{Transform, IndexDocumentsMethod, MaxPositionPolicy};
+use crate::update::words_prefixes::{clamp_max_prefix_length, clamp_threshold};
+use crate::update::{ClearDocuments, IndexDocuments, StopWordsDetection, UpdateIndexingStep, WordsPrefixes};
+use crate::search::{DEFAULT_MAX_NGRAM, MAX_NGRAM_LIMIT};
+use crate::{ComputedField, DistinctMode, Index, FieldsIdsMap, SearchDefaults};
 
 pub struct Settings<'a, 't, 'u, 'i> {
     wtxn: &'t mut heed::RwTxn<'i, 'u>,
@@ -24,6 +28,7 @@ pub struct Settings<'a, 't, 'u, 'i> {
     pub(crate) chunk_compression_level: Option<u32>,
     pub(crate) chunk_fusing_shrink_size: Option<u64>,
     pub(crate) thread_pool: Option<&'a ThreadPool>,
+    pub(crate) deadline: Option<Instant>,
     update_id: u64,
 
     // If a struct field is set to `None` it means that it hasn't been set by the user,
@@ -31,7 +36,30 @@ pub struct Settings<'a, 't, 'u, 'i> {
     searchable_fields: Option<Option<Vec<String>>>,
     displayed_fields: Option<Option<Vec<String>>>,
     faceted_fields: Option<Option<HashMap<String, String>>>,
+    sortable_fields: Option<Option<HashSet<String>>>,
+    auto_filterable_fields: Option<Option<HashSet<String>>>,
+    stored_only_fields: Option<Option<HashSet<String>>>,
+    stop_words: Option<Option<BTreeSet<String>>>,
+    stop_words_detection: Option<StopWordsDetection>,
+    attributes_stop_words: Option<Option<HashMap<String, BTreeSet<String>>>>,
+    attributes_languages: Option<Option<HashMap<String, String>>>,
+    attributes_weights: Option<Option<HashMap<String, u8>>>,
+    computed_fields: Option<Option<BTreeMap<String, ComputedField>>>,
+    collation_strength: Option<Option<CollationStrength>>,
+    facet_string_normalizations: Option<Option<HashMap<String, FacetStringNormalization>>>,
     criteria: Option<Option<Vec<String>>>,
+    words_prefixes_threshold: Option<Option<f64>>,
+    max_prefix_length: Option<Option<usize>>,
+    min_prefix_query_length: Option<Option<usize>>,
+    max_query_ngram: Option<Option<usize>>,
+    split_words_enabled: Option<Option<bool>>,
+    split_words_budget: Option<Option<usize>>,
+    max_total_hits: Option<Option<usize>>,
+    proximity_database_enabled: Option<Option<bool>>,
+    max_position_policy: Option<Option<MaxPositionPolicy>>,
+    distinct_attribute: Option<Option<String>>,
+    distinct_mode: Option<Option<DistinctMode>>,
+    search_defaults: Option<Option<SearchDefaults>>,
 }
 
 impl<'a, 't, 'u, 'i> Settings<'a, 't, 'u, 'i> {
@@ -51,10 +79,34 @@ impl<'a, 't, 'u, 'i> Settings<'a, 't, 'u, 'i> {
             chunk_compression_level: None,
             chunk_fusing_shrink_size: None,
             thread_pool: None,
+            deadline: None,
             searchable_fields: None,
             displayed_fields: None,
             faceted_fields: None,
+            sortable_fields: None,
+            auto_filterable_fields: None,
+            stored_only_fields: None,
+            stop_words: None,
+            stop_words_detection: None,
+            attributes_stop_words: None,
+            attributes_languages: None,
+            attributes_weights: None,
+            computed_fields: None,
+            collation_strength: None,
+            facet_string_normalizations: None,
             criteria: None,
+            words_prefixes_threshold: None,
+            max_prefix_length: None,
+            min_prefix_query_length: None,
+            max_query_ngram: None,
+            split_words_enabled: None,
+            split_words_budget: None,
+            max_total_hits: None,
+            proximity_database_enabled: None,
+            max_position_policy: None,
+            distinct_attribute: None,
+            distinct_mode: None,
+            search_defaults: None,
             update_id,
         }
     }
@@ -83,6 +135,136 @@ impl<'a, 't, 'u, 'i> Settings<'a, 't, 'u, 'i> {
         self.faceted_fields = Some(None);
     }
 
+    pub fn set_sortable_fields(&mut self, names: HashSet<String>) {
+        self.sortable_fields = Some(Some(names));
+    }
+
+    pub fn reset_sortable_fields(&mut self) {
+        self.sortable_fields = Some(None);
+    }
+
+    /// Sets the fields from which the numbers found in the text are also indexed as
+    /// integer facet values, e.g. "released in 1995" can be filtered with `year > 1990`.
+    pub fn set_auto_filterable_fields(&mut self, names: HashSet<String>) {
+        self.auto_filterable_fields = Some(Some(names));
+    }
+
+    pub fn reset_auto_filterable_fields(&mut self) {
+        self.auto_filterable_fields = Some(None);
+    }
+
+    /// Sets the fields that are returned in the documents but never tokenized
+    /// nor faceted, a stored-only field can't be faceted.
+    pub fn set_stored_only_fields(&mut self, names: HashSet<String>) {
+        self.stored_only_fields = Some(Some(names));
+    }
+
+    pub fn reset_stored_only_fields(&mut self) {
+        self.stored_only_fields = Some(None);
+    }
+
+    /// Sets the words that are ignored in every attribute and in the queries,
+    /// e.g. "the" and "of" in an english dataset. The documents are indexed again.
+    pub fn set_stop_words(&mut self, stop_words: BTreeSet<String>) {
+        let stop_words = stop_words.iter().map(|w| w.to_lowercase()).collect();
+        self.stop_words = Some(Some(stop_words));
+    }
+
+    pub fn reset_stop_words(&mut self) {
+        self.stop_words = Some(None);
+    }
+
+    /// Adds the stop words candidates of the index to the stop words, the candidates are
+    /// detected before the other settings are applied and are capped by the detection.
+    ///
+    /// Use [`StopWordsDetection::candidates`] to review the candidates without applying them.
+    pub fn detect_stop_words(&mut self, detection: StopWordsDetection) {
+        self.stop_words_detection = Some(detection);
+    }
+
+    /// Sets the stop words that are only ignored in the given attributes, by attribute name,
+    /// e.g. "inc" and "ltd" can be ignored in the company names but not in the descriptions.
+    pub fn set_attributes_stop_words(&mut self, stop_words: HashMap<String, BTreeSet<String>>) {
+        let stop_words = stop_words.into_iter()
+            .map(|(name, words)| (name, words.iter().map(|w| w.to_lowercase()).collect()))
+            .collect();
+        self.attributes_stop_words = Some(Some(stop_words));
+    }
+
+    pub fn reset_attributes_stop_words(&mut self) {
+        self.attributes_stop_words = Some(None);
+    }
+
+    /// Sets the language of the given attributes, by attribute name, e.g. `title_ja` is
+    /// in Japanese (`jpn`), the searches with language hints only match in the attributes
+    /// of these languages and in the attributes without a declared language.
+    ///
+    /// The languages are only used to select the attributes at search time, the tokenizer
+    /// detects the script of the text itself and the documents are not indexed again.
+    pub fn set_attributes_languages(&mut self, languages: HashMap<String, String>) {
+        let languages = languages.into_iter().map(|(name, lang)| (name, lang.to_lowercase())).collect();
+        self.attributes_languages = Some(Some(languages));
+    }
+
+    pub fn reset_attributes_languages(&mut self) {
+        self.attributes_languages = Some(None);
+    }
+
+    /// Sets the weight of the given attributes, by attribute name, e.g. `title: 3` and `body: 1`,
+    /// the attribute and exactness criteria rank the matches in the heaviest attributes first.
+    pub fn set_attributes_weights(&mut self, weights: HashMap<String, u8>) {
+        self.attributes_weights = Some(Some(weights));
+    }
+
+    pub fn reset_attributes_weights(&mut self) {
+        self.attributes_weights = Some(None);
+    }
+
+    /// Sets the fields that are derived from the other fields of the documents at indexing
+    /// time, by field name, a computed field can't be derived from another computed field.
+    pub fn set_computed_fields(&mut self, fields: BTreeMap<String, ComputedField>) {
+        self.computed_fields = Some(Some(fields));
+    }
+
+    pub fn reset_computed_fields(&mut self) {
+        self.computed_fields = Some(None);
+    }
+
+    pub fn set_distinct_attribute(&mut self, name: String) {
+        self.distinct_attribute = Some(Some(name));
+    }
+
+    pub fn reset_distinct_attribute(&mut self) {
+        self.distinct_attribute = Some(None);
+    }
+
+    /// Sets the way the documents with multiple values for the distinct attribute are grouped.
+    pub fn set_distinct_mode(&mut self, mode: DistinctMode) {
+        self.distinct_mode = Some(Some(mode));
+    }
+
+    pub fn reset_distinct_mode(&mut self) {
+        self.distinct_mode = Some(None);
+    }
+
+    pub fn set_collation_strength(&mut self, strength: CollationStrength) {
+        self.collation_strength = Some(Some(strength));
+    }
+
+    pub fn reset_collation_strength(&mut self) {
+        self.collation_strength = Some(None);
+    }
+
+    /// Sets the way the string facet values of the given fields are normalized, the values
+    /// of the other fields are only lowercased. The documents are indexed again.
+    pub fn set_facet_string_normalizations(&mut self, normalizations: HashMap<String, FacetStringNormalization>) {
+        self.facet_string_normalizations = Some(Some(normalizations));
+    }
+
+    pub fn reset_facet_string_normalizations(&mut self) {
+        self.facet_string_normalizations = Some(None);
+    }
+
     pub fn reset_criteria(&mut self) {
         self.criteria = Some(None);
     }
@@ -91,6 +273,172 @@ impl<'a, 't, 'u, 'i> Settings<'a, 't, 'u, 'i> {
         self.criteria = Some(Some(criteria));
     }
 
+    /// Sets the ratio of the words postings a prefix must cover to be part of the words prefixes databases,
+    /// the value is clamped between 0 and 1.
+    pub fn set_words_prefixes_threshold(&mut self, threshold: f64) {
+        self.words_prefixes_threshold = Some(Some(clamp_threshold(threshold)));
+    }
+
+    pub fn reset_words_prefixes_threshold(&mut self) {
+        self.words_prefixes_threshold = Some(None);
+    }
+
+    /// Sets the maximum length in bytes of the prefixes stored in the words prefixes databases,
+    /// the value is clamped between 1 and 25.
+    pub fn set_max_prefix_length(&mut self, length: usize) {
+        self.max_prefix_length = Some(Some(clamp_max_prefix_length(length)));
+    }
+
+    pub fn reset_max_prefix_length(&mut self) {
+        self.max_prefix_length = Some(None);
+    }
+
+    /// Sets the minimum number of characters the last word of a query must contain
+    /// to be considered as a prefix at search time.
+    pub fn set_min_prefix_query_length(&mut self, length: usize) {
+        self.min_prefix_query_length = Some(Some(length));
+    }
+
+    pub fn reset_min_prefix_query_length(&mut self) {
+        self.min_prefix_query_length = Some(None);
+    }
+
+    /// Sets the maximum number of consecutive query words that are concatenated and searched
+    /// as a single word, from 1 to `MAX_NGRAM_LIMIT`. Raising it helps the languages with
+    /// many compound words, e.g. German or Dutch.
+    pub fn set_max_query_ngram(&mut self, max: usize) {
+        self.max_query_ngram = Some(Some(max));
+    }
+
+    pub fn reset_max_query_ngram(&mut self) {
+        self.max_query_ngram = Some(None);
+    }
+
+    /// Sets whether the query words are split in two words at search time, e.g. `heythere`
+    /// matching the documents that contain `hey there`.
+    pub fn set_split_words_enabled(&mut self, enabled: bool) {
+        self.split_words_enabled = Some(Some(enabled));
+    }
+
+    pub fn reset_split_words_enabled(&mut self) {
+        self.split_words_enabled = Some(None);
+    }
+
+    /// Sets the maximum number of ways a query word is split in two words, each of them
+    /// costs two database lookups, the long and rare words are the most expensive ones.
+    pub fn set_split_words_budget(&mut self, budget: usize) {
+        self.split_words_budget = Some(Some(budget));
+    }
+
+    pub fn reset_split_words_budget(&mut self) {
+        self.split_words_budget = Some(None);
+    }
+
+    /// Sets the maximum number of documents a search can rank, the searches that ask
+    /// for documents after this bound (with a big offset or limit) are cut short.
+    pub fn set_max_total_hits(&mut self, max: usize) {
+        self.max_total_hits = Some(Some(max));
+    }
+
+    pub fn reset_max_total_hits(&mut self) {
+        self.max_total_hits = Some(None);
+    }
+
+    /// Defines whether the words pairs proximities database is built, disabling it makes the
+    /// indexation faster and the index smaller but the proximity ranking rule less relevant.
+    pub fn set_proximity_database_enabled(&mut self, enabled: bool) {
+        self.proximity_database_enabled = Some(Some(enabled));
+    }
+
+    pub fn reset_proximity_database_enabled(&mut self) {
+        self.proximity_database_enabled = Some(None);
+    }
+
+    /// Sets what happens to the words that appear after the maximum position of an attribute,
+    /// they are either dropped or indexed at the last position. The documents are indexed again.
+    pub fn set_max_position_policy(&mut self, policy: MaxPositionPolicy) {
+        self.max_position_policy = Some(Some(policy));
+    }
+
+    pub fn reset_max_position_policy(&mut self) {
+        self.max_position_policy = Some(None);
+    }
+
+    /// Sets the search parameters used by the searches that don't define them.
+    pub fn set_search_defaults(&mut self, defaults: SearchDefaults) {
+        self.search_defaults = Some(Some(defaults));
+    }
+
+    pub fn reset_search_defaults(&mut self) {
+        self.search_defaults = Some(None);
+    }
+
+    /// Sets every setting to the value it has in the snapshot, the settings that
+    /// aren't defined in the snapshot are reset.
+    ///
+    /// As everything is done in the same transaction, the settings are
+    /// either all applied or none of them are.
+    pub fn apply_snapshot(&mut self, snapshot: SettingsSnapshot) {
+        let SettingsSnapshot {
+            searchable_fields,
+            displayed_fields,
+            faceted_fields,
+            sortable_fields,
+            auto_filterable_fields,
+            stored_only_fields,
+            stop_words,
+            attributes_stop_words,
+            attributes_languages,
+            attributes_weights,
+            computed_fields,
+            distinct_attribute,
+            distinct_mode,
+            collation_strength,
+            facet_string_normalizations,
+            criteria,
+            words_prefixes_threshold,
+            max_prefix_length,
+            min_prefix_query_length,
+            max_query_ngram,
+            split_words_enabled,
+            split_words_budget,
+            max_total_hits,
+            proximity_database_enabled,
+            max_position_policy,
+            search_defaults,
+        } = snapshot;
+
+        let faceted_fields = faceted_fields.into_iter().map(|(name, ty)| (name, ty.to_string())).collect();
+        let criteria = criteria.iter().map(ToString::to_string).collect();
+
+        self.searchable_fields = Some(searchable_fields);
+        self.displayed_fields = Some(displayed_fields);
+        self.faceted_fields = Some(Some(faceted_fields));
+        self.sortable_fields = Some(Some(sortable_fields.into_iter().collect()));
+        self.auto_filterable_fields = Some(Some(auto_filterable_fields.into_iter().collect()));
+        self.stored_only_fields = Some(Some(stored_only_fields.into_iter().collect()));
+        self.stop_words = Some(Some(stop_words));
+        self.attributes_stop_words = Some(Some(attributes_stop_words));
+        self.attributes_languages = Some(Some(attributes_languages));
+        self.attributes_weights = Some(Some(attributes_weights));
+        self.computed_fields = Some(Some(computed_fields));
+        self.distinct_attribute = Some(distinct_attribute);
+        self.distinct_mode = Some(Some(distinct_mode));
+        self.collation_strength = Some(Some(collation_strength));
+        self.facet_string_normalizations = Some(Some(facet_string_normalizations));
+        self.criteria = Some(Some(criteria));
+        self.words_prefixes_threshold = Some(words_prefixes_threshold);
+        self.max_prefix_length = Some(max_prefix_length);
+        self.min_prefix_query_length = Some(Some(min_prefix_query_length));
+        self.max_query_ngram = Some(Some(max_query_ngram));
+        self.split_words_enabled = Some(Some(split_words_enabled));
+        self.split_words_budget = Some(split_words_budget);
+        self.max_total_hits = Some(max_total_hits);
+        self.proximity_database_enabled = Some(Some(proximity_database_enabled));
+        self.max_position_policy = Some(Some(max_position_policy));
+        self.search_defaults = Some(Some(search_defaults));
+    }
+
     fn reindex<F>(&mut self, cb: &F, old_fields_ids_map: FieldsIdsMap) -> anyhow::Result<()>
     where
         F: Fn(UpdateIndexingStep, u64) + Sync
@@ -140,6 +488,7 @@ impl<'a, 't, 'u, 'i> Settings<'a, 't, 'u, 'i> {
         indexing_builder.chunk_compression_level = self.chunk_compression_level;
         indexing_builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
         indexing_builder.thread_pool = self.thread_pool;
+        indexing_builder.deadline = self.deadline;
         indexing_builder.execute_raw(output, &cb)?;
         Ok(())
     }
@@ -229,6 +578,362 @@ impl<'a, 't, 'u, 'i> Settings<'a, 't, 'u, 'i> {
         Ok(true)
     }
 
+    /// Updates the auto-filterable fields, returns `true` if the documents must be indexed again.
+    ///
+    /// The auto-filterable fields are declared as integer faceted fields, this way the
+    /// filters, the facet distribution and the facet levels handle them like any facet.
+    fn update_auto_filterable_fields(&mut self) -> anyhow::Result<bool> {
+        let old_fields = self.index.auto_filterable_fields(self.wtxn)?;
+        let new_fields = match self.auto_filterable_fields {
+            Some(Some(ref fields)) => fields.clone(),
+            Some(None) => HashSet::new(),
+            // The faceted fields have been replaced, we must declare the auto-filterable fields again.
+            None if self.faceted_fields.is_some() => old_fields.clone(),
+            None => return Ok(false),
+        };
+
+        let explicit_facets = match self.faceted_fields {
+            Some(Some(ref fields)) => fields.keys().cloned().collect(),
+            _otherwise => HashSet::new(),
+        };
+
+        let mut faceted_fields = self.index.faceted_fields(self.wtxn)?;
+        for name in old_fields.difference(&new_fields) {
+            if !explicit_facets.contains(name) {
+                faceted_fields.remove(name);
+            }
+        }
+
+        let mut fields_ids_map = self.index.fields_ids_map(self.wtxn)?;
+        for name in &new_fields {
+            match faceted_fields.get(name) {
+                Some(FacetType::Integer) | None => (),
+                Some(other) => bail!(
+                    "Can't use {:?} as an auto-filterable field as it is a faceted field of type {}.",
+                    name, other,
+                ),
+            }
+            fields_ids_map.insert(name).context("field id limit exceeded")?;
+            faceted_fields.insert(name.clone(), FacetType::Integer);
+        }
+
+        if new_fields.is_empty() {
+            self.index.delete_auto_filterable_fields(self.wtxn)?;
+        } else {
+            self.index.put_auto_filterable_fields(self.wtxn, &new_fields)?;
+        }
+        self.index.put_faceted_fields(self.wtxn, &faceted_fields)?;
+        self.index.put_fields_ids_map(self.wtxn, &fields_ids_map)?;
+
+        Ok(old_fields != new_fields || self.faceted_fields.is_some())
+    }
+
+    fn update_sortable(&mut self) -> anyhow::Result<()> {
+        match self.sortable_fields {
+            Some(Some(ref fields)) => {
+                let faceted_fields = self.index.faceted_fields(&self.wtxn)?;
+                for name in fields {
+                    if !faceted_fields.contains_key(name) {
+                        bail!("Can't use {:?} as a sortable field as it isn't a faceted field.", name);
+                    }
+                }
+                self.index.put_sortable_fields(self.wtxn, fields)?;
+            }
+            Some(None) => { self.index.delete_sortable_fields(self.wtxn)?; }
+            None => (),
+        }
+        Ok(())
+    }
+
+    /// Updates the stored-only fields, returns `true` if the documents must be indexed again.
+    fn update_stored_only_fields(&mut self) -> anyhow::Result<bool> {
+        let updated = match self.stored_only_fields {
+            Some(Some(ref fields)) => { self.index.put_stored_only_fields(self.wtxn, fields)?; true },
+            Some(None) => { self.index.delete_stored_only_fields(self.wtxn)?; true },
+            None => false,
+        };
+
+        // We check this even if only the faceted fields were updated.
+        let stored_only_fields = self.index.stored_only_fields(&self.wtxn)?;
+        for name in self.index.faceted_fields(&self.wtxn)?.keys() {
+            let is_stored_only = stored_only_fields.iter().any(|field| {
+                name == field || name.strip_prefix(field.as_str()).map_or(false, |path| path.starts_with('.'))
+            });
+            if is_stored_only {
+                bail!("Can't use {:?} as a faceted field as it is a stored-only field.", name);
+            }
+        }
+
+        Ok(updated)
+    }
+
+    /// Updates the stop words, returns `true` if they changed and the documents must be indexed again.
+    fn update_stop_words(&mut self) -> anyhow::Result<bool> {
+        let old_stop_words = stop_words_set(self.wtxn, self.index)?;
+        let mut new_stop_words = match self.stop_words {
+            Some(Some(ref stop_words)) => stop_words.clone(),
+            Some(None) => BTreeSet::new(),
+            None if self.stop_words_detection.is_some() => old_stop_words.clone(),
+            None => return Ok(false),
+        };
+
+        if let Some(detection) = self.stop_words_detection {
+            let candidates = detection.candidates(self.wtxn, self.index)?;
+            new_stop_words.extend(candidates.into_iter().map(|candidate| candidate.word));
+        }
+
+        if new_stop_words.is_empty() {
+            self.index.delete_stop_words(self.wtxn)?;
+        } else {
+            let fst = fst::Set::from_iter(&new_stop_words)?;
+            self.index.put_stop_words(self.wtxn, &fst)?;
+        }
+
+        Ok(old_stop_words != new_stop_words)
+    }
+
+    /// Updates the attributes stop words, returns `true` if the documents must be indexed again.
+    fn update_attributes_stop_words(&mut self) -> anyhow::Result<bool> {
+        match self.attributes_stop_words {
+            Some(Some(ref stop_words)) => {
+                let mut fields_ids_map = self.index.fields_ids_map(self.wtxn)?;
+                for name in stop_words.keys() {
+                    fields_ids_map.insert(name).context("field id limit exceeded")?;
+                }
+                self.index.put_attributes_stop_words(self.wtxn, stop_words)?;
+                self.index.put_fields_ids_map(self.wtxn, &fields_ids_map)?;
+            },
+            Some(None) => { self.index.delete_attributes_stop_words(self.wtxn)?; },
+            None => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn update_attributes_languages(&mut self) -> anyhow::Result<()> {
+        match self.attributes_languages {
+            Some(Some(ref languages)) => {
+                let mut fields_ids_map = self.index.fields_ids_map(self.wtxn)?;
+                for name in languages.keys() {
+                    fields_ids_map.insert(name).context("field id limit exceeded")?;
+                }
+                self.index.put_attributes_languages(self.wtxn, languages)?;
+                self.index.put_fields_ids_map(self.wtxn, &fields_ids_map)?;
+            },
+            Some(None) => { self.index.delete_attributes_languages(self.wtxn)?; },
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn update_attributes_weights(&mut self) -> anyhow::Result<()> {
+        match self.attributes_weights {
+            Some(Some(ref weights)) => {
+                let mut fields_ids_map = self.index.fields_ids_map(self.wtxn)?;
+                for name in weights.keys() {
+                    fields_ids_map.insert(name).context("field id limit exceeded")?;
+                }
+                self.index.put_attributes_weights(self.wtxn, weights)?;
+                self.index.put_fields_ids_map(self.wtxn, &fields_ids_map)?;
+            },
+            Some(None) => { self.index.delete_attributes_weights(self.wtxn)?; },
+            None => (),
+        }
+        Ok(())
+    }
+
+    /// Updates the computed fields, returns `true` if the documents must be indexed again.
+    ///
+    /// Note that the values of the computed fields that are removed are kept in the documents.
+    fn update_computed_fields(&mut self) -> anyhow::Result<bool> {
+        match self.computed_fields {
+            Some(Some(ref fields)) => {
+                let primary_key = self.index.primary_key(&self.wtxn)?;
+                let mut fields_ids_map = self.index.fields_ids_map(self.wtxn)?;
+                for (name, computed_field) in fields {
+                    if primary_key == Some(name.as_str()) {
+                        bail!("Can't use {:?} as a computed field as it is the primary key.", name);
+                    }
+                    for source in computed_field.source_fields() {
+                        if fields.contains_key(source) {
+                            bail!("Can't derive {:?} from {:?} as it is a computed field.", name, source);
+                        }
+                    }
+                    fields_ids_map.insert(name).context("field id limit exceeded")?;
+                }
+                self.index.put_computed_fields(self.wtxn, fields)?;
+                self.index.put_fields_ids_map(self.wtxn, &fields_ids_map)?;
+            },
+            Some(None) => { self.index.delete_computed_fields(self.wtxn)?; },
+            None => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn update_distinct_attribute(&mut self) -> anyhow::Result<()> {
+        match self.distinct_attribute {
+            Some(Some(ref name)) => {
+                let faceted_fields = self.index.faceted_fields(&self.wtxn)?;
+                if !faceted_fields.contains_key(name) {
+                    bail!("Can't use {:?} as a distinct attribute as it isn't a faceted field.", name);
+                }
+                self.index.put_distinct_attribute(self.wtxn, name)?;
+            }
+            Some(None) => { self.index.delete_distinct_attribute(self.wtxn)?; }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn update_distinct_mode(&mut self) -> anyhow::Result<()> {
+        match self.distinct_mode {
+            Some(Some(mode)) => self.index.put_distinct_mode(self.wtxn, mode)?,
+            Some(None) => { self.index.delete_distinct_mode(self.wtxn)?; },
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn update_collation_strength(&mut self) -> anyhow::Result<()> {
+        match self.collation_strength {
+            Some(Some(strength)) => self.index.put_collation_strength(self.wtxn, strength)?,
+            Some(None) => { self.index.delete_collation_strength(self.wtxn)?; },
+            None => (),
+        }
+        Ok(())
+    }
+
+    /// Updates the facet string normalizations, returns `true` if they changed
+    /// and the facet values of the documents must be indexed again.
+    fn update_facet_string_normalizations(&mut self) -> anyhow::Result<bool> {
+        let old_normalizations = self.index.facet_string_normalizations(self.wtxn)?;
+        match self.facet_string_normalizations {
+            Some(Some(ref normalizations)) => {
+                let mut fields_ids_map = self.index.fields_ids_map(self.wtxn)?;
+                for name in normalizations.keys() {
+                    fields_ids_map.insert(name).context("field id limit exceeded")?;
+                }
+                self.index.put_facet_string_normalizations(self.wtxn, normalizations)?;
+                self.index.put_fields_ids_map(self.wtxn, &fields_ids_map)?;
+            },
+            Some(None) => { self.index.delete_facet_string_normalizations(self.wtxn)?; },
+            None => return Ok(false),
+        }
+        Ok(old_normalizations != self.index.facet_string_normalizations(self.wtxn)?)
+    }
+
+    /// Updates the words prefixes settings, returns `true` if the
+    /// words prefixes databases must be computed again.
+    fn update_words_prefixes(&mut self) -> anyhow::Result<bool> {
+        let mut updated = false;
+
+        match self.words_prefixes_threshold {
+            Some(Some(threshold)) => self.index.put_words_prefixes_threshold(self.wtxn, threshold)?,
+            Some(None) => { self.index.delete_words_prefixes_threshold(self.wtxn)?; },
+            None => (),
+        }
+        updated |= self.words_prefixes_threshold.is_some();
+
+        match self.max_prefix_length {
+            Some(Some(length)) => self.index.put_max_prefix_length(self.wtxn, length)?,
+            Some(None) => { self.index.delete_max_prefix_length(self.wtxn)?; },
+            None => (),
+        }
+        updated |= self.max_prefix_length.is_some();
+
+        Ok(updated)
+    }
+
+    /// Computes the words prefixes databases again from the current words FST,
+    /// the documents don't need to be indexed again.
+    fn rebuild_words_prefixes(&mut self) -> anyhow::Result<()> {
+        let mut builder = WordsPrefixes::new(self.wtxn, self.index, self.update_id);
+        builder.chunk_compression_type = self.chunk_compression_type;
+        builder.chunk_compression_level = self.chunk_compression_level;
+        builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+        builder.max_nb_chunks = self.max_nb_chunks;
+        builder.max_memory = self.max_memory;
+        builder.execute()
+    }
+
+    fn update_min_prefix_query_length(&mut self) -> anyhow::Result<()> {
+        match self.min_prefix_query_length {
+            Some(Some(length)) => self.index.put_min_prefix_query_length(self.wtxn, length)?,
+            Some(None) => { self.index.delete_min_prefix_query_length(self.wtxn)?; },
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn update_max_query_ngram(&mut self) -> anyhow::Result<()> {
+        match self.max_query_ngram {
+            Some(Some(max)) => {
+                if max == 0 || max > MAX_NGRAM_LIMIT {
+                    bail!("The maximum query ngram must be between 1 and {}, got {}.", MAX_NGRAM_LIMIT, max);
+                }
+                self.index.put_max_query_ngram(self.wtxn, max)?;
+            },
+            Some(None) => { self.index.delete_max_query_ngram(self.wtxn)?; },
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn update_split_words(&mut self) -> anyhow::Result<()> {
+        match self.split_words_enabled {
+            Some(Some(enabled)) => self.index.put_split_words_enabled(self.wtxn, enabled)?,
+            Some(None) => { self.index.delete_split_words_enabled(self.wtxn)?; },
+            None => (),
+        }
+        match self.split_words_budget {
+            Some(Some(budget)) => self.index.put_split_words_budget(self.wtxn, budget)?,
+            Some(None) => { self.index.delete_split_words_budget(self.wtxn)?; },
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn update_max_total_hits(&mut self) -> anyhow::Result<()> {
+        match self.max_total_hits {
+            Some(Some(max)) => self.index.put_max_total_hits(self.wtxn, max)?,
+            Some(None) => { self.index.delete_max_total_hits(self.wtxn)?; },
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn update_search_defaults(&mut self) -> anyhow::Result<()> {
+        match self.search_defaults {
+            Some(Some(ref defaults)) => self.index.put_search_defaults(self.wtxn, defaults)?,
+            Some(None) => { self.index.delete_search_defaults(self.wtxn)?; },
+            None => (),
+        }
+        Ok(())
+    }
+
+    /// Updates the proximity database setting, returns `true` if the documents
+    /// must be indexed again to build or remove the proximity database.
+    fn update_proximity_database_enabled(&mut self) -> anyhow::Result<bool> {
+        let old_enabled = self.index.proximity_database_enabled(self.wtxn)?;
+        match self.proximity_database_enabled {
+            Some(Some(enabled)) => self.index.put_proximity_database_enabled(self.wtxn, enabled)?,
+            Some(None) => { self.index.delete_proximity_database_enabled(self.wtxn)?; },
+            None => return Ok(false),
+        }
+        Ok(old_enabled != self.index.proximity_database_enabled(self.wtxn)?)
+    }
+
+    /// Updates the max position policy, returns `true` if it changed and
+    /// the documents must be indexed again to follow it.
+    fn update_max_position_policy(&mut self) -> anyhow::Result<bool> {
+        let old_policy = self.index.max_position_policy(self.wtxn)?;
+        match self.max_position_policy {
+            Some(Some(policy)) => self.index.put_max_position_policy(self.wtxn, policy)?,
+            Some(None) => { self.index.delete_max_position_policy(self.wtxn)?; },
+            None => return Ok(false),
+        }
+        Ok(old_policy != self.index.max_position_policy(self.wtxn)?)
+    }
+
     fn update_criteria(&mut self) -> anyhow::Result<()> {
         match self.criteria {
             Some(Some(ref fields)) => {
@@ -254,31 +959,196 @@ impl<'a, 't, 'u, 'i> Settings<'a, 't, 'u, 'i> {
             let old_fields_ids_map = self.index.fields_ids_map(&self.wtxn)?;
             self.update_displayed()?;
             let facets_updated = self.update_facets()?;
-            // update_criteria MUST be called after update_facets, since criterion fields must be set
-            // as facets.
+            // update_auto_filterable_fields MUST be called after update_facets,
+            // it declares the auto-filterable fields in the faceted fields.
+            let auto_filterable_updated = self.update_auto_filterable_fields()?;
+            let stored_only_updated = self.update_stored_only_fields()?;
+            let stop_words_updated = self.update_stop_words()?;
+            let attributes_stop_words_updated = self.update_attributes_stop_words()?;
+            self.update_attributes_languages()?;
+            self.update_attributes_weights()?;
+            let computed_fields_updated = self.update_computed_fields()?;
+            // update_sortable, update_distinct_attribute and update_criteria MUST be called
+            // after update_facets, since sortable, distinct and criterion fields must be set as facets.
+            self.update_sortable()?;
+            self.update_distinct_attribute()?;
+            self.update_distinct_mode()?;
+            self.update_collation_strength()?;
+            let normalizations_updated = self.update_facet_string_normalizations()?;
             self.update_criteria()?;
             let searchable_updated = self.update_searchable()?;
-
-            if facets_updated || searchable_updated {
+            let words_prefixes_updated = self.update_words_prefixes()?;
+            self.update_min_prefix_query_length()?;
+            self.update_max_query_ngram()?;
+            self.update_split_words()?;
+            self.update_max_total_hits()?;
+            self.update_search_defaults()?;
+            let proximity_updated = self.update_proximity_database_enabled()?;
+            let max_position_policy_updated = self.update_max_position_policy()?;
+
+            let reindex = facets_updated
+                || auto_filterable_updated
+                || stored_only_updated
+                || stop_words_updated
+                || attributes_stop_words_updated
+                || computed_fields_updated
+                || normalizations_updated
+                || searchable_updated
+                || proximity_updated
+                || max_position_policy_updated;
+
+            if reindex {
+                // The words prefixes databases are computed at the end of the reindexing.
                 self.reindex(&progress_callback, old_fields_ids_map)?;
+            } else if words_prefixes_updated {
+                self.rebuild_words_prefixes()?;
             }
+
+            self.index.refresh_metadata(self.wtxn)?;
             Ok(())
         }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// All the settings of an index, they can be serialized and applied
+/// to another index with [`Settings::apply_snapshot`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsSnapshot {
+    pub searchable_fields: Option<Vec<String>>,
+    pub displayed_fields: Option<Vec<String>>,
+    pub faceted_fields: HashMap<String, FacetType>,
+    pub sortable_fields: BTreeSet<String>,
+    pub auto_filterable_fields: BTreeSet<String>,
+    pub stored_only_fields: BTreeSet<String>,
+    #[serde(default)]
+    pub stop_words: BTreeSet<String>,
+    #[serde(default)]
+    pub attributes_stop_words: HashMap<String, BTreeSet<String>>,
+    #[serde(default)]
+    pub attributes_languages: HashMap<String, String>,
+    #[serde(default)]
+    pub attributes_weights: HashMap<String, u8>,
+    #[serde(default)]
+    pub computed_fields: BTreeMap<String, ComputedField>,
+    pub distinct_attribute: Option<String>,
+    #[serde(default)]
+    pub distinct_mode: DistinctMode,
+    #[serde(default)]
+    pub collation_strength: CollationStrength,
+    #[serde(default)]
+    pub facet_string_normalizations: HashMap<String, FacetStringNormalization>,
+    pub criteria: Vec<Criterion>,
+    pub words_prefixes_threshold: Option<f64>,
+    pub max_prefix_length: Option<usize>,
+    pub min_prefix_query_length: usize,
+    #[serde(default = "default_max_query_ngram")]
+    pub max_query_ngram: usize,
+    #[serde(default = "default_split_words_enabled")]
+    pub split_words_enabled: bool,
+    #[serde(default)]
+    pub split_words_budget: Option<usize>,
+    #[serde(default)]
+    pub max_total_hits: Option<usize>,
+    #[serde(default = "default_proximity_database_enabled")]
+    pub proximity_database_enabled: bool,
+    #[serde(default)]
+    pub max_position_policy: MaxPositionPolicy,
+    pub search_defaults: SearchDefaults,
+}
 
-    use heed::EnvOpenOptions;
-    use maplit::hashmap;
+impl SettingsSnapshot {
+    /// Reads the current settings of the index.
+    pub fn new(rtxn: &heed::RoTxn, index: &Index) -> anyhow::Result<SettingsSnapshot> {
+        let to_strings = |fields: Vec<&str>| -> Vec<String> { fields.into_iter().map(String::from).collect() };
+
+        Ok(SettingsSnapshot {
+            searchable_fields: index.searchable_fields(rtxn)?.map(to_strings),
+            displayed_fields: index.displayed_fields(rtxn)?.map(to_strings),
+            faceted_fields: index.faceted_fields(rtxn)?,
+            sortable_fields: index.sortable_fields(rtxn)?.into_iter().collect(),
+            auto_filterable_fields: index.auto_filterable_fields(rtxn)?.into_iter().collect(),
+            stored_only_fields: index.stored_only_fields(rtxn)?.into_iter().collect(),
+            stop_words: stop_words_set(rtxn, index)?,
+            attributes_stop_words: index.attributes_stop_words(rtxn)?,
+            attributes_languages: index.attributes_languages(rtxn)?,
+            attributes_weights: index.attributes_weights(rtxn)?,
+            computed_fields: index.computed_fields(rtxn)?,
+            distinct_attribute: index.distinct_attribute(rtxn)?.map(String::from),
+            distinct_mode: index.distinct_mode(rtxn)?,
+            collation_strength: index.collation_strength(rtxn)?,
+            facet_string_normalizations: index.facet_string_normalizations(rtxn)?,
+            criteria: index.criteria(rtxn)?,
+            words_prefixes_threshold: index.words_prefixes_threshold(rtxn)?,
+            max_prefix_length: index.max_prefix_length(rtxn)?,
+            min_prefix_query_length: index.min_prefix_query_length(rtxn)?,
+            max_query_ngram: index.max_query_ngram(rtxn)?,
+            split_words_enabled: index.split_words_enabled(rtxn)?,
+            split_words_budget: index.split_words_budget(rtxn)?,
+            max_total_hits: index.max_total_hits(rtxn)?,
+            proximity_database_enabled: index.proximity_database_enabled(rtxn)?,
+            max_position_policy: index.max_position_policy(rtxn)?,
+            search_defaults: index.search_defaults(rtxn)?,
+        })
+    }
 
-    use crate::facet::FacetType;
-    use crate::update::{IndexDocuments, UpdateFormat};
+    /// Returns a hash of these settings that doesn't depend on the iteration
+    /// order of the maps, two equal snapshots have the same hash.
+    pub fn hash(&self) -> serde_json::Result<u64> {
+        let canonical = sort_objects_keys(serde_json::to_value(self)?).to_string();
 
-    #[test]
-    fn set_and_reset_searchable_fields() {
-        let path = tempfile::tempdir().unwrap();
+        // The FNV-1a hash is stable across Rust versions and platforms.
+        Ok(canonical.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        }))
+    }
+}
+
+fn default_max_query_ngram() -> usize {
+    DEFAULT_MAX_NGRAM
+}
+
+fn default_split_words_enabled() -> bool {
+    true
+}
+
+fn default_proximity_database_enabled() -> bool {
+    true
+}
+
+/// Returns the stop words of the index as a set of strings.
+fn stop_words_set(rtxn: &heed::RoTxn, index: &Index) -> anyhow::Result<BTreeSet<String>> {
+    Ok(index.stop_words(rtxn)?.stream().into_strs()?.into_iter().collect())
+}
+
+fn sort_objects_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut entries: Vec<_> = object.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(entries.into_iter().map(|(k, v)| (k, sort_objects_keys(v))).collect())
+        },
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(sort_objects_keys).collect())
+        },
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heed::EnvOpenOptions;
+    use maplit::{btreemap, btreeset, hashmap, hashset};
+
+    use crate::facet::{FacetType, FacetValue};
+    use crate::index::tests::TempIndex;
+    use crate::update::{IndexDocuments, UpdateFormat};
+    use crate::{FacetCondition, TermsMatchingStrategy};
+
+    #[test]
+    fn set_and_reset_searchable_fields() {
+        let path = tempfile::tempdir().unwrap();
         let mut options = EnvOpenOptions::new();
         options.map_size(10 * 1024 * 1024); // 10 MB
         let index = Index::new(options, &path).unwrap();
@@ -482,43 +1352,1165 @@ mod tests {
     }
 
     #[test]
-    fn setting_searchable_recomputes_other_settings() {
+    fn set_sortable_fields() {
         let path = tempfile::tempdir().unwrap();
         let mut options = EnvOpenOptions::new();
         options.map_size(10 * 1024 * 1024); // 10 MB
         let index = Index::new(options, &path).unwrap();
 
-        // Set all the settings except searchable
+        // Set the age and the rank as faceted and sortable fields.
         let mut wtxn = index.write_txn().unwrap();
         let mut builder = Settings::new(&mut wtxn, &index, 0);
-        builder.set_displayed_fields(vec!["hello".to_string()]);
         builder.set_faceted_fields(hashmap!{
             "age".into() => "integer".into(),
-            "toto".into() => "integer".into(),
+            "rank".into() => "integer".into(),
         });
-        builder.set_criteria(vec!["asc(toto)".to_string()]);
+        builder.set_sortable_fields(hashset!{ "age".into(), "rank".into() });
+        builder.execute(|_, _| ()).unwrap();
+
+        // Then index some documents.
+        let content = &b"id,name,age,rank
+0,kevin,23,2
+1,kevina,23,1
+2,benoit,34,3
+"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // Check that the documents are sorted by age then by rank.
+        let rtxn = index.read_txn().unwrap();
+        let sortable_fields = index.sortable_fields(&rtxn).unwrap();
+        assert_eq!(sortable_fields, hashset!{ "age".to_string(), "rank".to_string() });
+        let result = index.search(&rtxn)
+            .sort(vec!["age:desc".parse().unwrap(), "rank:asc".parse().unwrap()])
+            .execute()
+            .unwrap();
+        assert_eq!(result.documents_ids, vec![2, 1, 0]);
+
+        // A field that isn't sortable can't be used to sort.
+        let result = index.search(&rtxn).sort(vec!["name:asc".parse().unwrap()]).execute();
+        assert!(result.is_err());
+        drop(rtxn);
+
+        // A field that isn't faceted can't be declared as sortable.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.set_sortable_fields(hashset!{ "name".into() });
+        assert!(builder.execute(|_, _| ()).is_err());
+    }
+
+    #[test]
+    fn set_auto_filterable_fields() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Set the title as an auto-filterable field.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_auto_filterable_fields(hashset!{ "title".into() });
         builder.execute(|_, _| ()).unwrap();
+
+        // Then index some documents with years in their titles.
+        let content = &b"id,title\n0,released in 1995\n1,released in 1988 then in 2001\n2,never released\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
         wtxn.commit().unwrap();
 
-        // check the output
+        // The title is declared as an integer facet and stays searchable.
         let rtxn = index.read_txn().unwrap();
-        assert_eq!(&["hello"][..], index.displayed_fields(&rtxn).unwrap().unwrap());
-        // since no documents have been pushed the primary key is still unset
-        assert!(index.primary_key(&rtxn).unwrap().is_none());
-        assert_eq!(vec![Criterion::Asc("toto".to_string())], index.criteria(&rtxn).unwrap());
+        assert_eq!(index.faceted_fields(&rtxn).unwrap(), hashmap!{ "title".to_string() => FacetType::Integer });
+        let result = index.search(&rtxn).query("released").execute().unwrap();
+        assert_eq!(result.documents_ids.len(), 3);
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "title > 1990").unwrap();
+        let mut result = index.search(&rtxn).facet_condition(condition).execute().unwrap();
+        result.documents_ids.sort_unstable();
+        assert_eq!(result.documents_ids, vec![0, 1]);
         drop(rtxn);
 
-        // We set toto and age as searchable to force reordering of the fields
+        // An auto-filterable field can't be declared with another facet type.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.set_faceted_fields(hashmap!{ "title".into() => "string".into() });
+        assert!(builder.execute(|_, _| ()).is_err());
+        drop(wtxn);
+
+        // Once reset, the title is no more a faceted field.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 3);
+        builder.reset_auto_filterable_fields();
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert!(index.auto_filterable_fields(&rtxn).unwrap().is_empty());
+        assert!(index.faceted_fields(&rtxn).unwrap().is_empty());
+        assert!(FacetCondition::from_str(&rtxn, &index, "title > 1990").is_err());
+    }
+
+    #[test]
+    fn set_stored_only_fields() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Index some documents with a stored-only description.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_stored_only_fields(hashset!{ "description".into() });
+        builder.execute(|_, _| ()).unwrap();
+        let content = &b"id,name,description\n0,kevin,blob\n1,kevina,blob\n2,benoit,doggo\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // The description is returned in the documents but isn't searchable.
+        let rtxn = index.read_txn().unwrap();
+        assert!(index.word_docids.get(&rtxn, "blob").unwrap().is_none());
+        let result = index.search(&rtxn).query("blob").execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+        let fields_ids_map = index.fields_ids_map(&rtxn).unwrap();
+        let description_id = fields_ids_map.id("description").unwrap();
+        let (_, document) = index.documents(&rtxn, Some(0)).unwrap().pop().unwrap();
+        assert_eq!(document.get(description_id), Some(&br#""blob""#[..]));
+        drop(rtxn);
+
+        // A stored-only field can't be faceted.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.set_faceted_fields(hashmap!{ "description".into() => "string".into() });
+        assert!(builder.execute(|_, _| ()).is_err());
+        drop(wtxn);
+
+        // Once reset, the description is indexed again.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 3);
+        builder.reset_stored_only_fields();
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("blob").execute().unwrap();
+        assert_eq!(result.documents_ids.len(), 2);
+    }
+
+    #[test]
+    fn set_and_detect_stop_words() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Index some documents, "the" is found in most of them.
         let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,title
+0,the cat
+1,the dog
+2,a bird
+3,the bird
+"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
         let mut builder = Settings::new(&mut wtxn, &index, 1);
-        builder.set_searchable_fields(vec!["toto".to_string(), "age".to_string()]);
+        builder.set_stop_words(btreeset!{ "A".into() });
+        builder.detect_stop_words(StopWordsDetection { min_frequency: 0.7, max_stop_words: 10 });
         builder.execute(|_, _| ()).unwrap();
         wtxn.commit().unwrap();
 
+        // The detected and the given stop words are neither indexed nor searched.
         let rtxn = index.read_txn().unwrap();
-        assert_eq!(&["hello"][..], index.displayed_fields(&rtxn).unwrap().unwrap());
-        assert!(index.primary_key(&rtxn).unwrap().is_none());
-        assert_eq!(vec![Criterion::Asc("toto".to_string())], index.criteria(&rtxn).unwrap());
+        let stop_words = stop_words_set(&rtxn, &index).unwrap();
+        assert_eq!(stop_words, btreeset!{ "a".to_string(), "the".to_string() });
+        assert!(index.word_docids.get(&rtxn, "the").unwrap().is_none());
+        let result = index.search(&rtxn).query("the cat").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        drop(rtxn);
+
+        // Once reset, the stop words are indexed again.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.reset_stop_words();
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert!(stop_words_set(&rtxn, &index).unwrap().is_empty());
+        assert_eq!(index.word_documents_count(&rtxn, "the").unwrap(), Some(3));
+    }
+
+    #[test]
+    fn set_attributes_stop_words() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Index some documents, "inc" is a stop word of the company only.
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,company,description\n0,Kevin Inc,kevin\n1,Kevina,the inc company\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_attributes_stop_words(hashmap!{ "company".into() => btreeset!{ "Inc".into() } });
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // Only the document with "inc" in its description matches.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("inc").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1]);
+        assert!(index.docid_word_positions.get(&rtxn, &(0, "inc")).unwrap().is_none());
+    }
+
+    #[test]
+    fn set_attributes_languages() {
+        let content = &b"id,title_en,title_fr\n0,chat room,salon\n1,living room,chat noir\n"[..];
+        let index = TempIndex::from_csv(content);
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_attributes_languages(hashmap!{ "title_en".into() => "ENG".into(), "title_fr".into() => "fra".into() });
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let languages = index.attributes_languages(&rtxn).unwrap();
+        assert_eq!(languages.get("title_en").map(String::as_str), Some("eng"));
+
+        let mut documents_ids = index.search(&rtxn).query("chat").execute().unwrap().documents_ids;
+        documents_ids.sort_unstable();
+        assert_eq!(documents_ids, vec![0, 1]);
+
+        // "chat" is only in the english title of the first document.
+        let result = index.search(&rtxn).query("chat").languages(vec!["eng".into()]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        let result = index.search(&rtxn).query("chat").languages(vec!["FRA".into()]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1]);
+
+        // The searchable attributes of the search are restricted to the languages too.
+        let mut search = index.search(&rtxn);
+        search.query("chat").languages(vec!["eng".into()]).searchable_attributes(&["title_fr"]);
+        assert!(search.execute().unwrap().documents_ids.is_empty());
+    }
+
+    #[test]
+    fn set_computed_fields() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Index some documents then derive the full name from the first and last names.
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,first_name,last_name\n0,kevin,bernard\n1,kevina,dupont\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_computed_fields(btreemap!{
+            "full_name".into() => ComputedField::Concat {
+                fields: vec!["first_name".into(), "last_name".into()],
+                separator: " ".into(),
+            },
+        });
+        builder.execute(|_, _| ()).unwrap();
+
+        // The full name is computed for the new documents too.
+        let content = &b"id,first_name,last_name\n2,benoit,durand\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 2);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let fields_ids_map = index.fields_ids_map(&rtxn).unwrap();
+        let full_name_id = fields_ids_map.id("full_name").unwrap();
+        let external_ids = index.external_documents_ids(&rtxn).unwrap();
+        let docids = vec![external_ids.get("0").unwrap(), external_ids.get("2").unwrap()];
+        let full_names: Vec<_> = index.documents(&rtxn, docids).unwrap()
+            .into_iter()
+            .map(|(_, doc)| doc.get(full_name_id).unwrap().to_vec())
+            .collect();
+        assert_eq!(full_names, vec![br#""kevin bernard""#.to_vec(), br#""benoit durand""#.to_vec()]);
+        drop(rtxn);
+
+        // A computed field can't be derived from another computed field.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 3);
+        builder.set_computed_fields(btreemap!{
+            "full_name".into() => ComputedField::Lowercase { field: "first_name".into() },
+            "code".into() => ComputedField::Lowercase { field: "full_name".into() },
+        });
+        assert!(builder.execute(|_, _| ()).is_err());
+    }
+
+    #[test]
+    fn sort_nulls_placement() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Set the age as a faceted and sortable field.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "age".into() => "integer".into() });
+        builder.set_sortable_fields(hashset!{ "age".into() });
+        builder.execute(|_, _| ()).unwrap();
+
+        // Then index some documents, one of them doesn't have an age.
+        let content = &b"id,name,age
+0,kevin,23
+1,kevina,
+2,benoit,34
+"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // By default the documents without age are returned last.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).sort(vec!["age:asc".parse().unwrap()]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 2, 1]);
+        let result = index.search(&rtxn).sort(vec!["age:desc".parse().unwrap()]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![2, 0, 1]);
+
+        // But they can also be returned first.
+        let result = index.search(&rtxn).sort(vec!["age:asc:nulls_first".parse().unwrap()]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![1, 0, 2]);
+        drop(rtxn);
+    }
+
+    #[test]
+    fn sort_buckets_ranked_by_relevance() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "price".into() => "integer".into() });
+        builder.set_sortable_fields(hashset!{ "price".into() });
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = &b"id,title,price
+0,the quick brown fox,10
+1,quick fox,10
+2,quick fox,20
+3,quik brown fox,10
+"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // The documents with the same price are ordered by the typos then the proximity.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn)
+            .query("quick fox")
+            .sort(vec!["price:asc".parse().unwrap()])
+            .execute()
+            .unwrap();
+        assert_eq!(result.documents_ids, vec![1, 0, 3, 2]);
+
+        // The same goes for a sort ranking rule placed before the relevance rules.
+        drop(rtxn);
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.set_criteria(vec!["desc(price)".into(), "typo".into(), "words".into(), "proximity".into()]);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("quick fox").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![2, 1, 0, 3]);
         drop(rtxn);
+
+        // The query-time sort is applied at the position of the sort ranking rule.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 3);
+        builder.set_criteria(vec!["typo".into(), "words".into(), "sort".into(), "proximity".into()]);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn)
+            .query("quick fox")
+            .sort(vec!["price:asc".parse().unwrap()])
+            .execute()
+            .unwrap();
+        assert_eq!(result.documents_ids, vec![1, 0, 2, 3]);
+    }
+
+    #[test]
+    fn query_criteria_override() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "price".into() => "integer".into() });
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = &b"id,title,price
+0,the quick brown fox,10
+1,quick fox,10
+2,quick fox,20
+3,quik brown fox,10
+"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // The ranking rules of the query are used instead of the ones of the index.
+        let rtxn = index.read_txn().unwrap();
+        let criteria = vec![
+            Criterion::Desc("price".into()),
+            Criterion::Typo { max: None },
+            Criterion::Words,
+            Criterion::Proximity { max: None },
+        ];
+        let result = index.search(&rtxn).query("quick fox").criteria(criteria).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![2, 1, 0, 3]);
+
+        // The other searches still use the ranking rules of the index.
+        let result = index.search(&rtxn).query("quick fox").execute().unwrap();
+        assert_eq!(result.documents_ids[0], 1);
+
+        // The fields of the ranking rules must be faceted.
+        let criteria = vec![Criterion::Asc("title".into())];
+        assert!(index.search(&rtxn).query("quick fox").criteria(criteria).execute().is_err());
+    }
+
+    #[test]
+    fn sort_by_string_facet() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Set the name as a faceted and sortable string field.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "name".into() => "string".into() });
+        builder.set_sortable_fields(hashset!{ "name".into() });
+        builder.set_collation_strength(CollationStrength::Primary);
+        builder.execute(|_, _| ()).unwrap();
+
+        // Then index some documents.
+        let content = "id,name\n0,Zoé\n1,émile\n2,eric\n3,ecole\n".as_bytes();
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // The accents are not significant, "émile" is sorted between "ecole" and "eric".
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.collation_strength(&rtxn).unwrap(), CollationStrength::Primary);
+        let result = index.search(&rtxn).sort(vec!["name:asc".parse().unwrap()]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![3, 1, 2, 0]);
+        let result = index.search(&rtxn).sort(vec!["name:desc".parse().unwrap()]).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 2, 1, 3]);
+        drop(rtxn);
+    }
+
+    #[test]
+    fn facet_string_normalizations() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Set the city as a faceted string field that ignores the case and the diacritics.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "city".into() => "string".into() });
+        let normalization = FacetStringNormalization { ignore_case: true, ignore_diacritics: true };
+        builder.set_facet_string_normalizations(hashmap!{ "city".into() => normalization });
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = r#"[
+            { "id": 0, "city": "Besançon" },
+            { "id": 1, "city": "besancon" },
+            { "id": 2, "city": "Paris" }
+        ]"#.as_bytes();
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Json);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let condition = FacetCondition::from_str(&rtxn, &index, "city = BESANCON").unwrap();
+        let docids = condition.evaluate(&rtxn, &index).unwrap();
+        assert_eq!(docids.iter().collect::<Vec<_>>(), vec![0, 1]);
+
+        // The values are returned as they were first seen in the documents.
+        let facets = index.facets_distribution(&rtxn).facets(&["city"]).execute().unwrap();
+        assert_eq!(facets["city"][&FacetValue::from("Besançon")], 2);
+        assert_eq!(facets["city"][&FacetValue::from("Paris")], 1);
+        drop(rtxn);
+
+        // The case and the diacritics are now significant, the documents are indexed again.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        let normalization = FacetStringNormalization { ignore_case: false, ignore_diacritics: false };
+        builder.set_facet_string_normalizations(hashmap!{ "city".into() => normalization });
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let condition = FacetCondition::from_str(&rtxn, &index, "city = besancon").unwrap();
+        let docids = condition.evaluate(&rtxn, &index).unwrap();
+        assert_eq!(docids.iter().collect::<Vec<_>>(), vec![1]);
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "city = paris").unwrap();
+        assert!(condition.evaluate(&rtxn, &index).unwrap().is_empty());
+        drop(rtxn);
+
+        // Without normalization the values are only lowercased.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 3);
+        builder.reset_facet_string_normalizations();
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let condition = FacetCondition::from_str(&rtxn, &index, "city = PARIS").unwrap();
+        let docids = condition.evaluate(&rtxn, &index).unwrap();
+        assert_eq!(docids.iter().collect::<Vec<_>>(), vec![2]);
+
+        let facets = index.facets_distribution(&rtxn).facets(&["city"]).execute().unwrap();
+        assert_eq!(facets["city"][&FacetValue::from("paris")], 1);
+        drop(rtxn);
+    }
+
+    #[test]
+    fn nested_faceted_fields() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Set the nested age and city as faceted fields and sort by the age.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{
+            "person.age".into() => "integer".into(),
+            "person.address.city".into() => "string".into(),
+        });
+        builder.set_criteria(vec!["asc(person.age)".to_string()]);
+        builder.execute(|_, _| ()).unwrap();
+
+        // Then index some documents with nested objects.
+        let content = &br#"[
+            { "id": 0, "person": { "age": 34, "address": { "city": "Paris" } } },
+            { "id": 1, "person": { "age": 21, "address": { "city": "Lyon" } } },
+            { "id": 2, "person": [{ "age": 25, "address": { "city": "Paris" } }] }
+        ]"#[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Json);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.criteria(&rtxn).unwrap(), vec![Criterion::Asc("person.age".to_string())]);
+
+        let condition = FacetCondition::from_str(&rtxn, &index, "person.address.city = paris").unwrap();
+        let result = index.search(&rtxn).facet_condition(condition).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![2, 0]);
+        drop(rtxn);
+
+        // A dotted path that isn't faceted can't be used as a criterion.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.set_criteria(vec!["asc(person.name)".to_string()]);
+        assert!(builder.execute(|_, _| ()).is_err());
+    }
+
+    #[test]
+    fn set_words_prefixes_settings() {
+        let content = &b"id,name\n0,kevin\n1,kevina\n2,benoit\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        // Every prefix matching at least one of the six words and no longer than 2 bytes.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_words_prefixes_threshold(0.3);
+        builder.set_max_prefix_length(2);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.words_prefixes_threshold(&rtxn).unwrap(), Some(0.3));
+        assert_eq!(index.max_prefix_length(&rtxn).unwrap(), Some(2));
+        let prefixes = index.words_prefixes_fst(&rtxn).unwrap();
+        assert!(prefixes.contains("ke"));
+        assert!(!prefixes.contains("kev"));
+        assert!(index.word_prefix_docids.get(&rtxn, "ke").unwrap().is_some());
+        drop(rtxn);
+
+        // Reseting the settings brings back the defaults.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.reset_words_prefixes_threshold();
+        builder.reset_max_prefix_length();
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.words_prefixes_threshold(&rtxn).unwrap(), None);
+        assert_eq!(index.max_prefix_length(&rtxn).unwrap(), None);
+        // The index is so small that every prefix of at most 4 bytes is cached.
+        assert!(index.words_prefixes_fst(&rtxn).unwrap().contains("kev"));
+        assert!(index.word_prefix_docids.get(&rtxn, "kev").unwrap().is_some());
+    }
+
+    #[test]
+    fn words_prefixes_frequency_admission() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // There is 13 (word, document) pairs, the ids and the names.
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n0,kevin\n1,kevin\n2,kevin\n3,kevin\n4,benoit\n5,bernard\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // A prefix must cover at least 3 pairs.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_words_prefixes_threshold(0.25);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // "ke" matches a single but frequent word, "be" matches two rare words.
+        let rtxn = index.read_txn().unwrap();
+        let prefixes = index.words_prefixes_fst(&rtxn).unwrap();
+        assert!(prefixes.contains("ke"));
+        assert!(prefixes.contains("kev"));
+        assert!(!prefixes.contains("be"));
+        assert!(!prefixes.contains("0"));
+    }
+
+    #[test]
+    fn set_max_query_ngram() {
+        let content = &b"id,name\n0,donaudampfschifffahrt\n1,donau\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        // Only three consecutive words are concatenated by default.
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.max_query_ngram(&rtxn).unwrap(), DEFAULT_MAX_NGRAM);
+        let result = index.search(&rtxn).query("donau dampf schiff fahrt ").optional_words(false).execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_max_query_ngram(4);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.max_query_ngram(&rtxn).unwrap(), 4);
+        let result = index.search(&rtxn).query("donau dampf schiff fahrt ").optional_words(false).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        drop(rtxn);
+
+        // The ngrams can't be bigger than the limit.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.set_max_query_ngram(MAX_NGRAM_LIMIT + 1);
+        assert!(builder.execute(|_, _| ()).is_err());
+    }
+
+    #[test]
+    fn set_split_words() {
+        let content = &b"id,name\n0,hey there\n1,kevin\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("heythere").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_split_words_enabled(false);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert!(!index.split_words_enabled(&rtxn).unwrap());
+        let result = index.search(&rtxn).query("heythere").execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+        drop(rtxn);
+
+        // Only "h eythere" and "he ythere" are explored with a budget of two.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.reset_split_words_enabled();
+        builder.set_split_words_budget(2);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.split_words_budget(&rtxn).unwrap(), Some(2));
+        let result = index.search(&rtxn).query("heythere").execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 3);
+        builder.set_split_words_budget(3);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("heythere").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+    }
+
+    #[test]
+    fn set_min_prefix_query_length() {
+        let content = &b"id,name\n0,kevin\n1,benoit\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("k").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_min_prefix_query_length(3);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // A single character is no more expanded to the words starting with it.
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.min_prefix_query_length(&rtxn).unwrap(), 3);
+        let result = index.search(&rtxn).query("k").execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+        let result = index.search(&rtxn).query("kev").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+    }
+
+    #[test]
+    fn set_search_defaults() {
+        let content = &b"id,name\n0,kevin\n1,kevina\n2,benoit\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_search_defaults(SearchDefaults { limit: Some(1), ..Default::default() });
+        });
+
+        // The limit of the index is used when the search doesn't define one.
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.search_defaults(&rtxn).unwrap().limit, Some(1));
+        let result = index.search(&rtxn).query("kevin").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        let result = index.search(&rtxn).query("kevin").limit(2).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1]);
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.reset_search_defaults();
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.search_defaults(&rtxn).unwrap(), SearchDefaults::default());
+        let result = index.search(&rtxn).query("kevin").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 1]);
+        drop(rtxn);
+
+        // The terms matching strategy of the index is used when the search doesn't define one.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 3);
+        let strategy = Some(TermsMatchingStrategy::All);
+        builder.set_search_defaults(SearchDefaults { terms_matching_strategy: strategy, ..Default::default() });
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("kevin benoit").execute().unwrap();
+        assert!(result.documents_ids.is_empty());
+        let result = index.search(&rtxn).query("kevin benoit").optional_words(true).execute().unwrap();
+        assert!(!result.documents_ids.is_empty());
+    }
+
+    #[test]
+    fn disable_proximity_database() {
+        let content = &b"id,name\n0,kevin le grand\n1,le grand benoit\n"[..];
+        let index = TempIndex::from_csv(content);
+
+        let rtxn = index.read_txn().unwrap();
+        assert!(index.word_pair_proximity_docids.iter(&rtxn).unwrap().next().is_some());
+        drop(rtxn);
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_proximity_database_enabled(false);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // The database is no more built but the phrases still
+        // match the documents containing all of their words.
+        let rtxn = index.read_txn().unwrap();
+        assert!(!index.proximity_database_enabled(&rtxn).unwrap());
+        assert!(index.word_pair_proximity_docids.iter(&rtxn).unwrap().next().is_none());
+        let result = index.search(&rtxn).query(r#""le grand""#).execute().unwrap();
+        assert_eq!(result.documents_ids.len(), 2);
+        let result = index.search(&rtxn).query("kevin grand").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0]);
+        drop(rtxn);
+
+        // Adding documents doesn't fill the database either.
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n2,tamo le grand\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 2);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert!(index.word_pair_proximity_docids.iter(&rtxn).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn distinct_facet_distribution() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Set the product and the color as faceted and group the variants by product.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{
+            "product".into() => "string".into(),
+            "color".into() => "string".into(),
+        });
+        builder.set_distinct_attribute("product".into());
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = &b"id,product,color\n0,shirt,red\n1,shirt,red\n2,shirt,blue\n3,pants,red\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.distinct_attribute(&rtxn).unwrap(), Some("product"));
+
+        let facets = index.facets_distribution(&rtxn).facets(&["color"]).execute().unwrap();
+        assert_eq!(facets["color"][&FacetValue::from("red")], 3);
+        assert_eq!(facets["color"][&FacetValue::from("blue")], 1);
+
+        // The two red shirts are only counted once.
+        let facets = index.facets_distribution(&rtxn).facets(&["color"]).distinct(true).execute().unwrap();
+        assert_eq!(facets["color"][&FacetValue::from("red")], 2);
+        assert_eq!(facets["color"][&FacetValue::from("blue")], 1);
+        drop(rtxn);
+
+        // The distinct attribute must be a faceted field.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.set_distinct_attribute("id".into());
+        assert!(builder.execute(|_, _| ()).is_err());
+    }
+
+    #[test]
+    fn set_criteria_with_parameters() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_criteria(vec!["typo(max=1)".into(), "words".into(), "proximity(max=3)".into()]);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let criteria = index.criteria(&rtxn).unwrap();
+        assert_eq!(criteria, vec![
+            Criterion::Typo { max: Some(1) },
+            Criterion::Words,
+            Criterion::Proximity { max: Some(3) },
+        ]);
+        let names: Vec<_> = criteria.iter().map(ToString::to_string).collect();
+        assert_eq!(names, vec!["typo(max=1)", "words", "proximity(max=3)"]);
+        drop(rtxn);
+
+        // The parameters must be valid.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_criteria(vec!["typo(max=one)".into()]);
+        assert!(builder.execute(|_, _| ()).is_err());
+    }
+
+    #[test]
+    fn exactness_criterion() {
+        let content = &b"id,name\n0,hello worlds\n1,the hello world\n2,hello world\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_criteria(vec!["words".into(), "exactness".into()]);
+        });
+
+        // The document equal to the query is returned first, then the one
+        // that contains it and finally the one that only matches by prefix.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).query("hello world").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn facet_values_iterator() {
+        let content = &b"id,color,size\n0,red,32\n1,red,34\n2,blue,32\n3,green,36\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_faceted_fields(hashmap!{
+                "color".into() => "string".into(),
+                "size".into() => "integer".into(),
+            });
+        });
+
+        let rtxn = index.read_txn().unwrap();
+        let values: Vec<_> = index.facet_values(&rtxn, "color").unwrap()
+            .collect::<heed::Result<_>>().unwrap();
+        assert_eq!(values, vec![
+            (FacetValue::from("blue"), 1),
+            (FacetValue::from("green"), 1),
+            (FacetValue::from("red"), 2),
+        ]);
+
+        let values: Vec<_> = index.facet_values(&rtxn, "size").unwrap()
+            .collect::<heed::Result<_>>().unwrap();
+        assert_eq!(values, vec![
+            (FacetValue::from(32i64), 2),
+            (FacetValue::from(34i64), 1),
+            (FacetValue::from(36i64), 1),
+        ]);
+
+        // Only the values of the candidates are returned.
+        let candidates = (0..2).collect();
+        let values: Vec<_> = index.facet_values(&rtxn, "size").unwrap()
+            .candidates(candidates)
+            .collect::<heed::Result<_>>().unwrap();
+        assert_eq!(values, vec![(FacetValue::from(32i64), 1), (FacetValue::from(34i64), 1)]);
+
+        // The field must be faceted.
+        assert!(index.facet_values(&rtxn, "id").is_err());
+    }
+
+    #[test]
+    fn distinct_search() {
+        let content = &b"id,product,color\n0,shirt,red\n1,shirt,red\n2,shirt,blue\n3,pants,red\n"[..];
+        let index = TempIndex::from_csv_with_settings(content, |builder| {
+            builder.set_faceted_fields(hashmap!{
+                "product".into() => "string".into(),
+                "color".into() => "string".into(),
+            });
+            builder.set_distinct_attribute("product".into());
+        });
+
+        // Only the first document of each product is returned.
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 3]);
+        let result = index.search(&rtxn).offset(1).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![3]);
+
+        // The distinct attribute of the query overrides the one of the index.
+        let result = index.search(&rtxn).distinct("color").execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 2]);
+
+        // The distinct attribute of the query must be a faceted field.
+        assert!(index.search(&rtxn).distinct("id").execute().is_err());
+    }
+
+    #[test]
+    fn multi_valued_distinct_search() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_faceted_fields(hashmap!{ "tags".into() => "string".into() });
+        builder.set_distinct_attribute("tags".into());
+        builder.execute(|_, _| ()).unwrap();
+
+        let content = &br#"[
+            { "id": 0, "tags": ["b", "a"] },
+            { "id": 1, "tags": ["a", "b"] },
+            { "id": 2, "tags": ["a"] },
+            { "id": 3, "tags": ["b"] },
+            { "id": 4 }
+        ]"#[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 1);
+        builder.update_format(UpdateFormat::Json);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // By default the documents belong to the group of their smallest value.
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(index.distinct_mode(&rtxn).unwrap(), DistinctMode::FirstValue);
+        let result = index.search(&rtxn).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 3, 4]);
+        drop(rtxn);
+
+        // The documents with the same set of values are grouped together.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 2);
+        builder.set_distinct_mode(DistinctMode::AllValues);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let result = index.search(&rtxn).execute().unwrap();
+        assert_eq!(result.documents_ids, vec![0, 2, 3, 4]);
+    }
+
+    #[test]
+    fn setting_searchable_recomputes_other_settings() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Set all the settings except searchable
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_displayed_fields(vec!["hello".to_string()]);
+        builder.set_faceted_fields(hashmap!{
+            "age".into() => "integer".into(),
+            "toto".into() => "integer".into(),
+        });
+        builder.set_criteria(vec!["asc(toto)".to_string()]);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        // check the output
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(&["hello"][..], index.displayed_fields(&rtxn).unwrap().unwrap());
+        // since no documents have been pushed the primary key is still unset
+        assert!(index.primary_key(&rtxn).unwrap().is_none());
+        assert_eq!(vec![Criterion::Asc("toto".to_string())], index.criteria(&rtxn).unwrap());
+        drop(rtxn);
+
+        // We set toto and age as searchable to force reordering of the fields
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 1);
+        builder.set_searchable_fields(vec!["toto".to_string(), "age".to_string()]);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        assert_eq!(&["hello"][..], index.displayed_fields(&rtxn).unwrap().unwrap());
+        assert!(index.primary_key(&rtxn).unwrap().is_none());
+        assert_eq!(vec![Criterion::Asc("toto".to_string())], index.criteria(&rtxn).unwrap());
+        drop(rtxn);
+    }
+
+    #[test]
+    fn settings_snapshot_and_restore() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // Define some settings on the first index.
+        let mut wtxn = index.write_txn().unwrap();
+        let mut builder = Settings::new(&mut wtxn, &index, 0);
+        builder.set_searchable_fields(vec!["name".into()]);
+        builder.set_displayed_fields(vec!["name".into(), "age".into()]);
+        builder.set_faceted_fields(hashmap!{ "age".into() => "integer".into() });
+        builder.set_sortable_fields(hashset!{ "age".into() });
+        builder.set_distinct_attribute("age".into());
+        builder.set_collation_strength(CollationStrength::Secondary);
+        builder.set_criteria(vec!["typo(max=1)".into(), "wordsposition".into(), "desc(age)".into()]);
+        builder.set_max_prefix_length(2);
+        builder.set_proximity_database_enabled(false);
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let snapshot = SettingsSnapshot::new(&rtxn, &index).unwrap();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: SettingsSnapshot = serde_json::from_str(&json).unwrap();
+        drop(rtxn);
+
+        // The second index contains documents and a setting that isn't part of the snapshot.
+        let other_path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let other_index = Index::new(options, &other_path).unwrap();
+        let mut wtxn = other_index.write_txn().unwrap();
+        let content = &b"id,name,age\n0,kevin,23\n1,kevina,21\n2,benoit,34\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &other_index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        let mut builder = Settings::new(&mut wtxn, &other_index, 1);
+        builder.set_words_prefixes_threshold(0.5);
+        builder.execute(|_, _| ()).unwrap();
+
+        let mut builder = Settings::new(&mut wtxn, &other_index, 2);
+        builder.apply_snapshot(snapshot.clone());
+        builder.execute(|_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = other_index.read_txn().unwrap();
+        assert_eq!(SettingsSnapshot::new(&rtxn, &other_index).unwrap(), snapshot);
+        assert_eq!(SettingsSnapshot::new(&rtxn, &other_index).unwrap().hash().unwrap(), snapshot.hash().unwrap());
+        assert_eq!(other_index.words_prefixes_threshold(&rtxn).unwrap(), None);
+        let fields_ids_map = other_index.fields_ids_map(&rtxn).unwrap();
+        let age_id = fields_ids_map.id("age").unwrap();
+        assert_eq!(other_index.faceted_documents_ids(&rtxn, age_id).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn settings_snapshot_missing_fields() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        // The snapshots written before these settings existed are read with their defaults.
+        let rtxn = index.read_txn().unwrap();
+        let snapshot = SettingsSnapshot::new(&rtxn, &index).unwrap();
+        let mut json = serde_json::to_value(&snapshot).unwrap();
+        let object = json.as_object_mut().unwrap();
+        for key in &[
+            "attributesStopWords",
+            "attributesLanguages",
+            "computedFields",
+            "distinctMode",
+            "collationStrength",
+            "facetStringNormalizations",
+            "proximityDatabaseEnabled",
+        ] {
+            assert!(object.remove(*key).is_some(), "{} is not part of the snapshot", key);
+        }
+
+        let old_snapshot: SettingsSnapshot = serde_json::from_value(json).unwrap();
+        assert_eq!(old_snapshot, snapshot);
     }
 }
@@ -0,0 +1,105 @@
+use heed::RoTxn;
+use serde::{Deserialize, Serialize};
+
+use crate::Index;
+
+const DEFAULT_MIN_FREQUENCY: f64 = 0.5; // 50%
+const DEFAULT_MAX_STOP_WORDS: usize = 32;
+
+/// Defines which words of an index are proposed as stop words, a word is a candidate
+/// when it is found in a too big part of the documents to help finding any of them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct StopWordsDetection {
+    /// The minimum ratio of the documents a word must be found in, between 0 and 1.
+    pub min_frequency: f64,
+    /// The maximum number of candidates, the most frequent words are kept.
+    pub max_stop_words: usize,
+}
+
+impl Default for StopWordsDetection {
+    fn default() -> StopWordsDetection {
+        StopWordsDetection {
+            min_frequency: DEFAULT_MIN_FREQUENCY,
+            max_stop_words: DEFAULT_MAX_STOP_WORDS,
+        }
+    }
+}
+
+/// A word that is proposed as a stop word, along with the statistics that explain why.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopWordCandidate {
+    pub word: String,
+    /// The number of documents containing the word.
+    pub documents_count: u64,
+    /// The ratio of the documents of the index containing the word.
+    pub frequency: f64,
+}
+
+impl StopWordsDetection {
+    /// Returns the stop words candidates of the index, the most frequent first, they can be
+    /// reviewed and applied with [`Settings::set_stop_words`](crate::update::Settings::set_stop_words).
+    ///
+    /// Only the number of documents of the words is read, the bitmaps are never deserialized.
+    pub fn candidates(&self, rtxn: &RoTxn, index: &Index) -> anyhow::Result<Vec<StopWordCandidate>> {
+        let documents_count = index.number_of_documents(rtxn)?;
+        if documents_count == 0 || self.max_stop_words == 0 {
+            return Ok(Vec::new());
+        }
+
+        let min_frequency = self.min_frequency.max(0.0).min(1.0);
+        let mut candidates = Vec::new();
+        for result in index.words_documents_count(rtxn)? {
+            let (word, count) = result?;
+            let frequency = count as f64 / documents_count as f64;
+            if frequency >= min_frequency {
+                let word = word.to_string();
+                candidates.push(StopWordCandidate { word, documents_count: count, frequency });
+            }
+        }
+
+        candidates.sort_by(|a, b| {
+            b.documents_count.cmp(&a.documents_count).then_with(|| a.word.cmp(&b.word))
+        });
+        candidates.truncate(self.max_stop_words);
+
+        Ok(candidates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heed::EnvOpenOptions;
+
+    use crate::update::{IndexDocuments, UpdateFormat};
+
+    #[test]
+    fn frequent_words_are_candidates() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,title\n0,the cat\n1,the dog\n2,a bird\n3,the bird\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        let detection = StopWordsDetection { min_frequency: 0.5, max_stop_words: 10 };
+        let candidates = detection.candidates(&rtxn, &index).unwrap();
+        let words: Vec<_> = candidates.iter().map(|c| (c.word.as_str(), c.documents_count)).collect();
+        assert_eq!(words, vec![("the", 3), ("bird", 2)]);
+        assert_eq!(candidates[0].frequency, 0.75);
+
+        let detection = StopWordsDetection { min_frequency: 0.5, max_stop_words: 1 };
+        let candidates = detection.candidates(&rtxn, &index).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].word, "the");
+    }
+}
@@ -1,8 +1,10 @@
+use std::time::{Duration, Instant};
+
 use grenad::CompressionType;
 use rayon::ThreadPool;
 
 use crate::Index;
-use super::{ClearDocuments, DeleteDocuments, IndexDocuments, Settings, Facets, WordsPrefixes};
+use super::{ClearDocuments, CompactDocumentsIds, DeleteDocuments, IndexDocuments, Settings, Facets, WordsPrefixes, Maintenance, MergeIndex};
 
 pub struct UpdateBuilder<'a> {
     pub(crate) log_every_n: Option<usize>,
@@ -13,6 +15,7 @@ pub struct UpdateBuilder<'a> {
     pub(crate) chunk_compression_level: Option<u32>,
     pub(crate) chunk_fusing_shrink_size: Option<u64>,
     pub(crate) thread_pool: Option<&'a ThreadPool>,
+    pub(crate) max_duration: Option<Duration>,
     pub(crate) update_id: u64,
 }
 
@@ -27,6 +30,7 @@ impl<'a> UpdateBuilder<'a> {
             chunk_compression_level: None,
             chunk_fusing_shrink_size: None,
             thread_pool: None,
+            max_duration: None,
             update_id,
         }
     }
@@ -63,6 +67,13 @@ impl<'a> UpdateBuilder<'a> {
         self.thread_pool = Some(thread_pool);
     }
 
+    /// The maximum time the documents additions and the settings updates can take,
+    /// counted from the creation of their builder, they return a `DeadlineExceeded`
+    /// error once it is reached and the write transaction must then be aborted.
+    pub fn max_duration(&mut self, max_duration: Duration) {
+        self.max_duration = Some(max_duration);
+    }
+
     pub fn clear_documents<'t, 'u, 'i>(
         self,
         wtxn: &'t mut heed::RwTxn<'i, 'u>,
@@ -97,6 +108,7 @@ impl<'a> UpdateBuilder<'a> {
         builder.chunk_compression_level = self.chunk_compression_level;
         builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
         builder.thread_pool = self.thread_pool;
+        builder.deadline = self.max_duration.map(|duration| Instant::now() + duration);
 
         builder
     }
@@ -109,6 +121,27 @@ impl<'a> UpdateBuilder<'a> {
     {
         let mut builder = Settings::new(wtxn, index, self.update_id);
 
+        builder.log_every_n = self.log_every_n;
+        builder.max_nb_chunks = self.max_nb_chunks;
+        builder.max_memory = self.max_memory;
+        builder.linked_hash_map_size = self.linked_hash_map_size;
+        builder.chunk_compression_type = self.chunk_compression_type;
+        builder.chunk_compression_level = self.chunk_compression_level;
+        builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+        builder.thread_pool = self.thread_pool;
+        builder.deadline = self.max_duration.map(|duration| Instant::now() + duration);
+
+        builder
+    }
+
+    pub fn compact_documents_ids<'t, 'u, 'i>(
+        self,
+        wtxn: &'t mut heed::RwTxn<'i, 'u>,
+        index: &'i Index,
+    ) -> CompactDocumentsIds<'a, 't, 'u, 'i>
+    {
+        let mut builder = CompactDocumentsIds::new(wtxn, index, self.update_id);
+
         builder.log_every_n = self.log_every_n;
         builder.max_nb_chunks = self.max_nb_chunks;
         builder.max_memory = self.max_memory;
@@ -150,4 +183,41 @@ impl<'a> UpdateBuilder<'a> {
 
         builder
     }
+
+    pub fn maintenance<'t, 'u, 'i>(
+        self,
+        wtxn: &'t mut heed::RwTxn<'i, 'u>,
+        index: &'i Index,
+    ) -> Maintenance<'a, 't, 'u, 'i>
+    {
+        let mut builder = Maintenance::new(wtxn, index, self.update_id);
+
+        builder.log_every_n = self.log_every_n;
+        builder.max_nb_chunks = self.max_nb_chunks;
+        builder.max_memory = self.max_memory;
+        builder.linked_hash_map_size = self.linked_hash_map_size;
+        builder.chunk_compression_type = self.chunk_compression_type;
+        builder.chunk_compression_level = self.chunk_compression_level;
+        builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+        builder.thread_pool = self.thread_pool;
+
+        builder
+    }
+
+    pub fn merge_index<'t, 'u, 'i, 's>(
+        self,
+        wtxn: &'t mut heed::RwTxn<'i, 'u>,
+        index: &'i Index,
+        source: &'s Index,
+        source_rtxn: &'s heed::RoTxn<'s>,
+    ) -> MergeIndex<'t, 'u, 'i, 's>
+    {
+        let mut builder = MergeIndex::new(wtxn, index, source, source_rtxn, self.update_id);
+
+        builder.chunk_compression_type = self.chunk_compression_type;
+        builder.chunk_compression_level = self.chunk_compression_level;
+        builder.chunk_fusing_shrink_size = self.chunk_fusing_shrink_size;
+
+        builder
+    }
 }
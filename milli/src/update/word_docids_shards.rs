@@ -0,0 +1,228 @@
+use std::str;
+
+use chrono::Utc;
+use fst::Streamer;
+use heed::types::{ByteSlice, DecodeIgnore};
+use roaring::RoaringBitmap;
+
+use crate::heed_codec::RoaringBitmapLenCodec;
+use crate::index::WORD_DOCIDS_SHARD_BITS;
+use crate::Index;
+
+const DEFAULT_THRESHOLD: u64 = 100_000;
+
+/// Splits the documents ids of the words that appear in a lot of documents
+/// into shards of contiguous documents ids ranges.
+///
+/// Intersecting the documents ids of such a word with a small set of candidates
+/// then only requires to deserialize the shards the candidates are part of.
+pub struct WordDocidsShards<'t, 'u, 'i> {
+    wtxn: &'t mut heed::RwTxn<'i, 'u>,
+    index: &'i Index,
+    threshold: Option<u64>,
+    words: Option<fst::Set<Vec<u8>>>,
+    _update_id: u64,
+}
+
+impl<'t, 'u, 'i> WordDocidsShards<'t, 'u, 'i> {
+    pub fn new(
+        wtxn: &'t mut heed::RwTxn<'i, 'u>,
+        index: &'i Index,
+        update_id: u64,
+    ) -> WordDocidsShards<'t, 'u, 'i>
+    {
+        WordDocidsShards { wtxn, index, threshold: None, words: None, _update_id: update_id }
+    }
+
+    /// Set the number of documents a word must appear in to have its documents ids sharded.
+    ///
+    /// Default value is `100_000` documents.
+    pub fn threshold(&mut self, value: u64) -> &mut Self {
+        self.threshold = Some(value.max(1));
+        self
+    }
+
+    /// Only computes the shards of these words again, e.g. the words of the documents
+    /// of an addition, the shards of the other words are kept as they are.
+    ///
+    /// Default is to compute the shards of all the words again.
+    pub fn words(&mut self, words: fst::Set<Vec<u8>>) -> &mut Self {
+        self.words = Some(words);
+        self
+    }
+
+    pub fn execute(self) -> anyhow::Result<()> {
+        self.index.set_updated_at(self.wtxn, &Utc::now())?;
+        let threshold = self.threshold.unwrap_or(DEFAULT_THRESHOLD);
+
+        if let Some(words) = self.words {
+            let mut stream = words.stream();
+            while let Some(word) = stream.next() {
+                let word = str::from_utf8(word)?;
+                delete_word_shards(self.wtxn, self.index, word)?;
+                let db = self.index.word_docids.remap_data_type::<RoaringBitmapLenCodec>();
+                if db.get(self.wtxn, word)?.map_or(false, |count| count >= threshold) {
+                    write_word_shards(self.wtxn, self.index, word)?;
+                }
+            }
+            return Ok(());
+        }
+
+        // Clear the shards, they are entirely computed again.
+        self.index.word_docids_shards.clear(self.wtxn)?;
+
+        // We first retrieve the frequent words without deserializing the bitmaps,
+        // we can't write into the shards database while iterating over the words.
+        let mut frequent_words = Vec::new();
+        let db = self.index.word_docids.remap_data_type::<RoaringBitmapLenCodec>();
+        for result in db.iter(self.wtxn)? {
+            let (word, count) = result?;
+            if count >= threshold {
+                frequent_words.push(word.to_string());
+            }
+        }
+
+        for word in frequent_words {
+            write_word_shards(self.wtxn, self.index, &word)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Deletes all the shards of the word.
+fn delete_word_shards(wtxn: &mut heed::RwTxn, index: &Index, word: &str) -> heed::Result<()> {
+    // The word is followed by a zero byte in the keys, the longer words are skipped.
+    let mut prefix = Vec::with_capacity(word.len() + 1);
+    prefix.extend_from_slice(word.as_bytes());
+    prefix.push(0);
+
+    let shards = index.word_docids_shards.remap_types::<ByteSlice, DecodeIgnore>();
+    let mut iter = shards.prefix_iter_mut(wtxn, &prefix)?;
+    while iter.next().transpose()?.is_some() {
+        iter.del_current()?;
+    }
+
+    Ok(())
+}
+
+/// Splits the documents ids of the word into its shards.
+fn write_word_shards(wtxn: &mut heed::RwTxn, index: &Index, word: &str) -> heed::Result<()> {
+    let docids = match index.word_docids.get(wtxn, word)? {
+        Some(docids) => docids,
+        None => return Ok(()),
+    };
+
+    let mut shard_start = None;
+    let mut shard = RoaringBitmap::new();
+    for id in docids {
+        let start = id >> WORD_DOCIDS_SHARD_BITS << WORD_DOCIDS_SHARD_BITS;
+        if shard_start != Some(start) {
+            if let Some(previous) = shard_start {
+                index.word_docids_shards.put(wtxn, &(word, previous), &shard)?;
+                shard.clear();
+            }
+            shard_start = Some(start);
+        }
+        shard.insert(id);
+    }
+
+    if let Some(start) = shard_start {
+        index.word_docids_shards.put(wtxn, &(word, start), &shard)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::FromIterator;
+
+    use heed::EnvOpenOptions;
+
+    use crate::update::{IndexDocuments, UpdateFormat};
+    use super::*;
+
+    #[test]
+    fn shard_frequent_words() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n1,kevin\n2,kevina\n3,kevin\n4,benoit\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+
+        let mut builder = WordDocidsShards::new(&mut wtxn, &index, 1);
+        builder.threshold(2);
+        builder.execute().unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.read_txn().unwrap();
+        // Only "kevin" appears in enough documents to be sharded.
+        let shards: Vec<_> = index.word_docids_shards.iter(&rtxn).unwrap()
+            .map(|result| result.unwrap())
+            .map(|((word, start), docids)| (word.to_string(), start, docids))
+            .collect();
+        let docids = RoaringBitmap::from_iter(vec![0, 2]);
+        assert_eq!(shards, vec![(String::from("kevin"), 0, docids)]);
+
+        let candidates = RoaringBitmap::from_iter(vec![1, 2, 3]);
+        let docids = index.word_docids_within(&rtxn, "kevin", &candidates).unwrap();
+        assert_eq!(docids, Some(RoaringBitmap::from_iter(vec![2])));
+        let docids = index.word_docids_within(&rtxn, "kevina", &candidates).unwrap();
+        assert_eq!(docids, Some(RoaringBitmap::from_iter(vec![1])));
+        let docids = index.word_docids_within(&rtxn, "missing", &candidates).unwrap();
+        assert_eq!(docids, None);
+    }
+
+    #[test]
+    fn shard_given_words() {
+        let path = tempfile::tempdir().unwrap();
+        let mut options = EnvOpenOptions::new();
+        options.map_size(10 * 1024 * 1024); // 10 MB
+        let index = Index::new(options, &path).unwrap();
+
+        let mut wtxn = index.write_txn().unwrap();
+        let content = &b"id,name\n1,kevin\n2,kevina\n3,kevin\n4,benoit\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 0);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+
+        let mut builder = WordDocidsShards::new(&mut wtxn, &index, 1);
+        builder.threshold(2);
+        builder.execute().unwrap();
+
+        // "kevina" and "benoit" are now frequent but only the shards of "kevina" are computed.
+        let content = &b"id,name\n5,kevina\n6,benoit\n"[..];
+        let mut builder = IndexDocuments::new(&mut wtxn, &index, 2);
+        builder.update_format(UpdateFormat::Csv);
+        builder.execute(content, |_, _| ()).unwrap();
+
+        let mut builder = WordDocidsShards::new(&mut wtxn, &index, 3);
+        builder.threshold(2);
+        builder.words(fst::Set::from_iter(vec!["kevina"]).unwrap());
+        builder.execute().unwrap();
+
+        let words = |wtxn: &heed::RwTxn| -> Vec<String> {
+            let mut words: Vec<_> = index.word_docids_shards.iter(wtxn).unwrap()
+                .map(|result| result.unwrap().0 .0.to_string())
+                .collect();
+            words.dedup();
+            words
+        };
+        assert_eq!(words(&wtxn), vec![String::from("kevin"), String::from("kevina")]);
+
+        // The shards of the given words that are no more frequent are removed.
+        let mut builder = WordDocidsShards::new(&mut wtxn, &index, 4);
+        builder.threshold(3);
+        builder.words(fst::Set::from_iter(vec!["kevin", "kevina"]).unwrap());
+        builder.execute().unwrap();
+        assert!(words(&wtxn).is_empty());
+
+        wtxn.commit().unwrap();
+    }
+}
@@ -9,11 +9,15 @@ use heed::BytesEncode;
 use heed::types::ByteSlice;
 
 use crate::heed_codec::StrStrU8Codec;
+use crate::storage::IndexDatabase;
 use crate::update::index_documents::WriteMethod;
-use crate::update::index_documents::{create_sorter, sorter_into_lmdb_database};
+use crate::update::index_documents::{create_sorter, sorter_into_storage};
 use crate::update::index_documents::{word_docids_merge, words_pairs_proximities_docids_merge};
 use crate::{Index, SmallString32};
 
+const DEFAULT_THRESHOLD: f64 = 0.1 / 100.0; // .1%
+const DEFAULT_MAX_PREFIX_LENGTH: usize = 4;
+
 pub struct WordsPrefixes<'t, 'u, 'i> {
     wtxn: &'t mut heed::RwTxn<'i, 'u>,
     index: &'i Index,
@@ -22,8 +26,8 @@ pub struct WordsPrefixes<'t, 'u, 'i> {
     pub(crate) chunk_fusing_shrink_size: Option<u64>,
     pub(crate) max_nb_chunks: Option<usize>,
     pub(crate) max_memory: Option<usize>,
-    threshold: f64,
-    max_prefix_length: usize,
+    threshold: Option<f64>,
+    max_prefix_length: Option<usize>,
     _update_id: u64,
 }
 
@@ -42,29 +46,33 @@ impl<'t, 'u, 'i> WordsPrefixes<'t, 'u, 'i> {
             chunk_fusing_shrink_size: None,
             max_nb_chunks: None,
             max_memory: None,
-            threshold: 0.1 / 100.0, // .01%
-            max_prefix_length: 4,
+            threshold: None,
+            max_prefix_length: None,
             _update_id: update_id,
         }
     }
 
-    /// Set the ratio of concerned words required to make a prefix be part of the words prefixes
-    /// database. If a word prefix is supposed to match more than this number of words in the
-    /// dictionnary, therefore this prefix is added to the words prefixes datastructures.
+    /// Set the ratio of the words postings required to make a prefix be part of the words prefixes
+    /// database. If the words matched by a prefix appear in more than this ratio of all the
+    /// (word, document) pairs of the index, this prefix is added to the words prefixes datastructures.
+    ///
+    /// The number of documents of the words is used instead of the number of words, this way
+    /// a prefix matching a few very frequent words is cached, while a prefix matching many rare
+    /// words, that are cheap to union at search time, is not.
     ///
-    /// Default value is `0.01` or `1%`. This value must be between 0 and 1 and will be clamped
-    /// to these bounds otherwise.
+    /// Default value is the one stored in the index settings or `0.001` (`0.1%`) if there is none.
+    /// This value must be between 0 and 1 and will be clamped to these bounds otherwise.
     pub fn threshold(&mut self, value: f64) -> &mut Self {
-        self.threshold = value.min(1.0).max(0.0); // clamp [0, 1]
+        self.threshold = Some(clamp_threshold(value));
         self
     }
 
     /// Set the maximum length of prefixes in bytes.
     ///
-    /// Default value is `4` bytes. This value must be between 1 and 25 will be clamped
-    /// to these bounds, otherwise.
+    /// Default value is the one stored in the index settings or `4` bytes if there is none.
+    /// This value must be between 1 and 25 will be clamped to these bounds, otherwise.
     pub fn max_prefix_length(&mut self, value: usize) -> &mut Self {
-        self.max_prefix_length = value.min(25).max(1); // clamp [1, 25]
+        self.max_prefix_length = Some(clamp_max_prefix_length(value));
         self
     }
 
@@ -73,10 +81,27 @@ impl<'t, 'u, 'i> WordsPrefixes<'t, 'u, 'i> {
         // Clear the words prefixes datastructures.
         self.index.word_prefix_docids.clear(self.wtxn)?;
         self.index.word_prefix_pair_proximity_docids.clear(self.wtxn)?;
+        self.index.prefix_word_pair_proximity_docids.clear(self.wtxn)?;
 
-        let words_fst = self.index.words_fst(&self.wtxn)?;
-        let number_of_words = words_fst.len();
-        let min_number_of_words = (number_of_words as f64 * self.threshold) as usize;
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => self.index.words_prefixes_threshold(self.wtxn)?
+                .map_or(DEFAULT_THRESHOLD, clamp_threshold),
+        };
+        let max_prefix_length = match self.max_prefix_length {
+            Some(length) => length,
+            None => self.index.max_prefix_length(self.wtxn)?
+                .map_or(DEFAULT_MAX_PREFIX_LENGTH, clamp_max_prefix_length),
+        };
+
+        // We retrieve the number of documents of every word, the words are iterated
+        // in lexicographic order like in the words FST.
+        let mut number_of_postings = 0u64;
+        for result in self.index.words_documents_count(self.wtxn)? {
+            let (_word, count) = result?;
+            number_of_postings += count;
+        }
+        let min_number_of_postings = ((number_of_postings as f64 * threshold) as u64).max(1);
 
         // It is forbidden to keep a mutable reference into the database
         // and write into it at the same time, therefore we write into another file.
@@ -89,19 +114,19 @@ impl<'t, 'u, 'i> WordsPrefixes<'t, 'u, 'i> {
             self.max_memory,
         );
 
-        let mut prefix_fsts = Vec::with_capacity(self.max_prefix_length);
-        for n in 1..=self.max_prefix_length {
+        let mut prefix_fsts = Vec::with_capacity(max_prefix_length);
+        for n in 1..=max_prefix_length {
 
             let mut current_prefix = SmallString32::new();
-            let mut current_prefix_count = 0;
+            let mut current_prefix_postings = 0;
+            let mut current_prefix_inserted = false;
             let mut builder = fst::SetBuilder::memory();
 
-            let mut stream = words_fst.stream();
-            while let Some(bytes) = stream.next() {
+            for result in self.index.words_documents_count(self.wtxn)? {
                 // We try to get the first n bytes out of this string but we only want
                 // to split at valid characters bounds. If we try to split in the middle of
                 // a character we ignore this word and go to the next one.
-                let word = str::from_utf8(bytes)?;
+                let (word, count) = result?;
                 let prefix = match word.get(..n) {
                     Some(prefix) => prefix,
                     None => continue,
@@ -109,16 +134,19 @@ impl<'t, 'u, 'i> WordsPrefixes<'t, 'u, 'i> {
 
                 // This is the first iteration of the loop,
                 // or the current word doesn't starts with the current prefix.
-                if current_prefix_count == 0 || prefix != current_prefix.as_str() {
+                if current_prefix_postings == 0 || prefix != current_prefix.as_str() {
                     current_prefix = SmallString32::from(prefix);
-                    current_prefix_count = 0;
+                    current_prefix_postings = 0;
+                    current_prefix_inserted = false;
                 }
 
-                current_prefix_count += 1;
+                current_prefix_postings += count;
 
-                // There is enough words corresponding to this prefix to add it to the cache.
-                if current_prefix_count == min_number_of_words {
+                // The words corresponding to this prefix appear in enough documents
+                // to add it to the cache.
+                if !current_prefix_inserted && current_prefix_postings >= min_number_of_postings {
                     builder.insert(prefix)?;
+                    current_prefix_inserted = true;
                 }
             }
 
@@ -147,9 +175,8 @@ impl<'t, 'u, 'i> WordsPrefixes<'t, 'u, 'i> {
         self.index.put_words_prefixes_fst(self.wtxn, &prefix_fst)?;
 
         // We finally write the word prefix docids into the LMDB database.
-        sorter_into_lmdb_database(
-            self.wtxn,
-            *self.index.word_prefix_docids.as_polymorph(),
+        sorter_into_storage(
+            &mut self.index.storage_mut(self.wtxn, IndexDatabase::WordPrefixDocids),
             prefix_docids_sorter,
             word_docids_merge,
             WriteMethod::Append,
@@ -167,8 +194,18 @@ impl<'t, 'u, 'i> WordsPrefixes<'t, 'u, 'i> {
             self.max_memory,
         );
 
-        // We insert all the word pairs corresponding to the word-prefix pairs
-        // where the prefixes appears in the prefix FST previously constructed.
+        // And another one for the prefix-word pairs.
+        let mut prefix_word_pair_proximity_docids_sorter = create_sorter(
+            words_pairs_proximities_docids_merge,
+            self.chunk_compression_type,
+            self.chunk_compression_level,
+            self.chunk_fusing_shrink_size,
+            self.max_nb_chunks,
+            self.max_memory,
+        );
+
+        // We insert all the word pairs corresponding to the word-prefix and prefix-word
+        // pairs where the prefixes appears in the prefix FST previously constructed.
         let db = self.index.word_pair_proximity_docids.remap_data_type::<ByteSlice>();
         for result in db.iter(self.wtxn)? {
             let ((word1, word2, prox), data) = result?;
@@ -180,17 +217,41 @@ impl<'t, 'u, 'i> WordsPrefixes<'t, 'u, 'i> {
                 let bytes = StrStrU8Codec::bytes_encode(&pair).unwrap();
                 word_prefix_pair_proximity_docids_sorter.insert(bytes, data)?;
             }
+
+            let automaton = Str::new(word1).starts_with();
+            let mut matching_prefixes = prefix_fst.search(automaton).into_stream();
+            while let Some(prefix) = matching_prefixes.next() {
+                let prefix = str::from_utf8(prefix)?;
+                let pair = (prefix, word2, prox);
+                let bytes = StrStrU8Codec::bytes_encode(&pair).unwrap();
+                prefix_word_pair_proximity_docids_sorter.insert(bytes, data)?;
+            }
         }
 
         // We finally write the word prefix pair proximity docids into the LMDB database.
-        sorter_into_lmdb_database(
-            self.wtxn,
-            *self.index.word_prefix_pair_proximity_docids.as_polymorph(),
+        sorter_into_storage(
+            &mut self.index.storage_mut(self.wtxn, IndexDatabase::WordPrefixPairProximityDocids),
             word_prefix_pair_proximity_docids_sorter,
             words_pairs_proximities_docids_merge,
             WriteMethod::Append,
         )?;
 
+        // And the prefix word pair proximity docids.
+        sorter_into_storage(
+            &mut self.index.storage_mut(self.wtxn, IndexDatabase::PrefixWordPairProximityDocids),
+            prefix_word_pair_proximity_docids_sorter,
+            words_pairs_proximities_docids_merge,
+            WriteMethod::Append,
+        )?;
+
         Ok(())
     }
 }
+
+pub(crate) fn clamp_threshold(value: f64) -> f64 {
+    value.min(1.0).max(0.0) // clamp [0, 1]
+}
+
+pub(crate) fn clamp_max_prefix_length(value: usize) -> usize {
+    value.min(25).max(1) // clamp [1, 25]
+}
@@ -15,6 +15,9 @@ pub struct UpdateStore<M, N> {
     pending: Database<OwnedType<BEU64>, ByteSlice>,
     processed_meta: Database<OwnedType<BEU64>, SerdeJson<N>>,
     aborted_meta: Database<OwnedType<BEU64>, SerdeJson<M>>,
+    changes_meta: Database<OwnedType<BEU64>, SerdeJson<M>>,
+    changes: Database<OwnedType<BEU64>, ByteSlice>,
+    keep_changes: bool,
     notification_sender: Sender<()>,
 }
 
@@ -31,9 +34,43 @@ where F: FnMut(u64, M, &[u8]) -> heed::Result<N> + Send + 'static {
 
 impl<M: 'static, N: 'static> UpdateStore<M, N> {
     pub fn open<P, U>(
+        options: EnvOpenOptions,
+        path: P,
+        update_handler: U,
+    ) -> heed::Result<Arc<UpdateStore<M, N>>>
+    where
+        P: AsRef<Path>,
+        U: UpdateHandler<M, N> + Send + 'static,
+        M: for<'a> Deserialize<'a>,
+        N: Serialize,
+    {
+        UpdateStore::open_inner(options, path, update_handler, false)
+    }
+
+    /// Opens an update store that keeps the meta and the content of the successfully
+    /// processed updates, in order, a follower can read them with `changes_since` and
+    /// register them in its own update store to stay in sync with this one.
+    ///
+    /// The changes are kept until they are pruned with `prune_changes`.
+    pub fn open_with_change_stream<P, U>(
+        options: EnvOpenOptions,
+        path: P,
+        update_handler: U,
+    ) -> heed::Result<Arc<UpdateStore<M, N>>>
+    where
+        P: AsRef<Path>,
+        U: UpdateHandler<M, N> + Send + 'static,
+        M: for<'a> Deserialize<'a>,
+        N: Serialize,
+    {
+        UpdateStore::open_inner(options, path, update_handler, true)
+    }
+
+    fn open_inner<P, U>(
         mut options: EnvOpenOptions,
         path: P,
         mut update_handler: U,
+        keep_changes: bool,
     ) -> heed::Result<Arc<UpdateStore<M, N>>>
     where
         P: AsRef<Path>,
@@ -41,12 +78,14 @@ impl<M: 'static, N: 'static> UpdateStore<M, N> {
         M: for<'a> Deserialize<'a>,
         N: Serialize,
     {
-        options.max_dbs(4);
+        options.max_dbs(6);
         let env = options.open(path)?;
         let pending_meta = env.create_database(Some("pending-meta"))?;
         let pending = env.create_database(Some("pending"))?;
         let processed_meta = env.create_database(Some("processed-meta"))?;
         let aborted_meta = env.create_database(Some("aborted-meta"))?;
+        let changes_meta = env.create_database(Some("changes-meta"))?;
+        let changes = env.create_database(Some("changes"))?;
 
         let (notification_sender, notification_receiver) = crossbeam_channel::bounded(1);
         // Send a first notification to trigger the process.
@@ -58,6 +97,9 @@ impl<M: 'static, N: 'static> UpdateStore<M, N> {
             pending_meta,
             processed_meta,
             aborted_meta,
+            changes_meta,
+            changes,
+            keep_changes,
             notification_sender,
         });
 
@@ -154,8 +196,18 @@ impl<M: 'static, N: 'static> UpdateStore<M, N> {
                     .get(&rtxn, &first_id)?
                     .expect("associated update content");
 
+                // The meta is given to the handler, we keep its raw bytes for the change stream.
+                let raw_meta = match self.keep_changes {
+                    true => {
+                        let pending_meta = self.pending_meta.remap_data_type::<ByteSlice>();
+                        pending_meta.get(&rtxn, &first_id)?.map(ToOwned::to_owned)
+                    },
+                    false => None,
+                };
+
                 // Process the pending update using the provided user function.
                 let new_meta = handler.handle_update(first_id.get(), first_meta, first_content)?;
+                let change = raw_meta.map(|meta| (meta, first_content.to_owned()));
                 drop(rtxn);
 
                 // Once the pending update have been successfully processed
@@ -165,6 +217,10 @@ impl<M: 'static, N: 'static> UpdateStore<M, N> {
                 self.pending_meta.delete(&mut wtxn, &first_id)?;
                 self.pending.delete(&mut wtxn, &first_id)?;
                 self.processed_meta.put(&mut wtxn, &first_id, &new_meta)?;
+                if let Some((meta, content)) = change {
+                    self.changes_meta.remap_data_type::<ByteSlice>().put(&mut wtxn, &first_id, &meta)?;
+                    self.changes.put(&mut wtxn, &first_id, &content)?;
+                }
                 wtxn.commit()?;
 
                 Ok(Some((first_id.get(), new_meta)))
@@ -233,6 +289,47 @@ impl<M: 'static, N: 'static> UpdateStore<M, N> {
         Ok(None)
     }
 
+    /// Returns, in order, at most `limit` successfully processed updates with an id
+    /// greater than or equal to the given one, along with their meta and content.
+    ///
+    /// The changes are only kept by the stores opened with `open_with_change_stream`.
+    pub fn changes_since(&self, update_id: u64, limit: usize) -> heed::Result<Vec<(u64, M, Vec<u8>)>>
+    where M: for<'a> Deserialize<'a>,
+    {
+        let rtxn = self.env.read_txn()?;
+        let mut changes = Vec::new();
+        for result in self.changes_meta.range(&rtxn, &(BEU64::new(update_id)..))?.take(limit) {
+            let (key, meta) = result?;
+            let content = self.changes.get(&rtxn, &key)?.expect("associated change content");
+            changes.push((key.get(), meta, content.to_owned()));
+        }
+        Ok(changes)
+    }
+
+    /// Deletes the changes of the updates with an id lower than the given one, the
+    /// followers must have registered them already. Returns the number of deleted changes.
+    pub fn prune_changes(&self, update_id: u64) -> heed::Result<usize> {
+        let mut wtxn = self.env.write_txn()?;
+        let range = ..BEU64::new(update_id);
+        let mut count = 0;
+
+        let mut iter = self.changes_meta.remap_data_type::<DecodeIgnore>().range_mut(&mut wtxn, &range)?;
+        while iter.next().transpose()?.is_some() {
+            iter.del_current()?;
+            count += 1;
+        }
+        drop(iter);
+
+        let mut iter = self.changes.remap_data_type::<DecodeIgnore>().range_mut(&mut wtxn, &range)?;
+        while iter.next().transpose()?.is_some() {
+            iter.del_current()?;
+        }
+        drop(iter);
+
+        wtxn.commit()?;
+        Ok(count)
+    }
+
     /// Aborts an update, an aborted update content is deleted and
     /// the meta of it is moved into the aborted updates database.
     ///
@@ -322,6 +419,31 @@ mod tests {
         assert_eq!(meta, UpdateStatusMeta::Processed(format!("kiki processed")));
     }
 
+    #[test]
+    fn change_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = EnvOpenOptions::new();
+        let update_store = UpdateStore::open_with_change_stream(options, dir, |_id, meta: String, _content:&_| {
+            Ok(meta + " processed")
+        }).unwrap();
+
+        let first_id = update_store.register_update(&String::from("kiki"), b"first").unwrap();
+        let second_id = update_store.register_update(&String::from("coco"), b"second").unwrap();
+
+        thread::sleep(Duration::from_millis(100));
+
+        // A follower reads the updates as they were registered.
+        let changes = update_store.changes_since(0, 10).unwrap();
+        assert_eq!(changes, vec![
+            (first_id, String::from("kiki"), b"first".to_vec()),
+            (second_id, String::from("coco"), b"second".to_vec()),
+        ]);
+
+        assert_eq!(update_store.prune_changes(second_id).unwrap(), 1);
+        let changes = update_store.changes_since(0, 10).unwrap();
+        assert_eq!(changes, vec![(second_id, String::from("coco"), b"second".to_vec())]);
+    }
+
     #[test]
     #[ignore]
     fn long_running_update() {